@@ -0,0 +1,407 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use nenya_client::{Client, Error};
+use tokio::time::{self, MissedTickBehavior};
+
+/// Fractional part of the golden ratio, spreading consecutive requests evenly over the segments.
+const GOLDEN_RATIO_FRACTION: f64 = 0.618_033_988_749_895;
+
+/// A segment to send requests for and its share of the load.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SegmentLoad {
+    pub name: String,
+    pub weight: f64,
+}
+
+/// Error returned when a segment is not given as `name` or `name:weight`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct InvalidSegment(String);
+
+impl fmt::Display for InvalidSegment {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "invalid segment {:?}, expected name or name:weight with a positive weight",
+            self.0
+        )
+    }
+}
+
+impl std::error::Error for InvalidSegment {}
+
+impl FromStr for SegmentLoad {
+    type Err = InvalidSegment;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || InvalidSegment(s.to_string());
+        let (name, weight) = match s.rsplit_once(':') {
+            Some((name, weight)) => (name, weight.parse().map_err(|_| invalid())?),
+            None => (s, 1.0),
+        };
+        if name.is_empty() || !(weight > 0.0 && f64::is_finite(weight)) {
+            return Err(invalid());
+        }
+        Ok(SegmentLoad {
+            name: name.to_string(),
+            weight,
+        })
+    }
+}
+
+/// Shape of the load sent to the sentinel.
+#[derive(Debug, Clone)]
+pub struct LoadConfig {
+    pub segments: Vec<SegmentLoad>,
+    /// Requests per second across all workers, or `None` to send as fast as the workers can.
+    pub qps: Option<f64>,
+    /// Number of workers, each with one request in flight at a time.
+    pub concurrency: usize,
+    pub duration: Duration,
+    /// Units of the segment's budget each request consumes.
+    pub cost: f32,
+}
+
+impl LoadConfig {
+    /// Returns the index of the segment of the `request`th request, following the weights.
+    fn segment(&self, request: u64) -> usize {
+        let total: f64 = self.segments.iter().map(|segment| segment.weight).sum();
+        let mut position = (request as f64 * GOLDEN_RATIO_FRACTION).fract() * total;
+        for (index, segment) in self.segments.iter().enumerate() {
+            if position < segment.weight {
+                return index;
+            }
+            position -= segment.weight;
+        }
+        self.segments.len() - 1
+    }
+}
+
+/// Decisions and latencies observed for one segment.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SegmentStats {
+    pub throttled: u64,
+    /// Failed calls by gRPC status code, or `transport` for connection errors.
+    pub errors: BTreeMap<String, u64>,
+    /// Time each call took, successful or not.
+    pub latencies: Vec<Duration>,
+}
+
+impl SegmentStats {
+    /// Returns the number of calls made.
+    pub fn requests(&self) -> u64 {
+        self.latencies.len() as u64
+    }
+
+    /// Returns the number of failed calls.
+    pub fn error_count(&self) -> u64 {
+        self.errors.values().sum()
+    }
+
+    /// Returns the fraction of the calls that failed, or zero without calls.
+    pub fn error_rate(&self) -> f64 {
+        if self.latencies.is_empty() {
+            return 0.0;
+        }
+        self.error_count() as f64 / self.latencies.len() as f64
+    }
+
+    /// Returns the latency `percentile` percent of the calls took at most, by nearest rank.
+    ///
+    /// The latencies must be sorted, as they are in a [`LoadReport`].
+    pub fn percentile(&self, percentile: f64) -> Duration {
+        if self.latencies.is_empty() {
+            return Duration::ZERO;
+        }
+        let rank = (percentile / 100.0 * self.latencies.len() as f64).ceil() as usize;
+        self.latencies[rank.clamp(1, self.latencies.len()) - 1]
+    }
+
+    fn merge(&mut self, other: SegmentStats) {
+        self.throttled += other.throttled;
+        for (code, count) in other.errors {
+            *self.errors.entry(code).or_default() += count;
+        }
+        self.latencies.extend(other.latencies);
+    }
+
+    fn record(&mut self, result: Result<bool, String>, latency: Duration) {
+        match result {
+            Ok(throttled) => self.throttled += u64::from(throttled),
+            Err(code) => *self.errors.entry(code).or_default() += 1,
+        }
+        self.latencies.push(latency);
+    }
+}
+
+/// Outcome of a load test.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LoadReport {
+    /// Time from the first request to the last response.
+    pub elapsed: Duration,
+    /// Statistics of each segment, by name.
+    pub segments: BTreeMap<String, SegmentStats>,
+    /// Statistics of all segments together.
+    pub total: SegmentStats,
+}
+
+impl LoadReport {
+    /// Returns the calls completed per second.
+    pub fn achieved_qps(&self) -> f64 {
+        if self.elapsed.is_zero() {
+            return 0.0;
+        }
+        self.total.requests() as f64 / self.elapsed.as_secs_f64()
+    }
+}
+
+/// Returns the label failed calls are counted under.
+fn error_code(error: &Error) -> String {
+    match error {
+        Error::Status(status) => format!("{:?}", status.code()),
+        Error::Transport(_) => "transport".to_string(),
+        Error::InvalidToken => "invalid token".to_string(),
+    }
+}
+
+/// Sends the configured load to the sentinel through `client`, which should not cache
+/// decisions so every request reaches the sentinel.
+pub async fn run(client: Client, config: LoadConfig) -> LoadReport {
+    let config = Arc::new(config);
+    let sent = Arc::new(AtomicU64::new(0));
+    let start = Instant::now();
+    let deadline = start + config.duration;
+    let concurrency = config.concurrency.max(1);
+
+    let workers: Vec<_> = (0..concurrency)
+        .map(|worker| {
+            let client = client.clone();
+            let config = config.clone();
+            let sent = sent.clone();
+            tokio::spawn(async move {
+                // Each worker paces its share of the rate, staggered so they do not send at once
+                let mut pacing = config.qps.map(|qps| {
+                    let period = Duration::from_secs_f64(concurrency as f64 / qps);
+                    let offset = period.mul_f64(worker as f64 / concurrency as f64);
+                    let mut pacing = time::interval_at((start + offset).into(), period);
+                    pacing.set_missed_tick_behavior(MissedTickBehavior::Delay);
+                    pacing
+                });
+                let mut stats = vec![SegmentStats::default(); config.segments.len()];
+                loop {
+                    if let Some(pacing) = &mut pacing {
+                        pacing.tick().await;
+                    }
+                    if Instant::now() >= deadline {
+                        break;
+                    }
+                    let index = config.segment(sent.fetch_add(1, Ordering::Relaxed));
+                    let sent_at = Instant::now();
+                    let result = client
+                        .should_throttle(&config.segments[index].name, config.cost)
+                        .await;
+                    let latency = sent_at.elapsed();
+                    stats[index].record(
+                        result
+                            .map(|decision| decision.throttled)
+                            .map_err(|error| error_code(&error)),
+                        latency,
+                    );
+                }
+                stats
+            })
+        })
+        .collect();
+
+    let mut segments: Vec<SegmentStats> = vec![SegmentStats::default(); config.segments.len()];
+    for worker in workers {
+        let stats = worker.await.expect("load worker panicked");
+        for (segment, stats) in segments.iter_mut().zip(stats) {
+            segment.merge(stats);
+        }
+    }
+    let elapsed = start.elapsed();
+
+    let mut report = LoadReport {
+        elapsed,
+        segments: BTreeMap::new(),
+        total: SegmentStats::default(),
+    };
+    for (segment, stats) in config.segments.iter().zip(segments) {
+        report.total.merge(stats.clone());
+        report
+            .segments
+            .entry(segment.name.clone())
+            .or_default()
+            .merge(stats);
+    }
+    report.total.latencies.sort_unstable();
+    for stats in report.segments.values_mut() {
+        stats.latencies.sort_unstable();
+    }
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nenya_client::proto::sentinel_server::{Sentinel, SentinelServer};
+    use nenya_client::proto::{
+        GossipMessage, Metrics, PingRequestMessage, ShouldThrottleBatchRequest,
+        ShouldThrottleBatchResponse, ShouldThrottleRequest, ShouldThrottleResponse, TargetUpdate,
+        WatchTargetsRequest,
+    };
+    use nenya_client::ClientBuilder;
+    use tokio_stream::wrappers::{ReceiverStream, TcpListenerStream};
+    use tonic::{Request, Response, Status};
+
+    /// Sentinel that throttles the segment "throttled" and fails requests for "broken".
+    #[derive(Debug, Default, Clone)]
+    struct MockSentinel;
+
+    #[tonic::async_trait]
+    impl Sentinel for MockSentinel {
+        async fn exchange_metrics(
+            &self,
+            request: Request<Metrics>,
+        ) -> Result<Response<Metrics>, Status> {
+            Ok(Response::new(request.into_inner()))
+        }
+
+        async fn should_throttle(
+            &self,
+            request: Request<ShouldThrottleRequest>,
+        ) -> Result<Response<ShouldThrottleResponse>, Status> {
+            let segment = request.into_inner().segment.unwrap_or_default();
+            if segment == "broken" {
+                return Err(Status::unavailable("broken"));
+            }
+            Ok(Response::new(ShouldThrottleResponse {
+                should_throttle: segment == "throttled",
+                node: "mock".to_string(),
+                ..Default::default()
+            }))
+        }
+
+        async fn should_throttle_batch(
+            &self,
+            _request: Request<ShouldThrottleBatchRequest>,
+        ) -> Result<Response<ShouldThrottleBatchResponse>, Status> {
+            Err(Status::unimplemented("should_throttle_batch"))
+        }
+
+        type WatchTargetsStream = ReceiverStream<Result<TargetUpdate, Status>>;
+
+        async fn watch_targets(
+            &self,
+            _request: Request<WatchTargetsRequest>,
+        ) -> Result<Response<Self::WatchTargetsStream>, Status> {
+            Err(Status::unimplemented("watch_targets"))
+        }
+
+        async fn gossip(
+            &self,
+            _request: Request<GossipMessage>,
+        ) -> Result<Response<GossipMessage>, Status> {
+            Err(Status::unimplemented("gossip"))
+        }
+
+        async fn ping_request(
+            &self,
+            _request: Request<PingRequestMessage>,
+        ) -> Result<Response<GossipMessage>, Status> {
+            Err(Status::unimplemented("ping_request"))
+        }
+    }
+
+    async fn serve() -> String {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(
+            tonic::transport::Server::builder()
+                .add_service(SentinelServer::new(MockSentinel))
+                .serve_with_incoming(TcpListenerStream::new(listener)),
+        );
+        address
+    }
+
+    fn segments(specs: &[&str]) -> Vec<SegmentLoad> {
+        specs.iter().map(|spec| spec.parse().unwrap()).collect()
+    }
+
+    #[test]
+    fn test_parses_weighted_segments() {
+        assert_eq!(
+            "checkout:3".parse(),
+            Ok(SegmentLoad {
+                name: "checkout".to_string(),
+                weight: 3.0
+            })
+        );
+        assert_eq!("search".parse::<SegmentLoad>().unwrap().weight, 1.0);
+        assert!("checkout:0".parse::<SegmentLoad>().is_err());
+        assert!(":2".parse::<SegmentLoad>().is_err());
+        assert!("checkout:fast".parse::<SegmentLoad>().is_err());
+    }
+
+    #[test]
+    fn test_spreads_requests_by_weight() {
+        let config = LoadConfig {
+            segments: segments(&["a:3", "b"]),
+            qps: None,
+            concurrency: 1,
+            duration: Duration::ZERO,
+            cost: 1.0,
+        };
+        let a = (0..1000)
+            .filter(|&request| config.segment(request) == 0)
+            .count();
+        assert!((740..=760).contains(&a), "{a}");
+    }
+
+    #[test]
+    fn test_percentiles_by_nearest_rank() {
+        let stats = SegmentStats {
+            latencies: (1..=100).map(Duration::from_millis).collect(),
+            ..Default::default()
+        };
+        assert_eq!(stats.percentile(50.0), Duration::from_millis(50));
+        assert_eq!(stats.percentile(99.9), Duration::from_millis(100));
+        assert_eq!(stats.percentile(0.0), Duration::from_millis(1));
+        assert_eq!(SegmentStats::default().percentile(99.0), Duration::ZERO);
+    }
+
+    #[tokio::test]
+    async fn test_reports_decisions_and_errors_per_segment() {
+        let client = ClientBuilder::new(serve().await)
+            .cache_decisions(false)
+            .timeout(Duration::from_secs(5))
+            .build()
+            .unwrap();
+        let config = LoadConfig {
+            segments: segments(&["checkout:2", "throttled", "broken"]),
+            qps: Some(400.0),
+            concurrency: 4,
+            duration: Duration::from_millis(500),
+            cost: 1.0,
+        };
+        let report = run(client, config).await;
+
+        let requests = report.total.requests();
+        assert!((100..=210).contains(&requests), "{requests}");
+        let checkout = &report.segments["checkout"];
+        assert_eq!(checkout.throttled, 0);
+        assert_eq!(checkout.error_count(), 0);
+        let throttled = &report.segments["throttled"];
+        assert_eq!(throttled.throttled, throttled.requests());
+        let broken = &report.segments["broken"];
+        assert_eq!(broken.errors["Unavailable"], broken.requests());
+        assert!(checkout.requests() > throttled.requests());
+        assert_eq!(report.total.error_count(), broken.requests());
+        assert!(report.total.percentile(50.0) <= report.total.percentile(99.0));
+    }
+}
@@ -0,0 +1,174 @@
+use std::error::Error;
+use std::time::Duration;
+
+use clap::{Arg, ArgMatches, Command};
+use nenya_client::ClientBuilder;
+
+use crate::load::{LoadConfig, LoadReport, SegmentLoad, SegmentStats};
+
+mod load;
+
+/// Builds the command line interface for the load testing binary.
+fn command() -> Command {
+    Command::new("nenya-bench")
+        .about("Load tests a running sentinel over gRPC, reporting decision latency percentiles and error rates")
+        .version(env!("CARGO_PKG_VERSION"))
+        .arg(
+            Arg::new("endpoints")
+                .required(true)
+                .num_args(1..)
+                .help("Sentinels to send requests to, e.g. http://[::1]:8080"),
+        )
+        .arg(
+            Arg::new("segments")
+                .short('s')
+                .long("segments")
+                .value_parser(clap::value_parser!(SegmentLoad))
+                .num_args(1..)
+                .use_value_delimiter(true)
+                .default_value("default")
+                .help("Segments to send requests for, as name or name:weight to set their share of the load"),
+        )
+        .arg(
+            Arg::new("qps")
+                .short('q')
+                .long("qps")
+                .value_parser(clap::value_parser!(f64))
+                .help("Requests per second across all workers (defaults to as fast as the workers can)"),
+        )
+        .arg(
+            Arg::new("concurrency")
+                .short('c')
+                .long("concurrency")
+                .value_parser(clap::value_parser!(usize))
+                .default_value("16")
+                .help("Number of workers, each with one request in flight"),
+        )
+        .arg(
+            Arg::new("duration")
+                .short('d')
+                .long("duration")
+                .value_parser(clap::value_parser!(u64))
+                .default_value("30")
+                .help("Duration of the test (seconds)"),
+        )
+        .arg(
+            Arg::new("cost")
+                .long("cost")
+                .value_parser(clap::value_parser!(f32))
+                .default_value("1.0")
+                .help("Units of the segment's budget each request consumes"),
+        )
+        .arg(
+            Arg::new("pool_size")
+                .long("pool_size")
+                .value_parser(clap::value_parser!(usize))
+                .default_value("4")
+                .help("Number of connections opened to each sentinel"),
+        )
+        .arg(
+            Arg::new("timeout")
+                .long("timeout")
+                .value_parser(clap::value_parser!(u64))
+                .default_value("100")
+                .help("Time a call may take before it counts as an error (milliseconds)"),
+        )
+        .arg(
+            Arg::new("token")
+                .long("token")
+                .help("Bearer token sent with every request"),
+        )
+        .arg(
+            Arg::new("namespace")
+                .long("namespace")
+                .help("Namespace of the segments (defaults to the sentinel's default namespace)"),
+        )
+}
+
+fn print_stats(name: &str, stats: &SegmentStats) {
+    let millis = |percentile| stats.percentile(percentile).as_secs_f64() * 1000.0;
+    println!(
+        "{:<20} {:>10} {:>10} {:>9.3}% {:>9.3} {:>9.3} {:>9.3} {:>9.3} {:>9.3}",
+        name,
+        stats.requests(),
+        stats.throttled,
+        stats.error_rate() * 100.0,
+        millis(50.0),
+        millis(90.0),
+        millis(99.0),
+        millis(99.9),
+        millis(100.0),
+    );
+}
+
+fn print_report(report: &LoadReport, concurrency: usize) {
+    println!(
+        "Sent {} requests in {:.1}s ({:.1} qps) from {} workers",
+        report.total.requests(),
+        report.elapsed.as_secs_f64(),
+        report.achieved_qps(),
+        concurrency
+    );
+    println!(
+        "{:<20} {:>10} {:>10} {:>10} {:>9} {:>9} {:>9} {:>9} {:>9}",
+        "segment", "requests", "throttled", "errors", "p50 (ms)", "p90", "p99", "p99.9", "max"
+    );
+    for (name, stats) in &report.segments {
+        print_stats(name, stats);
+    }
+    if report.segments.len() > 1 {
+        print_stats("total", &report.total);
+    }
+    for (code, count) in &report.total.errors {
+        println!("{}: {} errors", code, count);
+    }
+}
+
+fn load_config(matches: &ArgMatches) -> LoadConfig {
+    LoadConfig {
+        segments: matches
+            .get_many::<SegmentLoad>("segments")
+            .unwrap()
+            .cloned()
+            .collect(),
+        qps: matches.get_one::<f64>("qps").copied(),
+        concurrency: *matches.get_one::<usize>("concurrency").unwrap(),
+        duration: Duration::from_secs(*matches.get_one::<u64>("duration").unwrap()),
+        cost: *matches.get_one::<f32>("cost").unwrap(),
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn Error>> {
+    let matches = command().get_matches();
+    let mut endpoints = matches.get_many::<String>("endpoints").unwrap();
+    let mut builder = ClientBuilder::new(endpoints.next().unwrap())
+        .pool_size(*matches.get_one::<usize>("pool_size").unwrap())
+        .timeout(Duration::from_millis(
+            *matches.get_one::<u64>("timeout").unwrap(),
+        ))
+        // Every request has to reach the sentinel to measure it
+        .cache_decisions(false);
+    for endpoint in endpoints {
+        builder = builder.endpoint(endpoint);
+    }
+    if let Some(token) = matches.get_one::<String>("token") {
+        builder = builder.token(token);
+    }
+    if let Some(namespace) = matches.get_one::<String>("namespace") {
+        builder = builder.namespace(namespace);
+    }
+    let client = builder.build()?;
+
+    let config = load_config(&matches);
+    if config
+        .qps
+        .is_some_and(|qps| !(qps > 0.0 && qps.is_finite()))
+    {
+        return Err("--qps must be a positive number".into());
+    }
+    let concurrency = config.concurrency;
+    let report = load::run(client, config).await;
+    print_report(&report, concurrency);
+    Ok(())
+}
@@ -0,0 +1,119 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use crate::Decision;
+
+/// Latest state the sentinel reported for each segment.
+#[derive(Debug, Default)]
+pub(crate) struct DecisionCache {
+    segments: HashMap<String, CachedSegment>,
+    pushed: HashMap<String, PushedTarget>,
+}
+
+/// Target rate pushed by the sentinel to a `WatchTargets` subscription.
+#[derive(Debug, Clone)]
+pub(crate) struct PushedTarget {
+    pub(crate) target_rate: f32,
+    pub(crate) node: String,
+}
+
+#[derive(Debug)]
+struct CachedSegment {
+    limit: f32,
+    node: String,
+    throttled_until: Option<Instant>,
+}
+
+impl DecisionCache {
+    /// Returns a throttled decision if the sentinel asked callers of `segment` to back off
+    /// until after `now`.
+    pub(crate) fn throttled(&self, segment: &str, now: Instant) -> Option<Decision> {
+        let cached = self.segments.get(segment)?;
+        let retry_after = cached.throttled_until?.checked_duration_since(now)?;
+        if retry_after.is_zero() {
+            return None;
+        }
+        Some(Decision {
+            throttled: true,
+            retry_after,
+            limit: cached.limit,
+            remaining: 0.0,
+            node: cached.node.clone(),
+            fallback: false,
+        })
+    }
+
+    /// Records a decision made by the sentinel for `segment` at `now`.
+    pub(crate) fn update(&mut self, segment: &str, decision: &Decision, now: Instant) {
+        let throttled_until = (decision.throttled && decision.retry_after > Duration::ZERO)
+            .then(|| now + decision.retry_after);
+        self.segments.insert(
+            segment.to_string(),
+            CachedSegment {
+                limit: decision.limit,
+                node: decision.node.clone(),
+                throttled_until,
+            },
+        );
+    }
+
+    /// Returns the last target rate the sentinel reported for `segment`, preferring a pushed
+    /// target rate.
+    pub(crate) fn limit(&self, segment: &str) -> Option<f32> {
+        match self.pushed.get(segment) {
+            Some(pushed) => Some(pushed.target_rate),
+            None => self.segments.get(segment).map(|cached| cached.limit),
+        }
+    }
+
+    /// Returns the target rate pushed for `segment` by an active subscription.
+    pub(crate) fn pushed(&self, segment: &str) -> Option<PushedTarget> {
+        self.pushed.get(segment).cloned()
+    }
+
+    pub(crate) fn set_pushed(&mut self, segment: String, pushed: PushedTarget) {
+        self.pushed.insert(segment, pushed);
+    }
+
+    /// Forgets the pushed target rates of `segments`, e.g. because their subscription ended.
+    pub(crate) fn clear_pushed(&mut self, segments: &[String]) {
+        for segment in segments {
+            self.pushed.remove(segment);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decision(throttled: bool, retry_after: Duration) -> Decision {
+        Decision {
+            throttled,
+            retry_after,
+            limit: 10.0,
+            remaining: 0.0,
+            node: "sentinel-1".to_string(),
+            fallback: false,
+        }
+    }
+
+    #[test]
+    fn test_throttled_decisions_are_cached_until_retry_after() {
+        let mut cache = DecisionCache::default();
+        let now = Instant::now();
+        assert!(cache.throttled("checkout", now).is_none());
+
+        cache.update("checkout", &decision(true, Duration::from_secs(2)), now);
+        let cached = cache.throttled("checkout", now).unwrap();
+        assert!(cached.throttled);
+        assert_eq!(cached.retry_after, Duration::from_secs(2));
+        assert_eq!(cache.limit("checkout"), Some(10.0));
+
+        let later = now + Duration::from_secs(2);
+        assert!(cache.throttled("checkout", later).is_none());
+
+        cache.update("checkout", &decision(false, Duration::ZERO), now);
+        assert!(cache.throttled("checkout", now).is_none());
+    }
+}
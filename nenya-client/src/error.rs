@@ -0,0 +1,44 @@
+use std::fmt;
+
+/// Errors returned by the sentinel client.
+#[derive(Debug)]
+pub enum Error {
+    /// An endpoint address or the TLS settings are invalid.
+    Transport(tonic::transport::Error),
+    /// The sentinel rejected the request or could not be reached.
+    Status(Box<tonic::Status>),
+    /// The auth token contains characters that cannot be sent in a header.
+    InvalidToken,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Transport(err) => write!(f, "invalid sentinel endpoint: {}", err),
+            Error::Status(status) => write!(f, "sentinel request failed: {}", status),
+            Error::InvalidToken => write!(f, "auth tokens must be visible ASCII"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Transport(err) => Some(err),
+            Error::Status(status) => Some(status.as_ref()),
+            Error::InvalidToken => None,
+        }
+    }
+}
+
+impl From<tonic::transport::Error> for Error {
+    fn from(err: tonic::transport::Error) -> Self {
+        Error::Transport(err)
+    }
+}
+
+impl From<tonic::Status> for Error {
+    fn from(status: tonic::Status) -> Self {
+        Error::Status(Box::new(status))
+    }
+}
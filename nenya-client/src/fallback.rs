@@ -0,0 +1,98 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use nenya::{RateLimiter, RateLimiterBuilder};
+
+use crate::Decision;
+
+/// How the client decides requests when the sentinel cannot be reached or does not answer in
+/// time.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Fallback {
+    /// Admit every request.
+    AllowAll,
+    /// Throttle every request.
+    DenyAll,
+    /// Enforce each segment's last target rate reported by the sentinel with a local rate
+    /// limiter, or `default_rate` for segments the sentinel has not reported yet.
+    LocalLimit { default_rate: f32 },
+}
+
+impl Fallback {
+    /// Returns `true` if a failed call to the sentinel should fall back rather than fail.
+    ///
+    /// Only failures to reach the sentinel fall back, so misconfigurations such as a rejected
+    /// token are still reported.
+    pub(crate) fn applies_to(status: &tonic::Status) -> bool {
+        matches!(
+            status.code(),
+            tonic::Code::Unavailable
+                | tonic::Code::DeadlineExceeded
+                | tonic::Code::Cancelled
+                | tonic::Code::Unknown
+        )
+    }
+}
+
+/// Local rate limiters used for watched segments and by [`Fallback::LocalLimit`], created per
+/// segment on first use.
+#[derive(Debug, Default)]
+pub(crate) struct LocalLimiters {
+    limiters: HashMap<String, RateLimiter<f32>>,
+}
+
+impl LocalLimiters {
+    /// Decides a request locally, enforcing `target_rate` for `segment`.
+    pub(crate) fn decide(&mut self, segment: &str, cost: f32, target_rate: f32) -> Decision {
+        let rate_limiter = self
+            .limiters
+            .entry(segment.to_string())
+            .or_insert_with(|| RateLimiterBuilder::new(target_rate).build());
+        rate_limiter.set_fixed_target_rate(Some(target_rate));
+        let throttled = rate_limiter.should_throttle_weighted(if cost == 0.0 { 1.0 } else { cost });
+        Decision {
+            throttled,
+            retry_after: if throttled {
+                rate_limiter.retry_after()
+            } else {
+                Duration::ZERO
+            },
+            limit: target_rate,
+            remaining: rate_limiter.remaining_capacity(),
+            node: String::new(),
+            fallback: true,
+        }
+    }
+}
+
+/// Decides a request without a local limiter, admitting it unless `throttled`.
+pub(crate) fn fixed_decision(throttled: bool, limit: f32) -> Decision {
+    Decision {
+        throttled,
+        retry_after: Duration::ZERO,
+        limit,
+        remaining: 0.0,
+        node: String::new(),
+        fallback: true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_local_limiter_enforces_target_rate() {
+        let mut limiters = LocalLimiters::default();
+        assert!(!limiters.decide("checkout", 1.0, 5.0).throttled);
+        let throttled = (0..10)
+            .filter(|_| limiters.decide("checkout", 1.0, 5.0).throttled)
+            .count();
+        assert!(throttled > 0);
+
+        let decision = limiters.decide("search", 0.0, 5.0);
+        assert!(!decision.throttled);
+        assert!(decision.fallback);
+        assert_eq!(decision.limit, 5.0);
+    }
+}
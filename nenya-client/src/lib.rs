@@ -0,0 +1,686 @@
+//! Async client for the nenya sentinel rate limiting service.
+//!
+//! The client hides the gRPC types behind a [`Client::should_throttle`] call, spreads requests
+//! over a pool of connections to one or more sentinels, and remembers when the sentinel asked a
+//! segment's callers to back off, answering those requests locally until the backoff expires.
+//!
+//! Subscribing to segments with [`Client::watch_targets`] takes the sentinel off the request path
+//! for them: the sentinel pushes their target rates, and the client enforces them locally.
+//!
+//! So that the sentinel does not become a single point of failure, a [`Fallback`] can decide
+//! requests while the sentinel cannot be reached.
+//!
+//! # Example
+//!
+//! ```no_run
+//! use nenya_client::ClientBuilder;
+//!
+//! # async fn example() -> Result<(), nenya_client::Error> {
+//! let client = ClientBuilder::new("http://[::1]:8080")
+//!     .token("secret")
+//!     .build()?;
+//!
+//! let decision = client.should_throttle("checkout", 1.0).await?;
+//! if decision.throttled {
+//!     println!("retry in {:?}", decision.retry_after);
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use tonic::metadata::AsciiMetadataValue;
+use tonic::transport::{Channel, ClientTlsConfig, Endpoint};
+use tonic::Request;
+
+use crate::cache::{DecisionCache, PushedTarget};
+use crate::fallback::LocalLimiters;
+use crate::proto::sentinel_client::SentinelClient;
+use crate::proto::{
+    ShouldThrottleBatchRequest, ShouldThrottleRequest, ShouldThrottleResponse, WatchTargetsRequest,
+};
+
+mod cache;
+mod error;
+mod fallback;
+
+pub use crate::error::Error;
+pub use crate::fallback::Fallback;
+
+/// Generated gRPC types for the sentinel service.
+pub mod proto {
+    tonic::include_proto!("sentinel");
+}
+
+/// Largest number of decisions the sentinel accepts in a single batch.
+const MAX_BATCH_SIZE: usize = 1000;
+
+/// How long a target subscription waits before reconnecting after it fails.
+const WATCH_RETRY_DELAY: Duration = Duration::from_secs(1);
+
+/// A throttling decision for a single request.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Decision {
+    /// Whether the request should be rejected.
+    pub throttled: bool,
+    /// How long to wait before retrying a throttled request; zero when not throttled.
+    pub retry_after: Duration,
+    /// Target rate of the segment, in requests per second.
+    pub limit: f32,
+    /// Accepted requests per second still available under the limit.
+    pub remaining: f32,
+    /// Name of the sentinel node that made the decision. Empty for fallback decisions.
+    pub node: String,
+    /// Whether the decision was made by the fallback because the sentinel could not be reached.
+    pub fallback: bool,
+}
+
+impl From<ShouldThrottleResponse> for Decision {
+    fn from(response: ShouldThrottleResponse) -> Self {
+        Decision {
+            throttled: response.should_throttle,
+            retry_after: Duration::from_millis(response.retry_after_ms),
+            limit: response.limit,
+            remaining: response.remaining,
+            node: response.node,
+            fallback: false,
+        }
+    }
+}
+
+/// Async client for the sentinel.
+///
+/// Clones share the connection pool and the decision cache.
+#[derive(Debug, Clone)]
+pub struct Client {
+    pool: Arc<[SentinelClient<Channel>]>,
+    next: Arc<AtomicUsize>,
+    token: Option<AsciiMetadataValue>,
+    namespace: Option<String>,
+    cache: Arc<Mutex<DecisionCache>>,
+    cache_decisions: bool,
+    fallback: Option<Fallback>,
+    local_limiters: Arc<Mutex<LocalLimiters>>,
+}
+
+impl Client {
+    /// Decides whether a request consuming `cost` units of `segment`'s budget should be
+    /// throttled. A cost of zero counts as a single request.
+    pub async fn should_throttle(&self, segment: &str, cost: f32) -> Result<Decision, Error> {
+        if let Some(decision) = self.local_decision(segment, cost) {
+            return Ok(decision);
+        }
+        let request = ShouldThrottleRequest {
+            segment: Some(segment.to_string()),
+            cost,
+            namespace: self.namespace.clone(),
+        };
+        let response = match self
+            .connection()
+            .should_throttle(self.request(request))
+            .await
+        {
+            Ok(response) => response,
+            Err(status) => return self.fall_back(segment, cost, status),
+        };
+        let decision = Decision::from(response.into_inner());
+        self.cache
+            .lock()
+            .unwrap()
+            .update(segment, &decision, Instant::now());
+        Ok(decision)
+    }
+
+    /// Decides several requests, each given as a segment and a cost, with as few calls to the
+    /// sentinel as possible.
+    ///
+    /// Decisions are returned in the same order as the requests.
+    pub async fn should_throttle_batch(
+        &self,
+        requests: &[(&str, f32)],
+    ) -> Result<Vec<Decision>, Error> {
+        let mut decisions: Vec<Option<Decision>> = requests
+            .iter()
+            .map(|&(segment, cost)| self.local_decision(segment, cost))
+            .collect();
+        let pending: Vec<usize> = (0..requests.len())
+            .filter(|&index| decisions[index].is_none())
+            .collect();
+
+        for chunk in pending.chunks(MAX_BATCH_SIZE) {
+            let batch = ShouldThrottleBatchRequest {
+                requests: chunk
+                    .iter()
+                    .map(|&index| ShouldThrottleRequest {
+                        segment: Some(requests[index].0.to_string()),
+                        cost: requests[index].1,
+                        namespace: self.namespace.clone(),
+                    })
+                    .collect(),
+            };
+            let responses = match self
+                .connection()
+                .should_throttle_batch(self.request(batch))
+                .await
+            {
+                Ok(response) => response.into_inner().responses,
+                Err(status) => {
+                    for &index in chunk {
+                        let (segment, cost) = requests[index];
+                        decisions[index] = Some(self.fall_back(segment, cost, status.clone())?);
+                    }
+                    continue;
+                }
+            };
+
+            let now = Instant::now();
+            let mut cache = self.cache.lock().unwrap();
+            for (&index, response) in chunk.iter().zip(responses) {
+                let decision = Decision::from(response);
+                cache.update(requests[index].0, &decision, now);
+                decisions[index] = Some(decision);
+            }
+        }
+
+        decisions
+            .into_iter()
+            .map(|decision| {
+                decision.ok_or_else(|| {
+                    Error::from(tonic::Status::internal(
+                        "sentinel returned too few decisions",
+                    ))
+                })
+            })
+            .collect()
+    }
+
+    /// Subscribes to the target rates of `segments`.
+    ///
+    /// While subscribed, requests for these segments are decided locally by rate limiters
+    /// enforcing the target rates the sentinel pushes, without calling the sentinel. The
+    /// subscription reconnects after failures, falling back to calling the sentinel meanwhile,
+    /// and ends when the returned [`TargetWatch`] is dropped.
+    pub fn watch_targets(&self, segments: &[&str]) -> TargetWatch {
+        let segments: Vec<String> = segments.iter().map(|segment| segment.to_string()).collect();
+        let client = self.clone();
+        let watched = segments.clone();
+        let task = tokio::spawn(async move {
+            loop {
+                // Streams end when the sentinel shuts down, so both outcomes reconnect
+                let _ = client.watch_once(&watched).await;
+                client.cache.lock().unwrap().clear_pushed(&watched);
+                tokio::time::sleep(WATCH_RETRY_DELAY).await;
+            }
+        });
+        TargetWatch {
+            task,
+            cache: self.cache.clone(),
+            segments,
+        }
+    }
+
+    async fn watch_once(&self, segments: &[String]) -> Result<(), tonic::Status> {
+        let request = WatchTargetsRequest {
+            segments: segments.to_vec(),
+            namespace: self.namespace.clone(),
+        };
+        let mut updates = self
+            .connection()
+            .watch_targets(self.request(request))
+            .await?
+            .into_inner();
+        while let Some(update) = updates.message().await? {
+            let pushed = PushedTarget {
+                target_rate: update.target_tps,
+                node: update.node,
+            };
+            self.cache
+                .lock()
+                .unwrap()
+                .set_pushed(update.segment, pushed);
+        }
+        Ok(())
+    }
+
+    /// Returns the last target rate the sentinel reported for `segment`, in requests per second.
+    pub fn target_rate(&self, segment: &str) -> Option<f32> {
+        self.cache.lock().unwrap().limit(segment)
+    }
+
+    /// Decides a request with the fallback after a failed call to the sentinel, or returns the
+    /// failure if there is no fallback or the sentinel was reached.
+    fn fall_back(
+        &self,
+        segment: &str,
+        cost: f32,
+        status: tonic::Status,
+    ) -> Result<Decision, Error> {
+        let fallback = match self.fallback {
+            Some(fallback) if Fallback::applies_to(&status) => fallback,
+            _ => return Err(status.into()),
+        };
+        let target_rate = self.target_rate(segment);
+        Ok(match fallback {
+            Fallback::AllowAll => fallback::fixed_decision(false, target_rate.unwrap_or(0.0)),
+            Fallback::DenyAll => fallback::fixed_decision(true, target_rate.unwrap_or(0.0)),
+            Fallback::LocalLimit { default_rate } => self.local_limiters.lock().unwrap().decide(
+                segment,
+                cost,
+                target_rate.unwrap_or(default_rate),
+            ),
+        })
+    }
+
+    /// Decides a request without calling the sentinel if its segment's target rate is pushed
+    /// by a subscription, or if the sentinel asked the segment's callers to back off.
+    fn local_decision(&self, segment: &str, cost: f32) -> Option<Decision> {
+        let (pushed, throttled) = {
+            let cache = self.cache.lock().unwrap();
+            let throttled = self
+                .cache_decisions
+                .then(|| cache.throttled(segment, Instant::now()))
+                .flatten();
+            (cache.pushed(segment), throttled)
+        };
+        if let Some(pushed) = pushed {
+            let decision =
+                self.local_limiters
+                    .lock()
+                    .unwrap()
+                    .decide(segment, cost, pushed.target_rate);
+            return Some(Decision {
+                node: pushed.node,
+                fallback: false,
+                ..decision
+            });
+        }
+        throttled
+    }
+
+    /// Returns the next connection from the pool.
+    fn connection(&self) -> SentinelClient<Channel> {
+        let index = self.next.fetch_add(1, Ordering::Relaxed) % self.pool.len();
+        self.pool[index].clone()
+    }
+
+    fn request<T>(&self, message: T) -> Request<T> {
+        let mut request = Request::new(message);
+        if let Some(token) = &self.token {
+            request
+                .metadata_mut()
+                .insert("authorization", token.clone());
+        }
+        request
+    }
+}
+
+/// Subscription to target rates created by [`Client::watch_targets`]. Dropping it ends the
+/// subscription.
+#[derive(Debug)]
+pub struct TargetWatch {
+    task: tokio::task::JoinHandle<()>,
+    cache: Arc<Mutex<DecisionCache>>,
+    segments: Vec<String>,
+}
+
+impl Drop for TargetWatch {
+    fn drop(&mut self) {
+        self.task.abort();
+        self.cache.lock().unwrap().clear_pushed(&self.segments);
+    }
+}
+
+/// Builder for creating a [`Client`].
+#[derive(Debug, Clone)]
+pub struct ClientBuilder {
+    endpoints: Vec<String>,
+    pool_size: usize,
+    timeout: Duration,
+    connect_timeout: Duration,
+    tls: Option<ClientTlsConfig>,
+    token: Option<String>,
+    namespace: Option<String>,
+    cache_decisions: bool,
+    fallback: Option<Fallback>,
+}
+
+impl ClientBuilder {
+    /// Creates a builder for a client of the sentinel at `endpoint`, e.g. `http://[::1]:8080`.
+    pub fn new(endpoint: impl Into<String>) -> Self {
+        ClientBuilder {
+            endpoints: vec![endpoint.into()],
+            pool_size: 1,
+            timeout: Duration::from_millis(100),
+            connect_timeout: Duration::from_secs(1),
+            tls: None,
+            token: None,
+            namespace: None,
+            cache_decisions: true,
+            fallback: None,
+        }
+    }
+
+    /// Adds another sentinel to spread requests over.
+    pub fn endpoint(mut self, endpoint: impl Into<String>) -> Self {
+        self.endpoints.push(endpoint.into());
+        self
+    }
+
+    /// Sets the number of connections opened to each sentinel. Defaults to 1.
+    pub fn pool_size(mut self, pool_size: usize) -> Self {
+        self.pool_size = pool_size.max(1);
+        self
+    }
+
+    /// Sets how long a call to the sentinel may take. Defaults to 100 milliseconds.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Sets how long connecting to a sentinel may take. Defaults to 1 second.
+    pub fn connect_timeout(mut self, connect_timeout: Duration) -> Self {
+        self.connect_timeout = connect_timeout;
+        self
+    }
+
+    /// Connects to the sentinels over TLS. Endpoints must then use `https://`.
+    pub fn tls(mut self, tls: ClientTlsConfig) -> Self {
+        self.tls = Some(tls);
+        self
+    }
+
+    /// Sends `token` as a bearer token with every request.
+    pub fn token(mut self, token: impl Into<String>) -> Self {
+        self.token = Some(token.into());
+        self
+    }
+
+    /// Names segments within `namespace` instead of the sentinel's default namespace.
+    pub fn namespace(mut self, namespace: impl Into<String>) -> Self {
+        self.namespace = Some(namespace.into());
+        self
+    }
+
+    /// Sets whether throttled decisions are reused until their retry-after expires instead of
+    /// asking the sentinel again. Defaults to `true`.
+    pub fn cache_decisions(mut self, cache_decisions: bool) -> Self {
+        self.cache_decisions = cache_decisions;
+        self
+    }
+
+    /// Decides requests with `fallback` when the sentinel cannot be reached or times out.
+    /// Without a fallback, such calls return an error.
+    pub fn fallback(mut self, fallback: Fallback) -> Self {
+        self.fallback = Some(fallback);
+        self
+    }
+
+    /// Builds the client. Connections are established lazily on first use, so this must be
+    /// called from within a Tokio runtime.
+    pub fn build(self) -> Result<Client, Error> {
+        let token = self
+            .token
+            .map(|token| format!("Bearer {}", token).parse())
+            .transpose()
+            .map_err(|_| Error::InvalidToken)?;
+
+        let mut pool = Vec::with_capacity(self.endpoints.len() * self.pool_size);
+        for address in &self.endpoints {
+            let mut endpoint = Endpoint::from_shared(address.clone())?
+                .timeout(self.timeout)
+                .connect_timeout(self.connect_timeout);
+            if let Some(tls) = &self.tls {
+                endpoint = endpoint.tls_config(tls.clone())?;
+            }
+            for _ in 0..self.pool_size {
+                pool.push(SentinelClient::new(endpoint.connect_lazy()));
+            }
+        }
+
+        Ok(Client {
+            pool: pool.into(),
+            next: Arc::new(AtomicUsize::new(0)),
+            token,
+            namespace: self.namespace,
+            cache: Arc::new(Mutex::new(DecisionCache::default())),
+            cache_decisions: self.cache_decisions,
+            fallback: self.fallback,
+            local_limiters: Arc::new(Mutex::new(LocalLimiters::default())),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proto::sentinel_server::{Sentinel, SentinelServer};
+    use crate::proto::{
+        GossipMessage, Metrics, PingRequestMessage, ShouldThrottleBatchResponse, TargetUpdate,
+    };
+    use tokio_stream::wrappers::{ReceiverStream, TcpListenerStream};
+    use tonic::{Response, Status};
+
+    /// Sentinel that throttles every segment named "throttled" and counts the decisions made.
+    #[derive(Debug, Default, Clone)]
+    struct MockSentinel {
+        decisions: Arc<AtomicUsize>,
+    }
+
+    impl MockSentinel {
+        fn decide(&self, request: &ShouldThrottleRequest) -> ShouldThrottleResponse {
+            self.decisions.fetch_add(1, Ordering::Relaxed);
+            let throttled = request.segment.as_deref() == Some("throttled");
+            ShouldThrottleResponse {
+                should_throttle: throttled,
+                retry_after_ms: if throttled { 60_000 } else { 0 },
+                limit: 10.0,
+                remaining: if throttled { 0.0 } else { 10.0 - request.cost },
+                node: "mock".to_string(),
+            }
+        }
+    }
+
+    #[tonic::async_trait]
+    impl Sentinel for MockSentinel {
+        async fn exchange_metrics(
+            &self,
+            request: Request<Metrics>,
+        ) -> Result<Response<Metrics>, Status> {
+            Ok(Response::new(request.into_inner()))
+        }
+
+        async fn should_throttle(
+            &self,
+            request: Request<ShouldThrottleRequest>,
+        ) -> Result<Response<ShouldThrottleResponse>, Status> {
+            Ok(Response::new(self.decide(request.get_ref())))
+        }
+
+        async fn should_throttle_batch(
+            &self,
+            request: Request<ShouldThrottleBatchRequest>,
+        ) -> Result<Response<ShouldThrottleBatchResponse>, Status> {
+            let responses = request
+                .get_ref()
+                .requests
+                .iter()
+                .map(|request| self.decide(request))
+                .collect();
+            Ok(Response::new(ShouldThrottleBatchResponse { responses }))
+        }
+
+        type WatchTargetsStream = ReceiverStream<Result<TargetUpdate, Status>>;
+
+        async fn watch_targets(
+            &self,
+            request: Request<WatchTargetsRequest>,
+        ) -> Result<Response<Self::WatchTargetsStream>, Status> {
+            let segments = request.into_inner().segments;
+            let (sender, receiver) = tokio::sync::mpsc::channel(segments.len());
+            tokio::spawn(async move {
+                for segment in segments {
+                    let update = TargetUpdate {
+                        segment,
+                        target_tps: 2.0,
+                        node: "mock".to_string(),
+                    };
+                    sender.send(Ok(update)).await.unwrap();
+                }
+                sender.closed().await;
+            });
+            Ok(Response::new(ReceiverStream::new(receiver)))
+        }
+
+        async fn gossip(
+            &self,
+            _request: Request<GossipMessage>,
+        ) -> Result<Response<GossipMessage>, Status> {
+            Err(Status::unimplemented("gossip"))
+        }
+
+        async fn ping_request(
+            &self,
+            _request: Request<PingRequestMessage>,
+        ) -> Result<Response<GossipMessage>, Status> {
+            Err(Status::unimplemented("ping_request"))
+        }
+    }
+
+    async fn serve(sentinel: MockSentinel) -> String {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(
+            tonic::transport::Server::builder()
+                .add_service(SentinelServer::new(sentinel))
+                .serve_with_incoming(TcpListenerStream::new(listener)),
+        );
+        address
+    }
+
+    #[tokio::test]
+    async fn test_throttled_decisions_are_answered_locally() {
+        let sentinel = MockSentinel::default();
+        let client = ClientBuilder::new(serve(sentinel.clone()).await)
+            .pool_size(2)
+            .timeout(Duration::from_secs(5))
+            .build()
+            .unwrap();
+
+        let decision = client.should_throttle("checkout", 3.0).await.unwrap();
+        assert!(!decision.throttled);
+        assert_eq!(decision.remaining, 7.0);
+        assert_eq!(decision.node, "mock");
+        assert_eq!(client.target_rate("checkout"), Some(10.0));
+
+        for _ in 0..3 {
+            let decision = client.should_throttle("throttled", 1.0).await.unwrap();
+            assert!(decision.throttled);
+            assert!(decision.retry_after > Duration::ZERO);
+        }
+        assert_eq!(sentinel.decisions.load(Ordering::Relaxed), 2);
+    }
+
+    #[tokio::test]
+    async fn test_batch_skips_cached_decisions() {
+        let sentinel = MockSentinel::default();
+        let client = ClientBuilder::new(serve(sentinel.clone()).await)
+            .timeout(Duration::from_secs(5))
+            .build()
+            .unwrap();
+        client.should_throttle("throttled", 1.0).await.unwrap();
+
+        let decisions = client
+            .should_throttle_batch(&[("checkout", 1.0), ("throttled", 1.0), ("search", 2.0)])
+            .await
+            .unwrap();
+        let throttled: Vec<bool> = decisions
+            .iter()
+            .map(|decision| decision.throttled)
+            .collect();
+        assert_eq!(throttled, [false, true, false]);
+        assert_eq!(sentinel.decisions.load(Ordering::Relaxed), 3);
+    }
+
+    #[tokio::test]
+    async fn test_watched_segments_are_decided_locally() {
+        let sentinel = MockSentinel::default();
+        let client = ClientBuilder::new(serve(sentinel.clone()).await)
+            .timeout(Duration::from_secs(5))
+            .build()
+            .unwrap();
+
+        let watch = client.watch_targets(&["watched"]);
+        while client.target_rate("watched").is_none() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        let mut throttled = 0;
+        for _ in 0..10 {
+            let decision = client.should_throttle("watched", 1.0).await.unwrap();
+            assert_eq!(decision.limit, 2.0);
+            assert_eq!(decision.node, "mock");
+            throttled += decision.throttled as usize;
+        }
+        assert!(throttled > 0);
+        assert_eq!(sentinel.decisions.load(Ordering::Relaxed), 0);
+
+        drop(watch);
+        assert_eq!(client.target_rate("watched"), None);
+        client.should_throttle("watched", 1.0).await.unwrap();
+        assert_eq!(sentinel.decisions.load(Ordering::Relaxed), 1);
+    }
+
+    #[tokio::test]
+    async fn test_fallback_when_sentinel_is_unreachable() {
+        let unreachable = || ClientBuilder::new("http://127.0.0.1:1");
+
+        let client = unreachable().build().unwrap();
+        assert!(matches!(
+            client.should_throttle("checkout", 1.0).await,
+            Err(Error::Status(_))
+        ));
+
+        let client = unreachable().fallback(Fallback::AllowAll).build().unwrap();
+        let decision = client.should_throttle("checkout", 1.0).await.unwrap();
+        assert!(!decision.throttled);
+        assert!(decision.fallback);
+
+        let client = unreachable().fallback(Fallback::DenyAll).build().unwrap();
+        let decisions = client
+            .should_throttle_batch(&[("checkout", 1.0), ("search", 1.0)])
+            .await
+            .unwrap();
+        assert!(decisions.iter().all(|decision| decision.throttled));
+
+        let client = unreachable()
+            .fallback(Fallback::LocalLimit { default_rate: 5.0 })
+            .build()
+            .unwrap();
+        let mut throttled = 0;
+        for _ in 0..10 {
+            let decision = client.should_throttle("checkout", 1.0).await.unwrap();
+            assert_eq!(decision.limit, 5.0);
+            throttled += decision.throttled as usize;
+        }
+        assert!(throttled > 0);
+    }
+
+    #[test]
+    fn test_build_rejects_invalid_settings() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let _guard = runtime.enter();
+        assert!(matches!(
+            ClientBuilder::new("not a uri").build(),
+            Err(Error::Transport(_))
+        ));
+        assert!(matches!(
+            ClientBuilder::new("http://[::1]:8080")
+                .token("line\nbreak")
+                .build(),
+            Err(Error::InvalidToken)
+        ));
+    }
+}
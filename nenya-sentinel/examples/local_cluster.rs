@@ -0,0 +1,294 @@
+use std::error::Error;
+use std::fs::{self, File};
+use std::net::{SocketAddr, TcpListener};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command as Process, Stdio};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use clap::{Arg, Command};
+use nenya_client::{Client, ClientBuilder};
+use tokio::time::MissedTickBehavior;
+
+/// Segment the traffic is sent to.
+const SEGMENT: &str = "checkout";
+
+/// How long to wait for the nodes to start answering.
+const STARTUP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Sentinel processes of the cluster, killed when dropped.
+struct LocalCluster {
+    nodes: Vec<Child>,
+}
+
+impl Drop for LocalCluster {
+    fn drop(&mut self) {
+        for node in &mut self.nodes {
+            let _ = node.kill();
+            let _ = node.wait();
+        }
+    }
+}
+
+/// Returns the sentinel binary built next to this example, in `target/<profile>/`.
+fn default_sentinel() -> PathBuf {
+    let exe = std::env::current_exe().unwrap_or_default();
+    let profile_dir = exe
+        .parent()
+        .and_then(Path::parent)
+        .unwrap_or(Path::new("."));
+    profile_dir.join(format!("nenya-sentinel{}", std::env::consts::EXE_SUFFIX))
+}
+
+/// Reserves `count` free ports on localhost.
+fn free_addrs(count: usize) -> std::io::Result<Vec<SocketAddr>> {
+    // Keep every listener open until all ports are picked so none is picked twice
+    let listeners = (0..count)
+        .map(|_| TcpListener::bind("127.0.0.1:0"))
+        .collect::<std::io::Result<Vec<_>>>()?;
+    listeners.iter().map(TcpListener::local_addr).collect()
+}
+
+/// Returns the config of a node that exchanges metrics with `peers`.
+fn node_config(
+    name: &str,
+    addr: SocketAddr,
+    peers: &[String],
+    exchange_interval_ms: u64,
+    target_tps: f64,
+) -> String {
+    let peers: Vec<String> = peers.iter().map(|peer| format!("{:?}", peer)).collect();
+    format!(
+        r#"listen_addr = "{addr}"
+node_name = "{name}"
+peers = [{peers}]
+exchange_interval_ms = {exchange_interval_ms}
+update_interval_ms = 1000
+
+[pid]
+kp = 0.8
+ki = 0.05
+kd = 0.04
+error_bias = 0.0
+error_limit = 10.0
+output_limit = 3.0
+
+[default_segment]
+target_tps = {target_tps:.1}
+"#,
+        peers = peers.join(", "),
+    )
+}
+
+/// Writes a config for each of `nodes` nodes to `dir` and starts a sentinel with it, logging to
+/// `dir`. Returns the cluster and the endpoint of every node.
+fn launch(
+    sentinel: &Path,
+    dir: &Path,
+    nodes: usize,
+    exchange_interval_ms: u64,
+    target_tps: f64,
+) -> Result<(LocalCluster, Vec<String>), Box<dyn Error>> {
+    let endpoints: Vec<String> = free_addrs(nodes)?
+        .iter()
+        .map(|addr| format!("http://{}", addr))
+        .collect();
+    let mut cluster = LocalCluster { nodes: Vec::new() };
+    for (index, endpoint) in endpoints.iter().enumerate() {
+        let name = format!("sentinel-{}", index + 1);
+        let peers: Vec<String> = endpoints
+            .iter()
+            .filter(|peer| *peer != endpoint)
+            .cloned()
+            .collect();
+        let addr = endpoint.trim_start_matches("http://").parse()?;
+        let config_path = dir.join(format!("{}.toml", name));
+        fs::write(
+            &config_path,
+            node_config(&name, addr, &peers, exchange_interval_ms, target_tps),
+        )?;
+        let log = File::create(dir.join(format!("{}.log", name)))?;
+        let node = Process::new(sentinel)
+            .arg("--config")
+            .arg(&config_path)
+            .stdout(Stdio::from(log.try_clone()?))
+            .stderr(Stdio::from(log))
+            .spawn()
+            .map_err(|err| format!("failed to start {}: {}", sentinel.display(), err))?;
+        cluster.nodes.push(node);
+    }
+    Ok((cluster, endpoints))
+}
+
+/// Waits until `client`'s node answers, or fails after [`STARTUP_TIMEOUT`].
+async fn wait_ready(client: &Client, endpoint: &str) -> Result<(), Box<dyn Error>> {
+    let started = Instant::now();
+    loop {
+        // Probe another segment so the check does not count towards the traffic's rates
+        match client.should_throttle("local-cluster-probe", 1.0).await {
+            Ok(_) => return Ok(()),
+            Err(err) if started.elapsed() > STARTUP_TIMEOUT => {
+                return Err(format!("{} did not start: {}", endpoint, err).into())
+            }
+            Err(_) => tokio::time::sleep(Duration::from_millis(100)).await,
+        }
+    }
+}
+
+/// Sends requests for [`SEGMENT`] to `client`'s node at `rate` until `deadline`, counting the
+/// accepted ones.
+async fn drive(client: Client, rate: f64, deadline: Instant, accepted: Arc<AtomicU64>) {
+    let mut ticks = tokio::time::interval(Duration::from_secs_f64(1.0 / rate));
+    ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    while Instant::now() < deadline {
+        ticks.tick().await;
+        if let Ok(decision) = client.should_throttle(SEGMENT, 1.0).await {
+            if !decision.throttled {
+                accepted.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn Error>> {
+    let matches = Command::new("Local Sentinel Cluster")
+        .about(
+            "Starts a cluster of sentinels on localhost, sends each node a different share of \
+             the traffic, and prints how the cluster converges to the segment's target rate",
+        )
+        .arg(
+            Arg::new("nodes")
+                .short('n')
+                .long("nodes")
+                .value_parser(clap::value_parser!(usize))
+                .default_value("3")
+                .help("Number of sentinel processes to start"),
+        )
+        .arg(
+            Arg::new("target_tps")
+                .short('t')
+                .long("target_tps")
+                .value_parser(clap::value_parser!(f64))
+                .default_value("100.0")
+                .help("Target rate of the segment across the cluster"),
+        )
+        .arg(
+            Arg::new("rate")
+                .short('r')
+                .long("rate")
+                .value_parser(clap::value_parser!(f64))
+                .default_value("300.0")
+                .help("Requests per second sent to the cluster, skewed towards the last nodes"),
+        )
+        .arg(
+            Arg::new("duration")
+                .short('d')
+                .long("duration")
+                .value_parser(clap::value_parser!(u64))
+                .default_value("30")
+                .help("Time to send traffic for (seconds)"),
+        )
+        .arg(
+            Arg::new("exchange_interval")
+                .short('e')
+                .long("exchange_interval")
+                .value_parser(clap::value_parser!(u64))
+                .default_value("500")
+                .help("How often nodes exchange metrics (milliseconds)"),
+        )
+        .arg(
+            Arg::new("sentinel")
+                .long("sentinel")
+                .value_parser(clap::value_parser!(PathBuf))
+                .help("Sentinel binary to run (defaults to the one built next to this example)"),
+        )
+        .get_matches();
+    let nodes = *matches.get_one::<usize>("nodes").unwrap();
+    let target_tps = *matches.get_one::<f64>("target_tps").unwrap();
+    let rate = *matches.get_one::<f64>("rate").unwrap();
+    let duration = Duration::from_secs(*matches.get_one::<u64>("duration").unwrap());
+    let exchange_interval_ms = *matches.get_one::<u64>("exchange_interval").unwrap();
+    let sentinel = matches
+        .get_one::<PathBuf>("sentinel")
+        .cloned()
+        .unwrap_or_else(default_sentinel);
+    if nodes == 0 || rate <= 0.0 {
+        return Err("the cluster needs at least one node and some traffic".into());
+    }
+    if !sentinel.exists() {
+        return Err(format!(
+            "{} not found, build it with `cargo build -p nenya-sentinel` or pass --sentinel",
+            sentinel.display()
+        )
+        .into());
+    }
+
+    let dir = std::env::temp_dir().join(format!("nenya-local-cluster-{}", std::process::id()));
+    fs::create_dir_all(&dir)?;
+    let (_cluster, endpoints) = launch(&sentinel, &dir, nodes, exchange_interval_ms, target_tps)?;
+    println!("Configs and logs of the nodes are in {}", dir.display());
+
+    let mut clients = Vec::new();
+    for endpoint in &endpoints {
+        let client = ClientBuilder::new(endpoint.clone())
+            .cache_decisions(false)
+            .build()?;
+        wait_ready(&client, endpoint).await?;
+        clients.push(client);
+    }
+
+    // Node i gets i + 1 shares of the traffic, so the nodes only agree on each one's share of
+    // the target rate by exchanging their rates
+    let shares = (nodes * (nodes + 1) / 2) as f64;
+    let node_rates: Vec<f64> = (1..=nodes)
+        .map(|share| rate * share as f64 / shares)
+        .collect();
+    let accepted: Vec<Arc<AtomicU64>> = (0..nodes).map(|_| Arc::default()).collect();
+    let deadline = Instant::now() + duration;
+    for ((client, &node_rate), accepted) in clients.into_iter().zip(&node_rates).zip(&accepted) {
+        tokio::spawn(drive(client, node_rate, deadline, accepted.clone()));
+    }
+
+    let header: Vec<String> = endpoints
+        .iter()
+        .enumerate()
+        .map(|(index, _)| format!("{:>12}", format!("sentinel-{}", index + 1)))
+        .collect();
+    println!(
+        "\nAccepted requests per second ({:.0}/s sent, target {:.0}/s)",
+        rate, target_tps
+    );
+    println!("{:>5} {} {:>9}", "Time", header.join(" "), "Cluster");
+    let offered: Vec<String> = node_rates
+        .iter()
+        .map(|node_rate| format!("{:>12.0}", node_rate))
+        .collect();
+    println!("{:>5} {} {:>9.0}", "sent", offered.join(" "), rate);
+
+    let mut ticks = tokio::time::interval(Duration::from_secs(1));
+    ticks.tick().await;
+    let mut previous = vec![0; nodes];
+    for second in 1..=duration.as_secs() {
+        ticks.tick().await;
+        let rates: Vec<u64> = accepted
+            .iter()
+            .zip(&mut previous)
+            .map(|(accepted, previous)| {
+                let total = accepted.load(Ordering::Relaxed);
+                let rate = total - *previous;
+                *previous = total;
+                rate
+            })
+            .collect();
+        let columns: Vec<String> = rates.iter().map(|rate| format!("{:>12}", rate)).collect();
+        println!(
+            "{:>4}s {} {:>9}",
+            second,
+            columns.join(" "),
+            rates.iter().sum::<u64>()
+        );
+    }
+    Ok(())
+}
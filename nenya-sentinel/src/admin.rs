@@ -0,0 +1,722 @@
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::time::Duration;
+
+use nenya::pid_controller::ControllerMode;
+use tonic::{Request, Response, Status};
+
+use crate::auth::CallerIdentity;
+use crate::config::AuthConfig;
+use crate::segment::{ConfigOrigin, Segment, TargetOverride};
+use crate::sentinel::sentinel_admin_server::SentinelAdmin;
+use crate::sentinel::{
+    ConfigSource, ListSegmentsRequest, ListSegmentsResponse, NamespaceStats, NamespaceStatsRequest,
+    NodeStatus, NodeStatusRequest, PidCorrection, PidState, ResetSegmentRequest,
+    SegmentDescription, SegmentRequest, SegmentStats, SegmentStatus, TemporaryOverrideRequest,
+    UpdateSegmentRequest,
+};
+use crate::SentinelService;
+
+/// Admin API for overriding segment settings at runtime.
+///
+/// When token authentication is enabled, only callers listed in `auth.admin_identities` may use
+/// it. Without authentication every caller is allowed, so the admin API should then only be
+/// reachable from trusted networks.
+#[derive(Debug, Clone)]
+pub struct AdminService {
+    sentinel: SentinelService,
+    auth_enabled: bool,
+    admin_identities: Arc<HashSet<String>>,
+}
+
+impl AdminService {
+    pub fn new(sentinel: SentinelService, auth: &AuthConfig) -> Self {
+        AdminService {
+            sentinel,
+            auth_enabled: !auth.tokens.is_empty(),
+            admin_identities: Arc::new(auth.admin_identities.iter().cloned().collect()),
+        }
+    }
+
+    /// Returns the caller's identity if it may use the admin API.
+    fn authorize<T>(&self, request: &Request<T>) -> Option<String> {
+        let identity = request.extensions().get::<CallerIdentity>();
+        if !self.auth_enabled {
+            return Some(identity.map_or_else(|| "anonymous".to_string(), |id| id.0.clone()));
+        }
+        identity
+            .filter(|identity| self.admin_identities.contains(&identity.0))
+            .map(|identity| identity.0.clone())
+    }
+}
+
+#[tonic::async_trait]
+impl SentinelAdmin for AdminService {
+    async fn update_segment(
+        &self,
+        request: Request<UpdateSegmentRequest>,
+    ) -> Result<Response<SegmentStatus>, Status> {
+        let caller = self.authorize(&request).ok_or_else(permission_denied)?;
+        let update = request.into_inner();
+        let status = self.sentinel.update_segment(&update).await?;
+        tracing::info!(
+            %caller,
+            namespace = ?update.namespace,
+            segment = %update.segment,
+            target_tps = ?update.target_tps,
+            min_tps = ?update.min_tps,
+            max_tps = ?update.max_tps,
+            paused = ?update.paused,
+            caller_max_tps = ?update.caller_max_tps,
+            enforcement = ?update.enforcement.map(|_| update.enforcement().as_str_name()),
+            "segment updated"
+        );
+        Ok(Response::new(status))
+    }
+
+    async fn reset_segment(
+        &self,
+        request: Request<ResetSegmentRequest>,
+    ) -> Result<Response<SegmentStatus>, Status> {
+        let caller = self.authorize(&request).ok_or_else(permission_denied)?;
+        let reset = request.into_inner();
+        let status = self
+            .sentinel
+            .reset_segment(reset.namespace.as_deref(), &reset.segment)
+            .await?;
+        tracing::info!(%caller, namespace = ?reset.namespace, segment = %reset.segment, "segment reset");
+        Ok(Response::new(status))
+    }
+
+    async fn set_temporary_override(
+        &self,
+        request: Request<TemporaryOverrideRequest>,
+    ) -> Result<Response<SegmentStatus>, Status> {
+        let caller = self.authorize(&request).ok_or_else(permission_denied)?;
+        let temporary_override = request.into_inner();
+        let status = self
+            .sentinel
+            .set_temporary_override(&temporary_override)
+            .await?;
+        tracing::info!(
+            %caller,
+            namespace = ?temporary_override.namespace,
+            segment = %temporary_override.segment,
+            target_tps = temporary_override.target_tps,
+            ttl_ms = temporary_override.ttl_ms,
+            "temporary override set"
+        );
+        Ok(Response::new(status))
+    }
+
+    async fn get_segment_stats(
+        &self,
+        request: Request<SegmentRequest>,
+    ) -> Result<Response<SegmentStats>, Status> {
+        self.authorize(&request).ok_or_else(permission_denied)?;
+        let request = request.into_inner();
+        let stats = self
+            .sentinel
+            .segment_stats(request.namespace.as_deref(), &request.segment)
+            .await?;
+        Ok(Response::new(stats))
+    }
+
+    async fn list_segments(
+        &self,
+        request: Request<ListSegmentsRequest>,
+    ) -> Result<Response<ListSegmentsResponse>, Status> {
+        self.authorize(&request).ok_or_else(permission_denied)?;
+        let namespace = request.into_inner().namespace;
+        let segments = self.sentinel.list_segments(namespace.as_deref()).await?;
+        Ok(Response::new(ListSegmentsResponse { segments }))
+    }
+
+    async fn describe_segment(
+        &self,
+        request: Request<SegmentRequest>,
+    ) -> Result<Response<SegmentDescription>, Status> {
+        self.authorize(&request).ok_or_else(permission_denied)?;
+        let request = request.into_inner();
+        let description = self
+            .sentinel
+            .describe_segment(request.namespace.as_deref(), &request.segment)
+            .await?;
+        Ok(Response::new(description))
+    }
+
+    async fn get_namespace_stats(
+        &self,
+        request: Request<NamespaceStatsRequest>,
+    ) -> Result<Response<NamespaceStats>, Status> {
+        self.authorize(&request).ok_or_else(permission_denied)?;
+        let namespace = request.into_inner().namespace;
+        let stats = self.sentinel.namespace_stats(namespace.as_deref()).await?;
+        Ok(Response::new(stats))
+    }
+
+    async fn get_node_status(
+        &self,
+        request: Request<NodeStatusRequest>,
+    ) -> Result<Response<NodeStatus>, Status> {
+        self.authorize(&request).ok_or_else(permission_denied)?;
+        Ok(Response::new(self.sentinel.node_status().await))
+    }
+}
+
+impl SentinelService {
+    /// Applies runtime overrides to a segment, creating it if needed.
+    ///
+    /// Overridden segments are exempt from idle eviction so the overrides are not lost.
+    pub async fn update_segment(
+        &self,
+        update: &UpdateSegmentRequest,
+    ) -> Result<SegmentStatus, Status> {
+        if update.segment.is_empty() {
+            return Err(Status::invalid_argument("segment is required"));
+        }
+        let rates = [update.target_tps, update.min_tps, update.max_tps];
+        if rates
+            .into_iter()
+            .flatten()
+            .any(|rate| !rate.is_finite() || rate < 0.0)
+        {
+            return Err(Status::invalid_argument(
+                "rates must be non-negative numbers",
+            ));
+        }
+        if update
+            .caller_max_tps
+            .is_some_and(|caller_max_tps| !caller_max_tps.is_finite() || caller_max_tps <= 0.0)
+        {
+            return Err(Status::invalid_argument(
+                "caller_max_tps must be a positive number",
+            ));
+        }
+
+        let key = self
+            .segment_factory
+            .segment_key(update.namespace.as_deref(), &update.segment)
+            .ok_or_else(|| crate::unknown_namespace(update.namespace.as_deref()))?;
+
+        let mut segments = self.segments.write().await;
+        let segment = self.get_or_create_segment(&mut segments, &key);
+        let rate_limiter = &mut segment.rate_limiter;
+        let min_tps = update.min_tps.unwrap_or(rate_limiter.min_rate());
+        let max_tps = update.max_tps.unwrap_or(rate_limiter.max_rate());
+        if min_tps > max_tps {
+            return Err(Status::invalid_argument("min_tps must not exceed max_tps"));
+        }
+        if let Some(target_tps) = update.target_tps {
+            if target_tps < min_tps || target_tps > max_tps {
+                return Err(Status::invalid_argument(
+                    "target_tps must be between min_tps and max_tps",
+                ));
+            }
+        }
+
+        rate_limiter.set_rate_bounds(min_tps, max_tps);
+        if let Some(target_tps) = update.target_tps {
+            rate_limiter.set_target_rate(target_tps);
+        }
+        if let Some(paused) = update.paused {
+            segment.paused = paused;
+        }
+        if let Some(caller_max_tps) = update.caller_max_tps {
+            segment.set_caller_max_tps(Some(caller_max_tps));
+        }
+        if update.enforcement.is_some() {
+            segment.enforcement = update.enforcement();
+        }
+        segment.pin();
+        Ok(self.segment_status(&key, segment))
+    }
+
+    /// Enforces a target rate for a segment until the override's TTL expires, after which the
+    /// configured rates and PID control resume. Replaces any active override.
+    pub async fn set_temporary_override(
+        &self,
+        request: &TemporaryOverrideRequest,
+    ) -> Result<SegmentStatus, Status> {
+        if request.segment.is_empty() {
+            return Err(Status::invalid_argument("segment is required"));
+        }
+        if !request.target_tps.is_finite() || request.target_tps < 0.0 {
+            return Err(Status::invalid_argument(
+                "target_tps must be a non-negative number",
+            ));
+        }
+        if request.ttl_ms == 0 {
+            return Err(Status::invalid_argument("ttl_ms must be positive"));
+        }
+
+        let key = self
+            .segment_factory
+            .segment_key(request.namespace.as_deref(), &request.segment)
+            .ok_or_else(|| crate::unknown_namespace(request.namespace.as_deref()))?;
+        let mut segments = self.segments.write().await;
+        let segment = self.get_or_create_segment(&mut segments, &key);
+        segment.set_target_override(Some(TargetOverride {
+            target_tps: request.target_tps,
+            expires_at: self.now() + Duration::from_millis(request.ttl_ms),
+        }));
+        segment.pin();
+        Ok(self.segment_status(&key, segment))
+    }
+
+    /// Restores a segment to its configured rates, resuming enforcement and discarding the
+    /// observed requests, controller state and any temporary override.
+    pub async fn reset_segment(
+        &self,
+        namespace: Option<&str>,
+        name: &str,
+    ) -> Result<SegmentStatus, Status> {
+        let key = self
+            .segment_factory
+            .segment_key(namespace, name)
+            .ok_or_else(|| crate::unknown_namespace(namespace))?;
+        let mut segments = self.segments.write().await;
+        let segment = segments
+            .get_mut(&key)
+            .ok_or_else(|| unknown_segment(name))?;
+        let config = self.segment_factory.config(&key);
+        let rate_limiter = &mut segment.rate_limiter;
+        rate_limiter.reset();
+        rate_limiter.set_rate_bounds(
+            config.min_tps.unwrap_or(config.target_tps),
+            config.max_tps.unwrap_or(config.target_tps),
+        );
+        rate_limiter.set_target_rate(config.target_tps);
+        segment.set_caller_max_tps(None);
+        segment.set_caller_max_tps(config.caller_max_tps);
+        segment.enforcement = config.enforcement();
+        segment.shadow_throttled = 0;
+        segment.paused = false;
+        segment.set_target_override(None);
+        Ok(self.segment_status(&key, segment))
+    }
+
+    /// Returns the rates of a segment on this node.
+    pub async fn segment_stats(
+        &self,
+        namespace: Option<&str>,
+        name: &str,
+    ) -> Result<SegmentStats, Status> {
+        let key = self
+            .segment_factory
+            .segment_key(namespace, name)
+            .ok_or_else(|| crate::unknown_namespace(namespace))?;
+        let segments = self.segments.read().await;
+        let segment = segments.get(&key).ok_or_else(|| unknown_segment(name))?;
+        Ok(self.segment_stats_of(&key, segment))
+    }
+
+    /// Returns the status of every segment on this node in `namespace`, or in every namespace if
+    /// `None`, ordered by key.
+    pub async fn list_segments(
+        &self,
+        namespace: Option<&str>,
+    ) -> Result<Vec<SegmentStatus>, Status> {
+        let namespace = namespace.map(|namespace| Some(namespace).filter(|ns| !ns.is_empty()));
+        if let Some(Some(namespace)) = namespace {
+            if !self.segment_factory.has_namespace(namespace) {
+                return Err(crate::unknown_namespace(Some(namespace)));
+            }
+        }
+        let segments = self.segments.read().await;
+        let mut keys: Vec<&String> = segments
+            .keys()
+            .filter(|key| {
+                namespace
+                    .is_none_or(|namespace| self.segment_factory.namespace_of(key) == namespace)
+            })
+            .collect();
+        keys.sort();
+        Ok(keys
+            .into_iter()
+            .map(|key| self.segment_status(key, &segments[key]))
+            .collect())
+    }
+
+    /// Returns everything known about a segment on this node: its status, rates, PID controller
+    /// state, and where its config comes from.
+    pub async fn describe_segment(
+        &self,
+        namespace: Option<&str>,
+        name: &str,
+    ) -> Result<SegmentDescription, Status> {
+        let key = self
+            .segment_factory
+            .segment_key(namespace, name)
+            .ok_or_else(|| crate::unknown_namespace(namespace))?;
+        let segments = self.segments.read().await;
+        let segment = segments.get(&key).ok_or_else(|| unknown_segment(name))?;
+        let pid_controller = segment.rate_limiter.pid_controller();
+        let saturation_state = pid_controller.saturation_state();
+        let (origin, config) = self.segment_factory.resolve_config(&key);
+        let (config_source, config_pattern) = match origin {
+            ConfigOrigin::Segment => (ConfigSource::Segment, None),
+            ConfigOrigin::Pattern(prefix) => (ConfigSource::Pattern, Some(format!("{}*", prefix))),
+            ConfigOrigin::Namespace => (ConfigSource::Namespace, None),
+            ConfigOrigin::Default => (ConfigSource::Default, None),
+        };
+        Ok(SegmentDescription {
+            status: Some(self.segment_status(&key, segment)),
+            stats: Some(self.segment_stats_of(&key, segment)),
+            pid: Some(PidState {
+                setpoint: pid_controller.setpoint(),
+                accumulated_error: pid_controller.accumulated_error(),
+                manual: pid_controller.mode() == ControllerMode::Manual,
+                output_clamped: saturation_state.output_clamped,
+                integral_clamped: saturation_state.integral_clamped,
+                last_correction: pid_controller
+                    .history()
+                    .back()
+                    .map(|correction| PidCorrection {
+                        signal: correction.signal,
+                        error: correction.error,
+                        p: correction.p,
+                        i: correction.i,
+                        d: correction.d,
+                        output: correction.output,
+                    }),
+            }),
+            config_source: config_source.into(),
+            config_pattern,
+            config: Some(config.clone()),
+            dynamic: segment.is_dynamic(),
+        })
+    }
+
+    /// Returns the totals over the segments of `namespace` on this node, or of the default
+    /// namespace if `None`.
+    pub async fn namespace_stats(&self, namespace: Option<&str>) -> Result<NamespaceStats, Status> {
+        let namespace = namespace.filter(|namespace| !namespace.is_empty());
+        if let Some(namespace) = namespace {
+            if !self.segment_factory.has_namespace(namespace) {
+                return Err(crate::unknown_namespace(Some(namespace)));
+            }
+        }
+        let mut stats = NamespaceStats {
+            namespace: namespace.unwrap_or_default().to_string(),
+            ..Default::default()
+        };
+        let segments = self.segments.read().await;
+        for (key, segment) in segments.iter() {
+            if self.segment_factory.namespace_of(key) != namespace {
+                continue;
+            }
+            let rate_limiter = &segment.rate_limiter;
+            stats.segments += 1;
+            stats.target_tps += rate_limiter.target_rate();
+            stats.request_rate += rate_limiter.request_rate();
+            stats.accepted_request_rate += rate_limiter.accepted_request_rate();
+        }
+        Ok(stats)
+    }
+
+    /// Returns the load of this node.
+    pub async fn node_status(&self) -> NodeStatus {
+        let mut status = NodeStatus {
+            node: self.hostname.clone(),
+            segments: self.segments.read().await.len() as u32,
+            ..Default::default()
+        };
+        if let Some(overload) = &self.overload {
+            status.saturation = overload.saturation();
+            status.rpc_rate = overload.rpc_rate();
+            status.event_loop_lag_ms = overload.event_loop_lag().as_millis() as u64;
+            status.shed_exchanges = overload.shed_exchanges();
+        }
+        status
+    }
+
+    fn segment_stats_of(&self, key: &str, segment: &Segment) -> SegmentStats {
+        let rate_limiter = &segment.rate_limiter;
+        SegmentStats {
+            segment: self.segment_factory.segment_name(key).to_string(),
+            namespace: self
+                .segment_factory
+                .namespace_of(key)
+                .unwrap_or_default()
+                .to_string(),
+            target_tps: rate_limiter.target_rate(),
+            request_rate: rate_limiter.request_rate(),
+            accepted_request_rate: rate_limiter.accepted_request_rate(),
+            local_request_rate: rate_limiter.local_request_rate(),
+            local_accepted_request_rate: rate_limiter.local_accepted_request_rate(),
+            external_request_rate: rate_limiter.external_request_rate(),
+            external_accepted_request_rate: rate_limiter.external_accepted_request_rate(),
+            fixed_target_tps: rate_limiter.fixed_target_rate(),
+        }
+    }
+
+    fn segment_status(&self, key: &str, segment: &Segment) -> SegmentStatus {
+        let rate_limiter = &segment.rate_limiter;
+        SegmentStatus {
+            segment: self.segment_factory.segment_name(key).to_string(),
+            target_tps: rate_limiter.target_rate(),
+            min_tps: rate_limiter.min_rate(),
+            max_tps: rate_limiter.max_rate(),
+            paused: segment.paused,
+            request_rate: rate_limiter.request_rate(),
+            accepted_request_rate: rate_limiter.accepted_request_rate(),
+            namespace: self
+                .segment_factory
+                .namespace_of(key)
+                .unwrap_or_default()
+                .to_string(),
+            caller_max_tps: segment
+                .caller_quotas
+                .as_ref()
+                .map(|caller_quotas| caller_quotas.key_rate()),
+            enforcement: segment.enforcement.into(),
+            shadow_throttled: segment.shadow_throttled,
+            override_tps: segment
+                .target_override()
+                .map(|target_override| target_override.target_tps),
+            override_remaining_ms: segment.target_override().map_or(0, |target_override| {
+                target_override
+                    .expires_at
+                    .duration_since(self.now())
+                    .as_millis() as u64
+            }),
+        }
+    }
+}
+
+fn unknown_segment(name: &str) -> Status {
+    Status::not_found(format!("unknown segment {:?}", name))
+}
+
+fn permission_denied() -> Status {
+    Status::permission_denied("caller is not allowed to use the admin API")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sentinel::SegmentConfig;
+    use nenya::pid_controller::PIDController;
+    use std::collections::HashMap;
+    use std::time::Duration;
+
+    fn create_service() -> SentinelService {
+        SentinelService::new(
+            "local".to_string(),
+            HashMap::new(),
+            SegmentConfig {
+                target_tps: 10.0,
+                min_tps: None,
+                max_tps: None,
+                ..Default::default()
+            },
+            PIDController::new_static_controller(10.0),
+            Duration::from_secs(1),
+        )
+    }
+
+    fn update(segment: &str) -> UpdateSegmentRequest {
+        UpdateSegmentRequest {
+            segment: segment.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_update_and_reset_segment() {
+        let service = create_service();
+        let status = service
+            .update_segment(&UpdateSegmentRequest {
+                target_tps: Some(20.0),
+                max_tps: Some(30.0),
+                paused: Some(true),
+                ..update("search")
+            })
+            .await
+            .unwrap();
+        assert_eq!(status.target_tps, 20.0);
+        assert_eq!(status.min_tps, 10.0);
+        assert_eq!(status.max_tps, 30.0);
+        assert!(status.paused);
+        for _ in 0..100 {
+            let response = service.check_segment("search", None, 1.0).await;
+            assert!(!response.should_throttle);
+            assert_eq!(response.retry_after_ms, 0);
+        }
+
+        // Overridden segments are not evicted
+        let later = service.now() + Duration::from_secs(60);
+        assert!(service
+            .evict_idle_segments(later, Duration::from_secs(1))
+            .await
+            .is_empty());
+
+        let status = service.reset_segment(None, "search").await.unwrap();
+        assert_eq!(status.target_tps, 10.0);
+        assert_eq!(status.max_tps, 10.0);
+        assert!(!status.paused);
+    }
+
+    #[tokio::test]
+    async fn test_update_rejects_invalid_rates() {
+        let service = create_service();
+        let invalid = [
+            UpdateSegmentRequest {
+                min_tps: Some(-1.0),
+                ..update("search")
+            },
+            UpdateSegmentRequest {
+                min_tps: Some(5.0),
+                max_tps: Some(4.0),
+                ..update("search")
+            },
+            UpdateSegmentRequest {
+                target_tps: Some(50.0),
+                max_tps: Some(40.0),
+                ..update("search")
+            },
+            update(""),
+        ];
+        for request in invalid {
+            let status = service.update_segment(&request).await.unwrap_err();
+            assert_eq!(status.code(), tonic::Code::InvalidArgument);
+        }
+        assert!(service.reset_segment(None, "unknown").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_temporary_override() {
+        let service = create_service();
+        let request = TemporaryOverrideRequest {
+            segment: "sale".to_string(),
+            target_tps: 20.0,
+            ttl_ms: 60_000,
+            ..Default::default()
+        };
+        let status = service.set_temporary_override(&request).await.unwrap();
+        assert_eq!(status.target_tps, 20.0);
+        assert_eq!(status.override_tps, Some(20.0));
+        assert!(status.override_remaining_ms > 0 && status.override_remaining_ms <= 60_000);
+
+        let invalid = TemporaryOverrideRequest {
+            ttl_ms: 0,
+            ..request.clone()
+        };
+        let status = service.set_temporary_override(&invalid).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+
+        let status = service.reset_segment(None, "sale").await.unwrap();
+        assert_eq!(status.target_tps, 10.0);
+        assert_eq!(status.override_tps, None);
+    }
+
+    #[tokio::test]
+    async fn test_segment_introspection() {
+        let service = SentinelService::new(
+            "local".to_string(),
+            HashMap::from([(
+                "api.*".to_string(),
+                SegmentConfig {
+                    target_tps: 20.0,
+                    ..Default::default()
+                },
+            )]),
+            SegmentConfig {
+                target_tps: 10.0,
+                ..Default::default()
+            },
+            PIDController::new_static_controller(10.0),
+            Duration::from_secs(1),
+        );
+        for segment in ["web", "api.users"] {
+            service.check_segment(segment, None, 1.0).await;
+        }
+
+        let stats = service.segment_stats(None, "api.users").await.unwrap();
+        assert_eq!(stats.target_tps, 20.0);
+        assert_eq!(stats.external_request_rate, 0.0);
+        assert_eq!(stats.fixed_target_tps, None);
+
+        let segments = service.list_segments(None).await.unwrap();
+        let names: Vec<&str> = segments.iter().map(|s| s.segment.as_str()).collect();
+        assert_eq!(names, ["api.users", "web"]);
+
+        let description = service.describe_segment(None, "api.users").await.unwrap();
+        assert_eq!(description.config_source(), ConfigSource::Pattern);
+        assert_eq!(description.config_pattern.as_deref(), Some("api.*"));
+        assert_eq!(description.config.unwrap().target_tps, 20.0);
+        assert_eq!(description.pid.unwrap().setpoint, 20.0);
+        assert!(description.dynamic);
+
+        let description = service.describe_segment(None, "web").await.unwrap();
+        assert_eq!(description.config_source(), ConfigSource::Default);
+
+        let status = service.segment_stats(None, "unknown").await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::NotFound);
+    }
+
+    #[tokio::test]
+    async fn test_namespace_stats() {
+        let service = create_service().with_namespaces(HashMap::from([(
+            "payments".to_string(),
+            SegmentConfig {
+                target_tps: 20.0,
+                min_tps: None,
+                max_tps: None,
+                ..Default::default()
+            },
+        )]));
+        let status = service
+            .update_segment(&UpdateSegmentRequest {
+                namespace: Some("payments".to_string()),
+                paused: Some(true),
+                ..update("checkout")
+            })
+            .await
+            .unwrap();
+        assert_eq!(status.segment, "checkout");
+        assert_eq!(status.namespace, "payments");
+        service.check_segment("payments/search", None, 1.0).await;
+        service.check_segment("search", None, 1.0).await;
+
+        let stats = service.namespace_stats(Some("payments")).await.unwrap();
+        assert_eq!(stats.segments, 2);
+        assert_eq!(stats.target_tps, 40.0);
+        let stats = service.namespace_stats(None).await.unwrap();
+        assert_eq!(stats.segments, 1);
+        assert_eq!(stats.target_tps, 10.0);
+
+        assert!(service.namespace_stats(Some("search")).await.is_err());
+        assert!(service
+            .reset_segment(Some("payments"), "search")
+            .await
+            .is_ok());
+    }
+
+    #[test]
+    fn test_authorize_requires_admin_identity() {
+        let auth: AuthConfig = toml::from_str(
+            r#"
+            admin_identities = ["operator"]
+
+            [[tokens]]
+            identity = "operator"
+            token = "secret"
+            "#,
+        )
+        .unwrap();
+        let admin = AdminService::new(create_service(), &auth);
+
+        let mut request = Request::new(());
+        assert_eq!(admin.authorize(&request), None);
+        request
+            .extensions_mut()
+            .insert(CallerIdentity("checkout".to_string()));
+        assert_eq!(admin.authorize(&request), None);
+        request
+            .extensions_mut()
+            .insert(CallerIdentity("operator".to_string()));
+        assert_eq!(admin.authorize(&request), Some("operator".to_string()));
+    }
+}
@@ -0,0 +1,192 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::io::{self, BufWriter, Write};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::Serialize;
+
+/// Number of records buffered for the writer before new records are dropped.
+const AUDIT_BUFFER_SIZE: usize = 4096;
+
+/// Why a request was, or in observe mode would have been, throttled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ThrottleReason {
+    /// The segment's target rate was exceeded.
+    Segment,
+    /// The caller exceeded its quota of the segment.
+    CallerQuota,
+}
+
+/// A single throttle decision, written as one line of JSON.
+#[derive(Debug, Clone, Serialize)]
+pub struct AuditRecord {
+    /// Milliseconds since the Unix epoch.
+    pub timestamp_ms: u64,
+    pub node: String,
+    /// `None` for the default namespace.
+    pub namespace: Option<String>,
+    pub segment: String,
+    pub caller: Option<String>,
+    pub cost: f32,
+    pub target_tps: f32,
+    pub request_rate: f32,
+    pub accepted_request_rate: f32,
+    pub external_request_rate: f32,
+    pub throttled: bool,
+    /// Set if the request was throttled, or would have been in observe mode.
+    pub reason: Option<ThrottleReason>,
+    /// Set if the request was admitted only because the segment is in observe mode.
+    pub observed: bool,
+}
+
+/// Logs a sample of throttle decisions as JSON lines, for analyzing who got throttled and why.
+///
+/// Records are written by a dedicated thread so decisions never wait on the output. If the
+/// writer falls behind, records are dropped and counted rather than buffered without bound.
+#[derive(Debug)]
+pub struct AuditLog {
+    sample_rate: f64,
+    sender: SyncSender<AuditRecord>,
+    dropped: AtomicU64,
+}
+
+impl AuditLog {
+    /// Starts writing a `sample_rate` fraction of the decisions to `output`.
+    pub fn start(sample_rate: f64, output: Box<dyn Write + Send>) -> Self {
+        let (sender, receiver) = mpsc::sync_channel(AUDIT_BUFFER_SIZE);
+        std::thread::Builder::new()
+            .name("audit-log".to_string())
+            .spawn(move || write_records(receiver, output))
+            .expect("failed to spawn audit log writer");
+        AuditLog {
+            sample_rate,
+            sender,
+            dropped: AtomicU64::new(0),
+        }
+    }
+
+    /// Returns `true` if the current decision should be recorded.
+    pub fn sample(&self) -> bool {
+        if self.sample_rate >= 1.0 {
+            return true;
+        }
+        let random = RandomState::new().build_hasher().finish();
+        (random as f64 / u64::MAX as f64) < self.sample_rate
+    }
+
+    /// Queues `record` to be written.
+    pub fn record(&self, record: AuditRecord) {
+        match self.sender.try_send(record) {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) => {
+                let dropped = self.dropped.fetch_add(1, Ordering::Relaxed) + 1;
+                if dropped.is_power_of_two() {
+                    tracing::warn!(dropped, "audit log writer is behind, dropping records");
+                }
+            }
+            Err(TrySendError::Disconnected(_)) => {
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// Returns the number of records dropped so far.
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+/// Returns the current time in milliseconds since the Unix epoch.
+pub fn timestamp_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_millis() as u64)
+}
+
+/// Writes records until the audit log is dropped, flushing whenever no more records are queued.
+fn write_records(receiver: Receiver<AuditRecord>, output: Box<dyn Write + Send>) {
+    let mut output = BufWriter::new(output);
+    while let Ok(record) = receiver.recv() {
+        let mut result = write_record(&mut output, &record);
+        while let (Ok(()), Ok(record)) = (&result, receiver.try_recv()) {
+            result = write_record(&mut output, &record);
+        }
+        if let Err(err) = result.and_then(|()| output.flush()) {
+            tracing::error!(%err, "failed to write audit log, stopping it");
+            return;
+        }
+    }
+}
+
+fn write_record(output: &mut impl Write, record: &AuditRecord) -> io::Result<()> {
+    serde_json::to_writer(&mut *output, record)?;
+    output.write_all(b"\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn record(segment: &str) -> AuditRecord {
+        AuditRecord {
+            timestamp_ms: 1,
+            node: "local".to_string(),
+            namespace: None,
+            segment: segment.to_string(),
+            caller: Some("checkout".to_string()),
+            cost: 1.0,
+            target_tps: 10.0,
+            request_rate: 12.0,
+            accepted_request_rate: 10.0,
+            external_request_rate: 0.0,
+            throttled: true,
+            reason: Some(ThrottleReason::CallerQuota),
+            observed: false,
+        }
+    }
+
+    #[test]
+    fn test_writes_sampled_records_as_json_lines() {
+        let buffer = SharedBuffer::default();
+        let audit = AuditLog::start(1.0, Box::new(buffer.clone()));
+        assert!(audit.sample());
+        audit.record(record("search"));
+        audit.record(record("orders"));
+
+        let mut lines = Vec::new();
+        for _ in 0..100 {
+            let output = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+            lines = output.lines().map(str::to_string).collect();
+            if lines.len() == 2 {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].contains(r#""segment":"search""#));
+        assert!(lines[0].contains(r#""reason":"caller_quota""#));
+        assert!(lines[1].contains(r#""segment":"orders""#));
+        assert_eq!(audit.dropped(), 0);
+
+        let unsampled = AuditLog::start(0.0, Box::new(io::sink()));
+        assert!(!unsampled.sample());
+    }
+}
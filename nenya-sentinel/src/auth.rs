@@ -0,0 +1,145 @@
+use tonic::metadata::{AsciiMetadataValue, MetadataMap};
+use tonic::service::Interceptor;
+use tonic::{Request, Status};
+
+use crate::config::{AuthConfig, ConfigError};
+
+/// Identity of an authenticated caller, stored in the request extensions.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CallerIdentity(pub String);
+
+/// Interceptor that validates a bearer token or API key on every request.
+///
+/// Tokens are read from the `authorization: Bearer <token>` header or the `x-api-key` header. On
+/// success the caller's [`CallerIdentity`] is inserted into the request extensions. When no
+/// tokens are configured, every request is let through without an identity.
+#[derive(Debug, Clone, Default)]
+pub struct TokenAuthenticator {
+    tokens: Vec<(String, CallerIdentity)>,
+}
+
+impl TokenAuthenticator {
+    /// Creates an authenticator that accepts the given `(token, identity)` pairs.
+    pub fn new(tokens: Vec<(String, CallerIdentity)>) -> Self {
+        TokenAuthenticator { tokens }
+    }
+
+    /// Creates an authenticator from the `[auth]` config section, resolving tokens from the
+    /// environment where configured.
+    pub fn from_config(config: &AuthConfig) -> Result<Self, ConfigError> {
+        let tokens = config
+            .tokens
+            .iter()
+            .map(|token| Ok((token.resolve()?, CallerIdentity(token.identity.clone()))))
+            .collect::<Result<Vec<_>, ConfigError>>()?;
+        Ok(TokenAuthenticator::new(tokens))
+    }
+
+    /// Returns `true` if requests must carry a valid token.
+    pub fn is_enabled(&self) -> bool {
+        !self.tokens.is_empty()
+    }
+
+    /// Returns the identity matching the token presented in `metadata`, if any.
+    pub fn authenticate(&self, metadata: &MetadataMap) -> Option<CallerIdentity> {
+        let presented = presented_token(metadata)?;
+        self.tokens
+            .iter()
+            .find(|(token, _)| constant_time_eq(token.as_bytes(), presented.as_bytes()))
+            .map(|(_, identity)| identity.clone())
+    }
+}
+
+impl Interceptor for TokenAuthenticator {
+    fn call(&mut self, mut request: Request<()>) -> Result<Request<()>, Status> {
+        if !self.is_enabled() {
+            return Ok(request);
+        }
+        let identity = self
+            .authenticate(request.metadata())
+            .ok_or_else(|| Status::unauthenticated("missing or invalid token"))?;
+        request.extensions_mut().insert(identity);
+        Ok(request)
+    }
+}
+
+/// Attaches a bearer token to an outgoing request, if one is configured.
+pub fn with_token<T>(message: T, token: Option<&AsciiMetadataValue>) -> Request<T> {
+    let mut request = Request::new(message);
+    if let Some(token) = token {
+        request
+            .metadata_mut()
+            .insert("authorization", token.clone());
+    }
+    request
+}
+
+/// Builds the `authorization` header value for a bearer token.
+pub fn bearer_token(token: &str) -> Result<AsciiMetadataValue, ConfigError> {
+    format!("Bearer {}", token)
+        .parse()
+        .map_err(|_| ConfigError::Invalid("auth tokens must be visible ASCII".to_string()))
+}
+
+fn presented_token(metadata: &MetadataMap) -> Option<&str> {
+    if let Some(authorization) = metadata.get("authorization") {
+        return authorization.to_str().ok()?.strip_prefix("Bearer ");
+    }
+    metadata.get("x-api-key")?.to_str().ok()
+}
+
+/// Compares two byte strings without short-circuiting on the first mismatch.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_authenticator() -> TokenAuthenticator {
+        TokenAuthenticator::new(vec![(
+            "secret".to_string(),
+            CallerIdentity("checkout".to_string()),
+        )])
+    }
+
+    #[test]
+    fn test_accepts_bearer_token_and_api_key() {
+        let mut authenticator = create_authenticator();
+
+        let request = with_token((), Some(&bearer_token("secret").unwrap()));
+        let request = authenticator.call(request).unwrap();
+        assert_eq!(
+            request.extensions().get::<CallerIdentity>(),
+            Some(&CallerIdentity("checkout".to_string()))
+        );
+
+        let mut request = Request::new(());
+        request
+            .metadata_mut()
+            .insert("x-api-key", "secret".parse().unwrap());
+        assert!(authenticator.call(request).is_ok());
+    }
+
+    #[test]
+    fn test_rejects_missing_or_invalid_token() {
+        let mut authenticator = create_authenticator();
+
+        let status = authenticator.call(Request::new(())).unwrap_err();
+        assert_eq!(status.code(), tonic::Code::Unauthenticated);
+
+        let request = with_token((), Some(&bearer_token("wrong").unwrap()));
+        assert!(authenticator.call(request).is_err());
+    }
+
+    #[test]
+    fn test_disabled_authenticator_allows_all() {
+        let mut authenticator = TokenAuthenticator::default();
+        let request = authenticator.call(Request::new(())).unwrap();
+        assert!(request.extensions().get::<CallerIdentity>().is_none());
+    }
+}
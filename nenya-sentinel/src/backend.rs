@@ -0,0 +1,562 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use futures_util::StreamExt;
+use nenya::distributed::{
+    BackendError, BackendFuture, DistributedStateBackend, NodeMetrics, SegmentRates, TargetShares,
+};
+use tonic::transport::Channel;
+
+use crate::chaos::Chaos;
+use crate::locality::Locality;
+use crate::payload::ExchangeLimits;
+use crate::peer::PeerConnector;
+use crate::protocol::{self, PeerProtocol};
+use crate::retry::{PeerCircuit, RetryPolicy};
+use crate::sentinel::sentinel_client::SentinelClient;
+use crate::sentinel::{Capability, MetricData, Metrics, NodeShares};
+
+/// Backend that shares metrics by exchanging them directly with every peer over gRPC.
+///
+/// Publishing pushes this node's metrics to each peer and records the metrics the peer returns.
+/// Peers push their metrics to this node the same way, and the sentinel records them with
+/// [`MeshBackend::record`].
+///
+/// Exchanges with peers run concurrently, and peers whose exchanges keep failing are skipped
+/// for a while as set by the [`RetryPolicy`], so a flapping peer neither delays the exchanges
+/// with healthy peers nor gets retried on every tick.
+///
+/// Metrics not refreshed within the staleness window are dropped, so a crashed peer stops
+/// counting towards the cluster-wide rates.
+///
+/// Once a peer has received a full snapshot, only the segments that changed since are sent to
+/// it, and snapshots exceeding the [`ExchangeLimits`] are split into several pages. Peers that
+/// have not advertised support for delta exchanges, e.g. because they run an older version, get
+/// a single snapshot truncated to the limits instead.
+///
+/// The coordinator lease is held by the node with the lowest name among this node and the peers
+/// heard from within the lease TTL, and the coordinator's target shares travel with its metrics.
+#[derive(Debug)]
+pub struct MeshBackend {
+    nodes: Mutex<HashMap<String, (NodeMetrics, Instant)>>,
+    localities: Mutex<HashMap<String, Locality>>,
+    shares: Mutex<Option<TargetShares>>,
+    clients: Mutex<Vec<(String, SentinelClient<Channel>)>>,
+    circuits: Mutex<HashMap<String, PeerCircuit>>,
+    /// Segments last delivered to each peer, the base of the next delta.
+    delivered: Mutex<HashMap<String, HashMap<String, MetricData>>>,
+    /// Protocol each peer advertised in its last response.
+    protocols: Mutex<HashMap<String, PeerProtocol>>,
+    retry_policy: RetryPolicy,
+    limits: ExchangeLimits,
+    chaos: Option<Arc<Chaos>>,
+    staleness: Duration,
+    connector: PeerConnector,
+    timeout: Duration,
+}
+
+impl MeshBackend {
+    /// Creates a mesh without peers. Each exchange with a peer is limited to `timeout`, and
+    /// metrics are considered stale after three timeouts.
+    pub fn new(connector: PeerConnector, timeout: Duration) -> Self {
+        MeshBackend {
+            nodes: Mutex::new(HashMap::new()),
+            localities: Mutex::new(HashMap::new()),
+            shares: Mutex::new(None),
+            clients: Mutex::new(Vec::new()),
+            circuits: Mutex::new(HashMap::new()),
+            delivered: Mutex::new(HashMap::new()),
+            protocols: Mutex::new(HashMap::new()),
+            retry_policy: RetryPolicy::default(),
+            limits: ExchangeLimits::default(),
+            chaos: None,
+            staleness: timeout * 3,
+            connector,
+            timeout,
+        }
+    }
+
+    /// Sets how exchanges back off from failing peers.
+    pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    /// Sets the bounds on the size of exchanges.
+    pub fn with_limits(mut self, limits: ExchangeLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Delays, drops, or corrupts outgoing exchanges as set by `chaos`, for testing only.
+    pub fn with_chaos(mut self, chaos: Arc<Chaos>) -> Self {
+        self.chaos = Some(chaos);
+        self
+    }
+
+    /// Drops metrics of peers not heard from within `staleness`.
+    pub fn with_staleness(mut self, staleness: Duration) -> Self {
+        self.staleness = staleness;
+        self
+    }
+
+    /// Removes the metrics not refreshed within the staleness window as of `now`, along with
+    /// the target shares of a stale coordinator.
+    fn expire_stale(&self, now: Instant) {
+        let mut expired = Vec::new();
+        self.nodes.lock().unwrap().retain(|node, (_, received)| {
+            let stale = now.saturating_duration_since(*received) >= self.staleness;
+            if stale {
+                expired.push(node.clone());
+            }
+            !stale
+        });
+        if expired.is_empty() {
+            return;
+        }
+        let mut localities = self.localities.lock().unwrap();
+        for node in &expired {
+            localities.remove(node);
+        }
+        let mut shares = self.shares.lock().unwrap();
+        for node in expired {
+            tracing::warn!(peer = %node, "peer metrics expired");
+            if shares.as_ref().is_some_and(|shares| shares.leader == node) {
+                *shares = None;
+            }
+        }
+    }
+
+    /// Replaces the peers metrics are exchanged with, skipping peers with invalid addresses.
+    ///
+    /// Connections are established lazily on the first exchange.
+    pub fn set_peers(&self, peers: &[String]) {
+        let mut clients = Vec::new();
+        for peer in peers {
+            match self.connector.endpoint(peer) {
+                Ok(endpoint) => {
+                    let channel = endpoint.timeout(self.timeout).connect_lazy();
+                    let client = SentinelClient::new(channel)
+                        .max_decoding_message_size(self.limits.max_payload_bytes)
+                        .max_encoding_message_size(self.limits.max_payload_bytes);
+                    clients.push((peer.clone(), client));
+                }
+                Err(err) => tracing::warn!(%peer, %err, "ignoring peer with invalid address"),
+            }
+        }
+        tracing::info!(peers = clients.len(), "exchange peers changed");
+        let known = |peer: &String| clients.iter().any(|(address, _)| address == peer);
+        self.circuits.lock().unwrap().retain(|peer, _| known(peer));
+        self.delivered.lock().unwrap().retain(|peer, _| known(peer));
+        self.protocols.lock().unwrap().retain(|peer, _| known(peer));
+        *self.clients.lock().unwrap() = clients;
+    }
+
+    /// Returns the region and zone each node reported, keyed by node.
+    pub fn localities(&self) -> HashMap<String, Locality> {
+        self.localities.lock().unwrap().clone()
+    }
+
+    /// Returns `true` if metrics from `node` are held, so a delta from it can be applied.
+    pub fn has_metrics(&self, node: &str) -> bool {
+        self.nodes.lock().unwrap().contains_key(node)
+    }
+
+    /// Stores the latest metrics reported by a node, along with the target shares it assigned
+    /// if it is the coordinator.
+    ///
+    /// A full snapshot replaces any previous report, while a delta is merged into it.
+    pub fn record(&self, mut metrics: Metrics) {
+        let shares = std::mem::take(&mut metrics.shares);
+        if metrics.page == 0 {
+            let mut current = self.shares.lock().unwrap();
+            if !shares.is_empty() {
+                *current = Some(TargetShares {
+                    leader: metrics.source.clone(),
+                    nodes: shares
+                        .into_iter()
+                        .map(|(node, shares)| (node, shares.segments))
+                        .collect(),
+                });
+            } else if current
+                .as_ref()
+                .is_some_and(|current| current.leader == metrics.source)
+            {
+                // The coordinator stepped down
+                *current = None;
+            }
+        }
+
+        let locality = Locality {
+            region: std::mem::take(&mut metrics.region),
+            zone: std::mem::take(&mut metrics.zone),
+        };
+        self.localities
+            .lock()
+            .unwrap()
+            .insert(metrics.source.clone(), locality);
+        let delta = metrics.delta;
+        let protocol_version = metrics.protocol_version;
+        let removed_segments = std::mem::take(&mut metrics.removed_segments);
+        let metrics = NodeMetrics::from(metrics);
+        let mut nodes = self.nodes.lock().unwrap();
+        match nodes.get_mut(&metrics.node) {
+            Some((current, received)) if delta => {
+                for segment in &removed_segments {
+                    current.segments.remove(segment);
+                }
+                current.segments.extend(metrics.segments);
+                *received = Instant::now();
+            }
+            current => {
+                if current.is_none() {
+                    tracing::info!(
+                        peer = %metrics.node,
+                        protocol_version,
+                        "first metrics received from peer"
+                    );
+                }
+                nodes.insert(metrics.node.clone(), (metrics, Instant::now()));
+            }
+        }
+    }
+
+    /// Sends `metrics` to `peer` as a delta of the segments it last received, or as a full
+    /// snapshot if it holds none or lost them, returning the peer's last response.
+    ///
+    /// Until the peer advertised support for delta exchanges, only the first page of a full
+    /// snapshot is sent.
+    async fn exchange(
+        &self,
+        peer: &str,
+        client: &mut SentinelClient<Channel>,
+        metrics: &Metrics,
+    ) -> Result<Metrics, tonic::Status> {
+        let supports_delta = self
+            .protocols
+            .lock()
+            .unwrap()
+            .get(peer)
+            .is_some_and(|protocol| protocol.supports(Capability::DeltaExchange));
+        let previous = self.delivered.lock().unwrap().remove(peer);
+        let mut pages = self
+            .limits
+            .pages(metrics, previous.as_ref().filter(|_| supports_delta));
+        if !supports_delta && pages.len() > 1 {
+            tracing::debug!(%peer, "peer does not support paged exchanges, truncating snapshot");
+            pages.truncate(1);
+        }
+        let mut response = Metrics::default();
+        let mut index = 0;
+        while index < pages.len() {
+            let mut page = pages[index].clone();
+            protocol::advertise(&mut page);
+            if let Some(chaos) = &self.chaos {
+                if let Some(status) = chaos.exchange_fault(&mut page).await {
+                    return Err(status);
+                }
+            }
+            match client.exchange_metrics(self.connector.request(page)).await {
+                Ok(page_response) => response = page_response.into_inner(),
+                // The peer lost the base of the delta, e.g. because it restarted
+                Err(status)
+                    if status.code() == tonic::Code::FailedPrecondition
+                        && pages[0].delta
+                        && index == 0 =>
+                {
+                    tracing::debug!(%peer, "peer lacks the base of the delta, sending a snapshot");
+                    pages = self.limits.pages(metrics, None);
+                    continue;
+                }
+                Err(status) => return Err(status),
+            }
+            index += 1;
+        }
+
+        let protocol = PeerProtocol::from(&response);
+        let previous_protocol = self
+            .protocols
+            .lock()
+            .unwrap()
+            .insert(peer.to_string(), protocol.clone());
+        if previous_protocol.as_ref() != Some(&protocol) {
+            tracing::info!(
+                %peer,
+                protocol_version = protocol.version,
+                delta_exchange = protocol.supports(Capability::DeltaExchange),
+                "negotiated exchange protocol"
+            );
+        }
+        if supports_delta {
+            self.delivered
+                .lock()
+                .unwrap()
+                .insert(peer.to_string(), metrics.segments.clone());
+        }
+        Ok(response)
+    }
+
+    /// Records the outcome of an exchange with `peer`, updating its circuit.
+    fn record_exchange(&self, peer: &str, result: Result<Metrics, tonic::Status>) {
+        let mut circuits = self.circuits.lock().unwrap();
+        let circuit = circuits.entry(peer.to_string()).or_default();
+        match result {
+            Ok(metrics) => {
+                if circuit.record_success() {
+                    tracing::info!(%peer, "peer recovered, resuming exchanges");
+                }
+                drop(circuits);
+                tracing::debug!(%peer, node = %metrics.source, segments = metrics.segments.len(), "exchanged metrics");
+                self.record(metrics);
+            }
+            Err(status) => match circuit.record_failure(&self.retry_policy, Instant::now()) {
+                Some(backoff) => tracing::warn!(
+                    %peer,
+                    %status,
+                    backoff_ms = backoff.as_millis() as u64,
+                    "metrics exchange failed, backing off"
+                ),
+                None => tracing::warn!(%peer, %status, "metrics exchange failed"),
+            },
+        }
+    }
+
+    /// Adds the target shares to metrics sent by this node while it is the coordinator.
+    pub fn attach_shares(&self, metrics: &mut Metrics) {
+        let shares = self.shares.lock().unwrap();
+        if let Some(shares) = shares
+            .as_ref()
+            .filter(|shares| shares.leader == metrics.source)
+        {
+            metrics.shares = shares
+                .nodes
+                .iter()
+                .map(|(node, segments)| {
+                    let segments = segments.clone();
+                    (node.clone(), NodeShares { segments })
+                })
+                .collect();
+        }
+    }
+}
+
+impl DistributedStateBackend for MeshBackend {
+    fn publish(&self, metrics: NodeMetrics) -> BackendFuture<'_, ()> {
+        Box::pin(async move {
+            let now = Instant::now();
+            let clients: Vec<_> = {
+                let circuits = self.circuits.lock().unwrap();
+                self.clients
+                    .lock()
+                    .unwrap()
+                    .iter()
+                    .filter(|(peer, _)| circuits.get(peer).is_none_or(|c| c.allows(now)))
+                    .cloned()
+                    .collect()
+            };
+            let mut request = Metrics::from(metrics);
+            self.attach_shares(&mut request);
+            let request = &request;
+            futures_util::stream::iter(clients)
+                .for_each_concurrent(
+                    self.retry_policy.max_concurrent_exchanges,
+                    |(peer, mut client)| async move {
+                        let result = self.exchange(&peer, &mut client, request).await;
+                        self.record_exchange(&peer, result);
+                    },
+                )
+                .await;
+            Ok(())
+        })
+    }
+
+    fn node_metrics(&self) -> BackendFuture<'_, Vec<NodeMetrics>> {
+        self.expire_stale(Instant::now());
+        let nodes = self
+            .nodes
+            .lock()
+            .unwrap()
+            .values()
+            .map(|(metrics, _)| metrics.clone())
+            .collect();
+        Box::pin(async { Ok::<_, BackendError>(nodes) })
+    }
+
+    fn forget(&self, node: &str) -> BackendFuture<'_, ()> {
+        self.nodes.lock().unwrap().remove(node);
+        self.localities.lock().unwrap().remove(node);
+        let mut shares = self.shares.lock().unwrap();
+        if shares.as_ref().is_some_and(|shares| shares.leader == node) {
+            *shares = None;
+        }
+        Box::pin(async { Ok(()) })
+    }
+
+    fn acquire_lease(&self, node: &str, ttl: Duration) -> BackendFuture<'_, bool> {
+        let now = Instant::now();
+        let holds_lease = !self
+            .nodes
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|(peer, (_, received))| {
+                peer.as_str() < node && now.saturating_duration_since(*received) < ttl
+            });
+        if !holds_lease {
+            let mut shares = self.shares.lock().unwrap();
+            if shares.as_ref().is_some_and(|shares| shares.leader == node) {
+                *shares = None;
+            }
+        }
+        Box::pin(async move { Ok(holds_lease) })
+    }
+
+    fn publish_shares(&self, shares: TargetShares) -> BackendFuture<'_, ()> {
+        *self.shares.lock().unwrap() = Some(shares);
+        Box::pin(async { Ok(()) })
+    }
+
+    fn shares(&self) -> BackendFuture<'_, Option<TargetShares>> {
+        let shares = self.shares.lock().unwrap().clone();
+        Box::pin(async { Ok(shares) })
+    }
+}
+
+impl From<Metrics> for NodeMetrics {
+    fn from(metrics: Metrics) -> Self {
+        NodeMetrics {
+            node: metrics.source,
+            segments: metrics
+                .segments
+                .into_iter()
+                .map(|(segment, data)| {
+                    let rates = SegmentRates {
+                        request_rate: data.request_rate,
+                        accepted_request_rate: data.accepted_request_rate,
+                    };
+                    (segment, rates)
+                })
+                .collect(),
+        }
+    }
+}
+
+impl From<NodeMetrics> for Metrics {
+    fn from(metrics: NodeMetrics) -> Self {
+        Metrics {
+            source: metrics.node,
+            segments: metrics
+                .segments
+                .into_iter()
+                .map(|(segment, rates)| {
+                    let data = MetricData {
+                        request_rate: rates.request_rate,
+                        accepted_request_rate: rates.accepted_request_rate,
+                    };
+                    (segment, data)
+                })
+                .collect(),
+            ..Default::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn metrics(source: &str) -> Metrics {
+        Metrics {
+            source: source.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_failing_peers_are_skipped() {
+        let mesh = MeshBackend::new(PeerConnector::default(), Duration::from_secs(1))
+            .with_retry_policy(RetryPolicy {
+                failure_threshold: 1,
+                initial_backoff: Duration::from_secs(60),
+                ..Default::default()
+            });
+        let peer = "http://127.0.0.1:1".to_string();
+        mesh.set_peers(std::slice::from_ref(&peer));
+        mesh.publish(NodeMetrics::default()).await.unwrap();
+        assert!(!mesh.circuits.lock().unwrap()[&peer].allows(Instant::now()));
+
+        mesh.record_exchange(&peer, Ok(metrics("b")));
+        assert!(mesh.circuits.lock().unwrap()[&peer].allows(Instant::now()));
+        mesh.set_peers(&[]);
+        assert!(mesh.circuits.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_stale_metrics_expire() {
+        let mesh = MeshBackend::new(PeerConnector::default(), Duration::from_secs(1))
+            .with_staleness(Duration::from_secs(5));
+        mesh.record(metrics("a"));
+        mesh.record(metrics("b"));
+        mesh.nodes.lock().unwrap().get_mut("a").unwrap().1 -= Duration::from_secs(10);
+
+        let nodes = mesh.node_metrics().await.unwrap();
+        assert_eq!(nodes.len(), 1);
+        assert_eq!(nodes[0].node, "b");
+    }
+
+    #[test]
+    fn test_deltas_merge_into_previous_metrics() {
+        let mesh = MeshBackend::new(PeerConnector::default(), Duration::from_secs(1));
+        let data = |request_rate| MetricData {
+            request_rate,
+            accepted_request_rate: request_rate,
+        };
+        mesh.record(Metrics {
+            segments: HashMap::from([
+                ("checkout".to_string(), data(1.0)),
+                ("search".to_string(), data(2.0)),
+            ]),
+            ..metrics("a")
+        });
+        assert!(mesh.has_metrics("a"));
+        mesh.record(Metrics {
+            segments: HashMap::from([("orders".to_string(), data(3.0))]),
+            removed_segments: vec!["search".to_string()],
+            delta: true,
+            page: 1,
+            ..metrics("a")
+        });
+
+        let nodes = mesh.nodes.lock().unwrap();
+        let segments = &nodes["a"].0.segments;
+        assert_eq!(segments.len(), 2);
+        assert_eq!(segments["checkout"].request_rate, 1.0);
+        assert_eq!(segments["orders"].request_rate, 3.0);
+    }
+
+    #[tokio::test]
+    async fn test_lowest_live_node_holds_lease() {
+        let mesh = MeshBackend::new(PeerConnector::default(), Duration::from_secs(1));
+        let ttl = Duration::from_secs(60);
+        mesh.record(metrics("c"));
+        assert!(mesh.acquire_lease("b", ttl).await.unwrap());
+
+        let mut coordinator = metrics("a");
+        coordinator.shares = HashMap::from([(
+            "b".to_string(),
+            NodeShares {
+                segments: HashMap::from([("checkout".to_string(), 4.0)]),
+            },
+        )]);
+        mesh.record(coordinator);
+        assert!(!mesh.acquire_lease("b", ttl).await.unwrap());
+        let shares = mesh.shares().await.unwrap().unwrap();
+        assert_eq!(shares.share("b", "checkout"), Some(4.0));
+
+        // The lease passes on once the coordinator leaves
+        mesh.forget("a").await.unwrap();
+        assert!(mesh.acquire_lease("b", ttl).await.unwrap());
+        assert_eq!(mesh.shares().await.unwrap(), None);
+    }
+}
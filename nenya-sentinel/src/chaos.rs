@@ -0,0 +1,100 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::time::Duration;
+
+use tonic::Status;
+
+use crate::sentinel::Metrics;
+
+/// Largest factor corrupted rates are scaled by.
+const MAX_CORRUPTION_FACTOR: f64 = 10.0;
+
+/// Faults injected into the sentinel for testing how clients and the control loop cope with
+/// slow, lossy, or partitioned clusters. Never enable this in production.
+#[derive(Debug, Clone, Default)]
+pub struct Chaos {
+    /// Largest random delay added before each outgoing metric exchange.
+    pub max_exchange_delay: Duration,
+    /// Fraction of outgoing metric exchanges dropped.
+    pub exchange_drop_rate: f64,
+    /// Fraction of outgoing metric exchanges whose rates are scaled by a random factor.
+    pub exchange_corruption_rate: f64,
+    /// Fraction of incoming RPCs failed with `UNAVAILABLE`.
+    pub rpc_failure_rate: f64,
+}
+
+impl Chaos {
+    /// Returns the status to fail an incoming RPC with, if it should fail.
+    pub fn rpc_fault(&self) -> Option<Status> {
+        chance(self.rpc_failure_rate).then(|| Status::unavailable("chaos: injected RPC failure"))
+    }
+
+    /// Applies faults to an outgoing exchange of `metrics`: delays it, then returns the status
+    /// to fail it with if it is dropped, or corrupts its rates.
+    pub async fn exchange_fault(&self, metrics: &mut Metrics) -> Option<Status> {
+        if !self.max_exchange_delay.is_zero() {
+            tokio::time::sleep(self.max_exchange_delay.mul_f64(random())).await;
+        }
+        if chance(self.exchange_drop_rate) {
+            return Some(Status::unavailable("chaos: dropped metric exchange"));
+        }
+        if chance(self.exchange_corruption_rate) {
+            let factor = (random() * MAX_CORRUPTION_FACTOR) as f32;
+            for data in metrics.segments.values_mut() {
+                data.request_rate *= factor;
+                data.accepted_request_rate *= factor;
+            }
+        }
+        None
+    }
+}
+
+/// Returns `true` with probability `rate`.
+fn chance(rate: f64) -> bool {
+    rate > 0.0 && random() < rate
+}
+
+/// Returns a random number between 0 and 1.
+fn random() -> f64 {
+    RandomState::new().build_hasher().finish() as f64 / u64::MAX as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sentinel::MetricData;
+    use std::collections::HashMap;
+
+    #[tokio::test]
+    async fn test_injects_configured_faults() {
+        let disabled = Chaos::default();
+        let mut metrics = Metrics::default();
+        assert!(disabled.rpc_fault().is_none());
+        assert!(disabled.exchange_fault(&mut metrics).await.is_none());
+
+        let dropping = Chaos {
+            exchange_drop_rate: 1.0,
+            rpc_failure_rate: 1.0,
+            ..Default::default()
+        };
+        let status = dropping.rpc_fault().unwrap();
+        assert_eq!(status.code(), tonic::Code::Unavailable);
+        assert!(dropping.exchange_fault(&mut metrics).await.is_some());
+
+        let corrupting = Chaos {
+            exchange_corruption_rate: 1.0,
+            ..Default::default()
+        };
+        let data = MetricData {
+            request_rate: 1.0,
+            accepted_request_rate: 1.0,
+        };
+        let mut metrics = Metrics {
+            segments: HashMap::from([("checkout".to_string(), data)]),
+            ..Default::default()
+        };
+        assert!(corrupting.exchange_fault(&mut metrics).await.is_none());
+        let rate = metrics.segments["checkout"].request_rate;
+        assert!((0.0..=MAX_CORRUPTION_FACTOR as f32).contains(&rate));
+    }
+}
@@ -0,0 +1,109 @@
+use std::net::SocketAddr;
+
+use clap::{Arg, ArgMatches, Command};
+
+use crate::config::{ConfigError, SentinelConfig};
+
+/// Builds the command line interface for the sentinel binary.
+pub fn command() -> Command {
+    let command = Command::new("nenya-sentinel")
+        .about("A standalone rate limiting service using PID control")
+        .version(env!("CARGO_PKG_VERSION"))
+        .arg(
+            Arg::new("config")
+                .short('c')
+                .long("config")
+                .value_parser(clap::value_parser!(String))
+                .help("Path to a TOML configuration file"),
+        )
+        .arg(
+            Arg::new("listen_addr")
+                .short('l')
+                .long("listen_addr")
+                .value_parser(clap::value_parser!(SocketAddr))
+                .help("Address to listen on, overriding the config file"),
+        )
+        .arg(
+            Arg::new("node_name")
+                .short('n')
+                .long("node_name")
+                .value_parser(clap::value_parser!(String))
+                .help("Name reported to peers, overriding the config file (defaults to hostname)"),
+        )
+        .arg(
+            Arg::new("peers")
+                .short('p')
+                .long("peers")
+                .value_parser(clap::value_parser!(String))
+                .num_args(1..)
+                .use_value_delimiter(true)
+                .help("Peers to exchange metrics with, replacing the config file peer list"),
+        )
+        .arg(
+            Arg::new("log_level")
+                .long("log_level")
+                .value_parser(clap::value_parser!(tracing::Level))
+                .default_value("info")
+                .help(
+                    "Log level (trace, debug, info, warn, error), refined by RUST_LOG directives",
+                ),
+        );
+    #[cfg(feature = "test-util")]
+    let command = command.arg(
+        Arg::new("simulate")
+            .long("simulate")
+            .value_parser(clap::value_parser!(usize))
+            .help("Simulates a cluster of this many nodes with the config and exits"),
+    );
+    command
+}
+
+/// Loads the configuration file, if any, and applies command line overrides.
+pub fn load_config(matches: &ArgMatches) -> Result<SentinelConfig, ConfigError> {
+    let mut config = match matches.get_one::<String>("config") {
+        Some(path) => SentinelConfig::load(path)?,
+        None => SentinelConfig::default(),
+    };
+    if let Some(listen_addr) = matches.get_one::<SocketAddr>("listen_addr") {
+        config.listen_addr = *listen_addr;
+    }
+    if let Some(node_name) = matches.get_one::<String>("node_name") {
+        config.node_name = Some(node_name.clone());
+    }
+    if let Some(peers) = matches.get_many::<String>("peers") {
+        config.peers = peers.cloned().collect();
+    }
+    Ok(config)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_command_is_valid() {
+        command().debug_assert();
+    }
+
+    #[test]
+    fn test_overrides_apply_to_default_config() {
+        let matches = command().get_matches_from([
+            "nenya-sentinel",
+            "--listen_addr",
+            "127.0.0.1:9090",
+            "--node_name",
+            "sentinel-1",
+            "--peers",
+            "http://a:8080,http://b:8080",
+        ]);
+        let config = load_config(&matches).unwrap();
+
+        assert_eq!(config.listen_addr, "127.0.0.1:9090".parse().unwrap());
+        assert_eq!(config.node_name.as_deref(), Some("sentinel-1"));
+        assert_eq!(config.peers, vec!["http://a:8080", "http://b:8080"]);
+        assert_eq!(
+            matches.get_one::<tracing::Level>("log_level"),
+            Some(&tracing::Level::INFO)
+        );
+    }
+}
@@ -0,0 +1,1557 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use serde::Deserialize;
+
+use nenya::pid_controller::{PIDController, PIDControllerBuilder, PIDControllerError};
+
+use crate::chaos::Chaos;
+use crate::locality::{Locality, LocalityPolicy};
+use crate::payload::ExchangeLimits;
+use crate::retry::RetryPolicy;
+use crate::segment::{segment_key, NAMESPACE_SEPARATOR, PATTERN_WILDCARD};
+use crate::sentinel::{Enforcement, SegmentConfig};
+
+/// Errors that can occur while loading the sentinel configuration.
+#[derive(Debug)]
+pub enum ConfigError {
+    /// The configuration file could not be read.
+    Io(std::io::Error),
+    /// The configuration file is not valid TOML or does not match the expected schema.
+    Parse(toml::de::Error),
+    /// The configuration parsed but contains invalid values.
+    Invalid(String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Io(err) => write!(f, "unable to read config file: {}", err),
+            ConfigError::Parse(err) => write!(f, "unable to parse config file: {}", err),
+            ConfigError::Invalid(reason) => write!(f, "invalid config: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ConfigError::Io(err) => Some(err),
+            ConfigError::Parse(err) => Some(err),
+            ConfigError::Invalid(_) => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for ConfigError {
+    fn from(err: std::io::Error) -> Self {
+        ConfigError::Io(err)
+    }
+}
+
+impl From<toml::de::Error> for ConfigError {
+    fn from(err: toml::de::Error) -> Self {
+        ConfigError::Parse(err)
+    }
+}
+
+impl From<PIDControllerError> for ConfigError {
+    fn from(err: PIDControllerError) -> Self {
+        ConfigError::Invalid(format!("pid: {}", err))
+    }
+}
+
+/// Configuration for the sentinel service, loaded from a TOML file.
+///
+/// ```toml
+/// listen_addr = "[::1]:8080"
+/// peers = ["http://sentinel-2:8080"]
+/// update_interval_ms = 1000
+/// exchange_interval_ms = 1000
+///
+/// [pid]
+/// kp = 0.8
+/// ki = 0.05
+/// kd = 0.04
+///
+/// [default_segment]
+/// target_tps = 100.0
+///
+/// [segments.checkout]
+/// target_tps = 50.0
+/// min_tps = 25.0
+/// max_tps = 75.0
+/// ```
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SentinelConfig {
+    #[serde(default = "default_listen_addr")]
+    pub listen_addr: SocketAddr,
+    /// Name this node reports to its peers. Defaults to the hostname.
+    #[serde(default)]
+    pub node_name: Option<String>,
+    #[serde(default)]
+    pub peers: Vec<String>,
+    /// Dynamic peer discovery. Discovered peers are used in addition to `peers`.
+    #[serde(default)]
+    pub discovery: Option<DiscoveryConfig>,
+    /// SWIM-style gossip membership. When set, peers are learned and failure-checked through
+    /// gossip, and `peers` and discovery only provide the seeds used to join the cluster.
+    #[serde(default)]
+    pub gossip: Option<GossipConfig>,
+    /// Weight of the rates reported by each peer, keyed by node name, when summing them into
+    /// the external rates. Peers without an entry have a weight of 1, and a weight of 0 excludes
+    /// a peer, e.g. so a canary node or a node in another region counts only partially.
+    #[serde(default)]
+    pub peer_weights: HashMap<String, f32>,
+    /// Region and zone of this node, weighting peers by how close they run to it. Explicit
+    /// `peer_weights` take precedence.
+    #[serde(default)]
+    pub locality: Option<LocalityConfig>,
+    /// Protects the sentinel from overload by shedding metric exchanges. Disabled when unset.
+    #[serde(default)]
+    pub overload: Option<OverloadConfig>,
+    /// Injects faults into RPCs and metric exchanges, for testing clients and the control loop
+    /// under failures. Never set this in production.
+    #[serde(default)]
+    pub chaos: Option<ChaosConfig>,
+    /// Logs a sample of throttle decisions as JSON lines. Disabled when unset.
+    #[serde(default)]
+    pub audit: Option<AuditConfig>,
+    /// Elects a coordinator that computes the target rates for the whole cluster and assigns
+    /// each node a share, instead of every node adjusting the target rates independently.
+    #[serde(default)]
+    pub leader_election: Option<LeaderElectionConfig>,
+    /// TLS for the gRPC server and peer connections. Peers must use `https://` addresses, which
+    /// discovered peers do.
+    #[serde(default)]
+    pub tls: Option<TlsConfig>,
+    /// Token authentication for incoming requests.
+    #[serde(default)]
+    pub auth: AuthConfig,
+    /// Distributed tracing export.
+    #[serde(default)]
+    pub telemetry: TelemetryConfig,
+    #[serde(default = "default_update_interval_ms")]
+    pub update_interval_ms: u64,
+    /// How often metrics are exchanged with peers and applied to the segment limiters.
+    #[serde(default = "default_exchange_interval_ms")]
+    pub exchange_interval_ms: u64,
+    /// How long a peer's metrics count towards the cluster-wide rates without being refreshed.
+    /// Defaults to three exchange intervals.
+    #[serde(default)]
+    pub peer_staleness_ms: Option<u64>,
+    /// Backoff and concurrency of the metric exchanges with peers.
+    #[serde(default)]
+    pub exchange_retry: ExchangeRetryConfig,
+    #[serde(default)]
+    pub exchange_limits: ExchangeLimitsConfig,
+    /// How long a segment created on first use may go without requests or peer traffic before it
+    /// is evicted. Segments listed under `segments` are never evicted. Disabled when unset.
+    #[serde(default)]
+    pub segment_idle_timeout_ms: Option<u64>,
+    /// Most segments that may be created on first use. Once reached, requests for further
+    /// segments are decided by their namespace's default segment.
+    #[serde(default = "default_max_dynamic_segments")]
+    pub max_dynamic_segments: usize,
+    #[serde(default)]
+    pub pid: PidConfig,
+    #[serde(default)]
+    pub default_segment: SegmentSettings,
+    /// Settings of specific segments. Names ending in `*` are patterns configuring every segment
+    /// that starts with the rest of the name, e.g. `api.users.*`; exact names take precedence,
+    /// then the longest matching pattern.
+    #[serde(default)]
+    pub segments: HashMap<String, SegmentSettings>,
+    /// Namespaces isolating the segments of applications sharing the cluster, keyed by name.
+    /// Requests name their namespace, and requests without one use the default namespace
+    /// configured by `default_segment` and `segments`.
+    #[serde(default)]
+    pub namespaces: HashMap<String, NamespaceConfig>,
+}
+
+/// Segment settings of a namespace.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct NamespaceConfig {
+    /// Used for segments of the namespace without an explicit entry. Defaults to the top-level
+    /// `default_segment`.
+    pub default_segment: Option<SegmentSettings>,
+    #[serde(default)]
+    pub segments: HashMap<String, SegmentSettings>,
+}
+
+/// Peer discovery mechanism.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "mode", rename_all = "snake_case")]
+pub enum DiscoveryConfig {
+    /// Discovers peers from the endpoints of a Kubernetes headless service.
+    Kubernetes(KubernetesDiscoveryConfig),
+}
+
+/// Settings for discovering peers through a Kubernetes headless service.
+///
+/// A headless service resolves to the IP of every ready pod backing it, so resolving it
+/// periodically tracks the sentinel pods as they are scaled.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct KubernetesDiscoveryConfig {
+    /// DNS name of the headless service, e.g. `nenya-sentinel.default.svc.cluster.local`.
+    pub service: String,
+    /// Port the sentinel pods listen on.
+    #[serde(default = "default_discovery_port")]
+    pub port: u16,
+    /// How often the service endpoints are refreshed.
+    #[serde(default = "default_discovery_refresh_interval_ms")]
+    pub refresh_interval_ms: u64,
+    /// Environment variable holding this pod's IP, used to exclude this node from its own peers.
+    #[serde(default = "default_pod_ip_env")]
+    pub pod_ip_env: String,
+}
+
+impl KubernetesDiscoveryConfig {
+    /// Returns the interval at which the service endpoints are refreshed.
+    pub fn refresh_interval(&self) -> Duration {
+        Duration::from_millis(self.refresh_interval_ms)
+    }
+}
+
+/// Settings for gossip membership and failure detection.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct GossipConfig {
+    /// Address peers use to reach this node. Defaults to `http://<listen_addr>`.
+    pub advertise_addr: Option<String>,
+    /// How often a member is probed.
+    #[serde(default = "default_protocol_period_ms")]
+    pub protocol_period_ms: u64,
+    /// How long a probe waits for a response.
+    #[serde(default = "default_probe_timeout_ms")]
+    pub probe_timeout_ms: u64,
+    /// How long a member stays suspected before it is declared dead.
+    #[serde(default = "default_suspicion_timeout_ms")]
+    pub suspicion_timeout_ms: u64,
+    /// Number of members asked to probe a member that failed a direct probe.
+    #[serde(default = "default_indirect_probes")]
+    pub indirect_probes: usize,
+}
+
+impl GossipConfig {
+    /// Returns the interval between probes.
+    pub fn protocol_period(&self) -> Duration {
+        Duration::from_millis(self.protocol_period_ms)
+    }
+
+    /// Returns how long a probe waits for a response.
+    pub fn probe_timeout(&self) -> Duration {
+        Duration::from_millis(self.probe_timeout_ms)
+    }
+
+    /// Returns how long a member stays suspected before it is declared dead.
+    pub fn suspicion_timeout(&self) -> Duration {
+        Duration::from_millis(self.suspicion_timeout_ms)
+    }
+}
+
+/// Settings for backing off from peers whose metric exchanges fail.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ExchangeRetryConfig {
+    /// Consecutive failed exchanges after which a peer is skipped.
+    #[serde(default = "default_failure_threshold")]
+    pub failure_threshold: u32,
+    /// How long a peer is first skipped. The backoff doubles, with jitter, while the peer keeps
+    /// failing.
+    #[serde(default = "default_initial_backoff_ms")]
+    pub initial_backoff_ms: u64,
+    #[serde(default = "default_max_backoff_ms")]
+    pub max_backoff_ms: u64,
+    /// Largest number of exchanges with peers in flight at once.
+    #[serde(default = "default_max_concurrent_exchanges")]
+    pub max_concurrent_exchanges: usize,
+}
+
+impl ExchangeRetryConfig {
+    /// Returns the retry policy for the mesh backend.
+    pub fn retry_policy(&self) -> RetryPolicy {
+        RetryPolicy {
+            failure_threshold: self.failure_threshold,
+            initial_backoff: Duration::from_millis(self.initial_backoff_ms),
+            max_backoff: Duration::from_millis(self.max_backoff_ms),
+            max_concurrent_exchanges: self.max_concurrent_exchanges,
+        }
+    }
+}
+
+/// Where the node runs, and how the rates of peers count depending on where they run.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LocalityConfig {
+    pub region: String,
+    #[serde(default)]
+    pub zone: Option<String>,
+    /// Weight of peers in the same zone.
+    #[serde(default = "default_locality_weight")]
+    pub same_zone_weight: f32,
+    /// Weight of peers in another zone of the same region.
+    #[serde(default = "default_locality_weight")]
+    pub same_region_weight: f32,
+    /// Weight of peers in other regions. Set to 0 to enforce limits per region.
+    #[serde(default = "default_locality_weight")]
+    pub other_region_weight: f32,
+    /// Fraction of every segment's rates enforced by this node's region, splitting a global
+    /// limit into regional sub-targets. Defaults to the whole limit.
+    #[serde(default)]
+    pub region_share: Option<f32>,
+}
+
+impl LocalityConfig {
+    /// Returns the policy weighting peers by locality.
+    pub fn policy(&self) -> LocalityPolicy {
+        LocalityPolicy {
+            local: Locality {
+                region: self.region.clone(),
+                zone: self.zone.clone().unwrap_or_default(),
+            },
+            same_zone_weight: self.same_zone_weight,
+            same_region_weight: self.same_region_weight,
+            other_region_weight: self.other_region_weight,
+        }
+    }
+}
+
+/// Bounds on the size of metric exchanges, for clusters with many segments.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ExchangeLimitsConfig {
+    /// Largest number of segments in a single exchange. Larger snapshots are split into pages,
+    /// and peers pushing more are rejected.
+    #[serde(default = "default_max_exchange_segments")]
+    pub max_segments: usize,
+    /// Largest encoded size of an exchange message, in bytes.
+    #[serde(default = "default_max_exchange_payload_bytes")]
+    pub max_payload_bytes: usize,
+}
+
+impl ExchangeLimitsConfig {
+    /// Returns the exchange limits for the sentinel and the mesh backend.
+    pub fn limits(&self) -> ExchangeLimits {
+        ExchangeLimits {
+            max_segments: self.max_segments,
+            max_payload_bytes: self.max_payload_bytes,
+        }
+    }
+}
+
+/// Thresholds beyond which the sentinel sheds metric exchanges to keep throttling decisions
+/// responsive.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct OverloadConfig {
+    /// Largest rate of RPCs, of any kind, the node handles before shedding.
+    pub max_rpc_rate: f32,
+    /// Largest delay of the event loop in waking up tasks before shedding.
+    #[serde(default = "default_max_event_loop_lag_ms")]
+    pub max_event_loop_lag_ms: u64,
+}
+
+impl OverloadConfig {
+    /// Returns the largest tolerated event loop lag.
+    pub fn max_event_loop_lag(&self) -> Duration {
+        Duration::from_millis(self.max_event_loop_lag_ms)
+    }
+}
+
+/// Faults injected for chaos testing. Rates are fractions between 0 and 1.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ChaosConfig {
+    /// Largest random delay added before each outgoing metric exchange.
+    #[serde(default)]
+    pub max_exchange_delay_ms: u64,
+    /// Fraction of outgoing metric exchanges dropped, simulating partitions.
+    #[serde(default)]
+    pub exchange_drop_rate: f64,
+    /// Fraction of outgoing metric exchanges whose rates are scaled by a random factor.
+    #[serde(default)]
+    pub exchange_corruption_rate: f64,
+    /// Fraction of incoming RPCs failed with `UNAVAILABLE`, exercising client fallbacks.
+    #[serde(default)]
+    pub rpc_failure_rate: f64,
+}
+
+impl ChaosConfig {
+    /// Returns the faults to inject.
+    pub fn chaos(&self) -> Chaos {
+        Chaos {
+            max_exchange_delay: Duration::from_millis(self.max_exchange_delay_ms),
+            exchange_drop_rate: self.exchange_drop_rate,
+            exchange_corruption_rate: self.exchange_corruption_rate,
+            rpc_failure_rate: self.rpc_failure_rate,
+        }
+    }
+}
+
+/// Where and how often throttle decisions are written to the audit log.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AuditConfig {
+    /// Fraction of the decisions recorded, between 0 and 1.
+    #[serde(default = "default_audit_sample_rate")]
+    pub sample_rate: f64,
+    /// File the records are appended to. Records are written to stdout when unset.
+    pub path: Option<PathBuf>,
+}
+
+/// Settings for electing the coordinator.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LeaderElectionConfig {
+    /// How long the coordinator lease lasts without being renewed. The lease is renewed on every
+    /// exchange, so this must be longer than `exchange_interval_ms`.
+    #[serde(default = "default_lease_ttl_ms")]
+    pub lease_ttl_ms: u64,
+}
+
+impl LeaderElectionConfig {
+    /// Returns how long the coordinator lease lasts without being renewed.
+    pub fn lease_ttl(&self) -> Duration {
+        Duration::from_millis(self.lease_ttl_ms)
+    }
+}
+
+/// Paths and verification settings for TLS and mutual TLS.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TlsConfig {
+    /// PEM encoded certificate chain for this node.
+    pub cert_path: PathBuf,
+    /// PEM encoded private key for this node.
+    pub key_path: PathBuf,
+    /// PEM encoded CA bundle used to verify peers. Enables mutual TLS.
+    pub ca_path: Option<PathBuf>,
+    /// Name expected in the subject alternative names of peer certificates. Defaults to the host
+    /// of each peer's address.
+    pub domain_name: Option<String>,
+    /// Reject clients that do not present a certificate when `ca_path` is set.
+    #[serde(default = "default_require_client_auth")]
+    pub require_client_auth: bool,
+}
+
+/// Tokens accepted from callers, and the token this node presents to its peers.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AuthConfig {
+    /// Accepted tokens. Authentication is disabled when empty.
+    #[serde(default)]
+    pub tokens: Vec<TokenConfig>,
+    /// Token sent to peers when exchanging metrics and gossiping.
+    pub peer_token: Option<TokenConfig>,
+    /// Identities allowed to use the admin API when authentication is enabled.
+    #[serde(default)]
+    pub admin_identities: Vec<String>,
+}
+
+/// A token and the caller identity it authenticates.
+///
+/// Exactly one of `token` and `token_env` must be set.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TokenConfig {
+    #[serde(default)]
+    pub identity: String,
+    pub token: Option<String>,
+    /// Environment variable holding the token, to keep secrets out of the config file.
+    pub token_env: Option<String>,
+}
+
+impl TokenConfig {
+    /// Returns the token, reading it from the environment if configured with `token_env`.
+    pub fn resolve(&self) -> Result<String, ConfigError> {
+        match (&self.token, &self.token_env) {
+            (Some(token), None) => Ok(token.clone()),
+            (None, Some(token_env)) => std::env::var(token_env).map_err(|_| {
+                ConfigError::Invalid(format!("auth token variable {} is not set", token_env))
+            }),
+            _ => Err(ConfigError::Invalid(format!(
+                "auth token for {:?} must set exactly one of token or token_env",
+                self.identity
+            ))),
+        }
+    }
+}
+
+/// OpenTelemetry trace export settings.
+///
+/// Spans are only exported when the sentinel is built with the `otel` feature.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TelemetryConfig {
+    /// OTLP gRPC collector endpoint, e.g. `http://otel-collector:4317`. Export is disabled when
+    /// unset.
+    pub otlp_endpoint: Option<String>,
+    /// Service name attached to exported spans.
+    #[serde(default = "default_service_name")]
+    #[cfg_attr(not(feature = "otel"), allow(dead_code))]
+    pub service_name: String,
+}
+
+/// PID controller gains and limits shared by all segments.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PidConfig {
+    #[serde(default)]
+    pub kp: f32,
+    #[serde(default)]
+    pub ki: f32,
+    #[serde(default)]
+    pub kd: f32,
+    #[serde(default = "default_error_bias")]
+    pub error_bias: f32,
+    pub error_limit: Option<f32>,
+    pub output_limit: Option<f32>,
+}
+
+/// Target, minimum, and maximum rates for a segment.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SegmentSettings {
+    pub target_tps: f32,
+    pub min_tps: Option<f32>,
+    pub max_tps: Option<f32>,
+    /// Largest rate any single authenticated caller may reach within the segment.
+    pub caller_max_tps: Option<f32>,
+    /// Whether throttling decisions are returned to callers. Defaults to `enforce`.
+    #[serde(default)]
+    pub enforcement: EnforcementMode,
+}
+
+/// Whether a segment's throttling decisions are returned to callers.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EnforcementMode {
+    /// Computes and logs decisions but allows every request, e.g. to trial a new segment.
+    Observe,
+    #[default]
+    Enforce,
+}
+
+fn default_listen_addr() -> SocketAddr {
+    "[::1]:8080".parse().unwrap()
+}
+
+fn default_update_interval_ms() -> u64 {
+    1000
+}
+
+fn default_exchange_interval_ms() -> u64 {
+    1000
+}
+
+fn default_max_dynamic_segments() -> usize {
+    10_000
+}
+
+fn default_discovery_port() -> u16 {
+    8080
+}
+
+fn default_discovery_refresh_interval_ms() -> u64 {
+    10000
+}
+
+fn default_pod_ip_env() -> String {
+    "POD_IP".to_string()
+}
+
+fn default_protocol_period_ms() -> u64 {
+    1000
+}
+
+fn default_probe_timeout_ms() -> u64 {
+    500
+}
+
+fn default_suspicion_timeout_ms() -> u64 {
+    5000
+}
+
+fn default_indirect_probes() -> usize {
+    3
+}
+
+fn default_lease_ttl_ms() -> u64 {
+    5000
+}
+
+fn default_max_event_loop_lag_ms() -> u64 {
+    100
+}
+
+fn default_audit_sample_rate() -> f64 {
+    0.01
+}
+
+fn default_failure_threshold() -> u32 {
+    3
+}
+
+fn default_initial_backoff_ms() -> u64 {
+    500
+}
+
+fn default_max_backoff_ms() -> u64 {
+    30000
+}
+
+fn default_max_concurrent_exchanges() -> usize {
+    16
+}
+
+fn default_locality_weight() -> f32 {
+    1.0
+}
+
+fn default_max_exchange_segments() -> usize {
+    5000
+}
+
+fn default_max_exchange_payload_bytes() -> usize {
+    4 * 1024 * 1024
+}
+
+fn default_require_client_auth() -> bool {
+    true
+}
+
+fn default_service_name() -> String {
+    "nenya-sentinel".to_string()
+}
+
+fn default_error_bias() -> f32 {
+    1.0
+}
+
+impl Default for SentinelConfig {
+    fn default() -> Self {
+        SentinelConfig {
+            listen_addr: default_listen_addr(),
+            node_name: None,
+            peers: Vec::new(),
+            discovery: None,
+            gossip: None,
+            peer_weights: HashMap::new(),
+            locality: None,
+            leader_election: None,
+            tls: None,
+            auth: AuthConfig::default(),
+            telemetry: TelemetryConfig::default(),
+            update_interval_ms: default_update_interval_ms(),
+            exchange_interval_ms: default_exchange_interval_ms(),
+            peer_staleness_ms: None,
+            overload: None,
+            chaos: None,
+            audit: None,
+            exchange_retry: ExchangeRetryConfig::default(),
+            exchange_limits: ExchangeLimitsConfig::default(),
+            segment_idle_timeout_ms: None,
+            max_dynamic_segments: default_max_dynamic_segments(),
+            pid: PidConfig::default(),
+            default_segment: SegmentSettings::default(),
+            segments: HashMap::new(),
+            namespaces: HashMap::new(),
+        }
+    }
+}
+
+impl Default for ExchangeLimitsConfig {
+    fn default() -> Self {
+        ExchangeLimitsConfig {
+            max_segments: default_max_exchange_segments(),
+            max_payload_bytes: default_max_exchange_payload_bytes(),
+        }
+    }
+}
+
+impl Default for ExchangeRetryConfig {
+    fn default() -> Self {
+        ExchangeRetryConfig {
+            failure_threshold: default_failure_threshold(),
+            initial_backoff_ms: default_initial_backoff_ms(),
+            max_backoff_ms: default_max_backoff_ms(),
+            max_concurrent_exchanges: default_max_concurrent_exchanges(),
+        }
+    }
+}
+
+impl Default for TelemetryConfig {
+    fn default() -> Self {
+        TelemetryConfig {
+            otlp_endpoint: None,
+            service_name: default_service_name(),
+        }
+    }
+}
+
+impl Default for PidConfig {
+    fn default() -> Self {
+        PidConfig {
+            kp: 0.0,
+            ki: 0.0,
+            kd: 0.0,
+            error_bias: default_error_bias(),
+            error_limit: None,
+            output_limit: None,
+        }
+    }
+}
+
+impl Default for SegmentSettings {
+    fn default() -> Self {
+        SegmentSettings {
+            target_tps: 100.0,
+            min_tps: None,
+            max_tps: None,
+            caller_max_tps: None,
+            enforcement: EnforcementMode::Enforce,
+        }
+    }
+}
+
+impl SentinelConfig {
+    /// Reads, parses, and validates a configuration file.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, ConfigError> {
+        let contents = std::fs::read_to_string(path)?;
+        contents.parse()
+    }
+
+    /// Checks the configuration for values that would produce a misbehaving limiter.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.update_interval_ms == 0 {
+            return Err(ConfigError::Invalid(
+                "update_interval_ms must be greater than zero".to_string(),
+            ));
+        }
+        if self.exchange_interval_ms == 0 {
+            return Err(ConfigError::Invalid(
+                "exchange_interval_ms must be greater than zero".to_string(),
+            ));
+        }
+        if self.segment_idle_timeout_ms == Some(0) {
+            return Err(ConfigError::Invalid(
+                "segment_idle_timeout_ms must be greater than zero".to_string(),
+            ));
+        }
+        if let Some(DiscoveryConfig::Kubernetes(kubernetes)) = &self.discovery {
+            if kubernetes.service.is_empty() || kubernetes.refresh_interval_ms == 0 {
+                return Err(ConfigError::Invalid(
+                    "discovery requires a service name and a non-zero refresh_interval_ms"
+                        .to_string(),
+                ));
+            }
+        }
+        if let Some(gossip) = &self.gossip {
+            if gossip.protocol_period_ms == 0 || gossip.probe_timeout_ms == 0 {
+                return Err(ConfigError::Invalid(
+                    "gossip protocol_period_ms and probe_timeout_ms must be greater than zero"
+                        .to_string(),
+                ));
+            }
+        }
+        if self
+            .peer_staleness_ms
+            .is_some_and(|staleness| staleness <= self.exchange_interval_ms)
+        {
+            return Err(ConfigError::Invalid(
+                "peer_staleness_ms must be greater than exchange_interval_ms".to_string(),
+            ));
+        }
+        if let Some(overload) = &self.overload {
+            if !overload.max_rpc_rate.is_finite()
+                || overload.max_rpc_rate <= 0.0
+                || overload.max_event_loop_lag_ms == 0
+            {
+                return Err(ConfigError::Invalid(
+                    "overload max_rpc_rate and max_event_loop_lag_ms must be positive".to_string(),
+                ));
+            }
+        }
+        if let Some(chaos) = &self.chaos {
+            let rates = [
+                chaos.exchange_drop_rate,
+                chaos.exchange_corruption_rate,
+                chaos.rpc_failure_rate,
+            ];
+            if rates.iter().any(|rate| !(0.0..=1.0).contains(rate)) {
+                return Err(ConfigError::Invalid(
+                    "chaos rates must be between 0 and 1".to_string(),
+                ));
+            }
+        }
+        if self
+            .audit
+            .as_ref()
+            .is_some_and(|audit| !(0.0..=1.0).contains(&audit.sample_rate))
+        {
+            return Err(ConfigError::Invalid(
+                "audit sample_rate must be between 0 and 1".to_string(),
+            ));
+        }
+        if self.exchange_limits.max_segments == 0 || self.exchange_limits.max_payload_bytes == 0 {
+            return Err(ConfigError::Invalid(
+                "exchange_limits max_segments and max_payload_bytes must be greater than zero"
+                    .to_string(),
+            ));
+        }
+        let retry = &self.exchange_retry;
+        if retry.failure_threshold == 0
+            || retry.initial_backoff_ms == 0
+            || retry.max_concurrent_exchanges == 0
+        {
+            return Err(ConfigError::Invalid(
+                "exchange_retry failure_threshold, initial_backoff_ms, and max_concurrent_exchanges must be greater than zero"
+                    .to_string(),
+            ));
+        }
+        if retry.max_backoff_ms < retry.initial_backoff_ms {
+            return Err(ConfigError::Invalid(
+                "exchange_retry max_backoff_ms must not be less than initial_backoff_ms"
+                    .to_string(),
+            ));
+        }
+        for (node, weight) in &self.peer_weights {
+            if !weight.is_finite() || *weight < 0.0 {
+                return Err(ConfigError::Invalid(format!(
+                    "peer_weights.{}: weight must be a non-negative number",
+                    node
+                )));
+            }
+        }
+        if let Some(locality) = &self.locality {
+            let weights = [
+                locality.same_zone_weight,
+                locality.same_region_weight,
+                locality.other_region_weight,
+            ];
+            if locality.region.is_empty() {
+                return Err(ConfigError::Invalid(
+                    "locality region must not be empty".to_string(),
+                ));
+            }
+            if weights
+                .into_iter()
+                .any(|weight| !weight.is_finite() || weight < 0.0)
+            {
+                return Err(ConfigError::Invalid(
+                    "locality weights must be non-negative numbers".to_string(),
+                ));
+            }
+            if locality
+                .region_share
+                .is_some_and(|share| !(share > 0.0 && share <= 1.0))
+            {
+                return Err(ConfigError::Invalid(
+                    "locality region_share must be greater than 0 and at most 1".to_string(),
+                ));
+            }
+        }
+        if let Some(leader_election) = &self.leader_election {
+            if leader_election.lease_ttl_ms <= self.exchange_interval_ms {
+                return Err(ConfigError::Invalid(
+                    "leader_election lease_ttl_ms must be greater than exchange_interval_ms"
+                        .to_string(),
+                ));
+            }
+        }
+        for token in &self.auth.tokens {
+            if token.identity.is_empty() {
+                return Err(ConfigError::Invalid(
+                    "auth tokens require an identity".to_string(),
+                ));
+            }
+            if token.token.is_some() == token.token_env.is_some() {
+                return Err(ConfigError::Invalid(format!(
+                    "auth token for {:?} must set exactly one of token or token_env",
+                    token.identity
+                )));
+            }
+        }
+        self.default_segment.validate("default_segment")?;
+        for (segment_name, segment) in &self.segments {
+            segment.validate(&format!("segments.{}", segment_name))?;
+            validate_segment_name(segment_name, &format!("segments.{}", segment_name))?;
+            let reserved = segment_name
+                .split_once(NAMESPACE_SEPARATOR)
+                .is_some_and(|(prefix, _)| self.namespaces.contains_key(prefix));
+            if reserved {
+                return Err(ConfigError::Invalid(format!(
+                    "segments.{}: names starting with a namespace are reserved",
+                    segment_name
+                )));
+            }
+        }
+        for (namespace_name, namespace) in &self.namespaces {
+            if namespace_name.is_empty() || namespace_name.contains(NAMESPACE_SEPARATOR) {
+                return Err(ConfigError::Invalid(format!(
+                    "namespace names must be non-empty and must not contain {:?}",
+                    NAMESPACE_SEPARATOR
+                )));
+            }
+            if let Some(default_segment) = &namespace.default_segment {
+                default_segment
+                    .validate(&format!("namespaces.{}.default_segment", namespace_name))?;
+            }
+            for (segment_name, segment) in &namespace.segments {
+                let name = format!("namespaces.{}.segments.{}", namespace_name, segment_name);
+                segment.validate(&name)?;
+                validate_segment_name(segment_name, &name)?;
+            }
+        }
+        self.pid_controller()?;
+        Ok(())
+    }
+
+    /// Returns the interval at which the segment limiters update their target rates.
+    pub fn update_interval(&self) -> Duration {
+        Duration::from_millis(self.update_interval_ms)
+    }
+
+    /// Returns the interval at which metrics are exchanged with peers.
+    pub fn exchange_interval(&self) -> Duration {
+        Duration::from_millis(self.exchange_interval_ms)
+    }
+
+    /// Returns how long a peer's metrics count without being refreshed.
+    pub fn peer_staleness(&self) -> Duration {
+        self.peer_staleness_ms
+            .map(Duration::from_millis)
+            .unwrap_or(self.exchange_interval() * 3)
+    }
+
+    /// Returns how long a dynamically created segment may stay idle, if eviction is enabled.
+    pub fn segment_idle_timeout(&self) -> Option<Duration> {
+        self.segment_idle_timeout_ms.map(Duration::from_millis)
+    }
+
+    /// Builds the PID controller template used by the segment limiters.
+    ///
+    /// The template's setpoint is the default segment's target; each segment limiter replaces
+    /// it with its own segment's target.
+    pub fn pid_controller(&self) -> Result<PIDController<f32>, ConfigError> {
+        let mut builder = PIDControllerBuilder::new(self.default_segment.target_tps)
+            .kp(self.pid.kp)
+            .ki(self.pid.ki)
+            .kd(self.pid.kd)
+            .error_bias(self.pid.error_bias);
+        if let Some(error_limit) = self.pid.error_limit {
+            builder = builder.error_limit(error_limit);
+        }
+        if let Some(output_limit) = self.pid.output_limit {
+            builder = builder.output_limit(output_limit);
+        }
+        Ok(builder.try_build()?)
+    }
+
+    /// Returns the per-segment configuration as proto `SegmentConfig` messages, keyed by segment
+    /// key.
+    pub fn segment_configs(&self) -> HashMap<String, SegmentConfig> {
+        let namespaced = self
+            .namespaces
+            .iter()
+            .flat_map(|(namespace_name, namespace)| {
+                namespace.segments.iter().map(|(segment_name, segment)| {
+                    (segment_key(Some(namespace_name), segment_name), segment)
+                })
+            });
+        self.segments
+            .iter()
+            .map(|(segment_name, segment)| (segment_name.clone(), segment))
+            .chain(namespaced)
+            .map(|(key, segment)| (key, self.regional(segment.into())))
+            .collect()
+    }
+
+    /// Returns the config of segments without an explicit entry in the default namespace.
+    pub fn default_segment_config(&self) -> SegmentConfig {
+        self.regional((&self.default_segment).into())
+    }
+
+    /// Scales the rates of `config` down to this node's region's share of them.
+    fn regional(&self, mut config: SegmentConfig) -> SegmentConfig {
+        let Some(share) = self
+            .locality
+            .as_ref()
+            .and_then(|locality| locality.region_share)
+        else {
+            return config;
+        };
+        config.target_tps *= share;
+        config.min_tps = config.min_tps.map(|min_tps| min_tps * share);
+        config.max_tps = config.max_tps.map(|max_tps| max_tps * share);
+        config
+    }
+
+    /// Returns the default segment config of each namespace.
+    pub fn namespace_defaults(&self) -> HashMap<String, SegmentConfig> {
+        self.namespaces
+            .iter()
+            .map(|(namespace_name, namespace)| {
+                let default_segment = namespace
+                    .default_segment
+                    .as_ref()
+                    .unwrap_or(&self.default_segment);
+                (
+                    namespace_name.clone(),
+                    self.regional(default_segment.into()),
+                )
+            })
+            .collect()
+    }
+
+    /// Logs the effective configuration at startup, after command line overrides are applied.
+    ///
+    /// Tokens are never logged, only the identities they authenticate.
+    pub fn log_summary(&self, node_name: &str) {
+        let discovery = self
+            .discovery
+            .as_ref()
+            .map(|DiscoveryConfig::Kubernetes(kubernetes)| kubernetes.service.as_str());
+        let identities: Vec<&str> = self
+            .auth
+            .tokens
+            .iter()
+            .map(|token| token.identity.as_str())
+            .collect();
+        tracing::info!(
+            node = %node_name,
+            listen_addr = %self.listen_addr,
+            peers = ?self.peers,
+            discovery = ?discovery,
+            gossip = self.gossip.is_some(),
+            peer_weights = ?self.peer_weights,
+            region = ?self.locality.as_ref().map(|locality| locality.region.as_str()),
+            zone = ?self.locality.as_ref().and_then(|locality| locality.zone.as_deref()),
+            region_share = ?self.locality.as_ref().and_then(|locality| locality.region_share),
+            lease_ttl_ms = ?self
+                .leader_election
+                .as_ref()
+                .map(|leader_election| leader_election.lease_ttl_ms),
+            tls = self.tls.is_some(),
+            mutual_tls = self.tls.as_ref().is_some_and(|tls| tls.ca_path.is_some()),
+            auth_identities = ?identities,
+            admin_identities = ?self.auth.admin_identities,
+            peer_token = self.auth.peer_token.is_some(),
+            otlp_endpoint = ?self.telemetry.otlp_endpoint,
+            update_interval_ms = self.update_interval_ms,
+            exchange_interval_ms = self.exchange_interval_ms,
+            peer_staleness_ms = self.peer_staleness().as_millis() as u64,
+            overload_max_rpc_rate = ?self.overload.as_ref().map(|overload| overload.max_rpc_rate),
+            audit_sample_rate = ?self.audit.as_ref().map(|audit| audit.sample_rate),
+            audit_path = ?self.audit.as_ref().and_then(|audit| audit.path.as_ref()),
+            exchange_failure_threshold = self.exchange_retry.failure_threshold,
+            exchange_max_backoff_ms = self.exchange_retry.max_backoff_ms,
+            max_concurrent_exchanges = self.exchange_retry.max_concurrent_exchanges,
+            max_exchange_segments = self.exchange_limits.max_segments,
+            max_exchange_payload_bytes = self.exchange_limits.max_payload_bytes,
+            segment_idle_timeout_ms = ?self.segment_idle_timeout_ms,
+            max_dynamic_segments = self.max_dynamic_segments,
+            "starting sentinel"
+        );
+        tracing::info!(
+            kp = self.pid.kp,
+            ki = self.pid.ki,
+            kd = self.pid.kd,
+            error_bias = self.pid.error_bias,
+            error_limit = ?self.pid.error_limit,
+            output_limit = ?self.pid.output_limit,
+            "pid controller"
+        );
+        tracing::info!(
+            segment = "default",
+            target_tps = self.default_segment.target_tps,
+            min_tps = ?self.default_segment.min_tps,
+            max_tps = ?self.default_segment.max_tps,
+            caller_max_tps = ?self.default_segment.caller_max_tps,
+            enforcement = ?self.default_segment.enforcement,
+            "segment configured"
+        );
+        let mut namespace_names: Vec<&String> = self.namespaces.keys().collect();
+        namespace_names.sort();
+        for namespace_name in namespace_names {
+            let default_segment = &self.namespace_defaults()[namespace_name];
+            tracing::info!(
+                namespace = %namespace_name,
+                target_tps = default_segment.target_tps,
+                min_tps = ?default_segment.min_tps,
+                max_tps = ?default_segment.max_tps,
+                "namespace configured"
+            );
+        }
+        let segments = self.segment_configs();
+        let mut segment_keys: Vec<&String> = segments.keys().collect();
+        segment_keys.sort();
+        for segment_name in segment_keys {
+            let segment = &segments[segment_name];
+            tracing::info!(
+                segment = %segment_name,
+                target_tps = segment.target_tps,
+                min_tps = ?segment.min_tps,
+                max_tps = ?segment.max_tps,
+                caller_max_tps = ?segment.caller_max_tps,
+                enforcement = segment.enforcement().as_str_name(),
+                "segment configured"
+            );
+        }
+    }
+}
+
+impl std::str::FromStr for SentinelConfig {
+    type Err = ConfigError;
+
+    fn from_str(contents: &str) -> Result<Self, Self::Err> {
+        let config: SentinelConfig = toml::from_str(contents)?;
+        config.validate()?;
+        Ok(config)
+    }
+}
+
+impl SegmentSettings {
+    fn validate(&self, name: &str) -> Result<(), ConfigError> {
+        let invalid = |reason: &str| Err(ConfigError::Invalid(format!("{}: {}", name, reason)));
+
+        if !self.target_tps.is_finite() || self.target_tps <= 0.0 {
+            return invalid("target_tps must be a positive number");
+        }
+        if let Some(min_tps) = self.min_tps {
+            if !min_tps.is_finite() || min_tps < 0.0 || min_tps > self.target_tps {
+                return invalid("min_tps must be between zero and target_tps");
+            }
+        }
+        if let Some(max_tps) = self.max_tps {
+            if !max_tps.is_finite() || max_tps < self.target_tps {
+                return invalid("max_tps must not be less than target_tps");
+            }
+        }
+        if let Some(caller_max_tps) = self.caller_max_tps {
+            if !caller_max_tps.is_finite() || caller_max_tps <= 0.0 {
+                return invalid("caller_max_tps must be a positive number");
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Rejects segment names using the pattern wildcard anywhere but at the end.
+fn validate_segment_name(segment_name: &str, name: &str) -> Result<(), ConfigError> {
+    if segment_name
+        .trim_end_matches(PATTERN_WILDCARD)
+        .contains(PATTERN_WILDCARD)
+        || segment_name.ends_with("**")
+    {
+        return Err(ConfigError::Invalid(format!(
+            "{}: {:?} may only end a pattern",
+            name, PATTERN_WILDCARD
+        )));
+    }
+    Ok(())
+}
+
+impl From<&SegmentSettings> for SegmentConfig {
+    fn from(segment: &SegmentSettings) -> Self {
+        SegmentConfig {
+            target_tps: segment.target_tps,
+            min_tps: segment.min_tps,
+            max_tps: segment.max_tps,
+            caller_max_tps: segment.caller_max_tps,
+            enforcement: match segment.enforcement {
+                EnforcementMode::Observe => Enforcement::Observe,
+                EnforcementMode::Enforce => Enforcement::Enforce,
+            }
+            .into(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_full_config() {
+        let config: SentinelConfig = r#"
+            listen_addr = "127.0.0.1:9090"
+            node_name = "sentinel-1"
+            peers = ["http://sentinel-2:8080"]
+            update_interval_ms = 500
+            exchange_interval_ms = 250
+
+            [pid]
+            kp = 0.8
+            ki = 0.05
+            kd = 0.04
+            error_limit = 10.0
+            output_limit = 3.0
+
+            [default_segment]
+            target_tps = 80.0
+
+            [segments.checkout]
+            target_tps = 50.0
+            min_tps = 25.0
+            max_tps = 75.0
+
+            [segments.search]
+            target_tps = 20.0
+            enforcement = "observe"
+        "#
+        .parse()
+        .unwrap();
+
+        assert_eq!(config.listen_addr, "127.0.0.1:9090".parse().unwrap());
+        assert_eq!(config.node_name.as_deref(), Some("sentinel-1"));
+        assert_eq!(config.peers, vec!["http://sentinel-2:8080".to_string()]);
+        assert_eq!(config.update_interval(), Duration::from_millis(500));
+        assert_eq!(config.exchange_interval(), Duration::from_millis(250));
+        assert_eq!(config.default_segment.target_tps, 80.0);
+        let checkout = &config.segment_configs()["checkout"];
+        assert_eq!(checkout.target_tps, 50.0);
+        assert_eq!(checkout.min_tps, Some(25.0));
+        assert_eq!(checkout.max_tps, Some(75.0));
+        assert_eq!(checkout.enforcement(), Enforcement::Enforce);
+        let search = &config.segment_configs()["search"];
+        assert_eq!(search.enforcement(), Enforcement::Observe);
+        assert_eq!(config.pid_controller().unwrap().setpoint(), 80.0);
+    }
+
+    #[test]
+    fn test_parse_empty_config_uses_defaults() {
+        let config: SentinelConfig = "".parse().unwrap();
+        assert_eq!(config.listen_addr, default_listen_addr());
+        assert!(config.peers.is_empty());
+        assert_eq!(config.default_segment.target_tps, 100.0);
+        assert_eq!(config.peer_staleness(), Duration::from_secs(3));
+        assert_eq!(config.max_dynamic_segments, 10_000);
+    }
+
+    #[test]
+    fn test_parse_kubernetes_discovery() {
+        let config: SentinelConfig = r#"
+            [discovery]
+            mode = "kubernetes"
+            service = "nenya-sentinel.default.svc.cluster.local"
+        "#
+        .parse()
+        .unwrap();
+
+        let Some(DiscoveryConfig::Kubernetes(kubernetes)) = config.discovery else {
+            panic!("expected kubernetes discovery");
+        };
+        assert_eq!(
+            kubernetes.service,
+            "nenya-sentinel.default.svc.cluster.local"
+        );
+        assert_eq!(kubernetes.port, 8080);
+        assert_eq!(kubernetes.refresh_interval(), Duration::from_secs(10));
+        assert_eq!(kubernetes.pod_ip_env, "POD_IP");
+    }
+
+    #[test]
+    fn test_parse_gossip() {
+        let config: SentinelConfig = r#"
+            [gossip]
+            advertise_addr = "http://10.0.0.1:8080"
+            suspicion_timeout_ms = 3000
+        "#
+        .parse()
+        .unwrap();
+
+        let gossip = config.gossip.unwrap();
+        assert_eq!(
+            gossip.advertise_addr.as_deref(),
+            Some("http://10.0.0.1:8080")
+        );
+        assert_eq!(gossip.protocol_period(), Duration::from_secs(1));
+        assert_eq!(gossip.suspicion_timeout(), Duration::from_secs(3));
+        assert_eq!(gossip.indirect_probes, 3);
+    }
+
+    #[test]
+    fn test_parse_peer_weights() {
+        let config: SentinelConfig = r#"
+            [peer_weights]
+            canary-1 = 0.25
+            "sentinel.eu-west-1" = 0.0
+        "#
+        .parse()
+        .unwrap();
+        assert_eq!(config.peer_weights["canary-1"], 0.25);
+        assert_eq!(config.peer_weights["sentinel.eu-west-1"], 0.0);
+
+        let result: Result<SentinelConfig, _> = "peer_weights = { canary-1 = -1.0 }".parse();
+        assert!(matches!(result, Err(ConfigError::Invalid(_))));
+    }
+
+    #[test]
+    fn test_parse_namespaces() {
+        let config: SentinelConfig = r#"
+            [default_segment]
+            target_tps = 80.0
+
+            [namespaces.payments.default_segment]
+            target_tps = 20.0
+
+            [namespaces.payments.segments.checkout]
+            target_tps = 5.0
+
+            [namespaces.search]
+        "#
+        .parse()
+        .unwrap();
+
+        assert_eq!(
+            config.segment_configs()["payments/checkout"].target_tps,
+            5.0
+        );
+        let defaults = config.namespace_defaults();
+        assert_eq!(defaults["payments"].target_tps, 20.0);
+        assert_eq!(defaults["search"].target_tps, 80.0);
+
+        let result: Result<SentinelConfig, _> = r#"
+            [segments."payments/checkout"]
+            target_tps = 5.0
+
+            [namespaces.payments]
+        "#
+        .parse();
+        assert!(matches!(result, Err(ConfigError::Invalid(_))));
+    }
+
+    #[test]
+    fn test_parse_exchange_retry() {
+        let config: SentinelConfig = r#"
+            [exchange_retry]
+            failure_threshold = 5
+            max_backoff_ms = 10000
+        "#
+        .parse()
+        .unwrap();
+        let policy = config.exchange_retry.retry_policy();
+        assert_eq!(policy.failure_threshold, 5);
+        assert_eq!(policy.initial_backoff, Duration::from_millis(500));
+        assert_eq!(policy.max_backoff, Duration::from_secs(10));
+        assert_eq!(policy.max_concurrent_exchanges, 16);
+
+        let result = "[exchange_retry]\nmax_backoff_ms = 100".parse::<SentinelConfig>();
+        assert!(matches!(result, Err(ConfigError::Invalid(_))));
+    }
+
+    #[test]
+    fn test_parse_locality() {
+        let config: SentinelConfig = r#"
+            [default_segment]
+            target_tps = 100.0
+            max_tps = 200.0
+
+            [segments.checkout]
+            target_tps = 50.0
+
+            [locality]
+            region = "us-east"
+            zone = "us-east-1a"
+            other_region_weight = 0.0
+            region_share = 0.25
+        "#
+        .parse()
+        .unwrap();
+        let policy = config.locality.as_ref().unwrap().policy();
+        assert_eq!(policy.local.zone, "us-east-1a");
+        assert_eq!(policy.same_region_weight, 1.0);
+        assert_eq!(policy.other_region_weight, 0.0);
+
+        let default_segment = config.default_segment_config();
+        assert_eq!(default_segment.target_tps, 25.0);
+        assert_eq!(default_segment.max_tps, Some(50.0));
+        assert_eq!(config.segment_configs()["checkout"].target_tps, 12.5);
+
+        let result =
+            "[locality]\nregion = \"us-east\"\nregion_share = 0.0".parse::<SentinelConfig>();
+        assert!(matches!(result, Err(ConfigError::Invalid(_))));
+    }
+
+    #[test]
+    fn test_parse_exchange_limits() {
+        let config: SentinelConfig = "[exchange_limits]\nmax_segments = 1000".parse().unwrap();
+        let limits = config.exchange_limits.limits();
+        assert_eq!(limits.max_segments, 1000);
+        assert_eq!(limits.max_payload_bytes, 4 * 1024 * 1024);
+
+        let result = "[exchange_limits]\nmax_segments = 0".parse::<SentinelConfig>();
+        assert!(matches!(result, Err(ConfigError::Invalid(_))));
+    }
+
+    #[test]
+    fn test_parse_chaos() {
+        let config: SentinelConfig = r#"
+            [chaos]
+            max_exchange_delay_ms = 250
+            exchange_drop_rate = 0.1
+        "#
+        .parse()
+        .unwrap();
+        let chaos = config.chaos.unwrap().chaos();
+        assert_eq!(chaos.max_exchange_delay, Duration::from_millis(250));
+        assert_eq!(chaos.exchange_drop_rate, 0.1);
+        assert_eq!(chaos.rpc_failure_rate, 0.0);
+
+        let result = "[chaos]\nrpc_failure_rate = 2.0".parse::<SentinelConfig>();
+        assert!(matches!(result, Err(ConfigError::Invalid(_))));
+    }
+
+    #[test]
+    fn test_parse_audit() {
+        let audit = "[audit]".parse::<SentinelConfig>().unwrap().audit.unwrap();
+        assert_eq!(audit.sample_rate, 0.01);
+        assert_eq!(audit.path, None);
+
+        let result = "[audit]\nsample_rate = 1.5".parse::<SentinelConfig>();
+        assert!(matches!(result, Err(ConfigError::Invalid(_))));
+    }
+
+    #[test]
+    fn test_parse_leader_election() {
+        let config: SentinelConfig = "[leader_election]".parse().unwrap();
+        assert_eq!(
+            config.leader_election.unwrap().lease_ttl(),
+            Duration::from_secs(5)
+        );
+
+        let result: Result<SentinelConfig, _> = r#"
+            exchange_interval_ms = 5000
+
+            [leader_election]
+            lease_ttl_ms = 5000
+        "#
+        .parse();
+        assert!(matches!(result, Err(ConfigError::Invalid(_))));
+    }
+
+    #[test]
+    fn test_parse_tls() {
+        let config: SentinelConfig = r#"
+            [tls]
+            cert_path = "/etc/nenya/tls.crt"
+            key_path = "/etc/nenya/tls.key"
+            ca_path = "/etc/nenya/ca.crt"
+            domain_name = "sentinel.nenya.svc"
+        "#
+        .parse()
+        .unwrap();
+
+        let tls = config.tls.unwrap();
+        assert_eq!(tls.cert_path, PathBuf::from("/etc/nenya/tls.crt"));
+        assert_eq!(tls.ca_path, Some(PathBuf::from("/etc/nenya/ca.crt")));
+        assert_eq!(tls.domain_name.as_deref(), Some("sentinel.nenya.svc"));
+        assert!(tls.require_client_auth);
+    }
+
+    #[test]
+    fn test_parse_auth() {
+        let config: SentinelConfig = r#"
+            [[auth.tokens]]
+            identity = "checkout"
+            token = "secret"
+
+            [[auth.tokens]]
+            identity = "search"
+            token_env = "NENYA_TEST_SEARCH_TOKEN"
+
+            [auth.peer_token]
+            token = "peer-secret"
+        "#
+        .parse()
+        .unwrap();
+
+        assert_eq!(config.auth.tokens.len(), 2);
+        assert_eq!(config.auth.tokens[0].resolve().unwrap(), "secret");
+        assert!(config.auth.tokens[1].resolve().is_err());
+        assert_eq!(
+            config.auth.peer_token.unwrap().resolve().unwrap(),
+            "peer-secret"
+        );
+    }
+
+    #[test]
+    fn test_rejects_ambiguous_token() {
+        let result = r#"
+            [[auth.tokens]]
+            identity = "checkout"
+            token = "secret"
+            token_env = "SECRET"
+        "#
+        .parse::<SentinelConfig>();
+        assert!(matches!(result, Err(ConfigError::Invalid(_))));
+    }
+
+    #[test]
+    fn test_rejects_unknown_fields() {
+        let result = "listen_address = \"[::1]:8080\"".parse::<SentinelConfig>();
+        assert!(matches!(result, Err(ConfigError::Parse(_))));
+    }
+
+    #[test]
+    fn test_rejects_invalid_segment_bounds() {
+        for bounds in ["min_tps = 60.0", "caller_max_tps = 0.0"] {
+            let result = format!("[segments.checkout]\ntarget_tps = 50.0\n{}", bounds)
+                .parse::<SentinelConfig>();
+            assert!(matches!(result, Err(ConfigError::Invalid(_))));
+        }
+        for pattern in ["api.*.read", "api.**"] {
+            let result =
+                format!("[segments.\"{}\"]\ntarget_tps = 50.0", pattern).parse::<SentinelConfig>();
+            assert!(matches!(result, Err(ConfigError::Invalid(_))));
+        }
+    }
+
+    #[test]
+    fn test_rejects_invalid_pid_gains() {
+        let result = r#"
+            [pid]
+            kp = 1.0
+            output_limit = 0.0
+        "#
+        .parse::<SentinelConfig>();
+        assert!(matches!(result, Err(ConfigError::Invalid(_))));
+    }
+
+    #[test]
+    fn test_example_config_is_valid() {
+        let config: SentinelConfig = include_str!("../sentinel.example.toml").parse().unwrap();
+        assert!(config.segments.contains_key("checkout"));
+    }
+
+    #[tokio::test]
+    async fn test_segments_control_towards_their_own_target() {
+        let config: SentinelConfig = r#"
+            [default_segment]
+            target_tps = 100.0
+
+            [segments.checkout]
+            target_tps = 50.0
+        "#
+        .parse()
+        .unwrap();
+        let service = crate::SentinelService::new(
+            "local".to_string(),
+            config.segment_configs(),
+            (&config.default_segment).into(),
+            config.pid_controller().unwrap(),
+            config.update_interval(),
+        );
+
+        let segments = service.segments.read().await;
+        assert_eq!(segments["checkout"].rate_limiter.setpoint(), 50.0);
+    }
+}
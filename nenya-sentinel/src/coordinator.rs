@@ -0,0 +1,114 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use nenya::distributed::{compute_shares, NodeMetrics, TargetShares};
+
+use crate::SentinelService;
+
+impl SentinelService {
+    /// Renews the coordinator lease and, while this node holds it, assigns every node its share
+    /// of each segment's target rate.
+    ///
+    /// The coordinator's own limiters keep adjusting the target rates from the cluster-wide
+    /// rates, while the other nodes enforce the shares on their local traffic alone.
+    pub async fn coordinate(&self, lease_ttl: Duration) {
+        match self.backend.acquire_lease(&self.hostname, lease_ttl).await {
+            Ok(true) => {}
+            Ok(false) => return,
+            Err(err) => {
+                tracing::warn!(%err, "unable to acquire coordinator lease");
+                return;
+            }
+        }
+        let mut nodes = match self.backend.node_metrics().await {
+            Ok(nodes) => nodes,
+            Err(err) => {
+                tracing::warn!(%err, "unable to read peer metrics");
+                return;
+            }
+        };
+        nodes.retain(|node| node.node != self.hostname);
+        nodes.push(NodeMetrics::from(self.local_metrics().await));
+
+        let targets: HashMap<String, f32> = {
+            let mut segments = self.segments.write().await;
+            segments
+                .iter_mut()
+                .map(|(segment_id, segment)| {
+                    // Take back PID control of segments assigned while following another node
+                    segment.set_share(None);
+                    (segment_id.clone(), segment.rate_limiter.target_rate())
+                })
+                .collect()
+        };
+        let shares = TargetShares {
+            leader: self.hostname.clone(),
+            nodes: compute_shares(&nodes, &targets),
+        };
+        tracing::debug!(
+            nodes = nodes.len(),
+            segments = targets.len(),
+            "assigned target shares"
+        );
+        if let Err(err) = self.backend.publish_shares(shares).await {
+            tracing::warn!(%err, "unable to publish target shares");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sentinel::SegmentConfig;
+    use nenya::distributed::{DistributedStateBackend, InMemoryBackend};
+    use nenya::pid_controller::PIDController;
+    use std::sync::Arc;
+
+    fn create_service(hostname: &str, backend: Arc<InMemoryBackend>) -> SentinelService {
+        SentinelService::new(
+            hostname.to_string(),
+            HashMap::from([(
+                "checkout".to_string(),
+                SegmentConfig {
+                    target_tps: 10.0,
+                    min_tps: None,
+                    max_tps: None,
+                    ..Default::default()
+                },
+            )]),
+            SegmentConfig::default(),
+            PIDController::new_static_controller(10.0),
+            Duration::from_secs(1),
+        )
+        .with_backend(backend)
+        .with_leader_election(Duration::from_secs(60))
+    }
+
+    #[tokio::test]
+    async fn test_followers_enforce_assigned_shares() {
+        let backend = Arc::new(InMemoryBackend::default());
+        let leader = create_service("a", backend.clone());
+        let follower = create_service("b", backend.clone());
+        for service in [&leader, &follower] {
+            let metrics = service.local_metrics().await;
+            backend.publish(metrics.into()).await.unwrap();
+        }
+
+        let ttl = Duration::from_secs(60);
+        leader.coordinate(ttl).await;
+        follower.coordinate(ttl).await;
+        let shares = backend.shares().await.unwrap().unwrap();
+        assert_eq!(shares.leader, "a");
+        assert_eq!(shares.share("b", "checkout"), Some(5.0));
+
+        leader.apply_peer_metrics().await;
+        follower.apply_peer_metrics().await;
+        let leader_segments = leader.segments.read().await;
+        let follower_segments = follower.segments.read().await;
+        assert_eq!(leader_segments["checkout"].rate_limiter.target_rate(), 10.0);
+        assert_eq!(
+            follower_segments["checkout"].rate_limiter.target_rate(),
+            5.0
+        );
+    }
+}
@@ -0,0 +1,115 @@
+use std::collections::BTreeSet;
+use std::net::IpAddr;
+
+use tokio::sync::watch;
+
+use crate::config::KubernetesDiscoveryConfig;
+
+/// Periodically resolves a Kubernetes headless service and publishes the resulting peer list.
+///
+/// The published list is the union of `static_peers` and the discovered pod addresses, excluding
+/// this pod's own address, reached over `https` if `tls` is set. Resolution failures keep the
+/// previously published list so a DNS blip does not partition the cluster.
+pub async fn run_kubernetes_discovery(
+    config: KubernetesDiscoveryConfig,
+    static_peers: Vec<String>,
+    tls: bool,
+    peers: watch::Sender<Vec<String>>,
+) {
+    let pod_ip = std::env::var(&config.pod_ip_env)
+        .ok()
+        .and_then(|pod_ip| pod_ip.parse::<IpAddr>().ok());
+    if pod_ip.is_none() {
+        tracing::warn!(
+            env = %config.pod_ip_env,
+            "pod IP is not set, this node may discover itself as a peer"
+        );
+    }
+
+    let mut ticker = tokio::time::interval(config.refresh_interval());
+    loop {
+        ticker.tick().await;
+
+        let discovered = match resolve_peers(&config, pod_ip, tls).await {
+            Ok(discovered) => discovered,
+            Err(err) => {
+                tracing::warn!(service = %config.service, %err, "peer discovery failed");
+                continue;
+            }
+        };
+        let merged: Vec<String> = static_peers
+            .iter()
+            .cloned()
+            .chain(discovered)
+            .collect::<BTreeSet<String>>()
+            .into_iter()
+            .collect();
+
+        peers.send_if_modified(|current| {
+            if *current == merged {
+                return false;
+            }
+            tracing::info!(peers = ?merged, "discovered peers changed");
+            *current = merged;
+            true
+        });
+    }
+}
+
+/// Resolves the headless service to the addresses of its pods, excluding `pod_ip`, as `https`
+/// URIs if `tls` is set and `http` ones otherwise.
+async fn resolve_peers(
+    config: &KubernetesDiscoveryConfig,
+    pod_ip: Option<IpAddr>,
+    tls: bool,
+) -> std::io::Result<Vec<String>> {
+    let scheme = if tls { "https" } else { "http" };
+    let addresses = tokio::net::lookup_host((config.service.as_str(), config.port)).await?;
+    Ok(addresses
+        .filter(|address| Some(address.ip()) != pod_ip)
+        .map(|address| format!("{}://{}", scheme, address))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_config() -> KubernetesDiscoveryConfig {
+        KubernetesDiscoveryConfig {
+            service: "localhost".to_string(),
+            port: 8080,
+            refresh_interval_ms: 10,
+            pod_ip_env: "NENYA_TEST_UNSET_POD_IP".to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_resolve_peers_excludes_own_address() {
+        let config = create_config();
+        let peers = resolve_peers(&config, None, false).await.unwrap();
+        assert!(!peers.is_empty());
+        assert!(peers.iter().all(|peer| peer.starts_with("http://")));
+
+        let own_addresses: Vec<IpAddr> = tokio::net::lookup_host(("localhost", 8080))
+            .await
+            .unwrap()
+            .map(|address| address.ip())
+            .collect();
+        for own_address in own_addresses {
+            let peers = resolve_peers(&config, Some(own_address), false)
+                .await
+                .unwrap();
+            assert!(!peers
+                .iter()
+                .any(|peer| peer.contains(&own_address.to_string())));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_resolve_peers_uses_https_with_tls() {
+        let peers = resolve_peers(&create_config(), None, true).await.unwrap();
+        assert!(!peers.is_empty());
+        assert!(peers.iter().all(|peer| peer.starts_with("https://")));
+    }
+}
@@ -0,0 +1,355 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::time::Duration;
+
+use nenya::distributed::{aggregate_weighted_peer_rates, NodeMetrics, SegmentRates};
+use tokio::sync::watch;
+
+use crate::SentinelService;
+
+impl SentinelService {
+    /// Sums the rates other nodes published to the state backend for each segment, scaled by the
+    /// configured peer weights, and applies them as the external rates of the local segment
+    /// limiters.
+    ///
+    /// Segments that no peer reports have their external rates reset to zero. With leader
+    /// election, segments the coordinator assigned this node a share of instead enforce the share
+    /// on local traffic alone.
+    pub async fn apply_peer_metrics(&self) {
+        let nodes = match self.backend.node_metrics().await {
+            Ok(nodes) => nodes,
+            Err(err) => {
+                tracing::warn!(%err, "unable to read peer metrics");
+                return;
+            }
+        };
+        let weights = self.effective_peer_weights(&nodes);
+        let totals = aggregate_weighted_peer_rates(&nodes, &self.hostname, &weights);
+        // With leader election, nodes other than the coordinator enforce their assigned shares
+        let shares = match self.lease_ttl {
+            Some(_) => self.backend.shares().await.unwrap_or_else(|err| {
+                tracing::warn!(%err, "unable to read target shares");
+                None
+            }),
+            None => None,
+        }
+        .filter(|shares| shares.leader != self.hostname);
+
+        let now = self.now();
+        let mut segments = self.segments.write().await;
+        for (segment_id, segment) in segments.iter_mut() {
+            let rates = totals.get(segment_id).copied().unwrap_or_default();
+            // Peers report every segment they hold, with zero rates once they have had no requests
+            // for a window, so only fresh traffic keeps a segment alive
+            if rates.request_rate > 0.0 {
+                segment.touch(now);
+            }
+            let share = shares
+                .as_ref()
+                .and_then(|shares| shares.share(&self.hostname, segment_id));
+            // A share is enforced on local traffic alone
+            let rates = if share.is_some() {
+                SegmentRates::default()
+            } else {
+                rates
+            };
+            segment.set_share(share);
+            if segment.expire_target_override(now) {
+                tracing::info!(segment = %segment_id, "temporary override expired");
+            }
+            let rate_limiter = &mut segment.rate_limiter;
+            rate_limiter.set_external_request_rate(rates.request_rate);
+            rate_limiter.set_external_accepted_request_rate(rates.accepted_request_rate);
+            tracing::trace!(
+                segment = %segment_id,
+                peer_request_rate = rates.request_rate,
+                peer_accepted_request_rate = rates.accepted_request_rate,
+                local_request_rate = rate_limiter.local_request_rate(),
+                target_rate = rate_limiter.target_rate(),
+                "applied peer rates"
+            );
+        }
+    }
+
+    /// Publishes the local metrics to the state backend, takes part in leader election if
+    /// enabled, and applies the aggregated peer rates.
+    pub async fn exchange_round(&self) {
+        let local_metrics = self.local_metrics().await;
+        if let Err(err) = self.backend.publish(local_metrics.into()).await {
+            tracing::warn!(%err, "unable to publish metrics");
+        }
+        if let Some(lease_ttl) = self.lease_ttl {
+            self.coordinate(lease_ttl).await;
+        }
+
+        self.apply_peer_metrics().await;
+    }
+
+    /// Returns the weight of each node's rates: its configured weight, or with a locality
+    /// policy, the weight for where it runs.
+    fn effective_peer_weights(&self, nodes: &[NodeMetrics]) -> Cow<'_, HashMap<String, f32>> {
+        let (Some(locality), Some(mesh)) = (&self.locality, &self.mesh) else {
+            return Cow::Borrowed(&self.peer_weights);
+        };
+        let localities = mesh.localities();
+        let weights = nodes
+            .iter()
+            .filter_map(|node| {
+                let weight = self
+                    .peer_weights
+                    .get(&node.node)
+                    .copied()
+                    .or_else(|| localities.get(&node.node).map(|peer| locality.weight(peer)))?;
+                Some((node.node.clone(), weight))
+            })
+            .collect();
+        Cow::Owned(weights)
+    }
+}
+
+/// Periodically publishes the local metrics to the state backend, takes part in leader election
+/// if enabled, and feeds the aggregated peer rates into the segment limiters.
+///
+/// When the mesh backend is used, its peer list is re-read whenever it changes, so peers found by
+/// discovery join the exchange without a restart.
+pub async fn run_exchange_loop(
+    service: SentinelService,
+    mut peers: watch::Receiver<Vec<String>>,
+    interval: Duration,
+) {
+    if let Some(mesh) = &service.mesh {
+        mesh.set_peers(&peers.borrow_and_update());
+    }
+
+    let mut ticker = tokio::time::interval(interval);
+    loop {
+        ticker.tick().await;
+
+        if let Some(mesh) = &service.mesh {
+            if peers.has_changed().unwrap_or(false) {
+                mesh.set_peers(&peers.borrow_and_update());
+            }
+        }
+
+        service.exchange_round().await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::MeshBackend;
+    use crate::locality::{Locality, LocalityPolicy};
+    use crate::peer::PeerConnector;
+    use crate::sentinel::{MetricData, Metrics, SegmentConfig};
+    use nenya::distributed::InMemoryBackend;
+    use nenya::pid_controller::PIDController;
+    use std::sync::Arc;
+
+    fn create_service() -> SentinelService {
+        let segments = HashMap::from([(
+            "checkout".to_string(),
+            SegmentConfig {
+                target_tps: 10.0,
+                min_tps: None,
+                max_tps: None,
+                ..Default::default()
+            },
+        )]);
+        SentinelService::new(
+            "local".to_string(),
+            segments,
+            SegmentConfig::default(),
+            PIDController::new_static_controller(10.0),
+            Duration::from_secs(1),
+        )
+        .with_mesh(Arc::new(MeshBackend::new(
+            PeerConnector::default(),
+            Duration::from_secs(1),
+        )))
+    }
+
+    fn metrics(source: &str, request_rate: f32, accepted_request_rate: f32) -> Metrics {
+        Metrics {
+            source: source.to_string(),
+            segments: HashMap::from([(
+                "checkout".to_string(),
+                MetricData {
+                    request_rate,
+                    accepted_request_rate,
+                },
+            )]),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_apply_peer_metrics_sums_peers() {
+        let service = create_service();
+        service.record_node_metrics(metrics("a", 3.0, 2.0));
+        service.record_node_metrics(metrics("b", 4.0, 1.0));
+        service.record_node_metrics(metrics("a", 5.0, 4.0));
+
+        service.apply_peer_metrics().await;
+
+        let segments = service.segments.read().await;
+        let rate_limiter = &segments["checkout"].rate_limiter;
+        assert_eq!(rate_limiter.external_request_rate(), 9.0);
+        assert_eq!(rate_limiter.external_accepted_request_rate(), 5.0);
+    }
+
+    #[tokio::test]
+    async fn test_local_metrics_exclude_external_rates() {
+        let service = create_service();
+        service.record_node_metrics(metrics("a", 3.0, 2.0));
+        service.apply_peer_metrics().await;
+
+        let local_metrics = service.local_metrics().await;
+        assert_eq!(local_metrics.source, "local");
+        assert_eq!(local_metrics.segments["checkout"].request_rate, 0.0);
+    }
+
+    #[tokio::test]
+    async fn test_idle_segments_publish_zero_rates() {
+        let service = SentinelService::new(
+            "local".to_string(),
+            HashMap::from([("checkout".to_string(), SegmentConfig::default())]),
+            SegmentConfig::default(),
+            PIDController::new_static_controller(10.0),
+            Duration::from_millis(50),
+        );
+        for _ in 0..5 {
+            service.check_segment("checkout", None, 1.0).await;
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        let local_metrics = service.local_metrics().await;
+        assert!(local_metrics.segments["checkout"].request_rate > 0.0);
+
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let local_metrics = service.local_metrics().await;
+        assert_eq!(local_metrics.segments["checkout"].request_rate, 0.0);
+        assert_eq!(
+            local_metrics.segments["checkout"].accepted_request_rate,
+            0.0
+        );
+    }
+
+    #[tokio::test]
+    async fn test_unknown_segment_is_created_and_reported() {
+        let service = create_service();
+        let response = service.check_segment("search", None, 1.0).await;
+        assert_eq!(response.node, "local");
+
+        let local_metrics = service.local_metrics().await;
+        assert!(local_metrics.segments.contains_key("search"));
+        assert!(local_metrics.segments.contains_key("checkout"));
+    }
+
+    #[tokio::test]
+    async fn test_idle_segments_are_evicted_on_every_node() {
+        let backend = Arc::new(InMemoryBackend::default());
+        let node = |hostname: &str| {
+            SentinelService::new(
+                hostname.to_string(),
+                HashMap::new(),
+                SegmentConfig::default(),
+                PIDController::new_static_controller(10.0),
+                Duration::from_millis(50),
+            )
+            .with_backend(backend.clone())
+        };
+        let (a, b) = (node("a"), node("b"));
+        let exchange = || async {
+            for node in [&a, &b] {
+                let metrics = node.local_metrics().await;
+                node.backend.publish(metrics.into()).await.unwrap();
+            }
+            for node in [&a, &b] {
+                node.apply_peer_metrics().await;
+            }
+        };
+        for _ in 0..5 {
+            a.check_segment("search", None, 1.0).await;
+            b.check_segment("search", None, 1.0).await;
+        }
+        exchange().await;
+
+        // Once traffic stops and the nodes have replaced the rates they published, they keep
+        // exchanging metrics without touching each other's copy of the segment
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let stopped = a.now();
+        for _ in 0..3 {
+            exchange().await;
+        }
+        let timeout = Duration::from_secs(60);
+        for node in [&a, &b] {
+            assert_eq!(
+                node.evict_idle_segments(stopped + timeout, timeout).await,
+                vec!["search"]
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_nodes_share_rates_through_backend() {
+        let backend = Arc::new(InMemoryBackend::default());
+        let local = create_service().with_backend(backend.clone());
+        let mut peer = create_service().with_backend(backend);
+        peer.hostname = "peer".to_string();
+        for _ in 0..5 {
+            peer.check_segment("checkout", None, 1.0).await;
+        }
+
+        let metrics = peer.local_metrics().await;
+        peer.backend.publish(metrics.into()).await.unwrap();
+        local.apply_peer_metrics().await;
+
+        let segments = local.segments.read().await;
+        assert!(segments["checkout"].rate_limiter.external_request_rate() > 0.0);
+    }
+
+    #[tokio::test]
+    async fn test_peer_weights_scale_external_rates() {
+        let service = create_service().with_peer_weights(HashMap::from([
+            ("a".to_string(), 0.5),
+            ("b".to_string(), 0.0),
+        ]));
+        service.record_node_metrics(metrics("a", 4.0, 2.0));
+        service.record_node_metrics(metrics("b", 4.0, 1.0));
+        service.apply_peer_metrics().await;
+
+        let segments = service.segments.read().await;
+        let rate_limiter = &segments["checkout"].rate_limiter;
+        assert_eq!(rate_limiter.external_request_rate(), 2.0);
+        assert_eq!(rate_limiter.external_accepted_request_rate(), 1.0);
+    }
+
+    #[tokio::test]
+    async fn test_locality_weights_peers_by_region() {
+        let locality = |region: &str| Locality {
+            region: region.to_string(),
+            zone: String::new(),
+        };
+        let service = create_service()
+            .with_peer_weights(HashMap::from([("c".to_string(), 0.5)]))
+            .with_locality(LocalityPolicy {
+                local: locality("us-east"),
+                same_zone_weight: 1.0,
+                same_region_weight: 1.0,
+                other_region_weight: 0.0,
+            });
+        let in_region = |source, region: &str| Metrics {
+            region: region.to_string(),
+            ..metrics(source, 4.0, 2.0)
+        };
+        service.record_node_metrics(in_region("a", "us-east"));
+        service.record_node_metrics(in_region("b", "eu-west"));
+        service.record_node_metrics(in_region("c", "eu-west"));
+        service.apply_peer_metrics().await;
+
+        let segments = service.segments.read().await;
+        let rate_limiter = &segments["checkout"].rate_limiter;
+        assert_eq!(rate_limiter.external_request_rate(), 6.0);
+        assert_eq!(service.local_metrics().await.region, "us-east");
+    }
+}
@@ -0,0 +1,260 @@
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use tokio::sync::watch;
+use tonic::transport::Channel;
+use tonic::Status;
+
+use crate::config::GossipConfig;
+use crate::membership::Membership;
+use crate::peer::PeerConnector;
+use crate::sentinel::sentinel_client::SentinelClient;
+use crate::sentinel::{GossipMessage, Member, PingRequestMessage};
+use crate::SentinelService;
+
+/// Shared gossip state held by the sentinel service.
+#[derive(Debug)]
+pub struct GossipState {
+    membership: Mutex<Membership>,
+    probe_timeout: Duration,
+}
+
+impl GossipState {
+    /// Creates the gossip state for the local node.
+    ///
+    /// The incarnation starts at the current Unix time so a restarted node supersedes the
+    /// tombstone its previous run left behind.
+    pub fn new(name: String, address: String, probe_timeout: Duration) -> Self {
+        let incarnation = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_secs())
+            .unwrap_or_default();
+        GossipState {
+            membership: Mutex::new(Membership::new(name, address, incarnation)),
+            probe_timeout,
+        }
+    }
+
+    /// Returns a gossip message carrying the full membership list.
+    fn message(&self) -> GossipMessage {
+        let membership = self.membership.lock().unwrap();
+        GossipMessage {
+            source: membership.local_member().name.clone(),
+            members: membership.snapshot(),
+        }
+    }
+
+    fn merge(&self, members: Vec<Member>) {
+        self.membership
+            .lock()
+            .unwrap()
+            .apply_all(members, Instant::now());
+    }
+}
+
+impl SentinelService {
+    /// Handles a direct ping by merging the sender's membership list and replying with ours.
+    pub async fn handle_gossip(&self, message: GossipMessage) -> Result<GossipMessage, Status> {
+        let gossip = self.gossip_state().ok_or_else(gossip_disabled)?;
+        gossip.merge(message.members);
+        Ok(gossip.message())
+    }
+
+    /// Handles an indirect ping by probing the target on behalf of the sender.
+    pub async fn handle_ping_request(
+        &self,
+        request: PingRequestMessage,
+    ) -> Result<GossipMessage, Status> {
+        let gossip = self.gossip_state().ok_or_else(gossip_disabled)?;
+        if let Some(message) = request.message {
+            gossip.merge(message.members);
+        }
+        let response = ping(
+            &request.target_address,
+            gossip.message(),
+            gossip.probe_timeout,
+            &self.peer_connector,
+        )
+        .await?;
+        gossip.merge(response.members.clone());
+        Ok(response)
+    }
+
+    /// Removes the metrics reported by a node so its rates stop counting toward the cluster total.
+    pub async fn forget_node(&self, name: &str) {
+        if let Err(err) = self.backend.forget(name).await {
+            tracing::warn!(node = %name, %err, "unable to forget node metrics");
+        }
+        self.apply_peer_metrics().await;
+    }
+
+    fn gossip_state(&self) -> Option<&GossipState> {
+        self.gossip.as_deref()
+    }
+}
+
+fn gossip_disabled() -> Status {
+    Status::failed_precondition("gossip is disabled on this node")
+}
+
+/// Runs the SWIM failure detector and publishes the live member addresses to `peers`.
+///
+/// Every protocol period one member is probed in round-robin order. If it does not answer, up to
+/// `indirect_probes` other members are asked to probe it; if none of them reach it either, it is
+/// marked as suspect, and suspects that are not refuted within the suspicion timeout are declared
+/// dead. Addresses in `seeds` that are not yet members are contacted to join the cluster.
+pub async fn run_gossip_loop(
+    service: SentinelService,
+    config: GossipConfig,
+    mut seeds: watch::Receiver<Vec<String>>,
+    peers: watch::Sender<Vec<String>>,
+) {
+    let Some(gossip) = service.gossip.clone() else {
+        return;
+    };
+    let suspicion_timeout = config.suspicion_timeout();
+    let connector = service.peer_connector.clone();
+    let mut next_target = 0;
+
+    let mut ticker = tokio::time::interval(config.protocol_period());
+    loop {
+        ticker.tick().await;
+
+        let seed_addresses = seeds.borrow_and_update().clone();
+        for seed in seed_addresses {
+            if gossip.membership.lock().unwrap().knows_address(&seed) {
+                continue;
+            }
+            match ping(&seed, gossip.message(), gossip.probe_timeout, &connector).await {
+                Ok(response) => gossip.merge(response.members),
+                Err(status) => tracing::debug!(%seed, %status, "unable to join seed"),
+            }
+        }
+
+        let targets = gossip.membership.lock().unwrap().probe_targets();
+        if !targets.is_empty() {
+            let target = &targets[next_target % targets.len()];
+            next_target = next_target.wrapping_add(1);
+            let reachable = probe(
+                &gossip,
+                target,
+                &targets,
+                config.indirect_probes,
+                &connector,
+            )
+            .await;
+            let mut membership = gossip.membership.lock().unwrap();
+            if !reachable && membership.suspect(&target.name, Instant::now()) {
+                tracing::info!(member = %target.name, "member suspected");
+            }
+        }
+
+        let expired = gossip
+            .membership
+            .lock()
+            .unwrap()
+            .expire_suspects(Instant::now(), suspicion_timeout);
+        for name in expired {
+            tracing::warn!(member = %name, "member declared dead");
+            service.forget_node(&name).await;
+        }
+
+        let live_addresses = gossip.membership.lock().unwrap().live_addresses();
+        peers.send_if_modified(|current| {
+            if *current == live_addresses {
+                return false;
+            }
+            *current = live_addresses;
+            true
+        });
+    }
+}
+
+/// Probes `target` directly, falling back to indirect probes through other members.
+async fn probe(
+    gossip: &GossipState,
+    target: &Member,
+    members: &[Member],
+    indirect_probes: usize,
+    connector: &PeerConnector,
+) -> bool {
+    match ping(
+        &target.address,
+        gossip.message(),
+        gossip.probe_timeout,
+        connector,
+    )
+    .await
+    {
+        Ok(response) => {
+            gossip.merge(response.members);
+            return true;
+        }
+        Err(status) => tracing::debug!(member = %target.name, %status, "direct probe failed"),
+    }
+
+    let helpers = members
+        .iter()
+        .filter(|member| member.name != target.name)
+        .take(indirect_probes);
+    for helper in helpers {
+        let request = PingRequestMessage {
+            target_address: target.address.clone(),
+            message: Some(gossip.message()),
+        };
+        match ping_request(&helper.address, request, gossip.probe_timeout, connector).await {
+            Ok(response) => {
+                gossip.merge(response.members);
+                return true;
+            }
+            Err(status) => {
+                tracing::debug!(member = %target.name, helper = %helper.name, %status, "indirect probe failed")
+            }
+        }
+    }
+    false
+}
+
+async fn connect(
+    address: &str,
+    timeout: Duration,
+    connector: &PeerConnector,
+) -> Result<SentinelClient<Channel>, Status> {
+    let endpoint = connector
+        .endpoint(address)
+        .map_err(|err| Status::invalid_argument(err.to_string()))?
+        .timeout(timeout)
+        .connect_timeout(timeout);
+    let channel = endpoint
+        .connect()
+        .await
+        .map_err(|err| Status::unavailable(err.to_string()))?;
+    Ok(SentinelClient::new(channel))
+}
+
+async fn ping(
+    address: &str,
+    message: GossipMessage,
+    timeout: Duration,
+    connector: &PeerConnector,
+) -> Result<GossipMessage, Status> {
+    let mut client = connect(address, timeout, connector).await?;
+    Ok(client
+        .gossip(connector.request(message))
+        .await?
+        .into_inner())
+}
+
+async fn ping_request(
+    address: &str,
+    request: PingRequestMessage,
+    timeout: Duration,
+    connector: &PeerConnector,
+) -> Result<GossipMessage, Status> {
+    // The helper probes the target with the same timeout, so allow for both round trips
+    let mut client = connect(address, timeout * 2, connector).await?;
+    Ok(client
+        .ping_request(connector.request(request))
+        .await?
+        .into_inner())
+}
@@ -0,0 +1,305 @@
+//! In-process cluster of sentinel nodes for tests and config validation.
+//!
+//! A [`Cluster`] runs several [`SentinelService`] instances in one process. Instead of exchanging
+//! metrics over gRPC, the nodes publish them to a shared in-memory state backend, and instead of
+//! the system clock, their segment limiters read a manual clock. Synthetic [`Traffic`] is driven
+//! through the nodes tick by tick, with an exchange round every exchange interval, so minutes of
+//! cluster behavior run in milliseconds and the same run always gives the same result.
+//!
+//! The harness is compiled for the crate's own tests and with the `test-util` feature, which
+//! also adds the `--simulate` flag to validate a config file before deploying it.
+
+#[cfg(feature = "test-util")]
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use nenya::clock::ManualClock;
+use nenya::distributed::InMemoryBackend;
+
+use crate::config::{ConfigError, SentinelConfig};
+#[cfg(feature = "test-util")]
+use crate::segment::{DEFAULT_SEGMENT, PATTERN_WILDCARD};
+use crate::SentinelService;
+
+/// Time the clock advances between batches of synthetic requests.
+const TICK: Duration = Duration::from_millis(10);
+
+/// Synthetic requests for one segment, at a fixed rate per node.
+#[derive(Debug, Clone)]
+pub struct Traffic {
+    segment: String,
+    node_rates: Vec<f32>,
+}
+
+impl Traffic {
+    /// Sends requests for `segment` to each node at its rate in `node_rates`, in requests per
+    /// second.
+    pub fn new(segment: impl Into<String>, node_rates: Vec<f32>) -> Self {
+        Traffic {
+            segment: segment.into(),
+            node_rates,
+        }
+    }
+
+    /// Spreads `total_rate` requests per second for `segment` evenly over `nodes` nodes.
+    pub fn uniform(segment: impl Into<String>, total_rate: f32, nodes: usize) -> Self {
+        Traffic::new(segment, vec![total_rate / nodes as f32; nodes])
+    }
+}
+
+/// Requests sent to and accepted by each node during a run.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TrafficReport {
+    pub duration: Duration,
+    pub offered: Vec<u64>,
+    pub accepted: Vec<u64>,
+}
+
+impl TrafficReport {
+    /// Returns the rate of requests the cluster accepted as a whole.
+    pub fn accepted_rate(&self) -> f32 {
+        self.accepted.iter().sum::<u64>() as f32 / self.duration.as_secs_f32()
+    }
+
+    /// Returns the rate of requests `node` accepted.
+    pub fn node_accepted_rate(&self, node: usize) -> f32 {
+        self.accepted[node] as f32 / self.duration.as_secs_f32()
+    }
+}
+
+/// Sentinel nodes sharing rates through an in-memory backend, on a manual clock.
+#[derive(Debug)]
+pub struct Cluster {
+    nodes: Vec<SentinelService>,
+    clock: ManualClock,
+    exchange_interval: Duration,
+}
+
+impl Cluster {
+    /// Creates a cluster of `size` nodes named `node-0`, `node-1`, and so on, built by
+    /// `make_node` from their names.
+    ///
+    /// The nodes are given the cluster's backend and clock, replacing any they were built with.
+    pub fn new(size: usize, make_node: impl Fn(String) -> SentinelService) -> Self {
+        let backend = Arc::new(InMemoryBackend::default());
+        let clock = ManualClock::new();
+        let nodes = (0..size)
+            .map(|index| {
+                make_node(format!("node-{}", index))
+                    .with_backend(backend.clone())
+                    .with_clock(Arc::new(clock.clone()))
+            })
+            .collect();
+        Cluster {
+            nodes,
+            clock,
+            exchange_interval: Duration::from_secs(1),
+        }
+    }
+
+    /// Creates a cluster of `size` nodes configured by `config`, exchanging at its interval.
+    pub fn from_config(config: &SentinelConfig, size: usize) -> Result<Self, ConfigError> {
+        let pid_controller = config.pid_controller()?;
+        let cluster = Cluster::new(size, |node_name| {
+            let mut node = SentinelService::new(
+                node_name,
+                config.segment_configs(),
+                config.default_segment_config(),
+                pid_controller.clone(),
+                config.update_interval(),
+            )
+            .with_namespaces(config.namespace_defaults())
+            .with_peer_weights(config.peer_weights.clone());
+            if let Some(leader_election) = &config.leader_election {
+                node = node.with_leader_election(leader_election.lease_ttl());
+            }
+            node
+        });
+        Ok(cluster.with_exchange_interval(config.exchange_interval()))
+    }
+
+    /// Sets the simulated time between exchange rounds.
+    pub fn with_exchange_interval(mut self, exchange_interval: Duration) -> Self {
+        self.exchange_interval = exchange_interval;
+        self
+    }
+
+    /// Runs an exchange round on every node.
+    pub async fn exchange(&self) {
+        for node in &self.nodes {
+            node.exchange_round().await;
+        }
+    }
+
+    /// Drives `traffic` through the nodes for `duration` of simulated time.
+    pub async fn run(&self, traffic: &Traffic, duration: Duration) -> TrafficReport {
+        assert_eq!(
+            traffic.node_rates.len(),
+            self.nodes.len(),
+            "traffic needs a rate for every node"
+        );
+        let mut owed = vec![0.0_f32; self.nodes.len()];
+        let mut offered = vec![0; self.nodes.len()];
+        let mut accepted = vec![0; self.nodes.len()];
+        let mut elapsed = Duration::ZERO;
+        let mut since_exchange = Duration::ZERO;
+        while elapsed < duration {
+            self.clock.advance(TICK);
+            elapsed += TICK;
+            for (index, node) in self.nodes.iter().enumerate() {
+                owed[index] += traffic.node_rates[index] * TICK.as_secs_f32();
+                while owed[index] >= 1.0 {
+                    owed[index] -= 1.0;
+                    offered[index] += 1;
+                    let response = node.check_segment(&traffic.segment, None, 1.0).await;
+                    if !response.should_throttle {
+                        accepted[index] += 1;
+                    }
+                }
+            }
+            since_exchange += TICK;
+            if since_exchange >= self.exchange_interval {
+                since_exchange = Duration::ZERO;
+                self.exchange().await;
+            }
+        }
+        TrafficReport {
+            duration,
+            offered,
+            accepted,
+        }
+    }
+}
+
+/// Runs `config` on a simulated cluster of `size` nodes and prints how close each configured
+/// segment's accepted rate comes to its target.
+///
+/// Every segment is offered twice its target rate, spread evenly over the nodes, with a warm-up
+/// before the measured run.
+#[cfg(feature = "test-util")]
+pub async fn simulate(config: &SentinelConfig, size: usize) -> Result<(), ConfigError> {
+    let mut targets: HashMap<String, f32> = config
+        .segment_configs()
+        .into_iter()
+        .filter(|(segment, _)| !segment.ends_with(PATTERN_WILDCARD))
+        .map(|(segment, segment_config)| (segment, segment_config.target_tps))
+        .collect();
+    targets
+        .entry(DEFAULT_SEGMENT.to_string())
+        .or_insert(config.default_segment_config().target_tps);
+    let mut segments: Vec<_> = targets.into_iter().collect();
+    segments.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+    println!("simulating {} nodes", size);
+    for (segment, target_tps) in segments {
+        let cluster = Cluster::from_config(config, size)?;
+        let traffic = Traffic::uniform(segment.as_str(), target_tps * 2.0, size);
+        cluster.run(&traffic, Duration::from_secs(10)).await;
+        let report = cluster.run(&traffic, Duration::from_secs(30)).await;
+        let accepted_rate = report.accepted_rate();
+        let node_rates: Vec<String> = (0..size)
+            .map(|node| format!("{:.1}", report.node_accepted_rate(node)))
+            .collect();
+        println!(
+            "{}: target {:.1} tps, accepted {:.1} tps ({:+.1}%), per node {}",
+            segment,
+            target_tps,
+            accepted_rate,
+            (accepted_rate / target_tps - 1.0) * 100.0,
+            node_rates.join(", ")
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::segment::DEFAULT_SEGMENT;
+    use crate::sentinel::SegmentConfig;
+    use nenya::pid_controller::PIDController;
+    use std::collections::HashMap;
+
+    fn cluster(size: usize, target_tps: f32) -> Cluster {
+        Cluster::new(size, |node_name| {
+            SentinelService::new(
+                node_name,
+                HashMap::from([(
+                    "checkout".to_string(),
+                    SegmentConfig {
+                        target_tps,
+                        min_tps: None,
+                        max_tps: None,
+                        ..Default::default()
+                    },
+                )]),
+                SegmentConfig::default(),
+                PIDController::new_static_controller(target_tps),
+                Duration::from_secs(1),
+            )
+        })
+        .with_exchange_interval(Duration::from_millis(100))
+    }
+
+    #[tokio::test]
+    async fn test_cluster_converges_on_global_target() {
+        let cluster = cluster(3, 30.0);
+        let traffic = Traffic::uniform("checkout", 90.0, 3);
+        cluster.run(&traffic, Duration::from_secs(5)).await;
+        let report = cluster.run(&traffic, Duration::from_secs(20)).await;
+
+        let accepted_rate = report.accepted_rate();
+        assert!((25.0..36.0).contains(&accepted_rate), "{accepted_rate}");
+    }
+
+    #[tokio::test]
+    async fn test_busy_nodes_take_the_unused_budget() {
+        let cluster = cluster(3, 30.0);
+        let traffic = Traffic::new("checkout", vec![60.0, 2.0, 2.0]);
+        cluster.run(&traffic, Duration::from_secs(5)).await;
+        let report = cluster.run(&traffic, Duration::from_secs(20)).await;
+
+        let accepted_rate = report.accepted_rate();
+        assert!((25.0..36.0).contains(&accepted_rate), "{accepted_rate}");
+        assert!(report.node_accepted_rate(0) > 20.0);
+    }
+
+    #[tokio::test]
+    async fn test_isolated_nodes_each_admit_the_target() {
+        let cluster = cluster(3, 30.0).with_exchange_interval(Duration::MAX);
+        let traffic = Traffic::uniform("checkout", 180.0, 3);
+        cluster.run(&traffic, Duration::from_secs(5)).await;
+        let report = cluster.run(&traffic, Duration::from_secs(20)).await;
+
+        // Without exchanges, the cluster admits the target on every node
+        assert!(report.accepted_rate() > 75.0, "{}", report.accepted_rate());
+    }
+
+    #[tokio::test]
+    async fn test_cluster_from_config() {
+        let config: SentinelConfig = toml::from_str(
+            r#"
+            exchange_interval_ms = 200
+
+            [default_segment]
+            target_tps = 20.0
+
+            [pid]
+            kp = 0.0
+            ki = 0.0
+            kd = 0.0
+            "#,
+        )
+        .unwrap();
+        let cluster = Cluster::from_config(&config, 2).unwrap();
+        assert_eq!(cluster.nodes.len(), 2);
+        assert_eq!(cluster.exchange_interval, Duration::from_millis(200));
+
+        let traffic = Traffic::uniform(DEFAULT_SEGMENT, 60.0, 2);
+        cluster.run(&traffic, Duration::from_secs(5)).await;
+        let report = cluster.run(&traffic, Duration::from_secs(20)).await;
+        let accepted_rate = report.accepted_rate();
+        assert!((16.0..24.0).contains(&accepted_rate), "{accepted_rate}");
+    }
+}
@@ -0,0 +1,60 @@
+/// Region and zone a node runs in. Empty for nodes that do not report them.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Locality {
+    pub region: String,
+    pub zone: String,
+}
+
+/// How much the rates of peers count towards this node's external rates, depending on how close
+/// they run to it.
+///
+/// Peers that do not report a region, e.g. because they run an older version, count fully.
+#[derive(Debug, Clone)]
+pub struct LocalityPolicy {
+    pub local: Locality,
+    pub same_zone_weight: f32,
+    pub same_region_weight: f32,
+    pub other_region_weight: f32,
+}
+
+impl LocalityPolicy {
+    /// Returns the weight of the rates reported by a peer at `peer`.
+    pub fn weight(&self, peer: &Locality) -> f32 {
+        if peer.region.is_empty() {
+            1.0
+        } else if peer.region != self.local.region {
+            self.other_region_weight
+        } else if !peer.zone.is_empty() && peer.zone == self.local.zone {
+            self.same_zone_weight
+        } else {
+            self.same_region_weight
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn locality(region: &str, zone: &str) -> Locality {
+        Locality {
+            region: region.to_string(),
+            zone: zone.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_weights_peers_by_distance() {
+        let policy = LocalityPolicy {
+            local: locality("us-east", "us-east-1a"),
+            same_zone_weight: 1.0,
+            same_region_weight: 0.5,
+            other_region_weight: 0.0,
+        };
+        assert_eq!(policy.weight(&locality("us-east", "us-east-1a")), 1.0);
+        assert_eq!(policy.weight(&locality("us-east", "us-east-1b")), 0.5);
+        assert_eq!(policy.weight(&locality("us-east", "")), 0.5);
+        assert_eq!(policy.weight(&locality("eu-west", "eu-west-1a")), 0.0);
+        assert_eq!(policy.weight(&Locality::default()), 1.0);
+    }
+}
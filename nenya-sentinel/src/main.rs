@@ -1,145 +1,1125 @@
+use std::borrow::Cow;
 use std::collections::HashMap;
 use std::sync::Arc;
+use std::time::Duration;
 
-use tokio::sync::RwLock;
+use tokio::sync::{watch, RwLock};
+use tonic::service::interceptor::InterceptedService;
 use tonic::transport::Server;
 use tonic::{Request, Response, Status};
 
+use nenya::distributed::{DistributedStateBackend, InMemoryBackend};
 use nenya::pid_controller::PIDController;
-use nenya::{RateLimiter, RateLimiterBuilder};
+use sentinel::sentinel_admin_server::SentinelAdminServer;
 use sentinel::sentinel_server::{Sentinel, SentinelServer};
 use sentinel::{MetricData, Metrics};
 
-use crate::sentinel::{SegmentConfig, ShouldThrottleRequest, ShouldThrottleResponse};
+use crate::admin::AdminService;
+use crate::audit::{AuditLog, AuditRecord, ThrottleReason};
+use crate::auth::{CallerIdentity, TokenAuthenticator};
+use crate::backend::MeshBackend;
+use crate::chaos::Chaos;
+use crate::config::DiscoveryConfig;
+use crate::gossip::GossipState;
+use crate::locality::LocalityPolicy;
+use crate::overload::{OverloadGuard, RpcClass};
+use crate::payload::ExchangeLimits;
+use crate::peer::PeerConnector;
+use crate::segment::{Segment, SegmentFactory, DEFAULT_SEGMENT};
+use crate::sentinel::{
+    Enforcement, GossipMessage, PingRequestMessage, SegmentConfig, ShouldThrottleBatchRequest,
+    ShouldThrottleBatchResponse, ShouldThrottleRequest, ShouldThrottleResponse,
+    WatchTargetsRequest,
+};
+use crate::target_watch::{Subscribers, TargetStream, MAX_WATCHED_SEGMENTS};
+
+mod admin;
+mod audit;
+mod auth;
+mod backend;
+mod chaos;
+mod cli;
+mod config;
+mod coordinator;
+mod discovery;
+mod exchange;
+mod gossip;
+#[cfg(any(test, feature = "test-util"))]
+mod harness;
+mod locality;
+mod membership;
+mod overload;
+mod payload;
+mod peer;
+mod protocol;
+mod retry;
+mod segment;
+mod target_watch;
+mod telemetry;
+mod tls;
 
 pub mod sentinel {
     tonic::include_proto!("sentinel");
 }
 
-type SegmentMetrics = HashMap<String, MetricData>;
-type LockedSegmentMetrics = Arc<RwLock<SegmentMetrics>>;
+/// Largest number of decisions a single `ShouldThrottleBatch` call may request.
+const MAX_BATCH_SIZE: usize = 1000;
 
-#[derive(Debug, Default)]
+#[derive(Debug, Clone)]
 pub struct SentinelService {
-    segments: Arc<RwLock<HashMap<String, RateLimiter<f32>>>>,
-    node_metrics: Arc<RwLock<HashMap<String, LockedSegmentMetrics>>>,
+    segments: Arc<RwLock<HashMap<String, Segment>>>,
+    backend: Arc<dyn DistributedStateBackend>,
+    mesh: Option<Arc<MeshBackend>>,
     hostname: String,
-    _default_segment_config: SegmentConfig,
+    segment_factory: Arc<SegmentFactory>,
+    max_dynamic_segments: Option<usize>,
+    gossip: Option<Arc<GossipState>>,
+    lease_ttl: Option<Duration>,
+    peer_weights: Arc<HashMap<String, f32>>,
+    locality: Option<Arc<LocalityPolicy>>,
+    subscribers: Arc<Subscribers>,
+    overload: Option<Arc<OverloadGuard>>,
+    audit: Option<Arc<AuditLog>>,
+    exchange_limits: ExchangeLimits,
+    chaos: Option<Arc<Chaos>>,
+    peer_connector: PeerConnector,
 }
 
 impl SentinelService {
     pub fn new(
         hostname: String,
-        peers: Vec<String>,
         segments: HashMap<String, SegmentConfig>,
         default_segment_config: SegmentConfig,
         pid_controller: PIDController<f32>,
+        update_interval: Duration,
     ) -> Self {
-        let segment_limiters: HashMap<String, RateLimiter<f32>> = segments
-            .iter()
-            .map(|(segment_name, segment_config)| {
-                let mut rate_limiter = RateLimiterBuilder::new(segment_config.target_tps);
-                if let Some(min_tps) = segment_config.min_tps {
-                    rate_limiter = rate_limiter.min_rate(min_tps);
-                }
-                if let Some(max_tps) = segment_config.max_tps {
-                    rate_limiter = rate_limiter.max_rate(max_tps);
-                }
-                (
-                    segment_name.clone(),
-                    rate_limiter.pid_controller(pid_controller.clone()).build(),
-                )
-            })
-            .collect();
-        let node_metrics = peers
-            .iter()
-            .map(|node| (node.clone(), Arc::new(RwLock::new(HashMap::new()))))
-            .collect();
+        let segment_factory = SegmentFactory::new(
+            default_segment_config,
+            segments,
+            pid_controller,
+            update_interval,
+        );
         SentinelService {
             hostname,
-            node_metrics: Arc::new(RwLock::new(node_metrics)),
-            segments: Arc::new(RwLock::new(segment_limiters)),
-            _default_segment_config: default_segment_config,
+            backend: Arc::new(InMemoryBackend::default()),
+            mesh: None,
+            segments: Arc::new(RwLock::new(configured_segments(&segment_factory))),
+            segment_factory: Arc::new(segment_factory),
+            max_dynamic_segments: None,
+            gossip: None,
+            lease_ttl: None,
+            peer_weights: Arc::new(HashMap::new()),
+            locality: None,
+            subscribers: Arc::new(Subscribers::default()),
+            overload: None,
+            audit: None,
+            exchange_limits: ExchangeLimits::default(),
+            chaos: None,
+            peer_connector: PeerConnector::default(),
         }
     }
-}
 
-#[tonic::async_trait]
-impl Sentinel for SentinelService {
-    async fn exchange_metrics(
-        &self,
-        request: Request<Metrics>,
-    ) -> Result<Response<Metrics>, Status> {
-        let node_metrics = request.into_inner();
+    /// Adds namespaces, each with the default config for its segments.
+    ///
+    /// Segments of a namespace are isolated from equally named segments of other namespaces.
+    pub fn with_namespaces(mut self, namespaces: HashMap<String, SegmentConfig>) -> Self {
+        self.segment_factory =
+            Arc::new((*self.segment_factory).clone().with_namespaces(namespaces));
+        self
+    }
+
+    /// Limits the number of segments created on first use to `max_dynamic_segments`. Once it is
+    /// reached, requests for further segments are decided by their namespace's default segment.
+    pub fn with_max_dynamic_segments(mut self, max_dynamic_segments: usize) -> Self {
+        self.max_dynamic_segments = Some(max_dynamic_segments);
+        self
+    }
+
+    /// Reads the time for the segment limiters from `clock`, so simulations can run faster than
+    /// real time.
+    ///
+    /// Rebuilds the configured segments, so it must be called before the service is used.
+    #[cfg(any(test, feature = "test-util"))]
+    pub fn with_clock(mut self, clock: Arc<dyn nenya::clock::Clock>) -> Self {
+        let segment_factory = (*self.segment_factory).clone().with_clock(clock);
+        self.segments = Arc::new(RwLock::new(configured_segments(&segment_factory)));
+        self.segment_factory = Arc::new(segment_factory);
+        self
+    }
+
+    /// Sets the TLS and authentication settings used for connections to peers.
+    pub fn with_peer_connector(mut self, peer_connector: PeerConnector) -> Self {
+        self.peer_connector = peer_connector;
+        self
+    }
+
+    /// Shares metrics with peers through `backend`.
+    #[cfg_attr(not(any(test, feature = "test-util")), allow(dead_code))]
+    pub fn with_backend(mut self, backend: Arc<dyn DistributedStateBackend>) -> Self {
+        self.backend = backend;
+        self.mesh = None;
+        self
+    }
 
-        let node_metrics_guard = self.node_metrics.read().await;
-        let node_metrics_value = node_metrics_guard.get(&node_metrics.source);
+    /// Shares metrics by exchanging them directly with peers, and records metrics that peers
+    /// push to this node.
+    pub fn with_mesh(mut self, mesh: Arc<MeshBackend>) -> Self {
+        self.backend = mesh.clone();
+        self.mesh = Some(mesh);
+        self
+    }
+
+    /// Scales the rates reported by each named peer by its weight when aggregating them. Peers
+    /// without a weight count fully.
+    pub fn with_peer_weights(mut self, peer_weights: HashMap<String, f32>) -> Self {
+        self.peer_weights = Arc::new(peer_weights);
+        self
+    }
+
+    /// Reports this node's region and zone to peers, and weights the rates of peers without an
+    /// explicit weight by how close they run to this node.
+    pub fn with_locality(mut self, locality: LocalityPolicy) -> Self {
+        self.locality = Some(Arc::new(locality));
+        self
+    }
+
+    /// Enables leader election: the node holding a coordinator lease of `lease_ttl` computes the
+    /// target rates and assigns each node a share of them.
+    pub fn with_leader_election(mut self, lease_ttl: Duration) -> Self {
+        self.lease_ttl = Some(lease_ttl);
+        self
+    }
+
+    /// Sheds metric exchanges while `overload` reports the node as overloaded.
+    pub fn with_overload_guard(mut self, overload: Arc<OverloadGuard>) -> Self {
+        self.overload = Some(overload);
+        self
+    }
 
-        if let Some(metrics_value_lock) = node_metrics_value {
-            let mut metrics_value_guard = metrics_value_lock.write().await;
-            *metrics_value_guard = node_metrics.segments;
+    /// Rejects metrics pushed by peers that exceed `limits`, and truncates the responses to them.
+    pub fn with_exchange_limits(mut self, limits: ExchangeLimits) -> Self {
+        self.exchange_limits = limits;
+        self
+    }
+
+    /// Fails a fraction of the incoming RPCs as set by `chaos`, for testing only.
+    pub fn with_chaos(mut self, chaos: Arc<Chaos>) -> Self {
+        self.chaos = Some(chaos);
+        self
+    }
+
+    /// Records a sample of the throttle decisions to `audit`.
+    pub fn with_audit_log(mut self, audit: Arc<AuditLog>) -> Self {
+        self.audit = Some(audit);
+        self
+    }
+
+    /// Enables gossip membership, advertising this node at `address`.
+    pub fn with_gossip(mut self, address: String, probe_timeout: Duration) -> Self {
+        self.gossip = Some(Arc::new(GossipState::new(
+            self.hostname.clone(),
+            address,
+            probe_timeout,
+        )));
+        self
+    }
+
+    /// Makes a throttling decision for a request from `caller` consuming `cost` units of
+    /// `segment`'s budget, creating the segment's rate limiter on first use.
+    ///
+    /// Requests beyond the caller's quota are throttled without consuming the segment's budget,
+    /// so one caller cannot starve the others, and requests the segment throttles do not consume
+    /// the caller's quota. Callers are only known when authentication is enabled.
+    ///
+    /// Segments in observe mode admit every request, only counting the requests they would have
+    /// throttled.
+    ///
+    /// Once the limit on dynamically created segments is reached, requests for new segments are
+    /// decided by their namespace's default segment.
+    pub async fn check_segment(
+        &self,
+        segment: &str,
+        caller: Option<&str>,
+        cost: f32,
+    ) -> ShouldThrottleResponse {
+        let mut segments = self.segments.write().await;
+        let segment = self.admitted_segment(&segments, segment);
+        let segment = segment.as_ref();
+        let segment_state = self.get_or_create_segment(&mut segments, segment);
+        let now = self.now();
+        segment_state.touch(now);
+        if segment_state.expire_target_override(now) {
+            tracing::info!(%segment, "temporary override expired");
+        }
+        let caller = caller.map(str::to_string);
+        let caller_quotas = segment_state.caller_quotas.as_mut().zip(caller.as_ref());
+        // The segment counts every request toward its request rate, and only asks the caller's
+        // quota about requests within its target, so the caller's budget is only spent on
+        // requests that are admitted
+        let mut caller_throttled = false;
+        let rate_limiter = &mut segment_state.rate_limiter;
+        let would_throttle = rate_limiter.should_throttle_weighted_with(cost, || {
+            caller_throttled = caller_quotas.is_some_and(|(caller_quotas, caller)| {
+                caller_quotas.should_throttle_weighted(caller, cost)
+            });
+            !caller_throttled
+        }) && !segment_state.paused;
+        let observed = would_throttle && segment_state.enforcement == Enforcement::Observe;
+        if observed {
+            segment_state.shadow_throttled += 1;
+        }
+        let should_throttle = would_throttle && !observed;
+        if let Some(audit) = self.audit.as_ref().filter(|audit| audit.sample()) {
+            audit.record(AuditRecord {
+                timestamp_ms: audit::timestamp_ms(),
+                node: self.hostname.clone(),
+                namespace: self
+                    .segment_factory
+                    .namespace_of(segment)
+                    .map(str::to_string),
+                segment: self.segment_factory.segment_name(segment).to_string(),
+                caller: caller.clone(),
+                cost,
+                target_tps: rate_limiter.target_rate(),
+                request_rate: rate_limiter.request_rate(),
+                accepted_request_rate: rate_limiter.accepted_request_rate(),
+                external_request_rate: rate_limiter.external_request_rate(),
+                throttled: should_throttle,
+                reason: match (would_throttle, caller_throttled) {
+                    (false, _) => None,
+                    (true, true) => Some(ThrottleReason::CallerQuota),
+                    (true, false) => Some(ThrottleReason::Segment),
+                },
+                observed,
+            });
+        }
+        tracing::debug!(
+            %segment,
+            ?caller,
+            cost,
+            should_throttle,
+            would_throttle,
+            caller_throttled,
+            paused = segment_state.paused,
+            enforcement = segment_state.enforcement.as_str_name(),
+            request_rate = rate_limiter.request_rate(),
+            accepted_request_rate = rate_limiter.accepted_request_rate(),
+            target_rate = rate_limiter.target_rate(),
+            "throttle decision"
+        );
+
+        let mut limit = rate_limiter.target_rate();
+        let mut remaining = rate_limiter.remaining_capacity();
+        let caller_throttled = caller_throttled && should_throttle;
+        let mut retry_after = if should_throttle && !caller_throttled {
+            rate_limiter.retry_after()
         } else {
-            drop(node_metrics_guard);
-            let mut node_metrics_guard = self.node_metrics.write().await;
-            node_metrics_guard.insert(
-                node_metrics.source,
-                Arc::new(RwLock::new(node_metrics.segments)),
+            Duration::ZERO
+        };
+        if let Some((caller_quotas, caller)) = segment_state.caller_quotas.as_ref().zip(caller) {
+            limit = limit.min(caller_quotas.key_rate());
+            remaining = remaining.min(caller_quotas.remaining_capacity(&caller));
+            if caller_throttled {
+                retry_after = caller_quotas.retry_after(&caller);
+            }
+        }
+        ShouldThrottleResponse {
+            should_throttle,
+            retry_after_ms: retry_after.as_millis() as u64,
+            limit,
+            remaining,
+            node: self.hostname.clone(),
+        }
+    }
+
+    /// Counts an RPC of `class` against the overload guard, returning `false` if it should be
+    /// shed.
+    fn admit(&self, class: RpcClass) -> bool {
+        self.overload
+            .as_ref()
+            .is_none_or(|overload| overload.admit(class))
+    }
+
+    /// Returns the status to fail an RPC with if chaos testing injects a fault.
+    fn injected_fault(&self) -> Option<Status> {
+        self.chaos.as_ref().and_then(|chaos| chaos.rpc_fault())
+    }
+
+    /// Returns the key of the segment a throttle request is for, or `None` if its namespace does
+    /// not exist.
+    fn request_segment(&self, request: &ShouldThrottleRequest) -> Option<String> {
+        let segment = request.segment.as_deref().unwrap_or(DEFAULT_SEGMENT);
+        self.segment_factory
+            .segment_key(request.namespace.as_deref(), segment)
+    }
+
+    /// Returns the key of the segment deciding requests for `segment`: `segment` itself, or its
+    /// namespace's default segment if `segment` does not exist yet and the limit on dynamically
+    /// created segments is reached.
+    fn admitted_segment<'a>(
+        &self,
+        segments: &HashMap<String, Segment>,
+        segment: &'a str,
+    ) -> Cow<'a, str> {
+        let at_limit = |max_dynamic_segments| {
+            segments.values().filter(|state| state.is_dynamic()).count() >= max_dynamic_segments
+        };
+        if segments.contains_key(segment) || !self.max_dynamic_segments.is_some_and(at_limit) {
+            return Cow::Borrowed(segment);
+        }
+        let namespace = self.segment_factory.namespace_of(segment);
+        let fallback = crate::segment::segment_key(namespace, DEFAULT_SEGMENT);
+        tracing::debug!(%segment, %fallback, "dynamic segment limit reached");
+        Cow::Owned(fallback)
+    }
+
+    /// Returns the state of `segment`, creating it from the segment config if it does not exist.
+    fn get_or_create_segment<'a>(
+        &self,
+        segments: &'a mut HashMap<String, Segment>,
+        segment: &str,
+    ) -> &'a mut Segment {
+        if !segments.contains_key(segment) {
+            let segment_state = self.segment_factory.build_segment(segment, true);
+            tracing::info!(
+                %segment,
+                target_tps = segment_state.rate_limiter.target_rate(),
+                "segment created"
             );
+            segments.insert(segment.to_string(), segment_state);
         }
+        segments.get_mut(segment).unwrap()
+    }
 
+    /// Returns the locally observed rates of each segment, excluding rates reported by peers.
+    ///
+    /// The rates are only recalculated on decisions, so segments without requests in the last
+    /// update interval report zero rather than the rates of their last request.
+    pub async fn local_metrics(&self) -> Metrics {
         let segments = self.segments.read().await;
         let metric_segments: HashMap<String, MetricData> = segments
             .iter()
-            .map(|(segment_id, segment_rate_limiter)| {
-                (
-                    segment_id.clone(),
+            .map(|(segment_id, segment)| {
+                let rate_limiter = &segment.rate_limiter;
+                let metric_data = if rate_limiter.is_idle() {
+                    MetricData::default()
+                } else {
                     MetricData {
-                        request_rate: segment_rate_limiter.request_rate(),
-                        accepted_request_rate: segment_rate_limiter.accepted_request_rate(),
-                    },
-                )
+                        request_rate: rate_limiter.local_request_rate(),
+                        accepted_request_rate: rate_limiter.local_accepted_request_rate(),
+                    }
+                };
+                (segment_id.clone(), metric_data)
             })
             .collect();
 
-        return Ok(Response::new(Metrics {
+        let locality = self.locality.as_ref().map(|locality| &locality.local);
+        Metrics {
             segments: metric_segments,
             source: self.hostname.clone(),
-        }));
+            region: locality
+                .map(|local| local.region.clone())
+                .unwrap_or_default(),
+            zone: locality.map(|local| local.zone.clone()).unwrap_or_default(),
+            ..Default::default()
+        }
     }
 
+    /// Stores the latest metrics pushed by a peer, replacing any previous report or merging a
+    /// delta into it.
+    ///
+    /// Pushed metrics are only used by the mesh backend; other backends ignore them.
+    pub fn record_node_metrics(&self, node_metrics: Metrics) {
+        if let Some(mesh) = &self.mesh {
+            mesh.record(node_metrics);
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl Sentinel for SentinelService {
+    #[tracing::instrument(skip_all, fields(source = %request.get_ref().source))]
+    async fn exchange_metrics(
+        &self,
+        request: Request<Metrics>,
+    ) -> Result<Response<Metrics>, Status> {
+        telemetry::set_parent(&tracing::Span::current(), request.metadata());
+        if let Some(status) = self.injected_fault() {
+            return Err(status);
+        }
+        if !self.admit(RpcClass::Exchange) {
+            return Err(Status::resource_exhausted(
+                "sentinel overloaded, shedding metric exchanges",
+            ));
+        }
+        let node_metrics = request.into_inner();
+        self.exchange_limits.validate(&node_metrics)?;
+        tracing::debug!(
+            peer = %node_metrics.source,
+            segments = node_metrics.segments.len(),
+            removed_segments = node_metrics.removed_segments.len(),
+            delta = node_metrics.delta,
+            page = node_metrics.page,
+            "received peer metrics"
+        );
+        if node_metrics.delta
+            && self
+                .mesh
+                .as_ref()
+                .is_some_and(|mesh| !mesh.has_metrics(&node_metrics.source))
+        {
+            return Err(Status::failed_precondition(
+                "no previous metrics from the source to apply the delta to",
+            ));
+        }
+        let page = node_metrics.page;
+        if node_metrics.source != self.hostname {
+            self.record_node_metrics(node_metrics);
+        }
+        // The local metrics were already returned for the first page
+        if page > 0 {
+            let mut response = Metrics {
+                source: self.hostname.clone(),
+                delta: true,
+                page,
+                ..Default::default()
+            };
+            protocol::advertise(&mut response);
+            return Ok(Response::new(response));
+        }
+
+        let mut local_metrics = self.local_metrics().await;
+        if self.exchange_limits.truncate(&mut local_metrics) {
+            tracing::debug!(
+                segments = local_metrics.segments.len(),
+                "truncated metrics returned to peer"
+            );
+        }
+        if let Some(mesh) = &self.mesh {
+            mesh.attach_shares(&mut local_metrics);
+        }
+        protocol::advertise(&mut local_metrics);
+        Ok(Response::new(local_metrics))
+    }
+
+    async fn gossip(
+        &self,
+        request: Request<GossipMessage>,
+    ) -> Result<Response<GossipMessage>, Status> {
+        let response = self.handle_gossip(request.into_inner()).await?;
+        Ok(Response::new(response))
+    }
+
+    async fn ping_request(
+        &self,
+        request: Request<PingRequestMessage>,
+    ) -> Result<Response<GossipMessage>, Status> {
+        let response = self.handle_ping_request(request.into_inner()).await?;
+        Ok(Response::new(response))
+    }
+
+    #[tracing::instrument(skip_all, fields(segment = ?request.get_ref().segment))]
     async fn should_throttle(
         &self,
-        _request: Request<ShouldThrottleRequest>,
+        request: Request<ShouldThrottleRequest>,
     ) -> Result<Response<ShouldThrottleResponse>, Status> {
-        todo!()
+        telemetry::set_parent(&tracing::Span::current(), request.metadata());
+        if let Some(status) = self.injected_fault() {
+            return Err(status);
+        }
+        self.admit(RpcClass::Decision);
+        let caller = request.extensions().get::<CallerIdentity>().cloned();
+        let caller = caller.as_ref().map(|caller| caller.0.as_str());
+        let request = request.into_inner();
+        let cost = request_cost(&request).ok_or_else(invalid_cost)?;
+        let segment = self
+            .request_segment(&request)
+            .ok_or_else(|| unknown_namespace(request.namespace.as_deref()))?;
+        Ok(Response::new(
+            self.check_segment(&segment, caller, cost).await,
+        ))
+    }
+
+    #[tracing::instrument(skip_all, fields(requests = request.get_ref().requests.len()))]
+    async fn should_throttle_batch(
+        &self,
+        request: Request<ShouldThrottleBatchRequest>,
+    ) -> Result<Response<ShouldThrottleBatchResponse>, Status> {
+        telemetry::set_parent(&tracing::Span::current(), request.metadata());
+        if let Some(status) = self.injected_fault() {
+            return Err(status);
+        }
+        self.admit(RpcClass::Decision);
+        let caller = request.extensions().get::<CallerIdentity>().cloned();
+        let caller = caller.as_ref().map(|caller| caller.0.as_str());
+        let requests = request.into_inner().requests;
+        if requests.len() > MAX_BATCH_SIZE {
+            return Err(Status::invalid_argument(format!(
+                "batches are limited to {} requests",
+                MAX_BATCH_SIZE
+            )));
+        }
+        let costs = requests
+            .iter()
+            .map(request_cost)
+            .collect::<Option<Vec<f32>>>()
+            .ok_or_else(invalid_cost)?;
+        let mut segments = Vec::with_capacity(requests.len());
+        for request in &requests {
+            let segment = self
+                .request_segment(request)
+                .ok_or_else(|| unknown_namespace(request.namespace.as_deref()))?;
+            segments.push(segment);
+        }
+        let mut responses = Vec::with_capacity(requests.len());
+        for (segment, cost) in segments.iter().zip(costs) {
+            responses.push(self.check_segment(segment, caller, cost).await);
+        }
+        Ok(Response::new(ShouldThrottleBatchResponse { responses }))
+    }
+
+    type WatchTargetsStream = TargetStream;
+
+    #[tracing::instrument(skip_all, fields(segments = request.get_ref().segments.len()))]
+    async fn watch_targets(
+        &self,
+        request: Request<WatchTargetsRequest>,
+    ) -> Result<Response<Self::WatchTargetsStream>, Status> {
+        telemetry::set_parent(&tracing::Span::current(), request.metadata());
+        if let Some(status) = self.injected_fault() {
+            return Err(status);
+        }
+        let request = request.into_inner();
+        if request.segments.is_empty() || request.segments.len() > MAX_WATCHED_SEGMENTS {
+            return Err(Status::invalid_argument(format!(
+                "between 1 and {} segments may be watched",
+                MAX_WATCHED_SEGMENTS
+            )));
+        }
+        let namespace = request.namespace.as_deref();
+        let mut segments = Vec::with_capacity(request.segments.len());
+        for segment in &request.segments {
+            let key = self
+                .segment_factory
+                .segment_key(namespace, segment)
+                .ok_or_else(|| unknown_namespace(namespace))?;
+            segments.push(key);
+        }
+        Ok(Response::new(self.watch_targets(segments)))
+    }
+}
+
+/// Builds the segments with an explicit config, which exist from startup.
+fn configured_segments(segment_factory: &SegmentFactory) -> HashMap<String, Segment> {
+    segment_factory
+        .configured_segments()
+        .map(|segment_name| {
+            let segment = segment_factory.build_segment(segment_name, false);
+            (segment_name.clone(), segment)
+        })
+        .collect()
+}
+
+/// Returns the budget a request consumes, or `None` if its cost is negative or not finite.
+///
+/// An unset cost is zero on the wire and counts as a single request.
+fn request_cost(request: &ShouldThrottleRequest) -> Option<f32> {
+    match request.cost {
+        cost if !cost.is_finite() || cost < 0.0 => None,
+        0.0 => Some(1.0),
+        cost => Some(cost),
+    }
+}
+
+fn invalid_cost() -> Status {
+    Status::invalid_argument("cost must be a non-negative number")
+}
+
+/// Rejects a segment name in `namespace`, either because the namespace does not exist or because
+/// the name belongs to another namespace.
+fn unknown_namespace(namespace: Option<&str>) -> Status {
+    match namespace.filter(|namespace| !namespace.is_empty()) {
+        Some(namespace) => Status::invalid_argument(format!("unknown namespace {:?}", namespace)),
+        None => Status::invalid_argument("segment names must not start with a namespace"),
     }
 }
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let addr = "[::1]:8080".parse()?;
-    let hostname: String = hostname::get()?
-        .into_string()
-        .expect("Unable to get hostname");
-    let peers = vec!["foo".to_string(), "bar".to_string()];
-    let default_segment_config = SegmentConfig {
-        target_tps: 100.0,
-        min_tps: None,
-        max_tps: None,
+    let matches = cli::command().get_matches();
+    let log_level = *matches.get_one::<tracing::Level>("log_level").unwrap();
+    let config = cli::load_config(&matches)?;
+    #[cfg(feature = "test-util")]
+    if let Some(&nodes) = matches.get_one::<usize>("simulate") {
+        harness::simulate(&config, nodes).await?;
+        return Ok(());
+    }
+    telemetry::init(log_level, &config.telemetry)?;
+
+    let hostname = match &config.node_name {
+        Some(node_name) => node_name.clone(),
+        None => hostname::get()?
+            .into_string()
+            .expect("Unable to get hostname"),
     };
-    let pid_controller = PIDController::new_static_controller(100.0);
-    let sentinel = SentinelService::new(
+    config.log_summary(&hostname);
+    let mut sentinel = SentinelService::new(
         hostname,
-        peers,
-        HashMap::default(),
-        default_segment_config,
-        pid_controller,
-    );
-
-    Server::builder()
-        .add_service(SentinelServer::new(sentinel))
-        .serve(addr)
+        config.segment_configs(),
+        config.default_segment_config(),
+        config.pid_controller()?,
+        config.update_interval(),
+    )
+    .with_namespaces(config.namespace_defaults())
+    .with_max_dynamic_segments(config.max_dynamic_segments);
+
+    let tls = config
+        .tls
+        .as_ref()
+        .map(tls::TlsSettings::load)
+        .transpose()?;
+    let mut peer_connector = PeerConnector::default();
+    if let Some(tls) = &tls {
+        peer_connector = peer_connector.with_tls(tls.client.clone());
+    }
+    if let Some(peer_token) = &config.auth.peer_token {
+        peer_connector = peer_connector.with_token(auth::bearer_token(&peer_token.resolve()?)?);
+    }
+    let mesh = MeshBackend::new(peer_connector.clone(), config.exchange_interval())
+        .with_retry_policy(config.exchange_retry.retry_policy())
+        .with_staleness(config.peer_staleness())
+        .with_limits(config.exchange_limits.limits());
+    let chaos = config.chaos.as_ref().map(|chaos| Arc::new(chaos.chaos()));
+    let mesh = match &chaos {
+        Some(chaos) => mesh.with_chaos(chaos.clone()),
+        None => mesh,
+    };
+    if let Some(chaos) = chaos {
+        tracing::warn!(?chaos, "chaos testing enabled, injecting faults");
+        sentinel = sentinel.with_chaos(chaos);
+    }
+    sentinel = sentinel
+        .with_exchange_limits(config.exchange_limits.limits())
+        .with_peer_connector(peer_connector)
+        .with_mesh(Arc::new(mesh));
+    sentinel = sentinel.with_peer_weights(config.peer_weights.clone());
+    if let Some(locality) = &config.locality {
+        sentinel = sentinel.with_locality(locality.policy());
+    }
+    if let Some(leader_election) = &config.leader_election {
+        sentinel = sentinel.with_leader_election(leader_election.lease_ttl());
+    }
+    if let Some(overload_config) = &config.overload {
+        let overload = Arc::new(OverloadGuard::new(
+            overload_config.max_rpc_rate,
+            overload_config.max_event_loop_lag(),
+        ));
+        sentinel = sentinel.with_overload_guard(overload.clone());
+        tokio::spawn(overload::run_lag_monitor(overload));
+    }
+    if let Some(audit_config) = &config.audit {
+        let output: Box<dyn std::io::Write + Send> = match &audit_config.path {
+            Some(path) => Box::new(
+                std::fs::OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)?,
+            ),
+            None => Box::new(std::io::stdout()),
+        };
+        sentinel =
+            sentinel.with_audit_log(Arc::new(AuditLog::start(audit_config.sample_rate, output)));
+    }
+    let authenticator = TokenAuthenticator::from_config(&config.auth)?;
+
+    let (seed_sender, seed_receiver) = watch::channel(config.peers.clone());
+    if let Some(DiscoveryConfig::Kubernetes(kubernetes)) = &config.discovery {
+        tokio::spawn(discovery::run_kubernetes_discovery(
+            kubernetes.clone(),
+            config.peers.clone(),
+            tls.is_some(),
+            seed_sender,
+        ));
+    }
+
+    let peer_receiver = match &config.gossip {
+        Some(gossip_config) => {
+            let advertise_addr = gossip_config.advertise_addr.clone().unwrap_or_else(|| {
+                let scheme = if tls.is_some() { "https" } else { "http" };
+                format!("{}://{}", scheme, config.listen_addr)
+            });
+            sentinel = sentinel.with_gossip(advertise_addr, gossip_config.probe_timeout());
+            let (peer_sender, peer_receiver) = watch::channel(Vec::new());
+            tokio::spawn(gossip::run_gossip_loop(
+                sentinel.clone(),
+                gossip_config.clone(),
+                seed_receiver,
+                peer_sender,
+            ));
+            peer_receiver
+        }
+        None => seed_receiver,
+    };
+    if let Some(idle_timeout) = config.segment_idle_timeout() {
+        tokio::spawn(segment::run_eviction_loop(sentinel.clone(), idle_timeout));
+    }
+    tokio::spawn(exchange::run_exchange_loop(
+        sentinel.clone(),
+        peer_receiver,
+        config.exchange_interval(),
+    ));
+
+    let mut server = Server::builder();
+    if let Some(tls) = tls {
+        server = server.tls_config(tls.server)?;
+    }
+    server
+        .add_service(SentinelAdminServer::with_interceptor(
+            AdminService::new(sentinel.clone(), &config.auth),
+            authenticator.clone(),
+        ))
+        .add_service(InterceptedService::new(
+            SentinelServer::new(sentinel)
+                .max_decoding_message_size(config.exchange_limits.max_payload_bytes),
+            authenticator,
+        ))
+        .serve(config.listen_addr)
         .await?;
 
+    telemetry::shutdown();
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_service() -> SentinelService {
+        SentinelService::new(
+            "local".to_string(),
+            HashMap::new(),
+            SegmentConfig {
+                target_tps: 10.0,
+                min_tps: None,
+                max_tps: None,
+                ..Default::default()
+            },
+            PIDController::new_static_controller(10.0),
+            Duration::from_secs(1),
+        )
+    }
+
+    fn throttle_request(segment: Option<&str>) -> ShouldThrottleRequest {
+        ShouldThrottleRequest {
+            segment: segment.map(str::to_string),
+            cost: 0.0,
+            namespace: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_namespaces_isolate_segments() {
+        let service = create_service().with_namespaces(HashMap::from([(
+            "payments".to_string(),
+            SegmentConfig {
+                target_tps: 20.0,
+                min_tps: None,
+                max_tps: None,
+                ..Default::default()
+            },
+        )]));
+        let namespaced = |namespace: &str, segment: &str| ShouldThrottleRequest {
+            namespace: Some(namespace.to_string()),
+            ..throttle_request(Some(segment))
+        };
+
+        let response = service
+            .should_throttle(Request::new(namespaced("payments", "checkout")))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(response.limit, 20.0);
+        let response = service
+            .should_throttle(Request::new(throttle_request(Some("checkout"))))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(response.limit, 10.0);
+        assert!(service
+            .segments
+            .read()
+            .await
+            .contains_key("payments/checkout"));
+
+        for request in [
+            namespaced("search", "checkout"),
+            throttle_request(Some("payments/checkout")),
+        ] {
+            let status = service
+                .should_throttle(Request::new(request))
+                .await
+                .unwrap_err();
+            assert_eq!(status.code(), tonic::Code::InvalidArgument);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_dynamic_segments_are_limited() {
+        let service = create_service()
+            .with_namespaces(HashMap::from([(
+                "payments".to_string(),
+                SegmentConfig {
+                    target_tps: 20.0,
+                    ..Default::default()
+                },
+            )]))
+            .with_max_dynamic_segments(1);
+        service.check_segment("search", None, 1.0).await;
+
+        // Further segments share the default segment of their namespace
+        let response = service.check_segment("web", None, 1.0).await;
+        assert_eq!(response.limit, 10.0);
+        let response = service.check_segment("payments/checkout", None, 1.0).await;
+        assert_eq!(response.limit, 20.0);
+        let segments = service.segments.read().await;
+        let mut keys: Vec<&str> = segments.keys().map(String::as_str).collect();
+        keys.sort();
+        assert_eq!(keys, ["default", "payments/default", "search"]);
+    }
+
+    #[tokio::test]
+    async fn test_should_throttle_batch() {
+        let service = create_service();
+        let request = ShouldThrottleBatchRequest {
+            requests: vec![throttle_request(Some("search")), throttle_request(None)],
+        };
+        let responses = service
+            .should_throttle_batch(Request::new(request))
+            .await
+            .unwrap()
+            .into_inner()
+            .responses;
+        assert_eq!(responses.len(), 2);
+        assert!(responses.iter().all(|response| !response.should_throttle));
+
+        let segments = service.segments.read().await;
+        assert!(segments.contains_key("search"));
+        assert!(segments.contains_key(DEFAULT_SEGMENT));
+    }
+
+    #[tokio::test]
+    async fn test_should_throttle_batch_rejects_oversized_batches() {
+        let service = create_service();
+        let request = ShouldThrottleBatchRequest {
+            requests: vec![throttle_request(None); MAX_BATCH_SIZE + 1],
+        };
+        let status = service
+            .should_throttle_batch(Request::new(request))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+    }
+
+    #[tokio::test]
+    async fn test_should_throttle_rejects_invalid_cost() {
+        let service = create_service();
+        for cost in [-1.0, f32::NAN, f32::INFINITY] {
+            let request = ShouldThrottleRequest {
+                cost,
+                ..throttle_request(None)
+            };
+            let status = service
+                .should_throttle(Request::new(request))
+                .await
+                .unwrap_err();
+            assert_eq!(status.code(), tonic::Code::InvalidArgument);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_caller_quotas_do_not_consume_segment_budget() {
+        let service = SentinelService::new(
+            "local".to_string(),
+            HashMap::from([(
+                "checkout".to_string(),
+                SegmentConfig {
+                    target_tps: 100.0,
+                    min_tps: None,
+                    max_tps: None,
+                    caller_max_tps: Some(5.0),
+                    ..Default::default()
+                },
+            )]),
+            SegmentConfig::default(),
+            PIDController::new_static_controller(100.0),
+            Duration::from_secs(1),
+        );
+        let throttled = |responses: Vec<ShouldThrottleResponse>| {
+            responses
+                .iter()
+                .filter(|response| response.should_throttle)
+                .count()
+        };
+
+        let mut noisy = Vec::new();
+        for _ in 0..20 {
+            noisy.push(service.check_segment("checkout", Some("noisy"), 1.0).await);
+        }
+        assert!(throttled(noisy.clone()) > 0);
+        assert!(noisy.iter().all(|response| response.limit == 5.0));
+
+        let quiet = service.check_segment("checkout", Some("quiet"), 1.0).await;
+        assert!(!quiet.should_throttle);
+        assert_eq!(quiet.limit, 5.0);
+        let anonymous = service.check_segment("checkout", None, 1.0).await;
+        assert_eq!(anonymous.limit, 100.0);
+    }
+
+    #[tokio::test]
+    async fn test_segment_throttling_keeps_caller_quota() {
+        let config = |target_tps, caller_max_tps| SegmentConfig {
+            target_tps,
+            caller_max_tps,
+            ..Default::default()
+        };
+        let service = SentinelService::new(
+            "local".to_string(),
+            HashMap::from([
+                ("checkout".to_string(), config(1.0, Some(100.0))),
+                ("search".to_string(), config(100.0, Some(1.0))),
+            ]),
+            SegmentConfig::default(),
+            PIDController::new_static_controller(100.0),
+            Duration::from_secs(1),
+        );
+        let caller = "caller".to_string();
+        let remaining = || async {
+            let segments = service.segments.read().await;
+            let caller_quotas = segments["checkout"].caller_quotas.as_ref().unwrap();
+            caller_quotas.remaining_capacity(&caller)
+        };
+
+        let response = service.check_segment("checkout", Some("caller"), 1.0).await;
+        assert!(!response.should_throttle);
+        let before = remaining().await;
+        for _ in 0..5 {
+            let response = service.check_segment("checkout", Some("caller"), 1.0).await;
+            assert!(response.should_throttle);
+        }
+        assert_eq!(remaining().await, before);
+
+        // Requests over the caller's quota still count toward the segment's request rate
+        for _ in 0..5 {
+            service.check_segment("search", Some("caller"), 1.0).await;
+        }
+        let segments = service.segments.read().await;
+        let rate_limiter = &segments["search"].rate_limiter;
+        assert!((rate_limiter.local_request_rate() - 40.0).abs() < 1.0);
+        assert!((rate_limiter.local_accepted_request_rate() - 10.0).abs() < 1.0);
+    }
+
+    #[tokio::test]
+    async fn test_observed_segments_allow_every_request() {
+        let service = SentinelService::new(
+            "local".to_string(),
+            HashMap::from([(
+                "search".to_string(),
+                SegmentConfig {
+                    target_tps: 5.0,
+                    enforcement: Enforcement::Observe.into(),
+                    ..Default::default()
+                },
+            )]),
+            SegmentConfig::default(),
+            PIDController::new_static_controller(5.0),
+            Duration::from_secs(1),
+        );
+        for _ in 0..20 {
+            let response = service.check_segment("search", None, 1.0).await;
+            assert!(!response.should_throttle);
+            assert_eq!(response.retry_after_ms, 0);
+        }
+        let segments = service.segments.read().await;
+        assert!(segments["search"].shadow_throttled > 0);
+    }
+
+    #[tokio::test]
+    async fn test_overloaded_node_sheds_exchanges() {
+        let overload = Arc::new(OverloadGuard::new(1.0, Duration::from_secs(1)));
+        let service = create_service().with_overload_guard(overload.clone());
+        for _ in 0..5 {
+            service
+                .should_throttle(Request::new(throttle_request(None)))
+                .await
+                .unwrap();
+        }
+        let status = service
+            .exchange_metrics(Request::new(Metrics::default()))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::ResourceExhausted);
+        assert_eq!(service.node_status().await.shed_exchanges, 1);
+    }
+
+    #[tokio::test]
+    async fn test_exchange_rejects_invalid_payloads() {
+        let service = create_service()
+            .with_mesh(Arc::new(MeshBackend::new(
+                PeerConnector::default(),
+                Duration::from_secs(1),
+            )))
+            .with_exchange_limits(ExchangeLimits {
+                max_segments: 1,
+                ..Default::default()
+            });
+        let peer_metrics = |segments: &[&str], delta| Metrics {
+            source: "peer".to_string(),
+            segments: segments
+                .iter()
+                .map(|segment| (segment.to_string(), MetricData::default()))
+                .collect(),
+            delta,
+            ..Default::default()
+        };
+
+        let status = service
+            .exchange_metrics(Request::new(peer_metrics(&["a", "b"], false)))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+        let status = service
+            .exchange_metrics(Request::new(peer_metrics(&["a"], true)))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::FailedPrecondition);
+
+        service
+            .exchange_metrics(Request::new(peer_metrics(&["a"], false)))
+            .await
+            .unwrap();
+        service
+            .exchange_metrics(Request::new(peer_metrics(&["b"], true)))
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_costly_request_consumes_budget() {
+        let service = create_service();
+        let request = ShouldThrottleRequest {
+            cost: 5.0,
+            ..throttle_request(Some("search"))
+        };
+        let response = service
+            .should_throttle(Request::new(request))
+            .await
+            .unwrap();
+        assert!(!response.into_inner().should_throttle);
+        assert!(
+            service
+                .check_segment("search", None, 1.0)
+                .await
+                .should_throttle
+        );
+    }
+}
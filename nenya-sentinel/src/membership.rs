@@ -0,0 +1,242 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use crate::sentinel::{Member, MemberState};
+
+/// A member's latest known state and when that state was last changed.
+#[derive(Debug, Clone)]
+struct MemberEntry {
+    member: Member,
+    changed_at: Instant,
+}
+
+/// SWIM-style cluster membership list.
+///
+/// Each member carries an incarnation number that only the member itself increments. Updates are
+/// merged using the SWIM precedence rules: a higher incarnation always wins, and at the same
+/// incarnation `Dead` overrides `Suspect`, which overrides `Alive`. When a node hears that it is
+/// suspected or dead, it refutes the rumor by incrementing its own incarnation.
+#[derive(Debug, Clone)]
+pub struct Membership {
+    local: Member,
+    members: HashMap<String, MemberEntry>,
+}
+
+impl Membership {
+    /// Creates a membership list containing only the local node.
+    pub fn new(name: String, address: String, incarnation: u64) -> Self {
+        Membership {
+            local: Member {
+                name,
+                address,
+                state: MemberState::Alive.into(),
+                incarnation,
+            },
+            members: HashMap::new(),
+        }
+    }
+
+    /// Returns the local node's member record.
+    pub fn local_member(&self) -> &Member {
+        &self.local
+    }
+
+    /// Returns every known member, including the local node, for piggybacking on gossip messages.
+    pub fn snapshot(&self) -> Vec<Member> {
+        std::iter::once(self.local.clone())
+            .chain(self.members.values().map(|entry| entry.member.clone()))
+            .collect()
+    }
+
+    /// Merges a member update. Returns `true` if the membership list changed.
+    pub fn apply(&mut self, update: Member, now: Instant) -> bool {
+        if update.name == self.local.name {
+            return self.refute(&update);
+        }
+
+        match self.members.get_mut(&update.name) {
+            Some(entry) if !supersedes(&update, &entry.member) => false,
+            Some(entry) => {
+                if entry.member.state() != update.state() {
+                    entry.changed_at = now;
+                }
+                entry.member = update;
+                true
+            }
+            None => {
+                self.members.insert(
+                    update.name.clone(),
+                    MemberEntry {
+                        member: update,
+                        changed_at: now,
+                    },
+                );
+                true
+            }
+        }
+    }
+
+    /// Merges every update in `updates`. Returns `true` if the membership list changed.
+    pub fn apply_all(&mut self, updates: impl IntoIterator<Item = Member>, now: Instant) -> bool {
+        let mut changed = false;
+        for update in updates {
+            changed |= self.apply(update, now);
+        }
+        changed
+    }
+
+    /// Marks an alive member as suspected of having failed.
+    pub fn suspect(&mut self, name: &str, now: Instant) -> bool {
+        let Some(entry) = self.members.get(name) else {
+            return false;
+        };
+        if entry.member.state() != MemberState::Alive {
+            return false;
+        }
+        let mut update = entry.member.clone();
+        update.set_state(MemberState::Suspect);
+        self.apply(update, now)
+    }
+
+    /// Declares members dead if they have been suspected for longer than `suspicion_timeout`.
+    ///
+    /// Returns the names of the members that were declared dead.
+    pub fn expire_suspects(&mut self, now: Instant, suspicion_timeout: Duration) -> Vec<String> {
+        let mut expired = Vec::new();
+        for entry in self.members.values_mut() {
+            if entry.member.state() == MemberState::Suspect
+                && now.duration_since(entry.changed_at) >= suspicion_timeout
+            {
+                entry.member.set_state(MemberState::Dead);
+                entry.changed_at = now;
+                expired.push(entry.member.name.clone());
+            }
+        }
+        expired
+    }
+
+    /// Returns the members that should be probed, i.e. every member not known to be dead.
+    pub fn probe_targets(&self) -> Vec<Member> {
+        let mut targets: Vec<Member> = self
+            .members
+            .values()
+            .filter(|entry| entry.member.state() != MemberState::Dead)
+            .map(|entry| entry.member.clone())
+            .collect();
+        targets.sort_by(|a, b| a.name.cmp(&b.name));
+        targets
+    }
+
+    /// Returns the addresses of members not known to be dead, excluding the local node.
+    pub fn live_addresses(&self) -> Vec<String> {
+        self.probe_targets()
+            .into_iter()
+            .map(|member| member.address)
+            .collect()
+    }
+
+    /// Returns `true` if a member with the given address is known, regardless of its state.
+    pub fn knows_address(&self, address: &str) -> bool {
+        self.local.address == address
+            || self
+                .members
+                .values()
+                .any(|entry| entry.member.address == address)
+    }
+
+    /// Refutes a rumor that the local node is suspected or dead by bumping its incarnation.
+    fn refute(&mut self, update: &Member) -> bool {
+        if update.state() == MemberState::Alive || update.incarnation < self.local.incarnation {
+            return false;
+        }
+        self.local.incarnation = update.incarnation + 1;
+        true
+    }
+}
+
+/// Returns `true` if `update` takes precedence over `current` under the SWIM rules.
+fn supersedes(update: &Member, current: &Member) -> bool {
+    if update.incarnation != current.incarnation {
+        return update.incarnation > current.incarnation;
+    }
+    state_rank(update.state()) > state_rank(current.state())
+}
+
+fn state_rank(state: MemberState) -> u8 {
+    match state {
+        MemberState::Alive => 0,
+        MemberState::Suspect => 1,
+        MemberState::Dead => 2,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state(membership: &Membership, name: &str) -> Option<MemberState> {
+        membership
+            .snapshot()
+            .into_iter()
+            .find(|member| member.name == name)
+            .map(|member| member.state())
+    }
+
+    fn member(name: &str, state: MemberState, incarnation: u64) -> Member {
+        Member {
+            name: name.to_string(),
+            address: format!("http://{}:8080", name),
+            state: state.into(),
+            incarnation,
+        }
+    }
+
+    #[test]
+    fn test_apply_precedence() {
+        let now = Instant::now();
+        let mut membership = Membership::new("local".to_string(), "http://local".to_string(), 1);
+
+        assert!(membership.apply(member("a", MemberState::Alive, 1), now));
+        assert!(membership.apply(member("a", MemberState::Suspect, 1), now));
+        assert!(!membership.apply(member("a", MemberState::Alive, 1), now));
+        assert_eq!(state(&membership, "a"), Some(MemberState::Suspect));
+
+        // A refutation with a higher incarnation restores the member
+        assert!(membership.apply(member("a", MemberState::Alive, 2), now));
+        assert_eq!(state(&membership, "a"), Some(MemberState::Alive));
+
+        assert!(membership.apply(member("a", MemberState::Dead, 2), now));
+        assert!(!membership.apply(member("a", MemberState::Alive, 2), now));
+        assert_eq!(state(&membership, "a"), Some(MemberState::Dead));
+    }
+
+    #[test]
+    fn test_local_node_refutes_suspicion() {
+        let now = Instant::now();
+        let mut membership = Membership::new("local".to_string(), "http://local".to_string(), 3);
+
+        assert!(membership.apply(member("local", MemberState::Suspect, 3), now));
+        assert_eq!(membership.local_member().incarnation, 4);
+        assert_eq!(membership.local_member().state(), MemberState::Alive);
+        assert!(!membership.apply(member("local", MemberState::Alive, 10), now));
+    }
+
+    #[test]
+    fn test_suspects_expire_to_dead() {
+        let now = Instant::now();
+        let timeout = Duration::from_secs(5);
+        let mut membership = Membership::new("local".to_string(), "http://local".to_string(), 1);
+        membership.apply(member("a", MemberState::Alive, 1), now);
+        membership.apply(member("b", MemberState::Alive, 1), now);
+
+        assert!(membership.suspect("a", now));
+        assert!(membership.expire_suspects(now, timeout).is_empty());
+        assert_eq!(
+            membership.expire_suspects(now + timeout, timeout),
+            vec!["a"]
+        );
+        assert_eq!(state(&membership, "a"), Some(MemberState::Dead));
+        assert_eq!(membership.live_addresses(), vec!["http://b:8080"]);
+        assert!(membership.knows_address("http://a:8080"));
+    }
+}
@@ -0,0 +1,134 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use nenya::{RateLimiter, RateLimiterBuilder};
+
+/// How often the event loop lag is measured.
+const LAG_PROBE_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Kind of RPC, in the order RPCs are shed when the sentinel is overloaded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RpcClass {
+    /// Metric exchanges with peers, shed first since peers back off and retry them.
+    Exchange,
+    /// Throttling decisions, which are never shed so callers keep getting answers.
+    Decision,
+}
+
+/// Protects the sentinel from more RPCs than it can handle.
+///
+/// Every RPC is counted by a [`RateLimiter`] enforcing `max_rpc_rate`. While that limiter
+/// throttles, or while the event loop lags behind by more than `max_event_loop_lag`, metric
+/// exchanges are shed so throttling decisions stay responsive.
+#[derive(Debug)]
+pub struct OverloadGuard {
+    rpc_limiter: Mutex<RateLimiter<f32>>,
+    max_rpc_rate: f32,
+    max_event_loop_lag: Duration,
+    event_loop_lag_micros: AtomicU64,
+    shed_exchanges: AtomicU64,
+}
+
+impl OverloadGuard {
+    pub fn new(max_rpc_rate: f32, max_event_loop_lag: Duration) -> Self {
+        OverloadGuard {
+            rpc_limiter: Mutex::new(RateLimiterBuilder::new(max_rpc_rate).build()),
+            max_rpc_rate,
+            max_event_loop_lag,
+            event_loop_lag_micros: AtomicU64::new(0),
+            shed_exchanges: AtomicU64::new(0),
+        }
+    }
+
+    /// Counts an RPC of `class`, returning `false` if it should be shed.
+    pub fn admit(&self, class: RpcClass) -> bool {
+        let over_rate = self.rpc_limiter.lock().unwrap().should_throttle();
+        let overloaded = over_rate || self.event_loop_lag() > self.max_event_loop_lag;
+        if overloaded && class == RpcClass::Exchange {
+            self.shed_exchanges.fetch_add(1, Ordering::Relaxed);
+            return false;
+        }
+        true
+    }
+
+    /// Returns the rate of RPCs handled by this node, in RPCs per second.
+    pub fn rpc_rate(&self) -> f32 {
+        self.rpc_limiter.lock().unwrap().request_rate()
+    }
+
+    /// Returns the last measured event loop lag.
+    pub fn event_loop_lag(&self) -> Duration {
+        Duration::from_micros(self.event_loop_lag_micros.load(Ordering::Relaxed))
+    }
+
+    /// Returns the number of metric exchanges shed so far.
+    pub fn shed_exchanges(&self) -> u64 {
+        self.shed_exchanges.load(Ordering::Relaxed)
+    }
+
+    /// Returns how close the node is to its limits, where 1 or more means it sheds exchanges.
+    pub fn saturation(&self) -> f32 {
+        let rpc_saturation = self.rpc_rate() / self.max_rpc_rate;
+        let lag_saturation =
+            self.event_loop_lag().as_secs_f32() / self.max_event_loop_lag.as_secs_f32();
+        rpc_saturation.max(lag_saturation)
+    }
+
+    fn record_lag(&self, lag: Duration) {
+        self.event_loop_lag_micros
+            .store(lag.as_micros() as u64, Ordering::Relaxed);
+    }
+}
+
+/// Periodically measures how late the event loop wakes up a sleeping task, logging when the
+/// node becomes saturated or recovers.
+pub async fn run_lag_monitor(guard: Arc<OverloadGuard>) {
+    let mut saturated = false;
+    loop {
+        let start = Instant::now();
+        tokio::time::sleep(LAG_PROBE_INTERVAL).await;
+        guard.record_lag(start.elapsed().saturating_sub(LAG_PROBE_INTERVAL));
+
+        let saturation = guard.saturation();
+        if (saturation >= 1.0) != saturated {
+            saturated = !saturated;
+            if saturated {
+                tracing::warn!(
+                    saturation,
+                    rpc_rate = guard.rpc_rate(),
+                    event_loop_lag_ms = guard.event_loop_lag().as_millis() as u64,
+                    "sentinel overloaded, shedding metric exchanges"
+                );
+            } else {
+                tracing::info!(saturation, "sentinel recovered from overload");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sheds_exchanges_before_decisions() {
+        let guard = OverloadGuard::new(5.0, Duration::from_millis(100));
+        assert!(guard.admit(RpcClass::Exchange));
+        for _ in 0..20 {
+            assert!(guard.admit(RpcClass::Decision));
+        }
+        assert!(!guard.admit(RpcClass::Exchange));
+        assert!(guard.shed_exchanges() > 0);
+        assert!(guard.saturation() >= 1.0);
+    }
+
+    #[test]
+    fn test_event_loop_lag_sheds_exchanges() {
+        let guard = OverloadGuard::new(1000.0, Duration::from_millis(100));
+        guard.record_lag(Duration::from_millis(250));
+        assert!(!guard.admit(RpcClass::Exchange));
+        assert!(guard.admit(RpcClass::Decision));
+        assert_eq!(guard.saturation(), 2.5);
+    }
+}
@@ -0,0 +1,256 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::sentinel::{MetricData, Metrics};
+
+/// Rough encoded size of a segment entry besides its name, used to split pages by size.
+const SEGMENT_ENTRY_OVERHEAD: usize = 16;
+
+/// Bounds on the metric exchanges between peers.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExchangeLimits {
+    /// Largest number of segments, including removed ones, in a single exchange. Larger
+    /// snapshots are split into several pages.
+    pub max_segments: usize,
+    /// Largest encoded size of a single exchange message.
+    pub max_payload_bytes: usize,
+}
+
+impl Default for ExchangeLimits {
+    fn default() -> Self {
+        ExchangeLimits {
+            max_segments: 5000,
+            max_payload_bytes: 4 * 1024 * 1024,
+        }
+    }
+}
+
+/// Reasons a peer's metrics are rejected.
+#[derive(Debug, Clone, PartialEq)]
+pub enum PayloadError {
+    /// The metrics do not name the node they come from.
+    MissingSource,
+    /// The metrics hold more segments than allowed in a single exchange.
+    TooManySegments {
+        segments: usize,
+        max_segments: usize,
+    },
+    /// A segment reports a negative or non-finite rate.
+    InvalidRate(String),
+}
+
+impl fmt::Display for PayloadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PayloadError::MissingSource => write!(f, "metrics source is required"),
+            PayloadError::TooManySegments {
+                segments,
+                max_segments,
+            } => write!(
+                f,
+                "metrics hold {} segments, more than the limit of {}",
+                segments, max_segments
+            ),
+            PayloadError::InvalidRate(segment) => {
+                write!(f, "segment {:?} reports an invalid rate", segment)
+            }
+        }
+    }
+}
+
+impl std::error::Error for PayloadError {}
+
+impl From<PayloadError> for tonic::Status {
+    fn from(err: PayloadError) -> Self {
+        tonic::Status::invalid_argument(err.to_string())
+    }
+}
+
+impl ExchangeLimits {
+    /// Checks metrics pushed by a peer against the limits.
+    pub fn validate(&self, metrics: &Metrics) -> Result<(), PayloadError> {
+        if metrics.source.is_empty() {
+            return Err(PayloadError::MissingSource);
+        }
+        let segments = metrics.segments.len() + metrics.removed_segments.len();
+        if segments > self.max_segments {
+            return Err(PayloadError::TooManySegments {
+                segments,
+                max_segments: self.max_segments,
+            });
+        }
+        let invalid = metrics.segments.iter().find(|(_, data)| {
+            [data.request_rate, data.accepted_request_rate]
+                .into_iter()
+                .any(|rate| !rate.is_finite() || rate < 0.0)
+        });
+        match invalid {
+            Some((segment, _)) => Err(PayloadError::InvalidRate(segment.clone())),
+            None => Ok(()),
+        }
+    }
+
+    /// Splits `metrics` into the pages sent to a peer.
+    ///
+    /// If `previous` holds the segments last delivered to the peer, only the segments that
+    /// changed since, and the names of those removed since, are sent as a delta. Otherwise the
+    /// first page is a full snapshot. Every page after the first is a delta extending it, and
+    /// only the first page carries the target shares.
+    pub fn pages(
+        &self,
+        metrics: &Metrics,
+        previous: Option<&HashMap<String, MetricData>>,
+    ) -> Vec<Metrics> {
+        let mut segments: Vec<(&String, &MetricData)> = metrics
+            .segments
+            .iter()
+            .filter(|(segment, data)| {
+                previous.is_none_or(|previous| previous.get(*segment) != Some(*data))
+            })
+            .collect();
+        segments.sort_by_key(|(segment, _)| *segment);
+        let mut removed: Vec<&String> = previous
+            .into_iter()
+            .flat_map(HashMap::keys)
+            .filter(|segment| !metrics.segments.contains_key(*segment))
+            .collect();
+        removed.sort();
+
+        let new_page = |page: usize| Metrics {
+            source: metrics.source.clone(),
+            region: metrics.region.clone(),
+            zone: metrics.zone.clone(),
+            delta: previous.is_some() || page > 0,
+            page: page as u32,
+            ..Default::default()
+        };
+        let mut pages = vec![Metrics {
+            shares: metrics.shares.clone(),
+            ..new_page(0)
+        }];
+        let mut entries = 0;
+        let mut bytes = 0;
+        let mut next_entry = |pages: &mut Vec<Metrics>, name: &str| {
+            let size = name.len() + SEGMENT_ENTRY_OVERHEAD;
+            if entries > 0 && (entries == self.max_segments || bytes + size > self.page_bytes()) {
+                pages.push(new_page(pages.len()));
+                entries = 0;
+                bytes = 0;
+            }
+            entries += 1;
+            bytes += size;
+        };
+        for segment in removed {
+            next_entry(&mut pages, segment);
+            pages
+                .last_mut()
+                .unwrap()
+                .removed_segments
+                .push(segment.clone());
+        }
+        for (segment, data) in segments {
+            next_entry(&mut pages, segment);
+            let page = pages.last_mut().unwrap();
+            page.segments.insert(segment.clone(), data.clone());
+        }
+        pages
+    }
+
+    /// Drops segments from `metrics` until it fits the limits, turning it into a delta so the
+    /// receiver keeps the dropped segments it already has. Returns `true` if any were dropped.
+    pub fn truncate(&self, metrics: &mut Metrics) -> bool {
+        let mut bytes = 0;
+        let mut kept = 0;
+        let before = metrics.segments.len();
+        metrics.segments.retain(|segment, _| {
+            bytes += segment.len() + SEGMENT_ENTRY_OVERHEAD;
+            kept += 1;
+            kept <= self.max_segments && bytes <= self.page_bytes()
+        });
+        let truncated = metrics.segments.len() < before;
+        metrics.delta |= truncated;
+        truncated
+    }
+
+    /// Returns the budget for the segments of a page, leaving room for the target shares and
+    /// framing.
+    fn page_bytes(&self) -> usize {
+        self.max_payload_bytes / 2
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn metrics(segments: &[(&str, f32)]) -> Metrics {
+        Metrics {
+            source: "local".to_string(),
+            segments: segments
+                .iter()
+                .map(|(segment, request_rate)| {
+                    let data = MetricData {
+                        request_rate: *request_rate,
+                        accepted_request_rate: *request_rate,
+                    };
+                    (segment.to_string(), data)
+                })
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_pages_split_snapshots_and_encode_deltas() {
+        let limits = ExchangeLimits {
+            max_segments: 2,
+            ..Default::default()
+        };
+        let snapshot = metrics(&[("a", 1.0), ("b", 2.0), ("c", 3.0)]);
+        let pages = limits.pages(&snapshot, None);
+        assert_eq!(pages.len(), 2);
+        assert!(!pages[0].delta);
+        assert_eq!(pages[0].segments.len(), 2);
+        assert!(pages[1].delta);
+        assert_eq!(pages[1].page, 1);
+
+        let update = metrics(&[("a", 1.0), ("b", 5.0), ("d", 1.0)]);
+        let pages = limits.pages(&update, Some(&snapshot.segments));
+        assert_eq!(pages.len(), 2);
+        assert!(pages.iter().all(|page| page.delta));
+        assert_eq!(pages[0].removed_segments, ["c"]);
+        assert!(pages[0].segments.contains_key("b"));
+        assert!(pages[1].segments.contains_key("d"));
+
+        let unchanged = limits.pages(&update, Some(&update.segments));
+        assert_eq!(unchanged.len(), 1);
+        assert!(unchanged[0].segments.is_empty());
+    }
+
+    #[test]
+    fn test_validate_and_truncate() {
+        let limits = ExchangeLimits {
+            max_segments: 2,
+            ..Default::default()
+        };
+        assert_eq!(limits.validate(&metrics(&[("a", 1.0)])), Ok(()));
+        assert_eq!(
+            limits.validate(&metrics(&[("a", f32::NAN)])),
+            Err(PayloadError::InvalidRate("a".to_string()))
+        );
+        assert_eq!(
+            limits.validate(&Metrics::default()),
+            Err(PayloadError::MissingSource)
+        );
+
+        let mut oversized = metrics(&[("a", 1.0), ("b", 1.0), ("c", 1.0)]);
+        assert!(matches!(
+            limits.validate(&oversized),
+            Err(PayloadError::TooManySegments { segments: 3, .. })
+        ));
+        assert!(limits.truncate(&mut oversized));
+        assert_eq!(oversized.segments.len(), 2);
+        assert!(oversized.delta);
+        assert_eq!(limits.validate(&oversized), Ok(()));
+    }
+}
@@ -0,0 +1,67 @@
+use tonic::metadata::AsciiMetadataValue;
+use tonic::transport::{ClientTlsConfig, Endpoint};
+use tonic::Request;
+
+/// Settings applied to every outgoing connection and request to a peer.
+#[derive(Debug, Clone, Default)]
+pub struct PeerConnector {
+    tls: Option<ClientTlsConfig>,
+    token: Option<AsciiMetadataValue>,
+}
+
+impl PeerConnector {
+    /// Uses TLS for connections to peers.
+    pub fn with_tls(mut self, tls: ClientTlsConfig) -> Self {
+        self.tls = Some(tls);
+        self
+    }
+
+    /// Sends `token` in the `authorization` header of requests to peers.
+    pub fn with_token(mut self, token: AsciiMetadataValue) -> Self {
+        self.token = Some(token);
+        self
+    }
+
+    /// Creates an endpoint for a peer, applying the TLS settings if present.
+    pub fn endpoint(&self, address: &str) -> Result<Endpoint, tonic::transport::Error> {
+        let endpoint = Endpoint::from_shared(address.to_string())?;
+        match &self.tls {
+            Some(tls) => endpoint.tls_config(tls.clone()),
+            None => Ok(endpoint),
+        }
+    }
+
+    /// Wraps a message in a request carrying the peer token, if present.
+    pub fn request<T>(&self, message: T) -> Request<T> {
+        crate::auth::with_token(message, self.token.as_ref())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_endpoint_without_tls() {
+        let connector = PeerConnector::default();
+        let endpoint = connector.endpoint("http://127.0.0.1:8080").unwrap();
+        assert_eq!(endpoint.uri().to_string(), "http://127.0.0.1:8080/");
+        assert!(connector.endpoint("not a uri").is_err());
+    }
+
+    #[test]
+    fn test_request_carries_token() {
+        let connector =
+            PeerConnector::default().with_token(crate::auth::bearer_token("secret").unwrap());
+        let request = connector.request(());
+        assert_eq!(
+            request.metadata().get("authorization").unwrap(),
+            "Bearer secret"
+        );
+        assert!(PeerConnector::default()
+            .request(())
+            .metadata()
+            .get("authorization")
+            .is_none());
+    }
+}
@@ -0,0 +1,68 @@
+use crate::sentinel::{Capability, Metrics};
+
+/// Version of the exchange protocol spoken by this node.
+///
+/// Peers that predate versioning report version 0. Versions are informational; what a peer can
+/// handle is decided by its capabilities, so nodes of different versions interoperate during
+/// rolling upgrades.
+pub const PROTOCOL_VERSION: u32 = 2;
+
+/// Capabilities this node supports.
+const CAPABILITIES: [Capability; 1] = [Capability::DeltaExchange];
+
+/// Marks `metrics` with this node's protocol version and capabilities.
+pub fn advertise(metrics: &mut Metrics) {
+    metrics.protocol_version = PROTOCOL_VERSION;
+    metrics.capabilities = CAPABILITIES
+        .iter()
+        .map(|&capability| capability.into())
+        .collect();
+}
+
+/// Protocol version and capabilities a peer advertised in its last exchange.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PeerProtocol {
+    pub version: u32,
+    capabilities: Vec<Capability>,
+}
+
+impl PeerProtocol {
+    /// Returns `true` if the peer supports `capability`, and this node does too.
+    pub fn supports(&self, capability: Capability) -> bool {
+        CAPABILITIES.contains(&capability) && self.capabilities.contains(&capability)
+    }
+}
+
+impl From<&Metrics> for PeerProtocol {
+    fn from(metrics: &Metrics) -> Self {
+        PeerProtocol {
+            version: metrics.protocol_version,
+            // Capabilities added by newer versions are unknown to this node and ignored
+            capabilities: metrics
+                .capabilities
+                .iter()
+                .filter_map(|&capability| Capability::try_from(capability).ok())
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_negotiates_shared_capabilities() {
+        let legacy = PeerProtocol::from(&Metrics::default());
+        assert_eq!(legacy.version, 0);
+        assert!(!legacy.supports(Capability::DeltaExchange));
+
+        let mut metrics = Metrics::default();
+        advertise(&mut metrics);
+        metrics.capabilities.push(1000);
+        let current = PeerProtocol::from(&metrics);
+        assert_eq!(current.version, PROTOCOL_VERSION);
+        assert!(current.supports(Capability::DeltaExchange));
+        assert!(!current.supports(Capability::Unspecified));
+    }
+}
@@ -0,0 +1,116 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::time::{Duration, Instant};
+
+/// How metric exchanges back off from peers whose exchanges keep failing.
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    /// Consecutive failures after which a peer's circuit opens and the peer is skipped.
+    pub failure_threshold: u32,
+    /// How long the circuit stays open after it first opens.
+    pub initial_backoff: Duration,
+    /// Upper bound for the doubling backoff while a peer keeps failing.
+    pub max_backoff: Duration,
+    /// Largest number of exchanges in flight at once.
+    pub max_concurrent_exchanges: usize,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            failure_threshold: 3,
+            initial_backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(30),
+            max_concurrent_exchanges: 16,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Returns the backoff after `failures` consecutive failures, before jitter.
+    fn backoff(&self, failures: u32) -> Duration {
+        let exponent = failures.saturating_sub(self.failure_threshold).min(31);
+        self.initial_backoff
+            .saturating_mul(1 << exponent)
+            .min(self.max_backoff)
+    }
+}
+
+/// Circuit breaker for the exchanges with a single peer.
+///
+/// The circuit opens after `failure_threshold` consecutive failures, skipping the peer for an
+/// exponentially growing, jittered backoff. Once the backoff expires a single exchange is let
+/// through, closing the circuit on success or reopening it with a longer backoff on failure.
+#[derive(Debug, Default)]
+pub struct PeerCircuit {
+    failures: u32,
+    open_until: Option<Instant>,
+}
+
+impl PeerCircuit {
+    /// Returns `true` if an exchange with the peer may be attempted at `now`.
+    pub fn allows(&self, now: Instant) -> bool {
+        self.open_until.is_none_or(|open_until| now >= open_until)
+    }
+
+    /// Records a failed exchange at `now`, returning the backoff if the circuit (re)opened.
+    pub fn record_failure(&mut self, policy: &RetryPolicy, now: Instant) -> Option<Duration> {
+        self.failures = self.failures.saturating_add(1);
+        if self.failures < policy.failure_threshold {
+            return None;
+        }
+        let backoff = jitter(policy.backoff(self.failures));
+        self.open_until = Some(now + backoff);
+        Some(backoff)
+    }
+
+    /// Records a successful exchange, returning `true` if the circuit was open.
+    pub fn record_success(&mut self) -> bool {
+        self.failures = 0;
+        self.open_until.take().is_some()
+    }
+}
+
+/// Returns a random duration between half of `backoff` and `backoff`, so peers that failed
+/// together do not retry in lockstep.
+fn jitter(backoff: Duration) -> Duration {
+    let random = RandomState::new().build_hasher().finish();
+    let fraction = random as f64 / u64::MAX as f64;
+    backoff.mul_f64(0.5 + fraction / 2.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_circuit_opens_after_threshold_with_growing_backoff() {
+        let policy = RetryPolicy {
+            failure_threshold: 2,
+            initial_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(3),
+            max_concurrent_exchanges: 1,
+        };
+        let mut circuit = PeerCircuit::default();
+        let now = Instant::now();
+
+        assert_eq!(circuit.record_failure(&policy, now), None);
+        assert!(circuit.allows(now));
+
+        let backoff = circuit.record_failure(&policy, now).unwrap();
+        assert!(backoff >= Duration::from_millis(500) && backoff <= Duration::from_secs(1));
+        assert!(!circuit.allows(now));
+        assert!(circuit.allows(now + backoff));
+
+        let backoff = circuit.record_failure(&policy, now).unwrap();
+        assert!(backoff >= Duration::from_secs(1) && backoff <= Duration::from_secs(2));
+        for _ in 0..10 {
+            circuit.record_failure(&policy, now);
+        }
+        assert_eq!(policy.backoff(12), Duration::from_secs(3));
+
+        assert!(circuit.record_success());
+        assert!(circuit.allows(now));
+        assert!(!circuit.record_success());
+    }
+}
@@ -0,0 +1,558 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use nenya::clock::{Clock, SystemClock, Timestamp};
+use nenya::keyed::KeyedRateLimiter;
+use nenya::pid_controller::PIDController;
+use nenya::{RateLimiter, RateLimiterBuilder};
+
+use crate::sentinel::{Enforcement, SegmentConfig};
+use crate::SentinelService;
+
+/// Segment used when a request does not name one.
+pub const DEFAULT_SEGMENT: &str = "default";
+
+/// Separates the namespace from the segment name in the keys of namespaced segments.
+pub const NAMESPACE_SEPARATOR: char = '/';
+
+/// Suffix of segment names configuring every segment that starts with the rest of the name,
+/// e.g. `api.users.*`.
+pub const PATTERN_WILDCARD: char = '*';
+
+/// Returns the key identifying `segment` of `namespace`, or of the default namespace if `None`.
+///
+/// Segments of the default namespace are keyed by their name, and other segments by
+/// `namespace/segment`.
+pub fn segment_key(namespace: Option<&str>, segment: &str) -> String {
+    match namespace {
+        Some(namespace) => format!("{}{}{}", namespace, NAMESPACE_SEPARATOR, segment),
+        None => segment.to_string(),
+    }
+}
+
+/// A segment's rate limiter and when the segment was last active.
+#[derive(Debug)]
+pub struct Segment {
+    pub rate_limiter: RateLimiter<f32>,
+    /// Per-caller quotas within the segment, keyed by caller identity.
+    pub caller_quotas: Option<KeyedRateLimiter<String, f32>>,
+    /// When set, requests are counted but never throttled.
+    pub paused: bool,
+    /// Whether throttling decisions are returned to callers or only counted.
+    pub enforcement: Enforcement,
+    /// Requests that would have been throttled while in observe mode.
+    pub shadow_throttled: u64,
+    /// Share of the target rate assigned to this node by the coordinator.
+    share: Option<f32>,
+    target_override: Option<TargetOverride>,
+    last_active: Timestamp,
+    dynamic: bool,
+}
+
+/// Target rate enforced instead of the PID-controlled one until it expires.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TargetOverride {
+    pub target_tps: f32,
+    pub expires_at: Timestamp,
+}
+
+impl Segment {
+    /// Creates a segment. Only `dynamic` segments, created on first use rather than from the
+    /// config, are evicted when idle.
+    ///
+    /// The segment is active as of the current time on the rate limiter's clock.
+    pub fn new(rate_limiter: RateLimiter<f32>, dynamic: bool) -> Self {
+        let last_active = rate_limiter.clock().now();
+        Segment {
+            rate_limiter,
+            caller_quotas: None,
+            paused: false,
+            enforcement: Enforcement::Enforce,
+            shadow_throttled: 0,
+            share: None,
+            target_override: None,
+            last_active,
+            dynamic,
+        }
+    }
+
+    /// Exempts the segment from idle eviction, e.g. because it carries runtime overrides.
+    pub fn pin(&mut self) {
+        self.dynamic = false;
+    }
+
+    /// Returns `true` if the segment is evicted once idle.
+    pub fn is_dynamic(&self) -> bool {
+        self.dynamic
+    }
+
+    /// Records activity on the segment, postponing its eviction.
+    pub fn touch(&mut self, now: Timestamp) {
+        self.last_active = now;
+    }
+
+    /// Enforces `share` of the target rate, as assigned by the coordinator, or resumes PID control
+    /// if `None`.
+    pub fn set_share(&mut self, share: Option<f32>) {
+        self.share = share;
+        self.apply_fixed_target();
+    }
+
+    /// Returns the active temporary override, if any.
+    pub fn target_override(&self) -> Option<TargetOverride> {
+        self.target_override
+    }
+
+    /// Enforces a temporary target rate until it expires, taking precedence over the PID
+    /// controller and any assigned share. `None` removes the override.
+    pub fn set_target_override(&mut self, target_override: Option<TargetOverride>) {
+        self.target_override = target_override;
+        self.apply_fixed_target();
+    }
+
+    /// Removes the temporary override if it expired by `now`, returning `true` if it did.
+    pub fn expire_target_override(&mut self, now: Timestamp) -> bool {
+        let expired = self
+            .target_override
+            .is_some_and(|target_override| now >= target_override.expires_at);
+        if expired {
+            self.set_target_override(None);
+        }
+        expired
+    }
+
+    fn apply_fixed_target(&mut self) {
+        let fixed_target_rate = self
+            .target_override
+            .map(|target_override| target_override.target_tps)
+            .or(self.share);
+        self.rate_limiter.set_fixed_target_rate(fixed_target_rate);
+    }
+
+    /// Limits each caller to `caller_max_tps`, or removes the per-caller quotas if `None`.
+    pub fn set_caller_max_tps(&mut self, caller_max_tps: Option<f32>) {
+        match (&mut self.caller_quotas, caller_max_tps) {
+            (Some(caller_quotas), Some(caller_max_tps)) => {
+                caller_quotas.set_key_rate(caller_max_tps)
+            }
+            (_, caller_max_tps) => {
+                self.caller_quotas = caller_max_tps.map(KeyedRateLimiter::new);
+            }
+        }
+    }
+
+    /// Returns `true` if the segment is dynamic and has not been active for `idle_timeout`.
+    pub fn is_idle(&self, now: Timestamp, idle_timeout: Duration) -> bool {
+        self.dynamic && now.duration_since(self.last_active) >= idle_timeout
+    }
+}
+
+/// Where the config of a segment comes from, see [`SegmentFactory::config`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfigOrigin<'a> {
+    /// The segment's own config.
+    Segment,
+    /// The pattern with this prefix.
+    Pattern(&'a str),
+    /// The default segment config of the segment's namespace.
+    Namespace,
+    /// The default segment config.
+    Default,
+}
+
+/// Builds the rate limiter for a segment from its configured override, the most specific
+/// pattern matching it, its namespace's default segment config, or the default segment config.
+#[derive(Debug, Clone)]
+pub struct SegmentFactory {
+    default_config: SegmentConfig,
+    overrides: HashMap<String, SegmentConfig>,
+    /// Prefixes of the configured patterns, longest first.
+    patterns: Vec<(String, SegmentConfig)>,
+    namespaces: HashMap<String, SegmentConfig>,
+    pid_controller: PIDController<f32>,
+    update_interval: Duration,
+    /// Clock of the rate limiters, the system clock if `None`.
+    clock: Option<Arc<dyn Clock>>,
+}
+
+impl SegmentFactory {
+    /// Creates a factory from the segment configs keyed by segment key. Keys ending in
+    /// [`PATTERN_WILDCARD`] are patterns matching every segment starting with the rest of the
+    /// key.
+    pub fn new(
+        default_config: SegmentConfig,
+        segments: HashMap<String, SegmentConfig>,
+        pid_controller: PIDController<f32>,
+        update_interval: Duration,
+    ) -> Self {
+        let (patterns, overrides): (HashMap<_, _>, HashMap<_, _>) = segments
+            .into_iter()
+            .partition(|(key, _)| key.ends_with(PATTERN_WILDCARD));
+        let mut patterns: Vec<(String, SegmentConfig)> = patterns
+            .into_iter()
+            .map(|(mut pattern, config)| {
+                pattern.pop();
+                (pattern, config)
+            })
+            .collect();
+        patterns.sort_by(|(a, _), (b, _)| b.len().cmp(&a.len()).then_with(|| a.cmp(b)));
+        SegmentFactory {
+            default_config,
+            overrides,
+            patterns,
+            namespaces: HashMap::new(),
+            pid_controller,
+            update_interval,
+            clock: None,
+        }
+    }
+
+    /// Sets the namespaces, each with the default config for its segments.
+    pub fn with_namespaces(mut self, namespaces: HashMap<String, SegmentConfig>) -> Self {
+        self.namespaces = namespaces;
+        self
+    }
+
+    /// Sets the clock the rate limiters read the time from.
+    #[cfg(any(test, feature = "test-util"))]
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = Some(clock);
+        self
+    }
+
+    /// Returns the current time on the clock of the rate limiters.
+    pub fn now(&self) -> Timestamp {
+        match &self.clock {
+            Some(clock) => clock.now(),
+            None => SystemClock.now(),
+        }
+    }
+
+    /// Returns the keys of the segments with an explicit config, excluding patterns.
+    pub fn configured_segments(&self) -> impl Iterator<Item = &String> {
+        self.overrides.keys()
+    }
+
+    /// Returns `true` if `namespace` is configured.
+    pub fn has_namespace(&self, namespace: &str) -> bool {
+        self.namespaces.contains_key(namespace)
+    }
+
+    /// Returns the key of `segment` in `namespace`, or `None` if the namespace does not exist.
+    ///
+    /// Names in the default namespace that start with a namespace are rejected so callers cannot
+    /// reach into other namespaces. An empty namespace is the default namespace.
+    pub fn segment_key(&self, namespace: Option<&str>, segment: &str) -> Option<String> {
+        match namespace.filter(|namespace| !namespace.is_empty()) {
+            Some(namespace) => self
+                .has_namespace(namespace)
+                .then(|| segment_key(Some(namespace), segment)),
+            None => self
+                .namespace_of(segment)
+                .is_none()
+                .then(|| segment.to_string()),
+        }
+    }
+
+    /// Returns the namespace of the segment keyed by `key`, or `None` for the default namespace.
+    pub fn namespace_of<'a>(&self, key: &'a str) -> Option<&'a str> {
+        key.split_once(NAMESPACE_SEPARATOR)
+            .map(|(namespace, _)| namespace)
+            .filter(|namespace| self.has_namespace(namespace))
+    }
+
+    /// Returns the name of the segment keyed by `key` within its namespace.
+    pub fn segment_name<'a>(&self, key: &'a str) -> &'a str {
+        match self.namespace_of(key) {
+            Some(namespace) => &key[namespace.len() + NAMESPACE_SEPARATOR.len_utf8()..],
+            None => key,
+        }
+    }
+
+    /// Returns the config for the segment keyed by `key`, falling back to the longest pattern
+    /// matching it, its namespace's default segment config, and then the default segment
+    /// config.
+    ///
+    /// Patterns only match segments of their own namespace.
+    pub fn config(&self, key: &str) -> &SegmentConfig {
+        self.resolve_config(key).1
+    }
+
+    /// Returns the config for the segment keyed by `key` along with where it comes from.
+    pub fn resolve_config(&self, key: &str) -> (ConfigOrigin<'_>, &SegmentConfig) {
+        if let Some(config) = self.overrides.get(key) {
+            return (ConfigOrigin::Segment, config);
+        }
+        let namespace = self.namespace_of(key);
+        let pattern = self.patterns.iter().find(|(prefix, _)| {
+            key.starts_with(prefix.as_str()) && self.pattern_namespace(prefix) == namespace
+        });
+        if let Some((prefix, config)) = pattern {
+            return (ConfigOrigin::Pattern(prefix), config);
+        }
+        match namespace.and_then(|namespace| self.namespaces.get(namespace)) {
+            Some(config) => (ConfigOrigin::Namespace, config),
+            None => (ConfigOrigin::Default, &self.default_config),
+        }
+    }
+
+    /// Returns the namespace a pattern's prefix belongs to, or `None` for the default namespace.
+    fn pattern_namespace<'a>(&self, prefix: &'a str) -> Option<&'a str> {
+        prefix
+            .split_once(NAMESPACE_SEPARATOR)
+            .map(|(namespace, _)| namespace)
+            .filter(|namespace| self.has_namespace(namespace))
+    }
+
+    /// Returns the interval at which the segment limiters update their target rates.
+    pub fn update_interval(&self) -> Duration {
+        self.update_interval
+    }
+
+    /// Builds a new segment for the segment keyed by `key`.
+    pub fn build_segment(&self, key: &str, dynamic: bool) -> Segment {
+        let mut segment = Segment::new(self.build(key), dynamic);
+        let config = self.config(key);
+        segment.set_caller_max_tps(config.caller_max_tps);
+        segment.enforcement = config.enforcement();
+        segment
+    }
+
+    /// Builds a new rate limiter for `segment`.
+    pub fn build(&self, segment: &str) -> RateLimiter<f32> {
+        let config = self.config(segment);
+        let mut rate_limiter = RateLimiterBuilder::new(config.target_tps);
+        if let Some(min_tps) = config.min_tps {
+            rate_limiter = rate_limiter.min_rate(min_tps);
+        }
+        if let Some(max_tps) = config.max_tps {
+            rate_limiter = rate_limiter.max_rate(max_tps);
+        }
+        if let Some(clock) = &self.clock {
+            rate_limiter = rate_limiter.clock(clock.clone());
+        }
+        let mut pid_controller = self.pid_controller.clone();
+        // The template's setpoint is the default segment's target
+        pid_controller.set_setpoint(config.target_tps);
+        rate_limiter
+            .pid_controller(pid_controller)
+            .update_interval(self.update_interval)
+            .build()
+    }
+}
+
+impl SentinelService {
+    /// Returns the current time on the clock of the segment limiters, which segment activity and
+    /// temporary overrides are timed by.
+    pub fn now(&self) -> Timestamp {
+        self.segment_factory.now()
+    }
+
+    /// Removes dynamic segments that have had no requests and no peer traffic for `idle_timeout`.
+    ///
+    /// Returns the names of the evicted segments.
+    pub async fn evict_idle_segments(&self, now: Timestamp, idle_timeout: Duration) -> Vec<String> {
+        let mut evicted = Vec::new();
+        self.segments.write().await.retain(|segment_name, segment| {
+            if segment.is_idle(now, idle_timeout) {
+                evicted.push(segment_name.clone());
+                return false;
+            }
+            true
+        });
+        evicted
+    }
+}
+
+/// Periodically evicts idle dynamic segments to bound memory with high-cardinality segment keys.
+pub async fn run_eviction_loop(service: SentinelService, idle_timeout: Duration) {
+    let mut ticker = tokio::time::interval(idle_timeout / 2);
+    loop {
+        ticker.tick().await;
+        for segment in service
+            .evict_idle_segments(service.now(), idle_timeout)
+            .await
+        {
+            tracing::info!(%segment, "evicted idle segment");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nenya::clock::ManualClock;
+    use nenya::pid_controller::PIDControllerBuilder;
+
+    #[test]
+    fn test_unknown_segments_use_default_config() {
+        let factory = SegmentFactory::new(
+            SegmentConfig {
+                target_tps: 100.0,
+                min_tps: None,
+                max_tps: None,
+                ..Default::default()
+            },
+            HashMap::from([(
+                "checkout".to_string(),
+                SegmentConfig {
+                    target_tps: 50.0,
+                    min_tps: Some(25.0),
+                    max_tps: None,
+                    ..Default::default()
+                },
+            )]),
+            PIDController::new_static_controller(100.0),
+            Duration::from_secs(1),
+        );
+
+        assert_eq!(factory.build("checkout").target_rate(), 50.0);
+        assert_eq!(factory.build("search").target_rate(), 100.0);
+        assert_eq!(factory.config("search").min_tps, None);
+    }
+
+    #[test]
+    fn test_segments_control_towards_their_own_target() {
+        let config = |target_tps| SegmentConfig {
+            target_tps,
+            ..Default::default()
+        };
+        let factory = SegmentFactory::new(
+            config(100.0),
+            HashMap::from([("checkout".to_string(), config(50.0))]),
+            PIDControllerBuilder::new(1.0).kp(0.2).ki(0.05).build(),
+            Duration::from_secs(1),
+        );
+
+        assert_eq!(factory.build("checkout").setpoint(), 50.0);
+        assert_eq!(factory.build("search").setpoint(), 100.0);
+    }
+
+    #[test]
+    fn test_most_specific_pattern_wins() {
+        let config = |target_tps| SegmentConfig {
+            target_tps,
+            ..Default::default()
+        };
+        let factory = SegmentFactory::new(
+            config(100.0),
+            HashMap::from([
+                ("api.*".to_string(), config(50.0)),
+                ("api.users.*".to_string(), config(20.0)),
+                ("api.users.admin".to_string(), config(5.0)),
+                ("payments/*".to_string(), config(10.0)),
+            ]),
+            PIDController::new_static_controller(100.0),
+            Duration::from_secs(1),
+        )
+        .with_namespaces(HashMap::from([("payments".to_string(), config(30.0))]));
+
+        assert_eq!(factory.config("api.users.admin").target_tps, 5.0);
+        assert_eq!(factory.config("api.users.list").target_tps, 20.0);
+        assert_eq!(factory.config("api.orders").target_tps, 50.0);
+        assert_eq!(factory.config("web").target_tps, 100.0);
+        assert_eq!(factory.config("payments/checkout").target_tps, 10.0);
+        assert_eq!(factory.configured_segments().count(), 1);
+
+        let origin = |key| factory.resolve_config(key).0;
+        assert_eq!(origin("api.users.admin"), ConfigOrigin::Segment);
+        assert_eq!(
+            origin("api.users.list"),
+            ConfigOrigin::Pattern("api.users.")
+        );
+        assert_eq!(origin("payments/"), ConfigOrigin::Pattern("payments/"));
+        assert_eq!(origin("web"), ConfigOrigin::Default);
+    }
+
+    #[test]
+    fn test_namespaces_isolate_segments() {
+        let config = |target_tps| SegmentConfig {
+            target_tps,
+            min_tps: None,
+            max_tps: None,
+            ..Default::default()
+        };
+        let factory = SegmentFactory::new(
+            config(100.0),
+            HashMap::from([("payments/checkout".to_string(), config(5.0))]),
+            PIDController::new_static_controller(100.0),
+            Duration::from_secs(1),
+        )
+        .with_namespaces(HashMap::from([("payments".to_string(), config(20.0))]));
+
+        let key = factory.segment_key(Some("payments"), "checkout").unwrap();
+        assert_eq!(key, "payments/checkout");
+        assert_eq!(factory.namespace_of(&key), Some("payments"));
+        assert_eq!(factory.segment_name(&key), "checkout");
+        assert_eq!(factory.build(&key).target_rate(), 5.0);
+        assert_eq!(factory.build("payments/search").target_rate(), 20.0);
+        assert_eq!(factory.build("checkout").target_rate(), 100.0);
+
+        assert_eq!(factory.segment_key(None, "checkout").unwrap(), "checkout");
+        assert_eq!(factory.segment_key(Some(""), "a/b").unwrap(), "a/b");
+        assert_eq!(factory.segment_key(None, "payments/checkout"), None);
+        assert_eq!(factory.segment_key(Some("search"), "checkout"), None);
+    }
+
+    #[test]
+    fn test_only_dynamic_segments_go_idle() {
+        let timeout = Duration::from_secs(60);
+        let rate_limiter = || RateLimiterBuilder::new(10.0).build();
+        let configured = Segment::new(rate_limiter(), false);
+        let mut dynamic = Segment::new(rate_limiter(), true);
+        let now = dynamic.rate_limiter.clock().now();
+
+        assert!(!dynamic.is_idle(now, timeout));
+        assert!(dynamic.is_idle(now + timeout, timeout));
+        assert!(!configured.is_idle(now + timeout, timeout));
+
+        dynamic.touch(now + timeout);
+        assert!(!dynamic.is_idle(now + timeout, timeout));
+    }
+
+    #[test]
+    fn test_target_override_expires() {
+        let mut segment = Segment::new(RateLimiterBuilder::new(10.0).build(), false);
+        let now = segment.rate_limiter.clock().now();
+        segment.set_share(Some(4.0));
+        segment.set_target_override(Some(TargetOverride {
+            target_tps: 20.0,
+            expires_at: now + Duration::from_secs(60),
+        }));
+        assert_eq!(segment.rate_limiter.target_rate(), 20.0);
+
+        assert!(!segment.expire_target_override(now));
+        assert!(segment.expire_target_override(now + Duration::from_secs(60)));
+        assert_eq!(segment.rate_limiter.target_rate(), 4.0);
+        segment.set_share(None);
+        assert_eq!(segment.rate_limiter.target_rate(), 10.0);
+    }
+
+    #[tokio::test]
+    async fn test_evicts_idle_dynamic_segments() {
+        let clock = Arc::new(ManualClock::new());
+        let service = SentinelService::new(
+            "local".to_string(),
+            HashMap::from([("checkout".to_string(), SegmentConfig::default())]),
+            SegmentConfig::default(),
+            PIDController::new_static_controller(10.0),
+            Duration::from_secs(1),
+        )
+        .with_clock(clock.clone());
+        service.check_segment("search", None, 1.0).await;
+
+        let timeout = Duration::from_secs(60);
+        clock.advance(timeout - Duration::from_secs(1));
+        assert!(service
+            .evict_idle_segments(service.now(), timeout)
+            .await
+            .is_empty());
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(
+            service.evict_idle_segments(service.now(), timeout).await,
+            vec!["search"]
+        );
+        assert!(service.segments.read().await.contains_key("checkout"));
+    }
+}
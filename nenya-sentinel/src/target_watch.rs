@@ -0,0 +1,174 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::Status;
+
+use crate::sentinel::{Enforcement, TargetUpdate};
+use crate::SentinelService;
+
+/// Largest number of segments a single `WatchTargets` subscription may watch.
+pub const MAX_WATCHED_SEGMENTS: usize = 1000;
+
+/// Stream of target updates sent to a `WatchTargets` subscriber.
+pub type TargetStream = ReceiverStream<Result<TargetUpdate, Status>>;
+
+/// Number of `WatchTargets` subscribers of each segment on this node.
+#[derive(Debug, Default)]
+pub struct Subscribers {
+    counts: Mutex<HashMap<String, usize>>,
+}
+
+impl Subscribers {
+    fn subscribe(self: &Arc<Self>, segments: &[String]) -> Subscription {
+        let mut counts = self.counts.lock().unwrap();
+        for segment in segments {
+            *counts.entry(segment.clone()).or_default() += 1;
+        }
+        Subscription {
+            subscribers: self.clone(),
+            segments: segments.to_vec(),
+        }
+    }
+
+    fn count(&self, segment: &str) -> usize {
+        self.counts
+            .lock()
+            .unwrap()
+            .get(segment)
+            .copied()
+            .unwrap_or_default()
+    }
+}
+
+/// Subscription to a set of segments, cancelled when dropped.
+struct Subscription {
+    subscribers: Arc<Subscribers>,
+    segments: Vec<String>,
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        let mut counts = self.subscribers.counts.lock().unwrap();
+        for segment in &self.segments {
+            if let Some(count) = counts.get_mut(segment) {
+                *count -= 1;
+                if *count == 0 {
+                    counts.remove(segment);
+                }
+            }
+        }
+    }
+}
+
+impl SentinelService {
+    /// Streams the target rates of the segments keyed by `segments`, creating the segments if
+    /// needed. Updates name segments without their namespace.
+    ///
+    /// Each segment's target rate is split evenly between the node's subscribers of the segment,
+    /// and an update is sent whenever a subscriber's share changes. Paused segments and segments
+    /// in observe mode have an infinite share. Watched segments are kept alive while the stream
+    /// is open.
+    pub fn watch_targets(&self, mut segments: Vec<String>) -> TargetStream {
+        segments.sort();
+        segments.dedup();
+        let (sender, receiver) = mpsc::channel(segments.len());
+        tokio::spawn(self.clone().push_targets(segments, sender));
+        ReceiverStream::new(receiver)
+    }
+
+    async fn push_targets(
+        self,
+        segments: Vec<String>,
+        sender: mpsc::Sender<Result<TargetUpdate, Status>>,
+    ) {
+        let _subscription = self.subscribers.subscribe(&segments);
+        tracing::debug!(segments = segments.len(), "target watch started");
+        let mut sent = HashMap::new();
+        let mut ticker = tokio::time::interval(self.segment_factory.update_interval());
+        loop {
+            tokio::select! {
+                _ = ticker.tick() => {}
+                _ = sender.closed() => break,
+            }
+            for update in self.target_updates(&segments, &mut sent).await {
+                if sender.send(Ok(update)).await.is_err() {
+                    break;
+                }
+            }
+        }
+        tracing::debug!(segments = segments.len(), "target watch ended");
+    }
+
+    /// Returns updates for the watched segments whose share changed since it was last sent.
+    async fn target_updates(
+        &self,
+        segments: &[String],
+        sent: &mut HashMap<String, f32>,
+    ) -> Vec<TargetUpdate> {
+        let now = self.now();
+        let mut segment_states = self.segments.write().await;
+        let mut updates = Vec::new();
+        for segment in segments {
+            let key = self.admitted_segment(&segment_states, segment);
+            let state = self.get_or_create_segment(&mut segment_states, &key);
+            state.touch(now);
+            let target_tps = if state.paused || state.enforcement == Enforcement::Observe {
+                f32::INFINITY
+            } else {
+                let subscribers = self.subscribers.count(segment).max(1);
+                state.rate_limiter.target_rate() / subscribers as f32
+            };
+            if sent.insert(segment.clone(), target_tps) != Some(target_tps) {
+                updates.push(TargetUpdate {
+                    segment: self.segment_factory.segment_name(segment).to_string(),
+                    target_tps,
+                    node: self.hostname.clone(),
+                });
+            }
+        }
+        updates
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sentinel::SegmentConfig;
+    use nenya::pid_controller::PIDController;
+    use std::time::Duration;
+    use tokio_stream::StreamExt;
+
+    async fn next_target(stream: &mut TargetStream) -> f32 {
+        stream.next().await.unwrap().unwrap().target_tps
+    }
+
+    #[tokio::test]
+    async fn test_targets_are_shared_between_subscribers() {
+        let service = SentinelService::new(
+            "local".to_string(),
+            HashMap::new(),
+            SegmentConfig {
+                target_tps: 10.0,
+                min_tps: None,
+                max_tps: None,
+                ..Default::default()
+            },
+            PIDController::new_static_controller(10.0),
+            Duration::from_millis(10),
+        );
+        let segments = vec!["checkout".to_string()];
+
+        let mut first = service.watch_targets(segments.clone());
+        assert_eq!(next_target(&mut first).await, 10.0);
+
+        let mut second = service.watch_targets(segments);
+        assert_eq!(next_target(&mut second).await, 5.0);
+        assert_eq!(next_target(&mut first).await, 5.0);
+
+        drop(second);
+        assert_eq!(next_target(&mut first).await, 10.0);
+        assert!(service.segments.read().await.contains_key("checkout"));
+    }
+}
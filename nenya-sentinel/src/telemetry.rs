@@ -0,0 +1,134 @@
+use tonic::metadata::MetadataMap;
+use tracing::{Level, Span};
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::EnvFilter;
+
+use crate::config::TelemetryConfig;
+
+/// Installs the global tracing subscriber.
+///
+/// Events are always logged to stdout at `level`, which the directives in `RUST_LOG` refine, e.g.
+/// `RUST_LOG=nenya_sentinel::exchange=debug`. When built with the `otel` feature and an OTLP
+/// endpoint is configured, spans are also exported to an OpenTelemetry collector.
+pub fn init(level: Level, config: &TelemetryConfig) -> Result<(), Box<dyn std::error::Error>> {
+    let filter = EnvFilter::builder()
+        .with_default_directive(level.into())
+        .from_env_lossy();
+    let registry = tracing_subscriber::registry()
+        .with(filter)
+        .with(tracing_subscriber::fmt::layer());
+
+    #[cfg(feature = "otel")]
+    if let Some(endpoint) = &config.otlp_endpoint {
+        let tracer = otel::tracer(endpoint, &config.service_name)?;
+        registry
+            .with(tracing_opentelemetry::layer().with_tracer(tracer))
+            .init();
+        return Ok(());
+    }
+
+    registry.init();
+    if config.otlp_endpoint.is_some() && cfg!(not(feature = "otel")) {
+        tracing::warn!("telemetry.otlp_endpoint is set but the otel feature is disabled");
+    }
+    Ok(())
+}
+
+/// Flushes any spans that have not been exported yet.
+pub fn shutdown() {
+    #[cfg(feature = "otel")]
+    opentelemetry::global::shutdown_tracer_provider();
+}
+
+/// Makes the trace context propagated in `metadata`, if any, the parent of `span`.
+///
+/// Callers propagate their context with the W3C `traceparent` and `tracestate` headers, which
+/// places the sentinel's spans inside the caller's distributed trace.
+pub fn set_parent(span: &Span, metadata: &MetadataMap) {
+    #[cfg(feature = "otel")]
+    otel::set_parent(span, metadata);
+    #[cfg(not(feature = "otel"))]
+    let _ = (span, metadata);
+}
+
+#[cfg(feature = "otel")]
+mod otel {
+    use opentelemetry::propagation::{Extractor, TextMapPropagator};
+    use opentelemetry::KeyValue;
+    use opentelemetry_otlp::WithExportConfig;
+    use opentelemetry_sdk::propagation::TraceContextPropagator;
+    use opentelemetry_sdk::trace::{self, Tracer};
+    use opentelemetry_sdk::{runtime, Resource};
+    use tonic::metadata::MetadataMap;
+    use tracing::Span;
+    use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+    pub fn tracer(
+        endpoint: &str,
+        service_name: &str,
+    ) -> Result<Tracer, opentelemetry::trace::TraceError> {
+        opentelemetry_otlp::new_pipeline()
+            .tracing()
+            .with_exporter(
+                opentelemetry_otlp::new_exporter()
+                    .tonic()
+                    .with_endpoint(endpoint),
+            )
+            .with_trace_config(
+                trace::config().with_resource(Resource::new(vec![KeyValue::new(
+                    "service.name",
+                    service_name.to_string(),
+                )])),
+            )
+            .install_batch(runtime::Tokio)
+    }
+
+    pub fn set_parent(span: &Span, metadata: &MetadataMap) {
+        let context = TraceContextPropagator::new().extract(&MetadataExtractor(metadata));
+        span.set_parent(context);
+    }
+
+    /// Reads propagation headers from gRPC request metadata.
+    struct MetadataExtractor<'a>(&'a MetadataMap);
+
+    impl Extractor for MetadataExtractor<'_> {
+        fn get(&self, key: &str) -> Option<&str> {
+            self.0.get(key).and_then(|value| value.to_str().ok())
+        }
+
+        fn keys(&self) -> Vec<&str> {
+            self.0
+                .keys()
+                .filter_map(|key| match key {
+                    tonic::metadata::KeyRef::Ascii(key) => Some(key.as_str()),
+                    tonic::metadata::KeyRef::Binary(_) => None,
+                })
+                .collect()
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use opentelemetry::trace::TraceContextExt;
+
+        #[test]
+        fn test_extracts_w3c_trace_context() {
+            let mut metadata = MetadataMap::new();
+            metadata.insert(
+                "traceparent",
+                "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01"
+                    .parse()
+                    .unwrap(),
+            );
+            let context = TraceContextPropagator::new().extract(&MetadataExtractor(&metadata));
+            let span_context = context.span().span_context().clone();
+            assert!(span_context.is_remote());
+            assert_eq!(
+                span_context.trace_id().to_string(),
+                "4bf92f3577b34da6a3ce929d0e0e4736"
+            );
+        }
+    }
+}
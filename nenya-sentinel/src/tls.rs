@@ -0,0 +1,58 @@
+use tonic::transport::{Certificate, ClientTlsConfig, Identity, ServerTlsConfig};
+
+use crate::config::{ConfigError, TlsConfig};
+
+/// TLS settings for the gRPC server and for connections to peers.
+#[derive(Debug, Clone)]
+pub struct TlsSettings {
+    pub server: ServerTlsConfig,
+    pub client: ClientTlsConfig,
+}
+
+impl TlsSettings {
+    /// Reads the certificate, key, and CA bundle referenced by `config`.
+    ///
+    /// The node's certificate is used both to serve and as its client identity toward peers. When
+    /// a CA bundle is configured, the server requires clients to present a certificate signed by
+    /// it and peers' server certificates are verified against it instead of the system roots.
+    pub fn load(config: &TlsConfig) -> Result<Self, ConfigError> {
+        let cert = std::fs::read(&config.cert_path)?;
+        let key = std::fs::read(&config.key_path)?;
+        let identity = Identity::from_pem(cert, key);
+
+        let mut server = ServerTlsConfig::new().identity(identity.clone());
+        let mut client = ClientTlsConfig::new().identity(identity);
+        if let Some(ca_path) = &config.ca_path {
+            let ca = Certificate::from_pem(std::fs::read(ca_path)?);
+            server = server
+                .client_ca_root(ca.clone())
+                .client_auth_optional(!config.require_client_auth);
+            client = client.ca_certificate(ca);
+        }
+        if let Some(domain_name) = &config.domain_name {
+            client = client.domain_name(domain_name.clone());
+        }
+
+        Ok(TlsSettings { server, client })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_load_reports_missing_files() {
+        let config = TlsConfig {
+            cert_path: "does-not-exist.pem".into(),
+            key_path: "does-not-exist.key".into(),
+            ca_path: None,
+            domain_name: None,
+            require_client_auth: true,
+        };
+        assert!(matches!(
+            TlsSettings::load(&config),
+            Err(ConfigError::Io(_))
+        ));
+    }
+}
@@ -0,0 +1,303 @@
+use std::cmp::Ordering;
+use std::time::Instant;
+
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use nenya_client::proto::{Enforcement, NodeStatus, SegmentStatus};
+
+/// Column the segments are sorted by.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortKey {
+    Segment,
+    Target,
+    Measured,
+    Accepted,
+    ThrottleRatio,
+}
+
+impl SortKey {
+    pub const ALL: [SortKey; 5] = [
+        SortKey::Segment,
+        SortKey::Target,
+        SortKey::Measured,
+        SortKey::Accepted,
+        SortKey::ThrottleRatio,
+    ];
+
+    /// Returns the header of the column.
+    pub fn title(self) -> &'static str {
+        match self {
+            SortKey::Segment => "Segment",
+            SortKey::Target => "Target",
+            SortKey::Measured => "Measured",
+            SortKey::Accepted => "Accepted",
+            SortKey::ThrottleRatio => "Throttled",
+        }
+    }
+
+    fn next(self) -> SortKey {
+        let index = SortKey::ALL.iter().position(|&key| key == self).unwrap();
+        SortKey::ALL[(index + 1) % SortKey::ALL.len()]
+    }
+}
+
+/// Rates of a segment as displayed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SegmentRow {
+    /// Segment name, prefixed with its namespace outside the default namespace.
+    pub name: String,
+    pub target_tps: f32,
+    pub request_rate: f32,
+    pub accepted_request_rate: f32,
+    pub paused: bool,
+    pub observe: bool,
+    pub override_tps: Option<f32>,
+}
+
+impl SegmentRow {
+    /// Returns the fraction of the requests that were throttled.
+    pub fn throttle_ratio(&self) -> f32 {
+        if self.request_rate <= 0.0 {
+            return 0.0;
+        }
+        (1.0 - self.accepted_request_rate / self.request_rate).clamp(0.0, 1.0)
+    }
+
+    fn compare(&self, other: &SegmentRow, key: SortKey) -> Ordering {
+        let by = |value: fn(&SegmentRow) -> f32| value(self).total_cmp(&value(other));
+        match key {
+            SortKey::Segment => self.name.cmp(&other.name),
+            SortKey::Target => by(|row| row.target_tps),
+            SortKey::Measured => by(|row| row.request_rate),
+            SortKey::Accepted => by(|row| row.accepted_request_rate),
+            SortKey::ThrottleRatio => by(SegmentRow::throttle_ratio),
+        }
+    }
+}
+
+impl From<SegmentStatus> for SegmentRow {
+    fn from(status: SegmentStatus) -> Self {
+        let name = if status.namespace.is_empty() {
+            status.segment.clone()
+        } else {
+            format!("{}/{}", status.namespace, status.segment)
+        };
+        SegmentRow {
+            name,
+            target_tps: status.target_tps,
+            request_rate: status.request_rate,
+            accepted_request_rate: status.accepted_request_rate,
+            paused: status.paused,
+            observe: status.enforcement() == Enforcement::Observe,
+            override_tps: status.override_tps,
+        }
+    }
+}
+
+/// Segments and node status fetched from the sentinel in one refresh.
+#[derive(Debug, Clone, Default)]
+pub struct Snapshot {
+    pub segments: Vec<SegmentStatus>,
+    /// Status of the node, if the sentinel answered the status request.
+    pub node: Option<NodeStatus>,
+}
+
+/// State of the terminal view.
+#[derive(Debug)]
+pub struct App {
+    pub rows: Vec<SegmentRow>,
+    pub node: Option<NodeStatus>,
+    pub sort: SortKey,
+    pub descending: bool,
+    pub filter: String,
+    /// Whether keys are typed into the filter.
+    pub editing_filter: bool,
+    /// Error of the last refresh, if it failed.
+    pub error: Option<String>,
+    pub refreshed_at: Option<Instant>,
+    pub selected: usize,
+}
+
+impl Default for App {
+    fn default() -> Self {
+        App {
+            rows: Vec::new(),
+            node: None,
+            // Busiest segments first, like top
+            sort: SortKey::Measured,
+            descending: true,
+            filter: String::new(),
+            editing_filter: false,
+            error: None,
+            refreshed_at: None,
+            selected: 0,
+        }
+    }
+}
+
+impl App {
+    /// Replaces the displayed segments with those of a refresh, keeping the previous ones when
+    /// the refresh failed.
+    pub fn update(&mut self, refresh: Result<Snapshot, String>, now: Instant) {
+        match refresh {
+            Ok(snapshot) => {
+                self.rows = snapshot
+                    .segments
+                    .into_iter()
+                    .map(SegmentRow::from)
+                    .collect();
+                self.node = snapshot.node;
+                self.error = None;
+                self.refreshed_at = Some(now);
+            }
+            Err(error) => self.error = Some(error),
+        }
+        self.selected = self.selected.min(self.visible().len().saturating_sub(1));
+    }
+
+    /// Returns the segments matching the filter, in sort order.
+    pub fn visible(&self) -> Vec<&SegmentRow> {
+        let filter = self.filter.to_lowercase();
+        let mut rows: Vec<&SegmentRow> = self
+            .rows
+            .iter()
+            .filter(|row| row.name.to_lowercase().contains(&filter))
+            .collect();
+        rows.sort_by(|a, b| {
+            let ordering = a.compare(b, self.sort).then_with(|| a.name.cmp(&b.name));
+            if self.descending {
+                ordering.reverse()
+            } else {
+                ordering
+            }
+        });
+        rows
+    }
+
+    /// Sorts by `key`, or reverses the order if the segments are already sorted by it.
+    fn sort_by(&mut self, key: SortKey) {
+        if self.sort == key {
+            self.descending = !self.descending;
+        } else {
+            self.sort = key;
+            // Names read best A to Z, rates highest first
+            self.descending = key != SortKey::Segment;
+        }
+    }
+
+    /// Handles a key press, returning `false` when the view should exit.
+    pub fn handle_key(&mut self, key: KeyEvent) -> bool {
+        if key.modifiers.contains(KeyModifiers::CONTROL) && key.code == KeyCode::Char('c') {
+            return false;
+        }
+        if self.editing_filter {
+            match key.code {
+                KeyCode::Enter => self.editing_filter = false,
+                KeyCode::Esc => {
+                    self.filter.clear();
+                    self.editing_filter = false;
+                }
+                KeyCode::Backspace => {
+                    self.filter.pop();
+                }
+                KeyCode::Char(c) => self.filter.push(c),
+                _ => {}
+            }
+            self.selected = 0;
+            return true;
+        }
+        match key.code {
+            KeyCode::Char('q') => return false,
+            KeyCode::Esc if self.filter.is_empty() => return false,
+            KeyCode::Esc => self.filter.clear(),
+            KeyCode::Char('/') => self.editing_filter = true,
+            KeyCode::Char('s') => self.sort_by(self.sort.next()),
+            KeyCode::Char('r') => self.descending = !self.descending,
+            KeyCode::Char(c @ '1'..='5') => {
+                self.sort_by(SortKey::ALL[c as usize - '1' as usize]);
+            }
+            KeyCode::Up | KeyCode::Char('k') => self.selected = self.selected.saturating_sub(1),
+            KeyCode::Down | KeyCode::Char('j') => {
+                self.selected = (self.selected + 1).min(self.visible().len().saturating_sub(1));
+            }
+            _ => {}
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn status(segment: &str, namespace: &str, request_rate: f32, accepted: f32) -> SegmentStatus {
+        SegmentStatus {
+            segment: segment.to_string(),
+            namespace: namespace.to_string(),
+            target_tps: 50.0,
+            request_rate,
+            accepted_request_rate: accepted,
+            ..Default::default()
+        }
+    }
+
+    fn press(app: &mut App, code: KeyCode) -> bool {
+        app.handle_key(KeyEvent::new(code, KeyModifiers::NONE))
+    }
+
+    fn names(app: &App) -> Vec<&str> {
+        app.visible().iter().map(|row| row.name.as_str()).collect()
+    }
+
+    #[test]
+    fn test_sorts_and_filters_segments() {
+        let mut app = App::default();
+        let snapshot = Snapshot {
+            segments: vec![
+                status("checkout", "", 80.0, 40.0),
+                status("search", "", 120.0, 120.0),
+                status("checkout", "mobile", 10.0, 10.0),
+            ],
+            node: None,
+        };
+        app.update(Ok(snapshot), Instant::now());
+        assert_eq!(names(&app), ["search", "checkout", "mobile/checkout"]);
+        assert_eq!(app.rows[0].throttle_ratio(), 0.5);
+
+        press(&mut app, KeyCode::Char('5'));
+        assert_eq!(names(&app)[0], "checkout");
+        press(&mut app, KeyCode::Char('1'));
+        assert_eq!(names(&app), ["checkout", "mobile/checkout", "search"]);
+        press(&mut app, KeyCode::Char('r'));
+        assert_eq!(names(&app)[0], "search");
+
+        press(&mut app, KeyCode::Char('/'));
+        for c in "CHECK".chars() {
+            press(&mut app, KeyCode::Char(c));
+        }
+        press(&mut app, KeyCode::Enter);
+        assert_eq!(names(&app), ["mobile/checkout", "checkout"]);
+        // Keys that type into the filter act as commands again once it is confirmed
+        assert!(!press(&mut app, KeyCode::Char('q')));
+    }
+
+    #[test]
+    fn test_keeps_segments_when_a_refresh_fails() {
+        let mut app = App::default();
+        let snapshot = Snapshot {
+            segments: vec![status("checkout", "", 0.0, 0.0)],
+            node: None,
+        };
+        app.update(Ok(snapshot), Instant::now());
+        app.update(Err("unavailable".to_string()), Instant::now());
+        assert_eq!(app.rows.len(), 1);
+        assert_eq!(app.rows[0].throttle_ratio(), 0.0);
+        assert_eq!(app.error.as_deref(), Some("unavailable"));
+
+        press(&mut app, KeyCode::Char('/'));
+        press(&mut app, KeyCode::Char('x'));
+        press(&mut app, KeyCode::Esc);
+        assert!(app.filter.is_empty());
+        assert!(press(&mut app, KeyCode::Char('s')));
+        assert!(!press(&mut app, KeyCode::Esc));
+    }
+}
@@ -0,0 +1,179 @@
+use std::error::Error;
+use std::io::{self, Stdout};
+use std::time::{Duration, Instant};
+
+use clap::{Arg, ArgMatches, Command};
+use crossterm::event::{self, Event, KeyEventKind};
+use crossterm::execute;
+use crossterm::terminal::{
+    disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen,
+};
+use nenya_client::proto::sentinel_admin_client::SentinelAdminClient;
+use nenya_client::proto::{ListSegmentsRequest, NodeStatusRequest};
+use ratatui::backend::CrosstermBackend;
+use ratatui::Terminal;
+use tokio::sync::watch;
+use tonic::metadata::AsciiMetadataValue;
+use tonic::transport::{Channel, Endpoint};
+use tonic::Request;
+
+use crate::app::{App, Snapshot};
+
+mod app;
+mod ui;
+
+/// How long the view waits for a key press before redrawing.
+const INPUT_POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Builds the command line interface for the terminal view.
+fn command() -> Command {
+    Command::new("nenya-top")
+        .about("Live view of the target, measured, and accepted rates of a sentinel's segments")
+        .version(env!("CARGO_PKG_VERSION"))
+        .arg(
+            Arg::new("endpoint")
+                .default_value("http://[::1]:8080")
+                .help("Sentinel to observe"),
+        )
+        .arg(
+            Arg::new("interval")
+                .short('i')
+                .long("interval")
+                .value_parser(clap::value_parser!(u64))
+                .default_value("1000")
+                .help("Time between refreshes (milliseconds)"),
+        )
+        .arg(
+            Arg::new("namespace")
+                .short('n')
+                .long("namespace")
+                .help("Only show the segments of this namespace (defaults to every namespace)"),
+        )
+        .arg(
+            Arg::new("token")
+                .long("token")
+                .help("Bearer token sent with every request"),
+        )
+}
+
+/// Polls the sentinel's admin service for segments and node status.
+#[derive(Debug, Clone)]
+struct Poller {
+    client: SentinelAdminClient<Channel>,
+    token: Option<AsciiMetadataValue>,
+    namespace: Option<String>,
+}
+
+impl Poller {
+    fn request<T>(&self, message: T) -> Request<T> {
+        let mut request = Request::new(message);
+        if let Some(token) = &self.token {
+            request
+                .metadata_mut()
+                .insert("authorization", token.clone());
+        }
+        request
+    }
+
+    async fn poll(&mut self) -> Result<Snapshot, String> {
+        let list = ListSegmentsRequest {
+            namespace: self.namespace.clone(),
+        };
+        let segments = self
+            .client
+            .list_segments(self.request(list))
+            .await
+            .map_err(|status| status.message().to_string())?
+            .into_inner()
+            .segments;
+        // Older sentinels may not report their status, which only the header needs
+        let node = self
+            .client
+            .get_node_status(self.request(NodeStatusRequest {}))
+            .await
+            .ok()
+            .map(|response| response.into_inner());
+        Ok(Snapshot { segments, node })
+    }
+}
+
+fn poller(matches: &ArgMatches) -> Result<Poller, Box<dyn Error>> {
+    let endpoint = Endpoint::from_shared(matches.get_one::<String>("endpoint").unwrap().clone())?
+        .timeout(Duration::from_secs(5))
+        .connect_timeout(Duration::from_secs(1));
+    let token = matches
+        .get_one::<String>("token")
+        .map(|token| format!("Bearer {}", token).parse())
+        .transpose()
+        .map_err(|_| "auth tokens must be visible ASCII")?;
+    Ok(Poller {
+        client: SentinelAdminClient::new(endpoint.connect_lazy()),
+        token,
+        namespace: matches.get_one::<String>("namespace").cloned(),
+    })
+}
+
+/// Draws the view and handles key presses until the user quits.
+fn run_view(
+    terminal: &mut Terminal<CrosstermBackend<Stdout>>,
+    mut refreshes: watch::Receiver<Option<Result<Snapshot, String>>>,
+    endpoint: &str,
+) -> io::Result<()> {
+    let mut app = App::default();
+    loop {
+        if refreshes.has_changed().unwrap_or(false) {
+            if let Some(refresh) = refreshes.borrow_and_update().clone() {
+                app.update(refresh, Instant::now());
+            }
+        }
+        terminal.draw(|frame| ui::draw(frame, &app, endpoint))?;
+
+        if event::poll(INPUT_POLL_INTERVAL)? {
+            if let Event::Key(key) = event::read()? {
+                if key.kind == KeyEventKind::Press && !app.handle_key(key) {
+                    return Ok(());
+                }
+            }
+        }
+    }
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let matches = command().get_matches();
+    let interval = Duration::from_millis(*matches.get_one::<u64>("interval").unwrap());
+    let endpoint = matches.get_one::<String>("endpoint").unwrap().clone();
+
+    let runtime = tokio::runtime::Runtime::new()?;
+    let mut poller = {
+        let _guard = runtime.enter();
+        poller(&matches)?
+    };
+    let (sender, receiver) = watch::channel(None);
+    runtime.spawn(async move {
+        let mut ticks = tokio::time::interval(interval);
+        loop {
+            ticks.tick().await;
+            if sender.send(Some(poller.poll().await)).is_err() {
+                break;
+            }
+        }
+    });
+
+    // Restore the terminal even if the view panics, so the panic message is readable
+    let panic_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        let _ = disable_raw_mode();
+        let _ = execute!(io::stdout(), LeaveAlternateScreen);
+        panic_hook(info);
+    }));
+
+    enable_raw_mode()?;
+    execute!(io::stdout(), EnterAlternateScreen)?;
+    let mut terminal = Terminal::new(CrosstermBackend::new(io::stdout()))?;
+    let result = run_view(&mut terminal, receiver, &endpoint);
+    disable_raw_mode()?;
+    execute!(terminal.backend_mut(), LeaveAlternateScreen)?;
+    terminal.show_cursor()?;
+    result?;
+    Ok(())
+}
@@ -0,0 +1,175 @@
+use std::time::Instant;
+
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, Borders, Cell, Paragraph, Row, Table, TableState};
+use ratatui::Frame;
+
+use crate::app::{App, SegmentRow, SortKey};
+
+/// Throttle ratio from which a segment is highlighted.
+const HOT_THROTTLE_RATIO: f32 = 0.1;
+
+/// Draws the header with the node's status, the segment table, and the key help or filter.
+pub fn draw(frame: &mut Frame, app: &App, endpoint: &str) {
+    let [header, table, footer] = Layout::vertical([
+        Constraint::Length(1),
+        Constraint::Min(0),
+        Constraint::Length(1),
+    ])
+    .areas(frame.size());
+
+    frame.render_widget(Paragraph::new(header_line(app, endpoint)), header);
+
+    let rows = app.visible();
+    let mut state = TableState::default().with_selected(Some(app.selected));
+    frame.render_stateful_widget(segment_table(app, &rows), table, &mut state);
+
+    let footer_line = if app.editing_filter {
+        Line::from(vec![
+            Span::raw("Filter: "),
+            Span::raw(app.filter.as_str()),
+            "_".into(),
+        ])
+    } else {
+        let filter = if app.filter.is_empty() {
+            String::new()
+        } else {
+            format!("  filter: {}", app.filter)
+        };
+        Line::from(format!(
+            "q quit  / filter  s sort  r reverse  1-5 sort by column  j/k move{}",
+            filter
+        ))
+    };
+    frame.render_widget(
+        Paragraph::new(footer_line).style(Style::default().add_modifier(Modifier::DIM)),
+        footer,
+    );
+}
+
+fn header_line(app: &App, endpoint: &str) -> Line<'static> {
+    let mut spans = vec![Span::styled(
+        format!("nenya-top {}", endpoint),
+        Style::default().add_modifier(Modifier::BOLD),
+    )];
+    if let Some(node) = &app.node {
+        spans.push(Span::raw(format!(
+            "  node {}  {} segments  {:.0} rpc/s  saturation {:.0}%  lag {}ms",
+            node.node,
+            node.segments,
+            node.rpc_rate,
+            node.saturation * 100.0,
+            node.event_loop_lag_ms
+        )));
+    }
+    if let Some(refreshed_at) = app.refreshed_at {
+        spans.push(Span::raw(format!(
+            "  refreshed {:.1}s ago",
+            Instant::now().duration_since(refreshed_at).as_secs_f64()
+        )));
+    }
+    if let Some(error) = &app.error {
+        spans.push(Span::styled(
+            format!("  error: {}", error),
+            Style::default().fg(Color::Red),
+        ));
+    }
+    Line::from(spans)
+}
+
+fn segment_table<'a>(app: &App, rows: &[&'a SegmentRow]) -> Table<'a> {
+    let titles = SortKey::ALL
+        .iter()
+        .map(|&key| match (key == app.sort, app.descending) {
+            (true, true) => format!("{} ▼", key.title()),
+            (true, false) => format!("{} ▲", key.title()),
+            (false, _) => key.title().to_string(),
+        });
+    let header = Row::new(titles.chain(std::iter::once("Status".to_string())))
+        .style(Style::default().add_modifier(Modifier::BOLD | Modifier::REVERSED));
+
+    let rows = rows.iter().map(|row| {
+        let mut status = Vec::new();
+        if row.paused {
+            status.push("paused".to_string());
+        }
+        if row.observe {
+            status.push("observe".to_string());
+        }
+        if let Some(override_tps) = row.override_tps {
+            status.push(format!("override {:.1}", override_tps));
+        }
+        let style = if row.throttle_ratio() >= HOT_THROTTLE_RATIO {
+            Style::default().fg(Color::Yellow)
+        } else {
+            Style::default()
+        };
+        Row::new(vec![
+            Cell::from(row.name.clone()),
+            Cell::from(format!("{:.1}", row.target_tps)),
+            Cell::from(format!("{:.1}", row.request_rate)),
+            Cell::from(format!("{:.1}", row.accepted_request_rate)),
+            Cell::from(format!("{:.1}%", row.throttle_ratio() * 100.0)),
+            Cell::from(status.join(", ")),
+        ])
+        .style(style)
+    });
+
+    Table::new(
+        rows,
+        [
+            Constraint::Min(24),
+            Constraint::Length(12),
+            Constraint::Length(12),
+            Constraint::Length(12),
+            Constraint::Length(12),
+            Constraint::Min(10),
+        ],
+    )
+    .header(header)
+    .block(Block::default().borders(Borders::TOP))
+    .highlight_style(Style::default().add_modifier(Modifier::REVERSED))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::app::Snapshot;
+    use nenya_client::proto::SegmentStatus;
+    use ratatui::backend::TestBackend;
+    use ratatui::Terminal;
+
+    #[test]
+    fn test_draws_segments_with_their_throttle_ratio() {
+        let mut app = App::default();
+        let segments = vec![SegmentStatus {
+            segment: "checkout".to_string(),
+            target_tps: 50.0,
+            request_rate: 80.0,
+            accepted_request_rate: 60.0,
+            paused: true,
+            ..Default::default()
+        }];
+        app.update(
+            Ok(Snapshot {
+                segments,
+                node: None,
+            }),
+            Instant::now(),
+        );
+
+        let mut terminal = Terminal::new(TestBackend::new(100, 6)).unwrap();
+        terminal
+            .draw(|frame| draw(frame, &app, "http://sentinel:8080"))
+            .unwrap();
+        let buffer = terminal.backend().buffer();
+        let screen: String = buffer.content.iter().map(|cell| cell.symbol()).collect();
+        assert!(screen.contains("nenya-top http://sentinel:8080"));
+        assert!(screen.contains("Measured ▼"));
+        assert!(screen.contains("checkout"));
+        assert!(screen.contains("25.0%"));
+        assert!(screen.contains("paused"));
+    }
+}
@@ -0,0 +1,204 @@
+use std::error::Error;
+use std::time::Duration;
+
+use clap::{Arg, ArgMatches, Command};
+
+use nenya::analysis::AnalyzerBuilder;
+use nenya::recording::Recording;
+use nenya::tuning::{CostWeights, Evaluation, GainTunerBuilder};
+
+/// Builds the command line interface for the tuner binary.
+fn command() -> Command {
+    let gains = |name: &'static str, default: &'static str, help: &'static str| {
+        Arg::new(name)
+            .long(name)
+            .value_parser(clap::value_parser!(f64))
+            .num_args(1..)
+            .use_value_delimiter(true)
+            .default_value(default)
+            .help(help)
+    };
+    let weight = |name: &'static str, help: &'static str| {
+        Arg::new(name)
+            .long(name)
+            .value_parser(clap::value_parser!(f64))
+            .default_value("1.0")
+            .help(help)
+    };
+    Command::new("nenya-tune")
+        .about("Searches PID gains minimizing overshoot, settling time, and needless throttling on a traffic trace")
+        .version(env!("CARGO_PKG_VERSION"))
+        .arg(
+            Arg::new("trace")
+                .required(true)
+                .help("Recorded requests with a timestamp in seconds and optionally their outcome and target rate, as JSON Lines if the file ends in .jsonl and CSV with a header otherwise"),
+        )
+        .arg(
+            Arg::new("speed")
+                .long("speed")
+                .value_parser(clap::value_parser!(f64))
+                .default_value("1.0")
+                .help("Replays the trace this many times faster than recorded"),
+        )
+        .arg(
+            Arg::new("target_tps")
+                .short('t')
+                .long("target_tps")
+                .value_parser(clap::value_parser!(f64))
+                .required(true)
+                .help("Target TPS for the rate limiter"),
+        )
+        .arg(
+            Arg::new("min_tps")
+                .short('m')
+                .long("min_tps")
+                .value_parser(clap::value_parser!(f64))
+                .help("Lower bound of TPS for the rate limiter (defaults to the target)"),
+        )
+        .arg(
+            Arg::new("max_tps")
+                .short('x')
+                .long("max_tps")
+                .value_parser(clap::value_parser!(f64))
+                .help("Upper bound of TPS for the rate limiter (defaults to the target)"),
+        )
+        .arg(
+            Arg::new("update_interval")
+                .long("update_interval")
+                .value_parser(clap::value_parser!(u64))
+                .default_value("1000")
+                .help("Update interval for the PID controller (milliseconds)"),
+        )
+        .arg(
+            Arg::new("tick")
+                .long("tick")
+                .value_parser(clap::value_parser!(u64))
+                .default_value("100")
+                .help("Time between the metrics runs are scored on (milliseconds)"),
+        )
+        .arg(gains(
+            "kp",
+            "0,0.05,0.1,0.2,0.5,1",
+            "Proportional gains to try",
+        ))
+        .arg(gains("ki", "0,0.01,0.05,0.1,0.2", "Integral gains to try"))
+        .arg(gains("kd", "0,0.01,0.05", "Derivative gains to try"))
+        .arg(
+            Arg::new("tolerance")
+                .long("tolerance")
+                .value_parser(clap::value_parser!(f64))
+                .default_value("0.05")
+                .help("Distance from the ideal accepted rate counted as settled, as a fraction of the target"),
+        )
+        .arg(weight("overshoot_weight", "Weight of the overshoot in the cost"))
+        .arg(weight("settling_weight", "Weight of the settling time in the cost"))
+        .arg(weight(
+            "throttling_weight",
+            "Weight of the throttling of under-target traffic in the cost",
+        ))
+        .arg(
+            Arg::new("top")
+                .short('n')
+                .long("top")
+                .value_parser(clap::value_parser!(usize))
+                .default_value("10")
+                .help("Number of gain sets to report"),
+        )
+}
+
+/// Prints the control quality of the decisions recorded in production, the baseline the gain
+/// sets compete with.
+fn print_recorded_quality(recording: &Recording, target_tps: f64) {
+    // Recorded rates are counts per tick, so use whole seconds to keep them from being noisy
+    let quality = AnalyzerBuilder::new(target_tps)
+        .build()
+        .analyze(&recording.report(Duration::from_secs(1)));
+    println!(
+        "Recorded: {} of {} requests throttled, overshoot {:.3}, settled after {}, steady-state error {:.2}",
+        recording.throttled(),
+        recording.len(),
+        quality.max_overshoot,
+        quality
+            .settling_time
+            .map_or("never".to_string(), |settling_time| format!(
+                "{:.1}s",
+                settling_time.as_secs_f64()
+            )),
+        quality.steady_state_error,
+    );
+}
+
+fn values(matches: &ArgMatches, name: &str) -> Vec<f64> {
+    matches.get_many::<f64>(name).unwrap().copied().collect()
+}
+
+fn print_evaluation(rank: usize, evaluation: &Evaluation) {
+    println!(
+        "{:>4} {:>8} {:>8} {:>8} {:>8.4} {:>10.3} {:>12.1} {:>11.4}",
+        rank,
+        evaluation.gains.kp,
+        evaluation.gains.ki,
+        evaluation.gains.kd,
+        evaluation.cost,
+        evaluation.overshoot,
+        evaluation.settling_time.as_secs_f64(),
+        evaluation.under_target_throttle_ratio,
+    );
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let matches = command().get_matches();
+    let recording = Recording::load(matches.get_one::<String>("trace").unwrap())?;
+    if recording.is_empty() {
+        return Err("the trace holds no requests".into());
+    }
+    let arrivals = recording
+        .replay()
+        .speed(*matches.get_one::<f64>("speed").unwrap())
+        .collect();
+
+    let target_tps = *matches.get_one::<f64>("target_tps").unwrap();
+    let mut tuner = GainTunerBuilder::new(target_tps, arrivals)
+        .update_interval(Duration::from_millis(
+            *matches.get_one::<u64>("update_interval").unwrap(),
+        ))
+        .tick(Duration::from_millis(
+            *matches.get_one::<u64>("tick").unwrap(),
+        ))
+        .tolerance(*matches.get_one::<f64>("tolerance").unwrap())
+        .weights(CostWeights {
+            overshoot: *matches.get_one::<f64>("overshoot_weight").unwrap(),
+            settling_time: *matches.get_one::<f64>("settling_weight").unwrap(),
+            under_target_throttling: *matches.get_one::<f64>("throttling_weight").unwrap(),
+        });
+    if let Some(&min_tps) = matches.get_one::<f64>("min_tps") {
+        tuner = tuner.min_rate(min_tps);
+    }
+    if let Some(&max_tps) = matches.get_one::<f64>("max_tps") {
+        tuner = tuner.max_rate(max_tps);
+    }
+    let tuner = tuner.build();
+
+    let evaluations = tuner.search(
+        &values(&matches, "kp"),
+        &values(&matches, "ki"),
+        &values(&matches, "kd"),
+    );
+    if recording.has_outcomes() {
+        print_recorded_quality(&recording, target_tps);
+    }
+    println!(
+        "Evaluated {} gain sets over {:.1}s of traffic",
+        evaluations.len(),
+        tuner.duration().as_secs_f64()
+    );
+    println!(
+        "{:>4} {:>8} {:>8} {:>8} {:>8} {:>10} {:>12} {:>11}",
+        "rank", "kp", "ki", "kd", "cost", "overshoot", "settling (s)", "throttled"
+    );
+    let top = *matches.get_one::<usize>("top").unwrap();
+    for (index, evaluation) in evaluations.iter().take(top).enumerate() {
+        print_evaluation(index + 1, evaluation);
+    }
+    Ok(())
+}
@@ -0,0 +1,181 @@
+//! Latency and throughput of throttling decisions in the hot path.
+//!
+//! Every limiter admits 5000 requests per second of traffic offered at 10000 per second on a
+//! manual clock, so about half the decisions throttle and the windows hold a steady number of
+//! requests, independent of how fast the machine is.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+
+use nenya::bench;
+use nenya::clock::ManualClock;
+use nenya::pid_controller::PIDControllerBuilder;
+use nenya::shared::SharedRateLimiter;
+use nenya::token_bucket::{TokenBucket, TokenBucketBuilder};
+use nenya::{RateLimiter, RateLimiterBuilder};
+
+const TARGET_RATE: f64 = 5000.0;
+
+/// Time between offered requests.
+const INTERARRIVAL: Duration = Duration::from_micros(100);
+
+const CONTENDED_DECISIONS_PER_THREAD: u64 = 10_000;
+
+fn sliding_window(clock: &ManualClock) -> RateLimiter<f64> {
+    RateLimiterBuilder::new(TARGET_RATE)
+        .min_rate(TARGET_RATE / 2.0)
+        .max_rate(TARGET_RATE * 2.0)
+        .pid_controller(
+            PIDControllerBuilder::new(TARGET_RATE)
+                .kp(0.1)
+                .ki(0.01)
+                .build(),
+        )
+        .clock(Arc::new(clock.clone()))
+        .build()
+}
+
+fn bucketed_window(clock: &ManualClock) -> RateLimiter<f64> {
+    RateLimiterBuilder::new(TARGET_RATE)
+        .min_rate(TARGET_RATE / 2.0)
+        .max_rate(TARGET_RATE * 2.0)
+        .pid_controller(
+            PIDControllerBuilder::new(TARGET_RATE)
+                .kp(0.1)
+                .ki(0.01)
+                .build(),
+        )
+        .window_quantum(Duration::from_millis(100))
+        .clock(Arc::new(clock.clone()))
+        .build()
+}
+
+fn token_bucket(clock: &ManualClock) -> TokenBucket {
+    TokenBucketBuilder::new(TARGET_RATE)
+        .clock(Arc::new(clock.clone()))
+        .build()
+}
+
+fn single_threaded(c: &mut Criterion) {
+    let mut group = c.benchmark_group("should_throttle");
+    group.throughput(Throughput::Elements(1));
+
+    let clock = ManualClock::new();
+    let mut limiter = sliding_window(&clock);
+    group.bench_function("sliding_window", |b| {
+        b.iter(|| {
+            clock.advance(INTERARRIVAL);
+            limiter.should_throttle()
+        })
+    });
+
+    let clock = ManualClock::new();
+    let mut limiter = bucketed_window(&clock);
+    group.bench_function("bucketed_window", |b| {
+        b.iter(|| {
+            clock.advance(INTERARRIVAL);
+            limiter.should_throttle()
+        })
+    });
+
+    let clock = ManualClock::new();
+    let mut bucket = token_bucket(&clock);
+    group.bench_function("token_bucket", |b| {
+        b.iter(|| {
+            clock.advance(INTERARRIVAL);
+            bucket.should_throttle()
+        })
+    });
+
+    group.finish();
+}
+
+fn contended(c: &mut Criterion) {
+    let mut group = c.benchmark_group("should_throttle_contended");
+    for threads in [2, 4, 8] {
+        group.throughput(Throughput::Elements(
+            CONTENDED_DECISIONS_PER_THREAD * threads as u64,
+        ));
+
+        // Every decision advances the shared clock, so the offered rate stays the same however
+        // many threads share the limiter
+        group.bench_with_input(
+            BenchmarkId::new("sliding_window", threads),
+            &threads,
+            |b, &threads| {
+                b.iter_custom(|iterations| {
+                    (0..iterations)
+                        .map(|_| {
+                            let clock = ManualClock::new();
+                            let limiter = sliding_window(&clock);
+                            bench::contended(
+                                limiter,
+                                threads,
+                                CONTENDED_DECISIONS_PER_THREAD,
+                                |limiter| {
+                                    clock.advance(INTERARRIVAL);
+                                    limiter.should_throttle()
+                                },
+                            )
+                            .elapsed
+                        })
+                        .sum()
+                })
+            },
+        );
+        group.bench_with_input(
+            BenchmarkId::new("shared", threads),
+            &threads,
+            |b, &threads| {
+                b.iter_custom(|iterations| {
+                    (0..iterations)
+                        .map(|_| {
+                            let clock = ManualClock::new();
+                            let limiter = SharedRateLimiter::new(sliding_window(&clock));
+                            bench::concurrent(
+                                &limiter,
+                                threads,
+                                CONTENDED_DECISIONS_PER_THREAD,
+                                |limiter| {
+                                    clock.advance(INTERARRIVAL);
+                                    limiter.should_throttle()
+                                },
+                            )
+                            .elapsed
+                        })
+                        .sum()
+                })
+            },
+        );
+        group.bench_with_input(
+            BenchmarkId::new("token_bucket", threads),
+            &threads,
+            |b, &threads| {
+                b.iter_custom(|iterations| {
+                    (0..iterations)
+                        .map(|_| {
+                            let clock = ManualClock::new();
+                            let bucket = token_bucket(&clock);
+                            bench::contended(
+                                bucket,
+                                threads,
+                                CONTENDED_DECISIONS_PER_THREAD,
+                                |bucket| {
+                                    clock.advance(INTERARRIVAL);
+                                    bucket.should_throttle()
+                                },
+                            )
+                            .elapsed
+                        })
+                        .sum()
+                })
+            },
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, single_threaded, contended);
+criterion_main!(benches);
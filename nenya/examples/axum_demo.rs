@@ -0,0 +1,231 @@
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use axum::http::header::RETRY_AFTER;
+use axum::http::{HeaderName, StatusCode};
+use axum::routing::get;
+use axum::Router;
+use clap::{Arg, Command};
+use hyper::client::HttpConnector;
+use hyper::{Body, Client, Request};
+use prometheus::{Encoder, Registry, TextEncoder};
+
+use nenya::axum::KeyedRateLimitLayer;
+use nenya::key::Header;
+use nenya::keyed::KeyedRateLimiter;
+use nenya::prometheus::LimiterCollector;
+
+const API_KEY: HeaderName = HeaderName::from_static("x-api-key");
+
+/// Client of the demo service sending requests with its own API key.
+#[derive(Debug, Clone, Copy)]
+struct DemoClient {
+    api_key: &'static str,
+    rate: f64,
+    /// Whether the client waits for the `Retry-After` of throttled requests before sending again.
+    backs_off: bool,
+}
+
+const CLIENTS: [DemoClient; 3] = [
+    DemoClient {
+        api_key: "noisy",
+        rate: 60.0,
+        backs_off: false,
+    },
+    DemoClient {
+        api_key: "polite",
+        rate: 30.0,
+        backs_off: true,
+    },
+    DemoClient {
+        api_key: "quiet",
+        rate: 5.0,
+        backs_off: false,
+    },
+];
+
+/// Responses one client received.
+#[derive(Debug, Default)]
+struct ClientReport {
+    accepted: u64,
+    throttled: u64,
+    /// Time spent waiting for `Retry-After`.
+    backed_off: Duration,
+    /// `RateLimit-*` and `Retry-After` headers of the last throttled response.
+    last_throttled: BTreeMap<String, String>,
+}
+
+/// Sends requests to `addr` at the client's rate until `duration` has passed.
+async fn generate(
+    http: Client<HttpConnector>,
+    addr: SocketAddr,
+    client: DemoClient,
+    duration: Duration,
+) -> ClientReport {
+    let mut report = ClientReport::default();
+    let mut ticks = tokio::time::interval(Duration::from_secs_f64(1.0 / client.rate));
+    ticks.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    let deadline = Instant::now() + duration;
+    while Instant::now() < deadline {
+        ticks.tick().await;
+        let request = Request::get(format!("http://{}/orders", addr))
+            .header(API_KEY, client.api_key)
+            .body(Body::empty())
+            .unwrap();
+        let response = match http.request(request).await {
+            Ok(response) => response,
+            Err(err) => {
+                eprintln!("{}: request failed: {}", client.api_key, err);
+                continue;
+            }
+        };
+        if response.status() != StatusCode::TOO_MANY_REQUESTS {
+            report.accepted += 1;
+            continue;
+        }
+
+        report.throttled += 1;
+        report.last_throttled = response
+            .headers()
+            .iter()
+            .filter(|(name, _)| name.as_str().starts_with("ratelimit-") || *name == RETRY_AFTER)
+            .map(|(name, value)| {
+                let value = value.to_str().unwrap_or_default().to_string();
+                (name.to_string(), value)
+            })
+            .collect();
+        if client.backs_off {
+            let retry_after = response
+                .headers()
+                .get(RETRY_AFTER)
+                .and_then(|value| value.to_str().ok()?.parse().ok())
+                .map_or(Duration::from_secs(1), Duration::from_secs);
+            let wait = retry_after.min(deadline.saturating_duration_since(Instant::now()));
+            tokio::time::sleep(wait).await;
+            report.backed_off += wait;
+            ticks.reset();
+        }
+    }
+    report
+}
+
+#[tokio::main]
+async fn main() {
+    let matches = Command::new("Axum Rate Limiting Demo")
+        .about(
+            "Serves an axum app limiting each API key, drives it with local clients, and \
+             prints their responses and the Prometheus metrics of the limiter",
+        )
+        .arg(
+            Arg::new("port")
+                .short('p')
+                .long("port")
+                .value_parser(clap::value_parser!(u16))
+                .default_value("3000")
+                .help("Port to serve the app on"),
+        )
+        .arg(
+            Arg::new("key_rate")
+                .short('k')
+                .long("key_rate")
+                .value_parser(clap::value_parser!(f64))
+                .default_value("20.0")
+                .help("Requests per second each API key is limited to"),
+        )
+        .arg(
+            Arg::new("duration")
+                .short('d')
+                .long("duration")
+                .value_parser(clap::value_parser!(u64))
+                .default_value("10")
+                .help("Time the clients send requests for (seconds)"),
+        )
+        .arg(
+            Arg::new("serve")
+                .long("serve")
+                .action(clap::ArgAction::SetTrue)
+                .help("Keep serving after the clients are done, to explore the app with curl"),
+        )
+        .get_matches();
+    let port = *matches.get_one::<u16>("port").unwrap();
+    let key_rate = *matches.get_one::<f64>("key_rate").unwrap();
+    let duration = Duration::from_secs(*matches.get_one::<u64>("duration").unwrap());
+
+    let limiter = Arc::new(Mutex::new(KeyedRateLimiter::new(key_rate)));
+    let collector = LimiterCollector::new();
+    collector.add_keyed_limiter("orders", limiter.clone());
+    let registry = Registry::new();
+    registry.register(Box::new(collector)).unwrap();
+
+    // Routes added after the layer, such as the metrics, are not limited
+    let app = Router::new()
+        .route("/orders", get(|| async { "[]" }))
+        .layer(KeyedRateLimitLayer::new(limiter, Header(API_KEY)))
+        .route(
+            "/metrics",
+            get(move || async move {
+                let mut buffer = Vec::new();
+                TextEncoder::new()
+                    .encode(&registry.gather(), &mut buffer)
+                    .unwrap();
+                String::from_utf8(buffer).unwrap()
+            }),
+        );
+
+    let server = axum::Server::bind(&SocketAddr::from(([127, 0, 0, 1], port)))
+        .serve(app.into_make_service());
+    let addr = server.local_addr();
+    let server = tokio::spawn(server);
+    println!(
+        "Serving http://{}/orders, limited to {} requests per second per x-api-key",
+        addr, key_rate
+    );
+
+    let http = Client::new();
+    let generators: Vec<_> = CLIENTS
+        .iter()
+        .map(|&client| tokio::spawn(generate(http.clone(), addr, client, duration)))
+        .collect();
+    println!(
+        "\n{:<8} {:>6} {:>9} {:>9} {:>10}  Last 429 headers",
+        "Client", "Rate", "Accepted", "Throttled", "Backed off"
+    );
+    for (client, generator) in CLIENTS.iter().zip(generators) {
+        let report = generator.await.unwrap();
+        let headers: Vec<String> = report
+            .last_throttled
+            .iter()
+            .map(|(name, value)| format!("{}: {}", name, value))
+            .collect();
+        println!(
+            "{:<8} {:>6.1} {:>9} {:>9} {:>9.1}s  {}",
+            client.api_key,
+            client.rate,
+            report.accepted,
+            report.throttled,
+            report.backed_off.as_secs_f64(),
+            headers.join(", ")
+        );
+    }
+
+    let metrics = http
+        .get(format!("http://{}/metrics", addr).parse().unwrap())
+        .await
+        .unwrap();
+    let body = hyper::body::to_bytes(metrics.into_body()).await.unwrap();
+    println!("\nGET /metrics");
+    String::from_utf8_lossy(&body)
+        .lines()
+        .filter(|line| line.starts_with("nenya_key"))
+        .for_each(|line| println!("{}", line));
+
+    if matches.get_flag("serve") {
+        println!(
+            "\nStill serving, try: curl -i -H 'x-api-key: me' http://{}/orders",
+            addr
+        );
+        server.await.unwrap().unwrap();
+    }
+}
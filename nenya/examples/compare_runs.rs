@@ -0,0 +1,229 @@
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+use std::path::Path;
+use std::time::Duration;
+
+use clap::{Arg, Command};
+use plotters::prelude::*;
+
+use nenya::analysis::AnalyzerBuilder;
+use nenya::simulation::SimulationReport;
+
+/// Size of plots in pixels.
+const PLOT_SIZE: (u32, u32) = (1600, 800);
+
+/// Colors of the compared series, in the order they are given.
+const SERIES_COLORS: [RGBColor; 4] = [
+    RGBColor(31, 119, 180),
+    RGBColor(44, 160, 44),
+    RGBColor(214, 39, 40),
+    RGBColor(148, 103, 189),
+];
+
+/// Reads a simulation run written by the headless simulator, as JSON if the file ends in
+/// `.json` and as CSV otherwise.
+fn read_report(path: &str) -> Result<SimulationReport, Box<dyn Error>> {
+    let reader = BufReader::new(File::open(path).map_err(|err| format!("{}: {}", path, err))?);
+    if Path::new(path).extension().is_some_and(|ext| ext == "json") {
+        #[cfg(feature = "serde")]
+        return Ok(SimulationReport::read_json(reader)?);
+        #[cfg(not(feature = "serde"))]
+        return Err(format!("{}: reading JSON needs the `serde` feature", path).into());
+    }
+    Ok(SimulationReport::read_csv(reader)?)
+}
+
+/// Returns the points of a metric, by its CSV column name, over the elapsed time in seconds.
+fn series(report: &SimulationReport, metric: &str) -> Result<Vec<(f64, f64)>, String> {
+    report
+        .ticks
+        .iter()
+        .map(|tick| {
+            let value = tick
+                .value(metric)
+                .ok_or_else(|| format!("unknown series `{}`", metric))?;
+            Ok((tick.elapsed.as_secs_f64(), value))
+        })
+        .collect()
+}
+
+/// Draws every series of both runs on `root`, the candidate's bold over the baseline's faint
+/// lines of the same color.
+fn render<DB: DrawingBackend>(
+    root: DrawingArea<DB, plotters::coord::Shift>,
+    metrics: &[String],
+    runs: [(&str, &SimulationReport); 2],
+) -> Result<(), Box<dyn Error>>
+where
+    DB::ErrorType: 'static,
+{
+    let mut lines = Vec::new();
+    for (run, report) in runs {
+        for (index, metric) in metrics.iter().enumerate() {
+            let color = SERIES_COLORS[index % SERIES_COLORS.len()];
+            let style = if run == "baseline" {
+                color.mix(0.45).stroke_width(2)
+            } else {
+                color.stroke_width(2)
+            };
+            lines.push((
+                format!("{} {}", run, metric),
+                style,
+                series(report, metric)?,
+            ));
+        }
+    }
+    let points = lines.iter().flat_map(|(_, _, points)| points.iter());
+    let max_x = points.clone().map(|point| point.0).fold(1.0, f64::max);
+    let min_y = points.clone().map(|point| point.1).fold(0.0, f64::min);
+    let max_y = points.map(|point| point.1).fold(1.0, f64::max) * 1.1;
+
+    root.fill(&WHITE)?;
+    let mut chart = ChartBuilder::on(&root)
+        .caption("Baseline vs Candidate", ("sans-serif", 24))
+        .margin(10)
+        .x_label_area_size(40)
+        .y_label_area_size(60)
+        .build_cartesian_2d(0.0..max_x, min_y..max_y)?;
+    chart
+        .configure_mesh()
+        .light_line_style(RGBColor(235, 235, 235))
+        .x_desc("Elapsed (s)")
+        .draw()?;
+    for (name, style, points) in lines {
+        chart
+            .draw_series(LineSeries::new(points, style))?
+            .label(name)
+            .legend(move |(x, y)| PathElement::new(vec![(x, y), (x + 20, y)], style));
+    }
+    chart
+        .configure_series_labels()
+        .position(SeriesLabelPosition::UpperLeft)
+        .background_style(WHITE.mix(0.8))
+        .border_style(BLACK)
+        .draw()?;
+    root.present()?;
+    Ok(())
+}
+
+/// Writes the series of both runs side by side as CSV, one row per tick of the shorter run.
+fn write_overlay(
+    path: &Path,
+    metrics: &[String],
+    baseline: &SimulationReport,
+    candidate: &SimulationReport,
+) -> Result<(), Box<dyn Error>> {
+    let mut csv = BufWriter::new(File::create(path)?);
+    let mut columns = vec!["elapsed".to_string()];
+    let mut values = Vec::new();
+    for metric in metrics {
+        columns.push(format!("baseline_{}", metric));
+        columns.push(format!("candidate_{}", metric));
+        values.push(series(baseline, metric)?);
+        values.push(series(candidate, metric)?);
+    }
+    writeln!(csv, "{}", columns.join(","))?;
+    let rows = values.iter().map(Vec::len).min().unwrap_or(0);
+    for row in 0..rows {
+        let fields: Vec<String> = std::iter::once(values[0][row].0)
+            .chain(values.iter().map(|points| points[row].1))
+            .map(|value| value.to_string())
+            .collect();
+        writeln!(csv, "{}", fields.join(","))?;
+    }
+    csv.flush()?;
+    Ok(())
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let matches = Command::new("Simulation Run Comparison")
+        .about(
+            "Compares the control quality of two simulation runs, e.g. with the current and \
+             proposed gains, and overlays their series",
+        )
+        .arg(
+            Arg::new("baseline")
+                .required(true)
+                .help("Run to compare against, as written by headless_simulator (CSV or JSON)"),
+        )
+        .arg(
+            Arg::new("candidate")
+                .required(true)
+                .help("Run to compare, against the same traffic as the baseline"),
+        )
+        .arg(
+            Arg::new("setpoint")
+                .short('s')
+                .long("setpoint")
+                .value_parser(clap::value_parser!(f64))
+                .default_value("40.0")
+                .help("Target TPS both limiters were configured with"),
+        )
+        .arg(
+            Arg::new("tolerance")
+                .long("tolerance")
+                .value_parser(clap::value_parser!(f64))
+                .default_value("0.05")
+                .help("Distance from the reference within which a run counts as settled, as a fraction of the setpoint"),
+        )
+        .arg(
+            Arg::new("start")
+                .long("start")
+                .value_parser(clap::value_parser!(f64))
+                .default_value("0.0")
+                .help("Time the analysis starts at, e.g. a disturbance (seconds)"),
+        )
+        .arg(
+            Arg::new("series")
+                .long("series")
+                .num_args(1..)
+                .use_value_delimiter(true)
+                .default_value("accepted_rate,target_rate")
+                .help("Metrics to overlay, named like the CSV columns"),
+        )
+        .arg(
+            Arg::new("output")
+                .short('o')
+                .long("output")
+                .help("File to write the overlaid series to: a plot if it ends in .svg or .png, CSV otherwise"),
+        )
+        .get_matches();
+
+    let baseline = read_report(matches.get_one::<String>("baseline").unwrap())?;
+    let candidate = read_report(matches.get_one::<String>("candidate").unwrap())?;
+    let setpoint = *matches.get_one::<f64>("setpoint").unwrap();
+    let tolerance = *matches.get_one::<f64>("tolerance").unwrap();
+    let start = Duration::try_from_secs_f64(*matches.get_one::<f64>("start").unwrap())?;
+    let metrics: Vec<String> = matches
+        .get_many::<String>("series")
+        .unwrap()
+        .cloned()
+        .collect();
+
+    let analyzer = AnalyzerBuilder::new(setpoint)
+        .tolerance(tolerance)
+        .start(start)
+        .build();
+    println!("{}", analyzer.compare(&baseline, &candidate));
+
+    if let Some(output) = matches.get_one::<String>("output") {
+        let path = Path::new(output);
+        let runs = [("baseline", &baseline), ("candidate", &candidate)];
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("svg") => render(
+                SVGBackend::new(path, PLOT_SIZE).into_drawing_area(),
+                &metrics,
+                runs,
+            )?,
+            Some("png") => render(
+                BitMapBackend::new(path, PLOT_SIZE).into_drawing_area(),
+                &metrics,
+                runs,
+            )?,
+            _ => write_overlay(path, &metrics, &baseline, &candidate)?,
+        }
+        println!("\nWrote {}", path.display());
+    }
+    Ok(())
+}
@@ -0,0 +1,219 @@
+use std::fs::File;
+use std::io::{stdout, BufWriter, Write};
+use std::time::Duration;
+
+use clap::{Arg, Command};
+
+use nenya::pid_controller::PIDControllerBuilder;
+use nenya::queueing::{QueueSimulator, QueueValidation};
+use nenya::simulation::{PoissonArrivals, SimulationBuilder, SineWaveGenerator};
+use nenya::RateLimiterBuilder;
+
+fn main() {
+    let matches = Command::new("Headless Rate Limiter Simulation")
+        .about("Simulates a rate limiter in virtual time and writes per-tick metrics")
+        .arg(
+            Arg::new("base_tps")
+                .short('b')
+                .long("base_tps")
+                .value_parser(clap::value_parser!(f64))
+                .default_value("50.0")
+                .help("Base TPS for the request generator"),
+        )
+        .arg(
+            Arg::new("amplitudes")
+                .short('a')
+                .long("amplitudes")
+                .value_parser(clap::value_parser!(f64))
+                .num_args(1..)
+                .use_value_delimiter(true)
+                .default_value("20.0,10.0")
+                .help("Amplitudes for the sine waves"),
+        )
+        .arg(
+            Arg::new("frequencies")
+                .short('f')
+                .long("frequencies")
+                .value_parser(clap::value_parser!(f64))
+                .num_args(1..)
+                .use_value_delimiter(true)
+                .default_value("0.1,0.5")
+                .help("Frequencies for the sine waves"),
+        )
+        .arg(
+            Arg::new("target_tps")
+                .short('t')
+                .long("target_tps")
+                .value_parser(clap::value_parser!(f64))
+                .default_value("40.0")
+                .help("Target TPS for the rate limiter"),
+        )
+        .arg(
+            Arg::new("min_tps")
+                .short('m')
+                .long("min_tps")
+                .value_parser(clap::value_parser!(f64))
+                .default_value("1.0")
+                .help("Lower bound of TPS for the rate limiter"),
+        )
+        .arg(
+            Arg::new("max_tps")
+                .short('x')
+                .long("max_tps")
+                .value_parser(clap::value_parser!(f64))
+                .default_value("60.0")
+                .help("Upper bound of TPS for the rate limiter"),
+        )
+        .arg(
+            Arg::new("kp")
+                .long("kp")
+                .value_parser(clap::value_parser!(f64))
+                .default_value("0.5")
+                .help("Proportional gain for the PID controller"),
+        )
+        .arg(
+            Arg::new("ki")
+                .long("ki")
+                .value_parser(clap::value_parser!(f64))
+                .default_value("0.1")
+                .help("Integral gain for the PID controller"),
+        )
+        .arg(
+            Arg::new("kd")
+                .long("kd")
+                .value_parser(clap::value_parser!(f64))
+                .default_value("0.05")
+                .help("Derivative gain for the PID controller"),
+        )
+        .arg(
+            Arg::new("update_interval")
+                .long("update_interval")
+                .value_parser(clap::value_parser!(u64))
+                .default_value("1000")
+                .help("Update interval for the PID controller (milliseconds)"),
+        )
+        .arg(
+            Arg::new("duration")
+                .short('d')
+                .long("duration")
+                .value_parser(clap::value_parser!(u64))
+                .default_value("3600")
+                .help("Duration of the simulation (seconds of virtual time)"),
+        )
+        .arg(
+            Arg::new("tick")
+                .long("tick")
+                .value_parser(clap::value_parser!(u64))
+                .default_value("100")
+                .help("Time between recorded metrics (milliseconds)"),
+        )
+        .arg(
+            Arg::new("service_rate")
+                .long("service_rate")
+                .value_parser(clap::value_parser!(f64))
+                .help("Serve the accepted requests with exponential service times at this rate per server, and compare the queue with the M/M/c model at the accepted TPS"),
+        )
+        .arg(
+            Arg::new("servers")
+                .long("servers")
+                .value_parser(clap::value_parser!(usize))
+                .default_value("1")
+                .help("Number of servers of the validated queue"),
+        )
+        .arg(
+            Arg::new("seed")
+                .long("seed")
+                .value_parser(clap::value_parser!(u64))
+                .default_value("1")
+                .help("Seed of the Poisson arrivals and service times of the validated queue"),
+        )
+        .arg(
+            Arg::new("format")
+                .long("format")
+                .value_parser(["csv", "json"])
+                .default_value("csv")
+                .help("Output format"),
+        )
+        .arg(
+            Arg::new("output")
+                .short('o')
+                .long("output")
+                .help("File to write the metrics to, instead of stdout"),
+        )
+        .get_matches();
+
+    let base_tps = *matches.get_one::<f64>("base_tps").unwrap();
+    let amplitudes: Vec<f64> = matches
+        .get_many::<f64>("amplitudes")
+        .unwrap()
+        .copied()
+        .collect();
+    let frequencies: Vec<f64> = matches
+        .get_many::<f64>("frequencies")
+        .unwrap()
+        .copied()
+        .collect();
+    let target_tps = *matches.get_one::<f64>("target_tps").unwrap();
+    let min_tps = *matches.get_one::<f64>("min_tps").unwrap();
+    let max_tps = *matches.get_one::<f64>("max_tps").unwrap();
+    let kp = *matches.get_one::<f64>("kp").unwrap();
+    let ki = *matches.get_one::<f64>("ki").unwrap();
+    let kd = *matches.get_one::<f64>("kd").unwrap();
+    let update_interval =
+        Duration::from_millis(*matches.get_one::<u64>("update_interval").unwrap());
+    let duration = Duration::from_secs(*matches.get_one::<u64>("duration").unwrap());
+    let tick = Duration::from_millis(*matches.get_one::<u64>("tick").unwrap());
+    let service_rate = matches.get_one::<f64>("service_rate").copied();
+    let servers = *matches.get_one::<usize>("servers").unwrap();
+    let seed = *matches.get_one::<u64>("seed").unwrap();
+
+    let rate_limiter = RateLimiterBuilder::new(target_tps)
+        .min_rate(min_tps)
+        .max_rate(max_tps)
+        .pid_controller(
+            PIDControllerBuilder::new(target_tps)
+                .kp(kp)
+                .ki(ki)
+                .kd(kd)
+                .build(),
+        )
+        .update_interval(update_interval);
+    let generator = SineWaveGenerator::new(base_tps, amplitudes.clone(), frequencies);
+    let builder = match service_rate {
+        // Queueing models assume independent arrivals, so validate against Poisson traffic
+        Some(service_rate) => {
+            let max_rate = base_tps
+                + amplitudes
+                    .iter()
+                    .map(|amplitude| amplitude.abs())
+                    .sum::<f64>();
+            let arrivals = PoissonArrivals::time_varying(generator, max_rate, seed);
+            SimulationBuilder::from_arrivals(rate_limiter, arrivals).queue(QueueSimulator::new(
+                service_rate,
+                servers,
+                seed,
+            ))
+        }
+        None => SimulationBuilder::new(rate_limiter, generator),
+    };
+    let mut simulation = builder.tick(tick).build();
+    let report = simulation.run(duration);
+    if let Some(queue) = simulation.queue() {
+        let stats = queue.stats();
+        let validation = QueueValidation::new(queue.model(stats.arrival_rate), stats);
+        eprintln!(
+            "M/M/{} queue at {:.2} accepted TPS (target {:.2}), {} requests served:\n{}",
+            servers, stats.arrival_rate, target_tps, stats.served, validation
+        );
+    }
+
+    let mut writer: Box<dyn Write> = match matches.get_one::<String>("output") {
+        Some(path) => Box::new(BufWriter::new(File::create(path).unwrap())),
+        None => Box::new(BufWriter::new(stdout())),
+    };
+    match matches.get_one::<String>("format").unwrap().as_str() {
+        "json" => report.write_json(&mut writer).unwrap(),
+        _ => report.write_csv(&mut writer).unwrap(),
+    }
+    writer.flush().unwrap();
+}
@@ -0,0 +1,100 @@
+use std::io::{stdout, BufWriter, Write};
+use std::sync::Arc;
+use std::time::Duration;
+
+use clap::{Arg, Command};
+
+use nenya::clock::ManualClock;
+use nenya::keyed::KeyedRateLimiter;
+use nenya::soak::{CountingAllocator, MemorySoakBuilder};
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+fn main() {
+    let matches = Command::new("Keyed Rate Limiter Memory Soak")
+        .about(
+            "Sends millions of decisions from churning keys in virtual time and samples memory use",
+        )
+        .arg(
+            Arg::new("keys")
+                .short('k')
+                .long("keys")
+                .value_parser(clap::value_parser!(usize))
+                .default_value("5000")
+                .help("Number of keys sending requests at any time"),
+        )
+        .arg(
+            Arg::new("rate")
+                .short('r')
+                .long("rate")
+                .value_parser(clap::value_parser!(f64))
+                .default_value("50000.0")
+                .help("Requests per second across all keys (virtual time)"),
+        )
+        .arg(
+            Arg::new("key_rate")
+                .short('t')
+                .long("key_rate")
+                .value_parser(clap::value_parser!(f64))
+                .default_value("10.0")
+                .help("Rate each key is limited to"),
+        )
+        .arg(
+            Arg::new("decisions")
+                .short('d')
+                .long("decisions")
+                .value_parser(clap::value_parser!(u64))
+                .default_value("10000000")
+                .help("Number of decisions to make"),
+        )
+        .arg(
+            Arg::new("key_lifetime")
+                .short('l')
+                .long("key_lifetime")
+                .value_parser(clap::value_parser!(u64))
+                .default_value("60")
+                .help("Time a key sends requests before a new key replaces it (seconds)"),
+        )
+        .arg(
+            Arg::new("sample_every")
+                .long("sample_every")
+                .value_parser(clap::value_parser!(u64))
+                .default_value("100000")
+                .help("Number of decisions between samples"),
+        )
+        .get_matches();
+
+    let clock = ManualClock::new();
+    let mut limiter: KeyedRateLimiter<String, f64> = KeyedRateLimiter::with_clock(
+        *matches.get_one::<f64>("key_rate").unwrap(),
+        Arc::new(clock.clone()),
+    );
+    let report = MemorySoakBuilder::new(
+        *matches.get_one::<usize>("keys").unwrap(),
+        *matches.get_one::<f64>("rate").unwrap(),
+    )
+    .decisions(*matches.get_one::<u64>("decisions").unwrap())
+    .key_lifetime(Duration::from_secs(
+        *matches.get_one::<u64>("key_lifetime").unwrap(),
+    ))
+    .sample_every(*matches.get_one::<u64>("sample_every").unwrap())
+    .build()
+    .run(&mut limiter, &clock, |key| format!("key-{key}"));
+
+    let mut writer = BufWriter::new(stdout());
+    report.write_csv(&mut writer).unwrap();
+    writer.flush().unwrap();
+
+    eprintln!(
+        "Peak keys {}, peak window entries {}, peak heap {} bytes: memory is {}",
+        report.peak_keys(),
+        report.peak_window_entries(),
+        report.peak_live_bytes().unwrap_or_default(),
+        if report.is_bounded(0.25) {
+            "bounded"
+        } else {
+            "growing"
+        }
+    );
+}
@@ -6,6 +6,7 @@ use std::time::{Duration, Instant};
 use clap::{Arg, Command};
 
 use nenya::pid_controller::PIDController;
+use nenya::simulation::{SineWaveGenerator, TrafficGenerator};
 use nenya::RateLimiter;
 
 const LINE_LENGTH: usize = 80;
@@ -174,13 +175,13 @@ fn main() {
         update_interval,
     );
 
-    let generator = RequestGenerator::new(base_tps, amplitudes, frequencies);
+    let generator = SineWaveGenerator::new(base_tps, amplitudes, frequencies);
     generate_requests(&mut rate_limiter, &generator, trailing_window, duration);
 }
 
 fn generate_requests(
     rate_limiter: &mut RateLimiter<f32>,
-    generator: &RequestGenerator,
+    generator: &SineWaveGenerator,
     trailing_window: Duration,
     duration: Duration,
 ) {
@@ -202,8 +203,8 @@ fn generate_requests(
     while Instant::now().duration_since(start) < duration {
         let elapsed_seconds = Instant::now().duration_since(start).as_secs_f64();
 
-        // Generate a varying request rate using the RequestGenerator
-        let generated_tps = generator.generate_request_rate(elapsed_seconds);
+        // Generate a varying request rate using the SineWaveGenerator
+        let generated_tps = generator.request_rate(Duration::from_secs_f64(elapsed_seconds));
         let inter_request_delay = if generated_tps > 0.0 {
             (1000.0 / generated_tps) as u64
         } else {
@@ -284,27 +285,3 @@ fn print_metrics(
     println!("\rTarget TPS: {:.2}", rate_limiter.target_rate());
     println!("\rMeasured TPS: {:.2}", rate_limiter.request_rate());
 }
-
-pub struct RequestGenerator {
-    pub base_tps: f64,
-    pub amplitudes: Vec<f64>,
-    pub frequencies: Vec<f64>,
-}
-
-impl RequestGenerator {
-    pub fn new(base_tps: f64, amplitudes: Vec<f64>, frequencies: Vec<f64>) -> Self {
-        RequestGenerator {
-            base_tps,
-            amplitudes,
-            frequencies,
-        }
-    }
-
-    pub fn generate_request_rate(&self, elapsed_seconds: f64) -> f64 {
-        let mut rate = self.base_tps;
-        for (amplitude, frequency) in self.amplitudes.iter().zip(self.frequencies.iter()) {
-            rate += amplitude * (2.0 * std::f64::consts::PI * frequency * elapsed_seconds).sin();
-        }
-        rate
-    }
-}
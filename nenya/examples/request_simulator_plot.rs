@@ -1,12 +1,18 @@
 use std::collections::VecDeque;
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
 use std::time::{Duration, Instant};
 
 use clap::{Arg, Command};
 use eframe::egui;
 use egui::ViewportBuilder;
 use egui_plot::{Corner, Line, Plot};
+use plotters::prelude::*;
 
 use nenya::pid_controller::PIDControllerBuilder;
+use nenya::simulation::{SineWaveGenerator, TrafficGenerator};
 use nenya::RateLimiter;
 
 fn main() {
@@ -127,6 +133,29 @@ fn main() {
                 .default_value("1000")
                 .help("Update interval for the PID controller (milliseconds)"),
         )
+        .arg(
+            Arg::new("nodes")
+                .short('n')
+                .long("nodes")
+                .value_parser(clap::value_parser!(usize))
+                .default_value("1")
+                .help("Number of rate limiters splitting the traffic, each targeting the global TPS"),
+        )
+        .arg(
+            Arg::new("exchange_interval")
+                .short('e')
+                .long("exchange_interval")
+                .value_parser(clap::value_parser!(u64))
+                .default_value("1000")
+                .help("Interval between exchanges of the nodes' rates (milliseconds)"),
+        )
+        .arg(
+            Arg::new("output")
+                .short('o')
+                .long("output")
+                .value_parser(clap::value_parser!(PathBuf))
+                .help("Renders the run to this PNG or SVG file when it ends, with the series as CSV next to it"),
+        )
         .get_matches();
 
     let base_tps = *matches.get_one::<f64>("base_tps").unwrap();
@@ -155,6 +184,10 @@ fn main() {
     let output_limit = matches.get_one::<f32>("output_limit").copied();
     let update_interval =
         Duration::from_millis(*matches.get_one::<u64>("update_interval").unwrap());
+    let output = matches.get_one::<PathBuf>("output").cloned();
+    let nodes = (*matches.get_one::<usize>("nodes").unwrap()).max(1);
+    let exchange_interval =
+        Duration::from_millis(*matches.get_one::<u64>("exchange_interval").unwrap());
 
     let mut builder = PIDControllerBuilder::new(target_tps)
         .kp(kp)
@@ -171,15 +204,19 @@ fn main() {
     }
 
     let pid_controller = builder.build();
-    let rate_limiter = RateLimiter::new(
-        target_tps,
-        min_tps,
-        max_tps,
-        pid_controller,
-        update_interval,
-    );
+    let rate_limiters = (0..nodes)
+        .map(|_| {
+            RateLimiter::new(
+                target_tps,
+                min_tps,
+                max_tps,
+                pid_controller.clone(),
+                update_interval,
+            )
+        })
+        .collect();
 
-    let generator = RequestGenerator::new(base_tps, amplitudes, frequencies);
+    let generator = SineWaveGenerator::new(base_tps, amplitudes, frequencies);
 
     let trailing_window_clone: &'static mut Duration = Box::leak(Box::new(trailing_window));
     let duration_clone: &'static mut Duration = Box::leak(Box::new(duration));
@@ -190,21 +227,108 @@ fn main() {
             centered: true,
             ..Default::default()
         },
-        Box::new(|_cc| {
+        Box::new(move |_cc| {
             Box::new(App::new(
-                rate_limiter,
+                rate_limiters,
+                exchange_interval,
                 generator,
                 *trailing_window_clone,
                 *duration_clone,
+                output,
             ))
         }),
     )
     .unwrap();
 }
 
-struct App {
+/// Values of the controls panel, applied to the live rate limiter when changed.
+struct Controls {
+    kp: f32,
+    ki: f32,
+    kd: f32,
+    error_limit: Option<f32>,
+    output_limit: Option<f32>,
+    target_tps: f32,
+}
+
+impl Controls {
+    fn new(rate_limiter: &RateLimiter<f32>) -> Self {
+        let pid_controller = rate_limiter.pid_controller();
+        let (kp, ki, kd) = pid_controller.gains();
+        Self {
+            kp,
+            ki,
+            kd,
+            error_limit: pid_controller.error_limit(),
+            output_limit: pid_controller.output_limit(),
+            target_tps: rate_limiter.setpoint(),
+        }
+    }
+
+    /// Shows the controls, returning `true` if any of them changed.
+    fn show(&mut self, ui: &mut egui::Ui, max_tps: f32) -> bool {
+        let mut changed = false;
+        ui.heading("PID Controller");
+        changed |= ui
+            .add(egui::Slider::new(&mut self.kp, 0.0..=2.0).text("kp"))
+            .changed();
+        changed |= ui
+            .add(egui::Slider::new(&mut self.ki, 0.0..=1.0).text("ki"))
+            .changed();
+        changed |= ui
+            .add(egui::Slider::new(&mut self.kd, 0.0..=1.0).text("kd"))
+            .changed();
+        changed |= optional_slider(ui, "error limit", &mut self.error_limit, 100.0);
+        changed |= optional_slider(ui, "output limit", &mut self.output_limit, 50.0);
+
+        ui.separator();
+        ui.heading("Rate Limiter");
+        changed |= ui
+            .add(egui::Slider::new(&mut self.target_tps, 1.0..=max_tps).text("target TPS"))
+            .changed();
+        changed
+    }
+
+    fn apply(&self, rate_limiter: &mut RateLimiter<f32>) {
+        let pid_controller = rate_limiter.pid_controller_mut();
+        pid_controller.set_gains(self.kp, self.ki, self.kd);
+        pid_controller.set_error_limit(self.error_limit);
+        pid_controller.set_output_limit(self.output_limit);
+        if self.target_tps != rate_limiter.setpoint() {
+            rate_limiter.set_target_rate(self.target_tps);
+        }
+    }
+}
+
+/// Shows a checkbox enabling a limit and a slider setting it, returning `true` if either changed.
+fn optional_slider(ui: &mut egui::Ui, name: &str, limit: &mut Option<f32>, max: f32) -> bool {
+    let mut enabled = limit.is_some();
+    let mut value = limit.unwrap_or(max / 10.0);
+    let mut changed = false;
+    ui.horizontal(|ui| {
+        changed |= ui.checkbox(&mut enabled, "").changed();
+        changed |= ui
+            .add_enabled(enabled, egui::Slider::new(&mut value, 0.1..=max).text(name))
+            .changed();
+    });
+    *limit = enabled.then_some(value);
+    changed
+}
+
+/// A rate limiter of the simulated cluster, with the times of the requests it accepted.
+struct Node {
     rate_limiter: RateLimiter<f32>,
-    generator: RequestGenerator,
+    accepted_request_times: VecDeque<Instant>,
+    accepted_tps_data: Vec<[f64; 2]>,
+}
+
+struct App {
+    nodes: Vec<Node>,
+    exchange_interval: Duration,
+    last_exchange: Instant,
+    controls: Controls,
+    max_tps: f32,
+    generator: SineWaveGenerator,
     trailing_window: Duration,
     duration: Duration,
     start: Instant,
@@ -217,20 +341,35 @@ struct App {
     throttled_tps_data: Vec<[f64; 2]>,
     // measured_tps_data: Vec<[f64; 2]>,
     // measured_accepted_tps_data: Vec<[f64; 2]>,
-    accepted_request_times: VecDeque<Instant>,
     throttled_request_times: VecDeque<Instant>,
     last_time_point_added: f64,
+    output: Option<PathBuf>,
+    exported: bool,
+    export_status: Option<String>,
 }
 
 impl App {
     fn new(
-        rate_limiter: RateLimiter<f32>,
-        generator: RequestGenerator,
+        rate_limiters: Vec<RateLimiter<f32>>,
+        exchange_interval: Duration,
+        generator: SineWaveGenerator,
         trailing_window: Duration,
         duration: Duration,
+        output: Option<PathBuf>,
     ) -> Self {
         Self {
-            rate_limiter,
+            controls: Controls::new(&rate_limiters[0]),
+            max_tps: rate_limiters[0].max_rate() * 2.0,
+            nodes: rate_limiters
+                .into_iter()
+                .map(|rate_limiter| Node {
+                    rate_limiter,
+                    accepted_request_times: VecDeque::new(),
+                    accepted_tps_data: Vec::new(),
+                })
+                .collect(),
+            exchange_interval,
+            last_exchange: Instant::now(),
             generator,
             trailing_window,
             duration,
@@ -244,43 +383,213 @@ impl App {
             throttled_tps_data: Vec::new(),
             // measured_tps_data: Vec::new(),
             // measured_accepted_tps_data: Vec::new(),
-            accepted_request_times: VecDeque::new(),
             throttled_request_times: VecDeque::new(),
             last_time_point_added: 0.0,
+            output,
+            exported: false,
+            export_status: None,
+        }
+    }
+
+    /// Sets the external rates of every node to the summed local rates of the others, the way
+    /// the sentinel shares rates between the nodes of a cluster.
+    fn exchange_rates(&mut self) {
+        let request_rate: f32 = self
+            .nodes
+            .iter()
+            .map(|node| node.rate_limiter.local_request_rate())
+            .sum();
+        let accepted_request_rate: f32 = self
+            .nodes
+            .iter()
+            .map(|node| node.rate_limiter.local_accepted_request_rate())
+            .sum();
+        for node in &mut self.nodes {
+            let rate_limiter = &mut node.rate_limiter;
+            let external_request_rate = request_rate - rate_limiter.local_request_rate();
+            let external_accepted_request_rate =
+                accepted_request_rate - rate_limiter.local_accepted_request_rate();
+            rate_limiter.set_external_request_rate(external_request_rate);
+            rate_limiter.set_external_accepted_request_rate(external_accepted_request_rate);
+        }
+    }
+
+    /// Returns the plotted series by name, all sampled at the same times, with the accepted TPS
+    /// of every node after the aggregates when there is more than one.
+    fn series(&self) -> Vec<(String, &[[f64; 2]])> {
+        let mut series: Vec<(String, &[[f64; 2]])> = vec![
+            ("Setpoint".to_string(), &self.setpoint_data),
+            ("Generated TPS".to_string(), &self.generated_tps_data),
+            ("Trailing Accepted TPS".to_string(), &self.trailing_tps_data),
+            (
+                "Trailing Throttled TPS".to_string(),
+                &self.throttled_tps_data,
+            ),
+            ("Rate Limit Target TPS".to_string(), &self.target_tps_data),
+        ];
+        if self.nodes.len() > 1 {
+            for (index, node) in self.nodes.iter().enumerate() {
+                series.push((
+                    format!("Node {} Accepted TPS", index + 1),
+                    &node.accepted_tps_data,
+                ));
+            }
+        }
+        series
+    }
+
+    /// Renders the run to `path`, as SVG if it ends in `.svg` and as PNG otherwise, and writes
+    /// the series to a CSV file next to it.
+    fn export(&self, path: &Path) -> Result<PathBuf, Box<dyn Error>> {
+        let series = self.series();
+        if path.extension().is_some_and(|ext| ext == "svg") {
+            render(
+                SVGBackend::new(path, EXPORT_SIZE).into_drawing_area(),
+                &series,
+            )?;
+        } else {
+            render(
+                BitMapBackend::new(path, EXPORT_SIZE).into_drawing_area(),
+                &series,
+            )?;
+        }
+
+        let csv_path = path.with_extension("csv");
+        let mut csv = BufWriter::new(File::create(&csv_path)?);
+        write!(
+            csv,
+            "elapsed,setpoint,generated_tps,trailing_accepted_tps,trailing_throttled_tps,target_tps"
+        )?;
+        for (name, _) in &series[5..] {
+            write!(csv, ",{}", name.to_lowercase().replace(' ', "_"))?;
         }
+        writeln!(csv)?;
+        for (index, &[elapsed, setpoint]) in self.setpoint_data.iter().enumerate() {
+            let values: Vec<String> = series[1..]
+                .iter()
+                .map(|(_, points)| points[index][1].to_string())
+                .collect();
+            writeln!(csv, "{},{},{}", elapsed, setpoint, values.join(","))?;
+        }
+        csv.flush()?;
+        Ok(csv_path)
+    }
+
+    /// Exports the run to `path`, keeping the outcome to show in the controls panel.
+    fn export_to(&mut self, path: &Path) {
+        self.export_status = Some(match self.export(path) {
+            Ok(csv_path) => format!("Exported to {} and {}", path.display(), csv_path.display()),
+            Err(err) => format!("Export failed: {}", err),
+        });
     }
 }
+
+/// Size of exported plots in pixels.
+const EXPORT_SIZE: (u32, u32) = (1600, 800);
+
+/// Colors of the exported aggregate series, in the order of `App::series`.
+const EXPORT_COLORS: [RGBColor; 5] = [
+    RGBColor(31, 119, 180),
+    RGBColor(255, 127, 14),
+    RGBColor(44, 160, 44),
+    RGBColor(214, 39, 40),
+    RGBColor(148, 103, 189),
+];
+
+/// Returns the line style of the series at `index`, picking node colors from a palette.
+fn export_style(index: usize) -> ShapeStyle {
+    match EXPORT_COLORS.get(index) {
+        Some(color) => color.stroke_width(2),
+        None => Palette99::pick(index).stroke_width(1),
+    }
+}
+
+/// Draws the series on `root` with a legend, the way the window plots them.
+fn render<DB: DrawingBackend>(
+    root: DrawingArea<DB, plotters::coord::Shift>,
+    series: &[(String, &[[f64; 2]])],
+) -> Result<(), Box<dyn Error>>
+where
+    DB::ErrorType: 'static,
+{
+    root.fill(&WHITE)?;
+    let points = series.iter().flat_map(|(_, points)| points.iter());
+    let max_x = points.clone().map(|point| point[0]).fold(1.0, f64::max);
+    let max_y = points.map(|point| point[1]).fold(1.0, f64::max) * 1.1;
+
+    let mut chart = ChartBuilder::on(&root)
+        .caption("Rate Limiter Simulation", ("sans-serif", 24))
+        .margin(10)
+        .x_label_area_size(40)
+        .y_label_area_size(60)
+        .build_cartesian_2d(0.0..max_x, 0.0..max_y)?;
+    chart
+        .configure_mesh()
+        .light_line_style(RGBColor(235, 235, 235))
+        .x_desc("Elapsed (s)")
+        .y_desc("TPS")
+        .draw()?;
+    for (index, (name, points)) in series.iter().enumerate() {
+        let style = export_style(index);
+        chart
+            .draw_series(LineSeries::new(points.iter().map(|&[x, y]| (x, y)), style))?
+            .label(name)
+            .legend(move |(x, y)| PathElement::new(vec![(x, y), (x + 20, y)], style));
+    }
+    chart
+        .configure_series_labels()
+        .position(SeriesLabelPosition::UpperLeft)
+        .background_style(WHITE.mix(0.8))
+        .border_style(BLACK)
+        .draw()?;
+    root.present()?;
+    Ok(())
+}
 impl eframe::App for App {
     fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
         let elapsed_seconds = self.start.elapsed().as_secs_f64();
 
         if elapsed_seconds < self.duration.as_secs_f64() {
-            // Generate a varying request rate using the RequestGenerator
-            let generated_tps = self.generator.generate_request_rate(elapsed_seconds);
+            // Generate a varying request rate using the SineWaveGenerator
+            let generated_tps = self
+                .generator
+                .request_rate(Duration::from_secs_f64(elapsed_seconds));
             let inter_request_delay = if generated_tps > 0.0 {
                 (1000.0 / generated_tps) as u64
             } else {
                 1000
             };
 
-            let should_throttle_request = self.rate_limiter.should_throttle();
-            self.total_requests += 1;
             let now = Instant::now();
+            if self.nodes.len() > 1
+                && now.duration_since(self.last_exchange) >= self.exchange_interval
+            {
+                self.exchange_rates();
+                self.last_exchange = now;
+            }
+
+            // Spread the requests over the nodes in turn, as a load balancer would
+            let node_index = self.total_requests % self.nodes.len();
+            let node = &mut self.nodes[node_index];
+            let should_throttle_request = node.rate_limiter.should_throttle();
+            self.total_requests += 1;
 
             // Add new indicator at the end of the buffer
             if should_throttle_request {
                 self.throttled_request_times.push_back(now);
             } else {
                 self.accepted_requests += 1;
-                self.accepted_request_times.push_back(now);
+                node.accepted_request_times.push_back(now);
             }
 
             // Remove old timestamps outside the trailing window
-            while let Some(&time) = self.accepted_request_times.front() {
-                if now.duration_since(time) > self.trailing_window {
-                    self.accepted_request_times.pop_front();
-                } else {
-                    break;
+            for node in &mut self.nodes {
+                while let Some(&time) = node.accepted_request_times.front() {
+                    if now.duration_since(time) > self.trailing_window {
+                        node.accepted_request_times.pop_front();
+                    } else {
+                        break;
+                    }
                 }
             }
 
@@ -292,21 +601,38 @@ impl eframe::App for App {
                 }
             }
 
-            let trailing_tps =
-                self.accepted_request_times.len() as f64 / self.trailing_window.as_secs_f64();
+            let trailing_tps = self
+                .nodes
+                .iter()
+                .map(|node| node.accepted_request_times.len())
+                .sum::<usize>() as f64
+                / self.trailing_window.as_secs_f64();
             let throttled_tps =
                 self.throttled_request_times.len() as f64 / self.trailing_window.as_secs_f64();
 
             if elapsed_seconds - self.last_time_point_added >= 0.033 {
-                self.setpoint_data
-                    .push([elapsed_seconds, self.rate_limiter.setpoint() as f64]);
+                // Every node targets the global rate, so plot their mean target against it
+                let target_tps = self
+                    .nodes
+                    .iter()
+                    .map(|node| node.rate_limiter.target_rate() as f64)
+                    .sum::<f64>()
+                    / self.nodes.len() as f64;
+                self.setpoint_data.push([
+                    elapsed_seconds,
+                    self.nodes[0].rate_limiter.setpoint() as f64,
+                ]);
                 self.trailing_tps_data.push([elapsed_seconds, trailing_tps]);
                 self.generated_tps_data
                     .push([elapsed_seconds, generated_tps]);
-                self.target_tps_data
-                    .push([elapsed_seconds, self.rate_limiter.target_rate() as f64]);
+                self.target_tps_data.push([elapsed_seconds, target_tps]);
                 self.throttled_tps_data
                     .push([elapsed_seconds, throttled_tps]);
+                for node in &mut self.nodes {
+                    let accepted_tps = node.accepted_request_times.len() as f64
+                        / self.trailing_window.as_secs_f64();
+                    node.accepted_tps_data.push([elapsed_seconds, accepted_tps]);
+                }
                 // self.measured_tps_data
                 //     .push([elapsed_seconds, self.rate_limiter.request_rate() as f64]);
                 // self.measured_accepted_tps_data.push([
@@ -328,22 +654,51 @@ impl eframe::App for App {
             ctx.request_repaint_after(Duration::from_millis(inter_request_delay));
         }
 
+        if elapsed_seconds >= self.duration.as_secs_f64() && !self.exported {
+            self.exported = true;
+            if let Some(output) = self.output.clone() {
+                self.export_to(&output);
+            }
+        }
+
+        egui::SidePanel::left("controls").show(ctx, |ui| {
+            if self.controls.show(ui, self.max_tps) {
+                for node in &mut self.nodes {
+                    self.controls.apply(&mut node.rate_limiter);
+                }
+            }
+
+            if self.nodes.len() > 1 {
+                ui.separator();
+                ui.heading("Cluster");
+                ui.label(format!(
+                    "{} nodes exchanging rates every {:?}",
+                    self.nodes.len(),
+                    self.exchange_interval
+                ));
+            }
+
+            ui.separator();
+            if ui.button("Export").clicked() {
+                let output = self
+                    .output
+                    .clone()
+                    .unwrap_or_else(|| PathBuf::from("request_simulator.png"));
+                self.export_to(&output);
+            }
+            if let Some(status) = &self.export_status {
+                ui.label(status);
+            }
+        });
+
         egui::CentralPanel::default().show(ctx, |ui| {
             Plot::new("Rate Limiter Simulation")
                 .view_aspect(2.0)
                 .legend(egui_plot::Legend::default().position(Corner::LeftTop))
                 .show(ui, |plot_ui| {
-                    plot_ui.line(Line::new(self.setpoint_data.clone()).name("Setpoint"));
-                    plot_ui.line(Line::new(self.generated_tps_data.clone()).name("Generated TPS"));
-                    plot_ui.line(
-                        Line::new(self.trailing_tps_data.clone()).name("Trailing Accepted TPS"),
-                    );
-                    plot_ui.line(
-                        Line::new(self.throttled_tps_data.clone()).name("Trailing Throttled TPS"),
-                    );
-                    plot_ui.line(
-                        Line::new(self.target_tps_data.clone()).name("Rate Limit Target TPS"),
-                    );
+                    for (name, points) in self.series() {
+                        plot_ui.line(Line::new(points.to_vec()).name(name));
+                    }
                     // plot_ui.line(Line::new(self.measured_tps_data.clone()).name("Measured TPS"));
                     // plot_ui.line(
                     //     Line::new(self.measured_accepted_tps_data.clone())
@@ -353,28 +708,3 @@ impl eframe::App for App {
         });
     }
 }
-
-#[derive(Clone)]
-pub struct RequestGenerator {
-    pub base_tps: f64,
-    pub amplitudes: Vec<f64>,
-    pub frequencies: Vec<f64>,
-}
-
-impl RequestGenerator {
-    pub fn new(base_tps: f64, amplitudes: Vec<f64>, frequencies: Vec<f64>) -> Self {
-        RequestGenerator {
-            base_tps,
-            amplitudes,
-            frequencies,
-        }
-    }
-
-    pub fn generate_request_rate(&self, elapsed_seconds: f64) -> f64 {
-        let mut rate = self.base_tps;
-        for (amplitude, frequency) in self.amplitudes.iter().zip(self.frequencies.iter()) {
-            rate += amplitude * (2.0 * std::f64::consts::PI * frequency * elapsed_seconds).sin();
-        }
-        rate
-    }
-}
@@ -0,0 +1,52 @@
+use std::fs;
+use std::path::Path;
+use std::process::exit;
+
+use clap::{Arg, Command};
+
+use nenya::scenario::Scenario;
+
+fn main() {
+    let matches = Command::new("Rate Limiter Scenario Runner")
+        .about("Runs simulation scenarios from TOML or JSON files and checks their expectations")
+        .arg(
+            Arg::new("scenarios")
+                .num_args(1..)
+                .required(true)
+                .help("Scenario files, parsed as JSON if they end in .json and as TOML otherwise"),
+        )
+        .get_matches();
+
+    let mut failed = 0;
+    for path in matches.get_many::<String>("scenarios").unwrap() {
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(err) => {
+                eprintln!("{}: unable to read scenario: {}", path, err);
+                failed += 1;
+                continue;
+            }
+        };
+        let scenario = if Path::new(path).extension().is_some_and(|ext| ext == "json") {
+            Scenario::from_json(&contents)
+        } else {
+            Scenario::from_toml(&contents)
+        };
+        match scenario {
+            Ok(scenario) => {
+                let outcome = scenario.run();
+                println!("{}", outcome);
+                failed += usize::from(!outcome.passed());
+            }
+            Err(err) => {
+                eprintln!("{}: {}", path, err);
+                failed += 1;
+            }
+        }
+    }
+
+    if failed > 0 {
+        eprintln!("{} scenario(s) failed", failed);
+        exit(1);
+    }
+}
@@ -0,0 +1,84 @@
+use std::fs;
+use std::path::Path;
+use std::process::exit;
+
+use clap::{Arg, Command};
+
+use nenya::scenario::Scenario;
+use nenya::sensitivity::{Parameter, SensitivityAnalyzerBuilder};
+
+fn main() {
+    let matches = Command::new("Parameter Sensitivity Report")
+        .about(
+            "Reruns a scenario with each limiter parameter perturbed around its configured value \
+             and ranks the parameters by how much the outcome changes",
+        )
+        .arg(
+            Arg::new("scenario")
+                .required(true)
+                .help("Scenario file, parsed as JSON if it ends in .json and as TOML otherwise"),
+        )
+        .arg(
+            Arg::new("step")
+                .short('s')
+                .long("step")
+                .value_parser(clap::value_parser!(f64))
+                .default_value("0.1")
+                .help("Fraction of their value the parameters are perturbed by, up and down"),
+        )
+        .arg(
+            Arg::new("tolerance")
+                .long("tolerance")
+                .value_parser(clap::value_parser!(f64))
+                .default_value("0.05")
+                .help("Distance from the reference within which a run counts as settled, as a fraction of the setpoint"),
+        )
+        .arg(
+            Arg::new("parameters")
+                .short('p')
+                .long("parameters")
+                .num_args(1..)
+                .use_value_delimiter(true)
+                .help("Parameters to perturb, all by default, named like the scenario's fields"),
+        )
+        .get_matches();
+
+    let path = matches.get_one::<String>("scenario").unwrap();
+    let contents = fs::read_to_string(path).unwrap_or_else(|err| {
+        eprintln!("{}: unable to read scenario: {}", path, err);
+        exit(1);
+    });
+    let scenario = if Path::new(path).extension().is_some_and(|ext| ext == "json") {
+        Scenario::from_json(&contents)
+    } else {
+        Scenario::from_toml(&contents)
+    }
+    .unwrap_or_else(|err| {
+        eprintln!("{}: {}", path, err);
+        exit(1);
+    });
+
+    let mut builder = SensitivityAnalyzerBuilder::new(scenario)
+        .step(*matches.get_one::<f64>("step").unwrap())
+        .tolerance(*matches.get_one::<f64>("tolerance").unwrap());
+    if let Some(names) = matches.get_many::<String>("parameters") {
+        let parameters: Vec<Parameter> = names
+            .map(|name| {
+                Parameter::ALL
+                    .into_iter()
+                    .find(|parameter| parameter.to_string() == *name)
+                    .unwrap_or_else(|| {
+                        eprintln!("unknown parameter `{}`", name);
+                        exit(1);
+                    })
+            })
+            .collect();
+        builder = builder.parameters(&parameters);
+    }
+
+    let report = builder.build().analyze();
+    println!("{}", report);
+    if !report.baseline.passed {
+        println!("\nThe scenario fails its expectations as configured");
+    }
+}
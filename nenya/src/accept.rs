@@ -0,0 +1,259 @@
+//! Admission control for raw servers at connection-accept time.
+//!
+//! A [`ConnectionLimiter`] sits in a tokio accept loop and limits both how fast connections are
+//! accepted and how many are open at once, before any bytes are read. This suits TCP proxies
+//! and servers that want to shed load before paying for TLS handshakes or HTTP parsing.
+//!
+//! - Connections over the rate limiter's target rate are closed as soon as they are accepted.
+//! - While the maximum number of connections is open, no connections are accepted at all, so
+//!   new ones wait in the listen backlog until an open one closes.
+//!
+//! Accepted connections are wrapped in a [`LimitedStream`], which implements tokio's
+//! `AsyncRead` and `AsyncWrite` and can be handed to hyper or any other tokio based protocol
+//! implementation. Dropping it frees its slot for the next connection.
+//!
+//! # Example
+//!
+//! ```rust,no_run
+//! use nenya::accept::ConnectionLimiter;
+//! use nenya::RateLimiterBuilder;
+//! use tokio::net::TcpListener;
+//!
+//! # async fn example() -> std::io::Result<()> {
+//! let listener = TcpListener::bind("0.0.0.0:8080").await?;
+//! let limiter = ConnectionLimiter::new(RateLimiterBuilder::new(100.0).build(), 1000);
+//! loop {
+//!     let (stream, _addr) = limiter.accept(&listener).await?;
+//!     tokio::spawn(async move {
+//!         // Serve the connection, e.g. with hyper
+//!         drop(stream);
+//!     });
+//! }
+//! # }
+//! ```
+
+use num_traits::{Float, FromPrimitive, Signed};
+use std::future::Future;
+use std::io;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+use crate::RateLimiter;
+
+/// Limits the rate of accepted connections and the number of open ones.
+#[derive(Debug)]
+pub struct ConnectionLimiter<T> {
+    rate_limiter: Mutex<RateLimiter<T>>,
+    permits: Arc<Semaphore>,
+    max_concurrent: usize,
+    rejected: AtomicU64,
+}
+
+impl<T: Float + Signed + FromPrimitive + Copy> ConnectionLimiter<T> {
+    /// Creates a limiter accepting connections at up to the target rate of `rate_limiter`, with
+    /// at most `max_concurrent` of them open at once.
+    pub fn new(rate_limiter: RateLimiter<T>, max_concurrent: usize) -> Self {
+        ConnectionLimiter {
+            rate_limiter: Mutex::new(rate_limiter),
+            permits: Arc::new(Semaphore::new(max_concurrent)),
+            max_concurrent,
+            rejected: AtomicU64::new(0),
+        }
+    }
+
+    /// Accepts the next connection from `listener` that is within the limits.
+    pub async fn accept(
+        &self,
+        listener: &TcpListener,
+    ) -> io::Result<(LimitedStream<TcpStream>, SocketAddr)> {
+        self.accept_with(|| listener.accept()).await
+    }
+
+    /// Accepts the next connection within the limits from any listener, such as a
+    /// `UnixListener`, by calling `accept` until it returns one.
+    pub async fn accept_with<F, Fut, S, A>(
+        &self,
+        mut accept: F,
+    ) -> io::Result<(LimitedStream<S>, A)>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = io::Result<(S, A)>>,
+    {
+        let permit = self
+            .permits
+            .clone()
+            .acquire_owned()
+            .await
+            .expect("the semaphore is never closed");
+        loop {
+            let (stream, addr) = accept().await?;
+            if self.rate_limiter.lock().unwrap().should_throttle() {
+                self.rejected.fetch_add(1, Ordering::Relaxed);
+                drop(stream);
+                continue;
+            }
+            let stream = LimitedStream {
+                inner: stream,
+                _permit: permit,
+            };
+            return Ok((stream, addr));
+        }
+    }
+
+    /// Returns the number of accepted connections that are still open.
+    pub fn open_connections(&self) -> usize {
+        self.max_concurrent - self.permits.available_permits()
+    }
+
+    /// Returns the number of connections closed for exceeding the rate.
+    pub fn rejected_connections(&self) -> u64 {
+        self.rejected.load(Ordering::Relaxed)
+    }
+
+    /// Returns the current target rate of accepted connections.
+    pub fn target_rate(&self) -> T {
+        self.rate_limiter.lock().unwrap().target_rate()
+    }
+}
+
+/// Connection accepted by a [`ConnectionLimiter`], holding its slot until dropped.
+#[derive(Debug)]
+pub struct LimitedStream<S> {
+    inner: S,
+    _permit: OwnedSemaphorePermit,
+}
+
+impl<S> LimitedStream<S> {
+    /// Returns a reference to the underlying stream.
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+
+    /// Returns a mutable reference to the underlying stream.
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.inner
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for LimitedStream<S> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_read(cx, buf)
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for LimitedStream<S> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+
+    fn poll_write_vectored(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[io::IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write_vectored(cx, bufs)
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.inner.is_write_vectored()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::ManualClock;
+    use crate::RateLimiterBuilder;
+    use std::cell::Cell;
+    use std::task::Waker;
+    use std::time::Duration;
+
+    fn limiter(rate: f64, max_concurrent: usize) -> (ConnectionLimiter<f64>, ManualClock) {
+        let clock = ManualClock::new();
+        let rate_limiter = RateLimiterBuilder::new(rate)
+            .clock(Arc::new(clock.clone()))
+            .build();
+        (ConnectionLimiter::new(rate_limiter, max_concurrent), clock)
+    }
+
+    /// Polls an accept from a listener receiving 10 connections per second, numbering them with
+    /// `accepted`.
+    fn poll_accept(
+        limiter: &ConnectionLimiter<f64>,
+        clock: &ManualClock,
+        accepted: &Cell<u32>,
+    ) -> Poll<LimitedStream<u32>> {
+        let accept = limiter.accept_with(|| {
+            clock.advance(Duration::from_millis(100));
+            accepted.set(accepted.get() + 1);
+            std::future::ready(Ok((accepted.get(), ())))
+        });
+        std::pin::pin!(accept)
+            .poll(&mut Context::from_waker(Waker::noop()))
+            .map(|result| result.unwrap().0)
+    }
+
+    #[test]
+    fn test_limits_open_connections() {
+        let (limiter, clock) = limiter(1000.0, 2);
+        let accepted = Cell::new(0);
+        let Poll::Ready(first) = poll_accept(&limiter, &clock, &accepted) else {
+            panic!("the first connection is accepted")
+        };
+        let Poll::Ready(second) = poll_accept(&limiter, &clock, &accepted) else {
+            panic!("the second connection is accepted")
+        };
+        assert_eq!((*first.get_ref(), *second.get_ref()), (1, 2));
+        assert_eq!(limiter.open_connections(), 2);
+        assert!(poll_accept(&limiter, &clock, &accepted).is_pending());
+        // Nothing is accepted while the limit is reached
+        assert_eq!(accepted.get(), 2);
+
+        drop(first);
+        assert_eq!(limiter.open_connections(), 1);
+        assert!(poll_accept(&limiter, &clock, &accepted).is_ready());
+    }
+
+    #[test]
+    fn test_closes_connections_over_rate() {
+        let (limiter, clock) = limiter(5.0, 100);
+        let accepted = Cell::new(0);
+        let streams = (0..20)
+            .map(|_| match poll_accept(&limiter, &clock, &accepted) {
+                Poll::Ready(stream) => stream,
+                Poll::Pending => panic!("connections are accepted until one is within the rate"),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(limiter.open_connections(), 20);
+        assert_eq!(
+            limiter.rejected_connections(),
+            u64::from(accepted.get()) - 20
+        );
+        assert!(limiter.rejected_connections() > 0);
+        drop(streams);
+        assert_eq!(limiter.open_connections(), 0);
+    }
+}
@@ -0,0 +1,500 @@
+//! Control quality metrics of simulation runs.
+//!
+//! An [`Analyzer`] reduces the ticks of a [`SimulationReport`] to the numbers a control engineer
+//! would read off a plot, so tunings can be compared, or checked in tests, without one:
+//!
+//! - max overshoot: the largest excess of the accepted rate over the setpoint
+//! - settling time: how long the accepted rate took to stay within a tolerance of the reference,
+//!   the request rate capped at the setpoint, until the end of the run
+//! - steady-state error: how far the accepted rate stays from the reference at the end of the
+//!   run
+//! - oscillation amplitude: the peak-to-peak swing of the target rate at the end of the run
+//!
+//! The decisions of a limiter in production, imported as a [`Recording`], are analyzed the same
+//! way through [`Recording::report`], to compare a tuning with the one it would replace.
+//! [`Analyzer::compare`] puts two runs side by side, such as the current and proposed gains
+//! against the same traffic, and tells for each metric whether the candidate improved on the
+//! baseline.
+//!
+//! # Example
+//!
+//! ```rust
+//! use std::time::Duration;
+//!
+//! use nenya::analysis::AnalyzerBuilder;
+//! use nenya::pid_controller::PIDControllerBuilder;
+//! use nenya::simulation::SimulationBuilder;
+//! use nenya::RateLimiterBuilder;
+//!
+//! let rate_limiter = RateLimiterBuilder::new(50.0)
+//!     .min_rate(10.0)
+//!     .max_rate(100.0)
+//!     .pid_controller(PIDControllerBuilder::new(50.0).kp(0.5).ki(0.1).build());
+//! let report = SimulationBuilder::new(rate_limiter, |_: Duration| 80.0)
+//!     .build()
+//!     .run(Duration::from_secs(600));
+//!
+//! let quality = AnalyzerBuilder::new(50.0).build().analyze(&report);
+//! println!(
+//!     "overshoot {:.0}%, settled after {:?}",
+//!     quality.max_overshoot * 100.0,
+//!     quality.settling_time
+//! );
+//! ```
+//!
+//! [`Recording`]: crate::recording::Recording
+//! [`Recording::report`]: crate::recording::Recording::report
+
+use std::fmt;
+use std::time::Duration;
+
+use crate::simulation::{SimulationReport, TickMetrics};
+
+/// Control quality of a simulation run.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct ControlQuality {
+    /// Largest excess of the accepted rate over the setpoint, as a fraction of the setpoint.
+    pub max_overshoot: f64,
+    /// Time from the start of the analysis until the accepted rate stayed within the tolerance
+    /// of the reference, or `None` if it was outside at the end of the run.
+    pub settling_time: Option<Duration>,
+    /// Mean of the reference minus the accepted rate over the steady-state window, in requests
+    /// per second; positive when the limiter admits less than it could.
+    pub steady_state_error: f64,
+    /// Peak-to-peak amplitude of the target rate over the steady-state window, in requests per
+    /// second.
+    pub oscillation_amplitude: f64,
+}
+
+/// How a metric changed from a baseline run to a candidate run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Change {
+    Better,
+    Worse,
+    Unchanged,
+}
+
+impl Change {
+    /// Compares two values of a metric where lower is better.
+    fn lower_is_better(baseline: f64, candidate: f64) -> Self {
+        let epsilon = 1e-9 * baseline.abs().max(1.0);
+        if (candidate - baseline).abs() <= epsilon || candidate == baseline {
+            Change::Unchanged
+        } else if candidate < baseline {
+            Change::Better
+        } else {
+            Change::Worse
+        }
+    }
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Change::Better => write!(f, "better"),
+            Change::Worse => write!(f, "worse"),
+            Change::Unchanged => write!(f, "unchanged"),
+        }
+    }
+}
+
+/// Control quality of two runs against the same traffic, e.g. with the current and proposed
+/// gains.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Comparison {
+    pub baseline: ControlQuality,
+    pub candidate: ControlQuality,
+    /// Fraction of the offered requests the baseline accepted.
+    pub baseline_acceptance: f64,
+    /// Fraction of the offered requests the candidate accepted.
+    pub candidate_acceptance: f64,
+}
+
+impl Comparison {
+    /// Returns how each control quality metric changed from the baseline to the candidate.
+    ///
+    /// Lower overshoot, settling time, absolute steady-state error, and oscillation are better;
+    /// a run that never settled is worse than any that did. The acceptance is not judged, as
+    /// accepting more is only better up to the setpoint, which the other metrics measure.
+    pub fn changes(&self) -> [(&'static str, Change); 4] {
+        let settling = |quality: &ControlQuality| {
+            quality
+                .settling_time
+                .map_or(f64::INFINITY, |time| time.as_secs_f64())
+        };
+        let (baseline, candidate) = (&self.baseline, &self.candidate);
+        [
+            (
+                "max overshoot",
+                Change::lower_is_better(baseline.max_overshoot, candidate.max_overshoot),
+            ),
+            (
+                "settling time",
+                Change::lower_is_better(settling(baseline), settling(candidate)),
+            ),
+            (
+                "steady-state error",
+                Change::lower_is_better(
+                    baseline.steady_state_error.abs(),
+                    candidate.steady_state_error.abs(),
+                ),
+            ),
+            (
+                "oscillation amplitude",
+                Change::lower_is_better(
+                    baseline.oscillation_amplitude,
+                    candidate.oscillation_amplitude,
+                ),
+            ),
+        ]
+    }
+}
+
+impl fmt::Display for Comparison {
+    /// Formats the metrics of both runs as a table, with the change of each.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let settling = |quality: &ControlQuality| match quality.settling_time {
+            Some(time) => format!("{:.1}s", time.as_secs_f64()),
+            None => "never".to_string(),
+        };
+        let percent = |value: f64| format!("{:.1}%", value * 100.0);
+        let rate = |value: f64| format!("{:.2}", value);
+        let (baseline, candidate) = (&self.baseline, &self.candidate);
+        let values = [
+            (
+                percent(baseline.max_overshoot),
+                percent(candidate.max_overshoot),
+            ),
+            (settling(baseline), settling(candidate)),
+            (
+                rate(baseline.steady_state_error),
+                rate(candidate.steady_state_error),
+            ),
+            (
+                rate(baseline.oscillation_amplitude),
+                rate(candidate.oscillation_amplitude),
+            ),
+        ];
+
+        writeln!(
+            f,
+            "{:<22} {:>10} {:>10}  change",
+            "metric", "baseline", "candidate"
+        )?;
+        for ((name, change), (baseline, candidate)) in self.changes().iter().zip(values) {
+            writeln!(
+                f,
+                "{:<22} {:>10} {:>10}  {}",
+                name, baseline, candidate, change
+            )?;
+        }
+        write!(
+            f,
+            "{:<22} {:>10} {:>10}",
+            "accepted",
+            percent(self.baseline_acceptance),
+            percent(self.candidate_acceptance)
+        )
+    }
+}
+
+/// Computes the control quality of simulation runs against a setpoint.
+#[derive(Debug, Clone)]
+pub struct Analyzer {
+    setpoint: f64,
+    tolerance: f64,
+    start: Duration,
+    steady_state_window: Option<Duration>,
+}
+
+impl Analyzer {
+    /// Returns the control quality of `report`.
+    ///
+    /// Ticks before the start of the analysis are ignored. A report without ticks after it has
+    /// no overshoot, error, or oscillation, and has not settled.
+    pub fn analyze(&self, report: &SimulationReport) -> ControlQuality {
+        let ticks: Vec<&TickMetrics> = report
+            .ticks
+            .iter()
+            .filter(|tick| tick.elapsed > self.start)
+            .collect();
+        let Some(last) = ticks.last() else {
+            return ControlQuality {
+                max_overshoot: 0.0,
+                settling_time: None,
+                steady_state_error: 0.0,
+                oscillation_amplitude: 0.0,
+            };
+        };
+
+        let max_overshoot = ticks
+            .iter()
+            .map(|tick| (tick.accepted_rate - self.setpoint) / self.setpoint)
+            .fold(0.0, f64::max);
+
+        // Settled from the end of the last tick outside the band, or the start if there is none
+        let band = self.tolerance * self.setpoint;
+        let settling_time = match ticks
+            .iter()
+            .rposition(|tick| (tick.accepted_rate - self.reference(tick)).abs() > band)
+        {
+            Some(index) if index == ticks.len() - 1 => None,
+            Some(index) => Some(ticks[index].elapsed - self.start),
+            None => Some(Duration::ZERO),
+        };
+
+        let window = self
+            .steady_state_window
+            .unwrap_or_else(|| (last.elapsed - self.start) / 10);
+        let steady: Vec<&&TickMetrics> = ticks
+            .iter()
+            .filter(|tick| last.elapsed - tick.elapsed < window.max(Duration::from_nanos(1)))
+            .collect();
+        let steady_state_error = steady
+            .iter()
+            .map(|tick| self.reference(tick) - tick.accepted_rate)
+            .sum::<f64>()
+            / steady.len() as f64;
+        let (min, max) = steady
+            .iter()
+            .fold((f64::INFINITY, f64::NEG_INFINITY), |(min, max), tick| {
+                (min.min(tick.target_rate), max.max(tick.target_rate))
+            });
+
+        ControlQuality {
+            max_overshoot,
+            settling_time,
+            steady_state_error,
+            oscillation_amplitude: max - min,
+        }
+    }
+
+    /// Returns the control quality of `baseline` and `candidate`, two runs against the same
+    /// traffic, side by side.
+    pub fn compare(&self, baseline: &SimulationReport, candidate: &SimulationReport) -> Comparison {
+        Comparison {
+            baseline: self.analyze(baseline),
+            candidate: self.analyze(candidate),
+            baseline_acceptance: self.acceptance(baseline),
+            candidate_acceptance: self.acceptance(candidate),
+        }
+    }
+
+    /// Returns the fraction of the requests offered after the start that `report` accepted, or
+    /// one if none were offered.
+    fn acceptance(&self, report: &SimulationReport) -> f64 {
+        let (offered, accepted) = report
+            .ticks
+            .iter()
+            .filter(|tick| tick.elapsed > self.start)
+            .fold((0, 0), |(offered, accepted), tick| {
+                (offered + tick.offered, accepted + tick.accepted)
+            });
+        if offered == 0 {
+            1.0
+        } else {
+            accepted as f64 / offered as f64
+        }
+    }
+
+    /// Returns the accepted rate an ideal limiter would reach at `tick`.
+    fn reference(&self, tick: &TickMetrics) -> f64 {
+        tick.request_rate.min(self.setpoint)
+    }
+}
+
+/// Builder for creating an `Analyzer` instance.
+pub struct AnalyzerBuilder {
+    setpoint: f64,
+    tolerance: f64,
+    start: Duration,
+    steady_state_window: Option<Duration>,
+}
+
+impl AnalyzerBuilder {
+    /// Creates a new `AnalyzerBuilder` for runs of a limiter with `setpoint`, the target rate it
+    /// was configured with.
+    ///
+    /// By default the tolerance is 5% of the setpoint, the analysis starts at the start of the
+    /// run, and the steady-state window is the last 10% of the analyzed ticks.
+    pub fn new(setpoint: f64) -> Self {
+        AnalyzerBuilder {
+            setpoint,
+            tolerance: 0.05,
+            start: Duration::ZERO,
+            steady_state_window: None,
+        }
+    }
+
+    /// Sets the distance from the reference, as a fraction of the setpoint, within which the
+    /// accepted rate counts as settled.
+    pub fn tolerance(mut self, tolerance: f64) -> Self {
+        self.tolerance = tolerance;
+        self
+    }
+
+    /// Sets the time of the run the analysis starts at, e.g. a disturbance to measure the
+    /// response to.
+    pub fn start(mut self, start: Duration) -> Self {
+        self.start = start;
+        self
+    }
+
+    /// Sets the time at the end of the run the steady-state error and oscillation are measured
+    /// over.
+    pub fn steady_state_window(mut self, steady_state_window: Duration) -> Self {
+        self.steady_state_window = Some(steady_state_window);
+        self
+    }
+
+    /// Builds and returns the `Analyzer` instance.
+    pub fn build(self) -> Analyzer {
+        Analyzer {
+            setpoint: self.setpoint,
+            tolerance: self.tolerance,
+            start: self.start,
+            steady_state_window: self.steady_state_window,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Report of one tick per second with the given accepted and target rates, and requests
+    /// offered at 80 per second.
+    fn report(rates: &[(f64, f64)]) -> SimulationReport {
+        let ticks = rates
+            .iter()
+            .enumerate()
+            .map(|(index, &(accepted_rate, target_rate))| TickMetrics {
+                elapsed: Duration::from_secs(index as u64 + 1),
+                generated_rate: 80.0,
+                offered: 80,
+                accepted: accepted_rate as u64,
+                throttled: 80 - accepted_rate as u64,
+                target_rate,
+                request_rate: 80.0,
+                accepted_rate,
+                pid_p: 0.0,
+                pid_i: 0.0,
+                pid_d: 0.0,
+                pid_output: 0.0,
+                latency: 0.0,
+                error_rate: 0.0,
+            })
+            .collect();
+        SimulationReport { ticks }
+    }
+
+    #[test]
+    fn test_computes_step_response_metrics() {
+        let report = report(&[
+            (80.0, 50.0),
+            (65.0, 40.0),
+            (44.0, 45.0),
+            (51.0, 52.0),
+            (49.0, 48.0),
+            (49.0, 49.0),
+            (49.0, 51.0),
+            (49.0, 49.0),
+            (49.0, 51.0),
+            (49.0, 49.0),
+        ]);
+        let quality = AnalyzerBuilder::new(50.0).build().analyze(&report);
+        assert!((quality.max_overshoot - 0.6).abs() < 1e-9);
+        assert_eq!(quality.settling_time, Some(Duration::from_secs(3)));
+        assert_eq!(quality.steady_state_error, 1.0);
+        assert_eq!(quality.oscillation_amplitude, 0.0);
+
+        let quality = AnalyzerBuilder::new(50.0)
+            .tolerance(0.01)
+            .steady_state_window(Duration::from_secs(4))
+            .build()
+            .analyze(&report);
+        assert_eq!(quality.settling_time, None);
+        assert_eq!(quality.oscillation_amplitude, 2.0);
+
+        let quality = AnalyzerBuilder::new(50.0)
+            .start(Duration::from_secs(2))
+            .build()
+            .analyze(&report);
+        assert!((quality.max_overshoot - 0.02).abs() < 1e-9);
+        assert_eq!(quality.settling_time, Some(Duration::from_secs(1)));
+    }
+
+    #[test]
+    fn test_analyzes_simulation_runs() {
+        use crate::pid_controller::PIDControllerBuilder;
+        use crate::simulation::SimulationBuilder;
+        use crate::RateLimiterBuilder;
+
+        let fixed = SimulationBuilder::new(RateLimiterBuilder::new(50.0), |_: Duration| 30.0)
+            .build()
+            .run(Duration::from_secs(60));
+        let quality = AnalyzerBuilder::new(50.0).build().analyze(&fixed);
+        assert_eq!(quality.max_overshoot, 0.0);
+        assert!(quality.settling_time.unwrap() < Duration::from_secs(2));
+        assert!(quality.steady_state_error.abs() < 1.0, "{quality:?}");
+        assert_eq!(quality.oscillation_amplitude, 0.0);
+
+        // Light traffic lets the controller raise the target to the maximum, so a burst after it
+        // is admitted well above the setpoint
+        let rate_limiter = RateLimiterBuilder::new(50.0)
+            .min_rate(10.0)
+            .max_rate(100.0)
+            .pid_controller(PIDControllerBuilder::new(50.0).kp(0.5).ki(0.1).build());
+        let adaptive = SimulationBuilder::new(rate_limiter, |elapsed: Duration| {
+            if elapsed < Duration::from_secs(30) {
+                30.0
+            } else {
+                80.0
+            }
+        })
+        .build()
+        .run(Duration::from_secs(60));
+        let quality = AnalyzerBuilder::new(50.0)
+            .start(Duration::from_secs(30))
+            .build()
+            .analyze(&adaptive);
+        assert!(quality.max_overshoot > 0.5, "{quality:?}");
+        assert_eq!(quality.settling_time, None);
+        assert!(quality.steady_state_error < -10.0, "{quality:?}");
+
+        assert_eq!(
+            AnalyzerBuilder::new(50.0)
+                .build()
+                .analyze(&SimulationReport::default())
+                .settling_time,
+            None
+        );
+    }
+
+    #[test]
+    fn test_compares_runs() {
+        let baseline = report(&[(80.0, 50.0), (65.0, 40.0), (44.0, 45.0), (52.0, 52.0)]);
+        let candidate = report(&[(60.0, 50.0), (49.0, 48.0), (50.0, 50.0), (50.0, 50.0)]);
+        let analyzer = AnalyzerBuilder::new(50.0).build();
+        let comparison = analyzer.compare(&baseline, &candidate);
+        assert_eq!(
+            comparison.changes(),
+            [
+                ("max overshoot", Change::Better),
+                ("settling time", Change::Better),
+                ("steady-state error", Change::Better),
+                ("oscillation amplitude", Change::Unchanged),
+            ]
+        );
+        assert_eq!(comparison.baseline_acceptance, 241.0 / 320.0);
+
+        let reversed = analyzer.compare(&candidate, &baseline);
+        assert_eq!(reversed.changes()[0].1, Change::Worse);
+        let table = reversed.to_string();
+        assert!(table.contains("max overshoot"), "{table}");
+        assert!(table.contains("20.0%"), "{table}");
+        assert!(table.contains("worse"), "{table}");
+    }
+}
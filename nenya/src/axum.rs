@@ -0,0 +1,368 @@
+//! [Axum](https://docs.rs/axum) middleware limiting each client to its own rate.
+//!
+//! [`KeyedRateLimitLayer`] picks a key for every request with a [`KeyExtractor`], such as the
+//! client's IP address, an API key header, or the matched route, and checks it against a
+//! [`KeyedRateLimiter`]. Throttled
+//! requests are answered with `429 Too Many Requests` and a `Retry-After` header. Every response
+//! carries the `RateLimit-Limit`, `RateLimit-Remaining`, and `RateLimit-Reset` headers so
+//! well-behaved clients can pace themselves.
+//!
+//! # Example
+//!
+//! ```rust
+//! use std::sync::{Arc, Mutex};
+//!
+//! use axum::{routing::get, Router};
+//! use nenya::axum::KeyedRateLimitLayer;
+//! use nenya::key::PeerIp;
+//! use nenya::keyed::KeyedRateLimiter;
+//!
+//! let limiter = Arc::new(Mutex::new(KeyedRateLimiter::new(10.0)));
+//! let app: Router = Router::new()
+//!     .route("/", get(|| async { "hello" }))
+//!     .layer(KeyedRateLimitLayer::new(limiter, PeerIp::new()));
+//! ```
+//!
+//! [`PeerIp`](crate::key::PeerIp) reads the address axum records when the app is served with
+//! `into_make_service_with_connect_info::<SocketAddr>()`.
+//!
+//! With [`KeyedRateLimitLayer::latency_target`] and [`KeyedRateLimitLayer::error_target`], the
+//! layer also records the latency and status of every response it passes through, and lowers
+//! the rate of all keys while the app is slow or failing with `5xx` responses.
+
+use num_traits::{Float, FromPrimitive, Signed};
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use axum::http::header::{HeaderMap, HeaderName, HeaderValue, RETRY_AFTER};
+use axum::http::{Request, StatusCode};
+use axum::response::{IntoResponse, Response};
+use tower_layer::Layer;
+use tower_service::Service;
+
+use crate::feedback::ResponseFeedback;
+use crate::key::KeyExtractor;
+use crate::keyed::KeyedRateLimiter;
+
+const RATELIMIT_LIMIT: HeaderName = HeaderName::from_static("ratelimit-limit");
+const RATELIMIT_REMAINING: HeaderName = HeaderName::from_static("ratelimit-remaining");
+const RATELIMIT_RESET: HeaderName = HeaderName::from_static("ratelimit-reset");
+
+/// Outcome of checking a request against its key's limit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateLimitDecision {
+    pub throttled: bool,
+    /// Requests per second the key is limited to.
+    pub limit: u64,
+    /// Requests per second the key has left.
+    pub remaining: u64,
+    /// How long until the key's limit has room again, zero if it has room now.
+    pub reset: Duration,
+}
+
+impl RateLimitDecision {
+    /// Checks a request from `key` against `limiter`.
+    pub fn check<T>(limiter: &mut KeyedRateLimiter<String, T>, key: &String) -> Self
+    where
+        T: Float + Signed + FromPrimitive + Copy,
+    {
+        let throttled = limiter.should_throttle(key);
+        let rate = |rate: T| rate.floor().to_u64().unwrap_or(0);
+        RateLimitDecision {
+            throttled,
+            limit: rate(limiter.key_rate()),
+            remaining: rate(limiter.remaining_capacity(key)),
+            reset: limiter.retry_after(key),
+        }
+    }
+
+    /// Adds the `RateLimit-*` headers describing the decision to `headers`.
+    pub fn insert_headers(&self, headers: &mut HeaderMap) {
+        headers.insert(RATELIMIT_LIMIT, HeaderValue::from(self.limit));
+        headers.insert(RATELIMIT_REMAINING, HeaderValue::from(self.remaining));
+        headers.insert(RATELIMIT_RESET, HeaderValue::from(ceil_secs(self.reset)));
+    }
+}
+
+impl IntoResponse for RateLimitDecision {
+    /// Returns the `429 Too Many Requests` response for a throttled request.
+    fn into_response(self) -> Response {
+        let mut response = (StatusCode::TOO_MANY_REQUESTS, "Too Many Requests").into_response();
+        let headers = response.headers_mut();
+        self.insert_headers(headers);
+        headers.insert(RETRY_AFTER, HeaderValue::from(ceil_secs(self.reset).max(1)));
+        response
+    }
+}
+
+/// Rounds `duration` up to whole seconds, as the headers are in seconds.
+fn ceil_secs(duration: Duration) -> u64 {
+    duration.as_secs() + u64::from(duration.subsec_nanos() > 0)
+}
+
+/// Applies [`KeyedRateLimit`] to services.
+pub struct KeyedRateLimitLayer<T, E> {
+    limiter: Arc<Mutex<KeyedRateLimiter<String, T>>>,
+    extractor: E,
+    feedback: Option<Arc<Mutex<ResponseFeedback<T>>>>,
+}
+
+impl<T, E> KeyedRateLimitLayer<T, E> {
+    /// Creates a layer limiting the requests of each key from `extractor` with `limiter`.
+    pub fn new(limiter: Arc<Mutex<KeyedRateLimiter<String, T>>>, extractor: E) -> Self {
+        KeyedRateLimitLayer {
+            limiter,
+            extractor,
+            feedback: None,
+        }
+    }
+}
+
+impl<T: Float + Signed + FromPrimitive + Copy, E> KeyedRateLimitLayer<T, E> {
+    /// Lowers the rate of every key while the smoothed response latency exceeds
+    /// `target_latency`.
+    pub fn latency_target(mut self, target_latency: Duration) -> Self {
+        ResponseFeedback::configure(&mut self.feedback).set_latency_target(target_latency);
+        self
+    }
+
+    /// Lowers the rate of every key while the smoothed fraction of `5xx` responses exceeds
+    /// `error_target`.
+    pub fn error_target(mut self, error_target: f64) -> Self {
+        ResponseFeedback::configure(&mut self.feedback).set_error_target(error_target);
+        self
+    }
+}
+
+impl<T, E: Clone> Clone for KeyedRateLimitLayer<T, E> {
+    fn clone(&self) -> Self {
+        KeyedRateLimitLayer {
+            limiter: self.limiter.clone(),
+            extractor: self.extractor.clone(),
+            feedback: self.feedback.clone(),
+        }
+    }
+}
+
+impl<T: fmt::Debug, E: fmt::Debug> fmt::Debug for KeyedRateLimitLayer<T, E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("KeyedRateLimitLayer")
+            .field("limiter", &self.limiter)
+            .field("extractor", &self.extractor)
+            .field("feedback", &self.feedback)
+            .finish()
+    }
+}
+
+impl<S, T, E: Clone> Layer<S> for KeyedRateLimitLayer<T, E> {
+    type Service = KeyedRateLimit<S, T, E>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        KeyedRateLimit {
+            inner,
+            limiter: self.limiter.clone(),
+            extractor: self.extractor.clone(),
+            feedback: self.feedback.clone(),
+        }
+    }
+}
+
+/// Service answering requests over their key's limit with `429 Too Many Requests`.
+pub struct KeyedRateLimit<S, T, E> {
+    inner: S,
+    limiter: Arc<Mutex<KeyedRateLimiter<String, T>>>,
+    extractor: E,
+    feedback: Option<Arc<Mutex<ResponseFeedback<T>>>>,
+}
+
+impl<S: Clone, T, E: Clone> Clone for KeyedRateLimit<S, T, E> {
+    fn clone(&self) -> Self {
+        KeyedRateLimit {
+            inner: self.inner.clone(),
+            limiter: self.limiter.clone(),
+            extractor: self.extractor.clone(),
+            feedback: self.feedback.clone(),
+        }
+    }
+}
+
+impl<S: fmt::Debug, T: fmt::Debug, E: fmt::Debug> fmt::Debug for KeyedRateLimit<S, T, E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("KeyedRateLimit")
+            .field("inner", &self.inner)
+            .field("limiter", &self.limiter)
+            .field("extractor", &self.extractor)
+            .field("feedback", &self.feedback)
+            .finish()
+    }
+}
+
+impl<S, B, T, E> Service<Request<B>> for KeyedRateLimit<S, T, E>
+where
+    S: Service<Request<B>, Response = Response>,
+    S::Error: Send + 'static,
+    S::Future: Send + 'static,
+    T: Float + Signed + FromPrimitive + Copy + Send + 'static,
+    E: KeyExtractor<Request<B>>,
+{
+    type Response = Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Response, S::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request<B>) -> Self::Future {
+        let key = self.extractor.extract(&request).unwrap_or_default();
+        let decision = RateLimitDecision::check(&mut self.limiter.lock().unwrap(), &key);
+        if decision.throttled {
+            return Box::pin(std::future::ready(Ok(decision.into_response())));
+        }
+
+        let response = self.inner.call(request);
+        let feedback = self.feedback.clone();
+        let limiter = self.limiter.clone();
+        let started = Instant::now();
+        Box::pin(async move {
+            let response = response.await;
+            if let Some(feedback) = feedback {
+                let failed = response
+                    .as_ref()
+                    .map_or(true, |response| response.status().is_server_error());
+                let mut limiter = limiter.lock().unwrap();
+                let key_rate =
+                    feedback
+                        .lock()
+                        .unwrap()
+                        .observe(started.elapsed(), failed, limiter.key_rate());
+                limiter.set_key_rate(key_rate);
+            }
+            let mut response = response?;
+            decision.insert_headers(response.headers_mut());
+            Ok(response)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::key::Header;
+    use axum::body::Body;
+    use std::convert::Infallible;
+    use std::task::Waker;
+
+    #[derive(Clone)]
+    struct Ok200;
+
+    impl Service<Request<Body>> for Ok200 {
+        type Response = Response;
+        type Error = Infallible;
+        type Future = std::future::Ready<Result<Response, Infallible>>;
+
+        fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Infallible>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, _request: Request<Body>) -> Self::Future {
+            std::future::ready(Ok(StatusCode::OK.into_response()))
+        }
+    }
+
+    fn request(api_key: &str) -> Request<Body> {
+        Request::builder()
+            .uri("/orders/1")
+            .header("x-api-key", api_key)
+            .body(Body::empty())
+            .unwrap()
+    }
+
+    fn respond(service: &mut KeyedRateLimit<Ok200, f64, Header>, api_key: &str) -> Response {
+        let mut future = service.call(request(api_key));
+        match future
+            .as_mut()
+            .poll(&mut Context::from_waker(Waker::noop()))
+        {
+            Poll::Ready(Ok(response)) => response,
+            Poll::Ready(Err(err)) => match err {},
+            Poll::Pending => panic!("responses are always ready"),
+        }
+    }
+
+    #[test]
+    fn test_throttled_keys_get_429_with_headers() {
+        let limiter = Arc::new(Mutex::new(KeyedRateLimiter::new(5.0)));
+        let extractor = Header(HeaderName::from_static("x-api-key"));
+        let mut service = KeyedRateLimitLayer::new(limiter, extractor).layer(Ok200);
+
+        let first = respond(&mut service, "noisy");
+        assert_eq!(first.status(), StatusCode::OK);
+        assert_eq!(first.headers()[RATELIMIT_LIMIT], "5");
+
+        let throttled = (0..20)
+            .map(|_| respond(&mut service, "noisy"))
+            .find(|response| response.status() == StatusCode::TOO_MANY_REQUESTS)
+            .unwrap();
+        let headers = throttled.headers();
+        assert!(headers.contains_key(RETRY_AFTER));
+        assert_eq!(headers[RATELIMIT_REMAINING], "0");
+        assert_ne!(headers[RATELIMIT_RESET], "0");
+
+        assert_eq!(respond(&mut service, "quiet").status(), StatusCode::OK);
+    }
+
+    #[derive(Clone)]
+    struct Unavailable;
+
+    impl Service<Request<Body>> for Unavailable {
+        type Response = Response;
+        type Error = Infallible;
+        type Future = std::future::Ready<Result<Response, Infallible>>;
+
+        fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Infallible>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, _request: Request<Body>) -> Self::Future {
+            std::future::ready(Ok(StatusCode::SERVICE_UNAVAILABLE.into_response()))
+        }
+    }
+
+    #[test]
+    fn test_server_errors_lower_key_rate() {
+        let limiter = Arc::new(Mutex::new(KeyedRateLimiter::new(100.0)));
+        let extractor = Header(HeaderName::from_static("x-api-key"));
+        let mut service = KeyedRateLimitLayer::new(limiter.clone(), extractor)
+            .error_target(0.1)
+            .layer(Unavailable);
+
+        for _ in 0..10 {
+            let mut future = service.call(request("abc"));
+            let _ = future
+                .as_mut()
+                .poll(&mut Context::from_waker(Waker::noop()));
+        }
+        let key_rate = limiter.lock().unwrap().key_rate();
+        assert!((10.0..100.0).contains(&key_rate), "{key_rate}");
+    }
+
+    #[test]
+    fn test_reads_axum_connect_info() {
+        use crate::key::PeerIp;
+        use axum::extract::ConnectInfo;
+        use std::net::SocketAddr;
+
+        let mut request = request("abc");
+        let addr: SocketAddr = "192.0.2.1:443".parse().unwrap();
+        request.extensions_mut().insert(ConnectInfo(addr));
+        assert_eq!(
+            PeerIp::new().extract(&request),
+            Some("192.0.2.1".to_string())
+        );
+    }
+}
@@ -0,0 +1,166 @@
+//! Helpers measuring the throughput of throttling decisions.
+//!
+//! [`single_threaded`] times a run of decisions on one thread, and [`contended`] times decisions
+//! made by several threads sharing one limiter behind a mutex, as a limiter shared across a
+//! server's worker threads is. [`concurrent`] does the same without the mutex, for limiters
+//! deciding through `&self`. All work with any limiter through a closure returning whether
+//! the request was throttled, so they can compare limiters, or configurations of one, in an
+//! application's own tests or benchmarks. The crate's criterion benchmarks use them for the
+//! contended cases:
+//!
+//! ```sh
+//! cargo bench -p nenya --bench decisions
+//! ```
+//!
+//! # Example
+//!
+//! ```rust
+//! use nenya::bench;
+//! use nenya::token_bucket::TokenBucketBuilder;
+//!
+//! let mut bucket = TokenBucketBuilder::new(1000.0).build();
+//! let throughput = bench::single_threaded(10_000, || bucket.should_throttle());
+//! println!(
+//!     "{:.0} decisions/s, {:?} each",
+//!     throughput.decisions_per_sec(),
+//!     throughput.mean_latency()
+//! );
+//!
+//! let bucket = TokenBucketBuilder::new(1000.0).build();
+//! let throughput = bench::contended(bucket, 4, 10_000, |bucket| bucket.should_throttle());
+//! assert_eq!(throughput.decisions, 40_000);
+//! ```
+
+use std::sync::{Barrier, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Decisions made during a measurement and the wall time they took.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Throughput {
+    pub decisions: u64,
+    /// Decisions that throttled the request.
+    pub throttled: u64,
+    pub elapsed: Duration,
+}
+
+impl Throughput {
+    /// Returns the decisions made per second of wall time.
+    pub fn decisions_per_sec(&self) -> f64 {
+        if self.elapsed.is_zero() {
+            return 0.0;
+        }
+        self.decisions as f64 / self.elapsed.as_secs_f64()
+    }
+
+    /// Returns the wall time per decision, or zero without decisions.
+    ///
+    /// With several threads this is the time between decisions of the limiter, not the latency
+    /// one caller sees, which includes waiting for the lock.
+    pub fn mean_latency(&self) -> Duration {
+        if self.decisions == 0 {
+            return Duration::ZERO;
+        }
+        Duration::from_secs_f64(self.elapsed.as_secs_f64() / self.decisions as f64)
+    }
+}
+
+/// Makes `decisions` decisions with `decide`, which returns whether a request was throttled, and
+/// times them.
+pub fn single_threaded(decisions: u64, mut decide: impl FnMut() -> bool) -> Throughput {
+    let start = Instant::now();
+    let throttled = (0..decisions).filter(|_| decide()).count() as u64;
+    Throughput {
+        decisions,
+        throttled,
+        elapsed: start.elapsed(),
+    }
+}
+
+/// Makes `decisions_per_thread` decisions with `decide` on each of `threads` threads sharing
+/// `limiter` behind a mutex, and times them from when all threads are ready until the last one
+/// finishes.
+pub fn contended<L: Send>(
+    limiter: L,
+    threads: usize,
+    decisions_per_thread: u64,
+    decide: impl Fn(&mut L) -> bool + Sync,
+) -> Throughput {
+    concurrent(
+        &Mutex::new(limiter),
+        threads,
+        decisions_per_thread,
+        |limiter| decide(&mut limiter.lock().unwrap()),
+    )
+}
+
+/// Makes `decisions_per_thread` decisions with `decide` on each of `threads` threads sharing
+/// `limiter` by reference, for limiters deciding through `&self`, and times them like
+/// [`contended`].
+pub fn concurrent<L: Sync>(
+    limiter: &L,
+    threads: usize,
+    decisions_per_thread: u64,
+    decide: impl Fn(&L) -> bool + Sync,
+) -> Throughput {
+    // The measuring thread waits at the barrier too, so it starts the clock with the workers
+    let barrier = Barrier::new(threads + 1);
+    thread::scope(|scope| {
+        let workers: Vec<_> = (0..threads)
+            .map(|_| {
+                scope.spawn(|| {
+                    barrier.wait();
+                    (0..decisions_per_thread)
+                        .filter(|_| decide(limiter))
+                        .count() as u64
+                })
+            })
+            .collect();
+        barrier.wait();
+        let start = Instant::now();
+        let throttled = workers
+            .into_iter()
+            .map(|worker| worker.join().unwrap())
+            .sum();
+        Throughput {
+            decisions: decisions_per_thread * threads as u64,
+            throttled,
+            elapsed: start.elapsed(),
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_counts_decisions() {
+        let mut count = 0;
+        let throughput = single_threaded(10, || {
+            count += 1;
+            count % 2 == 0
+        });
+        assert_eq!(throughput.decisions, 10);
+        assert_eq!(throughput.throttled, 5);
+        assert!(throughput.decisions_per_sec() > 0.0);
+
+        let throughput = contended(0_u64, 4, 100, |count| {
+            *count += 1;
+            *count > 300
+        });
+        assert_eq!(throughput.decisions, 400);
+        assert_eq!(throughput.throttled, 100);
+        assert!(throughput.mean_latency() <= throughput.elapsed);
+
+        let count = std::sync::atomic::AtomicU64::new(0);
+        let throughput = concurrent(&count, 4, 100, |count| {
+            count.fetch_add(1, std::sync::atomic::Ordering::Relaxed) >= 300
+        });
+        assert_eq!(throughput.decisions, 400);
+        assert_eq!(throughput.throttled, 100);
+
+        assert_eq!(Throughput::default().decisions_per_sec(), 0.0);
+        assert_eq!(Throughput::default().mean_latency(), Duration::ZERO);
+    }
+}
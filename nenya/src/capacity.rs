@@ -0,0 +1,272 @@
+//! Estimation of downstream capacity from concurrency and latency, by Little's law.
+//!
+//! Little's law relates the mean number of requests in flight `L`, their throughput `λ`, and
+//! their mean latency `W` as `L = λW`. A downstream that serves at most `L` requests at once, e.g.
+//! through a connection pool or a fixed number of workers, therefore completes at most `L / W`
+//! requests per second. A [`CapacityEstimator`] applies this to the requests of a sliding window:
+//!
+//! - The concurrency is the configured concurrency limit or, without one, the highest
+//!   concurrency observed in the window.
+//! - The latency is the mean latency of the requests in the window.
+//!
+//! The estimate, scaled by a headroom factor, is the recommended `max_rate` of the rate limiter
+//! in front of the downstream. Given the limiter with [`CapacityEstimatorBuilder::rate_limiter`],
+//! the estimator sets it as the limiter's maximum rate with every recorded request.
+//!
+//! # Example
+//!
+//! ```rust
+//! use std::sync::{Arc, Mutex};
+//! use std::time::Duration;
+//!
+//! use nenya::capacity::CapacityEstimatorBuilder;
+//! use nenya::RateLimiterBuilder;
+//!
+//! let rate_limiter = Arc::new(Mutex::new(
+//!     RateLimiterBuilder::new(100.0).min_rate(1.0).max_rate(1000.0).build(),
+//! ));
+//! let mut estimator = CapacityEstimatorBuilder::new()
+//!     .concurrency_limit(8)
+//!     .headroom(0.5)
+//!     .rate_limiter(rate_limiter.clone())
+//!     .build();
+//!
+//! // A request completed in 125 ms while 5 were in flight
+//! estimator.record(5, Duration::from_millis(125));
+//! assert_eq!(rate_limiter.lock().unwrap().max_rate(), 32.0);
+//! ```
+
+use num_traits::{Float, FromPrimitive, Signed};
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::clock::{Clock, SystemClock, Timestamp};
+use crate::RateLimiter;
+
+/// A completed request.
+#[derive(Debug, Clone, Copy)]
+struct Sample {
+    completed_at: Timestamp,
+    concurrency: usize,
+    latency: Duration,
+}
+
+/// Estimates the capacity of a downstream from the concurrency and latency of its requests.
+#[derive(Debug)]
+pub struct CapacityEstimator<T> {
+    window: Duration,
+    concurrency_limit: Option<usize>,
+    headroom: f64,
+    clock: Arc<dyn Clock>,
+    rate_limiter: Option<Arc<Mutex<RateLimiter<T>>>>,
+    samples: VecDeque<Sample>,
+    /// Sum of the latencies in `samples`.
+    total_latency: Duration,
+}
+
+impl<T: Float + Signed + FromPrimitive + Copy> CapacityEstimator<T> {
+    /// Records a request that completed after `latency`, with `concurrency` requests in flight
+    /// including itself.
+    ///
+    /// If the estimator was given a rate limiter, the new recommended maximum rate is applied to
+    /// it.
+    pub fn record(&mut self, concurrency: usize, latency: Duration) {
+        let now = self.clock.now();
+        self.samples.push_back(Sample {
+            completed_at: now,
+            concurrency,
+            latency,
+        });
+        self.total_latency += latency;
+        self.trim(now);
+
+        if let Some(rate_limiter) = &self.rate_limiter {
+            self.apply(&mut rate_limiter.lock().unwrap());
+        }
+    }
+
+    /// Sets the maximum rate of `rate_limiter` to the recommended maximum rate, if there is one.
+    ///
+    /// The minimum rate is kept, and the maximum rate does not go below it.
+    pub fn apply(&self, rate_limiter: &mut RateLimiter<T>) {
+        if let Some(max_rate) = self.recommended_max_rate() {
+            let min_rate = rate_limiter.min_rate();
+            rate_limiter.set_rate_bounds(min_rate, max_rate.max(min_rate));
+        }
+    }
+
+    /// Returns the recommended maximum rate: the estimated capacity scaled by the headroom, or
+    /// `None` while the window holds no requests.
+    pub fn recommended_max_rate(&self) -> Option<T> {
+        self.estimated_capacity()
+            .map(|capacity| capacity * T::from_f64(self.headroom).unwrap())
+    }
+
+    /// Returns the estimated capacity in requests per second, or `None` while the window holds
+    /// no requests.
+    pub fn estimated_capacity(&self) -> Option<T> {
+        let concurrency = self.concurrency()?;
+        let latency = self.mean_latency()?.as_secs_f64();
+        if latency <= 0.0 {
+            return None;
+        }
+        T::from_f64(concurrency as f64 / latency)
+    }
+
+    /// Returns the concurrency the capacity is estimated at: the concurrency limit if configured,
+    /// otherwise the highest concurrency in the window.
+    pub fn concurrency(&self) -> Option<usize> {
+        self.concurrency_limit
+            .or_else(|| self.samples.iter().map(|sample| sample.concurrency).max())
+    }
+
+    /// Returns the mean latency of the requests in the window.
+    pub fn mean_latency(&self) -> Option<Duration> {
+        let count = u32::try_from(self.samples.len()).ok().filter(|&n| n > 0)?;
+        Some(self.total_latency / count)
+    }
+
+    /// Removes the requests that completed before the window.
+    fn trim(&mut self, now: Timestamp) {
+        while let Some(sample) = self.samples.front() {
+            if now.duration_since(sample.completed_at) <= self.window {
+                break;
+            }
+            self.total_latency -= sample.latency;
+            self.samples.pop_front();
+        }
+    }
+}
+
+/// Builder for creating a `CapacityEstimator` instance.
+pub struct CapacityEstimatorBuilder<T> {
+    window: Duration,
+    concurrency_limit: Option<usize>,
+    headroom: f64,
+    clock: Option<Arc<dyn Clock>>,
+    rate_limiter: Option<Arc<Mutex<RateLimiter<T>>>>,
+}
+
+impl<T: Float + Signed + FromPrimitive + Copy> CapacityEstimatorBuilder<T> {
+    /// Creates a new `CapacityEstimatorBuilder` with a 10 second window and no headroom.
+    pub fn new() -> Self {
+        CapacityEstimatorBuilder {
+            window: Duration::from_secs(10),
+            concurrency_limit: None,
+            headroom: 1.0,
+            clock: None,
+            rate_limiter: None,
+        }
+    }
+
+    /// Sets how long completed requests count towards the estimate.
+    pub fn window(mut self, window: Duration) -> Self {
+        self.window = window;
+        self
+    }
+
+    /// Sets the number of requests the downstream serves at once.
+    ///
+    /// Without a limit, the highest observed concurrency is used, which underestimates the
+    /// capacity until the downstream has been saturated within the window.
+    pub fn concurrency_limit(mut self, concurrency_limit: usize) -> Self {
+        self.concurrency_limit = Some(concurrency_limit);
+        self
+    }
+
+    /// Sets the fraction of the estimated capacity recommended as the maximum rate.
+    pub fn headroom(mut self, headroom: f64) -> Self {
+        self.headroom = headroom;
+        self
+    }
+
+    /// Sets the clock the window is measured with.
+    pub fn clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = Some(clock);
+        self
+    }
+
+    /// Sets a rate limiter whose maximum rate follows the recommended maximum rate.
+    pub fn rate_limiter(mut self, rate_limiter: Arc<Mutex<RateLimiter<T>>>) -> Self {
+        self.rate_limiter = Some(rate_limiter);
+        self
+    }
+
+    /// Builds and returns the `CapacityEstimator` instance.
+    pub fn build(self) -> CapacityEstimator<T> {
+        CapacityEstimator {
+            window: self.window,
+            concurrency_limit: self.concurrency_limit,
+            headroom: self.headroom,
+            clock: self.clock.unwrap_or_else(|| Arc::new(SystemClock)),
+            rate_limiter: self.rate_limiter,
+            samples: VecDeque::new(),
+            total_latency: Duration::ZERO,
+        }
+    }
+}
+
+impl<T: Float + Signed + FromPrimitive + Copy> Default for CapacityEstimatorBuilder<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::ManualClock;
+    use crate::RateLimiterBuilder;
+
+    #[test]
+    fn test_estimates_capacity_over_window() {
+        let clock = ManualClock::new();
+        let mut estimator = CapacityEstimatorBuilder::<f64>::new()
+            .window(Duration::from_secs(1))
+            .clock(Arc::new(clock.clone()))
+            .build();
+        assert_eq!(estimator.recommended_max_rate(), None);
+
+        estimator.record(4, Duration::from_millis(100));
+        estimator.record(2, Duration::from_millis(300));
+        assert_eq!(estimator.concurrency(), Some(4));
+        assert_eq!(estimator.mean_latency(), Some(Duration::from_millis(200)));
+        assert_eq!(estimator.estimated_capacity(), Some(20.0));
+
+        // The slow requests leave the window
+        clock.advance(Duration::from_millis(600));
+        estimator.record(3, Duration::from_millis(50));
+        clock.advance(Duration::from_millis(600));
+        estimator.record(1, Duration::from_millis(50));
+        assert_eq!(estimator.concurrency(), Some(3));
+        assert_eq!(estimator.estimated_capacity(), Some(60.0));
+    }
+
+    #[test]
+    fn test_feeds_max_rate_into_rate_limiter() {
+        let rate_limiter = Arc::new(Mutex::new(
+            RateLimiterBuilder::new(100.0)
+                .min_rate(10.0)
+                .max_rate(500.0)
+                .build(),
+        ));
+        let mut estimator = CapacityEstimatorBuilder::new()
+            .concurrency_limit(10)
+            .headroom(0.5)
+            .rate_limiter(rate_limiter.clone())
+            .build();
+
+        estimator.record(1, Duration::from_millis(100));
+        {
+            let rate_limiter = rate_limiter.lock().unwrap();
+            assert_eq!(rate_limiter.max_rate(), 50.0);
+            assert_eq!(rate_limiter.min_rate(), 10.0);
+            assert_eq!(rate_limiter.target_rate(), 50.0);
+        }
+
+        // The maximum rate never drops below the minimum rate
+        estimator.record(1, Duration::from_secs(10));
+        assert_eq!(rate_limiter.lock().unwrap().max_rate(), 10.0);
+    }
+}
@@ -0,0 +1,262 @@
+//! Time sources for the rate limiter.
+//!
+//! The limiter reads the time through a [`Clock`] rather than [`std::time::Instant`], which
+//! panics on `wasm32-unknown-unknown`. [`SystemClock`] is the default: a monotonic clock on native
+//! targets and `performance.now()` on `wasm32-unknown-unknown`, so the limiter runs unchanged in
+//! browsers and edge runtimes such as Cloudflare Workers and Fastly Compute.
+//!
+//! Note that some edge runtimes only advance `performance.now()` on I/O, so time stands still
+//! while a request is handled without awaiting anything. The limiter then sees all requests of a
+//! burst at the same instant, which its minimum window duration already accounts for.
+//!
+//! Where reading the clock on every decision costs more than the precision is worth,
+//! [`CoarseClock`] caches the time of another clock, refreshed by a background thread every
+//! resolution, or by [`CoarseClock::tick`] from an existing timer. Reading it is a single relaxed
+//! atomic load, and a resolution of a millisecond is plenty for windows of a second.
+//!
+//! # Example
+//!
+//! ```rust
+//! use std::sync::Arc;
+//! use std::time::Duration;
+//!
+//! use nenya::clock::ManualClock;
+//! use nenya::RateLimiterBuilder;
+//!
+//! let clock = ManualClock::new();
+//! let mut rate_limiter = RateLimiterBuilder::new(10.0)
+//!     .clock(Arc::new(clock.clone()))
+//!     .build();
+//! rate_limiter.should_throttle();
+//! clock.advance(Duration::from_secs(1));
+//! ```
+
+use std::fmt;
+use std::ops::{Add, Sub};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Point in time on a [`Clock`], measured from the clock's own epoch.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Timestamp(Duration);
+
+impl Timestamp {
+    /// Creates a timestamp `since_epoch` after the clock's epoch.
+    pub fn from_duration(since_epoch: Duration) -> Self {
+        Timestamp(since_epoch)
+    }
+
+    /// Returns the time since the clock's epoch.
+    pub fn as_duration(&self) -> Duration {
+        self.0
+    }
+
+    /// Returns the time elapsed from `earlier` to this timestamp, or zero if `earlier` is later.
+    pub fn duration_since(&self, earlier: Timestamp) -> Duration {
+        self.0.saturating_sub(earlier.0)
+    }
+}
+
+impl Add<Duration> for Timestamp {
+    type Output = Timestamp;
+
+    fn add(self, duration: Duration) -> Timestamp {
+        Timestamp(self.0 + duration)
+    }
+}
+
+impl Sub<Duration> for Timestamp {
+    type Output = Timestamp;
+
+    fn sub(self, duration: Duration) -> Timestamp {
+        Timestamp(self.0 - duration)
+    }
+}
+
+/// Monotonic source of the current time.
+pub trait Clock: fmt::Debug + Send + Sync {
+    /// Returns the current time.
+    fn now(&self) -> Timestamp;
+}
+
+/// The platform's monotonic clock.
+///
+/// Measures from the first time any `SystemClock` is read on native targets, and from the start
+/// of the JavaScript context on `wasm32-unknown-unknown`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+impl Clock for SystemClock {
+    fn now(&self) -> Timestamp {
+        use std::sync::OnceLock;
+        use std::time::Instant;
+
+        static EPOCH: OnceLock<Instant> = OnceLock::new();
+        Timestamp(EPOCH.get_or_init(Instant::now).elapsed())
+    }
+}
+
+#[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+mod performance {
+    use wasm_bindgen::prelude::wasm_bindgen;
+
+    #[wasm_bindgen]
+    extern "C" {
+        #[wasm_bindgen(js_namespace = performance)]
+        pub(super) fn now() -> f64;
+    }
+}
+
+#[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+impl Clock for SystemClock {
+    fn now(&self) -> Timestamp {
+        Timestamp(Duration::from_secs_f64(
+            performance::now().max(0.0) / 1000.0,
+        ))
+    }
+}
+
+/// Clock that only moves when told to, for tests and simulations.
+///
+/// Clones share the same time.
+#[derive(Debug, Clone, Default)]
+pub struct ManualClock {
+    nanos: Arc<AtomicU64>,
+}
+
+impl ManualClock {
+    /// Creates a clock standing at its epoch.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Moves the clock forward by `duration`.
+    pub fn advance(&self, duration: Duration) {
+        let nanos = u64::try_from(duration.as_nanos()).unwrap_or(u64::MAX);
+        self.nanos.fetch_add(nanos, Ordering::Relaxed);
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Timestamp {
+        Timestamp(Duration::from_nanos(self.nanos.load(Ordering::Relaxed)))
+    }
+}
+
+/// Clock caching the time of another clock, for deployments where reading the time on every
+/// decision costs more than millisecond precision is worth.
+///
+/// The cached time only moves when refreshed, with [`CoarseClock::tick`], or every resolution by
+/// the background thread of [`CoarseClock::spawn`]. Clones share the same cached time.
+#[derive(Debug, Clone)]
+pub struct CoarseClock {
+    inner: Arc<CoarseInner>,
+}
+
+#[derive(Debug)]
+struct CoarseInner {
+    source: Arc<dyn Clock>,
+    nanos: AtomicU64,
+}
+
+impl CoarseInner {
+    fn tick(&self) {
+        let nanos = u64::try_from(self.source.now().as_duration().as_nanos()).unwrap_or(u64::MAX);
+        self.nanos.fetch_max(nanos, Ordering::Relaxed);
+    }
+}
+
+impl CoarseClock {
+    /// Creates a clock caching the time of `source`, refreshed only by [`CoarseClock::tick`].
+    pub fn new(source: Arc<dyn Clock>) -> Self {
+        let inner = Arc::new(CoarseInner {
+            source,
+            nanos: AtomicU64::new(0),
+        });
+        inner.tick();
+        CoarseClock { inner }
+    }
+
+    /// Creates a clock caching the [`SystemClock`], refreshed every `resolution` by a background
+    /// thread, which exits once the clock and its clones are dropped.
+    #[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+    pub fn spawn(resolution: Duration) -> Self {
+        let clock = Self::new(Arc::new(SystemClock));
+        let inner = Arc::downgrade(&clock.inner);
+        std::thread::Builder::new()
+            .name("nenya-coarse-clock".to_string())
+            .spawn(move || loop {
+                std::thread::sleep(resolution);
+                match inner.upgrade() {
+                    Some(inner) => inner.tick(),
+                    None => break,
+                }
+            })
+            .expect("failed to spawn the coarse clock thread");
+        clock
+    }
+
+    /// Refreshes the cached time from the source clock.
+    pub fn tick(&self) {
+        self.inner.tick();
+    }
+}
+
+impl Clock for CoarseClock {
+    fn now(&self) -> Timestamp {
+        Timestamp(Duration::from_nanos(
+            self.inner.nanos.load(Ordering::Relaxed),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[cfg(feature = "pid")]
+    fn test_limiter_follows_its_clock() {
+        let clock = ManualClock::new();
+        let mut rate_limiter = crate::RateLimiterBuilder::new(10.0)
+            .clock(Arc::new(clock.clone()))
+            .build();
+        let throttled = (0..20).filter(|_| rate_limiter.should_throttle()).count();
+        assert!(throttled > 0);
+        assert!(rate_limiter.retry_after() > Duration::ZERO);
+
+        // Without time passing, the requests stay in the window
+        assert!(rate_limiter.should_throttle());
+        clock.advance(Duration::from_secs(2));
+        assert!(!rate_limiter.should_throttle());
+        assert_eq!(clock.now().as_duration(), Duration::from_secs(2));
+    }
+
+    #[test]
+    fn test_system_clock_is_monotonic() {
+        let earlier = SystemClock.now();
+        assert!(SystemClock.now() >= earlier);
+        assert_eq!(earlier.duration_since(SystemClock.now()), Duration::ZERO);
+    }
+
+    #[test]
+    fn test_coarse_clock_moves_on_tick() {
+        let source = ManualClock::new();
+        source.advance(Duration::from_secs(1));
+        let clock = CoarseClock::new(Arc::new(source.clone()));
+        assert_eq!(clock.now().as_duration(), Duration::from_secs(1));
+
+        source.advance(Duration::from_millis(5));
+        assert_eq!(clock.now().as_duration(), Duration::from_secs(1));
+        clock.clone().tick();
+        assert_eq!(clock.now().as_duration(), Duration::from_millis(1005));
+
+        let clock = CoarseClock::spawn(Duration::from_millis(1));
+        let earlier = clock.now();
+        std::thread::sleep(Duration::from_millis(50));
+        assert!(clock.now() > earlier);
+    }
+}
@@ -0,0 +1,283 @@
+//! Queue delay control for work waiting to be served, after CoDel and adaptive LIFO.
+//!
+//! A rate limiter bounds how fast work is admitted, but admitted work can still pile up in a
+//! queue in front of a slow worker pool, and work that waited too long is often useless by the
+//! time it is served. [`CoDel`] sheds such work based on its sojourn time, the time between
+//! being queued and being taken off the queue:
+//!
+//! - Work is stamped with a [`Ticket`] when queued, and checked with [`CoDel::should_drop`] when
+//!   taken off the queue.
+//! - Short bursts are absorbed: nothing is dropped until the sojourn time has stayed above the
+//!   target for a whole interval.
+//! - Once it has, work is dropped at an increasing rate, the interval divided by the square root
+//!   of the number of drops, until the sojourn time falls below the target again.
+//! - While dropping, [`CoDel::is_overloaded`] is `true`. Queues should then serve the newest work
+//!   first, which is the most likely to still be wanted, and drain the oldest through
+//!   `should_drop`.
+//!
+//! # Example
+//!
+//! ```rust
+//! use std::collections::VecDeque;
+//!
+//! use nenya::codel::CoDelBuilder;
+//!
+//! let mut codel = CoDelBuilder::new().build();
+//! let mut queue = VecDeque::new();
+//! queue.push_back(("request", codel.ticket()));
+//!
+//! while let Some((request, ticket)) = if codel.is_overloaded() {
+//!     queue.pop_back()
+//! } else {
+//!     queue.pop_front()
+//! } {
+//!     if codel.should_drop(ticket) {
+//!         // Reject the request, e.g. with 503 Service Unavailable
+//!         continue;
+//!     }
+//!     // Serve the request
+//! }
+//! ```
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::clock::{Clock, SystemClock, Timestamp};
+
+/// Number of intervals after leaving the dropping state within which the drop rate is resumed
+/// rather than restarted.
+const DROP_RATE_MEMORY: u32 = 16;
+
+/// When a piece of work was queued.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Ticket {
+    queued_at: Timestamp,
+}
+
+impl Ticket {
+    /// Returns when the work was queued.
+    pub fn queued_at(&self) -> Timestamp {
+        self.queued_at
+    }
+}
+
+/// Drops queued work when its sojourn time stays above a target.
+#[derive(Debug)]
+pub struct CoDel {
+    target: Duration,
+    interval: Duration,
+    clock: Arc<dyn Clock>,
+    /// When the sojourn time will have been above the target for an interval, if it is above.
+    first_above_time: Option<Timestamp>,
+    dropping: bool,
+    drop_next: Timestamp,
+    drop_count: u32,
+    last_drop_count: u32,
+    dropped: u64,
+}
+
+impl CoDel {
+    /// Returns a ticket for work queued now.
+    pub fn ticket(&self) -> Ticket {
+        Ticket {
+            queued_at: self.clock.now(),
+        }
+    }
+
+    /// Decides whether work taken off the queue now should be dropped instead of served.
+    pub fn should_drop(&mut self, ticket: Ticket) -> bool {
+        let now = self.clock.now();
+        let sojourn_time = now.duration_since(ticket.queued_at);
+        let above_for_interval = if sojourn_time < self.target {
+            self.first_above_time = None;
+            false
+        } else {
+            let first_above_time = *self.first_above_time.get_or_insert(now + self.interval);
+            now >= first_above_time
+        };
+
+        if self.dropping {
+            if !above_for_interval {
+                self.dropping = false;
+                return false;
+            }
+            if now < self.drop_next {
+                return false;
+            }
+            self.drop_count += 1;
+            self.drop_next = self.control_law(self.drop_next);
+        } else {
+            if !above_for_interval {
+                return false;
+            }
+            self.dropping = true;
+            let recent = now.duration_since(self.drop_next) < self.interval * DROP_RATE_MEMORY;
+            let delta = self.drop_count - self.last_drop_count;
+            self.drop_count = if recent && delta > 1 { delta } else { 1 };
+            self.last_drop_count = self.drop_count;
+            self.drop_next = self.control_law(now);
+        }
+        self.dropped += 1;
+        true
+    }
+
+    /// Returns `true` while work is being dropped, in which case the newest work should be
+    /// served first.
+    pub fn is_overloaded(&self) -> bool {
+        self.dropping
+    }
+
+    /// Returns the number of pieces of work dropped so far.
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+
+    /// Returns the target sojourn time.
+    pub fn target(&self) -> Duration {
+        self.target
+    }
+
+    /// Returns the interval the sojourn time has to stay above the target before dropping.
+    pub fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    /// Returns when to drop next, spacing drops closer as more are needed.
+    fn control_law(&self, from: Timestamp) -> Timestamp {
+        from + self.interval.div_f64(f64::from(self.drop_count).sqrt())
+    }
+}
+
+/// Builder for creating a `CoDel` instance.
+pub struct CoDelBuilder {
+    target: Duration,
+    interval: Duration,
+    clock: Option<Arc<dyn Clock>>,
+}
+
+impl CoDelBuilder {
+    /// Creates a new `CoDelBuilder` with a target sojourn time of 5 ms and an interval of 100 ms.
+    pub fn new() -> Self {
+        CoDelBuilder {
+            target: Duration::from_millis(5),
+            interval: Duration::from_millis(100),
+            clock: None,
+        }
+    }
+
+    /// Sets the sojourn time above which work counts as queueing too long.
+    pub fn target(mut self, target: Duration) -> Self {
+        self.target = target;
+        self
+    }
+
+    /// Sets how long the sojourn time has to stay above the target before work is dropped.
+    ///
+    /// The interval should be about the time it takes to serve a piece of work, so that a burst
+    /// that is worked off normally does not cause drops.
+    pub fn interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// Sets the clock sojourn times are measured with.
+    pub fn clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = Some(clock);
+        self
+    }
+
+    /// Builds and returns the `CoDel` instance.
+    pub fn build(self) -> CoDel {
+        CoDel {
+            target: self.target,
+            interval: self.interval,
+            clock: self.clock.unwrap_or_else(|| Arc::new(SystemClock)),
+            first_above_time: None,
+            dropping: false,
+            drop_next: Timestamp::default(),
+            drop_count: 0,
+            last_drop_count: 0,
+            dropped: 0,
+        }
+    }
+}
+
+impl Default for CoDelBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::ManualClock;
+
+    fn codel() -> (CoDel, ManualClock) {
+        let clock = ManualClock::new();
+        let codel = CoDelBuilder::new()
+            .target(Duration::from_millis(5))
+            .interval(Duration::from_millis(100))
+            .clock(Arc::new(clock.clone()))
+            .build();
+        (codel, clock)
+    }
+
+    /// Serves work every `step` that has been queued for `sojourn_time`, returning how much was
+    /// dropped.
+    fn serve(
+        codel: &mut CoDel,
+        clock: &ManualClock,
+        sojourn_time: Duration,
+        step: Duration,
+        count: usize,
+    ) -> usize {
+        (0..count)
+            .filter(|_| {
+                clock.advance(step);
+                let ticket = Ticket {
+                    queued_at: clock.now() - sojourn_time,
+                };
+                codel.should_drop(ticket)
+            })
+            .count()
+    }
+
+    #[test]
+    fn test_absorbs_short_bursts() {
+        let (mut codel, clock) = codel();
+        clock.advance(Duration::from_secs(1));
+        let step = Duration::from_millis(10);
+        assert_eq!(
+            serve(&mut codel, &clock, Duration::from_millis(50), step, 9),
+            0
+        );
+        assert_eq!(serve(&mut codel, &clock, Duration::ZERO, step, 100), 0);
+        assert!(!codel.is_overloaded());
+    }
+
+    #[test]
+    fn test_drops_at_increasing_rate_while_queue_stays_long() {
+        let (mut codel, clock) = codel();
+        clock.advance(Duration::from_secs(1));
+        let step = Duration::from_millis(10);
+        let sojourn_time = Duration::from_millis(50);
+
+        // Nothing is dropped during the first interval above the target
+        assert_eq!(serve(&mut codel, &clock, sojourn_time, step, 10), 0);
+        assert_eq!(serve(&mut codel, &clock, sojourn_time, step, 1), 1);
+        assert!(codel.is_overloaded());
+
+        let first_second = serve(&mut codel, &clock, sojourn_time, step, 100);
+        let next_second = serve(&mut codel, &clock, sojourn_time, step, 100);
+        assert!(first_second > 5, "{first_second}");
+        assert!(next_second > first_second, "{next_second} > {first_second}");
+
+        assert!(!codel.should_drop(codel.ticket()));
+        assert!(!codel.is_overloaded());
+        assert_eq!(
+            codel.dropped(),
+            1 + u64::try_from(first_second + next_second).unwrap()
+        );
+    }
+}
@@ -0,0 +1,460 @@
+//! Sharing request rates between the nodes of a distributed rate limiter.
+//!
+//! Each node publishes the rates it observes locally for every segment and reads back the rates
+//! published by the other nodes, which it feeds into its limiters as external rates. How the
+//! rates travel between nodes is up to the [`DistributedStateBackend`] implementation: a mesh of
+//! nodes exchanging them directly, a shared store such as Redis or DynamoDB, or the
+//! [`InMemoryBackend`] for tests and single-process deployments.
+//!
+//! Backends that support leases can also elect a coordinator. Instead of every node adjusting
+//! the target rates independently, the node holding the lease computes each segment's global
+//! target and publishes a [`TargetShares`] assignment splitting it between the nodes, see
+//! [`compute_shares`].
+//!
+//! [`share_rates`] does the publishing and reading back periodically for a set of segment
+//! limiters. It is a future for the application to spawn on whichever executor it runs, waiting
+//! between rounds with a [`Timer`].
+//!
+//! # Example
+//!
+//! ```rust
+//! use std::collections::HashMap;
+//!
+//! use nenya::distributed::{DistributedStateBackend, InMemoryBackend, NodeMetrics, SegmentRates};
+//!
+//! # async fn example() -> Result<(), nenya::distributed::BackendError> {
+//! let backend = InMemoryBackend::default();
+//! let metrics = NodeMetrics {
+//!     node: "node-1".to_string(),
+//!     segments: HashMap::from([(
+//!         "checkout".to_string(),
+//!         SegmentRates {
+//!             request_rate: 12.0,
+//!             accepted_request_rate: 10.0,
+//!         },
+//!     )]),
+//! };
+//! backend.publish(metrics).await?;
+//! assert_eq!(backend.node_metrics().await?.len(), 1);
+//! # Ok(())
+//! # }
+//! ```
+
+use std::collections::HashMap;
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::clock::{Clock, SystemClock, Timestamp};
+use crate::runtime::Timer;
+use crate::RateLimiter;
+
+/// Request rates observed for a single segment.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct SegmentRates {
+    pub request_rate: f32,
+    pub accepted_request_rate: f32,
+}
+
+/// Rates observed locally by one node, keyed by segment.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct NodeMetrics {
+    pub node: String,
+    pub segments: HashMap<String, SegmentRates>,
+}
+
+/// Target rates assigned to each node by the coordinator.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct TargetShares {
+    /// Node that computed the assignment.
+    pub leader: String,
+    /// Target rate of each segment, keyed by node and then by segment.
+    pub nodes: HashMap<String, HashMap<String, f32>>,
+}
+
+impl TargetShares {
+    /// Returns the target rate assigned to `node` for `segment`.
+    pub fn share(&self, node: &str, segment: &str) -> Option<f32> {
+        self.nodes.get(node)?.get(segment).copied()
+    }
+}
+
+/// Error returned when a backend cannot publish or read the shared state.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BackendError(pub String);
+
+impl fmt::Display for BackendError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "distributed state backend error: {}", self.0)
+    }
+}
+
+impl std::error::Error for BackendError {}
+
+/// Future returned by [`DistributedStateBackend`] methods.
+pub type BackendFuture<'a, T> = Pin<Box<dyn Future<Output = Result<T, BackendError>> + Send + 'a>>;
+
+/// Mechanism used to share each node's rates with the rest of the cluster.
+pub trait DistributedStateBackend: Send + Sync + fmt::Debug {
+    /// Publishes the rates observed locally by a node, replacing its previous metrics.
+    fn publish(&self, metrics: NodeMetrics) -> BackendFuture<'_, ()>;
+
+    /// Returns the latest metrics published by every known node, which may include the caller.
+    fn node_metrics(&self) -> BackendFuture<'_, Vec<NodeMetrics>>;
+
+    /// Discards the metrics of a node that has left the cluster.
+    fn forget(&self, node: &str) -> BackendFuture<'_, ()>;
+
+    /// Acquires or renews the coordinator lease for `node` for `ttl`.
+    ///
+    /// Returns `true` if `node` holds the lease. Backends without leases return an error.
+    fn acquire_lease(&self, node: &str, ttl: Duration) -> BackendFuture<'_, bool> {
+        let _ = (node, ttl);
+        Box::pin(async { Err(unsupported("leases")) })
+    }
+
+    /// Publishes the target shares computed by the lease holder, replacing the previous ones.
+    fn publish_shares(&self, shares: TargetShares) -> BackendFuture<'_, ()> {
+        let _ = shares;
+        Box::pin(async { Err(unsupported("target shares")) })
+    }
+
+    /// Returns the latest target shares published by a lease holder, if any.
+    fn shares(&self) -> BackendFuture<'_, Option<TargetShares>> {
+        Box::pin(async { Ok(None) })
+    }
+}
+
+fn unsupported(feature: &str) -> BackendError {
+    BackendError(format!("{} are not supported by this backend", feature))
+}
+
+/// Backend that keeps the metrics in process memory.
+///
+/// Clones share the same state, so several limiters in one process, such as simulated nodes in a
+/// test, can coordinate through it.
+#[derive(Debug, Clone, Default)]
+pub struct InMemoryBackend {
+    state: Arc<Mutex<InMemoryState>>,
+}
+
+#[derive(Debug, Default)]
+struct InMemoryState {
+    nodes: HashMap<String, NodeMetrics>,
+    lease: Option<(String, Timestamp)>,
+    shares: Option<TargetShares>,
+}
+
+impl DistributedStateBackend for InMemoryBackend {
+    fn publish(&self, metrics: NodeMetrics) -> BackendFuture<'_, ()> {
+        self.state
+            .lock()
+            .unwrap()
+            .nodes
+            .insert(metrics.node.clone(), metrics);
+        Box::pin(async { Ok(()) })
+    }
+
+    fn node_metrics(&self) -> BackendFuture<'_, Vec<NodeMetrics>> {
+        let nodes = self.state.lock().unwrap().nodes.values().cloned().collect();
+        Box::pin(async { Ok(nodes) })
+    }
+
+    fn forget(&self, node: &str) -> BackendFuture<'_, ()> {
+        self.state.lock().unwrap().nodes.remove(node);
+        Box::pin(async { Ok(()) })
+    }
+
+    fn acquire_lease(&self, node: &str, ttl: Duration) -> BackendFuture<'_, bool> {
+        let now = SystemClock.now();
+        let mut state = self.state.lock().unwrap();
+        let held_by_other = state
+            .lease
+            .as_ref()
+            .is_some_and(|(holder, expires_at)| holder != node && *expires_at > now);
+        if !held_by_other {
+            state.lease = Some((node.to_string(), now + ttl));
+        }
+        Box::pin(async move { Ok(!held_by_other) })
+    }
+
+    fn publish_shares(&self, shares: TargetShares) -> BackendFuture<'_, ()> {
+        self.state.lock().unwrap().shares = Some(shares);
+        Box::pin(async { Ok(()) })
+    }
+
+    fn shares(&self) -> BackendFuture<'_, Option<TargetShares>> {
+        let shares = self.state.lock().unwrap().shares.clone();
+        Box::pin(async { Ok(shares) })
+    }
+}
+
+/// Sums the rates of every node except `local_node` for each segment.
+///
+/// The result is what a node should apply as the external rates of its segment limiters.
+pub fn aggregate_peer_rates(
+    nodes: &[NodeMetrics],
+    local_node: &str,
+) -> HashMap<String, SegmentRates> {
+    aggregate_weighted_peer_rates(nodes, local_node, &HashMap::new())
+}
+
+/// Sums the rates of every node except `local_node` for each segment, scaling each node's rates
+/// by its weight in `weights`.
+///
+/// Nodes without a weight count fully, and nodes with a weight of zero are excluded. This lets a
+/// canary node or a node in another region contribute only a fraction of its traffic.
+pub fn aggregate_weighted_peer_rates(
+    nodes: &[NodeMetrics],
+    local_node: &str,
+    weights: &HashMap<String, f32>,
+) -> HashMap<String, SegmentRates> {
+    let mut totals: HashMap<String, SegmentRates> = HashMap::new();
+    for node in nodes.iter().filter(|node| node.node != local_node) {
+        let weight = weights.get(&node.node).copied().unwrap_or(1.0);
+        if weight == 0.0 {
+            continue;
+        }
+        for (segment, rates) in &node.segments {
+            let total = totals.entry(segment.clone()).or_default();
+            total.request_rate += rates.request_rate * weight;
+            total.accepted_request_rate += rates.accepted_request_rate * weight;
+        }
+    }
+    totals
+}
+
+/// Splits each segment's global target rate between `nodes`, returning the target rate of each
+/// node keyed by node and then by segment.
+///
+/// Each node is first given up to a fair share of the target according to its request rate, so
+/// nodes with little traffic do not hold on to capacity that busier nodes need. Whatever capacity
+/// is left is then split evenly, leaving every node headroom for traffic that starts before the
+/// next assignment.
+pub fn compute_shares(
+    nodes: &[NodeMetrics],
+    targets: &HashMap<String, f32>,
+) -> HashMap<String, HashMap<String, f32>> {
+    let mut shares: HashMap<String, HashMap<String, f32>> = nodes
+        .iter()
+        .map(|node| (node.node.clone(), HashMap::new()))
+        .collect();
+    if nodes.is_empty() {
+        return shares;
+    }
+
+    for (segment, &target) in targets {
+        let mut demands: Vec<(&str, f32)> = nodes
+            .iter()
+            .map(|node| {
+                let demand = node
+                    .segments
+                    .get(segment)
+                    .map_or(0.0, |rates| rates.request_rate.max(0.0));
+                (node.node.as_str(), demand)
+            })
+            .collect();
+        demands.sort_by(|a, b| a.1.total_cmp(&b.1));
+
+        let mut remaining = target;
+        let mut allocations = Vec::with_capacity(demands.len());
+        for (index, (node, demand)) in demands.iter().enumerate() {
+            let fair_share = remaining / (demands.len() - index) as f32;
+            let allocation = demand.min(fair_share);
+            remaining -= allocation;
+            allocations.push((*node, allocation));
+        }
+
+        let headroom = remaining / allocations.len() as f32;
+        for (node, allocation) in allocations {
+            shares
+                .get_mut(node)
+                .unwrap()
+                .insert(segment.clone(), allocation + headroom);
+        }
+    }
+    shares
+}
+
+/// Publishes the local rates of the segment limiters in `segments` as `node`'s metrics, then
+/// applies the summed rates of the other nodes as the limiters' external rates.
+pub async fn share_rates_once(
+    backend: &dyn DistributedStateBackend,
+    node: &str,
+    segments: &HashMap<String, Arc<Mutex<RateLimiter<f32>>>>,
+) -> Result<(), BackendError> {
+    let local = segments
+        .iter()
+        .map(|(segment, limiter)| {
+            let limiter = limiter.lock().unwrap();
+            let rates = SegmentRates {
+                request_rate: limiter.local_request_rate(),
+                accepted_request_rate: limiter.local_accepted_request_rate(),
+            };
+            (segment.clone(), rates)
+        })
+        .collect();
+    backend
+        .publish(NodeMetrics {
+            node: node.to_string(),
+            segments: local,
+        })
+        .await?;
+
+    let peers = aggregate_peer_rates(&backend.node_metrics().await?, node);
+    for (segment, limiter) in segments {
+        let rates = peers.get(segment).copied().unwrap_or_default();
+        let mut limiter = limiter.lock().unwrap();
+        limiter.set_external_request_rate(rates.request_rate);
+        limiter.set_external_accepted_request_rate(rates.accepted_request_rate);
+    }
+    Ok(())
+}
+
+/// Runs [`share_rates_once`] every `interval`, waiting with `timer`.
+///
+/// The future never completes; spawn it on the application's executor and drop it to stop
+/// sharing. A round that fails is logged and retried at the next interval.
+pub async fn share_rates(
+    backend: Arc<dyn DistributedStateBackend>,
+    node: String,
+    segments: HashMap<String, Arc<Mutex<RateLimiter<f32>>>>,
+    interval: Duration,
+    timer: Arc<dyn Timer>,
+) {
+    loop {
+        if let Err(err) = share_rates_once(backend.as_ref(), &node, &segments).await {
+            log::warn!("failed to share rates of node {node}: {err}");
+        }
+        timer.sleep(interval).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::task::{Context, Poll, Waker};
+
+    /// Polls a future that never waits, as the in-memory backend's futures are always ready.
+    fn ready<T>(mut future: BackendFuture<'_, T>) -> Result<T, BackendError> {
+        match future
+            .as_mut()
+            .poll(&mut Context::from_waker(Waker::noop()))
+        {
+            Poll::Ready(result) => result,
+            Poll::Pending => panic!("in-memory backend futures are always ready"),
+        }
+    }
+
+    fn metrics(node: &str, request_rate: f32) -> NodeMetrics {
+        NodeMetrics {
+            node: node.to_string(),
+            segments: HashMap::from([(
+                "checkout".to_string(),
+                SegmentRates {
+                    request_rate,
+                    accepted_request_rate: request_rate / 2.0,
+                },
+            )]),
+        }
+    }
+
+    #[test]
+    fn test_in_memory_backend_is_shared_between_clones() {
+        let backend = InMemoryBackend::default();
+        let other = backend.clone();
+        ready(backend.publish(metrics("a", 4.0))).unwrap();
+        ready(other.publish(metrics("b", 6.0))).unwrap();
+        ready(other.publish(metrics("b", 8.0))).unwrap();
+
+        let nodes = ready(backend.node_metrics()).unwrap();
+        assert_eq!(nodes.len(), 2);
+
+        ready(backend.forget("a")).unwrap();
+        assert_eq!(
+            ready(other.node_metrics()).unwrap(),
+            vec![metrics("b", 8.0)]
+        );
+    }
+
+    #[test]
+    fn test_aggregate_peer_rates_excludes_local_node() {
+        let nodes = [
+            metrics("local", 100.0),
+            metrics("a", 4.0),
+            metrics("b", 6.0),
+        ];
+        let totals = aggregate_peer_rates(&nodes, "local");
+        assert_eq!(
+            totals["checkout"],
+            SegmentRates {
+                request_rate: 10.0,
+                accepted_request_rate: 5.0,
+            }
+        );
+    }
+
+    #[test]
+    fn test_aggregate_weighted_peer_rates() {
+        let nodes = [
+            metrics("canary", 4.0),
+            metrics("remote", 6.0),
+            metrics("a", 2.0),
+        ];
+        let weights = HashMap::from([("canary".to_string(), 0.5), ("remote".to_string(), 0.0)]);
+        let totals = aggregate_weighted_peer_rates(&nodes, "local", &weights);
+        assert_eq!(totals["checkout"].request_rate, 4.0);
+        assert_eq!(totals["checkout"].accepted_request_rate, 2.0);
+    }
+
+    #[test]
+    fn test_in_memory_lease_excludes_other_nodes() {
+        let backend = InMemoryBackend::default();
+        let ttl = Duration::from_secs(60);
+        assert!(ready(backend.acquire_lease("a", ttl)).unwrap());
+        assert!(!ready(backend.acquire_lease("b", ttl)).unwrap());
+        assert!(ready(backend.acquire_lease("a", ttl)).unwrap());
+
+        // An expired lease can be taken over
+        assert!(ready(backend.acquire_lease("a", Duration::ZERO)).unwrap());
+        assert!(ready(backend.acquire_lease("b", ttl)).unwrap());
+    }
+
+    #[test]
+    fn test_compute_shares_favors_busy_nodes() {
+        let nodes = [metrics("idle", 0.0), metrics("a", 2.0), metrics("b", 100.0)];
+        let targets = HashMap::from([("checkout".to_string(), 30.0)]);
+        let shares = compute_shares(&nodes, &targets);
+
+        assert_eq!(shares["idle"]["checkout"], 0.0);
+        assert_eq!(shares["a"]["checkout"], 2.0);
+        assert_eq!(shares["b"]["checkout"], 28.0);
+
+        // Unused capacity is split evenly
+        let shares = compute_shares(&nodes[..2], &targets);
+        assert_eq!(shares["idle"]["checkout"], 14.0);
+        assert_eq!(shares["a"]["checkout"], 16.0);
+    }
+
+    #[test]
+    fn test_share_rates_applies_peer_rates() {
+        let backend = InMemoryBackend::default();
+        ready(backend.publish(metrics("peer", 6.0))).unwrap();
+        let limiter = Arc::new(Mutex::new(crate::RateLimiterBuilder::new(10.0).build()));
+        // The rates are calculated before each decision, so the second sees the first
+        limiter.lock().unwrap().should_throttle();
+        limiter.lock().unwrap().should_throttle();
+        let segments = HashMap::from([("checkout".to_string(), limiter.clone())]);
+
+        ready(Box::pin(share_rates_once(&backend, "local", &segments))).unwrap();
+        let limiter = limiter.lock().unwrap();
+        assert_eq!(limiter.external_request_rate(), 6.0);
+        assert_eq!(limiter.external_accepted_request_rate(), 3.0);
+        let published = ready(backend.node_metrics()).unwrap();
+        assert!(published
+            .iter()
+            .any(|node| node.node == "local" && node.segments["checkout"].request_rate > 0.0));
+    }
+}
@@ -0,0 +1,311 @@
+//! A/B testing of rate limiter configurations on live traffic.
+//!
+//! An [`Experiment`] runs a control and a candidate rate limiter side by side on the same
+//! requests. Both limiters decide on every request, but only one of them enforces its decision:
+//!
+//! - Requests are split between the arms by a hash of their key, such as a caller or an API key.
+//!   The hash is stable across processes, so every node sends a key to the same arm and a caller
+//!   sees one consistent policy.
+//! - The arm a request is assigned to enforces, while the other shadows: it counts the request
+//!   as if its decision had been enforced, so its rates evolve as they would in production.
+//! - [`Experiment::report`] compares the arms: how often each would throttle, how often it did
+//!   when enforcing, and how often the two disagreed.
+//!
+//! Starting with a small candidate share limits the blast radius of a bad configuration, which
+//! the report shows before it is rolled out further.
+//!
+//! # Example
+//!
+//! ```rust
+//! use nenya::experiment::Experiment;
+//! use nenya::RateLimiterBuilder;
+//!
+//! let control = RateLimiterBuilder::new(100.0).build();
+//! let candidate = RateLimiterBuilder::new(80.0).build();
+//! // The candidate enforces for 10% of the keys and shadows the rest
+//! let mut experiment = Experiment::new(control, candidate, 0.1);
+//!
+//! if experiment.should_throttle("customer-42") {
+//!     // Reject the request
+//! }
+//! let report = experiment.report();
+//! println!("disagreed on {} requests", report.disagreements);
+//! ```
+
+use num_traits::{Float, FromPrimitive, Signed};
+
+use crate::RateLimiter;
+
+/// Offset basis of the 64-bit FNV-1a hash.
+const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+
+/// Prime of the 64-bit FNV-1a hash.
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+/// Side of an experiment.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub enum Arm {
+    /// The current configuration.
+    Control,
+    /// The configuration under evaluation.
+    Candidate,
+}
+
+/// Decisions of one arm of an experiment.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct ArmStats {
+    /// Requests the arm decided on, enforcing or shadowing.
+    pub requests: u64,
+    /// Requests the arm decided to throttle, enforcing or shadowing.
+    pub throttled: u64,
+    /// Requests the arm enforced its decision on.
+    pub enforced_requests: u64,
+    /// Requests the arm throttled while enforcing.
+    pub enforced_throttled: u64,
+}
+
+impl ArmStats {
+    /// Returns the fraction of requests the arm decided to throttle, or zero without requests.
+    pub fn throttle_ratio(&self) -> f64 {
+        if self.requests == 0 {
+            return 0.0;
+        }
+        self.throttled as f64 / self.requests as f64
+    }
+
+    fn record(&mut self, throttled: bool, enforced: bool) {
+        self.requests += 1;
+        self.throttled += u64::from(throttled);
+        if enforced {
+            self.enforced_requests += 1;
+            self.enforced_throttled += u64::from(throttled);
+        }
+    }
+}
+
+/// Comparison of the arms of an experiment.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct ExperimentReport {
+    /// Fraction of the keys the candidate enforces for.
+    pub candidate_share: f64,
+    pub control: ArmStats,
+    pub candidate: ArmStats,
+    /// Requests one arm would throttle and the other would not.
+    pub disagreements: u64,
+}
+
+/// Control and candidate rate limiters deciding on the same requests, each enforcing for its
+/// share of the keys.
+#[derive(Debug)]
+pub struct Experiment<T> {
+    control: RateLimiter<T>,
+    candidate: RateLimiter<T>,
+    candidate_share: f64,
+    control_stats: ArmStats,
+    candidate_stats: ArmStats,
+    disagreements: u64,
+}
+
+impl<T: Float + Signed + FromPrimitive + Copy> Experiment<T> {
+    /// Creates an experiment where `candidate` enforces for `candidate_share` of the keys,
+    /// between 0 and 1, and `control` for the rest.
+    pub fn new(control: RateLimiter<T>, candidate: RateLimiter<T>, candidate_share: f64) -> Self {
+        Experiment {
+            control,
+            candidate,
+            candidate_share: candidate_share.clamp(0.0, 1.0),
+            control_stats: ArmStats::default(),
+            candidate_stats: ArmStats::default(),
+            disagreements: 0,
+        }
+    }
+
+    /// Returns the arm enforcing for `key`.
+    ///
+    /// The assignment only depends on the key and the candidate share, so it is the same in
+    /// every process running the experiment.
+    pub fn arm(&self, key: impl AsRef<[u8]>) -> Arm {
+        let bucket = mix(fnv1a(key.as_ref())) as f64 / u64::MAX as f64;
+        if bucket < self.candidate_share {
+            Arm::Candidate
+        } else {
+            Arm::Control
+        }
+    }
+
+    /// Determines if a request from `key` should be throttled, by the arm enforcing for it.
+    pub fn should_throttle(&mut self, key: impl AsRef<[u8]>) -> bool {
+        self.should_throttle_weighted(key, T::one())
+    }
+
+    /// Determines if a request from `key` consuming `cost` units of budget should be throttled,
+    /// by the arm enforcing for it.
+    ///
+    /// Both arms count the request according to their own decision.
+    pub fn should_throttle_weighted(&mut self, key: impl AsRef<[u8]>, cost: impl Into<T>) -> bool {
+        let cost = cost.into();
+        let arm = self.arm(key);
+        let control = self.control.should_throttle_weighted(cost);
+        let candidate = self.candidate.should_throttle_weighted(cost);
+        self.control_stats.record(control, arm == Arm::Control);
+        self.candidate_stats
+            .record(candidate, arm == Arm::Candidate);
+        self.disagreements += u64::from(control != candidate);
+        match arm {
+            Arm::Control => control,
+            Arm::Candidate => candidate,
+        }
+    }
+
+    /// Returns the comparison of the arms so far.
+    pub fn report(&self) -> ExperimentReport {
+        ExperimentReport {
+            candidate_share: self.candidate_share,
+            control: self.control_stats,
+            candidate: self.candidate_stats,
+            disagreements: self.disagreements,
+        }
+    }
+
+    /// Sets the fraction of the keys the candidate enforces for, e.g. to ramp it up.
+    pub fn set_candidate_share(&mut self, candidate_share: f64) {
+        self.candidate_share = candidate_share.clamp(0.0, 1.0);
+    }
+
+    /// Returns the control rate limiter.
+    pub fn control(&self) -> &RateLimiter<T> {
+        &self.control
+    }
+
+    /// Returns the candidate rate limiter.
+    pub fn candidate(&self) -> &RateLimiter<T> {
+        &self.candidate
+    }
+
+    /// Ends the experiment, returning the control and candidate rate limiters.
+    pub fn into_inner(self) -> (RateLimiter<T>, RateLimiter<T>) {
+        (self.control, self.candidate)
+    }
+}
+
+/// Hashes `bytes` with 64-bit FNV-1a, which unlike the standard library's hasher is stable
+/// across processes and Rust versions.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(FNV_OFFSET_BASIS, |hash, &byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(FNV_PRIME)
+    })
+}
+
+/// Spreads the bits of `hash` over the whole range with the MurmurHash3 finalizer, as FNV-1a
+/// hashes of keys that differ only in their last bytes share their high bits.
+fn mix(mut hash: u64) -> u64 {
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xff51_afd7_ed55_8ccd);
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xc4ce_b9fe_1a85_ec53);
+    hash ^ (hash >> 33)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::ManualClock;
+    use crate::RateLimiterBuilder;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    fn experiment(candidate_share: f64) -> Experiment<f64> {
+        experiment_with_clock(candidate_share, ManualClock::new())
+    }
+
+    /// Experiment whose control admits up to 1000 requests per second and candidate up to 5.
+    fn experiment_with_clock(candidate_share: f64, clock: ManualClock) -> Experiment<f64> {
+        let rate_limiter = |target_rate: f64| {
+            RateLimiterBuilder::new(target_rate)
+                .clock(Arc::new(clock.clone()))
+                .build()
+        };
+        Experiment::new(rate_limiter(1000.0), rate_limiter(5.0), candidate_share)
+    }
+
+    /// Sends a request for each of `keys` 10 ms apart, returning the arm enforcing for it and
+    /// whether it was throttled.
+    fn send(
+        experiment: &mut Experiment<f64>,
+        clock: &ManualClock,
+        keys: usize,
+    ) -> Vec<(Arm, bool)> {
+        (0..keys)
+            .map(|i| {
+                clock.advance(Duration::from_millis(10));
+                let key = format!("customer-{}", i);
+                (experiment.arm(&key), experiment.should_throttle(&key))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_splits_keys_consistently() {
+        assert_eq!(fnv1a(b""), FNV_OFFSET_BASIS);
+        assert_eq!(fnv1a(b"a"), 0xaf63_dc4c_8601_ec8c);
+
+        let experiment = experiment(0.2);
+        let keys: Vec<String> = (0..10_000).map(|i| format!("customer-{}", i)).collect();
+        let candidates = keys
+            .iter()
+            .filter(|key| experiment.arm(key) == Arm::Candidate)
+            .count();
+        assert!((1800..2200).contains(&candidates), "{candidates}");
+        assert!(keys
+            .iter()
+            .all(|key| experiment.arm(key) == experiment.arm(key.as_bytes())));
+
+        assert!(keys
+            .iter()
+            .all(|key| self::experiment(0.0).arm(key) == Arm::Control));
+        assert!(keys
+            .iter()
+            .all(|key| self::experiment(1.0).arm(key) == Arm::Candidate));
+    }
+
+    #[test]
+    fn test_shadow_arm_decides_without_enforcing() {
+        let clock = ManualClock::new();
+        let mut experiment = experiment_with_clock(0.0, clock.clone());
+        let decisions = send(&mut experiment, &clock, 50);
+        assert!(decisions.iter().all(|&(_, throttled)| !throttled));
+
+        let report = experiment.report();
+        assert_eq!(report.control.requests, 50);
+        assert_eq!(report.control.enforced_requests, 50);
+        assert_eq!(report.candidate.requests, 50);
+        assert_eq!(report.candidate.enforced_requests, 0);
+        assert!(report.candidate.throttled > 0);
+        assert_eq!(report.disagreements, report.candidate.throttled);
+        assert!(report.candidate.throttle_ratio() > report.control.throttle_ratio());
+    }
+
+    #[test]
+    fn test_candidate_enforces_its_share() {
+        let clock = ManualClock::new();
+        let mut experiment = experiment_with_clock(0.5, clock.clone());
+        let decisions = send(&mut experiment, &clock, 200);
+        assert!(decisions
+            .iter()
+            .all(|&(arm, throttled)| arm == Arm::Candidate || !throttled));
+        assert!(decisions
+            .iter()
+            .any(|&(arm, throttled)| arm == Arm::Candidate && throttled));
+
+        let report = experiment.report();
+        assert_eq!(
+            report.control.enforced_requests + report.candidate.enforced_requests,
+            200
+        );
+        assert!(report.candidate.enforced_throttled > 0);
+        assert_eq!(report.control.enforced_throttled, 0);
+    }
+}
@@ -0,0 +1,124 @@
+//! Feedback from the responses of a downstream to the rate of the limiter in front of it.
+
+use num_traits::{Float, FromPrimitive, Signed};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+
+use crate::RateLimiter;
+
+/// Weight of the latest response in the smoothed latency.
+const LATENCY_SMOOTHING: f64 = 0.2;
+
+/// Weight of the latest response in the smoothed error rate.
+const ERROR_SMOOTHING: f64 = 0.05;
+
+/// Lowers a rate in proportion to how far the smoothed response latency or error rate exceeds
+/// its target, and restores it once responses are fast and successful again.
+///
+/// Shared by the middleware of the `tower`, `axum`, and `tonic` integrations, which record every
+/// response they pass through, and by simulations of a limiter in front of a
+/// [`Downstream`](crate::simulation::Downstream).
+#[derive(Debug)]
+pub(crate) struct ResponseFeedback<T> {
+    latency_target: Option<Duration>,
+    error_target: Option<f64>,
+    /// Smoothed response latency, in seconds.
+    latency: Option<f64>,
+    /// Smoothed fraction of failed responses.
+    error_rate: f64,
+    /// Rate before any feedback was applied.
+    base_rate: Option<T>,
+}
+
+impl<T: Float + Signed + FromPrimitive + Copy> ResponseFeedback<T> {
+    /// Returns the feedback in `feedback`, creating it if the middleware has none yet.
+    pub(crate) fn configure(feedback: &mut Option<Arc<Mutex<Self>>>) -> MutexGuard<'_, Self> {
+        feedback
+            .get_or_insert_with(|| {
+                Arc::new(Mutex::new(ResponseFeedback {
+                    latency_target: None,
+                    error_target: None,
+                    latency: None,
+                    error_rate: 0.0,
+                    base_rate: None,
+                }))
+            })
+            .lock()
+            .unwrap()
+    }
+
+    pub(crate) fn set_latency_target(&mut self, latency_target: Duration) {
+        self.latency_target = Some(latency_target);
+    }
+
+    pub(crate) fn set_error_target(&mut self, error_target: f64) {
+        self.error_target = Some(error_target);
+    }
+
+    /// Records a response that took `latency`, returning the rate to apply in place of
+    /// `current_rate`.
+    pub(crate) fn observe(&mut self, latency: Duration, failed: bool, current_rate: T) -> T {
+        let latency = latency.as_secs_f64();
+        let smoothed = match self.latency {
+            Some(previous) => previous + LATENCY_SMOOTHING * (latency - previous),
+            None => latency,
+        };
+        self.latency = Some(smoothed);
+        let failure = if failed { 1.0 } else { 0.0 };
+        self.error_rate += ERROR_SMOOTHING * (failure - self.error_rate);
+
+        let base_rate = *self.base_rate.get_or_insert(current_rate);
+        let latency_scale = match self.latency_target {
+            Some(target) if smoothed > 0.0 => (target.as_secs_f64() / smoothed).min(1.0),
+            _ => 1.0,
+        };
+        let error_scale = match self.error_target {
+            Some(target) if self.error_rate > 0.0 => (target / self.error_rate).min(1.0),
+            _ => 1.0,
+        };
+        base_rate * T::from_f64(latency_scale.min(error_scale)).unwrap_or_else(T::one)
+    }
+
+    /// Records a response, setting the PID controller's setpoint of `rate_limiter`.
+    pub(crate) fn observe_setpoint(
+        &mut self,
+        latency: Duration,
+        failed: bool,
+        rate_limiter: &mut RateLimiter<T>,
+    ) {
+        let setpoint = num_traits::clamp(
+            self.observe(latency, failed, rate_limiter.setpoint()),
+            rate_limiter.min_rate(),
+            rate_limiter.max_rate(),
+        );
+        rate_limiter.set_setpoint(setpoint);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::RateLimiterBuilder;
+
+    #[test]
+    fn test_latency_feedback_lowers_setpoint() {
+        let mut rate_limiter = RateLimiterBuilder::new(100.0).min_rate(10.0).build();
+        let mut feedback = None;
+        ResponseFeedback::configure(&mut feedback).set_latency_target(Duration::from_millis(100));
+        let feedback = feedback.unwrap();
+        let mut feedback = feedback.lock().unwrap();
+
+        feedback.observe_setpoint(Duration::from_millis(50), false, &mut rate_limiter);
+        assert_eq!(rate_limiter.setpoint(), 100.0);
+
+        feedback.observe_setpoint(Duration::from_secs(2), false, &mut rate_limiter);
+        let lowered = rate_limiter.setpoint();
+        assert!((10.0..100.0).contains(&lowered));
+        assert_eq!(rate_limiter.target_rate(), 100.0);
+
+        for _ in 0..50 {
+            feedback.observe_setpoint(Duration::from_millis(10), false, &mut rate_limiter);
+        }
+        assert_eq!(rate_limiter.setpoint(), 100.0);
+    }
+}
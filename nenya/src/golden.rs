@@ -0,0 +1,518 @@
+//! Golden trajectories guarding the control behavior of rate limiters.
+//!
+//! A golden is the trajectory of a [`Simulation`] run recorded to a CSV file: the offered and
+//! accepted requests, the limiter's rates, and the PID terms of every tick. [`Golden::check`]
+//! compares a new run against it within a [`Tolerance`], so a change to the controller or the
+//! limiter that alters how they respond fails the check instead of slipping through unnoticed.
+//!
+//! When the change is intended, running the checks with the `NENYA_UPDATE_GOLDENS` environment
+//! variable set rewrites the goldens instead, and the diff of the CSV files shows the new
+//! behavior in review. A missing golden is an error unless the variable is set, so a new check
+//! cannot pass without its golden being committed.
+//!
+//! The [`CanonicalScenario`]s are the runs the crate's own goldens in `nenya/goldens` cover: a
+//! step in the offered load, a burst, and a change of the setpoint.
+//!
+//! # Example
+//!
+//! ```rust,no_run
+//! use nenya::golden::{CanonicalScenario, Golden, Tolerance};
+//!
+//! for scenario in CanonicalScenario::ALL {
+//!     let golden = Golden::new(format!("goldens/{}.csv", scenario.name()))
+//!         .tolerance(Tolerance::new(0.01, 0.001));
+//!     if let Err(err) = golden.check(&scenario.run()) {
+//!         panic!("{}: {}", scenario.name(), err);
+//!     }
+//! }
+//! ```
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::pid_controller::PIDControllerBuilder;
+use crate::simulation::{SimulationBuilder, SimulationReport};
+use crate::RateLimiterBuilder;
+
+#[cfg(doc)]
+use crate::simulation::Simulation;
+
+/// Environment variable that makes [`Golden::check`] rewrite goldens instead of comparing.
+pub const UPDATE_ENV: &str = "NENYA_UPDATE_GOLDENS";
+
+/// Number of deviations a mismatch error lists.
+const MAX_REPORTED_DEVIATIONS: usize = 10;
+
+/// Decimal places values are recorded with, so goldens stay readable and diffable.
+const RECORDED_DECIMALS: i32 = 6;
+
+/// Values of a run, one row per tick and one column per metric.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Trajectory {
+    pub columns: Vec<String>,
+    pub rows: Vec<Vec<f64>>,
+}
+
+impl Trajectory {
+    /// Creates a trajectory from the metrics of every tick of a simulation, in the columns of
+    /// [`SimulationReport::write_csv`].
+    pub fn from_report(report: &SimulationReport) -> Self {
+        let columns = match report.ticks.first() {
+            Some(tick) => tick
+                .values()
+                .iter()
+                .map(|(name, _)| name.to_string())
+                .collect(),
+            None => Vec::new(),
+        };
+        let scale = 10f64.powi(RECORDED_DECIMALS);
+        let rows = report
+            .ticks
+            .iter()
+            .map(|tick| {
+                tick.values()
+                    .iter()
+                    .map(|(_, value)| (value * scale).round() / scale)
+                    .collect()
+            })
+            .collect();
+        Trajectory { columns, rows }
+    }
+
+    /// Reads a trajectory from CSV with a header row.
+    pub fn read_csv(reader: impl BufRead) -> io::Result<Self> {
+        let mut lines = reader.lines();
+        let columns = match lines.next() {
+            Some(header) => header?.split(',').map(str::to_string).collect(),
+            None => Vec::new(),
+        };
+        let mut rows = Vec::new();
+        for (index, line) in lines.enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let row = line
+                .split(',')
+                .map(|value| value.trim().parse::<f64>())
+                .collect::<Result<Vec<f64>, _>>()
+                .map_err(|err| {
+                    // The header is the first line
+                    io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("line {}: {}", index + 2, err),
+                    )
+                })?;
+            rows.push(row);
+        }
+        Ok(Trajectory { columns, rows })
+    }
+
+    /// Writes the trajectory as CSV with a header row.
+    pub fn write_csv(&self, mut writer: impl Write) -> io::Result<()> {
+        writeln!(writer, "{}", self.columns.join(","))?;
+        for row in &self.rows {
+            let values: Vec<String> = row.iter().map(f64::to_string).collect();
+            writeln!(writer, "{}", values.join(","))?;
+        }
+        Ok(())
+    }
+}
+
+/// How far a value may deviate from its golden.
+///
+/// A value passes if it is within `absolute + relative * |golden|` of the golden.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Tolerance {
+    pub absolute: f64,
+    pub relative: f64,
+}
+
+impl Tolerance {
+    /// Creates a tolerance of `absolute` plus `relative` times the golden value.
+    pub fn new(absolute: f64, relative: f64) -> Self {
+        Tolerance { absolute, relative }
+    }
+
+    /// Returns whether `actual` is within the tolerance of `expected`.
+    pub fn allows(&self, expected: f64, actual: f64) -> bool {
+        if expected.is_nan() || actual.is_nan() {
+            return expected.is_nan() && actual.is_nan();
+        }
+        expected == actual
+            || (actual - expected).abs() <= self.absolute + self.relative * expected.abs()
+    }
+}
+
+impl Default for Tolerance {
+    /// Allows for the rounding of recorded values only.
+    fn default() -> Self {
+        Tolerance::new(1e-6, 0.0)
+    }
+}
+
+/// Value of a run outside the tolerance of its golden.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Deviation {
+    /// Index of the row, from zero.
+    pub row: usize,
+    pub column: String,
+    pub expected: f64,
+    pub actual: f64,
+}
+
+impl fmt::Display for Deviation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "row {} {}: expected {}, got {}",
+            self.row, self.column, self.expected, self.actual
+        )
+    }
+}
+
+/// Errors returned when a run does not match its golden.
+#[derive(Debug)]
+pub enum GoldenError {
+    /// The golden could not be read or written.
+    Io(io::Error),
+    /// The golden does not exist, and [`UPDATE_ENV`] is not set to record it.
+    Missing(PathBuf),
+    /// The run records other metrics than the golden.
+    ColumnsChanged {
+        expected: Vec<String>,
+        actual: Vec<String>,
+    },
+    /// The run has another number of ticks than the golden.
+    LengthChanged { expected: usize, actual: usize },
+    /// Values of the run are outside the tolerance of the golden. Lists the first deviations
+    /// and counts all of them.
+    Mismatch {
+        deviations: Vec<Deviation>,
+        count: usize,
+    },
+}
+
+impl fmt::Display for GoldenError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GoldenError::Io(err) => write!(f, "unable to access golden: {}", err),
+            GoldenError::Missing(path) => write!(
+                f,
+                "golden {} does not exist, set {}=1 to record it",
+                path.display(),
+                UPDATE_ENV
+            ),
+            GoldenError::ColumnsChanged { expected, actual } => write!(
+                f,
+                "columns changed from {} to {}",
+                expected.join(","),
+                actual.join(",")
+            ),
+            GoldenError::LengthChanged { expected, actual } => {
+                write!(f, "expected {} rows, got {}", expected, actual)
+            }
+            GoldenError::Mismatch { deviations, count } => {
+                write!(f, "{} values deviate from the golden", count)?;
+                for deviation in deviations {
+                    write!(f, "\n  {}", deviation)?;
+                }
+                if *count > deviations.len() {
+                    write!(f, "\n  ...")?;
+                }
+                write!(
+                    f,
+                    "\nset {}=1 to accept the new behavior if it is intended",
+                    UPDATE_ENV
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for GoldenError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            GoldenError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for GoldenError {
+    fn from(err: io::Error) -> Self {
+        GoldenError::Io(err)
+    }
+}
+
+/// Golden trajectory stored in a CSV file.
+#[derive(Debug, Clone)]
+pub struct Golden {
+    path: PathBuf,
+    tolerance: Tolerance,
+    column_tolerances: HashMap<String, Tolerance>,
+}
+
+impl Golden {
+    /// Creates a golden stored at `path`, compared with the default [`Tolerance`].
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Golden {
+            path: path.into(),
+            tolerance: Tolerance::default(),
+            column_tolerances: HashMap::new(),
+        }
+    }
+
+    /// Sets the tolerance of columns without their own.
+    pub fn tolerance(mut self, tolerance: Tolerance) -> Self {
+        self.tolerance = tolerance;
+        self
+    }
+
+    /// Sets the tolerance of `column`, e.g. a looser one for a noisy derivative term.
+    pub fn column_tolerance(mut self, column: impl Into<String>, tolerance: Tolerance) -> Self {
+        self.column_tolerances.insert(column.into(), tolerance);
+        self
+    }
+
+    /// Returns the path of the golden.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Compares the trajectory of `report` against the golden, or records it as the golden if
+    /// [`UPDATE_ENV`] is set.
+    pub fn check(&self, report: &SimulationReport) -> Result<(), GoldenError> {
+        let actual = Trajectory::from_report(report);
+        if update_requested() {
+            return self.record(&actual);
+        }
+        self.compare(&self.load()?, &actual)
+    }
+
+    /// Writes `trajectory` as the golden, creating its directory if needed.
+    pub fn record(&self, trajectory: &Trajectory) -> Result<(), GoldenError> {
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        trajectory.write_csv(io::BufWriter::new(File::create(&self.path)?))?;
+        Ok(())
+    }
+
+    /// Reads the golden.
+    pub fn load(&self) -> Result<Trajectory, GoldenError> {
+        let file = match File::open(&self.path) {
+            Ok(file) => file,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                return Err(GoldenError::Missing(self.path.clone()))
+            }
+            Err(err) => return Err(err.into()),
+        };
+        Ok(Trajectory::read_csv(BufReader::new(file))?)
+    }
+
+    /// Compares `actual` against `expected` with the golden's tolerances.
+    pub fn compare(&self, expected: &Trajectory, actual: &Trajectory) -> Result<(), GoldenError> {
+        if expected.columns != actual.columns {
+            return Err(GoldenError::ColumnsChanged {
+                expected: expected.columns.clone(),
+                actual: actual.columns.clone(),
+            });
+        }
+        if expected.rows.len() != actual.rows.len() {
+            return Err(GoldenError::LengthChanged {
+                expected: expected.rows.len(),
+                actual: actual.rows.len(),
+            });
+        }
+
+        let tolerances: Vec<Tolerance> = expected
+            .columns
+            .iter()
+            .map(|column| {
+                self.column_tolerances
+                    .get(column)
+                    .copied()
+                    .unwrap_or(self.tolerance)
+            })
+            .collect();
+        let mut deviations = Vec::new();
+        let mut count = 0;
+        for (row, (expected_row, actual_row)) in expected.rows.iter().zip(&actual.rows).enumerate()
+        {
+            for (column, (&expected_value, &actual_value)) in
+                expected_row.iter().zip(actual_row).enumerate()
+            {
+                if tolerances[column].allows(expected_value, actual_value) {
+                    continue;
+                }
+                count += 1;
+                if deviations.len() < MAX_REPORTED_DEVIATIONS {
+                    deviations.push(Deviation {
+                        row,
+                        column: expected.columns[column].clone(),
+                        expected: expected_value,
+                        actual: actual_value,
+                    });
+                }
+            }
+        }
+        if count == 0 {
+            Ok(())
+        } else {
+            Err(GoldenError::Mismatch { deviations, count })
+        }
+    }
+}
+
+/// Returns whether [`UPDATE_ENV`] asks for goldens to be rewritten.
+fn update_requested() -> bool {
+    std::env::var_os(UPDATE_ENV).is_some_and(|value| !value.is_empty() && value != "0")
+}
+
+/// Simulated run exercising a distinct part of the control behavior.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CanonicalScenario {
+    /// The offered load steps from under the target rate to well over it.
+    StepInput,
+    /// A short burst of several times the target rate on top of a steady load.
+    Burst,
+    /// The setpoint rises halfway through a steady overload.
+    SetpointChange,
+}
+
+impl CanonicalScenario {
+    pub const ALL: [CanonicalScenario; 3] = [
+        CanonicalScenario::StepInput,
+        CanonicalScenario::Burst,
+        CanonicalScenario::SetpointChange,
+    ];
+
+    /// Returns the name of the scenario, which is also the name of its golden.
+    pub fn name(self) -> &'static str {
+        match self {
+            CanonicalScenario::StepInput => "step_input",
+            CanonicalScenario::Burst => "burst",
+            CanonicalScenario::SetpointChange => "setpoint_change",
+        }
+    }
+
+    /// Runs the scenario for a minute of virtual time in ticks of 100 ms.
+    pub fn run(self) -> SimulationReport {
+        let duration = Duration::from_secs(60);
+        let rate_limiter = |setpoint: f64| {
+            RateLimiterBuilder::new(setpoint)
+                .min_rate(10.0)
+                .max_rate(150.0)
+                .pid_controller(
+                    PIDControllerBuilder::new(setpoint)
+                        .kp(0.2)
+                        .ki(0.05)
+                        .kd(0.05)
+                        .error_limit(50.0)
+                        .output_limit(5.0)
+                        .build(),
+                )
+        };
+        match self {
+            CanonicalScenario::StepInput => {
+                let generator = |elapsed: Duration| {
+                    if elapsed < Duration::from_secs(20) {
+                        45.0
+                    } else {
+                        55.0
+                    }
+                };
+                SimulationBuilder::new(rate_limiter(50.0), generator)
+                    .build()
+                    .run(duration)
+            }
+            CanonicalScenario::Burst => {
+                let burst = Duration::from_secs(20)..Duration::from_secs(25);
+                let generator = move |elapsed: Duration| {
+                    if burst.contains(&elapsed) {
+                        150.0
+                    } else {
+                        48.0
+                    }
+                };
+                SimulationBuilder::new(rate_limiter(50.0), generator)
+                    .build()
+                    .run(duration)
+            }
+            CanonicalScenario::SetpointChange => {
+                let mut simulation =
+                    SimulationBuilder::new(rate_limiter(50.0), |_: Duration| 60.0).build();
+                let mut report = simulation.run(duration / 2);
+                simulation.rate_limiter_mut().set_setpoint(70.0);
+                report.ticks.extend(simulation.run(duration / 2).ticks);
+                report
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Directory of the crate's goldens.
+    fn goldens() -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("goldens")
+    }
+
+    #[test]
+    fn test_canonical_scenarios_match_goldens() {
+        for scenario in CanonicalScenario::ALL {
+            let golden = Golden::new(goldens().join(format!("{}.csv", scenario.name())))
+                .tolerance(Tolerance::new(1e-3, 1e-4));
+            if let Err(err) = golden.check(&scenario.run()) {
+                panic!("{}: {}", scenario.name(), err);
+            }
+        }
+    }
+
+    #[test]
+    fn test_reports_deviations_outside_tolerance() {
+        let report = CanonicalScenario::StepInput.run();
+        let expected = Trajectory::from_report(&report);
+        let mut csv = Vec::new();
+        expected.write_csv(&mut csv).unwrap();
+        assert_eq!(Trajectory::read_csv(&csv[..]).unwrap(), expected);
+
+        let target_rate = expected
+            .columns
+            .iter()
+            .position(|column| column == "target_rate")
+            .unwrap();
+        let mut actual = expected.clone();
+        actual.rows[100][target_rate] += 1.0;
+        actual.rows[200][target_rate] *= 1.05;
+
+        let golden = Golden::new("unused.csv").tolerance(Tolerance::new(0.5, 0.0));
+        match golden.compare(&expected, &actual) {
+            Err(GoldenError::Mismatch { deviations, count }) => {
+                assert_eq!(count, 2);
+                assert_eq!(deviations[0].row, 100);
+                assert_eq!(deviations[0].column, "target_rate");
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+        let golden = golden.column_tolerance("target_rate", Tolerance::new(1.0, 0.1));
+        assert!(golden.compare(&expected, &actual).is_ok());
+
+        actual.rows.pop();
+        assert!(matches!(
+            golden.compare(&expected, &actual),
+            Err(GoldenError::LengthChanged { .. })
+        ));
+        assert!(matches!(
+            Golden::new(goldens().join("nonexistent.csv")).load(),
+            Err(GoldenError::Missing(_))
+        ));
+    }
+}
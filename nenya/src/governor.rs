@@ -0,0 +1,371 @@
+//! Compatibility with code written against the [governor](https://docs.rs/governor) crate.
+//!
+//! [`DirectLimiter`] and [`KeyedLimiter`] mirror the methods of governor's direct and keyed
+//! rate limiters, so integration code calling `check`, `check_key`, `until_ready`, or
+//! `wait_time_from` keeps working while the limit itself becomes PID-adaptive. Existing quotas
+//! convert into a [`RateLimiterBuilder`] with the same rate.
+//!
+//! The clocks interoperate as well: nenya's [`SystemClock`] and [`ManualClock`] can drive a
+//! governor rate limiter, and [`GovernorClock`] lets a nenya rate limiter read any governor
+//! clock, such as its `FakeRelativeClock` in tests.
+//!
+//! # Example
+//!
+//! ```rust
+//! use std::num::NonZeroU32;
+//! use std::sync::Arc;
+//!
+//! use governor::Quota;
+//! use nenya::governor::DirectLimiter;
+//! use nenya::runtime::TokioTimer;
+//! use nenya::RateLimiterBuilder;
+//!
+//! let quota = Quota::per_second(NonZeroU32::new(50).unwrap());
+//! let rate_limiter = RateLimiterBuilder::<f64>::from(quota).max_rate(100.0).build();
+//! let limiter = DirectLimiter::with_timer(rate_limiter, Arc::new(TokioTimer));
+//! if let Err(not_until) = limiter.check() {
+//!     let wait = not_until.wait_time_from(limiter.now());
+//!     // Answer with a 429 and `wait` as Retry-After
+//! }
+//! ```
+
+use num_traits::{Float, FromPrimitive, Signed};
+use std::fmt;
+use std::hash::Hash;
+use std::num::NonZeroU32;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use governor::clock::Reference;
+use governor::nanos::Nanos;
+use governor::Quota;
+
+use crate::clock::{Clock, ManualClock, SystemClock, Timestamp};
+use crate::keyed::KeyedRateLimiter;
+use crate::runtime::Timer;
+use crate::{RateLimiter, RateLimiterBuilder};
+
+/// Shortest wait between attempts in `until_ready`, so waiting callers do not spin.
+const MIN_WAIT: Duration = Duration::from_millis(1);
+
+impl<T: Float + Signed + FromPrimitive + Copy> From<Quota> for RateLimiterBuilder<T> {
+    /// Creates a builder targeting the quota's replenishment rate.
+    ///
+    /// The minimum and maximum rates equal the target, so widen them to let the PID controller
+    /// adapt the rate.
+    fn from(quota: Quota) -> Self {
+        let rate = 1.0 / quota.replenish_interval().as_secs_f64();
+        RateLimiterBuilder::new(T::from_f64(rate).unwrap())
+    }
+}
+
+/// Error returned by a check over the target rate, saying when to try again.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NotUntil {
+    earliest: Timestamp,
+}
+
+impl NotUntil {
+    /// Returns the earliest time a request could be admitted.
+    pub fn earliest_possible(&self) -> Timestamp {
+        self.earliest
+    }
+
+    /// Returns how long to wait from `from` until a request could be admitted.
+    pub fn wait_time_from(&self, from: Timestamp) -> Duration {
+        self.earliest.duration_since(from)
+    }
+}
+
+impl fmt::Display for NotUntil {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "rate-limited until {:?}", self.earliest.as_duration())
+    }
+}
+
+impl std::error::Error for NotUntil {}
+
+/// Error returned when a batch costs more than the limiter admits per second at its maximum rate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InsufficientCapacity(pub u32);
+
+impl fmt::Display for InsufficientCapacity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "required number of cells {} exceeds the capacity",
+            self.0
+        )
+    }
+}
+
+impl std::error::Error for InsufficientCapacity {}
+
+/// Rate limiter with the methods of governor's direct rate limiter.
+#[derive(Debug)]
+pub struct DirectLimiter<T> {
+    rate_limiter: Mutex<RateLimiter<T>>,
+    timer: Arc<dyn Timer>,
+}
+
+impl<T: Float + Signed + FromPrimitive + Copy> DirectLimiter<T> {
+    /// Wraps `rate_limiter`, waiting on the tokio runtime.
+    #[cfg(feature = "tokio")]
+    pub fn new(rate_limiter: RateLimiter<T>) -> Self {
+        Self::with_timer(rate_limiter, Arc::new(crate::runtime::TokioTimer))
+    }
+
+    /// Wraps `rate_limiter`, waiting with `timer`.
+    pub fn with_timer(rate_limiter: RateLimiter<T>, timer: Arc<dyn Timer>) -> Self {
+        DirectLimiter {
+            rate_limiter: Mutex::new(rate_limiter),
+            timer,
+        }
+    }
+
+    /// Returns the current time on the rate limiter's clock.
+    pub fn now(&self) -> Timestamp {
+        self.rate_limiter.lock().unwrap().clock().now()
+    }
+
+    /// Admits a request if it fits under the target rate.
+    pub fn check(&self) -> Result<(), NotUntil> {
+        check_weighted(&mut self.rate_limiter.lock().unwrap(), T::one())
+    }
+
+    /// Admits a batch of `n` requests if it fits under the target rate.
+    pub fn check_n(&self, n: NonZeroU32) -> Result<Result<(), NotUntil>, InsufficientCapacity> {
+        let mut rate_limiter = self.rate_limiter.lock().unwrap();
+        let cost = batch_cost(&rate_limiter, n)?;
+        Ok(check_weighted(&mut rate_limiter, cost))
+    }
+
+    /// Waits until a request is admitted.
+    pub async fn until_ready(&self) {
+        loop {
+            let now = self.now();
+            match self.check() {
+                Ok(()) => return,
+                Err(not_until) => {
+                    let wait = not_until.wait_time_from(now).max(MIN_WAIT);
+                    self.timer.sleep(wait).await;
+                }
+            }
+        }
+    }
+}
+
+/// Keyed rate limiter with the methods of governor's keyed rate limiter.
+#[derive(Debug)]
+pub struct KeyedLimiter<K, T> {
+    rate_limiter: Mutex<KeyedRateLimiter<K, T>>,
+    timer: Arc<dyn Timer>,
+}
+
+impl<K: Eq + Hash + Clone, T: Float + Signed + FromPrimitive + Copy> KeyedLimiter<K, T> {
+    /// Wraps `rate_limiter`, waiting on the tokio runtime.
+    #[cfg(feature = "tokio")]
+    pub fn new(rate_limiter: KeyedRateLimiter<K, T>) -> Self {
+        Self::with_timer(rate_limiter, Arc::new(crate::runtime::TokioTimer))
+    }
+
+    /// Wraps `rate_limiter`, waiting with `timer`.
+    pub fn with_timer(rate_limiter: KeyedRateLimiter<K, T>, timer: Arc<dyn Timer>) -> Self {
+        KeyedLimiter {
+            rate_limiter: Mutex::new(rate_limiter),
+            timer,
+        }
+    }
+
+    /// Returns the current time on the keys' clock.
+    pub fn now(&self) -> Timestamp {
+        SystemClock.now()
+    }
+
+    /// Admits a request from `key` if it fits under the key's rate.
+    pub fn check_key(&self, key: &K) -> Result<(), NotUntil> {
+        self.check_key_weighted(key, T::one())
+    }
+
+    /// Admits a batch of `n` requests from `key` if it fits under the key's rate.
+    pub fn check_key_n(
+        &self,
+        key: &K,
+        n: NonZeroU32,
+    ) -> Result<Result<(), NotUntil>, InsufficientCapacity> {
+        let key_rate = self.rate_limiter.lock().unwrap().key_rate();
+        if T::from_u32(n.get()).is_none_or(|cost| cost > key_rate) {
+            return Err(InsufficientCapacity(key_rate.to_u32().unwrap_or(0)));
+        }
+        Ok(self.check_key_weighted(key, T::from_u32(n.get()).unwrap()))
+    }
+
+    /// Waits until a request from `key` is admitted.
+    pub async fn until_key_ready(&self, key: &K) {
+        loop {
+            let now = self.now();
+            match self.check_key(key) {
+                Ok(()) => return,
+                Err(not_until) => {
+                    let wait = not_until.wait_time_from(now).max(MIN_WAIT);
+                    self.timer.sleep(wait).await;
+                }
+            }
+        }
+    }
+
+    fn check_key_weighted(&self, key: &K, cost: T) -> Result<(), NotUntil> {
+        let mut rate_limiter = self.rate_limiter.lock().unwrap();
+        if !rate_limiter.should_throttle_weighted(key, cost) {
+            return Ok(());
+        }
+        let key_limiter = rate_limiter.get(key).expect("checked keys have a limiter");
+        Err(NotUntil {
+            earliest: key_limiter.clock().now() + key_limiter.retry_after(),
+        })
+    }
+}
+
+fn check_weighted<T: Float + Signed + FromPrimitive + Copy>(
+    rate_limiter: &mut RateLimiter<T>,
+    cost: T,
+) -> Result<(), NotUntil> {
+    if !rate_limiter.should_throttle_weighted(cost) {
+        return Ok(());
+    }
+    Err(NotUntil {
+        earliest: rate_limiter.clock().now() + rate_limiter.retry_after(),
+    })
+}
+
+/// Returns the cost of a batch of `n` requests, or an error if it exceeds a second's worth of
+/// the maximum rate, which the limiter could never admit without going over its bounds.
+fn batch_cost<T: Float + Signed + FromPrimitive + Copy>(
+    rate_limiter: &RateLimiter<T>,
+    n: NonZeroU32,
+) -> Result<T, InsufficientCapacity> {
+    let max_rate = rate_limiter.max_rate();
+    match T::from_u32(n.get()) {
+        Some(cost) if cost <= max_rate => Ok(cost),
+        _ => Err(InsufficientCapacity(max_rate.to_u32().unwrap_or(0))),
+    }
+}
+
+impl std::ops::Add<Nanos> for Timestamp {
+    type Output = Timestamp;
+
+    fn add(self, nanos: Nanos) -> Timestamp {
+        self + Duration::from(nanos)
+    }
+}
+
+impl Reference for Timestamp {
+    fn duration_since(&self, earlier: Self) -> Nanos {
+        Timestamp::duration_since(self, earlier).into()
+    }
+
+    fn saturating_sub(&self, duration: Nanos) -> Self {
+        Timestamp::from_duration(self.as_duration().saturating_sub(duration.into()))
+    }
+}
+
+impl governor::clock::Clock for SystemClock {
+    type Instant = Timestamp;
+
+    fn now(&self) -> Timestamp {
+        Clock::now(self)
+    }
+}
+
+impl governor::clock::Clock for ManualClock {
+    type Instant = Timestamp;
+
+    fn now(&self) -> Timestamp {
+        Clock::now(self)
+    }
+}
+
+/// Adapter reading the time from a governor clock.
+#[derive(Debug, Clone)]
+pub struct GovernorClock<C: governor::clock::Clock> {
+    clock: C,
+    epoch: C::Instant,
+}
+
+impl<C: governor::clock::Clock> GovernorClock<C> {
+    /// Wraps `clock`, taking its current time as the epoch.
+    pub fn new(clock: C) -> Self {
+        let epoch = clock.now();
+        GovernorClock { clock, epoch }
+    }
+}
+
+impl<C> Clock for GovernorClock<C>
+where
+    C: governor::clock::Clock + fmt::Debug + Send + Sync,
+{
+    fn now(&self) -> Timestamp {
+        let elapsed = self.clock.now().duration_since(self.epoch);
+        Timestamp::from_duration(elapsed.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runtime::Sleep;
+    use governor::clock::FakeRelativeClock;
+
+    #[derive(Debug)]
+    struct Immediate;
+
+    impl Timer for Immediate {
+        fn sleep(&self, _duration: Duration) -> Sleep {
+            Box::pin(std::future::ready(()))
+        }
+    }
+
+    #[test]
+    fn test_direct_limiter_checks_like_governor() {
+        let quota = Quota::per_second(NonZeroU32::new(10).unwrap());
+        let builder: RateLimiterBuilder<f64> = quota.into();
+        let clock = FakeRelativeClock::default();
+        let limiter = DirectLimiter::with_timer(
+            builder
+                .clock(Arc::new(GovernorClock::new(clock.clone())))
+                .build(),
+            Arc::new(Immediate),
+        );
+
+        let not_until = (0..20).find_map(|_| limiter.check().err()).unwrap();
+        assert!(not_until.wait_time_from(limiter.now()) > Duration::ZERO);
+        clock.advance(Duration::from_secs(2));
+        assert_eq!(limiter.check(), Ok(()));
+        assert_eq!(
+            limiter.check_n(NonZeroU32::new(11).unwrap()),
+            Err(InsufficientCapacity(10))
+        );
+    }
+
+    #[test]
+    fn test_keyed_limiter_checks_keys_independently() {
+        let limiter = KeyedLimiter::with_timer(KeyedRateLimiter::new(2.0), Arc::new(Immediate));
+        assert!((0..10).any(|_| limiter.check_key(&"a").is_err()));
+        assert_eq!(limiter.check_key(&"b"), Ok(()));
+        assert_eq!(
+            limiter.check_key_n(&"b", NonZeroU32::new(3).unwrap()),
+            Err(InsufficientCapacity(2))
+        );
+    }
+
+    #[test]
+    fn test_nenya_clock_drives_governor() {
+        let clock = ManualClock::new();
+        let quota = Quota::per_second(NonZeroU32::new(1).unwrap());
+        let limiter = governor::RateLimiter::direct_with_clock(quota, &clock);
+        assert!(limiter.check().is_ok());
+        assert!(limiter.check().is_err());
+        clock.advance(Duration::from_secs(1));
+        assert!(limiter.check().is_ok());
+    }
+}
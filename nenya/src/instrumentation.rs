@@ -0,0 +1,92 @@
+use metrics::{Counter, Gauge};
+use std::fmt;
+
+/// Metrics a rate limiter reports through the [`metrics`] facade.
+///
+/// The handles are registered when the limiter is built, so the application's recorder must be
+/// installed before then.
+#[derive(Clone)]
+pub(crate) struct LimiterMetrics {
+    requests: Counter,
+    throttled: Counter,
+    target_rate: Gauge,
+    pid_output: Gauge,
+}
+
+impl LimiterMetrics {
+    /// Registers the metrics of a limiter, labelled with `limiter = name` if it has a name.
+    pub(crate) fn new(name: Option<&str>) -> Self {
+        let labels: Vec<(&'static str, String)> = name
+            .map(|name| ("limiter", name.to_string()))
+            .into_iter()
+            .collect();
+        LimiterMetrics {
+            requests: metrics::counter!("nenya_requests_total", &labels),
+            throttled: metrics::counter!("nenya_throttled_total", &labels),
+            target_rate: metrics::gauge!("nenya_target_rate", &labels),
+            pid_output: metrics::gauge!("nenya_pid_output", &labels),
+        }
+    }
+
+    pub(crate) fn record_decision(&self, throttled: bool, target_rate: f64) {
+        self.requests.increment(1);
+        if throttled {
+            self.throttled.increment(1);
+        }
+        self.target_rate.set(target_rate);
+    }
+
+    pub(crate) fn record_pid_output(&self, output: f64) {
+        self.pid_output.set(output);
+    }
+}
+
+impl fmt::Debug for LimiterMetrics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LimiterMetrics").finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::RateLimiterBuilder;
+    use metrics_util::debugging::{DebugValue, DebuggingRecorder};
+    use metrics_util::MetricKind;
+
+    #[test]
+    fn test_reports_decisions_through_the_facade() {
+        let recorder = DebuggingRecorder::new();
+        let snapshotter = recorder.snapshotter();
+        metrics::with_local_recorder(&recorder, || {
+            let mut rate_limiter = RateLimiterBuilder::new(5.0)
+                .metrics_name("checkout")
+                .build();
+            for _ in 0..20 {
+                rate_limiter.should_throttle();
+            }
+        });
+
+        let snapshot = snapshotter.snapshot().into_vec();
+        let value = |kind: MetricKind, name: &str| {
+            snapshot
+                .iter()
+                .find(|(key, _, _, _)| key.kind() == kind && key.key().name() == name)
+                .map(|(key, _, _, value)| {
+                    assert!(key
+                        .key()
+                        .labels()
+                        .any(|label| label.key() == "limiter" && label.value() == "checkout"));
+                    value
+                })
+        };
+        assert_eq!(
+            value(MetricKind::Counter, "nenya_requests_total"),
+            Some(&DebugValue::Counter(20))
+        );
+        assert!(matches!(
+            value(MetricKind::Counter, "nenya_throttled_total"),
+            Some(DebugValue::Counter(throttled)) if *throttled > 0
+        ));
+        assert!(value(MetricKind::Gauge, "nenya_target_rate").is_some());
+    }
+}
@@ -0,0 +1,205 @@
+//! Pacing of [rdkafka](https://docs.rs/rdkafka) consumers.
+//!
+//! A [`ConsumerPacer`] counts every consumed message against a [`RateLimiter`]. Once the
+//! consumer goes over the target rate, the pacer pauses all of its assigned partitions and
+//! resumes them when the limiter has room again. Pausing rather than not polling keeps the
+//! consumer in its group, since rebalances and heartbeats are still served while the partitions
+//! are paused.
+//!
+//! The consumer's lag is a backlog the target rate should be able to drain. Fed back with
+//! [`ConsumerPacer::record_lag`], it counts as external request rate, so a consumer falling
+//! behind pushes the PID controller towards a higher target rate within the limiter's bounds.
+//!
+//! # Example
+//!
+//! ```rust,no_run
+//! use std::time::Duration;
+//!
+//! use nenya::kafka::ConsumerPacer;
+//! use nenya::RateLimiterBuilder;
+//! use rdkafka::consumer::{BaseConsumer, Consumer};
+//! use rdkafka::ClientConfig;
+//!
+//! let consumer: BaseConsumer = ClientConfig::new()
+//!     .set("group.id", "orders")
+//!     .set("bootstrap.servers", "localhost:9092")
+//!     .create()
+//!     .unwrap();
+//! consumer.subscribe(&["orders"]).unwrap();
+//!
+//! let mut pacer = ConsumerPacer::new(RateLimiterBuilder::new(500.0).max_rate(2000.0).build());
+//! loop {
+//!     if let Some(message) = pacer.poll(&consumer, Duration::from_millis(100)) {
+//!         // Handle the message
+//!     }
+//! }
+//! ```
+
+use num_traits::{Float, FromPrimitive, Signed};
+use std::time::Duration;
+
+use rdkafka::consumer::{BaseConsumer, Consumer, ConsumerContext};
+use rdkafka::error::KafkaResult;
+use rdkafka::message::BorrowedMessage;
+use rdkafka::{Offset, TopicPartitionList};
+
+use crate::clock::Timestamp;
+use crate::RateLimiter;
+
+/// Pauses and resumes a consumer's partitions to keep it within a rate limiter's target rate.
+#[derive(Debug)]
+pub struct ConsumerPacer<T> {
+    rate_limiter: RateLimiter<T>,
+    /// When the paused partitions are resumed on the rate limiter's clock, set while they are
+    /// paused.
+    paused_until: Option<Timestamp>,
+    lag_drain_time: Duration,
+}
+
+impl<T: Float + Signed + FromPrimitive + Copy> ConsumerPacer<T> {
+    /// Creates a pacer consuming messages at up to the target rate of `rate_limiter`.
+    ///
+    /// The rate limiter's clock times when paused partitions are resumed.
+    pub fn new(rate_limiter: RateLimiter<T>) -> Self {
+        ConsumerPacer {
+            rate_limiter,
+            paused_until: None,
+            lag_drain_time: Duration::from_secs(60),
+        }
+    }
+
+    /// Sets how quickly the lag reported to [`ConsumerPacer::record_lag`] should be drained.
+    ///
+    /// Defaults to a minute.
+    pub fn with_lag_drain_time(mut self, lag_drain_time: Duration) -> Self {
+        self.lag_drain_time = lag_drain_time;
+        self
+    }
+
+    /// Returns the pacer's rate limiter.
+    pub fn rate_limiter(&self) -> &RateLimiter<T> {
+        &self.rate_limiter
+    }
+
+    /// Returns `true` while the consumer's partitions are paused.
+    pub fn is_paused(&self) -> bool {
+        self.paused_until.is_some()
+    }
+
+    /// Polls `consumer` for a message, pausing and resuming its partitions to pace consumption.
+    ///
+    /// Keep calling this while paused; it serves the consumer's events without returning
+    /// messages until the partitions are resumed.
+    pub fn poll<'a, C: ConsumerContext>(
+        &mut self,
+        consumer: &'a BaseConsumer<C>,
+        timeout: Duration,
+    ) -> Option<KafkaResult<BorrowedMessage<'a>>> {
+        if let Err(err) = self.resume_if_due(consumer) {
+            return Some(Err(err));
+        }
+        let message = consumer.poll(timeout)?;
+        if message.is_ok() {
+            if let Err(err) = self.record(consumer, T::one()) {
+                return Some(Err(err));
+            }
+        }
+        Some(message)
+    }
+
+    /// Counts a consumed message of `cost` units, pausing the consumer's partitions if it went
+    /// over the target rate.
+    ///
+    /// Use this with consumers other than [`BaseConsumer`], e.g. a `StreamConsumer`, together
+    /// with [`ConsumerPacer::resume_if_due`].
+    pub fn record<C: ConsumerContext>(
+        &mut self,
+        consumer: &impl Consumer<C>,
+        cost: impl Into<T>,
+    ) -> KafkaResult<()> {
+        if !self.rate_limiter.should_throttle_weighted(cost) || self.paused_until.is_some() {
+            return Ok(());
+        }
+        consumer.pause(&consumer.assignment()?)?;
+        self.paused_until = Some(self.rate_limiter.clock().now() + self.rate_limiter.retry_after());
+        Ok(())
+    }
+
+    /// Resumes the consumer's partitions if they are paused and the limiter has room again.
+    pub fn resume_if_due<C: ConsumerContext>(
+        &mut self,
+        consumer: &impl Consumer<C>,
+    ) -> KafkaResult<()> {
+        match self.paused_until {
+            Some(paused_until) if paused_until <= self.rate_limiter.clock().now() => {
+                consumer.resume(&consumer.assignment()?)?;
+                self.paused_until = None;
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Feeds the consumer's lag, in messages, back into the rate limiter as the rate needed to
+    /// drain it within the lag drain time.
+    pub fn record_lag(&mut self, lag: u64) {
+        let drain_rate = lag as f64 / self.lag_drain_time.as_secs_f64().max(f64::EPSILON);
+        self.rate_limiter
+            .set_external_request_rate(T::from_f64(drain_rate).unwrap_or_else(T::zero));
+    }
+}
+
+/// Returns the total lag of the partitions assigned to `consumer`: the messages between each
+/// partition's position and its high watermark.
+///
+/// This queries the brokers for every partition, so call it every few seconds rather than per
+/// message.
+pub fn consumer_lag<C: ConsumerContext>(
+    consumer: &impl Consumer<C>,
+    timeout: Duration,
+) -> KafkaResult<u64> {
+    let positions: TopicPartitionList = consumer.position()?;
+    let mut lag = 0;
+    for partition in positions.elements() {
+        let (_, high) =
+            consumer.fetch_watermarks(partition.topic(), partition.partition(), timeout)?;
+        if let Offset::Offset(position) = partition.offset() {
+            lag += u64::try_from(high - position).unwrap_or(0);
+        }
+    }
+    Ok(lag)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::ManualClock;
+    use crate::RateLimiterBuilder;
+    use rdkafka::ClientConfig;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_pauses_over_target_and_feeds_lag() {
+        let consumer: BaseConsumer = ClientConfig::new()
+            .set("group.id", "test")
+            .set("bootstrap.servers", "localhost:1")
+            .set("log_level", "0")
+            .create()
+            .unwrap();
+        let clock = Arc::new(ManualClock::new());
+        let rate_limiter = RateLimiterBuilder::new(5.0).clock(clock.clone()).build();
+        let mut pacer =
+            ConsumerPacer::new(rate_limiter).with_lag_drain_time(Duration::from_secs(10));
+
+        for _ in 0..20 {
+            pacer.record(&consumer, 1.0).unwrap();
+        }
+        assert!(pacer.is_paused());
+        clock.advance(pacer.rate_limiter().retry_after());
+        pacer.resume_if_due(&consumer).unwrap();
+        assert!(!pacer.is_paused());
+
+        pacer.record_lag(1000);
+        assert_eq!(pacer.rate_limiter().external_request_rate(), 100.0);
+    }
+}
@@ -0,0 +1,233 @@
+//! Extraction of the key a request is rate limited by.
+//!
+//! A [`KeyExtractor`] picks the part of a request that identifies who is limited, e.g. the
+//! client's address or an API key. The keyed middleware of the tower and axum integrations take
+//! any extractor, including a closure returning the key.
+//!
+//! With the `http` feature, extractors for the common cases of HTTP requests are provided:
+//!
+//! - [`PeerIp`]: the client's IP address, read through trusted proxies' `X-Forwarded-For`
+//! - [`Header`]: the value of a header, such as an API key
+//! - [`BearerSubject`]: the `sub` claim of a JWT bearer token
+//! - [`RouteTemplate`]: the route the request matched, to limit each endpoint as a whole
+
+/// Picks the key a request is rate limited by.
+///
+/// Returns `None` for requests without a key, which the middleware limits under a single shared
+/// key.
+pub trait KeyExtractor<Request> {
+    /// Returns the key of `request`, if it has one.
+    fn extract(&self, request: &Request) -> Option<String>;
+}
+
+impl<Request, F> KeyExtractor<Request> for F
+where
+    F: Fn(&Request) -> Option<String>,
+{
+    fn extract(&self, request: &Request) -> Option<String> {
+        self(request)
+    }
+}
+
+#[cfg(feature = "http")]
+pub use self::http_extractors::*;
+
+#[cfg(feature = "http")]
+mod http_extractors {
+    use std::net::{IpAddr, SocketAddr};
+
+    use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+    use base64::Engine;
+    use http::header::{HeaderName, AUTHORIZATION};
+    use http::Request;
+    use ipnet::IpNet;
+
+    use super::KeyExtractor;
+
+    const X_FORWARDED_FOR: HeaderName = HeaderName::from_static("x-forwarded-for");
+
+    /// Keys requests by the client's IP address.
+    ///
+    /// The address of the connected peer is read from axum's `ConnectInfo<SocketAddr>` or a
+    /// [`SocketAddr`] request extension. When the peer is a trusted proxy, the client is the
+    /// right-most address in `X-Forwarded-For` that is not a trusted proxy itself. Addresses in
+    /// the header are only believed as far as the chain of trusted proxies reaches, so clients
+    /// cannot pick their key by sending the header themselves.
+    #[derive(Debug, Clone, Default, PartialEq, Eq)]
+    pub struct PeerIp {
+        trusted_proxies: Vec<IpNet>,
+    }
+
+    impl PeerIp {
+        /// Creates an extractor trusting no proxies.
+        pub fn new() -> Self {
+            Self::default()
+        }
+
+        /// Trusts the proxies in `network`, e.g. `10.0.0.0/8` for a load balancer in the VPC.
+        pub fn trust_proxies(mut self, network: IpNet) -> Self {
+            self.trusted_proxies.push(network);
+            self
+        }
+
+        fn is_trusted(&self, ip: &IpAddr) -> bool {
+            self.trusted_proxies
+                .iter()
+                .any(|network| network.contains(ip))
+        }
+
+        fn peer<B>(request: &Request<B>) -> Option<IpAddr> {
+            #[cfg(feature = "axum")]
+            {
+                use axum::extract::ConnectInfo;
+                if let Some(ConnectInfo(addr)) =
+                    request.extensions().get::<ConnectInfo<SocketAddr>>()
+                {
+                    return Some(addr.ip());
+                }
+            }
+            request.extensions().get::<SocketAddr>().map(SocketAddr::ip)
+        }
+    }
+
+    impl<B> KeyExtractor<Request<B>> for PeerIp {
+        fn extract(&self, request: &Request<B>) -> Option<String> {
+            let mut client = Self::peer(request)?;
+            let forwarded = request
+                .headers()
+                .get_all(X_FORWARDED_FOR)
+                .iter()
+                .filter_map(|value| value.to_str().ok())
+                .flat_map(|value| value.split(','))
+                .collect::<Vec<_>>();
+            for hop in forwarded.iter().rev() {
+                if !self.is_trusted(&client) {
+                    break;
+                }
+                match hop.trim().parse() {
+                    Ok(ip) => client = ip,
+                    Err(_) => break,
+                }
+            }
+            Some(client.to_string())
+        }
+    }
+
+    /// Keys requests by the value of a header, such as an API key.
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct Header(pub HeaderName);
+
+    impl<B> KeyExtractor<Request<B>> for Header {
+        fn extract(&self, request: &Request<B>) -> Option<String> {
+            request
+                .headers()
+                .get(&self.0)
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_string)
+        }
+    }
+
+    /// Keys requests by the `sub` claim of the JWT in their `Authorization: Bearer` header.
+    ///
+    /// The token's signature is not verified, so anyone can put any subject in a token. Only
+    /// use this behind middleware that rejects requests with invalid tokens, or callers can
+    /// escape their limit by claiming a new subject with every request.
+    #[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+    pub struct BearerSubject;
+
+    impl<B> KeyExtractor<Request<B>> for BearerSubject {
+        fn extract(&self, request: &Request<B>) -> Option<String> {
+            let authorization = request.headers().get(AUTHORIZATION)?.to_str().ok()?;
+            let (scheme, token) = authorization.split_once(' ')?;
+            if !scheme.eq_ignore_ascii_case("bearer") {
+                return None;
+            }
+            let payload = token.trim().split('.').nth(1)?;
+            let claims: serde_json::Value =
+                serde_json::from_slice(&URL_SAFE_NO_PAD.decode(payload).ok()?).ok()?;
+            claims.get("sub")?.as_str().map(str::to_string)
+        }
+    }
+
+    /// Keys requests by the route template they matched, e.g. `/users/:id`.
+    ///
+    /// The template is read from axum's `MatchedPath`. Outside of an axum router, the request
+    /// path is used instead.
+    #[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+    pub struct RouteTemplate;
+
+    impl<B> KeyExtractor<Request<B>> for RouteTemplate {
+        fn extract(&self, request: &Request<B>) -> Option<String> {
+            #[cfg(feature = "axum")]
+            if let Some(path) = request.extensions().get::<axum::extract::MatchedPath>() {
+                return Some(path.as_str().to_string());
+            }
+            Some(request.uri().path().to_string())
+        }
+    }
+}
+
+#[cfg(all(test, feature = "http"))]
+mod tests {
+    use super::*;
+    use http::header::HeaderName;
+    use http::Request;
+    use std::net::SocketAddr;
+
+    fn request(peer: &str, forwarded_for: Option<&str>) -> Request<()> {
+        let mut builder = Request::builder().uri("/orders/1");
+        if let Some(forwarded_for) = forwarded_for {
+            builder = builder.header("x-forwarded-for", forwarded_for);
+        }
+        let mut request = builder.body(()).unwrap();
+        request
+            .extensions_mut()
+            .insert(peer.parse::<SocketAddr>().unwrap());
+        request
+    }
+
+    #[test]
+    fn test_peer_ip_only_believes_trusted_proxies() {
+        let extractor = PeerIp::new().trust_proxies("10.0.0.0/8".parse().unwrap());
+        let key = |peer, forwarded_for| extractor.extract(&request(peer, forwarded_for));
+
+        assert_eq!(key("10.1.2.3:80", None), Some("10.1.2.3".to_string()));
+        assert_eq!(
+            key("10.1.2.3:80", Some("203.0.113.7, 10.9.9.9")),
+            Some("203.0.113.7".to_string())
+        );
+        // Addresses the client sent itself, before the first untrusted hop, are ignored
+        assert_eq!(
+            key("10.1.2.3:80", Some("1.1.1.1, 198.51.100.2")),
+            Some("198.51.100.2".to_string())
+        );
+        assert_eq!(
+            key("198.51.100.2:80", Some("1.1.1.1")),
+            Some("198.51.100.2".to_string())
+        );
+    }
+
+    #[test]
+    fn test_header_bearer_and_route_keys() {
+        // {"alg":"none"}.{"sub":"user-42"}
+        let token = "eyJhbGciOiJub25lIn0.eyJzdWIiOiJ1c2VyLTQyIn0.";
+        let request = Request::builder()
+            .uri("/orders/1")
+            .header("x-api-key", "abc")
+            .header("authorization", format!("Bearer {token}"))
+            .body(())
+            .unwrap();
+
+        let header = Header(HeaderName::from_static("x-api-key"));
+        assert_eq!(header.extract(&request), Some("abc".to_string()));
+        assert_eq!(BearerSubject.extract(&request), Some("user-42".to_string()));
+        assert_eq!(
+            RouteTemplate.extract(&request),
+            Some("/orders/1".to_string())
+        );
+        assert_eq!(PeerIp::new().extract(&request), None);
+
+        let closure = |request: &Request<()>| Some(request.method().to_string());
+        assert_eq!(closure.extract(&request), Some("GET".to_string()));
+    }
+}
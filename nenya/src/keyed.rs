@@ -0,0 +1,194 @@
+//! Rate limiting of the individual keys sharing a resource.
+//!
+//! A [`KeyedRateLimiter`] gives every key, e.g. a caller or an API key, its own fixed rate limit.
+//! Combined with a [`RateLimiter`] for the resource as a whole, it enforces a hierarchy of limits
+//! where no single key can take more than its quota of the resource's budget.
+//!
+//! Keys that stop sending requests are evicted once their sliding window is empty, so memory
+//! stays proportional to the keys active recently rather than every key ever seen. The
+//! [`soak`](crate::soak) module measures this over millions of decisions.
+
+use num_traits::{Float, FromPrimitive, Signed};
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::clock::Clock;
+use crate::{RateLimiter, RateLimiterBuilder};
+
+/// Number of keys below which new keys never trigger a sweep for idle keys.
+const MIN_SWEEP_KEYS: usize = 1024;
+
+/// Rate limiters enforcing the same fixed rate for each key, created on the key's first request.
+#[derive(Debug)]
+pub struct KeyedRateLimiter<K, T> {
+    key_rate: T,
+    limiters: HashMap<K, RateLimiter<T>>,
+    clock: Option<Arc<dyn Clock>>,
+    /// Number of keys left after the last sweep for idle keys.
+    swept_len: usize,
+}
+
+impl<K: Eq + Hash + Clone, T: Float + Signed + FromPrimitive + Copy> KeyedRateLimiter<K, T> {
+    /// Creates a keyed rate limiter allowing each key `key_rate` requests per second.
+    pub fn new(key_rate: T) -> Self {
+        KeyedRateLimiter {
+            key_rate,
+            limiters: HashMap::new(),
+            clock: None,
+            swept_len: 0,
+        }
+    }
+
+    /// Creates a keyed rate limiter allowing each key `key_rate` requests per second, whose
+    /// limiters read the time from `clock`.
+    pub fn with_clock(key_rate: T, clock: Arc<dyn Clock>) -> Self {
+        KeyedRateLimiter {
+            clock: Some(clock),
+            ..Self::new(key_rate)
+        }
+    }
+
+    /// Returns the rate each key is limited to.
+    pub fn key_rate(&self) -> T {
+        self.key_rate
+    }
+
+    /// Sets the rate each key is limited to, including keys already seen.
+    pub fn set_key_rate(&mut self, key_rate: T) {
+        self.key_rate = key_rate;
+        for rate_limiter in self.limiters.values_mut() {
+            rate_limiter.set_fixed_target_rate(Some(key_rate));
+        }
+    }
+
+    /// Determines if a request from `key` should be throttled.
+    pub fn should_throttle(&mut self, key: &K) -> bool {
+        self.should_throttle_weighted(key, T::one())
+    }
+
+    /// Determines if a request from `key` consuming `cost` units of its budget should be
+    /// throttled.
+    ///
+    /// Whenever new keys have doubled the number of keys since the last sweep, idle keys are
+    /// evicted first, which keeps the cost of sweeping constant per request.
+    pub fn should_throttle_weighted(&mut self, key: &K, cost: impl Into<T>) -> bool {
+        if !self.limiters.contains_key(key) {
+            if self.limiters.len() >= (2 * self.swept_len).max(MIN_SWEEP_KEYS) {
+                self.evict_idle();
+            }
+            let mut builder = RateLimiterBuilder::new(self.key_rate);
+            if let Some(clock) = &self.clock {
+                builder = builder.clock(clock.clone());
+            }
+            let mut rate_limiter = builder.build();
+            rate_limiter.set_fixed_target_rate(Some(self.key_rate));
+            self.limiters.insert(key.clone(), rate_limiter);
+        }
+        self.limiters
+            .get_mut(key)
+            .unwrap()
+            .should_throttle_weighted(cost)
+    }
+
+    /// Forgets the keys without a request in their sliding window, returning how many were
+    /// evicted.
+    ///
+    /// An idle key's limiter decides like a new one, so evicting it changes no decision.
+    pub fn evict_idle(&mut self) -> usize {
+        let len = self.limiters.len();
+        self.limiters
+            .retain(|_, rate_limiter| !rate_limiter.is_idle());
+        self.swept_len = self.limiters.len();
+        len - self.swept_len
+    }
+
+    /// Returns how much more accepted traffic from `key` fits under its rate, in requests per
+    /// second.
+    pub fn remaining_capacity(&self, key: &K) -> T {
+        self.limiters
+            .get(key)
+            .map_or(self.key_rate, RateLimiter::remaining_capacity)
+    }
+
+    /// Returns how long a throttled `key` should wait before retrying.
+    pub fn retry_after(&self, key: &K) -> Duration {
+        self.limiters
+            .get(key)
+            .map_or(Duration::ZERO, RateLimiter::retry_after)
+    }
+
+    /// Returns the rate limiter of `key`, if it has made requests.
+    pub fn get(&self, key: &K) -> Option<&RateLimiter<T>> {
+        self.limiters.get(key)
+    }
+
+    /// Returns the keys that have made requests and their rate limiters, in arbitrary order.
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &RateLimiter<T>)> {
+        self.limiters.iter()
+    }
+
+    /// Forgets `key`, resetting its budget.
+    pub fn remove(&mut self, key: &K) {
+        self.limiters.remove(key);
+    }
+
+    /// Returns the number of keys that have made requests.
+    pub fn len(&self) -> usize {
+        self.limiters.len()
+    }
+
+    /// Returns `true` if no key has made requests.
+    pub fn is_empty(&self) -> bool {
+        self.limiters.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_keys_are_limited_independently() {
+        let mut limiter: KeyedRateLimiter<&str, f64> = KeyedRateLimiter::new(5.0);
+        let throttled = (0..20)
+            .filter(|_| limiter.should_throttle(&"noisy"))
+            .count();
+        assert!(throttled > 0);
+        assert!(limiter.retry_after(&"noisy") > Duration::ZERO);
+
+        assert!(!limiter.should_throttle(&"quiet"));
+        assert_eq!(limiter.remaining_capacity(&"unknown"), 5.0);
+        assert_eq!(limiter.len(), 2);
+
+        limiter.set_key_rate(100.0);
+        assert_eq!(limiter.get(&"noisy").unwrap().target_rate(), 100.0);
+        limiter.remove(&"noisy");
+        assert_eq!(limiter.len(), 1);
+    }
+
+    #[test]
+    fn test_evicts_idle_keys() {
+        let clock = crate::clock::ManualClock::new();
+        let mut limiter: KeyedRateLimiter<usize, f64> =
+            KeyedRateLimiter::with_clock(5.0, Arc::new(clock.clone()));
+        assert!(!limiter.should_throttle(&0));
+        clock.advance(Duration::from_millis(500));
+        assert!(!limiter.should_throttle(&1));
+        assert_eq!(limiter.evict_idle(), 0);
+
+        // Key 0 leaves its one second window first
+        clock.advance(Duration::from_millis(600));
+        assert_eq!(limiter.evict_idle(), 1);
+        assert!(limiter.get(&0).is_none());
+
+        // New keys sweep idle ones once they double the keys left after the last sweep
+        clock.advance(Duration::from_secs(2));
+        for key in 2..MIN_SWEEP_KEYS + 2 {
+            limiter.should_throttle(&key);
+        }
+        assert_eq!(limiter.len(), MIN_SWEEP_KEYS);
+        assert!(limiter.get(&1).is_none());
+    }
+}
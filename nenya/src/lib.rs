@@ -38,37 +38,145 @@
 //!     }
 //! }
 //! ```
+//!
+//! ## Features
+//!
+//! The default features provide the adaptive rate limiter:
+//!
+//! - `pid`: [`RateLimiter`] with its PID controller, and the components built on it, such as
+//!   keyed limiters and the probing controller
+//! - `async`: the runtime-agnostic async APIs, such as distributed rate sharing
+//!
+//! Without them, the crate compiles to a core of [`bench`], [`clock`], [`codel`], [`key`],
+//! [`recording`], [`simulation`], and [`token_bucket`], with no dependencies beyond `std`.
+//! Integration features, such as `tower`, `tokio`, or `metrics`, enable the components they build
+//! on.
 
 #[cfg(doctest)]
 #[doc = include_str!("../../README.md")]
 struct _README;
 
+#[cfg(feature = "pid")]
 use num_traits::{Float, FromPrimitive, Signed};
-use std::collections::VecDeque;
-use std::time::{Duration, Instant};
-
-use crate::pid_controller::PIDController;
-
+#[cfg(feature = "pid")]
+use std::sync::Arc;
+#[cfg(feature = "pid")]
+use std::time::Duration;
+
+#[cfg(feature = "pid")]
+use crate::clock::{Clock, SystemClock, Timestamp};
+#[cfg(feature = "pid")]
+use crate::pid_controller::{
+    ControllerMode, CorrectionRecord, PIDController, PIDForm, SaturationState,
+};
+#[cfg(feature = "pid")]
+use crate::request_window::RequestWindow;
+
+#[cfg(feature = "accept")]
+pub mod accept;
+#[cfg(feature = "pid")]
+pub mod analysis;
+#[cfg(feature = "axum")]
+pub mod axum;
+pub mod bench;
+#[cfg(feature = "pid")]
+pub mod capacity;
+pub mod clock;
+pub mod codel;
+#[cfg(feature = "async")]
+pub mod distributed;
+#[cfg(feature = "pid")]
+pub mod experiment;
+#[cfg(feature = "pid")]
+mod feedback;
+#[cfg(feature = "pid")]
+pub mod golden;
+#[cfg(feature = "governor")]
+pub mod governor;
+#[cfg(feature = "metrics")]
+mod instrumentation;
+#[cfg(feature = "rdkafka")]
+pub mod kafka;
+pub mod key;
+#[cfg(feature = "pid")]
+pub mod keyed;
+#[cfg(feature = "opentelemetry")]
+pub mod opentelemetry;
+#[cfg(feature = "pid")]
+pub mod oscillation_detector;
+#[cfg(feature = "pacing")]
+pub mod pacing;
+#[cfg(feature = "pid")]
 pub mod pid_controller;
-
+#[cfg(feature = "pid")]
+pub mod probing;
+#[cfg(feature = "prometheus")]
+pub mod prometheus;
+#[cfg(feature = "pid")]
+pub mod queue;
+#[cfg(feature = "pid")]
+pub mod queueing;
+pub mod recording;
+#[cfg(feature = "pid")]
+mod request_window;
+#[cfg(feature = "async")]
+pub mod runtime;
+#[cfg(feature = "scenario")]
+pub mod scenario;
+#[cfg(feature = "scenario")]
+pub mod sensitivity;
+#[cfg(feature = "pacing")]
+pub mod serverless;
+#[cfg(feature = "pid")]
+pub mod shared;
+pub mod simulation;
+#[cfg(feature = "pid")]
+pub mod soak;
+#[cfg(feature = "statsd")]
+pub mod statsd;
+#[cfg(feature = "test-util")]
+pub mod test_util;
+pub mod token_bucket;
+#[cfg(feature = "tonic")]
+pub mod tonic;
+#[cfg(feature = "tower")]
+pub mod tower;
+#[cfg(feature = "pid")]
+pub mod tuning;
+
+#[cfg(feature = "pid")]
 /// Sliding window rate limiter with an integrated PID controller for dynamic target rate adjustment.
 #[derive(Debug)]
 pub struct RateLimiter<T> {
     request_rate: T,
     accepted_request_rate: T,
+    local_request_rate: T,
+    local_accepted_request_rate: T,
     target_rate: T,
+    fixed_target_rate: Option<T>,
     min_rate: T,
     max_rate: T,
     pid_controller: PIDController<T>,
-    last_updated: Instant,
+    last_updated: Timestamp,
     previous_output: T,
     update_interval: Duration,
-    request_timestamps: VecDeque<Instant>,
-    accepted_request_timestamps: VecDeque<Instant>,
+    request_timestamps: RequestWindow<T>,
+    accepted_request_timestamps: RequestWindow<T>,
+    window_capacity: usize,
     external_request_rate: T,
     external_accepted_request_rate: T,
+    clock: Arc<dyn Clock>,
+    #[cfg(feature = "metrics")]
+    metrics: instrumentation::LimiterMetrics,
+    #[cfg(feature = "opentelemetry")]
+    otel: Option<crate::opentelemetry::OtelMetrics>,
+    #[cfg(feature = "statsd")]
+    statsd: Option<statsd::StatsdSink>,
+    #[cfg(feature = "tracing")]
+    tracing_segment: Option<String>,
 }
 
+#[cfg(feature = "pid")]
 impl<T: Float + Signed + FromPrimitive + Copy> RateLimiter<T> {
     /// Creates a new `RateLimiter` instance.
     pub fn new(
@@ -78,20 +186,34 @@ impl<T: Float + Signed + FromPrimitive + Copy> RateLimiter<T> {
         pid_controller: PIDController<T>,
         update_interval: Duration,
     ) -> RateLimiter<T> {
+        let window_capacity = window_capacity(max_rate, update_interval);
         RateLimiter {
             request_rate: T::zero(),
             accepted_request_rate: T::zero(),
+            local_request_rate: T::zero(),
+            local_accepted_request_rate: T::zero(),
             target_rate,
+            fixed_target_rate: None,
             min_rate,
             max_rate,
             pid_controller,
-            last_updated: Instant::now(),
+            last_updated: SystemClock.now(),
             previous_output: T::zero(),
             update_interval,
-            request_timestamps: VecDeque::new(),
-            accepted_request_timestamps: VecDeque::new(),
+            request_timestamps: RequestWindow::with_capacity(window_capacity),
+            accepted_request_timestamps: RequestWindow::with_capacity(window_capacity),
+            window_capacity,
             external_request_rate: T::zero(),
             external_accepted_request_rate: T::zero(),
+            clock: Arc::new(SystemClock),
+            #[cfg(feature = "metrics")]
+            metrics: instrumentation::LimiterMetrics::new(None),
+            #[cfg(feature = "opentelemetry")]
+            otel: None,
+            #[cfg(feature = "statsd")]
+            statsd: None,
+            #[cfg(feature = "tracing")]
+            tracing_segment: None,
         }
     }
 
@@ -99,91 +221,137 @@ impl<T: Float + Signed + FromPrimitive + Copy> RateLimiter<T> {
     ///
     /// Returns `true` if the request should be throttled, `false` otherwise.
     pub fn should_throttle(&mut self) -> bool {
-        let now = Instant::now();
+        self.should_throttle_weighted(T::one())
+    }
+
+    /// Determines if a request that consumes `cost` units of the rate budget should be throttled.
+    ///
+    /// A request with a cost of 3 counts as 3 requests toward the request and accepted request
+    /// rates. The decision itself is made the same way as for [`RateLimiter::should_throttle`], so
+    /// an expensive request is admitted whenever the accepted rate is within the target rate, and
+    /// its cost is then paid off by throttling the requests that follow.
+    ///
+    /// A NaN, infinite, or negative cost is throttled without being counted toward either rate.
+    ///
+    /// Returns `true` if the request should be throttled, `false` otherwise.
+    pub fn should_throttle_weighted(&mut self, cost: impl Into<T>) -> bool {
+        self.should_throttle_weighted_with(cost, || true)
+    }
+
+    /// Determines if a request that consumes `cost` units of the rate budget should be throttled,
+    /// admitting it only if `admit` also allows it.
+    ///
+    /// `admit` is only called for a request within the target rate, e.g. to check a further limit
+    /// such as a caller's quota, and the request counts toward the accepted request rate only if
+    /// it returns `true`. The request counts toward the request rate either way.
+    ///
+    /// Returns `true` if the request should be throttled, `false` otherwise.
+    pub fn should_throttle_weighted_with(
+        &mut self,
+        cost: impl Into<T>,
+        admit: impl FnOnce() -> bool,
+    ) -> bool {
+        let cost = cost.into();
+        if !cost.is_finite() || cost < T::zero() {
+            return true;
+        }
+        let now = self.clock.now();
         self.trim_request_window(now);
         self.calculate_request_rate(now);
 
-        // Update PID controller and target rate periodically
-        if now.duration_since(self.last_updated) > self.update_interval {
+        // Update PID controller and target rate periodically, unless the target is fixed
+        if self.fixed_target_rate.is_none()
+            && now.duration_since(self.last_updated) > self.update_interval
+        {
             self.last_updated = now;
 
+            #[cfg(feature = "tracing")]
+            let span = tracing::debug_span!(
+                "pid_update",
+                segment = self.tracing_segment.as_deref(),
+                target_rate = self.target_rate.to_f64().unwrap_or_default(),
+            );
+            #[cfg(feature = "tracing")]
+            let _entered = span.enter();
+
             let output = self.pid_controller.compute_correction(self.request_rate);
             self.previous_output = output;
+            #[cfg(feature = "metrics")]
+            self.metrics
+                .record_pid_output(output.to_f64().unwrap_or_default());
+            #[cfg(feature = "opentelemetry")]
+            if let Some(otel) = &self.otel {
+                otel.record_pid_output(output.to_f64().unwrap_or_default());
+            }
+            #[cfg(feature = "statsd")]
+            if let Some(statsd) = &self.statsd {
+                statsd.record_pid_output(output.to_f64().unwrap_or_default());
+            }
 
             self.target_rate =
                 num_traits::clamp(self.target_rate + output, self.min_rate, self.max_rate);
         }
 
         // Make a throttling decision based on the target rate
-        let should_handle_request = self.accepted_request_rate <= self.target_rate;
+        let should_handle_request = self.accepted_request_rate <= self.target_rate() && admit();
         if should_handle_request {
-            self.accepted_request_timestamps.push_back(now);
+            self.accepted_request_timestamps.push(now, cost);
         }
-        self.request_timestamps.push_back(now);
+        self.request_timestamps.push(now, cost);
+        #[cfg(feature = "metrics")]
+        self.metrics.record_decision(
+            !should_handle_request,
+            self.target_rate().to_f64().unwrap_or_default(),
+        );
+        #[cfg(feature = "opentelemetry")]
+        if let Some(otel) = &self.otel {
+            otel.record_decision(
+                !should_handle_request,
+                self.target_rate().to_f64().unwrap_or_default(),
+            );
+        }
+        #[cfg(feature = "statsd")]
+        if let Some(statsd) = &self.statsd {
+            statsd.record_decision(
+                !should_handle_request,
+                self.target_rate().to_f64().unwrap_or_default(),
+            );
+        }
+        #[cfg(feature = "tracing")]
+        tracing::trace!(
+            segment = self.tracing_segment.as_deref(),
+            request_rate = self.request_rate.to_f64().unwrap_or_default(),
+            accepted_request_rate = self.accepted_request_rate.to_f64().unwrap_or_default(),
+            target_rate = self.target_rate().to_f64().unwrap_or_default(),
+            pid_output = self.previous_output.to_f64().unwrap_or_default(),
+            cost = cost.to_f64().unwrap_or_default(),
+            throttled = !should_handle_request,
+            "rate limiter decision"
+        );
 
         !should_handle_request
     }
 
     /// Calculates the current request rate based on the timestamps of recent requests.
-    fn calculate_request_rate(&mut self, now: Instant) {
-        let min_duration = 0.1; // Minimum duration threshold in seconds
-
-        if let Some(&oldest) = self.accepted_request_timestamps.front() {
-            let window_duration = now.duration_since(oldest).as_secs_f32();
-            let effective_duration = if window_duration < min_duration {
-                min_duration
-            } else {
-                window_duration
-            };
-
-            self.accepted_request_rate = if T::from_f32(effective_duration).unwrap() > T::zero() {
-                T::from_usize(self.accepted_request_timestamps.len()).unwrap()
-                    / T::from_f32(effective_duration).unwrap()
-            } else {
-                T::zero()
-            };
-        } else {
-            self.accepted_request_rate = T::zero();
-        }
+    fn calculate_request_rate(&mut self, now: Timestamp) {
+        self.local_accepted_request_rate = self.accepted_request_timestamps.rate(now);
         self.accepted_request_rate =
-            self.accepted_request_rate + self.external_accepted_request_rate;
-
-        if let Some(&oldest) = self.request_timestamps.front() {
-            let window_duration = now.duration_since(oldest).as_secs_f32();
-            let effective_duration = if window_duration < min_duration {
-                min_duration
-            } else {
-                window_duration
-            };
+            self.local_accepted_request_rate + self.external_accepted_request_rate;
 
-            self.request_rate = if T::from_f32(effective_duration).unwrap() > T::zero() {
-                T::from_usize(self.request_timestamps.len()).unwrap()
-                    / T::from_f32(effective_duration).unwrap()
-            } else {
-                T::zero()
-            };
-        } else {
-            self.request_rate = T::zero();
-        }
-        self.request_rate = self.request_rate + self.external_request_rate;
+        self.local_request_rate = self.request_timestamps.rate(now);
+        self.request_rate = self.local_request_rate + self.external_request_rate;
     }
 
     /// Trims old request timestamps that are outside the update interval.
-    fn trim_request_window(&mut self, now: Instant) {
-        while let Some(timestamp) = self.accepted_request_timestamps.front() {
-            if now.duration_since(*timestamp) > self.update_interval {
-                self.accepted_request_timestamps.pop_front();
-            } else {
-                break;
-            }
-        }
-        while let Some(timestamp) = self.request_timestamps.front() {
-            if now.duration_since(*timestamp) > self.update_interval {
-                self.request_timestamps.pop_front();
-            } else {
-                break;
-            }
-        }
+    fn trim_request_window(&mut self, now: Timestamp) {
+        self.accepted_request_timestamps
+            .trim(now, self.update_interval);
+        self.request_timestamps.trim(now, self.update_interval);
+    }
+
+    /// Returns the clock the rate limiter reads the time from.
+    pub fn clock(&self) -> &Arc<dyn Clock> {
+        &self.clock
     }
 
     /// Returns the current setpoint of the PID controller.
@@ -191,9 +359,165 @@ impl<T: Float + Signed + FromPrimitive + Copy> RateLimiter<T> {
         self.pid_controller.setpoint()
     }
 
+    /// Sets the PID controller's setpoint without changing the current target rate, so the
+    /// controller moves the target rate towards it gradually.
+    ///
+    /// A NaN or infinite setpoint is ignored, and a negative one is treated as zero.
+    pub fn set_setpoint(&mut self, setpoint: impl Into<T>) {
+        let setpoint = sanitize_rate(self.setpoint(), setpoint.into());
+        self.pid_controller.set_setpoint(setpoint);
+    }
+
+    /// Returns the PID controller adjusting the target rate, e.g. to inspect its internal state.
+    pub fn pid_controller(&self) -> &PIDController<T> {
+        &self.pid_controller
+    }
+
+    /// Returns the PID controller adjusting the target rate, e.g. to change its gains or limits
+    /// while the limiter runs.
+    pub fn pid_controller_mut(&mut self) -> &mut PIDController<T> {
+        &mut self.pid_controller
+    }
+
+    /// Returns the saturation state of the PID controller as of its last update.
+    pub fn saturation_state(&self) -> SaturationState {
+        self.pid_controller.saturation_state()
+    }
+
     /// Returns the current target rate of the rate limiter.
+    ///
+    /// This is the fixed target rate while one is set.
     pub fn target_rate(&self) -> T {
-        self.target_rate
+        self.fixed_target_rate.unwrap_or(self.target_rate)
+    }
+
+    /// Returns the fixed target rate, if one is set.
+    pub fn fixed_target_rate(&self) -> Option<T> {
+        self.fixed_target_rate
+    }
+
+    /// Fixes the target rate at `fixed_target_rate`, suspending the PID controller, or resumes
+    /// PID control of the target rate when `None`.
+    ///
+    /// This lets an external coordinator assign the target rate, e.g. a node's share of a target
+    /// rate enforced across several limiters. The PID-controlled target rate is kept unchanged
+    /// while the fixed target rate is set.
+    ///
+    /// A NaN or infinite rate is ignored, and a negative one is treated as zero.
+    pub fn set_fixed_target_rate(&mut self, fixed_target_rate: Option<T>) {
+        self.fixed_target_rate = match fixed_target_rate {
+            Some(rate) if !rate.is_finite() => self.fixed_target_rate,
+            rate => rate.map(|rate| rate.max(T::zero())),
+        };
+    }
+
+    /// Returns how much more accepted traffic fits under the target rate, in requests per second.
+    pub fn remaining_capacity(&self) -> T {
+        (self.target_rate() - self.accepted_request_rate).max(T::zero())
+    }
+
+    /// Returns how long a throttled caller should wait before retrying.
+    ///
+    /// This is the time until the oldest accepted request leaves the sliding window, or zero if
+    /// the accepted rate is within the target rate.
+    pub fn retry_after(&self) -> Duration {
+        if self.accepted_request_rate <= self.target_rate() {
+            return Duration::ZERO;
+        }
+        match self.accepted_request_timestamps.oldest() {
+            Some(oldest) => (oldest + self.update_interval).duration_since(self.clock.now()),
+            None => Duration::ZERO,
+        }
+    }
+
+    /// Sets the target rate and the PID controller's setpoint.
+    ///
+    /// The minimum and maximum rates are widened if needed to include the new target. A NaN or
+    /// infinite target rate is ignored, and a negative one is treated as zero.
+    pub fn set_target_rate(&mut self, target_rate: impl Into<T>) {
+        let target_rate = target_rate.into();
+        if !target_rate.is_finite() {
+            return;
+        }
+        let target_rate = target_rate.max(T::zero());
+        self.target_rate = target_rate;
+        self.min_rate = self.min_rate.min(target_rate);
+        self.max_rate = self.max_rate.max(target_rate);
+        self.pid_controller.set_setpoint(target_rate);
+        self.resize_windows();
+    }
+
+    /// Returns the minimum allowable rate of requests.
+    pub fn min_rate(&self) -> T {
+        self.min_rate
+    }
+
+    /// Returns the maximum allowable rate of requests.
+    pub fn max_rate(&self) -> T {
+        self.max_rate
+    }
+
+    /// Returns how often the PID controller updates the target rate, which is also the length
+    /// of the sliding window the rates are measured over.
+    pub fn update_interval(&self) -> Duration {
+        self.update_interval
+    }
+
+    /// Sets the minimum and maximum allowable rates, clamping the current target rate into the
+    /// new range.
+    ///
+    /// A NaN or infinite bound is ignored and a negative one is treated as zero. A maximum below
+    /// the minimum is raised to the minimum.
+    pub fn set_rate_bounds(&mut self, min_rate: impl Into<T>, max_rate: impl Into<T>) {
+        self.min_rate = sanitize_rate(self.min_rate, min_rate.into());
+        self.max_rate = sanitize_rate(self.max_rate, max_rate.into()).max(self.min_rate);
+        self.target_rate = num_traits::clamp(self.target_rate, self.min_rate, self.max_rate);
+        self.resize_windows();
+    }
+
+    /// Returns the number of requests the sliding windows are sized for, the maximum rate over
+    /// the update interval, up to 4096, or the number of buckets of bucketed windows.
+    ///
+    /// At rates up to the maximum rate, the windows never allocate. Beyond 4096 requests per
+    /// update interval they grow with the traffic instead; use bucketed windows for such rates.
+    /// The window of offered requests also grows when more than the maximum rate is offered,
+    /// unless it is bucketed.
+    pub fn window_capacity(&self) -> usize {
+        self.window_capacity
+    }
+
+    /// Resizes exact sliding windows for the maximum rate, after it was changed.
+    fn resize_windows(&mut self) {
+        if self.request_timestamps.bucket_count().is_some() {
+            return;
+        }
+        self.window_capacity = window_capacity(self.max_rate, self.update_interval);
+        self.request_timestamps.set_capacity(self.window_capacity);
+        self.accepted_request_timestamps
+            .set_capacity(self.window_capacity);
+    }
+
+    /// Discards the observed requests and the PID controller's accumulated state.
+    ///
+    /// The target rate, bounds, and external rates are kept.
+    pub fn reset(&mut self) {
+        self.request_timestamps.clear();
+        self.accepted_request_timestamps.clear();
+        self.request_rate = T::zero();
+        self.accepted_request_rate = T::zero();
+        self.local_request_rate = T::zero();
+        self.local_accepted_request_rate = T::zero();
+        self.previous_output = T::zero();
+        self.last_updated = self.clock.now();
+        self.pid_controller.reset();
+    }
+
+    /// Returns `true` if no request arrived within the last update interval, so the sliding
+    /// windows hold nothing the next decision would count.
+    pub fn is_idle(&self) -> bool {
+        self.request_timestamps
+            .newest()
+            .is_none_or(|newest| self.clock.now().duration_since(newest) > self.update_interval)
     }
 
     /// Returns the current request rate.
@@ -206,14 +530,28 @@ impl<T: Float + Signed + FromPrimitive + Copy> RateLimiter<T> {
         self.accepted_request_rate
     }
 
+    /// Returns the current request rate observed by this limiter, excluding the external rate.
+    pub fn local_request_rate(&self) -> T {
+        self.local_request_rate
+    }
+
+    /// Returns the current accepted request rate observed by this limiter, excluding the external
+    /// accepted rate.
+    pub fn local_accepted_request_rate(&self) -> T {
+        self.local_accepted_request_rate
+    }
+
     /// Returns the current external request rate.
     pub fn external_request_rate(&self) -> T {
         self.external_request_rate
     }
 
     /// Sets the external request rate.
+    ///
+    /// A NaN rate is ignored, and a negative one is treated as zero.
     pub fn set_external_request_rate(&mut self, external_request_rate: impl Into<T>) {
-        self.external_request_rate = external_request_rate.into()
+        self.external_request_rate =
+            sanitize_external_rate(self.external_request_rate, external_request_rate.into())
     }
 
     /// Returns the current external accepted request rate.
@@ -222,14 +560,144 @@ impl<T: Float + Signed + FromPrimitive + Copy> RateLimiter<T> {
     }
 
     /// Sets the external accepted request rate.
+    ///
+    /// A NaN rate is ignored, and a negative one is treated as zero.
     pub fn set_external_accepted_request_rate(
         &mut self,
         external_accepted_request_rate: impl Into<T>,
     ) {
-        self.external_accepted_request_rate = external_accepted_request_rate.into()
+        self.external_accepted_request_rate = sanitize_external_rate(
+            self.external_accepted_request_rate,
+            external_accepted_request_rate.into(),
+        )
+    }
+
+    /// Returns a snapshot of the rate limiter's internal state, for debugging.
+    ///
+    /// With the `serde` feature the snapshot is serializable, e.g. to be dumped as JSON by an
+    /// admin endpoint during an incident.
+    pub fn debug_state(&self) -> DebugState<T> {
+        DebugState {
+            target_rate: self.target_rate(),
+            fixed_target_rate: self.fixed_target_rate,
+            min_rate: self.min_rate,
+            max_rate: self.max_rate,
+            setpoint: self.setpoint(),
+            request_rate: self.request_rate,
+            accepted_request_rate: self.accepted_request_rate,
+            local_request_rate: self.local_request_rate,
+            local_accepted_request_rate: self.local_accepted_request_rate,
+            external_request_rate: self.external_request_rate,
+            external_accepted_request_rate: self.external_accepted_request_rate,
+            request_window_len: self.request_timestamps.len(),
+            accepted_request_window_len: self.accepted_request_timestamps.len(),
+            window_capacity: self.window_capacity,
+            update_interval: self.update_interval,
+            since_last_update: self.clock.now().duration_since(self.last_updated),
+            pid_output: self.previous_output,
+            pid_accumulated_error: self.pid_controller.accumulated_error(),
+            pid_mode: self.pid_controller.mode(),
+            pid_form: self.pid_controller.form(),
+            saturation_state: self.pid_controller.saturation_state(),
+            last_correction: self.pid_controller.last_correction().copied(),
+        }
+    }
+}
+
+#[cfg(feature = "pid")]
+/// Snapshot of a [`RateLimiter`]'s internal state, returned by [`RateLimiter::debug_state`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[non_exhaustive]
+pub struct DebugState<T> {
+    /// The target rate, or the fixed target rate if one is set.
+    pub target_rate: T,
+    /// The fixed target rate suspending the PID controller, if one is set.
+    pub fixed_target_rate: Option<T>,
+    /// The lower bound of the target rate.
+    pub min_rate: T,
+    /// The upper bound of the target rate.
+    pub max_rate: T,
+    /// The setpoint of the PID controller.
+    pub setpoint: T,
+    /// The request rate, including the external request rate.
+    pub request_rate: T,
+    /// The accepted request rate, including the external accepted request rate.
+    pub accepted_request_rate: T,
+    /// The request rate observed by this limiter.
+    pub local_request_rate: T,
+    /// The accepted request rate observed by this limiter.
+    pub local_accepted_request_rate: T,
+    /// The request rate reported by other limiters.
+    pub external_request_rate: T,
+    /// The accepted request rate reported by other limiters.
+    pub external_accepted_request_rate: T,
+    /// The number of entries in the sliding window of requests.
+    pub request_window_len: usize,
+    /// The number of entries in the sliding window of accepted requests.
+    pub accepted_request_window_len: usize,
+    /// The number of requests the sliding windows are sized for, or their number of buckets.
+    pub window_capacity: usize,
+    /// The interval between updates of the target rate.
+    pub update_interval: Duration,
+    /// The time since the target rate was last updated.
+    pub since_last_update: Duration,
+    /// The last correction the PID controller applied to the target rate.
+    pub pid_output: T,
+    /// The accumulated error of the PID controller.
+    pub pid_accumulated_error: T,
+    /// The operating mode of the PID controller.
+    pub pid_mode: ControllerMode,
+    /// The algorithm form of the PID controller.
+    pub pid_form: PIDForm,
+    /// Whether the PID controller's last correction was clamped.
+    pub saturation_state: SaturationState,
+    /// The terms of the PID controller's last correction, if it computed one.
+    pub last_correction: Option<CorrectionRecord<T>>,
+}
+
+/// Returns `rate`, or `previous` if `rate` is NaN or infinite, with negative rates treated as
+/// zero.
+#[cfg(feature = "pid")]
+fn sanitize_rate<T: Float>(previous: T, rate: T) -> T {
+    if rate.is_finite() {
+        rate.max(T::zero())
+    } else {
+        previous
+    }
+}
+
+/// Returns `rate`, or `previous` if `rate` is NaN, with negative rates treated as zero.
+///
+/// Unlike a target rate, an external rate may be infinite, which saturates the limiter.
+#[cfg(feature = "pid")]
+fn sanitize_external_rate<T: Float>(previous: T, rate: T) -> T {
+    if rate.is_nan() {
+        previous
+    } else {
+        rate.max(T::zero())
     }
 }
 
+/// Most requests a sliding window is sized for up front, so that a high maximum rate does not
+/// reserve memory for traffic that may never arrive.
+#[cfg(feature = "pid")]
+const MAX_WINDOW_CAPACITY: usize = 1 << 12;
+
+/// Returns the number of requests a sliding window holds at `max_rate` over `update_interval`,
+/// including the request at each end of the window.
+#[cfg(feature = "pid")]
+fn window_capacity<T: Float>(max_rate: T, update_interval: Duration) -> usize {
+    let requests = max_rate.to_f64().unwrap_or(0.0) * update_interval.as_secs_f64();
+    if requests.is_nan() {
+        return 0;
+    }
+    (requests.ceil() as usize)
+        .saturating_add(1)
+        .min(MAX_WINDOW_CAPACITY)
+}
+
+#[cfg(feature = "pid")]
 /// Builder for creating a `RateLimiter` instance.
 pub struct RateLimiterBuilder<T> {
     target_rate: T,
@@ -237,10 +705,21 @@ pub struct RateLimiterBuilder<T> {
     max_rate: T,
     pid_controller: Option<PIDController<T>>,
     update_interval: Duration,
+    window_quantum: Option<Duration>,
     external_request_rate: T,
     external_accepted_request_rate: T,
+    clock: Option<Arc<dyn Clock>>,
+    #[cfg(feature = "metrics")]
+    metrics_name: Option<String>,
+    #[cfg(feature = "opentelemetry")]
+    otel: Option<crate::opentelemetry::OtelMetrics>,
+    #[cfg(feature = "statsd")]
+    statsd: Option<statsd::StatsdSink>,
+    #[cfg(feature = "tracing")]
+    tracing_segment: Option<String>,
 }
 
+#[cfg(feature = "pid")]
 impl<T: Float + Signed + FromPrimitive + Copy> RateLimiterBuilder<T> {
     /// Creates a new `RateLimiterBuilder` with default values.
     pub fn new(target_rate: T) -> Self {
@@ -250,8 +729,18 @@ impl<T: Float + Signed + FromPrimitive + Copy> RateLimiterBuilder<T> {
             max_rate: target_rate,
             pid_controller: None,
             update_interval: Duration::from_secs(1),
+            window_quantum: None,
             external_request_rate: T::zero(),
             external_accepted_request_rate: T::zero(),
+            clock: None,
+            #[cfg(feature = "metrics")]
+            metrics_name: None,
+            #[cfg(feature = "opentelemetry")]
+            otel: None,
+            #[cfg(feature = "statsd")]
+            statsd: None,
+            #[cfg(feature = "tracing")]
+            tracing_segment: None,
         }
     }
 
@@ -279,6 +768,19 @@ impl<T: Float + Signed + FromPrimitive + Copy> RateLimiterBuilder<T> {
         self
     }
 
+    /// Counts requests in a fixed ring of counters per `quantum` of time, e.g. 100 ms, instead of
+    /// keeping every request of the sliding window.
+    ///
+    /// Each decision then takes constant time and allocates nothing however many requests the
+    /// window holds, which suits high rates. In exchange, requests leave the window a quantum at
+    /// a time, checked once per quantum rather than on every decision, so the rates are measured
+    /// over the update interval to within a quantum. A zero
+    /// quantum keeps every request, the default.
+    pub fn window_quantum(mut self, quantum: Duration) -> Self {
+        self.window_quantum = Some(quantum).filter(|quantum| !quantum.is_zero());
+        self
+    }
+
     /// Sets the external request rate.
     pub fn external_request_rate(mut self, external_request_rate: T) -> Self {
         self.external_request_rate = external_request_rate;
@@ -291,35 +793,92 @@ impl<T: Float + Signed + FromPrimitive + Copy> RateLimiterBuilder<T> {
         self
     }
 
+    /// Sets the clock the rate limiter reads the time from. Defaults to [`SystemClock`].
+    pub fn clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = Some(clock);
+        self
+    }
+
+    /// Labels the metrics the rate limiter reports with `limiter = name`, to tell limiters apart.
+    #[cfg(feature = "metrics")]
+    pub fn metrics_name(mut self, name: impl Into<String>) -> Self {
+        self.metrics_name = Some(name.into());
+        self
+    }
+
+    /// Records the rate limiter's decisions with the OpenTelemetry instruments of `metrics`.
+    #[cfg(feature = "opentelemetry")]
+    pub fn opentelemetry(mut self, metrics: crate::opentelemetry::OtelMetrics) -> Self {
+        self.otel = Some(metrics);
+        self
+    }
+
+    /// Reports the rate limiter's decisions to a StatsD agent through `sink`.
+    #[cfg(feature = "statsd")]
+    pub fn statsd(mut self, sink: statsd::StatsdSink) -> Self {
+        self.statsd = Some(sink);
+        self
+    }
+
+    /// Records `segment` with the rate limiter's tracing events, to tell limiters apart.
+    #[cfg(feature = "tracing")]
+    pub fn tracing_segment(mut self, segment: impl Into<String>) -> Self {
+        self.tracing_segment = Some(segment.into());
+        self
+    }
+
     /// Builds and returns the `RateLimiter` instance.
     pub fn build(self) -> RateLimiter<T> {
+        let clock = self.clock.unwrap_or_else(|| Arc::new(SystemClock));
+        let mut pid_controller = self
+            .pid_controller
+            .unwrap_or_else(|| PIDController::new_static_controller(self.target_rate));
+        pid_controller.set_clock(clock.clone());
+        let exact_capacity = window_capacity(self.max_rate, self.update_interval);
+        let window = || match self.window_quantum {
+            Some(quantum) => RequestWindow::bucketed(quantum, self.update_interval),
+            None => RequestWindow::with_capacity(exact_capacity),
+        };
+        let (request_timestamps, accepted_request_timestamps) = (window(), window());
+        let window_capacity = request_timestamps.bucket_count().unwrap_or(exact_capacity);
         RateLimiter {
             request_rate: T::zero(),
             accepted_request_rate: T::zero(),
+            local_request_rate: T::zero(),
+            local_accepted_request_rate: T::zero(),
             target_rate: self.target_rate,
+            fixed_target_rate: None,
             min_rate: self.min_rate,
             max_rate: self.max_rate,
-            pid_controller: self
-                .pid_controller
-                .unwrap_or_else(|| PIDController::new_static_controller(self.target_rate)),
-            last_updated: Instant::now(),
+            pid_controller,
+            last_updated: clock.now(),
             previous_output: T::zero(),
             update_interval: self.update_interval,
-            request_timestamps: VecDeque::new(),
-            accepted_request_timestamps: VecDeque::new(),
+            request_timestamps,
+            accepted_request_timestamps,
+            window_capacity,
             external_request_rate: self.external_request_rate,
             external_accepted_request_rate: self.external_accepted_request_rate,
+            clock,
+            #[cfg(feature = "metrics")]
+            metrics: instrumentation::LimiterMetrics::new(self.metrics_name.as_deref()),
+            #[cfg(feature = "opentelemetry")]
+            otel: self.otel,
+            #[cfg(feature = "statsd")]
+            statsd: self.statsd,
+            #[cfg(feature = "tracing")]
+            tracing_segment: self.tracing_segment,
         }
     }
 }
 
-#[cfg(test)]
+#[cfg(all(test, feature = "pid"))]
 mod tests {
     use super::*;
     use crate::pid_controller::PIDControllerBuilder;
     use num_traits::FromPrimitive;
     use std::thread::sleep;
-    use std::time::{Duration, Instant};
+    use std::time::Duration;
 
     /// Utility function to create a RateLimiter with defaults
     fn create_rate_limiter<T: Float + Signed + FromPrimitive + Copy>(
@@ -374,7 +933,14 @@ mod tests {
         assert_eq!(rate_limiter.max_rate, 15.0);
         assert_eq!(rate_limiter.request_rate(), 0.0);
         assert_eq!(rate_limiter.accepted_request_rate(), 0.0);
-        assert!(rate_limiter.last_updated.elapsed().as_secs() <= 1);
+        assert!(
+            rate_limiter
+                .clock
+                .now()
+                .duration_since(rate_limiter.last_updated)
+                .as_secs()
+                <= 1
+        );
         assert_eq!(rate_limiter.previous_output, 0.0);
         assert_eq!(rate_limiter.request_timestamps.len(), 0);
         assert_eq!(rate_limiter.accepted_request_timestamps.len(), 0);
@@ -474,13 +1040,13 @@ mod tests {
         let pid = create_pid_controller(1.0, 0.1, 0.01, 0.001, 0.0, None, None);
         let mut rate_limiter = create_rate_limiter(10.0, 5.0, 15.0, pid, Duration::from_secs(1));
 
-        let now = Instant::now();
+        let now = Timestamp::from_duration(Duration::from_secs(10));
         rate_limiter
             .request_timestamps
-            .push_back(now - Duration::from_secs(2));
+            .push(now - Duration::from_secs(2), 1.0);
         rate_limiter
             .request_timestamps
-            .push_back(now - Duration::from_secs(1));
+            .push(now - Duration::from_secs(1), 1.0);
 
         rate_limiter.trim_request_window(now);
 
@@ -492,13 +1058,13 @@ mod tests {
         let pid = create_pid_controller(1.0, 0.1, 0.01, 0.001, 0.0, None, None);
         let mut rate_limiter = create_rate_limiter(10.0, 5.0, 15.0, pid, Duration::from_secs(1));
 
-        let now = Instant::now();
+        let now = Timestamp::from_duration(Duration::from_secs(10));
         rate_limiter
             .request_timestamps
-            .push_back(now - Duration::from_secs(2));
+            .push(now - Duration::from_secs(2), 1.0);
         rate_limiter
             .request_timestamps
-            .push_back(now - Duration::from_secs(1));
+            .push(now - Duration::from_secs(1), 1.0);
 
         rate_limiter.calculate_request_rate(now);
 
@@ -524,13 +1090,13 @@ mod tests {
 
         rate_limiter.set_external_request_rate(2.0);
 
-        let now = Instant::now();
+        let now = Timestamp::from_duration(Duration::from_secs(10));
         rate_limiter
             .request_timestamps
-            .push_back(now - Duration::from_secs(2));
+            .push(now - Duration::from_secs(2), 1.0);
         rate_limiter
             .request_timestamps
-            .push_back(now - Duration::from_secs(1));
+            .push(now - Duration::from_secs(1), 1.0);
 
         rate_limiter.calculate_request_rate(now);
 
@@ -544,16 +1110,397 @@ mod tests {
 
         rate_limiter.set_external_accepted_request_rate(2.0);
 
-        let now = Instant::now();
+        let now = Timestamp::from_duration(Duration::from_secs(10));
         rate_limiter
             .accepted_request_timestamps
-            .push_back(now - Duration::from_secs(2));
+            .push(now - Duration::from_secs(2), 1.0);
         rate_limiter
             .accepted_request_timestamps
-            .push_back(now - Duration::from_secs(1));
+            .push(now - Duration::from_secs(1), 1.0);
 
         rate_limiter.calculate_request_rate(now);
 
         assert_eq!(rate_limiter.accepted_request_rate(), 2.0 + (2.0 / 2.0));
     }
+
+    #[test]
+    fn test_local_rates_exclude_external_rates() {
+        let pid = create_pid_controller(1.0, 0.1, 0.01, 0.001, 0.0, None, None);
+        let mut rate_limiter = create_rate_limiter(10.0, 5.0, 15.0, pid, Duration::from_secs(1));
+
+        rate_limiter.set_external_request_rate(2.0);
+        rate_limiter.set_external_accepted_request_rate(2.0);
+
+        let now = Timestamp::from_duration(Duration::from_secs(10));
+        for timestamps in [
+            &mut rate_limiter.request_timestamps,
+            &mut rate_limiter.accepted_request_timestamps,
+        ] {
+            timestamps.push(now - Duration::from_secs(2), 1.0);
+            timestamps.push(now - Duration::from_secs(1), 1.0);
+        }
+
+        rate_limiter.calculate_request_rate(now);
+
+        assert_eq!(rate_limiter.local_request_rate(), 2.0 / 2.0);
+        assert_eq!(rate_limiter.local_accepted_request_rate(), 2.0 / 2.0);
+    }
+
+    #[test]
+    fn test_is_idle() {
+        let clock = clock::ManualClock::new();
+        let mut rate_limiter = RateLimiterBuilder::new(10.0)
+            .update_interval(Duration::from_secs(1))
+            .clock(Arc::new(clock.clone()))
+            .build();
+        assert!(rate_limiter.is_idle());
+
+        rate_limiter.should_throttle();
+        assert!(!rate_limiter.is_idle());
+
+        clock.advance(Duration::from_secs(2));
+        assert!(rate_limiter.is_idle());
+    }
+
+    #[test]
+    fn test_reconfigure_rates() {
+        let pid = create_pid_controller(1.0, 0.1, 0.01, 0.001, 0.0, None, None);
+        let mut rate_limiter = create_rate_limiter(10.0, 5.0, 15.0, pid, Duration::from_secs(1));
+
+        rate_limiter.set_rate_bounds(12.0, 20.0);
+        assert_eq!(rate_limiter.target_rate(), 12.0);
+
+        rate_limiter.set_target_rate(25.0);
+        assert_eq!(rate_limiter.target_rate(), 25.0);
+        assert_eq!(rate_limiter.max_rate(), 25.0);
+        assert_eq!(rate_limiter.min_rate(), 12.0);
+        assert_eq!(rate_limiter.setpoint(), 25.0);
+    }
+
+    #[test]
+    fn test_reset() {
+        let pid = create_pid_controller(1.0, 0.1, 0.01, 0.001, 0.0, None, None);
+        let mut rate_limiter = create_rate_limiter(10.0, 5.0, 15.0, pid, Duration::from_secs(1));
+        rate_limiter.set_external_request_rate(2.0);
+        for _ in 0..5 {
+            rate_limiter.should_throttle();
+        }
+
+        rate_limiter.reset();
+
+        assert!(rate_limiter.request_timestamps.is_empty());
+        assert_eq!(rate_limiter.local_request_rate(), 0.0);
+        assert_eq!(rate_limiter.external_request_rate(), 2.0);
+        assert_eq!(rate_limiter.target_rate(), 10.0);
+    }
+
+    #[test]
+    fn test_retry_after_and_remaining_capacity() {
+        let mut rate_limiter = RateLimiterBuilder::new(1.0)
+            .update_interval(Duration::from_secs(10))
+            .build();
+        assert_eq!(rate_limiter.retry_after(), Duration::ZERO);
+        assert_eq!(rate_limiter.remaining_capacity(), 1.0);
+
+        while !rate_limiter.should_throttle() {}
+
+        assert_eq!(rate_limiter.remaining_capacity(), 0.0);
+        let retry_after = rate_limiter.retry_after();
+        assert!(retry_after > Duration::ZERO && retry_after <= Duration::from_secs(10));
+    }
+
+    #[test]
+    fn test_weighted_requests_consume_budget() {
+        let mut rate_limiter = RateLimiterBuilder::new(10.0).build();
+
+        assert!(!rate_limiter.should_throttle_weighted(5.0));
+        assert!(rate_limiter.should_throttle());
+        // The rates are measured over the 0.1 second minimum window
+        assert!((rate_limiter.local_request_rate() - 50.0).abs() < 1e-3);
+        assert!((rate_limiter.local_accepted_request_rate() - 50.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn test_vetoed_requests_are_not_accepted() {
+        let mut rate_limiter = RateLimiterBuilder::new(10.0).build();
+
+        assert!(rate_limiter.should_throttle_weighted_with(1.0, || false));
+        assert!(!rate_limiter.should_throttle_weighted_with(1.0, || true));
+        assert!(!rate_limiter.should_throttle());
+        // The rates are measured over the 0.1 second minimum window, before the last request
+        assert!((rate_limiter.local_request_rate() - 20.0).abs() < 1e-3);
+        assert!((rate_limiter.local_accepted_request_rate() - 10.0).abs() < 1e-3);
+
+        // Over the target rate, the request is throttled without asking
+        assert!(rate_limiter.should_throttle_weighted_with(1.0, || unreachable!()));
+    }
+
+    #[test]
+    fn test_bucketed_window_tracks_exact_window() {
+        let clock = clock::ManualClock::new();
+        let build = |quantum| {
+            RateLimiterBuilder::new(100.0)
+                .window_quantum(quantum)
+                .clock(Arc::new(clock.clone()))
+                .build()
+        };
+        let mut exact = build(Duration::ZERO);
+        let mut bucketed = build(Duration::from_millis(100));
+
+        // 200 requests per second for 10 seconds
+        let (mut exact_accepted, mut bucketed_accepted) = (0, 0);
+        for _ in 0..2000 {
+            clock.advance(Duration::from_millis(5));
+            exact_accepted += u32::from(!exact.should_throttle());
+            bucketed_accepted += u32::from(!bucketed.should_throttle());
+        }
+        assert!(exact_accepted.abs_diff(bucketed_accepted) <= 20);
+        assert!((bucketed.local_request_rate() - 200.0).abs() < 200.0 * 0.1);
+        assert!(bucketed.request_timestamps.len() <= exact.request_timestamps.len());
+
+        clock.advance(Duration::from_secs(2));
+        assert!(bucketed.is_idle());
+        bucketed.reset();
+        assert!(bucketed.request_timestamps.is_empty());
+    }
+
+    #[test]
+    fn test_windows_are_sized_for_max_rate() {
+        let clock = clock::ManualClock::new();
+        let mut rate_limiter = RateLimiterBuilder::new(100.0)
+            .max_rate(200.0)
+            .clock(Arc::new(clock.clone()))
+            .build();
+        assert_eq!(rate_limiter.window_capacity(), 201);
+        assert_eq!(rate_limiter.debug_state().window_capacity, 201);
+        let capacity = rate_limiter.request_timestamps.capacity();
+        assert!(capacity >= 201);
+
+        // Below the maximum rate, the windows never grow
+        for _ in 0..1000 {
+            clock.advance(Duration::from_millis(5));
+            rate_limiter.should_throttle();
+        }
+        assert_eq!(rate_limiter.request_timestamps.capacity(), capacity);
+
+        rate_limiter.set_rate_bounds(100.0, 1000.0);
+        assert_eq!(rate_limiter.window_capacity(), 1001);
+        assert!(rate_limiter.request_timestamps.capacity() >= 1001);
+        assert!(rate_limiter.accepted_request_timestamps.capacity() >= 1001);
+
+        // Lowering the maximum rate releases the room it no longer needs
+        rate_limiter.set_rate_bounds(100.0, 200.0);
+        assert_eq!(rate_limiter.window_capacity(), 201);
+        assert!(rate_limiter.request_timestamps.capacity() < 1001);
+        assert!(rate_limiter.accepted_request_timestamps.capacity() < 1001);
+
+        rate_limiter.set_rate_bounds(100.0, 1_000_000.0);
+        assert_eq!(rate_limiter.window_capacity(), MAX_WINDOW_CAPACITY);
+        assert!(rate_limiter.request_timestamps.capacity() < 2 * MAX_WINDOW_CAPACITY);
+
+        // Bucketed windows report their buckets, whatever the maximum rate
+        let mut bucketed = RateLimiterBuilder::new(100.0)
+            .max_rate(200.0)
+            .window_quantum(Duration::from_millis(100))
+            .build();
+        assert_eq!(bucketed.window_capacity(), 11);
+        bucketed.set_rate_bounds(100.0, 1000.0);
+        assert_eq!(bucketed.debug_state().window_capacity, 11);
+        assert_eq!(bucketed.request_timestamps.capacity(), 11);
+    }
+
+    #[test]
+    fn test_rejects_non_finite_and_negative_inputs() {
+        let pid = create_pid_controller(10.0, 0.1, 0.01, 0.0, 0.0, None, None);
+        let mut rate_limiter = create_rate_limiter(10.0, 5.0, 15.0, pid, Duration::ZERO);
+
+        for cost in [f64::NAN, f64::INFINITY, f64::NEG_INFINITY, -1.0] {
+            assert!(rate_limiter.should_throttle_weighted(cost));
+        }
+        assert_eq!(rate_limiter.local_request_rate(), 0.0);
+
+        rate_limiter.set_external_request_rate(f64::NAN);
+        rate_limiter.set_external_accepted_request_rate(-5.0);
+        assert_eq!(rate_limiter.external_request_rate(), 0.0);
+        assert_eq!(rate_limiter.external_accepted_request_rate(), 0.0);
+
+        rate_limiter.set_target_rate(f64::NAN);
+        assert_eq!(rate_limiter.target_rate(), 10.0);
+        rate_limiter.set_target_rate(f64::INFINITY);
+        assert_eq!(rate_limiter.target_rate(), 10.0);
+        assert_eq!(rate_limiter.max_rate(), 15.0);
+        rate_limiter.set_setpoint(f64::INFINITY);
+        rate_limiter.set_setpoint(f64::NEG_INFINITY);
+        assert_eq!(rate_limiter.setpoint(), 10.0);
+        rate_limiter.set_fixed_target_rate(Some(f64::INFINITY));
+        assert_eq!(rate_limiter.fixed_target_rate(), None);
+        rate_limiter.set_rate_bounds(f64::NEG_INFINITY, f64::INFINITY);
+        assert_eq!(
+            (rate_limiter.min_rate(), rate_limiter.max_rate()),
+            (5.0, 15.0)
+        );
+        rate_limiter.set_rate_bounds(f64::NAN, 1.0);
+        assert_eq!(
+            (rate_limiter.min_rate(), rate_limiter.max_rate()),
+            (5.0, 5.0)
+        );
+        assert_eq!(rate_limiter.target_rate(), 5.0);
+
+        // An infinite external rate saturates the limiter without poisoning the target rate
+        rate_limiter.set_external_request_rate(f64::INFINITY);
+        for _ in 0..10 {
+            rate_limiter.should_throttle();
+        }
+        assert_eq!(rate_limiter.target_rate(), 5.0);
+        assert!(rate_limiter
+            .pid_controller()
+            .accumulated_error()
+            .is_finite());
+    }
+
+    #[test]
+    fn test_fixed_target_rate_suspends_pid() {
+        let pid = create_pid_controller(1.0, 0.1, 0.01, 0.001, 0.0, None, None);
+        let mut rate_limiter = create_rate_limiter(10.0, 5.0, 15.0, pid, Duration::ZERO);
+        rate_limiter.set_fixed_target_rate(Some(2.0));
+
+        for _ in 0..10 {
+            rate_limiter.should_throttle();
+        }
+        assert_eq!(rate_limiter.target_rate(), 2.0);
+
+        rate_limiter.set_fixed_target_rate(None);
+        assert_eq!(rate_limiter.target_rate(), 10.0);
+    }
+
+    #[test]
+    fn test_debug_state_snapshots_internals() {
+        let clock = clock::ManualClock::new();
+        let pid = create_pid_controller(10.0, 0.1, 0.0, 0.0, 0.0, None, None);
+        let mut rate_limiter = RateLimiterBuilder::new(10.0)
+            .min_rate(5.0)
+            .max_rate(15.0)
+            .pid_controller(pid)
+            .update_interval(Duration::from_secs(1))
+            .external_request_rate(2.0)
+            .clock(Arc::new(clock.clone()))
+            .build();
+        clock.advance(Duration::from_secs(2));
+        for _ in 0..3 {
+            rate_limiter.should_throttle();
+        }
+
+        let state = rate_limiter.debug_state();
+        assert_eq!(state.target_rate, rate_limiter.target_rate());
+        assert_eq!((state.min_rate, state.max_rate), (5.0, 15.0));
+        assert_eq!(state.external_request_rate, 2.0);
+        assert_eq!(state.request_window_len, 3);
+        assert_eq!(state.pid_mode, ControllerMode::Auto);
+        let correction = state.last_correction.unwrap();
+        assert_eq!(correction.output, state.pid_output);
+        assert_eq!(correction.p, 0.1 * correction.error);
+
+        #[cfg(feature = "serde")]
+        {
+            let json = serde_json::to_value(state).unwrap();
+            assert_eq!(json["max_rate"], 15.0);
+            assert_eq!(json["pid_mode"], "Auto");
+            assert_eq!(json["last_correction"]["output"], state.pid_output);
+        }
+    }
+
+    #[cfg(feature = "tracing")]
+    #[test]
+    fn test_traces_decisions_and_pid_updates() {
+        use crate::clock::ManualClock;
+        use std::fmt::Write;
+        use std::sync::Mutex;
+        use tracing::field::{Field, Visit};
+        use tracing::span::{Attributes, Id, Record};
+        use tracing::{Event, Metadata, Subscriber};
+
+        /// Records every event as its fields, prefixed by the name of the span it happened in.
+        #[derive(Default)]
+        struct Recorder {
+            spans: Mutex<Vec<&'static str>>,
+            current: Mutex<Option<usize>>,
+            events: Mutex<Vec<String>>,
+        }
+
+        struct Fields(String);
+
+        impl Visit for Fields {
+            fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+                let _ = write!(self.0, " {}={:?}", field.name(), value);
+            }
+        }
+
+        impl Subscriber for &'static Recorder {
+            fn enabled(&self, _metadata: &Metadata<'_>) -> bool {
+                true
+            }
+
+            fn new_span(&self, span: &Attributes<'_>) -> Id {
+                let mut spans = self.spans.lock().unwrap();
+                spans.push(span.metadata().name());
+                Id::from_u64(spans.len() as u64)
+            }
+
+            fn record(&self, _span: &Id, _values: &Record<'_>) {}
+
+            fn record_follows_from(&self, _span: &Id, _follows: &Id) {}
+
+            fn event(&self, event: &Event<'_>) {
+                let span = self
+                    .current
+                    .lock()
+                    .unwrap()
+                    .map(|id| self.spans.lock().unwrap()[id - 1]);
+                let mut fields = Fields(span.unwrap_or("root").to_string());
+                event.record(&mut fields);
+                self.events.lock().unwrap().push(fields.0);
+            }
+
+            fn enter(&self, span: &Id) {
+                *self.current.lock().unwrap() = Some(span.into_u64() as usize);
+            }
+
+            fn exit(&self, _span: &Id) {
+                *self.current.lock().unwrap() = None;
+            }
+        }
+
+        let recorder: &'static Recorder = Box::leak(Box::default());
+        tracing::subscriber::with_default(recorder, || {
+            let clock = ManualClock::new();
+            let pid = create_pid_controller(10.0, 0.1, 0.0, 0.0, 0.0, None, None);
+            let mut rate_limiter = RateLimiterBuilder::new(10.0)
+                .pid_controller(pid)
+                .update_interval(Duration::from_secs(1))
+                .clock(Arc::new(clock.clone()))
+                .tracing_segment("checkout")
+                .build();
+            for _ in 0..20 {
+                rate_limiter.should_throttle();
+            }
+            clock.advance(Duration::from_secs(2));
+            rate_limiter.should_throttle();
+        });
+
+        let events = recorder.events.lock().unwrap();
+        let decisions = events
+            .iter()
+            .filter(|event| event.contains("rate limiter decision"))
+            .collect::<Vec<_>>();
+        assert_eq!(decisions.len(), 21);
+        assert!(decisions
+            .iter()
+            .all(|event| event.starts_with("root") && event.contains("segment=\"checkout\"")));
+        assert!(decisions
+            .iter()
+            .any(|event| event.contains("throttled=true")));
+        assert!(events.iter().any(|event| event.starts_with("pid_update")
+            && event.contains("pid correction")
+            && event.contains(" p=")));
+    }
 }
@@ -0,0 +1,218 @@
+//! Export of rate limiter metrics through the OpenTelemetry metrics API.
+//!
+//! [`OtelMetrics`] given to
+//! [`RateLimiterBuilder::opentelemetry`](crate::RateLimiterBuilder::opentelemetry) records the
+//! limiter's decisions with instruments of an OpenTelemetry [`Meter`]:
+//!
+//! - `nenya.requests` and `nenya.throttled` counters
+//! - `nenya.target_rate` and `nenya.pid_output` gauges, observed at collection time
+//!
+//! The meter's provider decides where the metrics go, e.g. an OTLP exporter to the same collector
+//! the sentinel exports to. Without an installed provider, the global meter discards everything.
+//!
+//! # Example
+//!
+//! ```rust
+//! use nenya::opentelemetry::OtelMetrics;
+//! use nenya::RateLimiterBuilder;
+//! use opentelemetry::KeyValue;
+//!
+//! let meter = opentelemetry::global::meter("checkout-service");
+//! let metrics = OtelMetrics::new(&meter, [KeyValue::new("limiter", "checkout")]);
+//! let mut rate_limiter = RateLimiterBuilder::new(10.0).opentelemetry(metrics).build();
+//! rate_limiter.should_throttle();
+//! ```
+
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use opentelemetry::metrics::{Counter, Meter, ObservableGauge};
+use opentelemetry::KeyValue;
+
+/// Records rate limiter metrics with the instruments of an OpenTelemetry meter.
+///
+/// Clones share the instruments, so all limiters given the same `OtelMetrics` report the same
+/// time series; give each limiter its own attributes to tell them apart.
+#[derive(Clone)]
+pub struct OtelMetrics {
+    requests: Counter<u64>,
+    throttled: Counter<u64>,
+    attributes: Arc<[KeyValue]>,
+    /// Bits of the latest gauge values, read by the gauges' callbacks.
+    target_rate: Arc<AtomicU64>,
+    pid_output: Arc<AtomicU64>,
+    _gauges: Arc<[ObservableGauge<f64>; 2]>,
+}
+
+impl OtelMetrics {
+    /// Creates the instruments with `meter`, recording every measurement with `attributes`.
+    pub fn new(meter: &Meter, attributes: impl IntoIterator<Item = KeyValue>) -> Self {
+        let attributes: Arc<[KeyValue]> = attributes.into_iter().collect();
+        let target_rate = Arc::new(AtomicU64::new(0.0_f64.to_bits()));
+        let pid_output = Arc::new(AtomicU64::new(0.0_f64.to_bits()));
+        let observable = |name: &'static str, description: &'static str, value: &Arc<AtomicU64>| {
+            let value = value.clone();
+            let attributes = attributes.clone();
+            meter
+                .f64_observable_gauge(name)
+                .with_description(description)
+                .with_callback(move |observer| {
+                    observer.observe(f64::from_bits(value.load(Ordering::Relaxed)), &attributes)
+                })
+                .init()
+        };
+        let gauges = [
+            observable(
+                "nenya.target_rate",
+                "Current target rate in requests per second",
+                &target_rate,
+            ),
+            observable(
+                "nenya.pid_output",
+                "Latest correction of the PID controller",
+                &pid_output,
+            ),
+        ];
+        OtelMetrics {
+            requests: meter
+                .u64_counter("nenya.requests")
+                .with_description("Requests the rate limiter decided on")
+                .init(),
+            throttled: meter
+                .u64_counter("nenya.throttled")
+                .with_description("Requests the rate limiter throttled")
+                .init(),
+            attributes,
+            target_rate,
+            pid_output,
+            _gauges: Arc::new(gauges),
+        }
+    }
+
+    pub(crate) fn record_decision(&self, throttled: bool, target_rate: f64) {
+        self.requests.add(1, &self.attributes);
+        if throttled {
+            self.throttled.add(1, &self.attributes);
+        }
+        self.target_rate
+            .store(target_rate.to_bits(), Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_pid_output(&self, output: f64) {
+        self.pid_output.store(output.to_bits(), Ordering::Relaxed);
+    }
+}
+
+impl fmt::Debug for OtelMetrics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("OtelMetrics")
+            .field("attributes", &self.attributes)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::RateLimiterBuilder;
+    use opentelemetry::metrics::MeterProvider;
+    use opentelemetry_sdk::metrics::data::{Gauge, ResourceMetrics, Sum, Temporality};
+    use opentelemetry_sdk::metrics::reader::{
+        AggregationSelector, MetricReader, TemporalitySelector,
+    };
+    use opentelemetry_sdk::metrics::{
+        Aggregation, InstrumentKind, ManualReader, Pipeline, SdkMeterProvider,
+    };
+    use opentelemetry_sdk::Resource;
+    use std::sync::Weak;
+
+    /// Manual reader the test keeps a handle to after giving it to the meter provider.
+    #[derive(Debug, Clone, Default)]
+    struct SharedReader(Arc<ManualReader>);
+
+    impl TemporalitySelector for SharedReader {
+        fn temporality(&self, kind: InstrumentKind) -> Temporality {
+            self.0.temporality(kind)
+        }
+    }
+
+    impl AggregationSelector for SharedReader {
+        fn aggregation(&self, kind: InstrumentKind) -> Aggregation {
+            self.0.aggregation(kind)
+        }
+    }
+
+    impl MetricReader for SharedReader {
+        fn register_pipeline(&self, pipeline: Weak<Pipeline>) {
+            self.0.register_pipeline(pipeline)
+        }
+
+        fn collect(&self, rm: &mut ResourceMetrics) -> opentelemetry::metrics::Result<()> {
+            self.0.collect(rm)
+        }
+
+        fn force_flush(&self) -> opentelemetry::metrics::Result<()> {
+            self.0.force_flush()
+        }
+
+        fn shutdown(&self) -> opentelemetry::metrics::Result<()> {
+            self.0.shutdown()
+        }
+    }
+
+    #[test]
+    fn test_exports_decisions_through_meter() {
+        let reader = SharedReader::default();
+        let provider = SdkMeterProvider::builder()
+            .with_reader(reader.clone())
+            .build();
+        let metrics = OtelMetrics::new(
+            &provider.meter("nenya-test"),
+            [KeyValue::new("limiter", "checkout")],
+        );
+        let mut rate_limiter = RateLimiterBuilder::new(5.0)
+            .opentelemetry(metrics.clone())
+            .build();
+        for _ in 0..20 {
+            rate_limiter.should_throttle();
+        }
+        metrics.record_pid_output(-1.5);
+
+        let mut collected = ResourceMetrics {
+            resource: Resource::empty(),
+            scope_metrics: Vec::new(),
+        };
+        reader.collect(&mut collected).unwrap();
+        let metric = |name: &str| {
+            collected.scope_metrics[0]
+                .metrics
+                .iter()
+                .find(|metric| metric.name == name)
+                .unwrap()
+                .data
+                .as_any()
+        };
+        let count = |name: &str| {
+            let sum = metric(name).downcast_ref::<Sum<u64>>().unwrap();
+            let point = &sum.data_points[0];
+            assert!(point
+                .attributes
+                .iter()
+                .any(|(key, value)| key.as_str() == "limiter" && value.as_str() == "checkout"));
+            point.value
+        };
+        let gauge = |name: &str| {
+            metric(name)
+                .downcast_ref::<Gauge<f64>>()
+                .unwrap()
+                .data_points[0]
+                .value
+        };
+
+        assert_eq!(count("nenya.requests"), 20);
+        assert!((1..20).contains(&count("nenya.throttled")));
+        assert_eq!(gauge("nenya.target_rate"), 5.0);
+        assert_eq!(gauge("nenya.pid_output"), -1.5);
+    }
+}
@@ -0,0 +1,258 @@
+/// Detects sustained oscillation in a controller's output.
+///
+/// Badly tuned gains typically show up as an output that keeps flipping sign with a large
+/// amplitude. The detector keeps a sliding window of the most recent outputs and flags the
+/// controller as unstable when both the number of zero crossings and the peak-to-peak amplitude
+/// within the window exceed their thresholds.
+///
+/// # Example
+///
+/// ```rust
+/// use nenya::oscillation_detector::OscillationDetectorBuilder;
+/// use nenya::pid_controller::PIDControllerBuilder;
+///
+/// let mut pid_controller = PIDControllerBuilder::new(10.0)
+///     .kp(1.0)
+///     .ki(0.1)
+///     .kd(0.01)
+///     .build();
+///
+/// let mut detector = OscillationDetectorBuilder::new(20)
+///     .min_zero_crossings(6)
+///     .min_amplitude(2.0)
+///     .on_unstable(|unstable| println!("Controller unstable: {}", unstable))
+///     .build();
+///
+/// let correction: f32 = pid_controller.compute_correction(8.0);
+/// detector.observe(correction);
+/// assert!(!detector.is_unstable());
+/// ```
+use num_traits::Float;
+use std::collections::VecDeque;
+use std::fmt;
+
+/// Callback invoked whenever the instability flag changes.
+pub type InstabilityCallback = Box<dyn FnMut(bool) + Send>;
+
+pub struct OscillationDetector<T> {
+    window_size: usize,
+    min_zero_crossings: usize,
+    min_amplitude: T,
+    outputs: VecDeque<T>,
+    unstable: bool,
+    on_unstable: Option<InstabilityCallback>,
+}
+
+impl<T: Float> OscillationDetector<T> {
+    /// Creates a new `OscillationDetector`.
+    ///
+    /// The controller is considered unstable when at least `min_zero_crossings` sign changes and a
+    /// peak-to-peak amplitude of at least `min_amplitude` are observed within the last
+    /// `window_size` outputs.
+    pub fn new(window_size: usize, min_zero_crossings: usize, min_amplitude: T) -> Self {
+        OscillationDetector {
+            window_size,
+            min_zero_crossings,
+            min_amplitude,
+            outputs: VecDeque::with_capacity(window_size),
+            unstable: false,
+            on_unstable: None,
+        }
+    }
+
+    /// Records a controller output and re-evaluates the instability flag.
+    ///
+    /// Returns `true` if the controller is currently considered unstable.
+    pub fn observe(&mut self, output: T) -> bool {
+        if self.window_size == 0 {
+            return false;
+        }
+        if self.outputs.len() == self.window_size {
+            self.outputs.pop_front();
+        }
+        self.outputs.push_back(output);
+
+        let unstable = self.zero_crossings() >= self.min_zero_crossings
+            && self.amplitude() >= self.min_amplitude;
+        if unstable != self.unstable {
+            self.unstable = unstable;
+            if let Some(callback) = self.on_unstable.as_mut() {
+                callback(unstable);
+            }
+        }
+
+        self.unstable
+    }
+
+    /// Returns `true` if sustained oscillation was detected in the current window.
+    pub fn is_unstable(&self) -> bool {
+        self.unstable
+    }
+
+    /// Returns the number of sign changes in the current window, ignoring zero outputs.
+    pub fn zero_crossings(&self) -> usize {
+        let mut crossings = 0;
+        let mut previous_sign: Option<bool> = None;
+        for output in self.outputs.iter().filter(|output| !output.is_zero()) {
+            let sign = output.is_sign_positive();
+            if previous_sign.is_some_and(|previous| previous != sign) {
+                crossings += 1;
+            }
+            previous_sign = Some(sign);
+        }
+        crossings
+    }
+
+    /// Returns the peak-to-peak amplitude of the outputs in the current window.
+    pub fn amplitude(&self) -> T {
+        let (min, max) = self
+            .outputs
+            .iter()
+            .fold((T::infinity(), T::neg_infinity()), |(min, max), &output| {
+                (min.min(output), max.max(output))
+            });
+        if self.outputs.is_empty() {
+            T::zero()
+        } else {
+            max - min
+        }
+    }
+
+    /// Sets the callback invoked when the instability flag changes.
+    pub fn set_on_unstable(&mut self, callback: impl FnMut(bool) + Send + 'static) {
+        self.on_unstable = Some(Box::new(callback));
+    }
+
+    /// Clears the observed outputs and the instability flag.
+    pub fn reset(&mut self) {
+        self.outputs.clear();
+        self.unstable = false;
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for OscillationDetector<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("OscillationDetector")
+            .field("window_size", &self.window_size)
+            .field("min_zero_crossings", &self.min_zero_crossings)
+            .field("min_amplitude", &self.min_amplitude)
+            .field("outputs", &self.outputs)
+            .field("unstable", &self.unstable)
+            .field("on_unstable", &self.on_unstable.is_some())
+            .finish()
+    }
+}
+
+/// Builder for creating an `OscillationDetector` instance.
+pub struct OscillationDetectorBuilder<T> {
+    window_size: usize,
+    min_zero_crossings: usize,
+    min_amplitude: T,
+    on_unstable: Option<InstabilityCallback>,
+}
+
+impl<T: Float> OscillationDetectorBuilder<T> {
+    /// Creates a new `OscillationDetectorBuilder` with default values.
+    ///
+    /// By default half of the window must be sign changes and any non-zero amplitude counts.
+    pub fn new(window_size: usize) -> Self {
+        OscillationDetectorBuilder {
+            window_size,
+            min_zero_crossings: window_size / 2,
+            min_amplitude: T::zero(),
+            on_unstable: None,
+        }
+    }
+
+    /// Sets the minimum number of zero crossings within the window.
+    pub fn min_zero_crossings(mut self, min_zero_crossings: usize) -> Self {
+        self.min_zero_crossings = min_zero_crossings;
+        self
+    }
+
+    /// Sets the minimum peak-to-peak amplitude within the window.
+    pub fn min_amplitude(mut self, min_amplitude: impl Into<T>) -> Self {
+        self.min_amplitude = min_amplitude.into();
+        self
+    }
+
+    /// Sets the callback invoked when the instability flag changes.
+    pub fn on_unstable(mut self, callback: impl FnMut(bool) + Send + 'static) -> Self {
+        self.on_unstable = Some(Box::new(callback));
+        self
+    }
+
+    /// Builds and returns the `OscillationDetector` instance.
+    pub fn build(self) -> OscillationDetector<T> {
+        OscillationDetector {
+            window_size: self.window_size,
+            min_zero_crossings: self.min_zero_crossings,
+            min_amplitude: self.min_amplitude,
+            outputs: VecDeque::with_capacity(self.window_size),
+            unstable: false,
+            on_unstable: self.on_unstable,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    #[test]
+    fn test_detects_sustained_oscillation() {
+        let mut detector = OscillationDetector::new(10, 6, 2.0);
+        for n in 0..10 {
+            detector.observe(if n % 2 == 0 { 3.0 } else { -3.0 });
+        }
+        assert!(detector.is_unstable());
+        assert_eq!(detector.zero_crossings(), 9);
+        assert_eq!(detector.amplitude(), 6.0);
+    }
+
+    #[test]
+    fn test_ignores_small_oscillation() {
+        let mut detector = OscillationDetector::new(10, 6, 2.0);
+        for n in 0..10 {
+            detector.observe(if n % 2 == 0 { 0.1 } else { -0.1 });
+        }
+        assert!(!detector.is_unstable());
+    }
+
+    #[test]
+    fn test_ignores_monotonic_output() {
+        let mut detector = OscillationDetector::new(10, 6, 2.0);
+        for n in 0..10 {
+            detector.observe(n as f64);
+        }
+        assert!(!detector.is_unstable());
+        assert_eq!(detector.zero_crossings(), 0);
+    }
+
+    #[test]
+    fn test_callback_fires_on_transitions() {
+        let transitions = Arc::new(AtomicUsize::new(0));
+        let counter = transitions.clone();
+        let mut detector = OscillationDetectorBuilder::new(4)
+            .min_zero_crossings(3)
+            .min_amplitude(1.0)
+            .on_unstable(move |_| {
+                counter.fetch_add(1, Ordering::SeqCst);
+            })
+            .build();
+
+        for output in [1.0, -1.0, 1.0, -1.0, 1.0] {
+            detector.observe(output);
+        }
+        assert!(detector.is_unstable());
+        assert_eq!(transitions.load(Ordering::SeqCst), 1);
+
+        for _ in 0..4 {
+            detector.observe(0.5);
+        }
+        assert!(!detector.is_unstable());
+        assert_eq!(transitions.load(Ordering::SeqCst), 2);
+    }
+}
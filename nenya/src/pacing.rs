@@ -0,0 +1,257 @@
+//! Client-side pacing of requests to a rate limited upstream.
+//!
+//! A [`Pacer`] sits in front of an HTTP client and holds each outbound request in
+//! [`Pacer::acquire`] until the rate limiter has room for it. When the upstream answers with
+//! `429 Too Many Requests` or `503 Service Unavailable`, the pacer pauses for the response's
+//! `Retry-After` and halves the PID controller's setpoint; every successful response raises the
+//! setpoint a little again. The client thereby settles just below the rate the upstream can
+//! actually serve, rather than the rate it was configured with.
+//!
+//! With the `reqwest` feature, [`Pacer::send`] does all of this for a `reqwest` request.
+//!
+//! The pacer waits through a [`Timer`], so it runs on any async runtime; [`Pacer::new`] uses
+//! tokio's and is available with the `tokio` feature.
+//!
+//! # Example
+//!
+//! ```rust
+//! use std::sync::Arc;
+//!
+//! use nenya::pacing::Pacer;
+//! use nenya::runtime::TokioTimer;
+//! use nenya::RateLimiterBuilder;
+//!
+//! # async fn example() {
+//! let rate_limiter = RateLimiterBuilder::new(50.0).min_rate(1.0).build();
+//! let pacer = Pacer::with_timer(rate_limiter, Arc::new(TokioTimer));
+//! pacer.acquire().await;
+//! // Send the request, then report how the upstream answered
+//! pacer.record_response(429, Some("2"));
+//! # }
+//! ```
+
+use num_traits::{Float, FromPrimitive, Signed};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+use crate::clock::Timestamp;
+use crate::runtime::Timer;
+use crate::RateLimiter;
+
+/// Factor the setpoint is multiplied by when the upstream throttles a request.
+const BACKOFF_FACTOR: f64 = 0.5;
+
+/// Fraction of the maximum rate the setpoint is raised by for every successful request.
+const RECOVERY_STEP: f64 = 0.01;
+
+/// Pause after a throttled response that does not say when to retry.
+const DEFAULT_RETRY_AFTER: Duration = Duration::from_secs(1);
+
+/// Shortest wait between attempts to acquire, so waiting callers do not spin.
+const MIN_WAIT: Duration = Duration::from_millis(1);
+
+/// Paces outbound requests with a rate limiter tuned by the upstream's responses.
+#[derive(Debug)]
+pub struct Pacer<T> {
+    state: Mutex<PacerState<T>>,
+    timer: Arc<dyn Timer>,
+}
+
+#[derive(Debug)]
+struct PacerState<T> {
+    rate_limiter: RateLimiter<T>,
+    /// Set while honoring an upstream's `Retry-After`, on the rate limiter's clock.
+    paused_until: Option<Timestamp>,
+}
+
+impl<T: Float + Signed + FromPrimitive + Copy> Pacer<T> {
+    /// Creates a pacer sending requests at up to the target rate of `rate_limiter`, waiting on
+    /// the tokio runtime.
+    ///
+    /// The rate limiter's minimum and maximum rates bound how far the upstream's feedback can
+    /// move the rate, and its clock times the pauses after throttled responses.
+    #[cfg(feature = "tokio")]
+    pub fn new(rate_limiter: RateLimiter<T>) -> Self {
+        Self::with_timer(rate_limiter, Arc::new(crate::runtime::TokioTimer))
+    }
+
+    /// Creates a pacer sending requests at up to the target rate of `rate_limiter`, waiting
+    /// with `timer`.
+    pub fn with_timer(rate_limiter: RateLimiter<T>, timer: Arc<dyn Timer>) -> Self {
+        Pacer {
+            state: Mutex::new(PacerState {
+                rate_limiter,
+                paused_until: None,
+            }),
+            timer,
+        }
+    }
+
+    /// Waits until a request may be sent.
+    pub async fn acquire(&self) {
+        while let Some(wait) = self.try_acquire() {
+            self.timer.sleep(wait).await;
+        }
+    }
+
+    /// Claims room for a request if it may be sent now, returning `None`, or returns how long to
+    /// wait before trying again.
+    pub fn try_acquire(&self) -> Option<Duration> {
+        let mut state = self.state.lock().unwrap();
+        let now = state.rate_limiter.clock().now();
+        if let Some(paused_until) = state.paused_until {
+            if paused_until > now {
+                return Some(paused_until.duration_since(now));
+            }
+            state.paused_until = None;
+        }
+        let rate_limiter = &mut state.rate_limiter;
+        rate_limiter
+            .should_throttle()
+            .then(|| rate_limiter.retry_after().max(MIN_WAIT))
+    }
+
+    /// Feeds the upstream's answer to a request into the pacer.
+    ///
+    /// `retry_after` is the response's `Retry-After` header, if it has one.
+    pub fn record_response(&self, status: u16, retry_after: Option<&str>) {
+        match status {
+            429 | 503 => self.record_throttled(retry_after.and_then(parse_retry_after)),
+            200..=499 => self.record_success(),
+            _ => {}
+        }
+    }
+
+    /// Pauses for `retry_after`, or a second if it is unknown, and lowers the rate after the
+    /// upstream throttled a request.
+    pub fn record_throttled(&self, retry_after: Option<Duration>) {
+        let mut state = self.state.lock().unwrap();
+        let paused_until =
+            state.rate_limiter.clock().now() + retry_after.unwrap_or(DEFAULT_RETRY_AFTER);
+        state.paused_until = state.paused_until.max(Some(paused_until));
+        let factor = T::from_f64(BACKOFF_FACTOR).unwrap();
+        let setpoint = state.rate_limiter.setpoint() * factor;
+        set_clamped_setpoint(&mut state.rate_limiter, setpoint);
+    }
+
+    /// Raises the rate after the upstream served a request.
+    pub fn record_success(&self) {
+        let mut state = self.state.lock().unwrap();
+        let rate_limiter = &mut state.rate_limiter;
+        let step = rate_limiter.max_rate() * T::from_f64(RECOVERY_STEP).unwrap();
+        let setpoint = rate_limiter.setpoint() + step;
+        set_clamped_setpoint(rate_limiter, setpoint);
+    }
+
+    /// Returns the rate the pacer is currently steering towards.
+    pub fn setpoint(&self) -> T {
+        self.state.lock().unwrap().rate_limiter.setpoint()
+    }
+
+    /// Returns the current target rate of the pacer's rate limiter.
+    pub fn target_rate(&self) -> T {
+        self.state.lock().unwrap().rate_limiter.target_rate()
+    }
+
+    /// Runs `f` with the pacer's rate limiter.
+    pub(crate) fn with_rate_limiter<R>(&self, f: impl FnOnce(&mut RateLimiter<T>) -> R) -> R {
+        f(&mut self.state.lock().unwrap().rate_limiter)
+    }
+}
+
+#[cfg(feature = "reqwest")]
+impl<T: Float + Signed + FromPrimitive + Copy> Pacer<T> {
+    /// Sends `request` once the pacer allows it, feeding the response back into the pacer.
+    pub async fn send(
+        &self,
+        request: reqwest::RequestBuilder,
+    ) -> reqwest::Result<reqwest::Response> {
+        self.acquire().await;
+        let response = request.send().await?;
+        let retry_after = response
+            .headers()
+            .get(reqwest::header::RETRY_AFTER)
+            .and_then(|value| value.to_str().ok());
+        self.record_response(response.status().as_u16(), retry_after);
+        Ok(response)
+    }
+}
+
+fn set_clamped_setpoint<T: Float + Signed + FromPrimitive + Copy>(
+    rate_limiter: &mut RateLimiter<T>,
+    setpoint: T,
+) {
+    let setpoint = num_traits::clamp(setpoint, rate_limiter.min_rate(), rate_limiter.max_rate());
+    rate_limiter.set_setpoint(setpoint);
+}
+
+/// Parses a `Retry-After` header, either a number of seconds or an HTTP date.
+///
+/// Dates in the past yield zero.
+pub fn parse_retry_after(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(secs) = value.parse::<u64>() {
+        return Some(Duration::from_secs(secs));
+    }
+    let date = httpdate::parse_http_date(value).ok()?;
+    Some(
+        date.duration_since(SystemTime::now())
+            .unwrap_or(Duration::ZERO),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::ManualClock;
+    use crate::runtime::Sleep;
+    use crate::RateLimiterBuilder;
+
+    #[derive(Debug)]
+    struct Immediate;
+
+    impl Timer for Immediate {
+        fn sleep(&self, _duration: Duration) -> Sleep {
+            Box::pin(std::future::ready(()))
+        }
+    }
+
+    #[test]
+    fn test_upstream_feedback_moves_setpoint() {
+        let clock = Arc::new(ManualClock::new());
+        let rate_limiter = RateLimiterBuilder::new(100.0)
+            .min_rate(10.0)
+            .max_rate(100.0)
+            .clock(clock.clone())
+            .build();
+        let pacer = Pacer::with_timer(rate_limiter, Arc::new(Immediate));
+        assert_eq!(pacer.try_acquire(), None);
+
+        pacer.record_response(429, Some("5"));
+        assert_eq!(pacer.setpoint(), 50.0);
+        assert_eq!(pacer.try_acquire(), Some(Duration::from_secs(5)));
+        clock.advance(Duration::from_secs(5));
+        assert_eq!(pacer.try_acquire(), None);
+
+        pacer.record_response(200, None);
+        assert_eq!(pacer.setpoint(), 51.0);
+        for _ in 0..10 {
+            pacer.record_throttled(Some(Duration::ZERO));
+        }
+        assert_eq!(pacer.setpoint(), 10.0);
+        pacer.record_response(500, None);
+        assert_eq!(pacer.setpoint(), 10.0);
+    }
+
+    #[test]
+    fn test_parses_retry_after() {
+        assert_eq!(parse_retry_after(" 3 "), Some(Duration::from_secs(3)));
+        assert_eq!(
+            parse_retry_after("Wed, 21 Oct 2015 07:28:00 GMT"),
+            Some(Duration::ZERO)
+        );
+        let future = httpdate::fmt_http_date(SystemTime::now() + Duration::from_secs(60));
+        assert!(parse_retry_after(&future).is_some_and(|wait| wait > Duration::from_secs(50)));
+        assert_eq!(parse_retry_after("soon"), None);
+    }
+}
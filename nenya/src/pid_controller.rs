@@ -19,6 +19,136 @@
 /// println!("Correction: {}", correction);
 /// ```
 use num_traits::{Float, Signed};
+use std::collections::VecDeque;
+use std::fmt;
+use std::sync::Arc;
+
+use crate::clock::{Clock, SystemClock, Timestamp};
+
+/// A snapshot of a single `compute_correction` call, recorded for diagnostics.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct CorrectionRecord<T> {
+    /// When the correction was computed.
+    pub timestamp: Timestamp,
+    /// The measured signal passed to the controller.
+    pub signal: T,
+    /// The error between the setpoint and the signal.
+    pub error: T,
+    /// The proportional term.
+    pub p: T,
+    /// The integral term.
+    pub i: T,
+    /// The derivative term.
+    pub d: T,
+    /// The (clamped) correction returned to the caller.
+    pub output: T,
+}
+
+/// Errors returned by [`PIDControllerBuilder::try_build`] when the configuration is invalid, and
+/// by [`PIDController::try_compute_correction`] when a correction is rejected.
+#[derive(Debug, Clone, PartialEq)]
+pub enum PIDControllerError {
+    /// A parameter is NaN or infinite.
+    NonFinite { parameter: &'static str },
+    /// The gains have different signs, so the terms work against each other.
+    MixedGainSigns { kp: f64, ki: f64, kd: f64 },
+    /// The error bias is outside `[-1, 1]` and would flip the sign of the accumulated error.
+    InvalidErrorBias { error_bias: f64 },
+    /// The error limit is zero or negative.
+    NonPositiveErrorLimit { error_limit: f64 },
+    /// The output limit is zero or negative.
+    NonPositiveOutputLimit { output_limit: f64 },
+    /// An output limit is set with a zero integral gain, so anti-windup feedback would divide by
+    /// zero.
+    OutputLimitWithoutIntegralGain,
+    /// The signal is finite, but the correction computed from it is not, e.g. because the terms
+    /// overflowed.
+    NonFiniteCorrection,
+}
+
+impl fmt::Display for PIDControllerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PIDControllerError::NonFinite { parameter } => {
+                write!(f, "{} must be a finite number", parameter)
+            }
+            PIDControllerError::MixedGainSigns { kp, ki, kd } => write!(
+                f,
+                "gains must share the same sign (kp = {}, ki = {}, kd = {})",
+                kp, ki, kd
+            ),
+            PIDControllerError::InvalidErrorBias { error_bias } => write!(
+                f,
+                "error_bias must be between -1 and 1 (got {})",
+                error_bias
+            ),
+            PIDControllerError::NonPositiveErrorLimit { error_limit } => {
+                write!(f, "error_limit must be positive (got {})", error_limit)
+            }
+            PIDControllerError::NonPositiveOutputLimit { output_limit } => {
+                write!(f, "output_limit must be positive (got {})", output_limit)
+            }
+            PIDControllerError::OutputLimitWithoutIntegralGain => {
+                write!(
+                    f,
+                    "output_limit requires a non-zero ki for anti-windup feedback"
+                )
+            }
+            PIDControllerError::NonFiniteCorrection => {
+                write!(f, "the correction is not a finite number")
+            }
+        }
+    }
+}
+
+impl std::error::Error for PIDControllerError {}
+
+/// Algorithm form used by a `PIDController` to compute its output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub enum PIDForm {
+    /// The output is the absolute sum of the P, I, and D terms.
+    #[default]
+    Positional,
+    /// The output is the change in output since the last step (incremental form).
+    ///
+    /// Since no error is accumulated, the velocity form is immune to integral windup, and its
+    /// output maps directly onto the rate limiter's `target += output` update.
+    Velocity,
+}
+
+/// Operating mode of a `PIDController`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub enum ControllerMode {
+    /// The controller computes its output from the PID terms.
+    #[default]
+    Auto,
+    /// The controller output is forced to an operator-specified value.
+    Manual,
+}
+
+/// Saturation status of a `PIDController` as of its last correction.
+///
+/// An unsaturated controller is in control of the process. A saturated controller wants more
+/// authority than its limits allow, which usually means the limits or the rate bounds are too
+/// tight for the current load.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct SaturationState {
+    /// The last output was clamped by the output limit.
+    pub output_clamped: bool,
+    /// The accumulated error was pinned at the error limit.
+    pub integral_clamped: bool,
+}
+
+impl SaturationState {
+    /// Returns `true` if either the output or the accumulated error was clamped.
+    pub fn is_saturated(&self) -> bool {
+        self.output_clamped || self.integral_clamped
+    }
+}
 
 #[derive(Debug, Clone)]
 pub struct PIDController<T> {
@@ -31,6 +161,16 @@ pub struct PIDController<T> {
     output_limit: Option<T>,
     accumulated_error: T,
     previous_error: T,
+    history_capacity: usize,
+    history: VecDeque<CorrectionRecord<T>>,
+    last_correction: Option<CorrectionRecord<T>>,
+    mode: ControllerMode,
+    manual_output: T,
+    manual_step_pending: bool,
+    saturation_state: SaturationState,
+    form: PIDForm,
+    second_previous_error: T,
+    clock: Arc<dyn Clock>,
 }
 
 impl<T: Float + Signed + Copy> PIDController<T> {
@@ -58,6 +198,16 @@ impl<T: Float + Signed + Copy> PIDController<T> {
             accumulated_error: T::zero(),
             previous_error: T::zero(),
             error_bias,
+            history_capacity: 0,
+            history: VecDeque::new(),
+            last_correction: None,
+            mode: ControllerMode::Auto,
+            manual_output: T::zero(),
+            manual_step_pending: false,
+            saturation_state: SaturationState::default(),
+            form: PIDForm::Positional,
+            second_previous_error: T::zero(),
+            clock: Arc::new(SystemClock),
         }
     }
 
@@ -76,6 +226,16 @@ impl<T: Float + Signed + Copy> PIDController<T> {
             accumulated_error: T::zero(),
             previous_error: T::zero(),
             error_bias: T::one(),
+            history_capacity: 0,
+            history: VecDeque::new(),
+            last_correction: None,
+            mode: ControllerMode::Auto,
+            manual_output: T::zero(),
+            manual_step_pending: false,
+            saturation_state: SaturationState::default(),
+            form: PIDForm::Positional,
+            second_previous_error: T::zero(),
+            clock: Arc::new(SystemClock),
         }
     }
 
@@ -84,25 +244,81 @@ impl<T: Float + Signed + Copy> PIDController<T> {
     /// This method calculates the PID correction using the proportional, integral, and derivative
     /// components. The computed correction is clamped if the output limit is set, and anti-windup
     /// feedback correction is applied if necessary.
+    ///
+    /// In [`ControllerMode::Manual`] the manual output is returned instead, and the accumulated
+    /// error tracks it so that switching back to [`ControllerMode::Auto`] is bumpless.
+    ///
+    /// A NaN or infinite signal, or one whose correction is not finite, is ignored: the
+    /// correction is zero and the controller's state is left as it was, so a single bad sample
+    /// cannot poison every correction after it. Use
+    /// [`try_compute_correction`](Self::try_compute_correction) to tell these apart.
     pub fn compute_correction(&mut self, signal: impl Into<T>) -> T {
-        let error = self.setpoint - signal.into();
+        self.try_compute_correction(signal)
+            .unwrap_or_else(|_| T::zero())
+    }
+
+    /// Computes the correction like [`compute_correction`](Self::compute_correction), but
+    /// returns an error instead of a zero correction for a NaN or infinite signal, or a
+    /// correction that is not finite.
+    pub fn try_compute_correction(
+        &mut self,
+        signal: impl Into<T>,
+    ) -> Result<T, PIDControllerError> {
+        let signal = signal.into();
+        if !signal.is_finite() {
+            return Err(PIDControllerError::NonFinite {
+                parameter: "signal",
+            });
+        }
+
+        let state = (
+            self.accumulated_error,
+            self.previous_error,
+            self.second_previous_error,
+            self.saturation_state,
+        );
+        let record = self.correction(signal);
+        if !(record.output.is_finite()
+            && self.accumulated_error.is_finite()
+            && self.previous_error.is_finite())
+        {
+            (
+                self.accumulated_error,
+                self.previous_error,
+                self.second_previous_error,
+                self.saturation_state,
+            ) = state;
+            return Err(PIDControllerError::NonFiniteCorrection);
+        }
+        self.record_history(record);
+        Ok(record.output)
+    }
+
+    /// Computes the correction for `signal` and updates the controller's state, returning the
+    /// record of the correction.
+    fn correction(&mut self, signal: T) -> CorrectionRecord<T> {
+        let error = self.setpoint - signal;
         let p = self.kp * error;
 
-        // Apply error bias
-        let biased_error = if error.is_positive() {
-            error * (num_traits::one::<T>() + self.error_bias)
-        } else {
-            error * (num_traits::one::<T>() - self.error_bias)
-        };
-        self.accumulated_error = self.accumulated_error + biased_error;
+        if self.mode == ControllerMode::Manual {
+            return self.track_manual_output(signal, error, p);
+        }
+        if self.form == PIDForm::Velocity {
+            return self.compute_velocity_correction(signal, error);
+        }
+
+        self.accumulated_error = self.accumulated_error + self.bias_error(error);
 
         // Clamp accumulated_error to prevent integral windup
+        self.saturation_state.integral_clamped = false;
         if let Some(error_limit) = self.error_limit {
-            self.accumulated_error = num_traits::clamp(
+            let clamped_error = num_traits::clamp(
                 self.accumulated_error,
                 -error_limit.abs(),
                 error_limit.abs(),
             );
+            self.saturation_state.integral_clamped = clamped_error != self.accumulated_error;
+            self.accumulated_error = clamped_error;
         }
 
         let i = self.ki * self.accumulated_error;
@@ -115,15 +331,195 @@ impl<T: Float + Signed + Copy> PIDController<T> {
             correction
         };
 
-        // Anti-windup feedback correction
-        if correction != clamped_correction {
+        self.saturation_state.output_clamped = correction != clamped_correction;
+
+        // Anti-windup feedback correction, unless the integral gain was set to zero at runtime
+        if correction != clamped_correction && self.ki != T::zero() {
             let feedback = correction - clamped_correction;
             self.accumulated_error = self.accumulated_error - (feedback / self.ki);
         }
 
+        self.second_previous_error = self.previous_error;
+        self.previous_error = error;
+
+        CorrectionRecord {
+            timestamp: self.clock.now(),
+            signal,
+            error,
+            p,
+            i,
+            d,
+            output: clamped_correction,
+        }
+    }
+
+    /// Computes the change in output using the velocity form of the PID algorithm.
+    fn compute_velocity_correction(&mut self, signal: T, error: T) -> CorrectionRecord<T> {
+        let p = self.kp * (error - self.previous_error);
+        let i = self.ki * self.bias_error(error);
+        let d = self.kd
+            * (error - (self.previous_error + self.previous_error) + self.second_previous_error);
+
+        let correction = p + i + d;
+        let clamped_correction = if let Some(output_limit) = self.output_limit {
+            num_traits::clamp(correction, -output_limit.abs(), output_limit.abs())
+        } else {
+            correction
+        };
+
+        self.saturation_state = SaturationState {
+            output_clamped: correction != clamped_correction,
+            integral_clamped: false,
+        };
+
+        self.second_previous_error = self.previous_error;
+        self.previous_error = error;
+
+        CorrectionRecord {
+            timestamp: self.clock.now(),
+            signal,
+            error,
+            p,
+            i,
+            d,
+            output: clamped_correction,
+        }
+    }
+
+    /// Applies the error bias, reacting more to positive errors if the bias is positive and more
+    /// to negative errors if it is negative.
+    fn bias_error(&self, error: T) -> T {
+        if error.is_positive() {
+            error * (num_traits::one::<T>() + self.error_bias)
+        } else {
+            error * (num_traits::one::<T>() - self.error_bias)
+        }
+    }
+
+    /// Back-calculates the accumulated error so the integral term accounts for the manual output.
+    ///
+    /// The accumulated error is clamped to the error limit like in automatic mode, so a large
+    /// manual output cannot wind up the integral beyond its anti-windup bound.
+    fn track_manual_output(&mut self, signal: T, error: T, p: T) -> CorrectionRecord<T> {
+        let d = self.kd * (error - self.previous_error);
+        let i = self.manual_output - p - d;
+        let output = match self.form {
+            PIDForm::Positional => self.manual_output,
+            // A velocity-form output is a change, so the manual output is applied once and then
+            // held with zero changes
+            PIDForm::Velocity if std::mem::take(&mut self.manual_step_pending) => {
+                self.manual_output
+            }
+            PIDForm::Velocity => T::zero(),
+        };
+        self.saturation_state = SaturationState::default();
+        if self.form == PIDForm::Positional && self.ki != T::zero() {
+            self.accumulated_error = i / self.ki;
+            if let Some(error_limit) = self.error_limit {
+                let clamped_error = num_traits::clamp(
+                    self.accumulated_error,
+                    -error_limit.abs(),
+                    error_limit.abs(),
+                );
+                self.saturation_state.integral_clamped = clamped_error != self.accumulated_error;
+                self.accumulated_error = clamped_error;
+            }
+        }
+
+        self.second_previous_error = self.previous_error;
         self.previous_error = error;
 
-        clamped_correction
+        CorrectionRecord {
+            timestamp: self.clock.now(),
+            signal,
+            error,
+            p,
+            i,
+            d,
+            output,
+        }
+    }
+
+    /// Switches the controller to manual mode, forcing the output to `output`.
+    ///
+    /// May be called again while in manual mode to change the forced output.
+    ///
+    /// In [`PIDForm::Velocity`], `output` is a change: it is returned by the next correction
+    /// only, and the corrections after it are zero so the controlled value holds.
+    pub fn set_manual(&mut self, output: impl Into<T>) {
+        self.mode = ControllerMode::Manual;
+        self.manual_output = output.into();
+        self.manual_step_pending = true;
+    }
+
+    /// Switches the controller back to automatic mode.
+    ///
+    /// Because the accumulated error tracks the manual output, the first automatic correction
+    /// continues from the manual output rather than jumping back to the pre-manual value.
+    pub fn set_auto(&mut self) {
+        self.mode = ControllerMode::Auto;
+    }
+
+    /// Returns the current operating mode.
+    pub fn mode(&self) -> ControllerMode {
+        self.mode
+    }
+
+    /// Returns the output forced while in manual mode.
+    pub fn manual_output(&self) -> T {
+        self.manual_output
+    }
+
+    /// Appends a record to the history buffer, evicting the oldest record when full.
+    ///
+    /// With the `tracing` feature, every record is also emitted as a debug event.
+    fn record_history(&mut self, record: CorrectionRecord<T>) {
+        #[cfg(feature = "tracing")]
+        tracing::debug!(
+            signal = record.signal.to_f64().unwrap_or_default(),
+            error = record.error.to_f64().unwrap_or_default(),
+            p = record.p.to_f64().unwrap_or_default(),
+            i = record.i.to_f64().unwrap_or_default(),
+            d = record.d.to_f64().unwrap_or_default(),
+            output = record.output.to_f64().unwrap_or_default(),
+            "pid correction"
+        );
+        self.last_correction = Some(record);
+        if self.history_capacity == 0 {
+            return;
+        }
+        if self.history.len() == self.history_capacity {
+            self.history.pop_front();
+        }
+        self.history.push_back(record);
+    }
+
+    /// Returns the recorded correction history, oldest first.
+    ///
+    /// The history is empty unless a capacity was configured with
+    /// [`PIDControllerBuilder::history_capacity`].
+    pub fn history(&self) -> &VecDeque<CorrectionRecord<T>> {
+        &self.history
+    }
+
+    /// Returns the record of the last correction, whether or not a history is kept.
+    pub fn last_correction(&self) -> Option<&CorrectionRecord<T>> {
+        self.last_correction.as_ref()
+    }
+
+    /// Clears the recorded correction history.
+    pub fn clear_history(&mut self) {
+        self.history.clear();
+    }
+
+    /// Returns whether the last correction was limited by the output or error limits.
+    pub fn saturation_state(&self) -> SaturationState {
+        self.saturation_state
+    }
+
+    /// Returns the algorithm form of the PID controller.
+    pub fn form(&self) -> PIDForm {
+        self.form
     }
 
     /// Returns the accumulated error of the PID controller.
@@ -135,6 +531,70 @@ impl<T: Float + Signed + Copy> PIDController<T> {
     pub fn setpoint(&self) -> T {
         self.setpoint
     }
+
+    /// Sets the setpoint of the PID controller.
+    pub fn set_setpoint(&mut self, setpoint: impl Into<T>) {
+        self.setpoint = setpoint.into();
+    }
+
+    /// Returns the proportional, integral, and derivative gains.
+    pub fn gains(&self) -> (T, T, T) {
+        (self.kp, self.ki, self.kd)
+    }
+
+    /// Sets the proportional, integral, and derivative gains, e.g. to tune a live controller.
+    ///
+    /// In the positional form, the accumulated error is rescaled to the new integral gain so the
+    /// integral term, and with it the output, does not jump. Unlike
+    /// [`PIDControllerBuilder::try_build`], the gains are not validated.
+    pub fn set_gains(&mut self, kp: impl Into<T>, ki: impl Into<T>, kd: impl Into<T>) {
+        let ki = ki.into();
+        if self.form == PIDForm::Positional && self.ki != T::zero() && ki != T::zero() {
+            self.accumulated_error = self.accumulated_error * self.ki / ki;
+        }
+        self.kp = kp.into();
+        self.ki = ki;
+        self.kd = kd.into();
+    }
+
+    /// Returns the limit of the accumulated error, if any.
+    pub fn error_limit(&self) -> Option<T> {
+        self.error_limit
+    }
+
+    /// Sets or removes the limit of the accumulated error, which takes effect on the next
+    /// correction.
+    pub fn set_error_limit(&mut self, error_limit: Option<T>) {
+        self.error_limit = error_limit;
+    }
+
+    /// Returns the limit of the output, if any.
+    pub fn output_limit(&self) -> Option<T> {
+        self.output_limit
+    }
+
+    /// Sets or removes the limit of the output, which takes effect on the next correction.
+    ///
+    /// Without an integral gain there is no anti-windup feedback, so a clamped output leaves the
+    /// accumulated error as it is.
+    pub fn set_output_limit(&mut self, output_limit: Option<T>) {
+        self.output_limit = output_limit;
+    }
+
+    /// Clears the accumulated and previous errors, as if no corrections had been computed.
+    ///
+    /// The gains, limits, mode, and history are kept.
+    pub fn reset(&mut self) {
+        self.accumulated_error = T::zero();
+        self.previous_error = T::zero();
+        self.second_previous_error = T::zero();
+        self.saturation_state = SaturationState::default();
+    }
+
+    /// Sets the clock the correction history is timestamped with.
+    pub(crate) fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+        self.clock = clock;
+    }
 }
 
 /// Builder for creating a `PIDController` instance.
@@ -146,6 +606,8 @@ pub struct PIDControllerBuilder<T> {
     error_bias: T,
     error_limit: Option<T>,
     output_limit: Option<T>,
+    history_capacity: usize,
+    form: PIDForm,
 }
 
 impl<T: Float + Signed + Copy> PIDControllerBuilder<T> {
@@ -159,6 +621,8 @@ impl<T: Float + Signed + Copy> PIDControllerBuilder<T> {
             error_bias: T::one(),
             error_limit: None,
             output_limit: None,
+            history_capacity: 0,
+            form: PIDForm::Positional,
         }
     }
 
@@ -198,6 +662,85 @@ impl<T: Float + Signed + Copy> PIDControllerBuilder<T> {
         self
     }
 
+    /// Sets the number of recent corrections to keep for diagnostics.
+    ///
+    /// A capacity of zero (the default) disables history recording.
+    pub fn history_capacity(mut self, history_capacity: usize) -> Self {
+        self.history_capacity = history_capacity;
+        self
+    }
+
+    /// Sets the algorithm form. Defaults to [`PIDForm::Positional`].
+    pub fn form(mut self, form: PIDForm) -> Self {
+        self.form = form;
+        self
+    }
+
+    /// Validates the configuration, then builds and returns the `PIDController` instance.
+    ///
+    /// Unlike [`build`](Self::build), this rejects configurations that would silently produce a
+    /// misbehaving controller, such as NaN gains or a non-positive output limit.
+    pub fn try_build(self) -> Result<PIDController<T>, PIDControllerError> {
+        self.validate()?;
+        Ok(self.build())
+    }
+
+    /// Checks the builder's parameters for values that make the controller misbehave.
+    fn validate(&self) -> Result<(), PIDControllerError> {
+        let parameters = [
+            ("setpoint", Some(self.setpoint)),
+            ("kp", Some(self.kp)),
+            ("ki", Some(self.ki)),
+            ("kd", Some(self.kd)),
+            ("error_bias", Some(self.error_bias)),
+            ("error_limit", self.error_limit),
+            ("output_limit", self.output_limit),
+        ];
+        for (parameter, value) in parameters {
+            if value.is_some_and(|value| !value.is_finite()) {
+                return Err(PIDControllerError::NonFinite { parameter });
+            }
+        }
+
+        let gains = [self.kp, self.ki, self.kd];
+        let has_positive = gains.iter().any(|gain| *gain > T::zero());
+        let has_negative = gains.iter().any(|gain| *gain < T::zero());
+        if has_positive && has_negative {
+            return Err(PIDControllerError::MixedGainSigns {
+                kp: to_f64(self.kp),
+                ki: to_f64(self.ki),
+                kd: to_f64(self.kd),
+            });
+        }
+
+        if self.error_bias.abs() > T::one() {
+            return Err(PIDControllerError::InvalidErrorBias {
+                error_bias: to_f64(self.error_bias),
+            });
+        }
+
+        if let Some(error_limit) = self.error_limit {
+            if error_limit <= T::zero() {
+                return Err(PIDControllerError::NonPositiveErrorLimit {
+                    error_limit: to_f64(error_limit),
+                });
+            }
+        }
+
+        if let Some(output_limit) = self.output_limit {
+            if output_limit <= T::zero() {
+                return Err(PIDControllerError::NonPositiveOutputLimit {
+                    output_limit: to_f64(output_limit),
+                });
+            }
+            if self.form == PIDForm::Positional && self.ki == T::zero() {
+                return Err(PIDControllerError::OutputLimitWithoutIntegralGain);
+            }
+        }
+
+        Ok(())
+    }
+
     /// Builds and returns the `PIDController` instance.
     pub fn build(self) -> PIDController<T> {
         PIDController {
@@ -210,10 +753,25 @@ impl<T: Float + Signed + Copy> PIDControllerBuilder<T> {
             output_limit: self.output_limit,
             accumulated_error: T::zero(),
             previous_error: T::zero(),
+            history_capacity: self.history_capacity,
+            history: VecDeque::with_capacity(self.history_capacity),
+            last_correction: None,
+            mode: ControllerMode::Auto,
+            manual_output: T::zero(),
+            manual_step_pending: false,
+            saturation_state: SaturationState::default(),
+            form: self.form,
+            second_previous_error: T::zero(),
+            clock: Arc::new(SystemClock),
         }
     }
 }
 
+/// Converts a parameter to `f64` for error reporting.
+fn to_f64<T: Float>(value: T) -> f64 {
+    value.to_f64().unwrap_or(f64::NAN)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -315,4 +873,265 @@ mod tests {
         let pid = create_pid_controller(1.0, 2.0, 3.0, 4.0, 0.5, None, None);
         assert_eq!(pid.setpoint, 1.0);
     }
+
+    #[test]
+    fn test_pid_history_disabled_by_default() {
+        let mut pid = create_pid_controller(1.0, 2.0, 3.0, 4.0, 0.5, None, None);
+        pid.compute_correction(0.5);
+        assert!(pid.history().is_empty());
+    }
+
+    #[test]
+    fn test_pid_history_is_bounded() {
+        let mut pid: PIDController<f64> = PIDControllerBuilder::new(1.0)
+            .kp(2.0)
+            .ki(3.0)
+            .kd(4.0)
+            .history_capacity(2)
+            .build();
+        pid.compute_correction(0.1);
+        pid.compute_correction(0.2);
+        let output = pid.compute_correction(0.3);
+
+        assert_eq!(pid.history().len(), 2);
+        let latest = pid.history().back().unwrap();
+        assert_eq!(latest.signal, 0.3);
+        assert_eq!(latest.error, 1.0 - 0.3);
+        assert_eq!(latest.output, output);
+        assert_eq!(latest.p + latest.i + latest.d, output);
+        assert_eq!(pid.history().front().unwrap().signal, 0.2);
+
+        pid.clear_history();
+        assert!(pid.history().is_empty());
+    }
+
+    #[test]
+    fn test_pid_manual_mode_forces_output() {
+        let mut pid = create_pid_controller(1.0, 2.0, 3.0, 4.0, 0.5, None, None);
+        pid.set_manual(0.25);
+        assert_eq!(pid.mode(), ControllerMode::Manual);
+        assert_eq!(pid.compute_correction(0.5), 0.25);
+        assert_eq!(pid.compute_correction(10.0), 0.25);
+    }
+
+    #[test]
+    fn test_pid_bumpless_switch_to_auto() {
+        let mut pid = create_pid_controller(1.0, 0.5, 0.1, 0.0, 0.0, None, None);
+        for _ in 0..10 {
+            pid.compute_correction(0.0);
+        }
+
+        pid.set_manual(0.2);
+        pid.compute_correction(0.8);
+        pid.set_auto();
+        assert_eq!(pid.mode(), ControllerMode::Auto);
+
+        // With the signal unchanged, the first automatic output continues from the manual output
+        let correction = pid.compute_correction(0.8);
+        assert!((correction - 0.2).abs() < 0.05);
+    }
+
+    #[test]
+    fn test_pid_manual_mode_respects_error_limit() {
+        let mut pid = create_pid_controller(1.0, 0.5, 0.1, 0.0, 0.0, Some(1.0), None);
+        pid.set_manual(100.0);
+        assert_eq!(pid.compute_correction(0.8), 100.0);
+        assert_eq!(pid.accumulated_error(), 1.0);
+        assert!(pid.saturation_state().integral_clamped);
+
+        // Switching back only releases the integral the error limit allows
+        pid.set_auto();
+        let correction = pid.compute_correction(0.8);
+        assert!((correction - (0.5 * 0.2 + 0.1 * 1.0)).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_pid_saturation_state() {
+        let mut pid = create_pid_controller(1.0, 2.0, 3.0, 4.0, 0.5, Some(0.1), Some(0.5));
+        assert!(!pid.saturation_state().is_saturated());
+
+        pid.compute_correction(0.5);
+        let state = pid.saturation_state();
+        assert!(state.output_clamped);
+        assert!(state.integral_clamped);
+
+        let mut pid = create_pid_controller(1.0, 0.1, 0.0, 0.0, 0.0, Some(10.0), Some(5.0));
+        pid.compute_correction(0.5);
+        assert_eq!(pid.saturation_state(), SaturationState::default());
+    }
+
+    #[test]
+    fn test_pid_try_build_accepts_valid_config() {
+        let pid: Result<PIDController<f64>, _> = PIDControllerBuilder::new(10.0)
+            .kp(1.0)
+            .ki(0.1)
+            .kd(0.01)
+            .error_limit(10.0)
+            .output_limit(3.0)
+            .try_build();
+        assert!(pid.is_ok());
+
+        let reverse_acting: Result<PIDController<f64>, _> = PIDControllerBuilder::new(10.0)
+            .kp(-1.0)
+            .ki(-0.1)
+            .try_build();
+        assert!(reverse_acting.is_ok());
+    }
+
+    #[test]
+    fn test_pid_try_build_rejects_invalid_config() {
+        let builder = || PIDControllerBuilder::<f64>::new(10.0).kp(1.0).ki(0.1);
+
+        assert_eq!(
+            builder().kd(f64::NAN).try_build().unwrap_err(),
+            PIDControllerError::NonFinite { parameter: "kd" }
+        );
+        assert!(matches!(
+            builder().kd(-0.1).try_build().unwrap_err(),
+            PIDControllerError::MixedGainSigns { .. }
+        ));
+        assert!(matches!(
+            builder().error_bias(1.5).try_build().unwrap_err(),
+            PIDControllerError::InvalidErrorBias { .. }
+        ));
+        assert!(matches!(
+            builder().error_limit(0.0).try_build().unwrap_err(),
+            PIDControllerError::NonPositiveErrorLimit { .. }
+        ));
+        assert!(matches!(
+            builder().output_limit(-1.0).try_build().unwrap_err(),
+            PIDControllerError::NonPositiveOutputLimit { .. }
+        ));
+        assert_eq!(
+            builder().ki(0.0).output_limit(1.0).try_build().unwrap_err(),
+            PIDControllerError::OutputLimitWithoutIntegralGain
+        );
+    }
+
+    #[test]
+    fn test_pid_velocity_form() {
+        let mut pid: PIDController<f64> = PIDControllerBuilder::new(1.0)
+            .kp(2.0)
+            .ki(0.5)
+            .kd(0.1)
+            .error_bias(0.0)
+            .form(PIDForm::Velocity)
+            .build();
+        assert_eq!(pid.form(), PIDForm::Velocity);
+
+        // e = 0.5, previous errors are zero
+        let first = pid.compute_correction(0.5);
+        assert!((first - (2.0 * 0.5 + 0.5 * 0.5 + 0.1 * 0.5)).abs() < 1e-9);
+
+        // Holding the error constant only leaves the integral increment
+        pid.compute_correction(0.5);
+        let third = pid.compute_correction(0.5);
+        assert!((third - 0.5 * 0.5).abs() < 1e-9);
+        assert_eq!(pid.accumulated_error(), 0.0);
+    }
+
+    #[test]
+    fn test_pid_velocity_form_output_limit() {
+        let mut pid: PIDController<f64> = PIDControllerBuilder::new(10.0)
+            .kp(2.0)
+            .output_limit(1.0)
+            .form(PIDForm::Velocity)
+            .try_build()
+            .unwrap();
+        assert_eq!(pid.compute_correction(0.0), 1.0);
+        assert!(pid.saturation_state().output_clamped);
+    }
+
+    #[test]
+    fn test_pid_velocity_form_manual_mode_holds_output() {
+        let mut pid: PIDController<f64> = PIDControllerBuilder::new(1.0)
+            .kp(2.0)
+            .ki(0.5)
+            .form(PIDForm::Velocity)
+            .build();
+        pid.compute_correction(0.5);
+
+        // The manual change is applied once, and the output then holds instead of ramping
+        pid.set_manual(0.25);
+        let outputs: Vec<f64> = (0..5).map(|_| pid.compute_correction(0.5)).collect();
+        assert_eq!(outputs, vec![0.25, 0.0, 0.0, 0.0, 0.0]);
+
+        pid.set_manual(-0.5);
+        assert_eq!(pid.compute_correction(0.0), -0.5);
+        assert_eq!(pid.compute_correction(1.0), 0.0);
+    }
+
+    #[test]
+    fn test_pid_reset() {
+        let mut pid: PIDController<f64> = PIDControllerBuilder::new(10.0).kp(1.0).ki(0.5).build();
+        pid.compute_correction(4.0);
+        assert!(pid.accumulated_error() != 0.0);
+
+        pid.reset();
+        pid.set_setpoint(20.0);
+        assert_eq!(pid.accumulated_error(), 0.0);
+        assert_eq!(pid.setpoint(), 20.0);
+    }
+
+    #[test]
+    fn test_pid_runtime_tuning() {
+        let mut pid: PIDController<f64> = PIDControllerBuilder::new(10.0)
+            .kp(1.0)
+            .ki(0.5)
+            .error_bias(0.0)
+            .build();
+        pid.compute_correction(6.0);
+        let integral = pid.ki * pid.accumulated_error();
+
+        // The integral term carries over to the new integral gain
+        pid.set_gains(2.0, 0.25, 0.1);
+        assert_eq!(pid.gains(), (2.0, 0.25, 0.1));
+        assert_eq!(pid.ki * pid.accumulated_error(), integral);
+
+        pid.set_error_limit(Some(1.0));
+        pid.set_output_limit(Some(0.5));
+        assert_eq!(pid.compute_correction(6.0), 0.5);
+        assert!(pid.saturation_state().is_saturated());
+
+        // Dropping the integral gain under an output limit leaves the accumulated error finite
+        pid.set_gains(2.0, 0.0, 0.0);
+        assert_eq!(pid.compute_correction(6.0), 0.5);
+        assert!(pid.accumulated_error().is_finite());
+        pid.set_output_limit(None);
+        assert_eq!(pid.output_limit(), None);
+        assert_eq!(pid.compute_correction(6.0), 8.0);
+    }
+
+    #[test]
+    fn test_pid_ignores_non_finite_signals() {
+        let mut pid: PIDController<f64> = PIDControllerBuilder::new(10.0)
+            .kp(1.0)
+            .ki(0.5)
+            .kd(0.1)
+            .history_capacity(4)
+            .build();
+        let correction = pid.compute_correction(6.0);
+        let accumulated_error = pid.accumulated_error();
+
+        for signal in [f64::NAN, f64::INFINITY, f64::NEG_INFINITY] {
+            assert_eq!(pid.compute_correction(signal), 0.0);
+            assert_eq!(
+                pid.try_compute_correction(signal),
+                Err(PIDControllerError::NonFinite {
+                    parameter: "signal"
+                })
+            );
+        }
+        assert_eq!(pid.accumulated_error(), accumulated_error);
+        assert_eq!(pid.history().len(), 1);
+
+        // A finite signal whose terms overflow is rejected without touching the state
+        assert_eq!(
+            pid.try_compute_correction(-f64::MAX),
+            Err(PIDControllerError::NonFiniteCorrection)
+        );
+        assert_eq!(pid.accumulated_error(), accumulated_error);
+        assert_eq!(pid.last_correction().unwrap().output, correction);
+        assert!(pid.compute_correction(6.0).is_finite());
+    }
 }
@@ -0,0 +1,340 @@
+//! Bandwidth and latency probing controller, an alternative to PID control of the target rate.
+//!
+//! A PID controller steers towards a fixed setpoint, which suits downstreams with a known
+//! capacity. When the capacity drifts, e.g. as a shared database gains and loses other clients,
+//! any fixed setpoint is eventually wrong. A [`ProbingController`] instead measures the capacity
+//! the way BBR measures a network path:
+//!
+//! - Time is divided into rounds. In each round the controller records how many requests the
+//!   downstream completed, its delivery rate, and how long they took.
+//! - The capacity estimate is the highest delivery rate of the recent rounds, and the base
+//!   latency is the lowest latency of a longer span of rounds.
+//! - The target rate cycles through pacing gains: one round at 1.25 times the capacity estimate
+//!   probes for more capacity, one round at 0.75 drains the queue the probe may have built, and
+//!   six rounds cruise at the estimate. If the downstream had room, the probe raises the delivery
+//!   rate and with it the estimate.
+//! - Whenever a round's latency exceeds the base latency by more than the tolerance, requests are
+//!   queueing at the downstream, so the controller drains at once instead of probing or cruising.
+//!
+//! The controller sets the rate limiter's fixed target rate, which suspends its PID controller.
+//!
+//! # Example
+//!
+//! ```rust
+//! use std::time::Duration;
+//!
+//! use nenya::probing::ProbingControllerBuilder;
+//! use nenya::RateLimiterBuilder;
+//!
+//! let mut rate_limiter = RateLimiterBuilder::new(50.0).min_rate(1.0).max_rate(500.0).build();
+//! let mut controller = ProbingControllerBuilder::new(50.0)
+//!     .round_duration(Duration::from_millis(200))
+//!     .build();
+//!
+//! // Report every completed request, then apply the target to the limiter
+//! controller.on_delivered(1.0, Duration::from_millis(12));
+//! controller.apply(&mut rate_limiter);
+//! ```
+
+use num_traits::{Float, FromPrimitive, Signed};
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::clock::{Clock, SystemClock, Timestamp};
+use crate::RateLimiter;
+
+/// Pacing gains cycled through, one per round: probe, drain, then cruise.
+const PACING_GAINS: [f64; 8] = [1.25, 0.75, 1.0, 1.0, 1.0, 1.0, 1.0, 1.0];
+
+/// Index of the drain phase in [`PACING_GAINS`].
+const DRAIN_PHASE: usize = 1;
+
+/// Measurements of one round.
+#[derive(Debug, Clone, Copy)]
+struct Round<T> {
+    delivery_rate: T,
+    /// Mean latency of the requests completed in the round, if any were.
+    latency: Option<Duration>,
+}
+
+/// Controls a target rate by probing for capacity and backing off when latency builds up.
+#[derive(Debug)]
+pub struct ProbingController<T> {
+    round_duration: Duration,
+    bandwidth_window: usize,
+    latency_window: usize,
+    latency_tolerance: f64,
+    clock: Arc<dyn Clock>,
+    round_start: Timestamp,
+    round_delivered: T,
+    round_latency: Duration,
+    round_completions: u32,
+    rounds: VecDeque<Round<T>>,
+    phase: usize,
+    target_rate: T,
+}
+
+impl<T: Float + Signed + FromPrimitive + Copy> ProbingController<T> {
+    /// Records a completed request of `cost` that took `latency`, closing the current round if
+    /// it is over.
+    ///
+    /// Returns the new target rate when a round was closed.
+    pub fn on_delivered(&mut self, cost: impl Into<T>, latency: Duration) -> Option<T> {
+        let closed = self.update();
+        self.round_delivered = self.round_delivered + cost.into();
+        self.round_latency += latency;
+        self.round_completions += 1;
+        closed
+    }
+
+    /// Closes the current round if it is over, even if no request completed in it.
+    ///
+    /// Returns the new target rate when a round was closed.
+    pub fn update(&mut self) -> Option<T> {
+        let now = self.clock.now();
+        let elapsed = now.duration_since(self.round_start);
+        if elapsed < self.round_duration {
+            return None;
+        }
+
+        let round = Round {
+            delivery_rate: self.round_delivered / T::from_f64(elapsed.as_secs_f64()).unwrap(),
+            latency: (self.round_completions > 0)
+                .then(|| self.round_latency / self.round_completions),
+        };
+        self.round_start = now;
+        self.round_delivered = T::zero();
+        self.round_latency = Duration::ZERO;
+        self.round_completions = 0;
+
+        if self.rounds.len() == self.bandwidth_window.max(self.latency_window) {
+            self.rounds.pop_back();
+        }
+        self.rounds.push_front(round);
+
+        let queueing = match (round.latency, self.min_latency()) {
+            (Some(latency), Some(min_latency)) => {
+                latency.as_secs_f64() > min_latency.as_secs_f64() * self.latency_tolerance
+            }
+            _ => false,
+        };
+        self.phase = if queueing && self.phase != DRAIN_PHASE {
+            DRAIN_PHASE
+        } else {
+            (self.phase + 1) % PACING_GAINS.len()
+        };
+
+        let bandwidth = self.bandwidth_estimate();
+        if bandwidth > T::zero() {
+            self.target_rate = bandwidth * T::from_f64(self.pacing_gain()).unwrap();
+        }
+        Some(self.target_rate)
+    }
+
+    /// Sets the fixed target rate of `rate_limiter` to the controller's target rate, clamped to
+    /// the limiter's rate bounds.
+    pub fn apply(&self, rate_limiter: &mut RateLimiter<T>) {
+        let target_rate = num_traits::clamp(
+            self.target_rate,
+            rate_limiter.min_rate(),
+            rate_limiter.max_rate(),
+        );
+        rate_limiter.set_fixed_target_rate(Some(target_rate));
+    }
+
+    /// Returns the target rate for the current round.
+    pub fn target_rate(&self) -> T {
+        self.target_rate
+    }
+
+    /// Returns the estimated capacity of the downstream: the highest delivery rate of the recent
+    /// rounds.
+    pub fn bandwidth_estimate(&self) -> T {
+        self.rounds
+            .iter()
+            .take(self.bandwidth_window)
+            .fold(T::zero(), |max, round| max.max(round.delivery_rate))
+    }
+
+    /// Returns the base latency of the downstream: the lowest latency of the recent rounds.
+    pub fn min_latency(&self) -> Option<Duration> {
+        self.rounds
+            .iter()
+            .take(self.latency_window)
+            .filter_map(|round| round.latency)
+            .min()
+    }
+
+    /// Returns the pacing gain of the current round.
+    pub fn pacing_gain(&self) -> f64 {
+        PACING_GAINS[self.phase]
+    }
+}
+
+/// Builder for creating a `ProbingController` instance.
+pub struct ProbingControllerBuilder<T> {
+    initial_rate: T,
+    round_duration: Duration,
+    bandwidth_window: usize,
+    latency_window: usize,
+    latency_tolerance: f64,
+    clock: Option<Arc<dyn Clock>>,
+}
+
+impl<T: Float + Signed + FromPrimitive + Copy> ProbingControllerBuilder<T> {
+    /// Creates a new `ProbingControllerBuilder` starting at `initial_rate` until the first
+    /// capacity estimate.
+    ///
+    /// By default rounds last 100 ms, capacity is estimated over 10 rounds, base latency over
+    /// 100 rounds, and latencies 25% above the base latency count as queueing.
+    pub fn new(initial_rate: impl Into<T>) -> Self {
+        ProbingControllerBuilder {
+            initial_rate: initial_rate.into(),
+            round_duration: Duration::from_millis(100),
+            bandwidth_window: 10,
+            latency_window: 100,
+            latency_tolerance: 1.25,
+            clock: None,
+        }
+    }
+
+    /// Sets the duration of a round.
+    ///
+    /// Rounds should be long enough to complete several requests.
+    pub fn round_duration(mut self, round_duration: Duration) -> Self {
+        self.round_duration = round_duration;
+        self
+    }
+
+    /// Sets the number of rounds the capacity is estimated over.
+    ///
+    /// A lower capacity is only picked up once the higher delivery rates have left the window.
+    pub fn bandwidth_window(mut self, rounds: usize) -> Self {
+        self.bandwidth_window = rounds.max(1);
+        self
+    }
+
+    /// Sets the number of rounds the base latency is estimated over.
+    pub fn latency_window(mut self, rounds: usize) -> Self {
+        self.latency_window = rounds.max(1);
+        self
+    }
+
+    /// Sets the factor of the base latency above which requests are considered to be queueing.
+    pub fn latency_tolerance(mut self, latency_tolerance: f64) -> Self {
+        self.latency_tolerance = latency_tolerance;
+        self
+    }
+
+    /// Sets the clock rounds are timed with.
+    pub fn clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = Some(clock);
+        self
+    }
+
+    /// Builds and returns the `ProbingController` instance.
+    pub fn build(self) -> ProbingController<T> {
+        let clock = self.clock.unwrap_or_else(|| Arc::new(SystemClock));
+        ProbingController {
+            round_duration: self.round_duration,
+            bandwidth_window: self.bandwidth_window,
+            latency_window: self.latency_window,
+            latency_tolerance: self.latency_tolerance,
+            round_start: clock.now(),
+            clock,
+            round_delivered: T::zero(),
+            round_latency: Duration::ZERO,
+            round_completions: 0,
+            rounds: VecDeque::new(),
+            phase: PACING_GAINS.len() - 1,
+            target_rate: self.initial_rate,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::ManualClock;
+
+    /// Downstream serving `capacity` requests per second, queueing the excess.
+    struct Downstream {
+        capacity: f64,
+        queue: f64,
+    }
+
+    impl Downstream {
+        /// Offers requests at `rate` for `duration`, returning how many completed and their
+        /// latency.
+        fn serve(&mut self, rate: f64, duration: Duration) -> (f64, Duration) {
+            let secs = duration.as_secs_f64();
+            let backlog = self.queue + rate * secs;
+            let delivered = backlog.min(self.capacity * secs);
+            self.queue = backlog - delivered;
+            let latency =
+                Duration::from_millis(10) + Duration::from_secs_f64(self.queue / self.capacity);
+            (delivered, latency)
+        }
+    }
+
+    fn run(
+        controller: &mut ProbingController<f64>,
+        clock: &ManualClock,
+        downstream: &mut Downstream,
+        rounds: usize,
+    ) {
+        let step = Duration::from_millis(10);
+        for _ in 0..rounds * 10 {
+            let (delivered, latency) = downstream.serve(controller.target_rate(), step);
+            clock.advance(step);
+            controller.on_delivered(delivered, latency);
+        }
+    }
+
+    #[test]
+    fn test_probes_up_to_capacity_and_follows_it_down() {
+        let clock = ManualClock::new();
+        let mut controller = ProbingControllerBuilder::new(20.0)
+            .clock(Arc::new(clock.clone()))
+            .build();
+        let mut downstream = Downstream {
+            capacity: 100.0,
+            queue: 0.0,
+        };
+
+        run(&mut controller, &clock, &mut downstream, 200);
+        let bandwidth = controller.bandwidth_estimate();
+        assert!((90.0..=110.0).contains(&bandwidth), "{bandwidth}");
+        assert_eq!(controller.min_latency(), Some(Duration::from_millis(10)));
+
+        downstream.capacity = 60.0;
+        run(&mut controller, &clock, &mut downstream, 100);
+        let bandwidth = controller.bandwidth_estimate();
+        assert!((54.0..=66.0).contains(&bandwidth), "{bandwidth}");
+        assert!(downstream.queue < 10.0);
+    }
+
+    #[test]
+    fn test_applies_target_within_rate_bounds() {
+        let clock = ManualClock::new();
+        let mut controller = ProbingControllerBuilder::new(500.0)
+            .clock(Arc::new(clock.clone()))
+            .build();
+        let mut rate_limiter = crate::RateLimiterBuilder::new(50.0)
+            .min_rate(1.0)
+            .max_rate(100.0)
+            .build();
+
+        assert_eq!(controller.on_delivered(1.0, Duration::from_millis(5)), None);
+        controller.apply(&mut rate_limiter);
+        assert_eq!(rate_limiter.target_rate(), 100.0);
+
+        clock.advance(Duration::from_millis(100));
+        // The first round delivered 10 requests per second and probes above that
+        assert_eq!(controller.update(), Some(12.5));
+        assert_eq!(controller.pacing_gain(), 1.25);
+        controller.apply(&mut rate_limiter);
+        assert_eq!(rate_limiter.target_rate(), 12.5);
+    }
+}
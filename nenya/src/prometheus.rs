@@ -0,0 +1,281 @@
+//! Export of rate limiter state to a [Prometheus](https://docs.rs/prometheus) registry, for
+//! applications embedding the limiters rather than running the sentinel.
+//!
+//! A [`LimiterCollector`] reads the limiters added to it every time the registry is scraped, so
+//! the exported values are always current without the application updating any metrics. It
+//! exports, labelled by the name each limiter was added under:
+//!
+//! - `nenya_target_rate`, `nenya_request_rate`, and `nenya_accepted_request_rate`
+//! - `nenya_setpoint` and `nenya_pid_accumulated_error`
+//! - `nenya_pid_term`, the P, I, and D terms and the output of the last correction, labelled by
+//!   `term`. Only exported for controllers that record their history, see
+//!   [`PIDControllerBuilder::history_capacity`](crate::pid_controller::PIDControllerBuilder::history_capacity).
+//! - `nenya_key_accepted_request_rate` and `nenya_key_remaining_capacity` for each key of a
+//!   keyed limiter, labelled by `key`. Keys are exported as they are, so only add keyed limiters
+//!   with a bounded number of keys.
+//!
+//! # Example
+//!
+//! ```rust
+//! use std::sync::{Arc, Mutex};
+//!
+//! use nenya::prometheus::LimiterCollector;
+//! use nenya::RateLimiterBuilder;
+//! use prometheus::Registry;
+//!
+//! let limiter = Arc::new(Mutex::new(RateLimiterBuilder::new(10.0).build()));
+//! let collector = LimiterCollector::new();
+//! collector.add_limiter("checkout", limiter.clone());
+//!
+//! let registry = Registry::new();
+//! registry.register(Box::new(collector)).unwrap();
+//! assert!(!registry.gather().is_empty());
+//! ```
+
+use num_traits::{Float, FromPrimitive, Signed};
+use std::sync::{Arc, Mutex};
+
+use prometheus::core::{Collector, Desc};
+use prometheus::proto::MetricFamily;
+use prometheus::{GaugeVec, Opts};
+
+use crate::keyed::KeyedRateLimiter;
+use crate::RateLimiter;
+
+/// Prometheus collector reporting the state of rate limiters at scrape time.
+///
+/// Clones share the same limiters, so limiters can still be added after a clone was registered.
+#[derive(Clone)]
+pub struct LimiterCollector<T> {
+    inner: Arc<CollectorInner<T>>,
+}
+
+type SharedLimiter<T> = Arc<Mutex<RateLimiter<T>>>;
+type SharedKeyedLimiter<T> = Arc<Mutex<KeyedRateLimiter<String, T>>>;
+
+struct CollectorInner<T> {
+    limiters: Mutex<Vec<(String, SharedLimiter<T>)>>,
+    keyed_limiters: Mutex<Vec<(String, SharedKeyedLimiter<T>)>>,
+    target_rate: GaugeVec,
+    request_rate: GaugeVec,
+    accepted_request_rate: GaugeVec,
+    setpoint: GaugeVec,
+    accumulated_error: GaugeVec,
+    pid_term: GaugeVec,
+    key_accepted_request_rate: GaugeVec,
+    key_remaining_capacity: GaugeVec,
+}
+
+fn gauge_vec(name: &str, help: &str, labels: &[&str]) -> GaugeVec {
+    GaugeVec::new(Opts::new(name, help), labels).expect("metric options are valid")
+}
+
+impl<T: Float + Signed + FromPrimitive + Copy> LimiterCollector<T> {
+    /// Creates a collector without any limiters.
+    pub fn new() -> Self {
+        let limiter = &["limiter"];
+        let key = &["limiter", "key"];
+        LimiterCollector {
+            inner: Arc::new(CollectorInner {
+                limiters: Mutex::new(Vec::new()),
+                keyed_limiters: Mutex::new(Vec::new()),
+                target_rate: gauge_vec("nenya_target_rate", "Target request rate", limiter),
+                request_rate: gauge_vec("nenya_request_rate", "Observed request rate", limiter),
+                accepted_request_rate: gauge_vec(
+                    "nenya_accepted_request_rate",
+                    "Accepted request rate",
+                    limiter,
+                ),
+                setpoint: gauge_vec("nenya_setpoint", "PID controller setpoint", limiter),
+                accumulated_error: gauge_vec(
+                    "nenya_pid_accumulated_error",
+                    "Accumulated error of the PID controller",
+                    limiter,
+                ),
+                pid_term: gauge_vec(
+                    "nenya_pid_term",
+                    "Terms and output of the last PID correction",
+                    &["limiter", "term"],
+                ),
+                key_accepted_request_rate: gauge_vec(
+                    "nenya_key_accepted_request_rate",
+                    "Accepted request rate of a key",
+                    key,
+                ),
+                key_remaining_capacity: gauge_vec(
+                    "nenya_key_remaining_capacity",
+                    "Request rate a key has left",
+                    key,
+                ),
+            }),
+        }
+    }
+
+    /// Reports `limiter` labelled with `limiter = name`.
+    pub fn add_limiter(&self, name: impl Into<String>, limiter: SharedLimiter<T>) {
+        self.inner
+            .limiters
+            .lock()
+            .unwrap()
+            .push((name.into(), limiter));
+    }
+
+    /// Reports every key of `limiter` labelled with `limiter = name` and the key.
+    pub fn add_keyed_limiter(&self, name: impl Into<String>, limiter: SharedKeyedLimiter<T>) {
+        self.inner
+            .keyed_limiters
+            .lock()
+            .unwrap()
+            .push((name.into(), limiter));
+    }
+}
+
+impl<T: Float + Signed + FromPrimitive + Copy> Default for LimiterCollector<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> CollectorInner<T> {
+    fn gauges(&self) -> [&GaugeVec; 8] {
+        [
+            &self.target_rate,
+            &self.request_rate,
+            &self.accepted_request_rate,
+            &self.setpoint,
+            &self.accumulated_error,
+            &self.pid_term,
+            &self.key_accepted_request_rate,
+            &self.key_remaining_capacity,
+        ]
+    }
+}
+
+impl<T: Float + Signed + FromPrimitive + Copy> CollectorInner<T> {
+    /// Sets the gauges from the current state of the limiters.
+    fn update(&self) {
+        for gauge in self.gauges() {
+            gauge.reset();
+        }
+        let value = |value: T| value.to_f64().unwrap_or(f64::NAN);
+
+        for (name, limiter) in self.limiters.lock().unwrap().iter() {
+            let limiter = limiter.lock().unwrap();
+            let labels = &[name.as_str()];
+            self.target_rate
+                .with_label_values(labels)
+                .set(value(limiter.target_rate()));
+            self.request_rate
+                .with_label_values(labels)
+                .set(value(limiter.request_rate()));
+            self.accepted_request_rate
+                .with_label_values(labels)
+                .set(value(limiter.accepted_request_rate()));
+
+            let pid_controller = limiter.pid_controller();
+            self.setpoint
+                .with_label_values(labels)
+                .set(value(pid_controller.setpoint()));
+            self.accumulated_error
+                .with_label_values(labels)
+                .set(value(pid_controller.accumulated_error()));
+            if let Some(correction) = pid_controller.history().back() {
+                let terms = [
+                    ("p", correction.p),
+                    ("i", correction.i),
+                    ("d", correction.d),
+                    ("output", correction.output),
+                ];
+                for (term, term_value) in terms {
+                    self.pid_term
+                        .with_label_values(&[name, term])
+                        .set(value(term_value));
+                }
+            }
+        }
+
+        for (name, limiter) in self.keyed_limiters.lock().unwrap().iter() {
+            let limiter = limiter.lock().unwrap();
+            for (key, key_limiter) in limiter.iter() {
+                let labels = &[name.as_str(), key.as_str()];
+                self.key_accepted_request_rate
+                    .with_label_values(labels)
+                    .set(value(key_limiter.accepted_request_rate()));
+                self.key_remaining_capacity
+                    .with_label_values(labels)
+                    .set(value(key_limiter.remaining_capacity()));
+            }
+        }
+    }
+}
+
+impl<T> Collector for LimiterCollector<T>
+where
+    T: Float + Signed + FromPrimitive + Copy + Send + 'static,
+{
+    fn desc(&self) -> Vec<&Desc> {
+        self.inner
+            .gauges()
+            .into_iter()
+            .flat_map(|gauge| gauge.desc())
+            .collect()
+    }
+
+    fn collect(&self) -> Vec<MetricFamily> {
+        self.inner.update();
+        self.inner
+            .gauges()
+            .into_iter()
+            .flat_map(|gauge| gauge.collect())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pid_controller::PIDControllerBuilder;
+    use crate::RateLimiterBuilder;
+    use prometheus::Registry;
+    use std::time::Duration;
+
+    #[test]
+    fn test_exports_limiter_and_key_state() {
+        let pid_controller = PIDControllerBuilder::new(10.0)
+            .kp(0.5)
+            .history_capacity(4)
+            .build();
+        let limiter = RateLimiterBuilder::new(10.0)
+            .pid_controller(pid_controller)
+            .update_interval(Duration::ZERO)
+            .build();
+        let limiter = Arc::new(Mutex::new(limiter));
+        let keyed = Arc::new(Mutex::new(KeyedRateLimiter::new(5.0)));
+        let collector = LimiterCollector::new();
+        collector.add_limiter("checkout", limiter.clone());
+        collector.add_keyed_limiter("callers", keyed.clone());
+
+        let registry = Registry::new();
+        registry.register(Box::new(collector)).unwrap();
+        limiter.lock().unwrap().should_throttle();
+        std::thread::sleep(Duration::from_millis(1));
+        limiter.lock().unwrap().should_throttle();
+        keyed.lock().unwrap().should_throttle(&"search".to_string());
+
+        let families = registry.gather();
+        let family = |name: &str| {
+            families
+                .iter()
+                .find(|family| family.get_name() == name)
+                .unwrap()
+        };
+        let target = family("nenya_target_rate").get_metric();
+        assert_eq!(target.len(), 1);
+        assert_eq!(target[0].get_label()[0].get_value(), "checkout");
+        assert_eq!(family("nenya_pid_term").get_metric().len(), 4);
+        // Labels are sorted by name
+        let key = &family("nenya_key_remaining_capacity").get_metric()[0];
+        assert_eq!(key.get_label()[0].get_name(), "key");
+        assert_eq!(key.get_label()[0].get_value(), "search");
+    }
+}
@@ -0,0 +1,152 @@
+//! Pacing of workers pulling messages from a queue, such as SQS.
+//!
+//! A [`QueuePacer`] decides how many messages a worker asks for in its next receive call and how
+//! long it waits before making it, so the worker takes in messages at the rate limiter's target
+//! rate instead of as fast as the queue hands them out. Messages can weigh differently, e.g. by
+//! the work they trigger; the pacer tracks their average cost to turn the remaining capacity
+//! into a number of messages.
+//!
+//! # Example
+//!
+//! ```rust
+//! use nenya::queue::QueuePacer;
+//! use nenya::RateLimiterBuilder;
+//!
+//! // SQS receives at most 10 messages per call
+//! let mut pacer = QueuePacer::new(RateLimiterBuilder::new(20.0).build(), 10);
+//! let plan = pacer.next_receive();
+//! // Sleep for plan.wait, then receive up to plan.max_messages
+//! # let received = vec![1.0; plan.max_messages];
+//! for cost in received {
+//!     if pacer.record(cost) {
+//!         // Over the target rate: release the message back to the queue
+//!     }
+//! }
+//! ```
+
+use num_traits::{Float, FromPrimitive, Signed};
+use std::time::Duration;
+
+use crate::RateLimiter;
+
+/// Weight of the latest message in the average message cost.
+const COST_SMOOTHING: f64 = 0.1;
+
+/// How a worker should make its next receive call.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReceivePlan {
+    /// Largest number of messages to ask for.
+    pub max_messages: usize,
+    /// How long to wait before receiving.
+    pub wait: Duration,
+}
+
+/// Derives receive batch sizes and polling intervals from a rate limiter.
+#[derive(Debug)]
+pub struct QueuePacer<T> {
+    rate_limiter: RateLimiter<T>,
+    max_batch_size: usize,
+    /// Average cost of the messages received so far.
+    average_cost: T,
+}
+
+impl<T: Float + Signed + FromPrimitive + Copy> QueuePacer<T> {
+    /// Creates a pacer receiving up to `max_batch_size` messages per call at the target rate of
+    /// `rate_limiter`.
+    pub fn new(rate_limiter: RateLimiter<T>, max_batch_size: usize) -> Self {
+        QueuePacer {
+            rate_limiter,
+            max_batch_size: max_batch_size.max(1),
+            average_cost: T::one(),
+        }
+    }
+
+    /// Returns the pacer's rate limiter.
+    pub fn rate_limiter(&self) -> &RateLimiter<T> {
+        &self.rate_limiter
+    }
+
+    /// Returns the average cost of the messages received so far.
+    pub fn average_cost(&self) -> T {
+        self.average_cost
+    }
+
+    /// Returns how the next receive call should be made.
+    ///
+    /// While the limiter has room, the batch holds as many average messages as fit in the
+    /// remaining capacity and is received right away. Otherwise the worker waits until about one
+    /// more message fits.
+    pub fn next_receive(&self) -> ReceivePlan {
+        let capacity = self.rate_limiter.remaining_capacity();
+        let fitting = (capacity / self.average_cost)
+            .floor()
+            .to_usize()
+            .unwrap_or(0);
+        if fitting > 0 {
+            return ReceivePlan {
+                max_messages: fitting.min(self.max_batch_size),
+                wait: Duration::ZERO,
+            };
+        }
+
+        let target_rate = self.rate_limiter.target_rate();
+        let per_message = if target_rate > T::zero() {
+            (self.average_cost / target_rate).to_f64().unwrap_or(1.0)
+        } else {
+            1.0
+        };
+        ReceivePlan {
+            max_messages: 1,
+            wait: self
+                .rate_limiter
+                .retry_after()
+                .max(Duration::from_secs_f64(per_message)),
+        }
+    }
+
+    /// Counts a received message of `cost` units against the target rate.
+    ///
+    /// Returns `true` if the message goes over the target rate, in which case the worker should
+    /// release it back to the queue, e.g. by resetting its visibility timeout, rather than
+    /// process it.
+    pub fn record(&mut self, cost: impl Into<T>) -> bool {
+        let cost = cost.into();
+        let smoothing = T::from_f64(COST_SMOOTHING).unwrap();
+        self.average_cost = self.average_cost + smoothing * (cost - self.average_cost);
+        self.rate_limiter.should_throttle_weighted(cost)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::RateLimiterBuilder;
+
+    #[test]
+    fn test_batches_follow_remaining_capacity() {
+        let mut pacer = QueuePacer::new(RateLimiterBuilder::new(20.0).build(), 10);
+        assert_eq!(
+            pacer.next_receive(),
+            ReceivePlan {
+                max_messages: 10,
+                wait: Duration::ZERO
+            }
+        );
+
+        let released = (0..30).filter(|_| pacer.record(1.0)).count();
+        assert!(released > 0);
+        let plan = pacer.next_receive();
+        assert_eq!(plan.max_messages, 1);
+        assert!(plan.wait >= Duration::from_millis(50));
+    }
+
+    #[test]
+    fn test_weighted_messages_shrink_batches() {
+        let mut light = QueuePacer::new(RateLimiterBuilder::new(100.0).build(), 100);
+        let mut heavy = QueuePacer::new(RateLimiterBuilder::new(100.0).build(), 100);
+        assert!(!light.record(1.0));
+        assert!(!heavy.record(10.0));
+        assert!(heavy.average_cost() > light.average_cost());
+        assert!(heavy.next_receive().max_messages < light.next_receive().max_messages);
+    }
+}
@@ -0,0 +1,376 @@
+//! Analytic queueing models to cross-check simulations against.
+//!
+//! A limiter holding the rate admitted to a downstream at `λ` keeps the downstream's queue, and
+//! the latency of its requests, at the values queueing theory predicts for that rate. [`MMc`] is
+//! the steady state of the M/M/c queue: Poisson arrivals at rate `λ`, served in order by `c`
+//! servers whose service times are exponential with rate `μ`, or M/M/1 with a single server.
+//!
+//! A [`QueueSimulator`] given to [`SimulationBuilder::queue`] serves the requests a simulated
+//! limiter admits the same way, and [`QueueValidation`] compares what it measured with a model:
+//! at the measured arrival rate to check the simulation, or at the limiter's target rate to
+//! check that the limiter holds it. Below the target rate the limiter admits Poisson traffic
+//! untouched, so the two agree within sampling error; above it, the limiter spreads out the
+//! requests it admits, so the queue should stay below the model's.
+//!
+//! # Example
+//!
+//! ```rust
+//! use std::time::Duration;
+//!
+//! use nenya::queueing::{QueueSimulator, QueueValidation, MMc};
+//! use nenya::simulation::{PoissonArrivals, SimulationBuilder};
+//! use nenya::RateLimiterBuilder;
+//!
+//! // 40 requests per second against a limiter targeting 50 and a server completing 60
+//! let mut simulation =
+//!     SimulationBuilder::from_arrivals(RateLimiterBuilder::new(50.0), PoissonArrivals::new(40.0, 7))
+//!         .queue(QueueSimulator::new(60.0, 1, 7))
+//!         .build();
+//! simulation.run(Duration::from_secs(3600));
+//!
+//! let measured = simulation.queue().unwrap().stats();
+//! let validation = QueueValidation::new(MMc::mm1(measured.arrival_rate, 60.0), measured);
+//! println!("{}", validation);
+//! assert!(validation.holds(0.15));
+//! ```
+//!
+//! [`SimulationBuilder::queue`]: crate::simulation::SimulationBuilder::queue
+
+use std::fmt;
+use std::time::Duration;
+
+use crate::simulation::Rng;
+
+/// Steady state of an M/M/c queue.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MMc {
+    arrival_rate: f64,
+    service_rate: f64,
+    servers: usize,
+}
+
+impl MMc {
+    /// Creates the model of requests arriving at `arrival_rate` per second, served by `servers`
+    /// servers completing `service_rate` requests per second each.
+    pub fn new(arrival_rate: f64, service_rate: f64, servers: usize) -> Self {
+        MMc {
+            arrival_rate,
+            service_rate,
+            servers: servers.max(1),
+        }
+    }
+
+    /// Creates the model of a single server, M/M/1.
+    pub fn mm1(arrival_rate: f64, service_rate: f64) -> Self {
+        Self::new(arrival_rate, service_rate, 1)
+    }
+
+    /// Returns the fraction of time each server is busy, `λ / cμ`.
+    pub fn utilization(&self) -> f64 {
+        self.arrival_rate / (self.servers as f64 * self.service_rate)
+    }
+
+    /// Returns whether the servers keep up with the arrivals, so the queue has a steady state.
+    pub fn is_stable(&self) -> bool {
+        self.utilization() < 1.0
+    }
+
+    /// Returns the probability that a request has to wait for a server, by the Erlang C
+    /// formula, or `None` if the queue is unstable.
+    pub fn wait_probability(&self) -> Option<f64> {
+        if !self.is_stable() {
+            return None;
+        }
+        let offered = self.arrival_rate / self.service_rate;
+        // Terms of the sum of offered^k / k! up to k = c, built one from the other
+        let mut term = 1.0;
+        let mut idle = 0.0;
+        for k in 0..self.servers {
+            idle += term;
+            term *= offered / (k + 1) as f64;
+        }
+        let waiting = term / (1.0 - self.utilization());
+        Some(waiting / (idle + waiting))
+    }
+
+    /// Returns the mean number of requests waiting for a server, or `None` if the queue is
+    /// unstable.
+    pub fn mean_queue_length(&self) -> Option<f64> {
+        let utilization = self.utilization();
+        Some(self.wait_probability()? * utilization / (1.0 - utilization))
+    }
+
+    /// Returns the mean number of requests waiting or being served, or `None` if the queue is
+    /// unstable.
+    pub fn mean_in_system(&self) -> Option<f64> {
+        Some(self.mean_queue_length()? + self.arrival_rate / self.service_rate)
+    }
+
+    /// Returns the mean time requests wait for a server, or `None` if the queue is unstable.
+    pub fn mean_wait(&self) -> Option<Duration> {
+        if self.arrival_rate <= 0.0 {
+            return self.is_stable().then_some(Duration::ZERO);
+        }
+        // Little's law
+        Some(Duration::from_secs_f64(
+            self.mean_queue_length()? / self.arrival_rate,
+        ))
+    }
+
+    /// Returns the mean time from the arrival of requests to their completion, or `None` if the
+    /// queue is unstable.
+    pub fn mean_latency(&self) -> Option<Duration> {
+        Some(self.mean_wait()? + Duration::from_secs_f64(1.0 / self.service_rate))
+    }
+}
+
+/// Metrics a [`QueueSimulator`] measured.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct QueueStats {
+    /// Number of requests measured.
+    pub served: u64,
+    /// Requests that arrived per second.
+    pub arrival_rate: f64,
+    /// Fraction of time each server was busy.
+    pub utilization: f64,
+    /// Time-averaged number of requests waiting for a server.
+    pub mean_queue_length: f64,
+    /// Time-averaged number of requests waiting or being served.
+    pub mean_in_system: f64,
+    /// Mean time requests waited for a server.
+    pub mean_wait: Duration,
+    /// Mean time from the arrival of requests to their completion.
+    pub mean_latency: Duration,
+}
+
+/// Servers with exponential service times, serving the requests given to them in order of
+/// arrival. Service times are drawn from a seeded generator, so the same seed and arrivals give
+/// the same measurements.
+#[derive(Debug, Clone)]
+pub struct QueueSimulator {
+    service_rate: f64,
+    /// Time, in seconds, from which each server is free.
+    free_at: Vec<f64>,
+    rng: Rng,
+    warm_up: Duration,
+    /// Arrival of the first measured request, in seconds.
+    first_arrival: Option<f64>,
+    last_arrival: f64,
+    served: u64,
+    total_wait: f64,
+    total_service: f64,
+}
+
+impl QueueSimulator {
+    /// Creates `servers` servers completing `service_rate` requests per second each, drawing
+    /// service times with `seed`.
+    pub fn new(service_rate: f64, servers: usize, seed: u64) -> Self {
+        QueueSimulator {
+            service_rate,
+            free_at: vec![0.0; servers.max(1)],
+            rng: Rng::new(seed),
+            warm_up: Duration::ZERO,
+            first_arrival: None,
+            last_arrival: 0.0,
+            served: 0,
+            total_wait: 0.0,
+            total_service: 0.0,
+        }
+    }
+
+    /// Serves requests arriving before `warm_up` without measuring them, e.g. while the limiter
+    /// settles on its target rate.
+    pub fn warm_up(mut self, warm_up: Duration) -> Self {
+        self.warm_up = warm_up;
+        self
+    }
+
+    /// Returns the model of these servers with requests arriving at `arrival_rate`.
+    pub fn model(&self, arrival_rate: f64) -> MMc {
+        MMc::new(arrival_rate, self.service_rate, self.free_at.len())
+    }
+
+    /// Serves a request arriving at `arrival`, measured from the start of the simulation.
+    /// Requests must be given in order of arrival.
+    pub fn arrive(&mut self, arrival: Duration) {
+        let arrival_secs = arrival.as_secs_f64();
+        let service = self.rng.exponential(self.service_rate);
+        let server = self
+            .free_at
+            .iter_mut()
+            .min_by(|a, b| a.total_cmp(b))
+            .expect("at least one server");
+        let start = server.max(arrival_secs);
+        *server = start + service;
+        if arrival < self.warm_up {
+            return;
+        }
+        self.first_arrival.get_or_insert(arrival_secs);
+        self.last_arrival = arrival_secs;
+        self.served += 1;
+        self.total_wait += start - arrival_secs;
+        self.total_service += service;
+    }
+
+    /// Returns the metrics of the requests measured so far, over the time from the first to the
+    /// last of them.
+    pub fn stats(&self) -> QueueStats {
+        let Some(first_arrival) = self.first_arrival else {
+            return QueueStats::default();
+        };
+        let period = self.last_arrival - first_arrival;
+        if period <= 0.0 {
+            return QueueStats::default();
+        }
+        let served = self.served as f64;
+        // Every request adds its wait to the integral of the queue length over time
+        QueueStats {
+            served: self.served,
+            arrival_rate: served / period,
+            utilization: self.total_service / (self.free_at.len() as f64 * period),
+            mean_queue_length: self.total_wait / period,
+            mean_in_system: (self.total_wait + self.total_service) / period,
+            mean_wait: Duration::from_secs_f64(self.total_wait / served),
+            mean_latency: Duration::from_secs_f64((self.total_wait + self.total_service) / served),
+        }
+    }
+}
+
+/// Measured queue metrics next to the ones a model predicts.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct QueueValidation {
+    /// Model the measurements are checked against.
+    pub model: MMc,
+    /// Metrics the simulator measured.
+    pub measured: QueueStats,
+}
+
+impl QueueValidation {
+    /// Compares `measured` with `model`.
+    pub fn new(model: MMc, measured: QueueStats) -> Self {
+        QueueValidation { model, measured }
+    }
+
+    /// Returns each metric with its expected and measured values, times in seconds. Expected
+    /// values are infinite if the model is unstable.
+    pub fn metrics(&self) -> [(&'static str, f64, f64); 5] {
+        let seconds =
+            |time: Option<Duration>| time.map_or(f64::INFINITY, |time| time.as_secs_f64());
+        let model = &self.model;
+        let measured = &self.measured;
+        [
+            ("utilization", model.utilization(), measured.utilization),
+            (
+                "mean_queue_length",
+                model.mean_queue_length().unwrap_or(f64::INFINITY),
+                measured.mean_queue_length,
+            ),
+            (
+                "mean_in_system",
+                model.mean_in_system().unwrap_or(f64::INFINITY),
+                measured.mean_in_system,
+            ),
+            (
+                "mean_wait",
+                seconds(model.mean_wait()),
+                measured.mean_wait.as_secs_f64(),
+            ),
+            (
+                "mean_latency",
+                seconds(model.mean_latency()),
+                measured.mean_latency.as_secs_f64(),
+            ),
+        ]
+    }
+
+    /// Returns whether every measured metric is within `tolerance` of the expected one, relative
+    /// to the expected value, or to the measured one for metrics the model expects at zero.
+    pub fn holds(&self, tolerance: f64) -> bool {
+        self.metrics().iter().all(|&(_, expected, measured)| {
+            (measured - expected).abs() <= tolerance * expected.abs().max(measured.abs())
+        })
+    }
+}
+
+impl fmt::Display for QueueValidation {
+    /// Formats the expected and measured metrics as a table, with the difference of each.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:<18} {:>10} {:>10}  difference",
+            "metric", "expected", "measured"
+        )?;
+        for (name, expected, measured) in self.metrics() {
+            let difference = if expected.is_finite() && expected != 0.0 {
+                format!("{:+.1}%", (measured - expected) / expected * 100.0)
+            } else {
+                "-".to_string()
+            };
+            write!(
+                f,
+                "\n{:<18} {:>10.4} {:>10.4}  {}",
+                name, expected, measured, difference
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulation::{PoissonArrivals, SimulationBuilder};
+    use crate::RateLimiterBuilder;
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!((actual - expected).abs() < 1e-9, "{actual} != {expected}");
+    }
+
+    #[test]
+    fn test_mm1_and_mmc_steady_states() {
+        // ρ = 0.8: Lq = ρ² / (1 - ρ), W = 1 / (μ - λ)
+        let mm1 = MMc::mm1(8.0, 10.0);
+        assert_close(mm1.wait_probability().unwrap(), 0.8);
+        assert_close(mm1.mean_queue_length().unwrap(), 3.2);
+        assert_close(mm1.mean_in_system().unwrap(), 4.0);
+        assert_close(mm1.mean_latency().unwrap().as_secs_f64(), 0.5);
+
+        // Two servers at a = 1: C(2, 1) = 1/3, Lq = 1/3
+        let mm2 = MMc::new(10.0, 10.0, 2);
+        assert_close(mm2.utilization(), 0.5);
+        assert_close(mm2.wait_probability().unwrap(), 1.0 / 3.0);
+        assert_close(mm2.mean_queue_length().unwrap(), 1.0 / 3.0);
+
+        assert_eq!(MMc::mm1(10.0, 10.0).mean_latency(), None);
+    }
+
+    #[test]
+    fn test_simulated_queue_matches_model_below_target() {
+        let rate_limiter = RateLimiterBuilder::new(100.0);
+        let arrivals = PoissonArrivals::new(40.0, 3);
+        let mut simulation = SimulationBuilder::from_arrivals(rate_limiter, arrivals)
+            .queue(QueueSimulator::new(25.0, 2, 3))
+            .build();
+        simulation.run(Duration::from_secs(7200));
+
+        let queue = simulation.queue().unwrap();
+        let measured = queue.stats();
+        let validation = QueueValidation::new(queue.model(40.0), measured);
+        assert!(validation.holds(0.1), "\n{validation}");
+    }
+
+    #[test]
+    fn test_limiter_keeps_queue_below_model_above_target() {
+        let rate_limiter = RateLimiterBuilder::new(40.0);
+        let arrivals = PoissonArrivals::new(80.0, 5);
+        let mut simulation = SimulationBuilder::from_arrivals(rate_limiter, arrivals)
+            .queue(QueueSimulator::new(50.0, 1, 5).warm_up(Duration::from_secs(10)))
+            .build();
+        simulation.run(Duration::from_secs(3600));
+
+        let measured = simulation.queue().unwrap().stats();
+        assert!((measured.arrival_rate - 40.0).abs() < 2.0, "{measured:?}");
+        let model = MMc::mm1(40.0, 50.0);
+        assert!(measured.mean_queue_length < model.mean_queue_length().unwrap());
+    }
+}
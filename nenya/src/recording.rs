@@ -0,0 +1,438 @@
+//! Import format for request logs recorded in production.
+//!
+//! A [`Recording`] holds the timestamps of recorded requests with the decisions the limiter made
+//! on them, so a single export from production feeds every step of offline tuning: a
+//! [`TraceReplay`] of its arrivals for simulations, the arrivals the
+//! [`GainTuner`](crate::tuning::GainTuner) scores gains on, and, with the `pid` feature, a
+//! [`SimulationReport`] of the recorded decisions that an [`Analyzer`](crate::analysis::Analyzer)
+//! can compare with simulated ones.
+//!
+//! Each request has the fields:
+//!
+//! - `timestamp`: seconds, such as a Unix timestamp with a fraction
+//! - `outcome` (optional): `accepted` or `throttled`, or the HTTP status of the response, where
+//!   `429` means throttled and any other status accepted
+//! - `target_rate` (optional): the limiter's target rate when it decided on the request
+//!
+//! CSV files name the columns in a header line and may have other columns, which are ignored:
+//!
+//! ```text
+//! timestamp,outcome,target_rate
+//! 1717000000.25,accepted,50
+//! 1717000000.5,throttled,50
+//! ```
+//!
+//! JSON Lines files, read with the `serde` feature, have one object per request:
+//!
+//! ```text
+//! {"timestamp": 1717000000.25, "outcome": "accepted", "target_rate": 50}
+//! {"timestamp": 1717000000.5, "outcome": 429}
+//! ```
+//!
+//! # Example
+//!
+//! ```rust
+//! use std::time::Duration;
+//!
+//! use nenya::recording::Recording;
+//!
+//! let log = "timestamp,path,outcome\n1717000000.25,/,200\n1717000000.5,/cart,429\n";
+//! let recording = Recording::from_csv(log.as_bytes()).unwrap();
+//! assert_eq!(recording.throttled(), 1);
+//!
+//! let arrivals: Vec<Duration> = recording.replay().collect();
+//! assert_eq!(arrivals, [Duration::ZERO, Duration::from_millis(250)]);
+//! ```
+
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::path::Path;
+use std::str::FromStr;
+use std::time::Duration;
+
+use crate::simulation::TraceReplay;
+#[cfg(feature = "pid")]
+use crate::simulation::{SimulationReport, TickMetrics};
+
+/// Decision the limiter made on a recorded request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    Accepted,
+    Throttled,
+}
+
+impl FromStr for Outcome {
+    type Err = String;
+
+    /// Parses `accepted` or `throttled`, in any case, or an HTTP status, which is throttled if
+    /// it is `429`.
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let value = value.trim();
+        if value.eq_ignore_ascii_case("accepted") {
+            Ok(Outcome::Accepted)
+        } else if value.eq_ignore_ascii_case("throttled") {
+            Ok(Outcome::Throttled)
+        } else {
+            match value.parse::<u16>() {
+                Ok(429) => Ok(Outcome::Throttled),
+                Ok(_) => Ok(Outcome::Accepted),
+                Err(_) => Err(format!("unknown outcome `{value}`")),
+            }
+        }
+    }
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Outcome::Accepted => "accepted",
+            Outcome::Throttled => "throttled",
+        })
+    }
+}
+
+/// A request of a [`Recording`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RecordedRequest {
+    /// Seconds, e.g. since the Unix epoch.
+    pub timestamp: f64,
+    /// The limiter's decision, if recorded.
+    pub outcome: Option<Outcome>,
+    /// The limiter's target rate at the decision, if recorded.
+    pub target_rate: Option<f64>,
+}
+
+/// Requests recorded in production, sorted by timestamp.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Recording {
+    requests: Vec<RecordedRequest>,
+}
+
+impl Recording {
+    /// Creates a recording of `requests`, which do not need to be sorted.
+    ///
+    /// Requests without a finite timestamp are dropped.
+    pub fn new(mut requests: Vec<RecordedRequest>) -> Self {
+        requests.retain(|request| request.timestamp.is_finite());
+        requests.sort_by(|a, b| a.timestamp.total_cmp(&b.timestamp));
+        Recording { requests }
+    }
+
+    /// Reads a recording from a CSV file with a header line naming its columns.
+    ///
+    /// Empty lines are ignored, and any other line without a timestamp, or with an unknown
+    /// outcome or a target rate that is not a number, is an error.
+    pub fn from_csv(reader: impl BufRead) -> io::Result<Self> {
+        let mut lines = reader.lines().enumerate();
+        let header = match lines.next() {
+            Some((_, line)) => line?,
+            None => return Ok(Self::default()),
+        };
+        let columns: Vec<&str> = header
+            .split(',')
+            .map(|name| name.trim().trim_matches('"'))
+            .collect();
+        let column = |name: &str| columns.iter().position(|column| *column == name);
+        let timestamp_column =
+            column("timestamp").ok_or_else(|| invalid_line(0, "no `timestamp` column"))?;
+        let outcome_column = column("outcome");
+        let target_rate_column = column("target_rate");
+
+        let mut requests = Vec::new();
+        for (index, line) in lines {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let fields: Vec<&str> = line
+                .split(',')
+                .map(|field| field.trim().trim_matches('"'))
+                .collect();
+            let field = |column: Option<usize>| {
+                column
+                    .and_then(|column| fields.get(column).copied())
+                    .filter(|field| !field.is_empty())
+            };
+            let timestamp = field(Some(timestamp_column))
+                .and_then(|field| field.parse::<f64>().ok())
+                .ok_or_else(|| invalid_line(index, "no timestamp"))?;
+            let outcome = field(outcome_column)
+                .map(Outcome::from_str)
+                .transpose()
+                .map_err(|err| invalid_line(index, &err))?;
+            let target_rate = field(target_rate_column)
+                .map(|field| {
+                    field
+                        .parse::<f64>()
+                        .map_err(|_| invalid_line(index, "target_rate is not a number"))
+                })
+                .transpose()?;
+            requests.push(RecordedRequest {
+                timestamp,
+                outcome,
+                target_rate,
+            });
+        }
+        Ok(Self::new(requests))
+    }
+
+    /// Reads a recording from a JSON Lines file with one object per request.
+    ///
+    /// Empty lines are ignored, and any other line without a numeric timestamp, or with an
+    /// unknown outcome or a target rate that is not a number, is an error.
+    #[cfg(feature = "serde")]
+    pub fn from_jsonl(reader: impl BufRead) -> io::Result<Self> {
+        use serde_json::Value;
+
+        let mut requests = Vec::new();
+        for (index, line) in reader.lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let value: Value =
+                serde_json::from_str(&line).map_err(|err| invalid_line(index, &err.to_string()))?;
+            let timestamp = value
+                .get("timestamp")
+                .and_then(Value::as_f64)
+                .ok_or_else(|| invalid_line(index, "no numeric field `timestamp`"))?;
+            let outcome = match value.get("outcome") {
+                None | Some(Value::Null) => None,
+                Some(Value::String(outcome)) => Some(outcome.parse()),
+                Some(Value::Number(status)) => Some(status.to_string().parse()),
+                Some(other) => Some(Err(format!("unknown outcome `{other}`"))),
+            }
+            .transpose()
+            .map_err(|err| invalid_line(index, &err))?;
+            let target_rate = match value.get("target_rate") {
+                None | Some(Value::Null) => None,
+                Some(target_rate) => Some(
+                    target_rate
+                        .as_f64()
+                        .ok_or_else(|| invalid_line(index, "target_rate is not a number"))?,
+                ),
+            };
+            requests.push(RecordedRequest {
+                timestamp,
+                outcome,
+                target_rate,
+            });
+        }
+        Ok(Self::new(requests))
+    }
+
+    /// Reads a recording from the file at `path`, as JSON Lines if it ends in `.jsonl` and CSV
+    /// otherwise.
+    ///
+    /// JSON Lines files need the `serde` feature; without it they are an error.
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref();
+        let reader = BufReader::new(File::open(path)?);
+        if path.extension().is_some_and(|ext| ext == "jsonl") {
+            #[cfg(feature = "serde")]
+            return Self::from_jsonl(reader);
+            #[cfg(not(feature = "serde"))]
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "reading JSON Lines recordings needs the `serde` feature",
+            ));
+        }
+        Self::from_csv(reader)
+    }
+
+    /// Returns the recorded requests, sorted by timestamp.
+    pub fn requests(&self) -> &[RecordedRequest] {
+        &self.requests
+    }
+
+    /// Returns the number of recorded requests.
+    pub fn len(&self) -> usize {
+        self.requests.len()
+    }
+
+    /// Returns `true` if the recording holds no requests.
+    pub fn is_empty(&self) -> bool {
+        self.requests.is_empty()
+    }
+
+    /// Returns the time between the first and last request.
+    pub fn duration(&self) -> Duration {
+        match (self.requests.first(), self.requests.last()) {
+            (Some(first), Some(last)) => Duration::from_secs_f64(last.timestamp - first.timestamp),
+            _ => Duration::ZERO,
+        }
+    }
+
+    /// Returns the number of requests recorded as throttled.
+    pub fn throttled(&self) -> usize {
+        self.requests
+            .iter()
+            .filter(|request| request.outcome == Some(Outcome::Throttled))
+            .count()
+    }
+
+    /// Returns `true` if any request has a recorded outcome.
+    pub fn has_outcomes(&self) -> bool {
+        self.requests
+            .iter()
+            .any(|request| request.outcome.is_some())
+    }
+
+    /// Returns a replay of the recorded arrivals, as a generator of simulation traffic.
+    pub fn replay(&self) -> TraceReplay {
+        TraceReplay::new(
+            self.requests
+                .iter()
+                .map(|request| request.timestamp)
+                .collect(),
+        )
+    }
+
+    /// Returns the arrival times since the first request, e.g. for a
+    /// [`GainTunerBuilder`](crate::tuning::GainTunerBuilder).
+    pub fn arrivals(&self) -> Vec<Duration> {
+        self.replay().collect()
+    }
+
+    /// Returns the recorded decisions as a report with a tick every `tick` since the first
+    /// request, for analysis next to simulated runs.
+    ///
+    /// The rates of a tick are its requests per second, requests without an outcome count as
+    /// accepted, and the target rate is the last recorded one, or zero before any. The PID terms
+    /// are not recorded and are zero.
+    #[cfg(feature = "pid")]
+    pub fn report(&self, tick: Duration) -> SimulationReport {
+        let Some(first) = self.requests.first() else {
+            return SimulationReport::default();
+        };
+        let tick_secs = tick.as_secs_f64();
+        let mut ticks = Vec::new();
+        let mut target_rate = 0.0;
+        let mut requests = self.requests.iter().peekable();
+        while requests.peek().is_some() {
+            let end = tick_secs * (ticks.len() + 1) as f64;
+            let mut offered = 0;
+            let mut throttled = 0;
+            while let Some(request) =
+                requests.next_if(|request| request.timestamp - first.timestamp < end)
+            {
+                offered += 1;
+                throttled += u64::from(request.outcome == Some(Outcome::Throttled));
+                target_rate = request.target_rate.unwrap_or(target_rate);
+            }
+            let accepted = offered - throttled;
+            ticks.push(TickMetrics {
+                elapsed: tick * (ticks.len() as u32 + 1),
+                generated_rate: offered as f64 / tick_secs,
+                offered,
+                accepted,
+                throttled,
+                target_rate,
+                request_rate: offered as f64 / tick_secs,
+                accepted_rate: accepted as f64 / tick_secs,
+                pid_p: 0.0,
+                pid_i: 0.0,
+                pid_d: 0.0,
+                pid_output: 0.0,
+                latency: 0.0,
+                error_rate: 0.0,
+            });
+        }
+        SimulationReport { ticks }
+    }
+}
+
+/// Returns the error for an unreadable line of a recording, numbered from one.
+fn invalid_line(index: usize, message: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("line {}: {}", index + 1, message),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CSV: &str = "\
+host,timestamp,outcome,target_rate
+a,100.5,throttled,40
+b,100.0,accepted,
+
+c,101.25,200,
+d,102.0,,45
+";
+
+    #[test]
+    fn test_reads_csv() {
+        let recording = Recording::from_csv(CSV.as_bytes()).unwrap();
+        assert_eq!(recording.len(), 4);
+        assert_eq!(
+            recording.requests()[0],
+            RecordedRequest {
+                timestamp: 100.0,
+                outcome: Some(Outcome::Accepted),
+                target_rate: None,
+            }
+        );
+        assert_eq!(recording.requests()[1].target_rate, Some(40.0));
+        assert_eq!(recording.requests()[3].outcome, None);
+        assert_eq!(recording.throttled(), 1);
+        assert!(recording.has_outcomes());
+        assert_eq!(recording.duration(), Duration::from_secs(2));
+        assert_eq!(
+            recording.arrivals(),
+            [0, 500, 1250, 2000].map(Duration::from_millis)
+        );
+
+        let err = Recording::from_csv("timestamp,outcome\n1,ok\n".as_bytes()).unwrap_err();
+        assert_eq!(err.to_string(), "line 2: unknown outcome `ok`");
+        let err = Recording::from_csv("time\n1\n".as_bytes()).unwrap_err();
+        assert_eq!(err.to_string(), "line 1: no `timestamp` column");
+        assert!(Recording::from_csv("".as_bytes()).unwrap().is_empty());
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn test_reads_jsonl() {
+        let jsonl = r#"{"timestamp": 100.5, "outcome": 429, "target_rate": 40}
+{"timestamp": 100.0, "outcome": "Accepted"}
+
+{"timestamp": 101.25, "outcome": null}
+"#;
+        let recording = Recording::from_jsonl(jsonl.as_bytes()).unwrap();
+        assert_eq!(recording.len(), 3);
+        assert_eq!(recording.requests()[0].outcome, Some(Outcome::Accepted));
+        assert_eq!(
+            recording.requests()[1],
+            RecordedRequest {
+                timestamp: 100.5,
+                outcome: Some(Outcome::Throttled),
+                target_rate: Some(40.0),
+            }
+        );
+        assert_eq!(recording.requests()[2].outcome, None);
+
+        let err =
+            Recording::from_jsonl(r#"{"timestamp": 1, "outcome": true}"#.as_bytes()).unwrap_err();
+        assert_eq!(err.to_string(), "line 1: unknown outcome `true`");
+    }
+
+    #[test]
+    #[cfg(feature = "pid")]
+    fn test_reports_recorded_decisions() {
+        let recording = Recording::from_csv(CSV.as_bytes()).unwrap();
+        let report = recording.report(Duration::from_secs(1));
+        let counts: Vec<(u64, u64)> = report
+            .ticks
+            .iter()
+            .map(|tick| (tick.offered, tick.throttled))
+            .collect();
+        assert_eq!(counts, [(2, 1), (1, 0), (1, 0)]);
+        let target_rates: Vec<f64> = report.ticks.iter().map(|tick| tick.target_rate).collect();
+        assert_eq!(target_rates, [40.0, 40.0, 45.0]);
+        assert_eq!(report.ticks[0].accepted_rate, 1.0);
+        assert_eq!(report.ticks[2].elapsed, Duration::from_secs(3));
+    }
+}
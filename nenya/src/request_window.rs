@@ -0,0 +1,387 @@
+use num_traits::{Float, FromPrimitive};
+use std::collections::VecDeque;
+use std::time::Duration;
+
+use crate::clock::Timestamp;
+
+/// Minimum duration a window is assumed to span when calculating its rate, so that a burst of
+/// requests arriving at the same instant does not produce an unbounded rate.
+const MIN_WINDOW_DURATION: f32 = 0.1;
+
+/// Returns the summed weight `total` of a window's requests per second, measured from its oldest
+/// request at `oldest`.
+pub(crate) fn rate_since<T: Float + FromPrimitive>(
+    total: T,
+    oldest: Timestamp,
+    now: Timestamp,
+) -> T {
+    let window_duration = now.duration_since(oldest).as_secs_f32();
+    match T::from_f32(window_duration.max(MIN_WINDOW_DURATION)) {
+        Some(effective_duration) => total / effective_duration,
+        None => T::zero(),
+    }
+}
+
+/// Returns the number of buckets of `quantum` a bucketed window needs to span `span`: one more
+/// than the span holds, for the quantum partly outside it.
+pub(crate) fn bucket_count(quantum: Duration, span: Duration) -> usize {
+    span.as_nanos().div_ceil(quantum.as_nanos()) as usize + 1
+}
+
+/// Returns the quantum `timestamp` falls in, as whole quanta since the epoch of the clock.
+pub(crate) fn quantum_of(timestamp: Timestamp, quantum: Duration) -> u64 {
+    (timestamp.as_duration().as_nanos() / quantum.as_nanos()) as u64
+}
+
+/// Returns the start of the quantum with the given index since the epoch of the clock.
+pub(crate) fn start_of(index: u64, quantum: Duration) -> Timestamp {
+    let nanos = index as u128 * quantum.as_nanos();
+    Timestamp::from_duration(Duration::new(
+        (nanos / 1_000_000_000) as u64,
+        (nanos % 1_000_000_000) as u32,
+    ))
+}
+
+/// Sliding window of weighted request timestamps.
+///
+/// Each request carries a weight, and the window keeps a running total of the weights so the
+/// rate can be calculated without walking the window. Requests are kept either one entry each,
+/// or summed into a [`BucketRing`] of counters per quantum of time.
+#[derive(Debug, Clone)]
+pub(crate) struct RequestWindow<T> {
+    storage: Storage<T>,
+    total: T,
+}
+
+#[derive(Debug, Clone)]
+enum Storage<T> {
+    /// Every request with its own timestamp.
+    Exact(VecDeque<(Timestamp, T)>),
+    /// Requests summed per quantum of time.
+    Buckets(BucketRing<T>),
+}
+
+impl<T: Float + FromPrimitive> RequestWindow<T> {
+    /// Creates a window with room for `capacity` requests before it allocates.
+    pub(crate) fn with_capacity(capacity: usize) -> Self {
+        RequestWindow {
+            storage: Storage::Exact(VecDeque::with_capacity(capacity)),
+            total: T::zero(),
+        }
+    }
+
+    /// Sizes the window for `capacity` requests in total, so it does not allocate until it holds
+    /// more, and releases any room beyond that the requests it holds do not need. A bucketed
+    /// window never allocates, so this does nothing for one.
+    pub(crate) fn set_capacity(&mut self, capacity: usize) {
+        if let Storage::Exact(entries) = &mut self.storage {
+            if capacity > entries.capacity() {
+                entries.reserve(capacity - entries.len());
+            } else {
+                entries.shrink_to(capacity);
+            }
+        }
+    }
+
+    /// Returns the number of buckets of a bucketed window, or `None` for an exact window.
+    pub(crate) fn bucket_count(&self) -> Option<usize> {
+        match &self.storage {
+            Storage::Exact(_) => None,
+            Storage::Buckets(ring) => Some(ring.buckets.len()),
+        }
+    }
+
+    /// Returns the number of requests the window holds before it allocates, or the number of
+    /// buckets of a bucketed window.
+    #[cfg(test)]
+    pub(crate) fn capacity(&self) -> usize {
+        match &self.storage {
+            Storage::Exact(entries) => entries.capacity(),
+            Storage::Buckets(ring) => ring.buckets.len(),
+        }
+    }
+
+    /// Creates a window counting requests per `quantum`, with room for `span` of them.
+    pub(crate) fn bucketed(quantum: Duration, span: Duration) -> Self {
+        RequestWindow {
+            storage: Storage::Buckets(BucketRing::new(quantum, span)),
+            total: T::zero(),
+        }
+    }
+
+    /// Records a request with the given weight.
+    pub(crate) fn push(&mut self, timestamp: Timestamp, weight: T) {
+        match &mut self.storage {
+            Storage::Exact(entries) => entries.push_back((timestamp, weight)),
+            Storage::Buckets(ring) => {
+                let evicted = ring.push(timestamp, weight);
+                self.total = self.total - evicted;
+            }
+        }
+        self.total = self.total + weight;
+    }
+
+    /// Removes requests older than `max_age`.
+    ///
+    /// A bucketed window removes the requests of a quantum together, once its start is older
+    /// than `max_age`, and only trims once per quantum, so requests may stay up to a quantum
+    /// longer. An exact window pops each request once, so trimming is amortized constant time
+    /// either way.
+    pub(crate) fn trim(&mut self, now: Timestamp, max_age: Duration) {
+        match &mut self.storage {
+            Storage::Exact(entries) => {
+                while let Some(&(timestamp, weight)) = entries.front() {
+                    if now.duration_since(timestamp) <= max_age {
+                        break;
+                    }
+                    entries.pop_front();
+                    self.total = self.total - weight;
+                }
+            }
+            Storage::Buckets(ring) => {
+                let evicted = ring.trim(now, max_age);
+                self.total = self.total - evicted;
+            }
+        }
+        if self.is_empty() {
+            // Avoid accumulating floating point error across many pushes and pops
+            self.total = T::zero();
+        }
+    }
+
+    /// Returns the timestamp of the oldest request in the window.
+    pub(crate) fn oldest(&self) -> Option<Timestamp> {
+        match &self.storage {
+            Storage::Exact(entries) => entries.front().map(|&(timestamp, _)| timestamp),
+            Storage::Buckets(ring) => ring.oldest(),
+        }
+    }
+
+    /// Returns the timestamp of the newest request in the window.
+    pub(crate) fn newest(&self) -> Option<Timestamp> {
+        match &self.storage {
+            Storage::Exact(entries) => entries.back().map(|&(timestamp, _)| timestamp),
+            Storage::Buckets(ring) => ring.newest,
+        }
+    }
+
+    /// Returns the summed weight of the requests in the window per second.
+    pub(crate) fn rate(&self, now: Timestamp) -> T {
+        match self.oldest() {
+            Some(oldest) => rate_since(self.total, oldest, now),
+            None => T::zero(),
+        }
+    }
+
+    /// Returns the number of requests in the window.
+    pub(crate) fn len(&self) -> usize {
+        match &self.storage {
+            Storage::Exact(entries) => entries.len(),
+            Storage::Buckets(ring) => ring.count,
+        }
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub(crate) fn clear(&mut self) {
+        match &mut self.storage {
+            Storage::Exact(entries) => entries.clear(),
+            Storage::Buckets(ring) => ring.clear(),
+        }
+        self.total = T::zero();
+    }
+}
+
+/// Requests of one quantum of time.
+#[derive(Debug, Clone, Copy)]
+struct Bucket<T> {
+    weight: T,
+    count: usize,
+    /// Earliest request of the quantum, from which the window's rate is measured.
+    oldest: Timestamp,
+}
+
+/// Fixed-size ring of per-quantum request counters, updated in constant time without
+/// allocating.
+///
+/// Buckets are addressed by the number of whole quanta since the epoch of the clock, modulo the
+/// ring's size. While the ring holds requests, `first` is the quantum of the oldest of them, and
+/// every one of them is within the ring's size of it. `trimmed` is the quantum of the last trim.
+#[derive(Debug, Clone)]
+struct BucketRing<T> {
+    quantum: Duration,
+    buckets: Vec<Bucket<T>>,
+    first: u64,
+    trimmed: Option<u64>,
+    count: usize,
+    newest: Option<Timestamp>,
+}
+
+impl<T: Float> BucketRing<T> {
+    fn new(quantum: Duration, span: Duration) -> Self {
+        let quantum = quantum.max(Duration::from_nanos(1));
+        let size = bucket_count(quantum, span);
+        let empty = Bucket {
+            weight: T::zero(),
+            count: 0,
+            oldest: Timestamp::default(),
+        };
+        BucketRing {
+            quantum,
+            buckets: vec![empty; size],
+            first: 0,
+            trimmed: None,
+            count: 0,
+            newest: None,
+        }
+    }
+
+    fn bucket(&mut self, quantum: u64) -> &mut Bucket<T> {
+        let size = self.buckets.len() as u64;
+        &mut self.buckets[(quantum % size) as usize]
+    }
+
+    /// Adds a request to the bucket of its quantum, evicting the oldest buckets if the ring has
+    /// no room for it. Returns the weight evicted.
+    fn push(&mut self, timestamp: Timestamp, weight: T) -> T {
+        let mut evicted = T::zero();
+        let mut quantum = quantum_of(timestamp, self.quantum);
+        if self.count > 0 {
+            // A request stamped before the oldest held one counts towards the oldest quantum
+            quantum = quantum.max(self.first);
+        }
+        let size = self.buckets.len() as u64;
+        while self.count > 0 && quantum >= self.first + size {
+            evicted = evicted + self.evict_first();
+        }
+        if self.count == 0 {
+            self.first = quantum;
+        }
+
+        let bucket = self.bucket(quantum);
+        if bucket.count == 0 {
+            bucket.oldest = timestamp;
+        }
+        bucket.weight = bucket.weight + weight;
+        bucket.count += 1;
+        self.count += 1;
+        self.newest = Some(
+            self.newest
+                .map_or(timestamp, |newest| newest.max(timestamp)),
+        );
+        evicted
+    }
+
+    /// Evicts the buckets whose quantum started more than `max_age` before `now`, unless the ring
+    /// was already trimmed during the quantum of `now`. Returns the weight evicted.
+    fn trim(&mut self, now: Timestamp, max_age: Duration) -> T {
+        let mut evicted = T::zero();
+        let quantum = quantum_of(now, self.quantum);
+        if self.trimmed == Some(quantum) {
+            return evicted;
+        }
+        self.trimmed = Some(quantum);
+        while self.count > 0 && now.duration_since(start_of(self.first, self.quantum)) > max_age {
+            evicted = evicted + self.evict_first();
+        }
+        evicted
+    }
+
+    /// Empties the bucket at `first` and advances `first` to the next bucket holding requests.
+    fn evict_first(&mut self) -> T {
+        let first = self.first;
+        let bucket = self.bucket(first);
+        let (weight, count) = (bucket.weight, bucket.count);
+        bucket.weight = T::zero();
+        bucket.count = 0;
+        self.count -= count;
+        self.first += 1;
+        while self.count > 0 && self.bucket(self.first).count == 0 {
+            self.first += 1;
+        }
+        if self.count == 0 {
+            self.newest = None;
+        }
+        weight
+    }
+
+    fn oldest(&self) -> Option<Timestamp> {
+        if self.count == 0 {
+            return None;
+        }
+        let size = self.buckets.len() as u64;
+        Some(self.buckets[(self.first % size) as usize].oldest)
+    }
+
+    fn clear(&mut self) {
+        for bucket in &mut self.buckets {
+            bucket.weight = T::zero();
+            bucket.count = 0;
+        }
+        self.count = 0;
+        self.trimmed = None;
+        self.newest = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_weighted_rate() {
+        let now = Timestamp::from_duration(Duration::from_secs(10));
+        let mut window: RequestWindow<f64> = RequestWindow::with_capacity(2);
+        window.push(now - Duration::from_secs(2), 1.0);
+        window.push(now - Duration::from_secs(1), 3.0);
+        assert_eq!(window.rate(now), 4.0 / 2.0);
+
+        window.trim(now, Duration::from_millis(1500));
+        assert_eq!(window.len(), 1);
+        assert_eq!(window.rate(now), 3.0);
+
+        window.trim(now + Duration::from_secs(1), Duration::from_millis(1500));
+        assert!(window.is_empty());
+        assert_eq!(window.rate(now), 0.0);
+    }
+
+    #[test]
+    fn test_bucketed_window_sums_quanta() {
+        let quantum = Duration::from_millis(100);
+        let span = Duration::from_secs(1);
+        let start = Timestamp::from_duration(Duration::from_secs(10));
+        let mut window: RequestWindow<f64> = RequestWindow::bucketed(quantum, span);
+        for offset in [0, 20, 150, 980] {
+            window.push(start + Duration::from_millis(offset), 1.0);
+        }
+        window.push(start + Duration::from_millis(990), 2.0);
+        assert_eq!(window.len(), 5);
+        assert_eq!(window.oldest(), Some(start));
+        assert_eq!(window.newest(), Some(start + Duration::from_millis(990)));
+        assert_eq!(window.rate(start + Duration::from_secs(1)), 6.0);
+
+        // The first quantum leaves the window as a whole once its start is a second old
+        window.trim(start + Duration::from_millis(1050), span);
+        assert_eq!(window.len(), 3);
+        assert_eq!(window.oldest(), Some(start + Duration::from_millis(150)));
+
+        // The second quantum is due just after 1100ms, but stays until the ring is trimmed in the
+        // next quantum
+        window.trim(start + Duration::from_millis(1100), span);
+        window.trim(start + Duration::from_millis(1150), span);
+        assert_eq!(window.len(), 3);
+        window.trim(start + Duration::from_millis(1200), span);
+        assert_eq!(window.len(), 2);
+
+        // After a long pause, a request evicts everything before it
+        window.push(start + Duration::from_secs(60), 1.0);
+        assert_eq!(window.len(), 1);
+        assert_eq!(window.rate(start + Duration::from_secs(61)), 1.0);
+
+        window.clear();
+        assert!(window.is_empty());
+        assert_eq!(window.newest(), None);
+    }
+}
@@ -0,0 +1,81 @@
+//! Async runtime abstraction for the waiting done by the async APIs.
+//!
+//! Nothing in nenya spawns tasks: long-running work, such as
+//! [`share_rates`](crate::distributed::share_rates), is returned as a future for the application
+//! to spawn on its own executor. The only thing the async APIs need from a runtime is a way to
+//! wait, which they get through a [`Timer`]. Adapters are provided behind features:
+//!
+//! - `tokio`: [`TokioTimer`]
+//! - `async-io`: [`AsyncIoTimer`], for smol and async-std, which both run on `async-io`
+//!
+//! Any other runtime only needs to implement [`Timer`].
+
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::time::Duration;
+
+/// Future returned by [`Timer::sleep`].
+pub type Sleep = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+/// Source of timed waits on an async runtime.
+pub trait Timer: fmt::Debug + Send + Sync {
+    /// Returns a future that completes after `duration`.
+    fn sleep(&self, duration: Duration) -> Sleep;
+}
+
+/// Timer running on the tokio runtime.
+#[cfg(feature = "tokio")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TokioTimer;
+
+#[cfg(feature = "tokio")]
+impl Timer for TokioTimer {
+    fn sleep(&self, duration: Duration) -> Sleep {
+        Box::pin(tokio::time::sleep(duration))
+    }
+}
+
+/// Timer running on the `async-io` reactor used by smol and async-std.
+#[cfg(feature = "async-io")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AsyncIoTimer;
+
+#[cfg(feature = "async-io")]
+impl Timer for AsyncIoTimer {
+    fn sleep(&self, duration: Duration) -> Sleep {
+        Box::pin(async move {
+            async_io::Timer::after(duration).await;
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::task::{Context, Poll, Waker};
+
+    #[cfg(feature = "async-io")]
+    #[test]
+    fn test_async_io_timer_waits() {
+        let start = std::time::Instant::now();
+        async_io::block_on(AsyncIoTimer.sleep(Duration::from_millis(20)));
+        assert!(start.elapsed() >= Duration::from_millis(20));
+    }
+
+    #[test]
+    fn test_custom_timer() {
+        #[derive(Debug)]
+        struct Immediate;
+
+        impl Timer for Immediate {
+            fn sleep(&self, _duration: Duration) -> Sleep {
+                Box::pin(std::future::ready(()))
+            }
+        }
+
+        let mut sleep = Immediate.sleep(Duration::from_secs(60));
+        let mut cx = Context::from_waker(Waker::noop());
+        assert_eq!(sleep.as_mut().poll(&mut cx), Poll::Ready(()));
+    }
+}
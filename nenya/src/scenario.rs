@@ -0,0 +1,823 @@
+//! Declarative simulation scenarios with expected outcomes.
+//!
+//! A [`Scenario`] describes a [`Simulation`] in TOML or JSON, so the traffic a limiter
+//! configuration is tuned against, and the behavior it is expected to show, can be kept under
+//! version control next to the configuration and checked in CI:
+//!
+//! - `limiter`: the rate limiter and PID controller settings
+//! - `traffic`: generators whose rates are added up, such as `sine`, `on_off`, or `flash_crowd`
+//! - `disturbances`: changes during the run, such as a traffic surge, a new target rate, a
+//!   capacity drop or latency spike, or peers joining the cluster
+//! - `expect`: bounds on metrics over a time range, e.g. the accepted rate after the controller
+//!   settled
+//!
+//! Running a scenario returns a [`ScenarioOutcome`] with the value of every expectation and
+//! whether it passed.
+//!
+//! # Example
+//!
+//! ```rust
+//! use nenya::scenario::Scenario;
+//!
+//! let scenario = Scenario::from_toml(
+//!     r#"
+//!     name = "steady overload"
+//!     duration_secs = 600
+//!
+//!     [limiter]
+//!     target_rate = 50.0
+//!     min_rate = 10.0
+//!     max_rate = 100.0
+//!
+//!     [[traffic]]
+//!     type = "constant"
+//!     rate = 80.0
+//!
+//!     [[disturbances]]
+//!     type = "traffic"
+//!     start_secs = 300
+//!     duration_secs = 60
+//!     rate = 200.0
+//!
+//!     [[expect]]
+//!     metric = "accepted_rate"
+//!     from_secs = 60
+//!     min = 45.0
+//!     max = 55.0
+//!     "#,
+//! )
+//! .unwrap();
+//!
+//! let outcome = scenario.run();
+//! assert!(outcome.passed(), "{}", outcome);
+//! ```
+
+use std::fmt;
+use std::time::Duration;
+
+use serde::Deserialize;
+
+use crate::pid_controller::PIDControllerBuilder;
+use crate::simulation::{
+    self, DisturbanceKind, FlashCrowdGenerator, OnOffGenerator, SimulationBuilder,
+    SimulationReport, SineWaveGenerator, TickMetrics, TrafficGenerator,
+};
+use crate::RateLimiterBuilder;
+
+#[cfg(doc)]
+use crate::simulation::Simulation;
+
+/// Errors returned when a scenario cannot be loaded.
+#[derive(Debug)]
+pub enum ScenarioError {
+    /// The scenario is not valid TOML or does not match the expected schema.
+    Toml(toml::de::Error),
+    /// The scenario is not valid JSON or does not match the expected schema.
+    Json(serde_json::Error),
+    /// The scenario parsed but contains invalid values.
+    Invalid(String),
+}
+
+impl fmt::Display for ScenarioError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ScenarioError::Toml(err) => write!(f, "unable to parse scenario: {}", err),
+            ScenarioError::Json(err) => write!(f, "unable to parse scenario: {}", err),
+            ScenarioError::Invalid(reason) => write!(f, "invalid scenario: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for ScenarioError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ScenarioError::Toml(err) => Some(err),
+            ScenarioError::Json(err) => Some(err),
+            ScenarioError::Invalid(_) => None,
+        }
+    }
+}
+
+/// Simulation of a rate limiter against traffic, with the outcome it is expected to have.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Scenario {
+    /// Name of the scenario in reports.
+    #[serde(default)]
+    pub name: String,
+    /// Virtual time the scenario runs for.
+    pub duration_secs: f64,
+    /// Virtual time between recorded metrics.
+    #[serde(default = "default_tick_ms")]
+    pub tick_ms: u64,
+    pub limiter: LimiterConfig,
+    /// Generators whose rates are added up to the offered rate.
+    #[serde(default)]
+    pub traffic: Vec<TrafficConfig>,
+    #[serde(default)]
+    pub disturbances: Vec<Disturbance>,
+    #[serde(default)]
+    pub expect: Vec<Expectation>,
+}
+
+fn default_tick_ms() -> u64 {
+    100
+}
+
+/// Settings of the simulated rate limiter.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LimiterConfig {
+    pub target_rate: f64,
+    /// Lower bound of the target rate, the target rate if not set.
+    pub min_rate: Option<f64>,
+    /// Upper bound of the target rate, the target rate if not set.
+    pub max_rate: Option<f64>,
+    /// Interval between updates of the target rate, one second if not set.
+    pub update_interval_ms: Option<u64>,
+    /// PID controller settings, a static controller if not set.
+    pub pid: Option<PidConfig>,
+}
+
+/// Settings of the PID controller of the simulated rate limiter.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PidConfig {
+    #[serde(default)]
+    pub kp: f64,
+    #[serde(default)]
+    pub ki: f64,
+    #[serde(default)]
+    pub kd: f64,
+    pub error_bias: Option<f64>,
+    pub error_limit: Option<f64>,
+    pub output_limit: Option<f64>,
+}
+
+/// Generator of offered traffic.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum TrafficConfig {
+    /// A fixed rate.
+    Constant { rate: f64 },
+    /// A [`SineWaveGenerator`].
+    Sine {
+        base_rate: f64,
+        #[serde(default)]
+        amplitudes: Vec<f64>,
+        #[serde(default)]
+        frequencies: Vec<f64>,
+    },
+    /// An [`OnOffGenerator`].
+    OnOff {
+        on_rate: f64,
+        #[serde(default)]
+        off_rate: f64,
+        on_secs: f64,
+        off_secs: f64,
+    },
+    /// A [`FlashCrowdGenerator`].
+    FlashCrowd {
+        base_rate: f64,
+        peak_rate: f64,
+        start_secs: f64,
+        decay_secs: f64,
+    },
+}
+
+impl TrafficConfig {
+    fn generator(&self) -> Box<dyn TrafficGenerator> {
+        match self {
+            &TrafficConfig::Constant { rate } => Box::new(move |_: Duration| rate),
+            TrafficConfig::Sine {
+                base_rate,
+                amplitudes,
+                frequencies,
+            } => Box::new(SineWaveGenerator::new(
+                *base_rate,
+                amplitudes.clone(),
+                frequencies.clone(),
+            )),
+            &TrafficConfig::OnOff {
+                on_rate,
+                off_rate,
+                on_secs,
+                off_secs,
+            } => Box::new(OnOffGenerator::new(
+                on_rate,
+                off_rate,
+                Duration::from_secs_f64(on_secs),
+                Duration::from_secs_f64(off_secs),
+            )),
+            &TrafficConfig::FlashCrowd {
+                base_rate,
+                peak_rate,
+                start_secs,
+                decay_secs,
+            } => Box::new(FlashCrowdGenerator::new(
+                base_rate,
+                peak_rate,
+                Duration::from_secs_f64(start_secs),
+                Duration::from_secs_f64(decay_secs),
+            )),
+        }
+    }
+}
+
+/// Change during a scenario.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum Disturbance {
+    /// Extra traffic at `rate`, or less traffic if negative, from `start_secs` for
+    /// `duration_secs`, or until the end if not set.
+    Traffic {
+        start_secs: f64,
+        duration_secs: Option<f64>,
+        rate: f64,
+    },
+    /// A new target rate for the limiter from `at_secs`, as an operator would set it.
+    TargetRate { at_secs: f64, rate: f64 },
+    /// Loss of `fraction` of the downstream capacity, lowering the limiter's maximum rate.
+    CapacityDrop {
+        start_secs: f64,
+        profile: Option<DisturbanceProfile>,
+        duration_secs: Option<f64>,
+        fraction: f64,
+    },
+    /// Downstream latency growing by `factor`, dividing the limiter's maximum rate by it.
+    LatencySpike {
+        start_secs: f64,
+        profile: Option<DisturbanceProfile>,
+        duration_secs: Option<f64>,
+        factor: f64,
+    },
+    /// Peers joining the cluster with the given rates, or leaving it if negative; the accepted
+    /// rate is the request rate if not set.
+    Peers {
+        start_secs: f64,
+        profile: Option<DisturbanceProfile>,
+        duration_secs: Option<f64>,
+        request_rate: f64,
+        accepted_rate: Option<f64>,
+    },
+}
+
+impl Disturbance {
+    /// Returns the disturbance of the simulation's environment, or `None` for changes to the
+    /// traffic or the limiter.
+    fn injection(&self) -> Result<Option<simulation::Disturbance>, ScenarioError> {
+        let (start_secs, profile, duration_secs, kind) = match *self {
+            Disturbance::Traffic { .. } | Disturbance::TargetRate { .. } => return Ok(None),
+            Disturbance::CapacityDrop {
+                start_secs,
+                profile,
+                duration_secs,
+                fraction,
+            } => (
+                start_secs,
+                profile,
+                duration_secs,
+                DisturbanceKind::CapacityDrop { fraction },
+            ),
+            Disturbance::LatencySpike {
+                start_secs,
+                profile,
+                duration_secs,
+                factor,
+            } => (
+                start_secs,
+                profile,
+                duration_secs,
+                DisturbanceKind::LatencySpike { factor },
+            ),
+            Disturbance::Peers {
+                start_secs,
+                profile,
+                duration_secs,
+                request_rate,
+                accepted_rate,
+            } => (
+                start_secs,
+                profile,
+                duration_secs,
+                DisturbanceKind::Peers {
+                    request_rate,
+                    accepted_rate: accepted_rate.unwrap_or(request_rate),
+                },
+            ),
+        };
+
+        let start = Duration::from_secs_f64(start_secs.max(0.0));
+        let duration = duration_secs.map(|secs| Duration::from_secs_f64(secs.max(0.0)));
+        let default_profile = if duration.is_some() {
+            DisturbanceProfile::Impulse
+        } else {
+            DisturbanceProfile::Step
+        };
+        let disturbance = match (profile.unwrap_or(default_profile), duration) {
+            (DisturbanceProfile::Step, None) => simulation::Disturbance::step(start, kind),
+            (DisturbanceProfile::Impulse, Some(duration)) => {
+                simulation::Disturbance::impulse(start, duration, kind)
+            }
+            (DisturbanceProfile::Ramp, Some(duration)) => {
+                simulation::Disturbance::ramp(start, duration, kind)
+            }
+            (DisturbanceProfile::Step, Some(_)) => {
+                return Err(ScenarioError::Invalid(
+                    "step disturbances last until the end and take no duration_secs".to_string(),
+                ))
+            }
+            (profile, None) => {
+                return Err(ScenarioError::Invalid(format!(
+                    "{} disturbances need duration_secs",
+                    profile
+                )))
+            }
+        };
+        Ok(Some(disturbance))
+    }
+}
+
+/// How a capacity, latency, or peer disturbance takes effect from its start.
+///
+/// Without a profile, a disturbance with `duration_secs` is an impulse and one without is a
+/// step.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DisturbanceProfile {
+    /// Full effect until the end.
+    Step,
+    /// Full effect for `duration_secs`.
+    Impulse,
+    /// Effect growing linearly to full over `duration_secs`, then held.
+    Ramp,
+}
+
+impl fmt::Display for DisturbanceProfile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            DisturbanceProfile::Step => "step",
+            DisturbanceProfile::Impulse => "impulse",
+            DisturbanceProfile::Ramp => "ramp",
+        })
+    }
+}
+
+/// Metric of a scenario checked by an [`Expectation`], over the ticks of its time range.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Metric {
+    /// Requests offered per second.
+    OfferedRate,
+    /// Requests accepted per second.
+    AcceptedRate,
+    /// Fraction of the offered requests that were throttled.
+    ThrottleRatio,
+    /// Mean target rate.
+    TargetRate,
+    /// Lowest target rate, e.g. to check the undershoot after a burst.
+    MinTargetRate,
+    /// Highest target rate, e.g. to check the overshoot after a burst.
+    MaxTargetRate,
+}
+
+impl Metric {
+    /// Returns the value of the metric over `ticks`, or `None` if there are none.
+    fn value(self, ticks: &[TickMetrics], tick: Duration) -> Option<f64> {
+        if ticks.is_empty() {
+            return None;
+        }
+        let secs = tick.as_secs_f64() * ticks.len() as f64;
+        let offered: u64 = ticks.iter().map(|tick| tick.offered).sum();
+        let target_rates = ticks.iter().map(|tick| tick.target_rate);
+        Some(match self {
+            Metric::OfferedRate => offered as f64 / secs,
+            Metric::AcceptedRate => {
+                ticks.iter().map(|tick| tick.accepted).sum::<u64>() as f64 / secs
+            }
+            Metric::ThrottleRatio if offered == 0 => 0.0,
+            Metric::ThrottleRatio => {
+                ticks.iter().map(|tick| tick.throttled).sum::<u64>() as f64 / offered as f64
+            }
+            Metric::TargetRate => target_rates.sum::<f64>() / ticks.len() as f64,
+            Metric::MinTargetRate => target_rates.fold(f64::INFINITY, f64::min),
+            Metric::MaxTargetRate => target_rates.fold(f64::NEG_INFINITY, f64::max),
+        })
+    }
+}
+
+impl fmt::Display for Metric {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Metric::OfferedRate => "offered_rate",
+            Metric::AcceptedRate => "accepted_rate",
+            Metric::ThrottleRatio => "throttle_ratio",
+            Metric::TargetRate => "target_rate",
+            Metric::MinTargetRate => "min_target_rate",
+            Metric::MaxTargetRate => "max_target_rate",
+        };
+        f.write_str(name)
+    }
+}
+
+/// Bounds a metric is expected to stay within over a time range of a scenario.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Expectation {
+    pub metric: Metric,
+    /// Start of the time range, the start of the scenario if not set.
+    #[serde(default)]
+    pub from_secs: f64,
+    /// End of the time range, the end of the scenario if not set.
+    pub to_secs: Option<f64>,
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+}
+
+/// Value of an expectation's metric in a run, and whether it was within bounds.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Check {
+    pub expectation: Expectation,
+    /// The metric's value, or `None` if the time range held no ticks.
+    pub value: Option<f64>,
+    pub passed: bool,
+}
+
+/// Result of running a scenario.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScenarioOutcome {
+    pub name: String,
+    pub report: SimulationReport,
+    pub checks: Vec<Check>,
+}
+
+impl ScenarioOutcome {
+    /// Returns `true` if every expectation was met.
+    pub fn passed(&self) -> bool {
+        self.checks.iter().all(|check| check.passed)
+    }
+}
+
+impl fmt::Display for ScenarioOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let status = if self.passed() { "passed" } else { "FAILED" };
+        write!(f, "{}: {}", self.name, status)?;
+        for check in &self.checks {
+            let expectation = &check.expectation;
+            let value = check
+                .value
+                .map_or_else(|| "no ticks".to_string(), |value| format!("{:.3}", value));
+            let range = match expectation.to_secs {
+                Some(to_secs) => format!("{}s..{}s", expectation.from_secs, to_secs),
+                None => format!("{}s..", expectation.from_secs),
+            };
+            write!(
+                f,
+                "\n  [{}] {} over {} = {}",
+                if check.passed { "ok" } else { "fail" },
+                expectation.metric,
+                range,
+                value
+            )?;
+            if let Some(min) = expectation.min {
+                write!(f, ", min {}", min)?;
+            }
+            if let Some(max) = expectation.max {
+                write!(f, ", max {}", max)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Scenario {
+    /// Parses a scenario from TOML.
+    pub fn from_toml(toml: &str) -> Result<Self, ScenarioError> {
+        toml::from_str::<Self>(toml)
+            .map_err(ScenarioError::Toml)?
+            .validate()
+    }
+
+    /// Parses a scenario from JSON.
+    pub fn from_json(json: &str) -> Result<Self, ScenarioError> {
+        serde_json::from_str::<Self>(json)
+            .map_err(ScenarioError::Json)?
+            .validate()
+    }
+
+    /// Runs the scenario and checks its expectations.
+    pub fn run(&self) -> ScenarioOutcome {
+        let tick = Duration::from_millis(self.tick_ms);
+        let mut simulation = SimulationBuilder::new(self.rate_limiter(), self.generator())
+            .tick(tick)
+            .build();
+        for disturbance in &self.disturbances {
+            // Validated when the scenario was loaded
+            if let Ok(Some(disturbance)) = disturbance.injection() {
+                simulation.inject(disturbance);
+            }
+        }
+
+        let mut target_changes: Vec<(f64, f64)> = self
+            .disturbances
+            .iter()
+            .filter_map(|disturbance| match *disturbance {
+                Disturbance::TargetRate { at_secs, rate } => Some((at_secs, rate)),
+                _ => None,
+            })
+            .collect();
+        target_changes.sort_by(|a, b| a.0.total_cmp(&b.0));
+        let mut target_changes = target_changes.into_iter().peekable();
+
+        let duration = Duration::from_secs_f64(self.duration_secs);
+        let mut ticks = Vec::new();
+        while simulation.elapsed() < duration {
+            let elapsed = simulation.elapsed().as_secs_f64();
+            while let Some((_, rate)) = target_changes.next_if(|&(at_secs, _)| at_secs <= elapsed) {
+                simulation.rate_limiter_mut().set_target_rate(rate);
+            }
+            ticks.push(simulation.step());
+        }
+
+        let checks = self
+            .expect
+            .iter()
+            .map(|expectation| {
+                let to_secs = expectation.to_secs.unwrap_or(f64::INFINITY);
+                // A tick's metrics cover the tick before its end
+                let range: Vec<TickMetrics> = ticks
+                    .iter()
+                    .filter(|metrics| {
+                        let start = (metrics.elapsed - tick).as_secs_f64();
+                        start >= expectation.from_secs && metrics.elapsed.as_secs_f64() <= to_secs
+                    })
+                    .copied()
+                    .collect();
+                let value = expectation.metric.value(&range, tick);
+                let passed = value.is_some_and(|value| {
+                    expectation.min.is_none_or(|min| value >= min)
+                        && expectation.max.is_none_or(|max| value <= max)
+                });
+                Check {
+                    expectation: expectation.clone(),
+                    value,
+                    passed,
+                }
+            })
+            .collect();
+
+        ScenarioOutcome {
+            name: self.name.clone(),
+            report: SimulationReport { ticks },
+            checks,
+        }
+    }
+
+    fn validate(self) -> Result<Self, ScenarioError> {
+        if !(self.duration_secs > 0.0 && self.duration_secs.is_finite()) {
+            return Err(ScenarioError::Invalid(
+                "duration_secs must be positive".to_string(),
+            ));
+        }
+        if self.tick_ms == 0 {
+            return Err(ScenarioError::Invalid(
+                "tick_ms must be positive".to_string(),
+            ));
+        }
+        for disturbance in &self.disturbances {
+            disturbance.injection()?;
+        }
+        for expectation in &self.expect {
+            if expectation.min.is_none() && expectation.max.is_none() {
+                return Err(ScenarioError::Invalid(format!(
+                    "expectation on {} needs a min or max",
+                    expectation.metric
+                )));
+            }
+        }
+        Ok(self)
+    }
+
+    fn rate_limiter(&self) -> RateLimiterBuilder<f64> {
+        let limiter = &self.limiter;
+        let mut builder = RateLimiterBuilder::new(limiter.target_rate)
+            .min_rate(limiter.min_rate.unwrap_or(limiter.target_rate))
+            .max_rate(limiter.max_rate.unwrap_or(limiter.target_rate));
+        if let Some(update_interval_ms) = limiter.update_interval_ms {
+            builder = builder.update_interval(Duration::from_millis(update_interval_ms));
+        }
+        if let Some(pid) = &limiter.pid {
+            let mut pid_controller = PIDControllerBuilder::new(limiter.target_rate)
+                .kp(pid.kp)
+                .ki(pid.ki)
+                .kd(pid.kd);
+            if let Some(error_bias) = pid.error_bias {
+                pid_controller = pid_controller.error_bias(error_bias);
+            }
+            if let Some(error_limit) = pid.error_limit {
+                pid_controller = pid_controller.error_limit(error_limit);
+            }
+            if let Some(output_limit) = pid.output_limit {
+                pid_controller = pid_controller.output_limit(output_limit);
+            }
+            builder = builder.pid_controller(pid_controller.build());
+        }
+        builder
+    }
+
+    /// Returns the generator adding up the traffic and the traffic disturbances.
+    fn generator(&self) -> impl TrafficGenerator {
+        let generators: Vec<Box<dyn TrafficGenerator>> =
+            self.traffic.iter().map(TrafficConfig::generator).collect();
+        let surges: Vec<(f64, f64, f64)> = self
+            .disturbances
+            .iter()
+            .filter_map(|disturbance| match *disturbance {
+                Disturbance::Traffic {
+                    start_secs,
+                    duration_secs,
+                    rate,
+                } => Some((
+                    start_secs,
+                    start_secs + duration_secs.unwrap_or(f64::INFINITY),
+                    rate,
+                )),
+                _ => None,
+            })
+            .collect();
+        move |elapsed: Duration| {
+            let secs = elapsed.as_secs_f64();
+            let base: f64 = generators
+                .iter()
+                .map(|generator| generator.request_rate(elapsed))
+                .sum();
+            let surge: f64 = surges
+                .iter()
+                .filter(|&&(start, end, _)| start <= secs && secs < end)
+                .map(|&(_, _, rate)| rate)
+                .sum();
+            (base + surge).max(0.0)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FLASH_CROWD: &str = r#"
+        name = "flash crowd"
+        duration_secs = 120
+        tick_ms = 500
+
+        [limiter]
+        target_rate = 40.0
+        min_rate = 5.0
+        max_rate = 80.0
+
+        [limiter.pid]
+        kp = 0.2
+        ki = 0.05
+
+        [[traffic]]
+        type = "flash_crowd"
+        base_rate = 30.0
+        peak_rate = 300.0
+        start_secs = 30.0
+        decay_secs = 10.0
+
+        [[disturbances]]
+        type = "target_rate"
+        at_secs = 100
+        rate = 20.0
+
+        [[expect]]
+        metric = "accepted_rate"
+        to_secs = 30
+        min = 25.0
+        max = 35.0
+
+        [[expect]]
+        metric = "throttle_ratio"
+        from_secs = 30
+        to_secs = 40
+        min = 0.5
+
+        [[expect]]
+        metric = "offered_rate"
+        max = 10.0
+    "#;
+
+    #[test]
+    fn test_runs_scenario_and_checks_expectations() {
+        let scenario = Scenario::from_toml(FLASH_CROWD).unwrap();
+        assert_eq!(scenario.traffic.len(), 1);
+        let outcome = scenario.run();
+        assert_eq!(outcome.report.ticks.len(), 240);
+
+        let passed: Vec<bool> = outcome.checks.iter().map(|check| check.passed).collect();
+        assert_eq!(passed, [true, true, false]);
+        assert!(!outcome.passed());
+        let report = outcome.to_string();
+        assert!(report.starts_with("flash crowd: FAILED"), "{report}");
+        assert!(report.contains("[fail] offered_rate over 0s.."), "{report}");
+    }
+
+    #[test]
+    fn test_parses_json_and_rejects_invalid_scenarios() {
+        let scenario = Scenario::from_json(
+            r#"{
+                "duration_secs": 10,
+                "limiter": { "target_rate": 10.0 },
+                "traffic": [{ "type": "constant", "rate": 5.0 }],
+                "disturbances": [
+                    { "type": "traffic", "start_secs": 5, "rate": 10.0 },
+                    { "type": "target_rate", "at_secs": 8, "rate": 4.0 }
+                ],
+                "expect": [{ "metric": "offered_rate", "from_secs": 5, "min": 14.0, "max": 16.0 }]
+            }"#,
+        )
+        .unwrap();
+        let outcome = scenario.run();
+        assert!(outcome.passed(), "{outcome}");
+        let ticks = &outcome.report.ticks;
+        assert_eq!(ticks[79].target_rate, 10.0);
+        assert_eq!(ticks[80].target_rate, 4.0);
+
+        let err =
+            Scenario::from_toml("duration_secs = 0\n[limiter]\ntarget_rate = 1.0").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "invalid scenario: duration_secs must be positive"
+        );
+        assert!(matches!(
+            Scenario::from_json(r#"{"duration_secs": 1, "limiter": {"target": 1}}"#),
+            Err(ScenarioError::Json(_))
+        ));
+    }
+
+    #[test]
+    fn test_injects_environment_disturbances() {
+        let scenario = Scenario::from_toml(
+            r#"
+            duration_secs = 60
+            tick_ms = 1000
+
+            [limiter]
+            target_rate = 50.0
+            min_rate = 10.0
+            max_rate = 100.0
+
+            [[traffic]]
+            type = "constant"
+            rate = 80.0
+
+            [[disturbances]]
+            type = "capacity_drop"
+            start_secs = 10
+            duration_secs = 10
+            fraction = 0.6
+
+            [[disturbances]]
+            type = "peers"
+            start_secs = 30
+            profile = "ramp"
+            duration_secs = 10
+            request_rate = 20.0
+
+            [[expect]]
+            metric = "max_target_rate"
+            from_secs = 12
+            to_secs = 20
+            max = 40.0
+
+            [[expect]]
+            metric = "accepted_rate"
+            from_secs = 45
+            min = 19.0
+            max = 21.0
+            "#,
+        )
+        .unwrap();
+        let outcome = scenario.run();
+        assert!(outcome.passed(), "{outcome}");
+
+        let err = Scenario::from_toml(
+            r#"
+            duration_secs = 1
+            [limiter]
+            target_rate = 1.0
+            [[disturbances]]
+            type = "latency_spike"
+            start_secs = 0
+            profile = "ramp"
+            factor = 2.0
+            "#,
+        )
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "invalid scenario: ramp disturbances need duration_secs"
+        );
+    }
+}
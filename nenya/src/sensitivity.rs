@@ -0,0 +1,419 @@
+//! Sensitivity of a scenario's outcome to the limiter's parameters.
+//!
+//! A [`SensitivityAnalyzer`] perturbs each parameter of a [`Scenario`]'s limiter, such as the PID
+//! gains, the rate bounds, or the update interval, up and down by a relative step around the
+//! configured value, reruns the scenario in virtual time for each, and measures how far the
+//! [control quality](crate::analysis) of the run moved. Each metric's change is taken as a
+//! fraction, so they add up to one score per parameter:
+//!
+//! - max overshoot, already a fraction of the setpoint
+//! - settling time, as a fraction of the run, a run that never settled counting as the whole run
+//! - steady-state error and oscillation amplitude, as fractions of the setpoint
+//! - the throttle ratio
+//!
+//! Parameters with a high score are the ones the outcome hinges on, which operators need to get
+//! right, or which should not be exposed to them at all; those with a score near zero can be left
+//! at their defaults. Parameters that are unset or zero are left out, as a relative step would
+//! not change them.
+//!
+//! # Example
+//!
+//! ```rust
+//! use nenya::scenario::Scenario;
+//! use nenya::sensitivity::SensitivityAnalyzerBuilder;
+//!
+//! let scenario = Scenario::from_toml(
+//!     r#"
+//!     duration_secs = 300
+//!
+//!     [limiter]
+//!     target_rate = 50.0
+//!     min_rate = 10.0
+//!     max_rate = 100.0
+//!
+//!     [limiter.pid]
+//!     kp = 0.5
+//!     ki = 0.1
+//!
+//!     [[traffic]]
+//!     type = "sine"
+//!     base_rate = 50.0
+//!     amplitudes = [30.0]
+//!     frequencies = [0.02]
+//!     "#,
+//! )
+//! .unwrap();
+//!
+//! let report = SensitivityAnalyzerBuilder::new(scenario)
+//!     .step(0.2)
+//!     .build()
+//!     .analyze();
+//! println!("{}", report);
+//! println!("most sensitive to {}", report.parameters[0].parameter);
+//! ```
+
+use std::fmt;
+use std::time::Duration;
+
+use crate::analysis::{AnalyzerBuilder, ControlQuality};
+use crate::scenario::{LimiterConfig, Scenario};
+
+/// Parameter of a scenario's limiter that can be perturbed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Parameter {
+    Kp,
+    Ki,
+    Kd,
+    ErrorBias,
+    ErrorLimit,
+    OutputLimit,
+    MinRate,
+    MaxRate,
+    UpdateInterval,
+}
+
+impl Parameter {
+    pub const ALL: [Parameter; 9] = [
+        Parameter::Kp,
+        Parameter::Ki,
+        Parameter::Kd,
+        Parameter::ErrorBias,
+        Parameter::ErrorLimit,
+        Parameter::OutputLimit,
+        Parameter::MinRate,
+        Parameter::MaxRate,
+        Parameter::UpdateInterval,
+    ];
+
+    /// Returns the value of the parameter in `limiter`, or `None` if it is not set.
+    pub fn value(self, limiter: &LimiterConfig) -> Option<f64> {
+        let pid = limiter.pid.as_ref();
+        match self {
+            Parameter::Kp => pid.map(|pid| pid.kp),
+            Parameter::Ki => pid.map(|pid| pid.ki),
+            Parameter::Kd => pid.map(|pid| pid.kd),
+            Parameter::ErrorBias => pid?.error_bias,
+            Parameter::ErrorLimit => pid?.error_limit,
+            Parameter::OutputLimit => pid?.output_limit,
+            Parameter::MinRate => Some(limiter.min_rate.unwrap_or(limiter.target_rate)),
+            Parameter::MaxRate => Some(limiter.max_rate.unwrap_or(limiter.target_rate)),
+            Parameter::UpdateInterval => Some(limiter.update_interval_ms.unwrap_or(1000) as f64),
+        }
+    }
+
+    /// Sets the parameter in `limiter` to `value`, if the limiter has it.
+    pub fn set(self, limiter: &mut LimiterConfig, value: f64) {
+        match (self, limiter.pid.as_mut()) {
+            (Parameter::Kp, Some(pid)) => pid.kp = value,
+            (Parameter::Ki, Some(pid)) => pid.ki = value,
+            (Parameter::Kd, Some(pid)) => pid.kd = value,
+            (Parameter::ErrorBias, Some(pid)) => pid.error_bias = Some(value),
+            (Parameter::ErrorLimit, Some(pid)) => pid.error_limit = Some(value),
+            (Parameter::OutputLimit, Some(pid)) => pid.output_limit = Some(value),
+            (Parameter::MinRate, _) => limiter.min_rate = Some(value),
+            (Parameter::MaxRate, _) => limiter.max_rate = Some(value),
+            (Parameter::UpdateInterval, _) => {
+                limiter.update_interval_ms = Some(value.round().max(1.0) as u64)
+            }
+            _ => {}
+        }
+    }
+}
+
+impl fmt::Display for Parameter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad(match self {
+            Parameter::Kp => "kp",
+            Parameter::Ki => "ki",
+            Parameter::Kd => "kd",
+            Parameter::ErrorBias => "error_bias",
+            Parameter::ErrorLimit => "error_limit",
+            Parameter::OutputLimit => "output_limit",
+            Parameter::MinRate => "min_rate",
+            Parameter::MaxRate => "max_rate",
+            Parameter::UpdateInterval => "update_interval_ms",
+        })
+    }
+}
+
+/// Outcome of one run of the scenario.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RunOutcome {
+    pub quality: ControlQuality,
+    /// Fraction of the offered requests that were throttled.
+    pub throttle_ratio: f64,
+    /// Whether the run met the scenario's expectations.
+    pub passed: bool,
+}
+
+impl RunOutcome {
+    /// Returns the metrics of the run as fractions: the max overshoot, the settling time of the
+    /// `duration`, the steady-state error and oscillation amplitude of the `setpoint`, and the
+    /// throttle ratio.
+    fn fractions(&self, setpoint: f64, duration: Duration) -> [f64; 5] {
+        let quality = &self.quality;
+        let settling_time = quality.settling_time.unwrap_or(duration);
+        [
+            quality.max_overshoot,
+            settling_time.as_secs_f64() / duration.as_secs_f64(),
+            quality.steady_state_error.abs() / setpoint,
+            quality.oscillation_amplitude / setpoint,
+            self.throttle_ratio,
+        ]
+    }
+}
+
+/// Outcomes of a parameter perturbed down and up by the analyzer's step.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ParameterSensitivity {
+    pub parameter: Parameter,
+    /// Configured value of the parameter.
+    pub value: f64,
+    pub lower: RunOutcome,
+    pub upper: RunOutcome,
+    /// Change of each metric from the lower to the upper run, as fractions in the order of the
+    /// [module](self) documentation.
+    pub changes: [f64; 5],
+    /// Mean absolute change of the metrics per step, the sum of the changes halved.
+    pub score: f64,
+}
+
+/// Sensitivity of a scenario's outcome to each parameter, most sensitive first.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SensitivityReport {
+    pub name: String,
+    /// Relative step the parameters were perturbed by.
+    pub step: f64,
+    /// Outcome of the scenario as configured.
+    pub baseline: RunOutcome,
+    pub parameters: Vec<ParameterSensitivity>,
+}
+
+impl fmt::Display for SensitivityReport {
+    /// Formats the parameters as a table, with their score and the change of each metric from
+    /// the lower to the upper run, and which runs failed the scenario's expectations.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{}: sensitivity to a ±{:.0}% change of each parameter",
+            self.name,
+            self.step * 100.0
+        )?;
+        write!(
+            f,
+            "{:<20} {:>10} {:>7} {:>10} {:>10} {:>10} {:>10} {:>10}  expectations",
+            "parameter",
+            "value",
+            "score",
+            "overshoot",
+            "settling",
+            "error",
+            "amplitude",
+            "throttled"
+        )?;
+        for sensitivity in &self.parameters {
+            write!(
+                f,
+                "\n{:<20} {:>10.4} {:>7.3}",
+                sensitivity.parameter, sensitivity.value, sensitivity.score
+            )?;
+            for change in sensitivity.changes {
+                write!(f, " {:>+9.1}%", change * 100.0)?;
+            }
+            let expectations = match (sensitivity.lower.passed, sensitivity.upper.passed) {
+                (true, true) => "ok",
+                (false, true) => "fail when lower",
+                (true, false) => "fail when higher",
+                (false, false) => "fail",
+            };
+            write!(f, "  {}", expectations)?;
+        }
+        Ok(())
+    }
+}
+
+/// Reruns a scenario with each parameter perturbed to measure the outcome's sensitivity to it.
+#[derive(Debug, Clone)]
+pub struct SensitivityAnalyzer {
+    scenario: Scenario,
+    step: f64,
+    tolerance: f64,
+    parameters: Vec<Parameter>,
+}
+
+impl SensitivityAnalyzer {
+    /// Runs the scenario as configured and with each parameter perturbed, returning the
+    /// parameters by descending score.
+    pub fn analyze(&self) -> SensitivityReport {
+        let setpoint = self.scenario.limiter.target_rate;
+        let duration = Duration::from_secs_f64(self.scenario.duration_secs);
+        let baseline = self.run(&self.scenario.limiter);
+
+        let mut parameters: Vec<ParameterSensitivity> = self
+            .parameters
+            .iter()
+            .filter_map(|&parameter| {
+                let value = parameter
+                    .value(&self.scenario.limiter)
+                    .filter(|value| *value != 0.0)?;
+                let perturbed = |factor: f64| {
+                    let mut limiter = self.scenario.limiter.clone();
+                    parameter.set(&mut limiter, value * factor);
+                    self.run(&limiter)
+                };
+                let lower = perturbed(1.0 - self.step);
+                let upper = perturbed(1.0 + self.step);
+                let (lower_fractions, upper_fractions) = (
+                    lower.fractions(setpoint, duration),
+                    upper.fractions(setpoint, duration),
+                );
+                let changes: [f64; 5] =
+                    std::array::from_fn(|metric| upper_fractions[metric] - lower_fractions[metric]);
+                Some(ParameterSensitivity {
+                    parameter,
+                    value,
+                    lower,
+                    upper,
+                    changes,
+                    score: changes.iter().map(|change| change.abs()).sum::<f64>() / 2.0,
+                })
+            })
+            .collect();
+        parameters.sort_by(|a, b| b.score.total_cmp(&a.score));
+
+        SensitivityReport {
+            name: self.scenario.name.clone(),
+            step: self.step,
+            baseline,
+            parameters,
+        }
+    }
+
+    /// Runs the scenario with `limiter` in place of its own.
+    fn run(&self, limiter: &LimiterConfig) -> RunOutcome {
+        let scenario = Scenario {
+            limiter: limiter.clone(),
+            ..self.scenario.clone()
+        };
+        let outcome = scenario.run();
+        let analyzer = AnalyzerBuilder::new(self.scenario.limiter.target_rate)
+            .tolerance(self.tolerance)
+            .build();
+        let offered = outcome.report.offered();
+        let throttle_ratio = if offered == 0 {
+            0.0
+        } else {
+            (offered - outcome.report.accepted()) as f64 / offered as f64
+        };
+        RunOutcome {
+            quality: analyzer.analyze(&outcome.report),
+            throttle_ratio,
+            passed: outcome.passed(),
+        }
+    }
+}
+
+/// Builder for a [`SensitivityAnalyzer`].
+#[derive(Debug, Clone)]
+pub struct SensitivityAnalyzerBuilder {
+    scenario: Scenario,
+    step: f64,
+    tolerance: f64,
+    parameters: Vec<Parameter>,
+}
+
+impl SensitivityAnalyzerBuilder {
+    /// Creates a builder perturbing every parameter of `scenario` by 10%.
+    pub fn new(scenario: Scenario) -> Self {
+        SensitivityAnalyzerBuilder {
+            scenario,
+            step: 0.1,
+            tolerance: 0.05,
+            parameters: Parameter::ALL.to_vec(),
+        }
+    }
+
+    /// Sets the fraction of their value the parameters are perturbed by, below 1.
+    pub fn step(mut self, step: f64) -> Self {
+        self.step = step.clamp(f64::EPSILON, 0.99);
+        self
+    }
+
+    /// Sets the tolerance of the settling time, as a fraction of the setpoint.
+    pub fn tolerance(mut self, tolerance: f64) -> Self {
+        self.tolerance = tolerance;
+        self
+    }
+
+    /// Sets the parameters to perturb, all of them by default.
+    pub fn parameters(mut self, parameters: &[Parameter]) -> Self {
+        self.parameters = parameters.to_vec();
+        self
+    }
+
+    /// Builds and returns the `SensitivityAnalyzer` instance.
+    pub fn build(self) -> SensitivityAnalyzer {
+        SensitivityAnalyzer {
+            scenario: self.scenario,
+            step: self.step,
+            tolerance: self.tolerance,
+            parameters: self.parameters,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ranks_parameters_by_sensitivity() {
+        let scenario = Scenario::from_toml(
+            r#"
+            name = "overload"
+            duration_secs = 120
+
+            [limiter]
+            target_rate = 50.0
+            min_rate = 10.0
+            max_rate = 100.0
+
+            [limiter.pid]
+            kp = 0.5
+            ki = 0.1
+
+            [[traffic]]
+            type = "constant"
+            rate = 80.0
+
+            [[expect]]
+            metric = "accepted_rate"
+            from_secs = 60
+            min = 9.5
+            "#,
+        )
+        .unwrap();
+        let report = SensitivityAnalyzerBuilder::new(scenario)
+            .step(0.2)
+            .build()
+            .analyze();
+        assert!(report.baseline.passed);
+
+        // Overloaded, the limiter settles at its minimum rate, so the outcome hinges on it; kd is
+        // unset
+        let names: Vec<Parameter> = report.parameters.iter().map(|p| p.parameter).collect();
+        assert_eq!(names[0], Parameter::MinRate, "{report}");
+        assert!(!names.contains(&Parameter::Kd));
+        let min_rate = &report.parameters[0];
+        assert_eq!(min_rate.value, 10.0);
+        assert!(!min_rate.lower.passed && min_rate.upper.passed);
+        assert!(min_rate.changes[4] < 0.0);
+
+        let table = report.to_string();
+        assert!(
+            table.starts_with("overload: sensitivity to a ±20% change"),
+            "{table}"
+        );
+        assert!(table.contains("fail when lower"), "{table}");
+    }
+}
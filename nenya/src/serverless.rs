@@ -0,0 +1,226 @@
+//! Feedback from serverless platforms' throttling to the functions calling them.
+//!
+//! Functions on platforms such as AWS Lambda share account-level limits: every concurrent
+//! instance draws from the same concurrency and request quotas, and none of them sees what the
+//! others send. [`ThrottleFeedback`] turns the platform's throttling signals into feedback for a
+//! [`Pacer`]:
+//!
+//! - A throttled call, such as a `TooManyRequestsException` or a `429` response, pauses the pacer
+//!   for the signal's retry delay and halves its rate, like any throttled response.
+//! - With [`ThrottleFeedback::with_account_limit`], the share of the account limit used by other
+//!   instances is estimated as whatever this instance did not use when it was throttled. The
+//!   estimate is set as the rate limiter's external accepted request rate, so the instance leaves
+//!   room for the others instead of retrying into the limit. It decays with every served call.
+//!
+//! # Example
+//!
+//! ```rust
+//! use std::sync::Arc;
+//!
+//! use nenya::pacing::Pacer;
+//! use nenya::runtime::TokioTimer;
+//! use nenya::serverless::ThrottleFeedback;
+//! use nenya::RateLimiterBuilder;
+//!
+//! # async fn example() {
+//! let rate_limiter = RateLimiterBuilder::new(100.0).min_rate(1.0).build();
+//! let feedback =
+//!     ThrottleFeedback::new(Pacer::with_timer(rate_limiter, Arc::new(TokioTimer)))
+//!         .with_account_limit(100.0);
+//! feedback.acquire().await;
+//! // Invoke the downstream function, then report how the platform answered
+//! feedback.record_error_code("TooManyRequestsException", None);
+//! # }
+//! ```
+
+use num_traits::{Float, FromPrimitive, Signed};
+use std::time::Duration;
+
+use crate::pacing::{parse_retry_after, Pacer};
+
+/// Error codes AWS services use for throttled requests.
+const THROTTLING_ERROR_CODES: &[&str] = &[
+    "TooManyRequestsException",
+    "ThrottlingException",
+    "Throttling",
+    "ThrottledException",
+    "RequestThrottled",
+    "RequestThrottledException",
+    "RequestLimitExceeded",
+    "ProvisionedThroughputExceededException",
+    "SlowDown",
+];
+
+/// Fraction of the external rate estimate kept with every served call.
+const EXTERNAL_RATE_DECAY: f64 = 0.95;
+
+/// How the platform answered a call.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ThrottleSignal {
+    /// The call was throttled, optionally with how long to wait before retrying.
+    Throttled(Option<Duration>),
+    /// The call was served.
+    Served,
+    /// The call failed for a reason unrelated to throttling.
+    Other,
+}
+
+impl ThrottleSignal {
+    /// Classifies an AWS SDK error code, such as `TooManyRequestsException`.
+    pub fn from_error_code(code: &str, retry_after: Option<Duration>) -> Self {
+        if THROTTLING_ERROR_CODES.contains(&code) {
+            ThrottleSignal::Throttled(retry_after)
+        } else {
+            ThrottleSignal::Other
+        }
+    }
+
+    /// Classifies an HTTP status and its `Retry-After` header.
+    pub fn from_status(status: u16, retry_after: Option<&str>) -> Self {
+        match status {
+            429 | 503 => ThrottleSignal::Throttled(retry_after.and_then(parse_retry_after)),
+            200..=499 => ThrottleSignal::Served,
+            _ => ThrottleSignal::Other,
+        }
+    }
+}
+
+/// Feeds a platform's throttling signals into a [`Pacer`].
+#[derive(Debug)]
+pub struct ThrottleFeedback<T> {
+    pacer: Pacer<T>,
+    account_limit: Option<T>,
+}
+
+impl<T: Float + Signed + FromPrimitive + Copy> ThrottleFeedback<T> {
+    /// Creates an adapter feeding signals into `pacer`.
+    pub fn new(pacer: Pacer<T>) -> Self {
+        ThrottleFeedback {
+            pacer,
+            account_limit: None,
+        }
+    }
+
+    /// Sets the account-level limit shared with other instances, in calls per second, from which
+    /// the other instances' rate is estimated.
+    pub fn with_account_limit(mut self, account_limit: T) -> Self {
+        self.account_limit = Some(account_limit);
+        self
+    }
+
+    /// Returns the pacer the signals are fed into.
+    pub fn pacer(&self) -> &Pacer<T> {
+        &self.pacer
+    }
+
+    /// Waits until a call may be made.
+    pub async fn acquire(&self) {
+        self.pacer.acquire().await
+    }
+
+    /// Feeds the platform's answer to a call into the pacer.
+    pub fn record(&self, signal: ThrottleSignal) {
+        match signal {
+            ThrottleSignal::Throttled(retry_after) => {
+                self.pacer.record_throttled(retry_after);
+                if let Some(account_limit) = self.account_limit {
+                    self.pacer.with_rate_limiter(|rate_limiter| {
+                        let others = (account_limit - rate_limiter.local_accepted_request_rate())
+                            .max(T::zero());
+                        let external = rate_limiter.external_accepted_request_rate().max(others);
+                        rate_limiter.set_external_accepted_request_rate(external);
+                    });
+                }
+            }
+            ThrottleSignal::Served => {
+                self.pacer.record_success();
+                self.pacer.with_rate_limiter(|rate_limiter| {
+                    let decay = T::from_f64(EXTERNAL_RATE_DECAY).unwrap();
+                    let external = rate_limiter.external_accepted_request_rate() * decay;
+                    rate_limiter.set_external_accepted_request_rate(external);
+                });
+            }
+            ThrottleSignal::Other => {}
+        }
+    }
+
+    /// Feeds an AWS SDK error code into the pacer, see [`ThrottleSignal::from_error_code`].
+    pub fn record_error_code(&self, code: &str, retry_after: Option<Duration>) {
+        self.record(ThrottleSignal::from_error_code(code, retry_after));
+    }
+
+    /// Feeds an HTTP status into the pacer, see [`ThrottleSignal::from_status`].
+    pub fn record_status(&self, status: u16, retry_after: Option<&str>) {
+        self.record(ThrottleSignal::from_status(status, retry_after));
+    }
+
+    /// Returns the estimated rate of the other instances sharing the account limit.
+    pub fn external_rate(&self) -> T {
+        self.pacer
+            .with_rate_limiter(|rate_limiter| rate_limiter.external_accepted_request_rate())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runtime::{Sleep, Timer};
+    use crate::RateLimiterBuilder;
+    use std::sync::Arc;
+
+    #[derive(Debug)]
+    struct Immediate;
+
+    impl Timer for Immediate {
+        fn sleep(&self, _duration: Duration) -> Sleep {
+            Box::pin(std::future::ready(()))
+        }
+    }
+
+    #[test]
+    fn test_classifies_signals() {
+        assert_eq!(
+            ThrottleSignal::from_error_code("TooManyRequestsException", None),
+            ThrottleSignal::Throttled(None)
+        );
+        assert_eq!(
+            ThrottleSignal::from_error_code("ResourceNotFoundException", None),
+            ThrottleSignal::Other
+        );
+        assert_eq!(
+            ThrottleSignal::from_status(429, Some("3")),
+            ThrottleSignal::Throttled(Some(Duration::from_secs(3)))
+        );
+        assert_eq!(
+            ThrottleSignal::from_status(204, None),
+            ThrottleSignal::Served
+        );
+        assert_eq!(
+            ThrottleSignal::from_status(500, None),
+            ThrottleSignal::Other
+        );
+    }
+
+    #[test]
+    fn test_throttling_leaves_room_for_other_instances() {
+        let rate_limiter = RateLimiterBuilder::new(100.0)
+            .min_rate(1.0)
+            .max_rate(100.0)
+            .build();
+        let feedback = ThrottleFeedback::new(Pacer::with_timer(rate_limiter, Arc::new(Immediate)))
+            .with_account_limit(50.0);
+        // The rate is measured before each call, so the first call over the 0.1 second minimum
+        // window is seen as a local rate of 10 by the second
+        assert_eq!(feedback.pacer().try_acquire(), None);
+        assert_eq!(feedback.pacer().try_acquire(), None);
+
+        feedback.record_error_code("TooManyRequestsException", Some(Duration::ZERO));
+        assert_eq!(feedback.pacer().setpoint(), 50.0);
+        assert!((feedback.external_rate() - 40.0).abs() < 1e-3);
+
+        feedback.record_status(200, None);
+        assert!((feedback.external_rate() - 38.0).abs() < 1e-3);
+        feedback.record_error_code("ValidationException", None);
+        assert!((feedback.external_rate() - 38.0).abs() < 1e-3);
+    }
+}
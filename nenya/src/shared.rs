@@ -0,0 +1,487 @@
+//! Rate limiting shared across threads without locking on the decision path.
+//!
+//! A [`RateLimiter`] makes its decisions through `&mut self`, so threads sharing one have to
+//! serialize every decision behind a mutex, and [`contended`](crate::bench::contended) benchmarks
+//! show the lock rather than the limiter. A [`SharedRateLimiter`] decides through `&self`
+//! instead: requests are added atomically into a ring of per-quantum counters, and the target
+//! rate is published as an atomic that decisions read with relaxed ordering. Only the
+//! periodic PID update takes a lock, with `try_lock`, so a decision never waits for it; whichever
+//! thread is first to find the update due makes it.
+//!
+//! The rates are measured to within a quantum, as with
+//! [`window_quantum`](crate::RateLimiterBuilder::window_quantum), and a request counted at the
+//! instant another thread recycles its quantum's counters may go uncounted. Each decision costs
+//! the same whatever the load, and scales with the threads making them.
+//!
+//! # Example
+//!
+//! ```rust
+//! use std::sync::Arc;
+//! use std::thread;
+//!
+//! use nenya::pid_controller::PIDControllerBuilder;
+//! use nenya::shared::SharedRateLimiter;
+//! use nenya::RateLimiterBuilder;
+//!
+//! let rate_limiter = RateLimiterBuilder::new(100.0)
+//!     .min_rate(50.0)
+//!     .max_rate(200.0)
+//!     .pid_controller(PIDControllerBuilder::new(100.0).kp(0.1).build())
+//!     .build();
+//! let limiter = Arc::new(SharedRateLimiter::new(rate_limiter));
+//!
+//! let workers: Vec<_> = (0..4)
+//!     .map(|_| {
+//!         let limiter = limiter.clone();
+//!         thread::spawn(move || (0..100).filter(|_| !limiter.should_throttle()).count())
+//!     })
+//!     .collect();
+//! let accepted: usize = workers.into_iter().map(|worker| worker.join().unwrap()).sum();
+//! println!("accepted {} of 400 requests", accepted);
+//! ```
+
+use num_traits::{Float, FromPrimitive, Signed};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::clock::{Clock, Timestamp};
+use crate::pid_controller::PIDController;
+use crate::request_window::{bucket_count, quantum_of, rate_since, start_of};
+use crate::RateLimiter;
+
+/// Number of quanta the sliding window is divided into by default.
+const DEFAULT_QUANTA: u32 = 10;
+
+/// Marks a bucket that has not counted any quantum yet.
+const UNUSED: u64 = u64::MAX;
+
+/// Marks a tally that has not counted any request since its bucket was recycled.
+const EMPTY: u64 = u64::MAX;
+
+/// Sliding window rate limiter with a PID-controlled target rate, deciding through `&self` without
+/// locking.
+#[derive(Debug)]
+pub struct SharedRateLimiter<T> {
+    quantum: Duration,
+    update_interval: Duration,
+    buckets: Box<[Bucket]>,
+    /// Bits of the target rate as an `f64`.
+    target_rate: AtomicU64,
+    /// Bits of the external request rate as an `f64`.
+    external_request_rate: AtomicU64,
+    /// Bits of the external accepted request rate as an `f64`.
+    external_accepted_request_rate: AtomicU64,
+    /// Nanoseconds since the clock's epoch at which the next PID update is due.
+    next_update: AtomicU64,
+    control: Mutex<Control<T>>,
+    clock: Arc<dyn Clock>,
+}
+
+/// Requests of one quantum of time.
+#[derive(Debug)]
+struct Bucket {
+    /// Quantum counted, as whole quanta since the clock's epoch.
+    quantum: AtomicU64,
+    requests: Tally,
+    accepted: Tally,
+}
+
+/// Weighted requests of a bucket.
+#[derive(Debug)]
+struct Tally {
+    /// Bits of the summed weight as an `f64`.
+    weight: AtomicU64,
+    /// Nanoseconds since the clock's epoch of the earliest request, from which the window's rate
+    /// is measured.
+    oldest: AtomicU64,
+}
+
+/// State of the PID update, touched only under the lock.
+#[derive(Debug)]
+struct Control<T> {
+    pid_controller: PIDController<T>,
+    target_rate: T,
+    fixed_target_rate: Option<T>,
+    min_rate: T,
+    max_rate: T,
+    last_updated: Timestamp,
+}
+
+impl<T: Float + Signed + FromPrimitive + Copy> SharedRateLimiter<T> {
+    /// Shares `rate_limiter`, dividing its window into tenths of its update interval.
+    ///
+    /// The limiter's PID controller, target rate, bounds, external rates, and clock carry over;
+    /// the requests it observed do not.
+    pub fn new(rate_limiter: RateLimiter<T>) -> Self {
+        let quantum = rate_limiter.update_interval / DEFAULT_QUANTA;
+        Self::with_quantum(rate_limiter, quantum)
+    }
+
+    /// Shares `rate_limiter`, counting its requests per `quantum`.
+    ///
+    /// As with [`window_quantum`](crate::RateLimiterBuilder::window_quantum), the window holds
+    /// one more quantum than the update interval needs, for the quantum partly outside it.
+    pub fn with_quantum(rate_limiter: RateLimiter<T>, quantum: Duration) -> Self {
+        let quantum = quantum.max(Duration::from_nanos(1));
+        let update_interval = rate_limiter.update_interval;
+        let buckets = (0..bucket_count(quantum, update_interval))
+            .map(|_| Bucket {
+                quantum: AtomicU64::new(UNUSED),
+                requests: Tally::new(),
+                accepted: Tally::new(),
+            })
+            .collect();
+        let now = rate_limiter.clock.now();
+        SharedRateLimiter {
+            quantum,
+            update_interval,
+            buckets,
+            target_rate: AtomicU64::new(to_bits(rate_limiter.target_rate())),
+            external_request_rate: AtomicU64::new(to_bits(rate_limiter.external_request_rate)),
+            external_accepted_request_rate: AtomicU64::new(to_bits(
+                rate_limiter.external_accepted_request_rate,
+            )),
+            next_update: AtomicU64::new(nanos(now + update_interval)),
+            control: Mutex::new(Control {
+                pid_controller: rate_limiter.pid_controller,
+                target_rate: rate_limiter.target_rate,
+                fixed_target_rate: rate_limiter.fixed_target_rate,
+                min_rate: rate_limiter.min_rate,
+                max_rate: rate_limiter.max_rate,
+                last_updated: now,
+            }),
+            clock: rate_limiter.clock,
+        }
+    }
+
+    /// Determines if the current request should be throttled.
+    ///
+    /// Returns `true` if the request should be throttled, `false` otherwise.
+    pub fn should_throttle(&self) -> bool {
+        self.should_throttle_weighted(T::one())
+    }
+
+    /// Determines if a request that consumes `cost` units of the rate budget should be throttled,
+    /// as [`RateLimiter::should_throttle_weighted`] does.
+    ///
+    /// A NaN, infinite, or negative cost is throttled without being counted toward either rate.
+    ///
+    /// Returns `true` if the request should be throttled, `false` otherwise.
+    pub fn should_throttle_weighted(&self, cost: impl Into<T>) -> bool {
+        let cost = match cost.into().to_f64() {
+            Some(cost) if cost.is_finite() && cost >= 0.0 => cost,
+            _ => return true,
+        };
+        let now = self.clock.now();
+        if nanos(now) > self.next_update.load(Ordering::Relaxed) {
+            self.update(now);
+        }
+
+        let quantum = quantum_of(now, self.quantum);
+        let accepted_rate = self.window_rate(quantum, now, |bucket| &bucket.accepted)
+            + load(&self.external_accepted_request_rate);
+        let throttled = accepted_rate > load(&self.target_rate);
+
+        let bucket = self.bucket(quantum);
+        if !throttled {
+            bucket.accepted.add(now, cost);
+        }
+        bucket.requests.add(now, cost);
+        throttled
+    }
+
+    /// Returns the current target rate.
+    pub fn target_rate(&self) -> T {
+        from_f64(load(&self.target_rate))
+    }
+
+    /// Returns the rate of requests offered over the window, including the external request
+    /// rate.
+    pub fn request_rate(&self) -> T {
+        let now = self.clock.now();
+        let local = self.window_rate(quantum_of(now, self.quantum), now, |bucket| {
+            &bucket.requests
+        });
+        from_f64(local + load(&self.external_request_rate))
+    }
+
+    /// Returns the rate of requests accepted over the window, including the external accepted
+    /// request rate.
+    pub fn accepted_request_rate(&self) -> T {
+        let now = self.clock.now();
+        let local = self.window_rate(quantum_of(now, self.quantum), now, |bucket| {
+            &bucket.accepted
+        });
+        from_f64(local + load(&self.external_accepted_request_rate))
+    }
+
+    /// Returns the current external request rate.
+    pub fn external_request_rate(&self) -> T {
+        from_f64(load(&self.external_request_rate))
+    }
+
+    /// Sets the external request rate.
+    ///
+    /// A NaN rate is ignored, and a negative one is treated as zero.
+    pub fn set_external_request_rate(&self, external_request_rate: impl Into<T>) {
+        let rate = crate::sanitize_external_rate(
+            self.external_request_rate(),
+            external_request_rate.into(),
+        );
+        self.external_request_rate
+            .store(to_bits(rate), Ordering::Relaxed);
+    }
+
+    /// Returns the current external accepted request rate.
+    pub fn external_accepted_request_rate(&self) -> T {
+        from_f64(load(&self.external_accepted_request_rate))
+    }
+
+    /// Sets the external accepted request rate.
+    ///
+    /// A NaN rate is ignored, and a negative one is treated as zero.
+    pub fn set_external_accepted_request_rate(&self, external_accepted_request_rate: impl Into<T>) {
+        let rate = crate::sanitize_external_rate(
+            self.external_accepted_request_rate(),
+            external_accepted_request_rate.into(),
+        );
+        self.external_accepted_request_rate
+            .store(to_bits(rate), Ordering::Relaxed);
+    }
+
+    /// Returns the quantum requests are counted per.
+    pub fn quantum(&self) -> Duration {
+        self.quantum
+    }
+
+    /// Returns the clock the rate limiter reads the time from.
+    pub fn clock(&self) -> &Arc<dyn Clock> {
+        &self.clock
+    }
+
+    /// Updates the target rate with the PID controller, unless another thread holds the lock,
+    /// and so is already updating it.
+    fn update(&self, now: Timestamp) {
+        let Ok(mut control) = self.control.try_lock() else {
+            return;
+        };
+        // Another thread may have made the update between the check and the lock
+        if now.duration_since(control.last_updated) <= self.update_interval {
+            return;
+        }
+        control.last_updated = now;
+        self.next_update
+            .store(nanos(now + self.update_interval), Ordering::Relaxed);
+        if control.fixed_target_rate.is_some() {
+            return;
+        }
+
+        let local = self.window_rate(quantum_of(now, self.quantum), now, |bucket| {
+            &bucket.requests
+        });
+        let Some(request_rate) = T::from_f64(local + load(&self.external_request_rate)) else {
+            return;
+        };
+        let output = control.pid_controller.compute_correction(request_rate);
+        control.target_rate = num_traits::clamp(
+            control.target_rate + output,
+            control.min_rate,
+            control.max_rate,
+        );
+        self.target_rate
+            .store(to_bits(control.target_rate), Ordering::Relaxed);
+    }
+
+    /// Returns the bucket of `quantum`, recycling it if it still holds an older quantum.
+    fn bucket(&self, quantum: u64) -> &Bucket {
+        let bucket = &self.buckets[(quantum % self.buckets.len() as u64) as usize];
+        let held = bucket.quantum.load(Ordering::Acquire);
+        if (held == UNUSED || held < quantum)
+            && bucket
+                .quantum
+                .compare_exchange(held, quantum, Ordering::AcqRel, Ordering::Acquire)
+                .is_ok()
+        {
+            bucket.requests.reset();
+            bucket.accepted.reset();
+        }
+        bucket
+    }
+
+    /// Returns the summed weight per second of the `tally` of each bucket in the window ending
+    /// with `quantum`, measured from the earliest request they hold.
+    ///
+    /// A bucket leaves the window once its quantum started more than the update interval before
+    /// `now`, as it does for a bucketed [`RateLimiter`].
+    fn window_rate(&self, quantum: u64, now: Timestamp, tally: impl Fn(&Bucket) -> &Tally) -> f64 {
+        let (mut weight, mut earliest) = (0.0, None);
+        for bucket in self.buckets.iter() {
+            let held = bucket.quantum.load(Ordering::Acquire);
+            if held == UNUSED || held > quantum {
+                continue;
+            }
+            let start = start_of(held, self.quantum);
+            if now.duration_since(start) > self.update_interval {
+                continue;
+            }
+            let tally = tally(bucket);
+            let oldest = tally.oldest.load(Ordering::Relaxed);
+            if oldest == EMPTY {
+                continue;
+            }
+            // A bucket being recycled may still hold the time of its previous quantum
+            let oldest = oldest.max(nanos(start));
+            weight += load(&tally.weight);
+            earliest = Some(earliest.map_or(oldest, |earliest: u64| earliest.min(oldest)));
+        }
+        match earliest {
+            Some(oldest) => rate_since(weight, timestamp(oldest), now),
+            None => 0.0,
+        }
+    }
+}
+
+impl Tally {
+    fn new() -> Self {
+        Tally {
+            weight: AtomicU64::new(0f64.to_bits()),
+            oldest: AtomicU64::new(EMPTY),
+        }
+    }
+
+    /// Counts a request of `weight` at `now`.
+    fn add(&self, now: Timestamp, weight: f64) {
+        self.oldest.fetch_min(nanos(now), Ordering::Relaxed);
+        // The closure always returns `Some`, so the update cannot fail
+        let _ = self
+            .weight
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |bits| {
+                Some((f64::from_bits(bits) + weight).to_bits())
+            });
+    }
+
+    fn reset(&self) {
+        self.weight.store(0f64.to_bits(), Ordering::Relaxed);
+        self.oldest.store(EMPTY, Ordering::Relaxed);
+    }
+}
+
+fn load(rate: &AtomicU64) -> f64 {
+    f64::from_bits(rate.load(Ordering::Relaxed))
+}
+
+/// Returns the bits of `rate` as an `f64`, or of zero if it has no `f64` representation.
+fn to_bits<T: Float>(rate: T) -> u64 {
+    rate.to_f64().unwrap_or_default().to_bits()
+}
+
+/// Converts a rate measured as an `f64` to `T`, or zero if `T` cannot represent it.
+fn from_f64<T: Float + FromPrimitive>(rate: f64) -> T {
+    T::from_f64(rate).unwrap_or_else(T::zero)
+}
+
+fn nanos(timestamp: Timestamp) -> u64 {
+    timestamp.as_duration().as_nanos() as u64
+}
+
+fn timestamp(nanos: u64) -> Timestamp {
+    Timestamp::from_duration(Duration::from_nanos(nanos))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::ManualClock;
+    use crate::pid_controller::PIDControllerBuilder;
+    use crate::RateLimiterBuilder;
+    use std::thread;
+
+    #[test]
+    fn test_throttles_to_target_across_threads() {
+        let clock = ManualClock::new();
+        clock.advance(Duration::from_secs(1));
+        let rate_limiter = RateLimiterBuilder::new(100.0)
+            .clock(Arc::new(clock.clone()))
+            .build();
+        let limiter = SharedRateLimiter::new(rate_limiter);
+        assert_eq!(limiter.quantum(), Duration::from_millis(100));
+
+        // Four threads offer 1000 requests per second between them for ten seconds
+        let accepted: usize = thread::scope(|scope| {
+            let workers: Vec<_> = (0..4)
+                .map(|_| {
+                    scope.spawn(|| {
+                        (0..2500)
+                            .filter(|_| {
+                                clock.advance(Duration::from_millis(1));
+                                !limiter.should_throttle()
+                            })
+                            .count()
+                    })
+                })
+                .collect();
+            workers
+                .into_iter()
+                .map(|worker| worker.join().unwrap())
+                .sum()
+        });
+        assert!(accepted.abs_diff(1000) <= 100, "accepted {accepted}");
+        assert!((limiter.request_rate() - 1000.0).abs() < 100.0);
+        assert!((limiter.accepted_request_rate() - 100.0).abs() < 20.0);
+    }
+
+    #[test]
+    fn test_weighted_and_external_rates() {
+        let clock = ManualClock::new();
+        let rate_limiter = RateLimiterBuilder::new(100.0)
+            .clock(Arc::new(clock.clone()))
+            .build();
+        let limiter = SharedRateLimiter::new(rate_limiter);
+
+        assert!(!limiter.should_throttle_weighted(50.0));
+        clock.advance(Duration::from_millis(500));
+        assert_eq!(limiter.accepted_request_rate(), 100.0);
+        for cost in [f64::NAN, f64::INFINITY, f64::NEG_INFINITY, -1.0] {
+            assert!(limiter.should_throttle_weighted(cost));
+        }
+        assert_eq!(limiter.request_rate(), 100.0);
+
+        limiter.set_external_request_rate(20.0);
+        limiter.set_external_accepted_request_rate(f64::NAN);
+        assert_eq!(limiter.request_rate(), 120.0);
+        assert_eq!(limiter.external_accepted_request_rate(), 0.0);
+
+        // Within the target on its own, the external accepted rate tips the window over it
+        assert!(!limiter.should_throttle());
+        limiter.set_external_accepted_request_rate(5.0);
+        assert!(limiter.should_throttle());
+    }
+
+    #[test]
+    fn test_pid_update_adjusts_target_rate() {
+        let clock = ManualClock::new();
+        let rate_limiter = RateLimiterBuilder::new(100.0)
+            .min_rate(50.0)
+            .max_rate(200.0)
+            .pid_controller(PIDControllerBuilder::new(100.0).kp(0.1).build())
+            .clock(Arc::new(clock.clone()))
+            .build();
+        let limiter = SharedRateLimiter::new(rate_limiter);
+
+        // Offered 500 requests per second against a setpoint of 100, the target falls to the
+        // minimum rate
+        for _ in 0..10_000 {
+            clock.advance(Duration::from_millis(2));
+            limiter.should_throttle();
+        }
+        assert_eq!(limiter.target_rate(), 50.0);
+        let accepted = (0..500)
+            .filter(|_| {
+                clock.advance(Duration::from_millis(2));
+                !limiter.should_throttle()
+            })
+            .count();
+        assert!(accepted.abs_diff(50) <= 10, "accepted {accepted}");
+    }
+}
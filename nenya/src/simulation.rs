@@ -0,0 +1,1699 @@
+//! Synthetic traffic for tuning rate limiters.
+//!
+//! A [`TrafficGenerator`] describes how the offered request rate changes over a simulation, so
+//! a limiter configuration can be tried against varying load before it meets production traffic.
+//! [`SineWaveGenerator`] models the periodic load typical of services: a base rate with sine
+//! waves on top, e.g. a slow daily cycle and faster bursts. Any closure from the elapsed time to
+//! a rate is a generator too.
+//!
+//! Worst-case patterns show how far a limiter overshoots and how long it takes to recover:
+//! [`OnOffGenerator`] alternates between bursts and quiet periods, and [`FlashCrowdGenerator`]
+//! jumps to a peak that decays back to the base rate.
+//!
+//! Rates hide how requests arrive within a second. Real traffic is bursty at small timescales,
+//! which [`PoissonArrivals`] models by drawing individual arrival times: exponentially
+//! distributed gaps at a fixed rate, or at a rate given by any generator over time.
+//! [`TraceReplay`] replays the arrival times of recorded traffic instead, such as timestamps
+//! exported from access logs or a [`Recording`](crate::recording::Recording).
+//!
+//! With the `pid` feature, a [`Simulation`] runs a rate limiter against a generator or arrival
+//! times on a virtual clock, without sleeping, and records every tick in a [`SimulationReport`]
+//! that can be written as CSV or JSON for offline analysis. An hour of traffic runs in
+//! milliseconds and always gives the same result, which makes simulations usable as regression
+//! tests of controller settings in CI.
+//!
+//! To evaluate recovery rather than steady-state tracking, a [`Disturbance`] injected into a
+//! simulation changes the limiter's environment mid-run: a sudden capacity drop or latency spike
+//! lowers its maximum rate, as a [`CapacityEstimator`] would, and peers joining or leaving the
+//! cluster change its external rates. Each takes effect as a step, a temporary impulse, or a
+//! linear ramp.
+//!
+//! # Example
+//!
+//! ```rust
+//! use std::time::Duration;
+//!
+//! use nenya::simulation::{SineWaveGenerator, TrafficGenerator};
+//!
+//! // 50 requests per second, swinging by 20 every 10 seconds and by 5 every second
+//! let generator = SineWaveGenerator::new(50.0, vec![20.0, 5.0], vec![0.1, 1.0]);
+//!
+//! let mut elapsed = Duration::ZERO;
+//! while elapsed < Duration::from_secs(60) {
+//!     let rate = generator.request_rate(elapsed);
+//!     // Send `rate` requests per second to the limiter for the next 100 ms
+//!     elapsed += Duration::from_millis(100);
+//! }
+//! ```
+//!
+//! [`CapacityEstimator`]: crate::capacity::CapacityEstimator
+
+use std::f64::consts::PI;
+use std::fmt;
+use std::io::{self, BufRead};
+use std::time::Duration;
+
+/// Offered request rate over the course of a simulation.
+pub trait TrafficGenerator {
+    /// Returns the request rate, in requests per second, `elapsed` into the simulation.
+    fn request_rate(&self, elapsed: Duration) -> f64;
+}
+
+impl<F> TrafficGenerator for F
+where
+    F: Fn(Duration) -> f64,
+{
+    fn request_rate(&self, elapsed: Duration) -> f64 {
+        self(elapsed)
+    }
+}
+
+/// Request rate made of a base rate and sine waves added on top of it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SineWaveGenerator {
+    /// Rate the waves oscillate around, in requests per second.
+    pub base_rate: f64,
+    /// Amplitude of each wave, in requests per second.
+    pub amplitudes: Vec<f64>,
+    /// Frequency of each wave, in hertz.
+    pub frequencies: Vec<f64>,
+}
+
+impl SineWaveGenerator {
+    /// Creates a generator adding a wave for each pair of `amplitudes` and `frequencies` to
+    /// `base_rate`.
+    ///
+    /// Amplitudes without a frequency, or the other way around, are ignored.
+    pub fn new(base_rate: f64, amplitudes: Vec<f64>, frequencies: Vec<f64>) -> Self {
+        SineWaveGenerator {
+            base_rate,
+            amplitudes,
+            frequencies,
+        }
+    }
+}
+
+impl TrafficGenerator for SineWaveGenerator {
+    /// Returns the sum of the base rate and the waves, or zero where the waves pull it below
+    /// zero.
+    fn request_rate(&self, elapsed: Duration) -> f64 {
+        let secs = elapsed.as_secs_f64();
+        let rate = self
+            .amplitudes
+            .iter()
+            .zip(&self.frequencies)
+            .fold(self.base_rate, |rate, (amplitude, frequency)| {
+                rate + amplitude * (2.0 * PI * frequency * secs).sin()
+            });
+        rate.max(0.0)
+    }
+}
+
+/// Request rate alternating between bursts and quiet periods.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OnOffGenerator {
+    /// Rate during a burst, in requests per second.
+    pub on_rate: f64,
+    /// Rate between bursts, in requests per second.
+    pub off_rate: f64,
+    /// Duration of a burst.
+    pub on_duration: Duration,
+    /// Duration of the quiet period after a burst.
+    pub off_duration: Duration,
+}
+
+impl OnOffGenerator {
+    /// Creates a generator starting with a burst at `on_rate` for `on_duration`, followed by
+    /// `off_rate` for `off_duration`, repeating.
+    pub fn new(on_rate: f64, off_rate: f64, on_duration: Duration, off_duration: Duration) -> Self {
+        OnOffGenerator {
+            on_rate,
+            off_rate,
+            on_duration,
+            off_duration,
+        }
+    }
+}
+
+impl TrafficGenerator for OnOffGenerator {
+    fn request_rate(&self, elapsed: Duration) -> f64 {
+        let period = self.on_duration + self.off_duration;
+        if period.is_zero() {
+            return self.off_rate;
+        }
+        let phase = elapsed.as_nanos() % period.as_nanos();
+        if phase < self.on_duration.as_nanos() {
+            self.on_rate
+        } else {
+            self.off_rate
+        }
+    }
+}
+
+/// Request rate stepping up to a peak at once, then decaying exponentially back to its base,
+/// like the crowd following a link from a popular page.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FlashCrowdGenerator {
+    /// Rate before and long after the crowd, in requests per second.
+    pub base_rate: f64,
+    /// Rate the crowd arrives at, in requests per second.
+    pub peak_rate: f64,
+    /// When the crowd arrives.
+    pub start: Duration,
+    /// Time for the rate above the base to decay to `1/e` of the peak above the base.
+    pub decay: Duration,
+}
+
+impl FlashCrowdGenerator {
+    /// Creates a generator at `base_rate` that jumps to `peak_rate` at `start` and decays back
+    /// with time constant `decay`.
+    pub fn new(base_rate: f64, peak_rate: f64, start: Duration, decay: Duration) -> Self {
+        FlashCrowdGenerator {
+            base_rate,
+            peak_rate,
+            start,
+            decay,
+        }
+    }
+}
+
+impl TrafficGenerator for FlashCrowdGenerator {
+    fn request_rate(&self, elapsed: Duration) -> f64 {
+        let Some(since_start) = elapsed.checked_sub(self.start) else {
+            return self.base_rate;
+        };
+        if self.decay.is_zero() {
+            return if since_start.is_zero() {
+                self.peak_rate
+            } else {
+                self.base_rate
+            };
+        }
+        let remaining = (-since_start.as_secs_f64() / self.decay.as_secs_f64()).exp();
+        self.base_rate + (self.peak_rate - self.base_rate) * remaining
+    }
+}
+
+/// Arrival times of a Poisson process, as the time since the start of the simulation.
+///
+/// The gaps between arrivals are exponentially distributed, so requests cluster and spread out
+/// the way independent clients do. The arrivals are drawn from a seeded generator, so the same
+/// seed gives the same traffic.
+///
+/// # Example
+///
+/// ```rust
+/// use std::time::Duration;
+///
+/// use nenya::simulation::PoissonArrivals;
+///
+/// // Requests arriving at 100 per second on average during the first 10 seconds
+/// let arrivals: Vec<Duration> = PoissonArrivals::new(100.0, 42)
+///     .take_while(|&arrival| arrival < Duration::from_secs(10))
+///     .collect();
+/// ```
+pub struct PoissonArrivals {
+    rate: Box<dyn TrafficGenerator + Send>,
+    max_rate: f64,
+    rng: Rng,
+    elapsed: f64,
+}
+
+impl PoissonArrivals {
+    /// Creates arrivals at `rate` requests per second on average, drawn with `seed`.
+    pub fn new(rate: f64, seed: u64) -> Self {
+        Self::time_varying(move |_: Duration| rate, rate, seed)
+    }
+
+    /// Creates arrivals at the rate of `generator` over time, drawn with `seed`.
+    ///
+    /// Arrivals are drawn at `max_rate` and each is kept with the probability of the generator's
+    /// rate at that time over `max_rate`, so rates above `max_rate` are capped to it.
+    pub fn time_varying(
+        generator: impl TrafficGenerator + Send + 'static,
+        max_rate: f64,
+        seed: u64,
+    ) -> Self {
+        PoissonArrivals {
+            rate: Box::new(generator),
+            max_rate,
+            rng: Rng::new(seed),
+            elapsed: 0.0,
+        }
+    }
+}
+
+impl Iterator for PoissonArrivals {
+    type Item = Duration;
+
+    /// Returns the time of the next arrival, or `None` if the maximum rate is not positive.
+    fn next(&mut self) -> Option<Duration> {
+        if self.max_rate <= 0.0 || !self.max_rate.is_finite() {
+            return None;
+        }
+        loop {
+            self.elapsed += self.rng.exponential(self.max_rate);
+            let elapsed = Duration::from_secs_f64(self.elapsed);
+            let rate = self.rate.request_rate(elapsed);
+            if self.rng.next_f64() * self.max_rate < rate {
+                return Some(elapsed);
+            }
+        }
+    }
+}
+
+impl fmt::Debug for PoissonArrivals {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PoissonArrivals")
+            .field("max_rate", &self.max_rate)
+            .field("elapsed", &Duration::from_secs_f64(self.elapsed))
+            .finish_non_exhaustive()
+    }
+}
+
+/// Arrival times of recorded requests, as the time since the first of them.
+///
+/// # Example
+///
+/// ```rust
+/// use std::time::Duration;
+///
+/// use nenya::simulation::TraceReplay;
+///
+/// let log = "timestamp,path\n1717000000.25,/\n1717000000.5,/cart\n1717000001.25,/\n";
+/// // Replay the trace at twice the recorded speed
+/// let trace = TraceReplay::from_csv(log.as_bytes(), 0).unwrap().speed(2.0);
+/// assert_eq!(trace.duration(), Duration::from_millis(500));
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct TraceReplay {
+    /// Seconds since the first arrival, sorted.
+    arrivals: Vec<f64>,
+    speed: f64,
+    next: usize,
+}
+
+impl TraceReplay {
+    /// Creates a replay of requests arriving at `timestamps`, in seconds, e.g. Unix timestamps.
+    ///
+    /// The timestamps do not need to be sorted.
+    pub fn new(mut timestamps: Vec<f64>) -> Self {
+        timestamps.retain(|timestamp| timestamp.is_finite());
+        timestamps.sort_by(f64::total_cmp);
+        let first = timestamps.first().copied().unwrap_or_default();
+        TraceReplay {
+            arrivals: timestamps
+                .into_iter()
+                .map(|timestamp| timestamp - first)
+                .collect(),
+            speed: 1.0,
+            next: 0,
+        }
+    }
+
+    /// Reads the timestamps, in seconds, from `column` of a CSV file, counting from zero.
+    ///
+    /// A first line whose column is not a number is skipped as the header. Empty lines are
+    /// ignored, and any other line without a timestamp is an error.
+    pub fn from_csv(reader: impl BufRead, column: usize) -> io::Result<Self> {
+        let mut timestamps = Vec::new();
+        for (index, line) in reader.lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let timestamp = line
+                .split(',')
+                .nth(column)
+                .and_then(|field| field.trim().trim_matches('"').parse::<f64>().ok());
+            match timestamp {
+                Some(timestamp) => timestamps.push(timestamp),
+                None if index == 0 => {}
+                None => {
+                    return Err(invalid_line(
+                        index,
+                        &format!("no timestamp in column {column}"),
+                    ))
+                }
+            }
+        }
+        Ok(Self::new(timestamps))
+    }
+
+    /// Reads the timestamps, in seconds, from `field` of each object of a JSON Lines file.
+    ///
+    /// Empty lines are ignored, and any other line without a numeric timestamp is an error.
+    #[cfg(feature = "serde")]
+    pub fn from_jsonl(reader: impl BufRead, field: &str) -> io::Result<Self> {
+        let mut timestamps = Vec::new();
+        for (index, line) in reader.lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let value: serde_json::Value =
+                serde_json::from_str(&line).map_err(|err| invalid_line(index, &err.to_string()))?;
+            let timestamp = value
+                .get(field)
+                .and_then(serde_json::Value::as_f64)
+                .ok_or_else(|| invalid_line(index, &format!("no numeric field `{field}`")))?;
+            timestamps.push(timestamp);
+        }
+        Ok(Self::new(timestamps))
+    }
+
+    /// Replays the trace `multiplier` times faster than recorded, or slower below 1.
+    pub fn speed(mut self, multiplier: f64) -> Self {
+        self.speed = multiplier;
+        self
+    }
+
+    /// Returns the number of requests in the trace.
+    pub fn len(&self) -> usize {
+        self.arrivals.len()
+    }
+
+    /// Returns `true` if the trace holds no requests.
+    pub fn is_empty(&self) -> bool {
+        self.arrivals.is_empty()
+    }
+
+    /// Returns the time between the first and last request at the replay speed.
+    pub fn duration(&self) -> Duration {
+        self.arrivals
+            .last()
+            .map_or(Duration::ZERO, |&last| self.scale(last))
+    }
+
+    /// Converts recorded seconds to the time they are replayed at.
+    fn scale(&self, secs: f64) -> Duration {
+        Duration::from_secs_f64(secs / self.speed)
+    }
+}
+
+impl Iterator for TraceReplay {
+    type Item = Duration;
+
+    /// Returns the time of the next request at the replay speed.
+    fn next(&mut self) -> Option<Duration> {
+        let arrival = *self.arrivals.get(self.next)?;
+        self.next += 1;
+        Some(self.scale(arrival))
+    }
+}
+
+/// Returns the error for an unreadable line of a trace, numbered from one.
+fn invalid_line(index: usize, message: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("line {}: {}", index + 1, message),
+    )
+}
+
+#[cfg(feature = "pid")]
+pub use self::runner::*;
+
+#[cfg(feature = "pid")]
+mod runner {
+    use std::collections::VecDeque;
+    use std::io::{self, BufRead, Write};
+    use std::iter::Peekable;
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    use num_traits::{Float, FromPrimitive, Signed};
+
+    use super::TrafficGenerator;
+    use crate::clock::ManualClock;
+    use crate::feedback::ResponseFeedback;
+    use crate::queueing::QueueSimulator;
+    use crate::{RateLimiter, RateLimiterBuilder};
+
+    /// Columns of [`SimulationReport::write_csv`].
+    const CSV_HEADER: &str = "elapsed,generated_rate,offered,accepted,throttled,target_rate,\
+        request_rate,accepted_rate,pid_p,pid_i,pid_d,pid_output,latency,error_rate";
+
+    /// Where the requests of a simulation come from.
+    enum Source {
+        /// Requests offered at a generator's rate, spread evenly over each tick.
+        Rate {
+            generator: Box<dyn TrafficGenerator>,
+            /// Fraction of a request carried over to the next tick.
+            owed: f64,
+        },
+        /// Requests arriving at the given times since the start of the simulation.
+        Arrivals(Peekable<Box<dyn Iterator<Item = Duration>>>),
+    }
+
+    /// Rate limiter driven by traffic on a virtual clock.
+    ///
+    /// Each tick, the requests arriving during the tick are sent to the limiter in order, with
+    /// the clock set to their arrival times, and the clock then moves to the end of the tick.
+    /// Hours of traffic run in milliseconds, and the same scenario always gives the same result.
+    ///
+    /// Other components can share the [virtual clock](Simulation::clock), e.g. a CoDel queue or
+    /// a probing controller observing the same requests.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use std::time::Duration;
+    ///
+    /// use nenya::simulation::{SimulationBuilder, SineWaveGenerator};
+    /// use nenya::RateLimiterBuilder;
+    ///
+    /// let generator = SineWaveGenerator::new(50.0, vec![20.0], vec![0.1]);
+    /// let mut simulation = SimulationBuilder::new(RateLimiterBuilder::new(40.0), generator).build();
+    /// let report = simulation.run(Duration::from_secs(60));
+    ///
+    /// let mut csv = Vec::new();
+    /// report.write_csv(&mut csv).unwrap();
+    /// ```
+    pub struct Simulation<T> {
+        rate_limiter: RateLimiter<T>,
+        source: Source,
+        clock: ManualClock,
+        tick: Duration,
+        /// Virtual time at the end of the last tick.
+        elapsed: Duration,
+        disturbances: Vec<Disturbance>,
+        /// Rate bounds of the limiter before capacity disturbances changed them.
+        undisturbed_bounds: Option<(T, T)>,
+        /// Request and accepted rates the peer disturbances added to the external rates.
+        peer_rates: (f64, f64),
+        downstream: Option<Downstream>,
+        feedback: Option<Arc<Mutex<ResponseFeedback<T>>>>,
+        /// End and accepted requests of the ticks within the downstream's window.
+        downstream_window: VecDeque<(Duration, u64)>,
+        queue: Option<QueueSimulator>,
+    }
+
+    impl<T: Float + Signed + FromPrimitive + Copy> Simulation<T> {
+        /// Runs the simulation for `duration` of virtual time, returning the metrics of every
+        /// tick.
+        pub fn run(&mut self, duration: Duration) -> SimulationReport {
+            let end = self.elapsed + duration;
+            let mut ticks = Vec::new();
+            while self.elapsed < end {
+                ticks.push(self.step());
+            }
+            SimulationReport { ticks }
+        }
+
+        /// Runs a single tick, returning its metrics.
+        pub fn step(&mut self) -> TickMetrics {
+            let start = self.elapsed;
+            let end = start + self.tick;
+            self.apply_disturbances(start);
+            let (generated_rate, arrivals) = self.arrivals(start, end);
+
+            let mut now = start;
+            let mut throttled = 0;
+            for arrival in &arrivals {
+                self.clock.advance(arrival.saturating_sub(now));
+                now = now.max(*arrival);
+                let throttle = self.rate_limiter.should_throttle();
+                throttled += u64::from(throttle);
+                if let (false, Some(queue)) = (throttle, &mut self.queue) {
+                    queue.arrive(*arrival);
+                }
+            }
+            self.clock.advance(end - now);
+            self.elapsed = end;
+            let offered = arrivals.len() as u64;
+            let (latency, error_rate) = self.respond(offered - throttled);
+
+            let to_f64 = |value: T| value.to_f64().unwrap_or_default();
+            let correction = self.rate_limiter.pid_controller().last_correction();
+            TickMetrics {
+                elapsed: self.elapsed,
+                generated_rate,
+                offered,
+                accepted: offered - throttled,
+                throttled,
+                target_rate: to_f64(self.rate_limiter.target_rate()),
+                request_rate: to_f64(self.rate_limiter.request_rate()),
+                accepted_rate: to_f64(self.rate_limiter.accepted_request_rate()),
+                pid_p: correction.map_or(0.0, |correction| to_f64(correction.p)),
+                pid_i: correction.map_or(0.0, |correction| to_f64(correction.i)),
+                pid_d: correction.map_or(0.0, |correction| to_f64(correction.d)),
+                pid_output: correction.map_or(0.0, |correction| to_f64(correction.output)),
+                latency: latency.as_secs_f64(),
+                error_rate,
+            }
+        }
+
+        /// Sends the `accepted` requests of the last tick to the downstream, feeding its
+        /// responses back to the limiter. Returns their latency and the fraction that failed.
+        fn respond(&mut self, accepted: u64) -> (Duration, f64) {
+            let Some(downstream) = &self.downstream else {
+                return (Duration::ZERO, 0.0);
+            };
+            self.downstream_window.push_back((self.elapsed, accepted));
+            while let Some(&(end, _)) = self.downstream_window.front() {
+                if end + downstream.window > self.elapsed {
+                    break;
+                }
+                self.downstream_window.pop_front();
+            }
+            let window = downstream.window.min(self.elapsed).max(self.tick);
+            let requests: u64 = self
+                .downstream_window
+                .iter()
+                .map(|(_, accepted)| accepted)
+                .sum();
+            let load = requests as f64 / window.as_secs_f64();
+            let latency = downstream.latency(load);
+            let error_rate = downstream.error_rate(load);
+            if let Some(feedback) = &self.feedback {
+                let mut feedback = feedback.lock().unwrap();
+                let failed = (error_rate * accepted as f64).round() as u64;
+                for response in 0..accepted {
+                    feedback.observe_setpoint(latency, response < failed, &mut self.rate_limiter);
+                }
+            }
+            (latency, error_rate)
+        }
+
+        /// Injects `disturbance` into the simulation, taking effect from the first tick starting
+        /// at or after its start.
+        pub fn inject(&mut self, disturbance: Disturbance) {
+            self.disturbances.push(disturbance);
+        }
+
+        /// Sets the limiter's rate bounds and external rates to the effect of the disturbances
+        /// at `elapsed`.
+        ///
+        /// Capacity disturbances scale the bounds the limiter had before the first of them took
+        /// effect, which are restored once none does; peer disturbances add to the external
+        /// rates, so rates set in between are kept.
+        fn apply_disturbances(&mut self, elapsed: Duration) {
+            if self.disturbances.is_empty() {
+                return;
+            }
+            let mut capacity = 1.0;
+            let mut peer_rates = (0.0, 0.0);
+            for disturbance in &self.disturbances {
+                let level = disturbance.level(elapsed);
+                match disturbance.kind {
+                    DisturbanceKind::CapacityDrop { fraction } => {
+                        capacity *= 1.0 - fraction * level
+                    }
+                    DisturbanceKind::LatencySpike { factor } => {
+                        // With the concurrency fixed, capacity is inversely proportional to
+                        // latency by Little's law
+                        capacity /= 1.0 + (factor - 1.0) * level
+                    }
+                    DisturbanceKind::Peers {
+                        request_rate,
+                        accepted_rate,
+                    } => {
+                        peer_rates.0 += request_rate * level;
+                        peer_rates.1 += accepted_rate * level;
+                    }
+                }
+            }
+
+            let from_f64 = |value: f64| T::from_f64(value).unwrap_or_else(T::zero);
+            let rate_limiter = &mut self.rate_limiter;
+            if capacity != 1.0 {
+                let (min_rate, max_rate) = *self
+                    .undisturbed_bounds
+                    .get_or_insert((rate_limiter.min_rate(), rate_limiter.max_rate()));
+                let max_rate = max_rate * from_f64(capacity.max(0.0));
+                rate_limiter.set_rate_bounds(min_rate.min(max_rate), max_rate);
+            } else if let Some((min_rate, max_rate)) = self.undisturbed_bounds.take() {
+                rate_limiter.set_rate_bounds(min_rate, max_rate);
+            }
+
+            if peer_rates != self.peer_rates {
+                let request_rate = rate_limiter.external_request_rate()
+                    + from_f64(peer_rates.0 - self.peer_rates.0);
+                let accepted_rate = rate_limiter.external_accepted_request_rate()
+                    + from_f64(peer_rates.1 - self.peer_rates.1);
+                rate_limiter.set_external_request_rate(request_rate.max(T::zero()));
+                rate_limiter.set_external_accepted_request_rate(accepted_rate.max(T::zero()));
+                self.peer_rates = peer_rates;
+            }
+        }
+
+        /// Returns the offered rate and the arrival times of the requests between `start` and
+        /// `end`.
+        fn arrivals(&mut self, start: Duration, end: Duration) -> (f64, Vec<Duration>) {
+            match &mut self.source {
+                Source::Rate { generator, owed } => {
+                    // A NaN or infinite rate offers no requests rather than unbounded ones
+                    let rate = generator.request_rate(start);
+                    let rate = if rate.is_finite() { rate.max(0.0) } else { 0.0 };
+                    *owed += rate * (end - start).as_secs_f64();
+                    let count = owed.floor();
+                    *owed -= count;
+                    let gap = (end - start).div_f64(count + 1.0);
+                    let arrivals = (1..=count as u32).map(|k| start + gap * k).collect();
+                    (rate, arrivals)
+                }
+                Source::Arrivals(arrivals) => {
+                    let mut due = Vec::new();
+                    while let Some(arrival) = arrivals.next_if(|&arrival| arrival < end) {
+                        due.push(arrival);
+                    }
+                    let rate = due.len() as f64 / (end - start).as_secs_f64();
+                    (rate, due)
+                }
+            }
+        }
+
+        /// Returns the downstream the accepted requests are sent to, if any.
+        pub fn downstream(&self) -> Option<&Downstream> {
+            self.downstream.as_ref()
+        }
+
+        /// Returns the downstream the accepted requests are sent to, e.g. to change its capacity
+        /// between ticks.
+        pub fn downstream_mut(&mut self) -> Option<&mut Downstream> {
+            self.downstream.as_mut()
+        }
+
+        /// Returns the queue serving the accepted requests, if any.
+        pub fn queue(&self) -> Option<&QueueSimulator> {
+            self.queue.as_ref()
+        }
+
+        /// Returns the simulated rate limiter.
+        pub fn rate_limiter(&self) -> &RateLimiter<T> {
+            &self.rate_limiter
+        }
+
+        /// Returns the simulated rate limiter, e.g. to change its target rate between ticks.
+        pub fn rate_limiter_mut(&mut self) -> &mut RateLimiter<T> {
+            &mut self.rate_limiter
+        }
+
+        /// Returns the virtual clock of the simulation, which is at the end of the last tick.
+        pub fn clock(&self) -> &ManualClock {
+            &self.clock
+        }
+
+        /// Returns the virtual time elapsed since the start of the simulation.
+        pub fn elapsed(&self) -> Duration {
+            self.elapsed
+        }
+    }
+
+    /// Builder for creating a `Simulation` instance.
+    pub struct SimulationBuilder<T> {
+        rate_limiter: RateLimiterBuilder<T>,
+        source: Source,
+        tick: Duration,
+        disturbances: Vec<Disturbance>,
+        downstream: Option<Downstream>,
+        feedback: Option<Arc<Mutex<ResponseFeedback<T>>>>,
+        queue: Option<QueueSimulator>,
+    }
+
+    impl<T: Float + Signed + FromPrimitive + Copy> SimulationBuilder<T> {
+        /// Creates a new `SimulationBuilder` sending the traffic of `generator` to the rate
+        /// limiter built by `rate_limiter`, in ticks of 100 ms.
+        ///
+        /// The rate limiter's clock is replaced by the simulation's virtual clock.
+        pub fn new(
+            rate_limiter: RateLimiterBuilder<T>,
+            generator: impl TrafficGenerator + 'static,
+        ) -> Self {
+            SimulationBuilder {
+                rate_limiter,
+                source: Source::Rate {
+                    generator: Box::new(generator),
+                    owed: 0.0,
+                },
+                tick: Duration::from_millis(100),
+                disturbances: Vec::new(),
+                downstream: None,
+                feedback: None,
+                queue: None,
+            }
+        }
+
+        /// Creates a new `SimulationBuilder` sending requests to the rate limiter built by
+        /// `rate_limiter` at `arrivals`, the times since the start of the simulation, in ticks of
+        /// 100 ms.
+        ///
+        /// The arrivals can be any sorted times, such as [`PoissonArrivals`] or a
+        /// [`TraceReplay`]. The rate limiter's clock is replaced by the simulation's virtual
+        /// clock.
+        ///
+        /// [`PoissonArrivals`]: super::PoissonArrivals
+        /// [`TraceReplay`]: super::TraceReplay
+        pub fn from_arrivals(
+            rate_limiter: RateLimiterBuilder<T>,
+            arrivals: impl Iterator<Item = Duration> + 'static,
+        ) -> Self {
+            let arrivals: Box<dyn Iterator<Item = Duration>> = Box::new(arrivals);
+            SimulationBuilder {
+                rate_limiter,
+                source: Source::Arrivals(arrivals.peekable()),
+                tick: Duration::from_millis(100),
+                disturbances: Vec::new(),
+                downstream: None,
+                feedback: None,
+                queue: None,
+            }
+        }
+
+        /// Sets the virtual time between recorded metrics.
+        pub fn tick(mut self, tick: Duration) -> Self {
+            self.tick = tick;
+            self
+        }
+
+        /// Adds a disturbance to inject into the simulation.
+        pub fn disturbance(mut self, disturbance: Disturbance) -> Self {
+            self.disturbances.push(disturbance);
+            self
+        }
+
+        /// Sends the requests the limiter accepts to `downstream`, recording the latency and
+        /// error rate of its responses every tick.
+        pub fn downstream(mut self, downstream: Downstream) -> Self {
+            self.downstream = Some(downstream);
+            self
+        }
+
+        /// Lowers the limiter's setpoint while the smoothed latency of the downstream's responses
+        /// exceeds `target_latency`, as the `latency_target` of the `tower`, `axum`, and `tonic`
+        /// middleware does. Has no effect without a [`downstream`](Self::downstream).
+        pub fn latency_target(mut self, target_latency: Duration) -> Self {
+            ResponseFeedback::configure(&mut self.feedback).set_latency_target(target_latency);
+            self
+        }
+
+        /// Lowers the limiter's setpoint while the smoothed fraction of the downstream's
+        /// responses that failed exceeds `error_target`, as the `error_target` of the middleware
+        /// does. Has no effect without a [`downstream`](Self::downstream).
+        pub fn error_target(mut self, error_target: f64) -> Self {
+            ResponseFeedback::configure(&mut self.feedback).set_error_target(error_target);
+            self
+        }
+
+        /// Serves the requests the limiter accepts with `queue`, to compare the queue length and
+        /// latency they cause with a [queueing model](crate::queueing).
+        pub fn queue(mut self, queue: QueueSimulator) -> Self {
+            self.queue = Some(queue);
+            self
+        }
+
+        /// Builds and returns the `Simulation` instance.
+        pub fn build(self) -> Simulation<T> {
+            let clock = ManualClock::new();
+            Simulation {
+                rate_limiter: self.rate_limiter.clock(Arc::new(clock.clone())).build(),
+                source: self.source,
+                clock,
+                tick: self.tick,
+                elapsed: Duration::ZERO,
+                disturbances: self.disturbances,
+                undisturbed_bounds: None,
+                peer_rates: (0.0, 0.0),
+                downstream: self.downstream,
+                feedback: self.feedback,
+                downstream_window: VecDeque::new(),
+                queue: self.queue,
+            }
+        }
+    }
+
+    /// Service behind a simulated limiter whose latency and errors grow with the rate of
+    /// requests the limiter admits, so the signals fed back to the limiter depend on its own
+    /// decisions, as they do in production.
+    ///
+    /// Up to the knee, a fraction of the capacity, responses take the base latency. Past it,
+    /// queues build up and the latency grows without bound as the load nears the capacity, until
+    /// requests time out. Requests beyond the capacity fail. The load is averaged over a window,
+    /// so bursts within it are absorbed like a queue would.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use std::time::Duration;
+    ///
+    /// use nenya::pid_controller::PIDControllerBuilder;
+    /// use nenya::simulation::{Downstream, SimulationBuilder};
+    /// use nenya::RateLimiterBuilder;
+    ///
+    /// let rate_limiter = RateLimiterBuilder::new(100.0)
+    ///     .min_rate(10.0)
+    ///     .max_rate(100.0)
+    ///     .pid_controller(PIDControllerBuilder::new(100.0).kp(0.5).ki(0.1).build());
+    /// let mut simulation = SimulationBuilder::new(rate_limiter, |_: Duration| 100.0)
+    ///     .downstream(Downstream::new(60.0, Duration::from_millis(20)))
+    ///     .latency_target(Duration::from_millis(40))
+    ///     .build();
+    /// let report = simulation.run(Duration::from_secs(120));
+    /// let last = report.ticks.last().unwrap();
+    /// assert!(last.latency < 0.04 && last.error_rate == 0.0);
+    /// ```
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub struct Downstream {
+        capacity: f64,
+        base_latency: Duration,
+        knee: f64,
+        timeout: Duration,
+        window: Duration,
+    }
+
+    impl Downstream {
+        /// Creates a downstream serving up to `capacity` requests per second, in `base_latency`
+        /// each while lightly loaded. The knee is at 70% of the capacity, requests time out after
+        /// 20 times the base latency, and the load is averaged over one second.
+        pub fn new(capacity: f64, base_latency: Duration) -> Self {
+            Downstream {
+                capacity,
+                base_latency,
+                knee: 0.7,
+                timeout: base_latency * 20,
+                window: Duration::from_secs(1),
+            }
+        }
+
+        /// Sets the fraction of the capacity from which the latency grows, below 1.
+        pub fn knee(mut self, knee: f64) -> Self {
+            self.knee = knee.clamp(0.0, 0.99);
+            self
+        }
+
+        /// Sets the latency at which requests time out.
+        pub fn timeout(mut self, timeout: Duration) -> Self {
+            self.timeout = timeout;
+            self
+        }
+
+        /// Sets the period the load is averaged over, e.g. the time the downstream's queues take
+        /// to drain.
+        pub fn window(mut self, window: Duration) -> Self {
+            self.window = window;
+            self
+        }
+
+        /// Returns the requests per second the downstream serves at most.
+        pub fn capacity(&self) -> f64 {
+            self.capacity
+        }
+
+        /// Sets the requests per second the downstream serves at most, e.g. to simulate an
+        /// outage.
+        pub fn set_capacity(&mut self, capacity: f64) {
+            self.capacity = capacity;
+        }
+
+        /// Returns the latency of responses while the downstream receives `load` requests per
+        /// second.
+        pub fn latency(&self, load: f64) -> Duration {
+            let utilization = if self.capacity > 0.0 {
+                load / self.capacity
+            } else {
+                f64::INFINITY
+            };
+            if utilization.is_nan() || utilization <= self.knee {
+                return self.base_latency.min(self.timeout);
+            }
+            if utilization >= 1.0 {
+                return self.timeout;
+            }
+            // Continuous at the knee, like the waiting time of a queue nearing saturation
+            let factor = (1.0 - self.knee) / (1.0 - utilization);
+            self.base_latency.mul_f64(factor).min(self.timeout)
+        }
+
+        /// Returns the fraction of requests that fail while the downstream receives `load`
+        /// requests per second.
+        pub fn error_rate(&self, load: f64) -> f64 {
+            if load <= self.capacity.max(0.0) || load.is_nan() {
+                0.0
+            } else {
+                1.0 - self.capacity.max(0.0) / load
+            }
+        }
+    }
+
+    /// How the effect of a [`Disturbance`] changes from its start.
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub enum Profile {
+        /// Full effect from the start on.
+        Step,
+        /// Full effect for the duration, then none.
+        Impulse(Duration),
+        /// Effect growing linearly from none to full over the duration, then held.
+        Ramp(Duration),
+    }
+
+    /// What a [`Disturbance`] changes at full effect.
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub enum DisturbanceKind {
+        /// The downstream loses `fraction` of its capacity, lowering the limiter's maximum rate
+        /// by that fraction.
+        CapacityDrop { fraction: f64 },
+        /// The downstream's latency grows by `factor`, dividing the limiter's maximum rate by it
+        /// as the capacity of a downstream with fixed concurrency shrinks.
+        LatencySpike { factor: f64 },
+        /// Peers join the cluster, adding their rates to the limiter's external rates, or leave
+        /// it if the rates are negative.
+        Peers {
+            request_rate: f64,
+            accepted_rate: f64,
+        },
+    }
+
+    /// Change to a simulated limiter's environment, injected to evaluate how it recovers.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use std::time::Duration;
+    ///
+    /// use nenya::analysis::AnalyzerBuilder;
+    /// use nenya::pid_controller::PIDControllerBuilder;
+    /// use nenya::simulation::{Disturbance, DisturbanceKind, SimulationBuilder};
+    /// use nenya::RateLimiterBuilder;
+    ///
+    /// // Half the capacity is lost for 30 seconds after a minute
+    /// let outage = Disturbance::impulse(
+    ///     Duration::from_secs(60),
+    ///     Duration::from_secs(30),
+    ///     DisturbanceKind::CapacityDrop { fraction: 0.5 },
+    /// );
+    /// let rate_limiter = RateLimiterBuilder::new(50.0)
+    ///     .min_rate(10.0)
+    ///     .max_rate(100.0)
+    ///     .pid_controller(PIDControllerBuilder::new(50.0).kp(0.5).ki(0.1).build());
+    /// let report = SimulationBuilder::new(rate_limiter, |_: Duration| 40.0)
+    ///     .disturbance(outage)
+    ///     .build()
+    ///     .run(Duration::from_secs(180));
+    ///
+    /// let recovery = AnalyzerBuilder::new(50.0)
+    ///     .start(Duration::from_secs(90))
+    ///     .build()
+    ///     .analyze(&report);
+    /// println!("recovered after {:?}", recovery.settling_time);
+    /// ```
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub struct Disturbance {
+        pub kind: DisturbanceKind,
+        /// Virtual time since the start of the simulation the disturbance starts at.
+        pub start: Duration,
+        pub profile: Profile,
+    }
+
+    impl Disturbance {
+        /// Creates a disturbance taking full effect at `start` and lasting until the end.
+        pub fn step(start: Duration, kind: DisturbanceKind) -> Self {
+            Disturbance {
+                kind,
+                start,
+                profile: Profile::Step,
+            }
+        }
+
+        /// Creates a disturbance taking full effect at `start` and ending after `duration`.
+        pub fn impulse(start: Duration, duration: Duration, kind: DisturbanceKind) -> Self {
+            Disturbance {
+                kind,
+                start,
+                profile: Profile::Impulse(duration),
+            }
+        }
+
+        /// Creates a disturbance growing from `start` to full effect over `duration`.
+        pub fn ramp(start: Duration, duration: Duration, kind: DisturbanceKind) -> Self {
+            Disturbance {
+                kind,
+                start,
+                profile: Profile::Ramp(duration),
+            }
+        }
+
+        /// Returns the share of the full effect at `elapsed`, from 0 to 1.
+        pub fn level(&self, elapsed: Duration) -> f64 {
+            let Some(since) = elapsed.checked_sub(self.start) else {
+                return 0.0;
+            };
+            match self.profile {
+                Profile::Step => 1.0,
+                Profile::Impulse(duration) if since < duration => 1.0,
+                Profile::Impulse(_) => 0.0,
+                Profile::Ramp(duration) if since < duration => {
+                    since.as_secs_f64() / duration.as_secs_f64()
+                }
+                Profile::Ramp(_) => 1.0,
+            }
+        }
+    }
+
+    /// State of a simulation at the end of a tick.
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    #[cfg_attr(feature = "serde", derive(serde::Serialize))]
+    pub struct TickMetrics {
+        /// Virtual time since the start of the simulation.
+        pub elapsed: Duration,
+        /// Rate of the generator at the start of the tick, or the rate requests arrived at
+        /// during the tick.
+        pub generated_rate: f64,
+        /// Requests sent to the limiter during the tick.
+        pub offered: u64,
+        /// Requests the limiter accepted during the tick.
+        pub accepted: u64,
+        /// Requests the limiter throttled during the tick.
+        pub throttled: u64,
+        /// The limiter's target rate.
+        pub target_rate: f64,
+        /// The limiter's request rate.
+        pub request_rate: f64,
+        /// The limiter's accepted request rate.
+        pub accepted_rate: f64,
+        /// The proportional term of the last PID correction.
+        pub pid_p: f64,
+        /// The integral term of the last PID correction.
+        pub pid_i: f64,
+        /// The derivative term of the last PID correction.
+        pub pid_d: f64,
+        /// The last PID correction applied to the target rate.
+        pub pid_output: f64,
+        /// Latency of the downstream's responses during the tick, in seconds, or zero without a
+        /// [`Downstream`].
+        pub latency: f64,
+        /// Fraction of the downstream's responses that failed during the tick.
+        pub error_rate: f64,
+    }
+
+    impl TickMetrics {
+        /// Returns the values in the order of the CSV columns.
+        pub(crate) fn values(&self) -> [(&'static str, f64); 14] {
+            [
+                ("elapsed", self.elapsed.as_secs_f64()),
+                ("generated_rate", self.generated_rate),
+                ("offered", self.offered as f64),
+                ("accepted", self.accepted as f64),
+                ("throttled", self.throttled as f64),
+                ("target_rate", self.target_rate),
+                ("request_rate", self.request_rate),
+                ("accepted_rate", self.accepted_rate),
+                ("pid_p", self.pid_p),
+                ("pid_i", self.pid_i),
+                ("pid_d", self.pid_d),
+                ("pid_output", self.pid_output),
+                ("latency", self.latency),
+                ("error_rate", self.error_rate),
+            ]
+        }
+
+        /// Returns the value of the CSV column named `column`, with the elapsed time in seconds,
+        /// or `None` if there is no such column.
+        pub fn value(&self, column: &str) -> Option<f64> {
+            self.values()
+                .iter()
+                .find(|(name, _)| *name == column)
+                .map(|(_, value)| *value)
+        }
+
+        /// Creates metrics from values named like the CSV columns. Unknown names are ignored,
+        /// and missing values are zero.
+        fn from_values<'a>(values: impl IntoIterator<Item = (&'a str, f64)>) -> Self {
+            let count = |value: f64| value.max(0.0).round() as u64;
+            let mut tick = TickMetrics {
+                elapsed: Duration::ZERO,
+                generated_rate: 0.0,
+                offered: 0,
+                accepted: 0,
+                throttled: 0,
+                target_rate: 0.0,
+                request_rate: 0.0,
+                accepted_rate: 0.0,
+                pid_p: 0.0,
+                pid_i: 0.0,
+                pid_d: 0.0,
+                pid_output: 0.0,
+                latency: 0.0,
+                error_rate: 0.0,
+            };
+            for (name, value) in values {
+                match name {
+                    // Rounded to whole nanoseconds, which seconds in decimal rarely are exactly
+                    "elapsed" if value.is_finite() && value >= 0.0 => {
+                        tick.elapsed = Duration::from_nanos((value * 1e9).round() as u64)
+                    }
+                    "generated_rate" => tick.generated_rate = value,
+                    "offered" => tick.offered = count(value),
+                    "accepted" => tick.accepted = count(value),
+                    "throttled" => tick.throttled = count(value),
+                    "target_rate" => tick.target_rate = value,
+                    "request_rate" => tick.request_rate = value,
+                    "accepted_rate" => tick.accepted_rate = value,
+                    "pid_p" => tick.pid_p = value,
+                    "pid_i" => tick.pid_i = value,
+                    "pid_d" => tick.pid_d = value,
+                    "pid_output" => tick.pid_output = value,
+                    "latency" => tick.latency = value,
+                    "error_rate" => tick.error_rate = value,
+                    _ => {}
+                }
+            }
+            tick
+        }
+    }
+
+    /// Metrics of every tick of a simulation run.
+    #[derive(Debug, Clone, Default, PartialEq)]
+    #[cfg_attr(feature = "serde", derive(serde::Serialize))]
+    pub struct SimulationReport {
+        pub ticks: Vec<TickMetrics>,
+    }
+
+    impl SimulationReport {
+        /// Returns the number of requests sent to the limiter.
+        pub fn offered(&self) -> u64 {
+            self.ticks.iter().map(|tick| tick.offered).sum()
+        }
+
+        /// Returns the number of requests the limiter accepted.
+        pub fn accepted(&self) -> u64 {
+            self.ticks.iter().map(|tick| tick.accepted).sum()
+        }
+
+        /// Reads metrics written by [`write_csv`](Self::write_csv), e.g. to compare runs.
+        ///
+        /// Columns are matched by the names in the header, so other columns are ignored and
+        /// missing ones read as zero. Empty lines are ignored, and any other line with a value
+        /// that is not a number is an error.
+        pub fn read_csv(reader: impl BufRead) -> io::Result<Self> {
+            let mut lines = reader.lines();
+            let header = match lines.next() {
+                Some(header) => header?,
+                None => return Ok(SimulationReport::default()),
+            };
+            let columns: Vec<String> = header
+                .split(',')
+                .map(|column| column.trim().to_string())
+                .collect();
+            let mut ticks = Vec::new();
+            for (index, line) in lines.enumerate() {
+                let line = line?;
+                if line.trim().is_empty() {
+                    continue;
+                }
+                let values = line
+                    .split(',')
+                    .map(|value| value.trim().parse::<f64>())
+                    .collect::<Result<Vec<f64>, _>>()
+                    // Numbered from the line after the header
+                    .map_err(|err| super::invalid_line(index + 1, &err.to_string()))?;
+                ticks.push(TickMetrics::from_values(
+                    columns.iter().map(String::as_str).zip(values),
+                ));
+            }
+            Ok(SimulationReport { ticks })
+        }
+
+        /// Reads metrics written by [`write_json`](Self::write_json), matching fields by name
+        /// like [`read_csv`](Self::read_csv).
+        #[cfg(feature = "serde")]
+        pub fn read_json(reader: impl io::Read) -> io::Result<Self> {
+            let ticks: Vec<serde_json::Map<String, serde_json::Value>> =
+                serde_json::from_reader(reader)
+                    .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+            let ticks = ticks
+                .iter()
+                .map(|fields| {
+                    TickMetrics::from_values(
+                        fields
+                            .iter()
+                            .filter_map(|(name, value)| Some((name.as_str(), value.as_f64()?))),
+                    )
+                })
+                .collect();
+            Ok(SimulationReport { ticks })
+        }
+
+        /// Writes the metrics as CSV, one row per tick, with the elapsed time in seconds.
+        pub fn write_csv(&self, mut writer: impl Write) -> io::Result<()> {
+            writeln!(writer, "{}", CSV_HEADER)?;
+            for tick in &self.ticks {
+                let row: Vec<String> = tick
+                    .values()
+                    .iter()
+                    .map(|(_, value)| value.to_string())
+                    .collect();
+                writeln!(writer, "{}", row.join(","))?;
+            }
+            Ok(())
+        }
+
+        /// Writes the metrics as a JSON array, one object per tick, with the elapsed time in
+        /// seconds.
+        pub fn write_json(&self, mut writer: impl Write) -> io::Result<()> {
+            write!(writer, "[")?;
+            for (index, tick) in self.ticks.iter().enumerate() {
+                let fields: Vec<String> = tick
+                    .values()
+                    .iter()
+                    .map(|(name, value)| {
+                        // JSON has no representation of NaN or infinity
+                        let value = if value.is_finite() { *value } else { 0.0 };
+                        format!("\"{}\":{}", name, value)
+                    })
+                    .collect();
+                let separator = if index == 0 { "" } else { "," };
+                write!(writer, "{}\n{{{}}}", separator, fields.join(","))?;
+            }
+            writeln!(writer, "\n]")
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::clock::Clock;
+        use crate::pid_controller::PIDControllerBuilder;
+        use crate::simulation::PoissonArrivals;
+
+        fn simulation(offered_rate: f64) -> Simulation<f64> {
+            let rate_limiter = RateLimiterBuilder::new(50.0)
+                .min_rate(10.0)
+                .max_rate(100.0)
+                .pid_controller(PIDControllerBuilder::new(50.0).kp(0.5).ki(0.1).build());
+            SimulationBuilder::new(rate_limiter, move |_: Duration| offered_rate).build()
+        }
+
+        #[test]
+        fn test_runs_in_virtual_time() {
+            let mut simulation = simulation(80.0);
+            let report = simulation.run(Duration::from_secs(3600));
+            assert_eq!(simulation.elapsed(), Duration::from_secs(3600));
+            assert_eq!(report.ticks.len(), 36_000);
+            assert_eq!(report.offered(), 288_000);
+
+            // Once the controller has settled, the limiter admits its target rate
+            let last_minute = SimulationReport {
+                ticks: report.ticks[report.ticks.len() - 600..].to_vec(),
+            };
+            let accepted_rate = last_minute.accepted() as f64 / 60.0;
+            let target_rate = last_minute.ticks[599].target_rate;
+            assert!(
+                (accepted_rate - target_rate).abs() < target_rate * 0.1,
+                "{accepted_rate} != {target_rate}"
+            );
+            assert!(report.ticks.iter().any(|tick| tick.pid_output != 0.0));
+        }
+
+        #[test]
+        fn test_replays_arrivals_deterministically() {
+            let run = |seed| {
+                let rate_limiter = RateLimiterBuilder::new(50.0);
+                let arrivals = PoissonArrivals::new(80.0, seed);
+                SimulationBuilder::from_arrivals(rate_limiter, arrivals)
+                    .tick(Duration::from_secs(1))
+                    .build()
+                    .run(Duration::from_secs(3600))
+            };
+            let report = run(11);
+            assert_eq!(report, run(11));
+            assert_ne!(report, run(12));
+
+            let offered_rate = report.offered() as f64 / 3600.0;
+            assert!((78.0..82.0).contains(&offered_rate), "{offered_rate}");
+            let accepted_rate = report.accepted() as f64 / 3600.0;
+            assert!((47.0..53.0).contains(&accepted_rate), "{accepted_rate}");
+        }
+
+        #[test]
+        fn test_arrivals_keep_their_times() {
+            let arrivals = [100, 150, 250, 1000].map(Duration::from_millis);
+            let mut simulation = SimulationBuilder::from_arrivals(
+                RateLimiterBuilder::new(10.0),
+                arrivals.into_iter(),
+            )
+            .build();
+            let offered: Vec<u64> = (0..3).map(|_| simulation.step().offered).collect();
+            assert_eq!(offered, [0, 2, 1]);
+            assert_eq!(
+                simulation.clock().now().as_duration(),
+                Duration::from_millis(300)
+            );
+            let report = simulation.run(Duration::from_secs(1));
+            assert_eq!(report.offered(), 1);
+        }
+
+        #[test]
+        fn test_disturbance_profiles() {
+            let kind = DisturbanceKind::CapacityDrop { fraction: 0.5 };
+            let start = Duration::from_secs(10);
+            let step = Disturbance::step(start, kind);
+            let impulse = Disturbance::impulse(start, Duration::from_secs(5), kind);
+            let ramp = Disturbance::ramp(start, Duration::from_secs(4), kind);
+
+            let levels = |disturbance: Disturbance| {
+                [5, 10, 11, 15, 60].map(|secs| disturbance.level(Duration::from_secs(secs)))
+            };
+            assert_eq!(levels(step), [0.0, 1.0, 1.0, 1.0, 1.0]);
+            assert_eq!(levels(impulse), [0.0, 1.0, 1.0, 0.0, 0.0]);
+            assert_eq!(levels(ramp), [0.0, 0.0, 0.25, 1.0, 1.0]);
+        }
+
+        #[test]
+        fn test_capacity_disturbances_scale_max_rate() {
+            let rate_limiter = RateLimiterBuilder::new(50.0).min_rate(10.0).max_rate(100.0);
+            let mut simulation = SimulationBuilder::new(rate_limiter, |_: Duration| 80.0)
+                .tick(Duration::from_secs(1))
+                .disturbance(Disturbance::impulse(
+                    Duration::from_secs(10),
+                    Duration::from_secs(20),
+                    DisturbanceKind::CapacityDrop { fraction: 0.6 },
+                ))
+                .disturbance(Disturbance::ramp(
+                    Duration::from_secs(40),
+                    Duration::from_secs(10),
+                    DisturbanceKind::LatencySpike { factor: 5.0 },
+                ))
+                .build();
+
+            let before = simulation.run(Duration::from_secs(10));
+            assert_eq!(before.ticks[9].accepted, 50);
+
+            // The limiter's target is clamped to the lost capacity while the drop lasts
+            let outage = simulation.run(Duration::from_secs(20));
+            assert_eq!(simulation.rate_limiter().max_rate(), 40.0);
+            assert_eq!(outage.ticks[19].target_rate, 40.0);
+            assert_eq!(outage.ticks[19].accepted, 40);
+
+            simulation.run(Duration::from_secs(10));
+            assert_eq!(simulation.rate_limiter().max_rate(), 100.0);
+            assert_eq!(simulation.rate_limiter().min_rate(), 10.0);
+
+            // Halfway up the ramp the latency has tripled, a third of the capacity
+            simulation.run(Duration::from_secs(6));
+            assert!((simulation.rate_limiter().max_rate() - 100.0 / 3.0).abs() < 1e-9);
+            simulation.run(Duration::from_secs(10));
+            assert_eq!(simulation.rate_limiter().max_rate(), 20.0);
+            assert_eq!(simulation.rate_limiter().min_rate(), 10.0);
+        }
+
+        #[test]
+        fn test_peer_disturbances_change_external_rates() {
+            let rate_limiter = RateLimiterBuilder::new(50.0).external_request_rate(5.0);
+            let mut simulation = SimulationBuilder::new(rate_limiter, |_: Duration| 80.0)
+                .tick(Duration::from_secs(1))
+                .build();
+            simulation.run(Duration::from_secs(10));
+
+            // Peers join mid-run and take part of the target rate, then one leaves
+            let peers = |request_rate, accepted_rate| DisturbanceKind::Peers {
+                request_rate,
+                accepted_rate,
+            };
+            simulation.inject(Disturbance::step(
+                Duration::from_secs(10),
+                peers(60.0, 30.0),
+            ));
+            simulation.inject(Disturbance::step(
+                Duration::from_secs(40),
+                peers(-30.0, -15.0),
+            ));
+            let joined = simulation.run(Duration::from_secs(30));
+            assert_eq!(simulation.rate_limiter().external_request_rate(), 65.0);
+            assert_eq!(
+                simulation.rate_limiter().external_accepted_request_rate(),
+                30.0
+            );
+            let local_accepted = joined.ticks[29].accepted as f64;
+            assert!((local_accepted - 20.0).abs() <= 1.0, "{local_accepted}");
+
+            simulation.run(Duration::from_secs(10));
+            assert_eq!(simulation.rate_limiter().external_request_rate(), 35.0);
+            assert_eq!(
+                simulation.rate_limiter().external_accepted_request_rate(),
+                15.0
+            );
+        }
+
+        #[test]
+        fn test_downstream_latency_grows_past_knee() {
+            let downstream = Downstream::new(100.0, Duration::from_millis(10)).knee(0.5);
+            assert_eq!(downstream.latency(40.0), Duration::from_millis(10));
+            assert_eq!(downstream.latency(75.0), Duration::from_millis(20));
+            assert_eq!(downstream.latency(100.0), Duration::from_millis(200));
+            assert_eq!(downstream.error_rate(100.0), 0.0);
+            assert_eq!(downstream.error_rate(400.0), 0.75);
+        }
+
+        #[test]
+        fn test_downstream_closes_the_loop() {
+            let run = |latency_target: Option<Duration>| {
+                let rate_limiter = RateLimiterBuilder::new(100.0)
+                    .min_rate(10.0)
+                    .max_rate(100.0)
+                    .pid_controller(PIDControllerBuilder::new(100.0).kp(0.5).ki(0.1).build());
+                let mut builder = SimulationBuilder::new(rate_limiter, |_: Duration| 100.0)
+                    .downstream(Downstream::new(60.0, Duration::from_millis(20)));
+                if let Some(latency_target) = latency_target {
+                    builder = builder.latency_target(latency_target);
+                }
+                let report = builder.build().run(Duration::from_secs(120));
+                SimulationReport {
+                    ticks: report.ticks[report.ticks.len() - 100..].to_vec(),
+                }
+            };
+
+            // Open loop, the limiter keeps admitting more than the downstream can serve
+            let open = run(None);
+            assert!(open.ticks.iter().all(|tick| tick.latency == 0.4));
+            assert!(open.ticks.iter().all(|tick| tick.error_rate > 0.3));
+
+            // Fed back the slow responses, it backs off until the downstream recovers
+            let closed = run(Some(Duration::from_millis(40)));
+            let accepted_rate = closed.accepted() as f64 / 10.0;
+            assert!(accepted_rate < 60.0, "{accepted_rate}");
+            assert!(closed.ticks.iter().all(|tick| tick.latency <= 0.04));
+            assert!(closed.ticks.iter().all(|tick| tick.error_rate == 0.0));
+        }
+
+        #[test]
+        fn test_writes_csv_and_json() {
+            let mut simulation = simulation(20.0);
+            let report = simulation.run(Duration::from_millis(200));
+
+            let mut csv = Vec::new();
+            report.write_csv(&mut csv).unwrap();
+            let csv = String::from_utf8(csv).unwrap();
+            let lines: Vec<&str> = csv.lines().collect();
+            assert_eq!(lines.len(), 3);
+            assert_eq!(lines[0], CSV_HEADER);
+            assert!(lines[1].starts_with("0.1,20,2,2,0,50,"), "{}", lines[1]);
+
+            let mut json = Vec::new();
+            report.write_json(&mut json).unwrap();
+            let json: serde_json::Value = serde_json::from_slice(&json).unwrap();
+            assert_eq!(json[1]["elapsed"], 0.2);
+            assert_eq!(json[1]["accepted"], 2.0);
+        }
+
+        #[test]
+        fn test_reads_written_reports() {
+            let mut simulation = simulation(80.0);
+            let report = simulation.run(Duration::from_secs(5));
+
+            let mut csv = Vec::new();
+            report.write_csv(&mut csv).unwrap();
+            assert_eq!(SimulationReport::read_csv(&csv[..]).unwrap(), report);
+
+            #[cfg(feature = "serde")]
+            {
+                let mut json = Vec::new();
+                report.write_json(&mut json).unwrap();
+                // JSON numbers are parsed to within a rounding error
+                let read = SimulationReport::read_json(&json[..]).unwrap();
+                assert_eq!(read.ticks.len(), report.ticks.len());
+                for (read, written) in read.ticks.iter().zip(&report.ticks) {
+                    assert_eq!(read.elapsed, written.elapsed);
+                    assert_eq!(read.throttled, written.throttled);
+                    assert!((read.accepted_rate - written.accepted_rate).abs() < 1e-9);
+                }
+            }
+
+            // Columns are matched by name, and missing ones read as zero
+            let partial = "accepted_rate,elapsed,note\n40.5,0.1,3\n\n";
+            let report = SimulationReport::read_csv(partial.as_bytes()).unwrap();
+            assert_eq!(report.ticks.len(), 1);
+            assert_eq!(report.ticks[0].elapsed, Duration::from_millis(100));
+            assert_eq!(report.ticks[0].accepted_rate, 40.5);
+            assert_eq!(report.ticks[0].target_rate, 0.0);
+            assert_eq!(report.ticks[0].value("accepted_rate"), Some(40.5));
+            assert_eq!(report.ticks[0].value("note"), None);
+            assert!(SimulationReport::read_csv("elapsed\nsoon\n".as_bytes()).is_err());
+        }
+    }
+}
+
+/// Seeded SplitMix64 random number generator, which is all the simulations need.
+#[derive(Debug, Clone)]
+pub(crate) struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub(crate) fn new(seed: u64) -> Self {
+        Rng { state: seed }
+    }
+
+    pub(crate) fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// Returns a number uniformly distributed in `[0, 1)`.
+    pub(crate) fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// Returns an exponentially distributed number with mean `1 / rate`.
+    pub(crate) fn exponential(&mut self, rate: f64) -> f64 {
+        -(1.0 - self.next_f64()).ln() / rate
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!((actual - expected).abs() < 1e-9, "{actual} != {expected}");
+    }
+
+    #[test]
+    fn test_sine_waves_add_to_base_rate() {
+        let generator = SineWaveGenerator::new(50.0, vec![20.0, 5.0], vec![0.25, 1.0]);
+        assert_close(generator.request_rate(Duration::ZERO), 50.0);
+        // A quarter period into the slow wave, and a whole period into the fast one
+        assert_close(generator.request_rate(Duration::from_secs(1)), 70.0);
+        assert_close(generator.request_rate(Duration::from_secs(3)), 30.0);
+
+        let generator = SineWaveGenerator::new(10.0, vec![20.0], vec![0.25]);
+        assert_eq!(generator.request_rate(Duration::from_secs(3)), 0.0);
+    }
+
+    #[test]
+    fn test_closures_are_generators() {
+        let ramp = |elapsed: Duration| elapsed.as_secs_f64() * 10.0;
+        assert_eq!(ramp.request_rate(Duration::from_secs(2)), 20.0);
+    }
+
+    #[test]
+    fn test_poisson_gaps_are_exponential() {
+        let arrivals: Vec<f64> = PoissonArrivals::new(100.0, 7)
+            .take(20_000)
+            .map(|arrival| arrival.as_secs_f64())
+            .collect();
+        assert!(arrivals.windows(2).all(|pair| pair[0] <= pair[1]));
+
+        let gaps: Vec<f64> = arrivals.windows(2).map(|pair| pair[1] - pair[0]).collect();
+        let mean = gaps.iter().sum::<f64>() / gaps.len() as f64;
+        let variance = gaps.iter().map(|gap| (gap - mean).powi(2)).sum::<f64>() / gaps.len() as f64;
+        assert!((0.0095..0.0105).contains(&mean), "{mean}");
+        // Exponential gaps have a standard deviation equal to their mean
+        let cv = variance.sqrt() / mean;
+        assert!((0.95..1.05).contains(&cv), "{cv}");
+
+        // The same seed gives the same arrivals
+        let replay: Vec<f64> = PoissonArrivals::new(100.0, 7)
+            .take(100)
+            .map(|arrival| arrival.as_secs_f64())
+            .collect();
+        assert_eq!(replay, arrivals[..100]);
+    }
+
+    #[test]
+    fn test_poisson_follows_time_varying_rate() {
+        let step = |elapsed: Duration| {
+            if elapsed < Duration::from_secs(10) {
+                20.0
+            } else {
+                200.0
+            }
+        };
+        let arrivals: Vec<Duration> = PoissonArrivals::time_varying(step, 200.0, 3)
+            .take_while(|&arrival| arrival < Duration::from_secs(20))
+            .collect();
+        let before = arrivals
+            .iter()
+            .filter(|&&arrival| arrival < Duration::from_secs(10))
+            .count();
+        let after = arrivals.len() - before;
+        assert!((150..250).contains(&before), "{before}");
+        assert!((1850..2150).contains(&after), "{after}");
+
+        assert_eq!(PoissonArrivals::new(0.0, 3).next(), None);
+    }
+
+    #[test]
+    fn test_on_off_alternates() {
+        let generator =
+            OnOffGenerator::new(100.0, 5.0, Duration::from_secs(2), Duration::from_secs(8));
+        assert_eq!(generator.request_rate(Duration::ZERO), 100.0);
+        assert_eq!(generator.request_rate(Duration::from_millis(1999)), 100.0);
+        assert_eq!(generator.request_rate(Duration::from_secs(2)), 5.0);
+        assert_eq!(generator.request_rate(Duration::from_secs(9)), 5.0);
+        assert_eq!(generator.request_rate(Duration::from_secs(11)), 100.0);
+    }
+
+    #[test]
+    fn test_flash_crowd_decays_to_base() {
+        let generator =
+            FlashCrowdGenerator::new(10.0, 110.0, Duration::from_secs(5), Duration::from_secs(2));
+        assert_eq!(generator.request_rate(Duration::from_secs(4)), 10.0);
+        assert_eq!(generator.request_rate(Duration::from_secs(5)), 110.0);
+        assert_close(
+            generator.request_rate(Duration::from_secs(7)),
+            10.0 + 100.0 / std::f64::consts::E,
+        );
+        assert!(generator.request_rate(Duration::from_secs(60)) - 10.0 < 1e-3);
+    }
+
+    #[test]
+    fn test_trace_replays_csv_at_speed() {
+        let log = "ip,timestamp\n10.0.0.1,100.5\n\n10.0.0.2,100.0\n10.0.0.1,\"102.0\"\n";
+        let trace = TraceReplay::from_csv(log.as_bytes(), 1).unwrap();
+        assert_eq!(trace.len(), 3);
+        assert_eq!(trace.duration(), Duration::from_secs(2));
+        assert_eq!(
+            trace.speed(0.5).collect::<Vec<_>>(),
+            [
+                Duration::ZERO,
+                Duration::from_secs(1),
+                Duration::from_secs(4)
+            ]
+        );
+
+        let err = TraceReplay::from_csv("100.0\nGET /\n".as_bytes(), 0).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(err.to_string(), "line 2: no timestamp in column 0");
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn test_trace_replays_jsonl() {
+        let log = r#"{"ts": 10.25, "status": 200}
+{"ts": 10, "status": 429}
+"#;
+        let trace = TraceReplay::from_jsonl(log.as_bytes(), "ts")
+            .unwrap()
+            .speed(0.25);
+        assert_eq!(
+            trace.collect::<Vec<_>>(),
+            [Duration::ZERO, Duration::from_secs(1)]
+        );
+
+        let err = TraceReplay::from_jsonl(r#"{"time": 1}"#.as_bytes(), "ts").unwrap_err();
+        assert_eq!(err.to_string(), "line 1: no numeric field `ts`");
+    }
+}
@@ -0,0 +1,366 @@
+//! Long-running memory soaks of keyed rate limiters in virtual time.
+//!
+//! A [`MemorySoak`] sends millions of decisions from thousands of keys to a
+//! [`KeyedRateLimiter`] on a [`ManualClock`], with keys going quiet and new ones arriving all the
+//! time, and samples the number of keys, the entries in their sliding windows, and the heap in
+//! use as it goes. A limiter with bounded memory levels off after the first key lifetime; one
+//! that leaks keeps growing until the end of the run, which [`SoakReport::is_bounded`] detects.
+//!
+//! The heap is only measured when [`CountingAllocator`] is the global allocator of the binary,
+//! so a soak can run against any configuration from a test or an example:
+//!
+//! ```rust
+//! use std::sync::Arc;
+//! use std::time::Duration;
+//!
+//! use nenya::clock::ManualClock;
+//! use nenya::keyed::KeyedRateLimiter;
+//! use nenya::soak::{CountingAllocator, MemorySoakBuilder};
+//!
+//! #[global_allocator]
+//! static ALLOCATOR: CountingAllocator = CountingAllocator;
+//!
+//! let clock = ManualClock::new();
+//! let mut limiter: KeyedRateLimiter<u64, f64> =
+//!     KeyedRateLimiter::with_clock(5.0, Arc::new(clock.clone()));
+//! let report = MemorySoakBuilder::new(2_000, 10_000.0)
+//!     .decisions(200_000)
+//!     .key_lifetime(Duration::from_secs(5))
+//!     .build()
+//!     .run(&mut limiter, &clock, |key| key);
+//! assert!(report.is_bounded(0.5));
+//! ```
+
+use num_traits::{Float, FromPrimitive, Signed};
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::hash::Hash;
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::time::Duration;
+
+use crate::clock::ManualClock;
+use crate::keyed::KeyedRateLimiter;
+use crate::simulation::Rng;
+
+static LIVE_BYTES: AtomicUsize = AtomicUsize::new(0);
+static ALLOCATIONS: AtomicU64 = AtomicU64::new(0);
+static INSTALLED: AtomicBool = AtomicBool::new(false);
+
+/// Global allocator counting the bytes in use and the allocations made, on top of the system
+/// allocator.
+///
+/// Install it with `#[global_allocator]` in the binary running a soak for the samples to
+/// include the heap in use.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CountingAllocator;
+
+impl CountingAllocator {
+    /// Returns `true` if the counting allocator is the global allocator of this binary.
+    pub fn is_installed() -> bool {
+        INSTALLED.load(Ordering::Relaxed)
+    }
+
+    /// Returns the bytes allocated and not yet freed.
+    pub fn live_bytes() -> usize {
+        LIVE_BYTES.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of allocations made, including reallocations.
+    pub fn allocations() -> u64 {
+        ALLOCATIONS.load(Ordering::Relaxed)
+    }
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        INSTALLED.store(true, Ordering::Relaxed);
+        let ptr = System.alloc(layout);
+        if !ptr.is_null() {
+            LIVE_BYTES.fetch_add(layout.size(), Ordering::Relaxed);
+            ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout);
+        LIVE_BYTES.fetch_sub(layout.size(), Ordering::Relaxed);
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_ptr = System.realloc(ptr, layout, new_size);
+        if !new_ptr.is_null() {
+            LIVE_BYTES.fetch_sub(layout.size(), Ordering::Relaxed);
+            LIVE_BYTES.fetch_add(new_size, Ordering::Relaxed);
+            ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        }
+        new_ptr
+    }
+}
+
+/// Memory use of the limiter at one point of a soak.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SoakSample {
+    /// Virtual time since the start of the soak.
+    pub elapsed: Duration,
+    /// Decisions made so far.
+    pub decisions: u64,
+    /// Keys the limiter holds.
+    pub keys: usize,
+    /// Entries in the sliding windows of all keys.
+    pub window_entries: usize,
+    /// Bytes in use on the heap, if [`CountingAllocator`] is installed.
+    pub live_bytes: Option<usize>,
+}
+
+/// Samples taken during a [`MemorySoak`] run.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SoakReport {
+    pub samples: Vec<SoakSample>,
+}
+
+impl SoakReport {
+    /// Returns the most keys held at any sample.
+    pub fn peak_keys(&self) -> usize {
+        self.samples
+            .iter()
+            .map(|sample| sample.keys)
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Returns the most sliding window entries held at any sample.
+    pub fn peak_window_entries(&self) -> usize {
+        self.samples
+            .iter()
+            .map(|sample| sample.window_entries)
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Returns the most heap in use at any sample, if it was measured.
+    pub fn peak_live_bytes(&self) -> Option<usize> {
+        self.samples
+            .iter()
+            .filter_map(|sample| sample.live_bytes)
+            .max()
+    }
+
+    /// Writes the samples as CSV with a header row, the elapsed time in seconds, and an empty
+    /// heap column if it was not measured.
+    pub fn write_csv(&self, mut writer: impl Write) -> io::Result<()> {
+        writeln!(writer, "elapsed,decisions,keys,window_entries,live_bytes")?;
+        for sample in &self.samples {
+            writeln!(
+                writer,
+                "{},{},{},{},{}",
+                sample.elapsed.as_secs_f64(),
+                sample.decisions,
+                sample.keys,
+                sample.window_entries,
+                sample
+                    .live_bytes
+                    .map_or(String::new(), |live_bytes| live_bytes.to_string())
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Returns `true` if no measure peaks in the second half of the samples more than `slack`,
+    /// a fraction, above its peak in the first half.
+    ///
+    /// Memory that is bounded levels off early, while a leak makes every measure keep growing.
+    pub fn is_bounded(&self, slack: f64) -> bool {
+        let (first, second) = self.samples.split_at(self.samples.len() / 2);
+        let within = |measure: fn(&SoakSample) -> Option<usize>| {
+            let peak = |samples: &[SoakSample]| samples.iter().filter_map(measure).max();
+            match (peak(first), peak(second)) {
+                (Some(first), Some(second)) => second as f64 <= first as f64 * (1.0 + slack),
+                _ => true,
+            }
+        };
+        within(|sample| Some(sample.keys))
+            && within(|sample| Some(sample.window_entries))
+            && within(|sample| sample.live_bytes)
+    }
+}
+
+/// Soak of a keyed rate limiter, created with [`MemorySoakBuilder`].
+#[derive(Debug, Clone)]
+pub struct MemorySoak {
+    keys: usize,
+    rate: f64,
+    decisions: u64,
+    key_lifetime: Duration,
+    sample_every: u64,
+    seed: u64,
+}
+
+impl MemorySoak {
+    /// Sends the soak's decisions to `limiter`, advancing `clock`, which must be the clock the
+    /// limiter reads, and samples its memory use.
+    ///
+    /// Each request comes from one of the active keys picked at random, and each of those is
+    /// replaced by a new key once per key lifetime, at staggered times. `key` maps the index of
+    /// a key to the limiter's key type, e.g. to format it as a string like real callers.
+    pub fn run<K, T>(
+        &self,
+        limiter: &mut KeyedRateLimiter<K, T>,
+        clock: &ManualClock,
+        mut key: impl FnMut(u64) -> K,
+    ) -> SoakReport
+    where
+        K: Eq + Hash + Clone,
+        T: Float + Signed + FromPrimitive + Copy,
+    {
+        let mut rng = Rng::new(self.seed);
+        let mut report = SoakReport::default();
+        let lifetime = self.key_lifetime.as_secs_f64();
+        let mut elapsed = Duration::ZERO;
+        for decision in 1..=self.decisions {
+            let now = Duration::from_secs_f64(decision as f64 / self.rate);
+            clock.advance(now - elapsed);
+            elapsed = now;
+
+            // Slot `slot` is taken by a new key every lifetime, offset so keys churn steadily
+            let slot = (rng.next_u64() % self.keys as u64) as usize;
+            let offset = lifetime * slot as f64 / self.keys as f64;
+            let generation = ((now.as_secs_f64() + offset) / lifetime).floor() as u64;
+            limiter.should_throttle(&key(generation * self.keys as u64 + slot as u64));
+
+            if decision % self.sample_every == 0 || decision == self.decisions {
+                report.samples.push(SoakSample {
+                    elapsed,
+                    decisions: decision,
+                    keys: limiter.len(),
+                    window_entries: limiter
+                        .iter()
+                        .map(|(_, rate_limiter)| {
+                            let state = rate_limiter.debug_state();
+                            state.request_window_len + state.accepted_request_window_len
+                        })
+                        .sum(),
+                    live_bytes: CountingAllocator::is_installed()
+                        .then(CountingAllocator::live_bytes),
+                });
+            }
+        }
+        report
+    }
+}
+
+/// Builder for creating a [`MemorySoak`].
+#[derive(Debug, Clone)]
+pub struct MemorySoakBuilder {
+    keys: usize,
+    rate: f64,
+    decisions: u64,
+    key_lifetime: Duration,
+    sample_every: u64,
+    seed: u64,
+}
+
+impl MemorySoakBuilder {
+    /// Creates a builder for a soak of `rate` decisions per second of virtual time spread over
+    /// `keys` active keys.
+    ///
+    /// The soak makes a million decisions, replaces each key every minute, samples every 10,000
+    /// decisions, and uses seed 0.
+    pub fn new(keys: usize, rate: f64) -> Self {
+        MemorySoakBuilder {
+            keys: keys.max(1),
+            rate,
+            decisions: 1_000_000,
+            key_lifetime: Duration::from_secs(60),
+            sample_every: 10_000,
+            seed: 0,
+        }
+    }
+
+    /// Sets the number of decisions to make.
+    pub fn decisions(mut self, decisions: u64) -> Self {
+        self.decisions = decisions;
+        self
+    }
+
+    /// Sets how long a key sends requests before it goes quiet and a new key takes its place.
+    pub fn key_lifetime(mut self, key_lifetime: Duration) -> Self {
+        self.key_lifetime = key_lifetime;
+        self
+    }
+
+    /// Sets the number of decisions between samples.
+    pub fn sample_every(mut self, sample_every: u64) -> Self {
+        self.sample_every = sample_every.max(1);
+        self
+    }
+
+    /// Sets the seed picking the key of each request.
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    /// Builds the soak.
+    pub fn build(self) -> MemorySoak {
+        MemorySoak {
+            keys: self.keys,
+            rate: self.rate,
+            decisions: self.decisions,
+            key_lifetime: self.key_lifetime,
+            sample_every: self.sample_every,
+            seed: self.seed,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_keyed_limiter_memory_is_bounded() {
+        let clock = ManualClock::new();
+        let mut limiter: KeyedRateLimiter<String, f64> =
+            KeyedRateLimiter::with_clock(5.0, Arc::new(clock.clone()));
+        let report = MemorySoakBuilder::new(2_000, 20_000.0)
+            .decisions(2_000_000)
+            .key_lifetime(Duration::from_secs(10))
+            .build()
+            .run(&mut limiter, &clock, |key| format!("caller-{key}"));
+
+        assert_eq!(report.samples.len(), 200);
+        assert_eq!(report.samples[199].elapsed, Duration::from_secs(100));
+        // Ten lifetimes saw about 20,000 keys, but only the recently active ones are held
+        assert!(report.peak_keys() < 8_000, "{}", report.peak_keys());
+        assert!(report.is_bounded(0.25));
+    }
+
+    #[test]
+    fn test_detects_unbounded_growth() {
+        let sample = |keys| SoakSample {
+            elapsed: Duration::ZERO,
+            decisions: 0,
+            keys,
+            window_entries: 0,
+            live_bytes: None,
+        };
+        let growing = SoakReport {
+            samples: (1..=10).map(|keys| sample(keys * 100)).collect(),
+        };
+        assert!(!growing.is_bounded(0.25));
+        assert_eq!(growing.peak_keys(), 1_000);
+        assert_eq!(growing.peak_live_bytes(), None);
+
+        let level = SoakReport {
+            samples: [100, 900, 1_000, 950, 1_000, 980].map(sample).to_vec(),
+        };
+        assert!(level.is_bounded(0.25));
+
+        let mut csv = Vec::new();
+        level.write_csv(&mut csv).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        assert_eq!(csv.lines().nth(2), Some("0,0,900,0,"));
+    }
+}
@@ -0,0 +1,224 @@
+//! Reporting of rate limiter decisions to a StatsD or DogStatsD agent over UDP.
+//!
+//! A [`StatsdSink`] given to
+//! [`RateLimiterBuilder::statsd`](crate::RateLimiterBuilder::statsd) sends, for every sampled
+//! decision:
+//!
+//! - `nenya.requests` and `nenya.throttled` counters, tagged with the sample rate
+//! - a `nenya.target_rate` gauge
+//!
+//! and a `nenya.pid_output` gauge for every sampled PID correction.
+//!
+//! Packets are sent without blocking and without waiting for an answer, so a missing agent only
+//! costs the send itself. At high request rates, [`StatsdSink::with_sample_rate`] keeps even that
+//! negligible: only every n-th decision is sent, and the agent scales the counters back up.
+//!
+//! # Example
+//!
+//! ```rust
+//! use nenya::statsd::StatsdSink;
+//! use nenya::RateLimiterBuilder;
+//!
+//! let sink = StatsdSink::new("127.0.0.1:8125")
+//!     .unwrap()
+//!     .with_sample_rate(0.1)
+//!     .with_tag("limiter", "checkout");
+//! let mut rate_limiter = RateLimiterBuilder::new(10.0).statsd(sink).build();
+//! rate_limiter.should_throttle();
+//! ```
+
+use std::fmt::Write;
+use std::io;
+use std::net::{ToSocketAddrs, UdpSocket};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+/// Sends rate limiter metrics to a StatsD agent.
+///
+/// Clones share the socket and the sampling, so one sink can serve several limiters.
+#[derive(Debug, Clone)]
+pub struct StatsdSink {
+    socket: Arc<UdpSocket>,
+    prefix: String,
+    /// DogStatsD tags, already formatted as `|#name:value,...`.
+    tags: String,
+    sample_rate: f64,
+    /// Send every `sample_interval`-th event.
+    sample_interval: u64,
+    events: Arc<AtomicU64>,
+}
+
+impl StatsdSink {
+    /// Creates a sink sending to the agent at `addr`, e.g. `127.0.0.1:8125`.
+    pub fn new(addr: impl ToSocketAddrs) -> io::Result<Self> {
+        let socket = UdpSocket::bind(("0.0.0.0", 0))?;
+        socket.connect(addr)?;
+        socket.set_nonblocking(true)?;
+        Ok(StatsdSink {
+            socket: Arc::new(socket),
+            prefix: "nenya".to_string(),
+            tags: String::new(),
+            sample_rate: 1.0,
+            sample_interval: 1,
+            events: Arc::new(AtomicU64::new(0)),
+        })
+    }
+
+    /// Sets the prefix of the metric names.
+    ///
+    /// Defaults to `nenya`.
+    pub fn with_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.prefix = prefix.into();
+        self
+    }
+
+    /// Adds a DogStatsD tag to every metric.
+    ///
+    /// Plain StatsD agents do not understand tags, so leave them out when sending to one.
+    pub fn with_tag(mut self, name: &str, value: &str) -> Self {
+        let separator = if self.tags.is_empty() { "|#" } else { "," };
+        let _ = write!(self.tags, "{separator}{name}:{value}");
+        self
+    }
+
+    /// Sets the fraction of decisions that are sent, between 0 and 1.
+    ///
+    /// The sink sends every n-th decision for a rate of 1/n, rounding n to the nearest integer.
+    /// Defaults to 1, sending every decision.
+    pub fn with_sample_rate(mut self, sample_rate: f64) -> Self {
+        self.sample_interval = (1.0 / sample_rate.clamp(f64::EPSILON, 1.0))
+            .round()
+            .min(u64::MAX as f64) as u64;
+        self.sample_rate = 1.0 / self.sample_interval as f64;
+        self
+    }
+
+    /// Returns the rate decisions are actually sampled at.
+    pub fn sample_rate(&self) -> f64 {
+        self.sample_rate
+    }
+
+    pub(crate) fn record_decision(&self, throttled: bool, target_rate: f64) {
+        if !self.sample() {
+            return;
+        }
+        let mut payload = String::new();
+        self.push_counter(&mut payload, "requests");
+        if throttled {
+            self.push_counter(&mut payload, "throttled");
+        }
+        self.push_gauge(&mut payload, "target_rate", target_rate);
+        self.send(&payload);
+    }
+
+    pub(crate) fn record_pid_output(&self, output: f64) {
+        if !self.sample() {
+            return;
+        }
+        let mut payload = String::new();
+        self.push_gauge(&mut payload, "pid_output", output);
+        self.send(&payload);
+    }
+
+    fn sample(&self) -> bool {
+        self.events
+            .fetch_add(1, Ordering::Relaxed)
+            .is_multiple_of(self.sample_interval)
+    }
+
+    fn push_counter(&self, payload: &mut String, name: &str) {
+        self.push_line(payload, name, "1|c");
+        if self.sample_interval > 1 {
+            let _ = write!(payload, "|@{}", self.sample_rate);
+        }
+        payload.push_str(&self.tags);
+    }
+
+    fn push_gauge(&self, payload: &mut String, name: &str, value: f64) {
+        // StatsD reads a leading sign as a change of the gauge, so negative values are sent as
+        // a reset to zero followed by the decrement
+        if value < 0.0 {
+            self.push_line(payload, name, "0|g");
+            payload.push_str(&self.tags);
+        }
+        self.push_line(payload, name, &format!("{value}|g"));
+        payload.push_str(&self.tags);
+    }
+
+    fn push_line(&self, payload: &mut String, name: &str, value: &str) {
+        if !payload.is_empty() {
+            payload.push('\n');
+        }
+        let _ = write!(payload, "{}.{name}:{value}", self.prefix);
+    }
+
+    /// Sends `payload`, dropping it if the socket's buffer is full or the agent is unreachable.
+    fn send(&self, payload: &str) {
+        let _ = self.socket.send(payload.as_bytes());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::RateLimiterBuilder;
+    use std::time::Duration;
+
+    fn agent() -> UdpSocket {
+        let agent = UdpSocket::bind("127.0.0.1:0").unwrap();
+        agent
+            .set_read_timeout(Some(Duration::from_secs(1)))
+            .unwrap();
+        agent
+    }
+
+    fn receive(agent: &UdpSocket) -> String {
+        let mut buffer = [0; 1024];
+        let len = agent.recv(&mut buffer).unwrap();
+        String::from_utf8(buffer[..len].to_vec()).unwrap()
+    }
+
+    #[test]
+    fn test_sends_sampled_decisions() {
+        let agent = agent();
+        let sink = StatsdSink::new(agent.local_addr().unwrap())
+            .unwrap()
+            .with_sample_rate(0.5)
+            .with_tag("limiter", "checkout")
+            .with_tag("env", "test");
+        assert_eq!(sink.sample_rate(), 0.5);
+
+        sink.record_decision(true, 12.5);
+        sink.record_decision(false, 12.5);
+        sink.record_decision(false, 10.0);
+        assert_eq!(
+            receive(&agent),
+            "nenya.requests:1|c|@0.5|#limiter:checkout,env:test\n\
+             nenya.throttled:1|c|@0.5|#limiter:checkout,env:test\n\
+             nenya.target_rate:12.5|g|#limiter:checkout,env:test"
+        );
+        assert_eq!(
+            receive(&agent),
+            "nenya.requests:1|c|@0.5|#limiter:checkout,env:test\n\
+             nenya.target_rate:10|g|#limiter:checkout,env:test"
+        );
+    }
+
+    #[test]
+    fn test_rate_limiter_reports_to_sink() {
+        let agent = agent();
+        let sink = StatsdSink::new(agent.local_addr().unwrap())
+            .unwrap()
+            .with_prefix("api");
+        let mut rate_limiter = RateLimiterBuilder::new(5.0).statsd(sink).build();
+        rate_limiter.should_throttle();
+        assert_eq!(receive(&agent), "api.requests:1|c\napi.target_rate:5|g");
+
+        let sink = StatsdSink::new(agent.local_addr().unwrap()).unwrap();
+        sink.record_pid_output(-1.5);
+        assert_eq!(
+            receive(&agent),
+            "nenya.pid_output:0|g\nnenya.pid_output:-1.5|g"
+        );
+    }
+}
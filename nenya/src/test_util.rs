@@ -0,0 +1,410 @@
+//! Utilities for unit testing throttling logic without real sleeps.
+//!
+//! Limiters built with [`mock_rate_limiter`] or [`mock_token_bucket`] read the time from a
+//! [`MockClock`] that only moves when the test advances it, so their decisions are the same on
+//! every run. [`send_at_rate`] offers them traffic at a fixed rate in mock time, and
+//! [`assert_rate_close_to`] checks the resulting rates:
+//!
+//! ```toml
+//! [dev-dependencies]
+//! nenya = { version = "0.0.2", features = ["test-util"] }
+//! ```
+//!
+//! # Example
+//!
+//! ```rust
+//! use std::time::Duration;
+//!
+//! use nenya::test_util::{assert_rate_close_to, mock_rate_limiter, send_at_rate};
+//! use nenya::RateLimiterBuilder;
+//!
+//! let (mut rate_limiter, clock) = mock_rate_limiter(RateLimiterBuilder::new(10.0));
+//!
+//! // An hour of traffic at 40 requests per second takes no time at all
+//! let decisions = send_at_rate(&clock, 40.0, Duration::from_secs(3600), || {
+//!     rate_limiter.should_throttle()
+//! });
+//! assert_rate_close_to(decisions.accepted_rate(), 10.0, 0.05);
+//! ```
+//!
+//! [`WorkerHarness`] drives one rate limiter shared behind a mutex from several worker threads
+//! at once, checking after every step of mock time that the accepted rate stays within the
+//! maximum rate, to catch races single-threaded tests cannot.
+
+#[cfg(feature = "pid")]
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+#[cfg(feature = "pid")]
+use std::sync::{Barrier, Mutex};
+use std::time::Duration;
+#[cfg(feature = "pid")]
+use std::{collections::VecDeque, panic, thread};
+
+use crate::token_bucket::{TokenBucket, TokenBucketBuilder};
+#[cfg(feature = "pid")]
+use crate::{RateLimiter, RateLimiterBuilder};
+
+#[cfg(feature = "pid")]
+use num_traits::{Float, FromPrimitive, Signed};
+
+/// Clock that only moves when the test calls [`advance`](MockClock::advance).
+///
+/// This is [`ManualClock`](crate::clock::ManualClock); clones share the same time.
+pub use crate::clock::ManualClock as MockClock;
+
+/// Builds the rate limiter of `builder` on a new mock clock, returning both.
+#[cfg(feature = "pid")]
+pub fn mock_rate_limiter<T: Float + Signed + FromPrimitive + Copy>(
+    builder: RateLimiterBuilder<T>,
+) -> (RateLimiter<T>, MockClock) {
+    let clock = MockClock::new();
+    (builder.clock(Arc::new(clock.clone())).build(), clock)
+}
+
+/// Builds the token bucket of `builder` on a new mock clock, returning both.
+pub fn mock_token_bucket(builder: TokenBucketBuilder) -> (TokenBucket, MockClock) {
+    let clock = MockClock::new();
+    (builder.clock(Arc::new(clock.clone())).build(), clock)
+}
+
+/// Decisions on requests sent with [`send_at_rate`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Decisions {
+    pub accepted: u64,
+    pub throttled: u64,
+    /// Mock time the requests were sent over.
+    pub elapsed: Duration,
+}
+
+impl Decisions {
+    /// Returns the requests accepted per second of mock time.
+    pub fn accepted_rate(&self) -> f64 {
+        if self.elapsed.is_zero() {
+            return 0.0;
+        }
+        self.accepted as f64 / self.elapsed.as_secs_f64()
+    }
+
+    /// Returns the fraction of the requests that were throttled, or zero without requests.
+    pub fn throttle_ratio(&self) -> f64 {
+        let requests = self.accepted + self.throttled;
+        if requests == 0 {
+            return 0.0;
+        }
+        self.throttled as f64 / requests as f64
+    }
+}
+
+/// Sends requests evenly spaced at `rate` per second for `duration` of mock time, advancing
+/// `clock` before each, and counts the decisions of `decide`, which returns whether a request
+/// was throttled.
+pub fn send_at_rate(
+    clock: &MockClock,
+    rate: f64,
+    duration: Duration,
+    mut decide: impl FnMut() -> bool,
+) -> Decisions {
+    let mut decisions = Decisions::default();
+    if rate <= 0.0 {
+        clock.advance(duration);
+        decisions.elapsed = duration;
+        return decisions;
+    }
+    let interval = Duration::from_secs_f64(1.0 / rate);
+    while decisions.elapsed + interval <= duration {
+        clock.advance(interval);
+        decisions.elapsed += interval;
+        if decide() {
+            decisions.throttled += 1;
+        } else {
+            decisions.accepted += 1;
+        }
+    }
+    // Leave the clock at the end of the duration, as if the remainder passed without requests
+    clock.advance(duration - decisions.elapsed);
+    decisions.elapsed = duration;
+    decisions
+}
+
+/// Asserts that `actual` is within `tolerance`, a fraction of `expected`, of `expected`.
+///
+/// # Panics
+///
+/// Panics with both rates if `actual` is further from `expected` than the tolerance.
+#[track_caller]
+pub fn assert_rate_close_to(actual: f64, expected: f64, tolerance: f64) {
+    let allowed = expected.abs() * tolerance;
+    assert!(
+        (actual - expected).abs() <= allowed,
+        "rate {} is not within {}% of {}",
+        actual,
+        tolerance * 100.0,
+        expected
+    );
+}
+
+/// Drives one rate limiter from several worker threads under a mock clock.
+///
+/// Time moves in steps: the clock advances by one step, every worker sends the requests it owes
+/// for the step at the same instant, contending for the limiter's mutex, and once all of them
+/// are done the harness checks that the accepted rate over the limiter's sliding window stays
+/// within the maximum rate plus one request per window, the quantum a single admission can
+/// overshoot by. The decisions are counted per step, so the totals are the same on every run
+/// even though which worker's requests are throttled depends on how the threads interleave.
+#[cfg(feature = "pid")]
+#[derive(Debug, Clone)]
+pub struct WorkerHarness {
+    workers: usize,
+    rate: f64,
+    step: Duration,
+}
+
+/// Decisions of a [`WorkerHarness`] run.
+#[cfg(feature = "pid")]
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct WorkerDecisions {
+    /// Decisions on the requests of each worker.
+    pub workers: Vec<Decisions>,
+    /// Highest accepted rate measured over the sliding window after any step.
+    pub max_accepted_rate: f64,
+}
+
+#[cfg(feature = "pid")]
+impl WorkerDecisions {
+    /// Returns the decisions on the requests of all workers.
+    pub fn total(&self) -> Decisions {
+        self.workers
+            .iter()
+            .fold(Decisions::default(), |total, decisions| Decisions {
+                accepted: total.accepted + decisions.accepted,
+                throttled: total.throttled + decisions.throttled,
+                elapsed: decisions.elapsed,
+            })
+    }
+}
+
+#[cfg(feature = "pid")]
+impl WorkerHarness {
+    /// Creates a harness of `workers` threads each sending `rate` requests per second, in steps
+    /// of 10 milliseconds.
+    pub fn new(workers: usize, rate: f64) -> Self {
+        WorkerHarness {
+            workers,
+            rate,
+            step: Duration::from_millis(10),
+        }
+    }
+
+    /// Sets how far the clock advances between the rounds of concurrent requests.
+    pub fn step(mut self, step: Duration) -> Self {
+        self.step = step;
+        self
+    }
+
+    /// Sends requests from the workers for `duration` of mock time, advancing `clock`, which
+    /// must be the clock `rate_limiter` reads.
+    ///
+    /// # Panics
+    ///
+    /// Panics with the time and the rate if the accepted rate exceeds the maximum rate by more
+    /// than one request per window after any step, or with the panic of a worker, once all the
+    /// workers are done.
+    #[track_caller]
+    pub fn run<T: Float + Signed + FromPrimitive + Copy + Send>(
+        &self,
+        rate_limiter: &Mutex<RateLimiter<T>>,
+        clock: &MockClock,
+        duration: Duration,
+    ) -> WorkerDecisions {
+        let (max_rate, window) = {
+            let rate_limiter = rate_limiter.lock().unwrap();
+            (
+                rate_limiter.max_rate().to_f64().unwrap(),
+                rate_limiter.update_interval(),
+            )
+        };
+        // The limiter never divides its window by less than 0.1 seconds
+        let effective_window = window.as_secs_f64().max(0.1);
+        let quantum = 1.0 / effective_window;
+
+        let steps = (duration.as_secs_f64() / self.step.as_secs_f64()).floor() as u64;
+        let owed_per_step = self.rate * self.step.as_secs_f64();
+        let start = Barrier::new(self.workers + 1);
+        let done = Barrier::new(self.workers + 1);
+        let accepted = AtomicU64::new(0);
+
+        let mut run = WorkerDecisions::default();
+        let mut violation = None;
+        thread::scope(|scope| {
+            let workers: Vec<_> = (0..self.workers)
+                .map(|_| {
+                    scope.spawn(|| {
+                        let mut decisions = Decisions::default();
+                        let mut owed = 0.0;
+                        // A panicking worker keeps meeting the barriers so the others finish
+                        let mut failure = None;
+                        for _ in 0..steps {
+                            start.wait();
+                            owed += owed_per_step;
+                            if failure.is_none() {
+                                let result = panic::catch_unwind(panic::AssertUnwindSafe(|| {
+                                    while owed >= 1.0 {
+                                        owed -= 1.0;
+                                        if rate_limiter.lock().unwrap().should_throttle() {
+                                            decisions.throttled += 1;
+                                        } else {
+                                            decisions.accepted += 1;
+                                            accepted.fetch_add(1, Ordering::Relaxed);
+                                        }
+                                    }
+                                }));
+                                failure = result.err();
+                            }
+                            done.wait();
+                        }
+                        if let Some(payload) = failure {
+                            panic::resume_unwind(payload);
+                        }
+                        decisions
+                    })
+                })
+                .collect();
+
+            // Accepted requests of each step still within the sliding window
+            let mut in_window = VecDeque::new();
+            let mut accepted_in_window = 0;
+            for step in 1..=steps {
+                clock.advance(self.step);
+                start.wait();
+                done.wait();
+
+                let now = self.step * step as u32;
+                let step_accepted = accepted.swap(0, Ordering::Relaxed);
+                in_window.push_back((now, step_accepted));
+                accepted_in_window += step_accepted;
+                while let Some(&(time, count)) = in_window.front() {
+                    if now - time <= window {
+                        break;
+                    }
+                    in_window.pop_front();
+                    accepted_in_window -= count;
+                }
+
+                let accepted_rate = accepted_in_window as f64 / effective_window;
+                run.max_accepted_rate = run.max_accepted_rate.max(accepted_rate);
+                if accepted_rate > max_rate + quantum + 1e-9 && violation.is_none() {
+                    violation = Some((now, accepted_rate));
+                }
+            }
+
+            run.workers = workers
+                .into_iter()
+                .map(|worker| {
+                    worker
+                        .join()
+                        .unwrap_or_else(|payload| panic::resume_unwind(payload))
+                })
+                .collect();
+        });
+        if let Some((time, accepted_rate)) = violation {
+            panic!(
+                "accepted rate {} after {:?} exceeds the maximum rate {} by more than {}",
+                accepted_rate, time, max_rate, quantum
+            );
+        }
+
+        let elapsed = self.step * steps as u32;
+        for decisions in &mut run.workers {
+            decisions.elapsed = elapsed;
+        }
+        clock.advance(duration - elapsed);
+        run
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::Clock;
+
+    #[test]
+    fn test_sends_at_rate_in_mock_time() {
+        let (mut bucket, clock) = mock_token_bucket(TokenBucketBuilder::new(5.0));
+        let decisions = send_at_rate(&clock, 20.0, Duration::from_millis(10_050), || {
+            bucket.should_throttle()
+        });
+        assert_eq!(decisions.accepted + decisions.throttled, 201);
+        assert_eq!(clock.now().as_duration(), Duration::from_millis(10_050));
+        assert_rate_close_to(decisions.accepted_rate(), 5.5, 0.05);
+        assert_rate_close_to(decisions.throttle_ratio(), 0.73, 0.05);
+
+        let idle = send_at_rate(&clock, 0.0, Duration::from_secs(1), || unreachable!());
+        assert_eq!(idle.accepted_rate(), 0.0);
+        assert_eq!(clock.now().as_duration(), Duration::from_millis(11_050));
+    }
+
+    #[test]
+    #[should_panic(expected = "rate 11 is not within 5% of 10")]
+    fn test_assert_rate_close_to_panics_outside_tolerance() {
+        assert_rate_close_to(10.4, 10.0, 0.05);
+        assert_rate_close_to(11.0, 10.0, 0.05);
+    }
+
+    #[test]
+    #[cfg(feature = "pid")]
+    fn test_workers_share_a_limiter_deterministically() {
+        let run = || {
+            let (rate_limiter, clock) = mock_rate_limiter(
+                RateLimiterBuilder::new(50.0)
+                    .min_rate(10.0)
+                    .max_rate(100.0)
+                    .pid_controller(
+                        crate::pid_controller::PIDControllerBuilder::new(50.0)
+                            .kp(0.5)
+                            .ki(0.1)
+                            .build(),
+                    ),
+            );
+            let rate_limiter = Mutex::new(rate_limiter);
+            let decisions =
+                WorkerHarness::new(8, 40.0).run(&rate_limiter, &clock, Duration::from_secs(30));
+            assert_eq!(clock.now().as_duration(), Duration::from_secs(30));
+            decisions
+        };
+        let decisions = run();
+        assert_eq!(decisions.workers.len(), 8);
+        assert!(decisions.max_accepted_rate <= 101.0);
+        let total = decisions.total();
+        assert_eq!(total.accepted + total.throttled, 8 * 1200);
+        assert!(total.accepted_rate() >= 10.0 && total.accepted_rate() <= 100.0);
+        assert_eq!(total, run().total());
+    }
+
+    #[test]
+    #[cfg(feature = "pid")]
+    #[should_panic(expected = "exceeds the maximum rate 10")]
+    fn test_worker_harness_panics_over_max_rate() {
+        let (rate_limiter, clock) = mock_rate_limiter(RateLimiterBuilder::new(10.0));
+        let rate_limiter = Mutex::new(rate_limiter);
+        // A limiter pinned above its maximum rate admits everything the workers send
+        rate_limiter
+            .lock()
+            .unwrap()
+            .set_fixed_target_rate(Some(1000.0));
+        WorkerHarness::new(4, 10.0).run(&rate_limiter, &clock, Duration::from_secs(2));
+    }
+
+    #[test]
+    #[cfg(feature = "pid")]
+    fn test_mock_rate_limiter_is_deterministic() {
+        let run = || {
+            let (mut rate_limiter, clock) = mock_rate_limiter(RateLimiterBuilder::new(10.0));
+            send_at_rate(&clock, 25.0, Duration::from_secs(60), || {
+                rate_limiter.should_throttle()
+            })
+        };
+        let decisions = run();
+        assert_eq!(decisions, run());
+        assert_rate_close_to(decisions.accepted_rate(), 10.0, 0.05);
+    }
+}
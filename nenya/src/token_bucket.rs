@@ -0,0 +1,191 @@
+//! Token bucket rate limiter, for builds that do without PID control.
+//!
+//! A [`TokenBucket`] admits requests at a fixed rate while allowing bursts up to its capacity:
+//! tokens are added at the rate until the bucket is full, and every request takes its cost in
+//! tokens or is throttled. Unlike [`RateLimiter`](crate::RateLimiter), it neither adapts its rate
+//! nor is generic over the float type, so it is available without the default features, e.g. for
+//! embedded targets that only need the core:
+//!
+//! ```toml
+//! [dependencies]
+//! nenya = { version = "0.0.2", default-features = false }
+//! ```
+//!
+//! # Example
+//!
+//! ```rust
+//! use nenya::token_bucket::TokenBucketBuilder;
+//!
+//! // 10 requests per second, with bursts of up to 20
+//! let mut bucket = TokenBucketBuilder::new(10.0).capacity(20.0).build();
+//!
+//! if bucket.should_throttle() {
+//!     println!("Request throttled, retry in {:?}", bucket.retry_after());
+//! }
+//! ```
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::clock::{Clock, SystemClock, Timestamp};
+
+/// Fixed rate limiter admitting bursts up to a capacity.
+#[derive(Debug)]
+pub struct TokenBucket {
+    rate: f64,
+    capacity: f64,
+    tokens: f64,
+    refilled_at: Timestamp,
+    clock: Arc<dyn Clock>,
+}
+
+impl TokenBucket {
+    /// Determines if a request should be throttled, taking a token if it is not.
+    pub fn should_throttle(&mut self) -> bool {
+        self.should_throttle_weighted(1.0)
+    }
+
+    /// Determines if a request consuming `cost` tokens should be throttled, taking the tokens if
+    /// it is not.
+    ///
+    /// A request costing more than the capacity is always throttled, as is one with a NaN,
+    /// infinite, or negative cost.
+    pub fn should_throttle_weighted(&mut self, cost: f64) -> bool {
+        if !cost.is_finite() || cost < 0.0 {
+            return true;
+        }
+        self.refill();
+        if self.tokens < cost {
+            return true;
+        }
+        self.tokens -= cost;
+        false
+    }
+
+    /// Returns how long a throttled caller should wait before retrying.
+    ///
+    /// This is the time until a token is available, or zero if one is.
+    pub fn retry_after(&self) -> Duration {
+        let missing = 1.0 - self.tokens();
+        if missing <= 0.0 || self.rate <= 0.0 {
+            return Duration::ZERO;
+        }
+        Duration::from_secs_f64(missing / self.rate)
+    }
+
+    /// Returns the number of tokens available now.
+    pub fn tokens(&self) -> f64 {
+        let elapsed = self.clock.now().duration_since(self.refilled_at);
+        (self.tokens + elapsed.as_secs_f64() * self.rate).min(self.capacity)
+    }
+
+    /// Returns the rate tokens are added at, per second.
+    pub fn rate(&self) -> f64 {
+        self.rate
+    }
+
+    /// Sets the rate tokens are added at, per second, keeping the tokens added so far.
+    pub fn set_rate(&mut self, rate: f64) {
+        self.refill();
+        self.rate = rate;
+    }
+
+    /// Returns the maximum number of tokens the bucket holds.
+    pub fn capacity(&self) -> f64 {
+        self.capacity
+    }
+
+    /// Adds the tokens accrued since the last refill.
+    fn refill(&mut self) {
+        self.tokens = self.tokens();
+        self.refilled_at = self.clock.now();
+    }
+}
+
+/// Builder for creating a `TokenBucket` instance.
+pub struct TokenBucketBuilder {
+    rate: f64,
+    capacity: Option<f64>,
+    clock: Option<Arc<dyn Clock>>,
+}
+
+impl TokenBucketBuilder {
+    /// Creates a new `TokenBucketBuilder` adding `rate` tokens per second, with a capacity of one
+    /// second's worth of tokens.
+    pub fn new(rate: f64) -> Self {
+        TokenBucketBuilder {
+            rate,
+            capacity: None,
+            clock: None,
+        }
+    }
+
+    /// Sets the maximum number of tokens, which is the largest burst admitted at once.
+    pub fn capacity(mut self, capacity: f64) -> Self {
+        self.capacity = Some(capacity);
+        self
+    }
+
+    /// Sets the clock tokens are added by.
+    pub fn clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = Some(clock);
+        self
+    }
+
+    /// Builds and returns the `TokenBucket` instance, starting full.
+    pub fn build(self) -> TokenBucket {
+        let clock = self.clock.unwrap_or_else(|| Arc::new(SystemClock));
+        let capacity = self.capacity.unwrap_or(self.rate);
+        TokenBucket {
+            rate: self.rate,
+            capacity,
+            tokens: capacity,
+            refilled_at: clock.now(),
+            clock,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::ManualClock;
+
+    #[test]
+    fn test_admits_bursts_up_to_capacity() {
+        let clock = ManualClock::new();
+        let mut bucket = TokenBucketBuilder::new(10.0)
+            .capacity(5.0)
+            .clock(Arc::new(clock.clone()))
+            .build();
+
+        let admitted = (0..20).filter(|_| !bucket.should_throttle()).count();
+        assert_eq!(admitted, 5);
+        assert_eq!(bucket.retry_after(), Duration::from_millis(100));
+
+        // Tokens accrue at the rate, up to the capacity
+        clock.advance(Duration::from_millis(300));
+        assert_eq!(bucket.tokens(), 3.0);
+        clock.advance(Duration::from_secs(10));
+        assert_eq!(bucket.tokens(), 5.0);
+        assert_eq!(bucket.retry_after(), Duration::ZERO);
+        assert!(bucket.should_throttle_weighted(6.0));
+        assert!(!bucket.should_throttle_weighted(4.0));
+    }
+
+    #[test]
+    fn test_set_rate_keeps_accrued_tokens() {
+        let clock = ManualClock::new();
+        let mut bucket = TokenBucketBuilder::new(4.0)
+            .clock(Arc::new(clock.clone()))
+            .build();
+        assert_eq!(bucket.capacity(), 4.0);
+        assert!(!bucket.should_throttle_weighted(4.0));
+
+        clock.advance(Duration::from_millis(500));
+        bucket.set_rate(1.0);
+        assert_eq!(bucket.tokens(), 2.0);
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(bucket.tokens(), 3.0);
+    }
+}
@@ -0,0 +1,611 @@
+//! [Tonic](https://docs.rs/tonic) middleware limiting the rate of each gRPC method.
+//!
+//! [`MethodRateLimitLayer`] checks every inbound call against a [`KeyedRateLimiter`] keyed by the
+//! call's full method name, e.g. `/nenya.Orders/Checkout`, so each method gets its own limit.
+//! Throttled calls never reach the service; they fail with `RESOURCE_EXHAUSTED` carrying a
+//! `google.rpc.RetryInfo` detail telling the client how long to wait before retrying.
+//!
+//! A plain tonic interceptor cannot see which method is called, so the limit is applied as a
+//! tower layer around the server's routes:
+//!
+//! ```rust
+//! use std::sync::{Arc, Mutex};
+//!
+//! use nenya::keyed::KeyedRateLimiter;
+//! use nenya::tonic::MethodRateLimitLayer;
+//!
+//! let limiter = Arc::new(Mutex::new(KeyedRateLimiter::new(100.0)));
+//! let layer = MethodRateLimitLayer::new(limiter);
+//! // Server::builder().layer(layer).add_service(...)
+//! ```
+//!
+//! With [`MethodRateLimitLayer::latency_target`] and [`MethodRateLimitLayer::error_target`], the
+//! layer also records the latency and status of every call it passes through, and lowers the
+//! rate of all methods while the service is slow or failing with `UNAVAILABLE`, `INTERNAL`,
+//! `DEADLINE_EXCEEDED`, or `RESOURCE_EXHAUSTED`. Only statuses sent in the response headers are
+//! seen, which covers calls that fail before sending a message.
+//!
+//! With the `pacing` feature, [`MethodPacingLayer`] does the reverse for clients calling rate
+//! limited services: it holds each outgoing call until its method's [`Pacer`] has room for it,
+//! and backs off whenever the server answers `RESOURCE_EXHAUSTED` or `UNAVAILABLE`, honoring
+//! the retry delay the server sent along.
+
+use num_traits::{Float, FromPrimitive, Signed};
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use prost::Message;
+use tonic::body::BoxBody;
+use tonic::{Code, Status};
+use tower_layer::Layer;
+use tower_service::Service;
+
+use crate::feedback::ResponseFeedback;
+use crate::keyed::KeyedRateLimiter;
+
+const RETRY_INFO_TYPE_URL: &str = "type.googleapis.com/google.rpc.RetryInfo";
+
+/// `google.rpc.Status`, the message carried in the `grpc-status-details-bin` trailer.
+#[derive(Clone, PartialEq, Message)]
+struct RpcStatus {
+    #[prost(int32, tag = "1")]
+    code: i32,
+    #[prost(string, tag = "2")]
+    message: String,
+    #[prost(message, repeated, tag = "3")]
+    details: Vec<prost_types::Any>,
+}
+
+/// `google.rpc.RetryInfo`, telling the client when to retry.
+#[derive(Clone, PartialEq, Message)]
+struct RetryInfo {
+    #[prost(message, optional, tag = "1")]
+    retry_delay: Option<prost_types::Duration>,
+}
+
+/// Returns the `RESOURCE_EXHAUSTED` status failing a call throttled for `retry_after`.
+pub fn resource_exhausted(message: impl Into<String>, retry_after: Duration) -> Status {
+    let message = message.into();
+    let retry_info = RetryInfo {
+        retry_delay: Some(prost_types::Duration {
+            seconds: retry_after.as_secs() as i64,
+            nanos: retry_after.subsec_nanos() as i32,
+        }),
+    };
+    let details = RpcStatus {
+        code: Code::ResourceExhausted as i32,
+        message: message.clone(),
+        details: vec![prost_types::Any {
+            type_url: RETRY_INFO_TYPE_URL.to_string(),
+            value: retry_info.encode_to_vec(),
+        }],
+    };
+    Status::with_details(
+        Code::ResourceExhausted,
+        message,
+        details.encode_to_vec().into(),
+    )
+}
+
+/// Returns the retry delay in the `google.rpc.RetryInfo` detail of `status`, if it has one.
+pub fn retry_delay(status: &Status) -> Option<Duration> {
+    let details = RpcStatus::decode(status.details()).ok()?;
+    let retry_info = details
+        .details
+        .iter()
+        .find(|detail| detail.type_url == RETRY_INFO_TYPE_URL)?;
+    let delay = RetryInfo::decode(retry_info.value.as_slice())
+        .ok()?
+        .retry_delay?;
+    Some(Duration::new(
+        u64::try_from(delay.seconds).ok()?,
+        u32::try_from(delay.nanos).ok()?,
+    ))
+}
+
+/// Returns `true` if `response` carries a status showing the service is overloaded or failing.
+fn is_failure(response: &http::Response<BoxBody>) -> bool {
+    if response.status().is_server_error() {
+        return true;
+    }
+    Status::from_header_map(response.headers()).is_some_and(|status| {
+        matches!(
+            status.code(),
+            Code::Unavailable | Code::Internal | Code::DeadlineExceeded | Code::ResourceExhausted
+        )
+    })
+}
+
+/// Applies [`MethodRateLimit`] to services.
+pub struct MethodRateLimitLayer<T> {
+    limiter: Arc<Mutex<KeyedRateLimiter<String, T>>>,
+    feedback: Option<Arc<Mutex<ResponseFeedback<T>>>>,
+}
+
+impl<T> MethodRateLimitLayer<T> {
+    /// Creates a layer limiting the calls of each method with `limiter`.
+    pub fn new(limiter: Arc<Mutex<KeyedRateLimiter<String, T>>>) -> Self {
+        MethodRateLimitLayer {
+            limiter,
+            feedback: None,
+        }
+    }
+}
+
+impl<T: Float + Signed + FromPrimitive + Copy> MethodRateLimitLayer<T> {
+    /// Lowers the rate of every method while the smoothed call latency exceeds `target_latency`.
+    pub fn latency_target(mut self, target_latency: Duration) -> Self {
+        ResponseFeedback::configure(&mut self.feedback).set_latency_target(target_latency);
+        self
+    }
+
+    /// Lowers the rate of every method while the smoothed fraction of failed calls exceeds
+    /// `error_target`.
+    pub fn error_target(mut self, error_target: f64) -> Self {
+        ResponseFeedback::configure(&mut self.feedback).set_error_target(error_target);
+        self
+    }
+}
+
+impl<T> Clone for MethodRateLimitLayer<T> {
+    fn clone(&self) -> Self {
+        MethodRateLimitLayer {
+            limiter: self.limiter.clone(),
+            feedback: self.feedback.clone(),
+        }
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for MethodRateLimitLayer<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MethodRateLimitLayer")
+            .field("limiter", &self.limiter)
+            .field("feedback", &self.feedback)
+            .finish()
+    }
+}
+
+impl<S, T> Layer<S> for MethodRateLimitLayer<T> {
+    type Service = MethodRateLimit<S, T>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        MethodRateLimit {
+            inner,
+            limiter: self.limiter.clone(),
+            feedback: self.feedback.clone(),
+        }
+    }
+}
+
+/// Service failing calls over their method's limit with `RESOURCE_EXHAUSTED`.
+pub struct MethodRateLimit<S, T> {
+    inner: S,
+    limiter: Arc<Mutex<KeyedRateLimiter<String, T>>>,
+    feedback: Option<Arc<Mutex<ResponseFeedback<T>>>>,
+}
+
+impl<S: Clone, T> Clone for MethodRateLimit<S, T> {
+    fn clone(&self) -> Self {
+        MethodRateLimit {
+            inner: self.inner.clone(),
+            limiter: self.limiter.clone(),
+            feedback: self.feedback.clone(),
+        }
+    }
+}
+
+impl<S: fmt::Debug, T: fmt::Debug> fmt::Debug for MethodRateLimit<S, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MethodRateLimit")
+            .field("inner", &self.inner)
+            .field("limiter", &self.limiter)
+            .field("feedback", &self.feedback)
+            .finish()
+    }
+}
+
+impl<S, B, T> Service<http::Request<B>> for MethodRateLimit<S, T>
+where
+    S: Service<http::Request<B>, Response = http::Response<BoxBody>>,
+    S::Error: Send + 'static,
+    S::Future: Send + 'static,
+    T: Float + Signed + FromPrimitive + Copy + Send + 'static,
+{
+    type Response = http::Response<BoxBody>;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, S::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: http::Request<B>) -> Self::Future {
+        let method = request.uri().path().to_string();
+        let retry_after = {
+            let mut limiter = self.limiter.lock().unwrap();
+            limiter
+                .should_throttle(&method)
+                .then(|| limiter.retry_after(&method))
+        };
+        match retry_after {
+            Some(retry_after) => {
+                let status = resource_exhausted(format!("{} is rate limited", method), retry_after);
+                Box::pin(std::future::ready(Ok(status.to_http())))
+            }
+            None => {
+                let response = self.inner.call(request);
+                let Some(feedback) = self.feedback.clone() else {
+                    return Box::pin(response);
+                };
+                let limiter = self.limiter.clone();
+                let started = Instant::now();
+                Box::pin(async move {
+                    let response = response.await;
+                    let failed = response.as_ref().map_or(true, is_failure);
+                    let mut limiter = limiter.lock().unwrap();
+                    let key_rate = feedback.lock().unwrap().observe(
+                        started.elapsed(),
+                        failed,
+                        limiter.key_rate(),
+                    );
+                    limiter.set_key_rate(key_rate);
+                    response
+                })
+            }
+        }
+    }
+}
+
+#[cfg(feature = "pacing")]
+pub use self::client::*;
+
+#[cfg(feature = "pacing")]
+mod client {
+    use num_traits::{Float, FromPrimitive, Signed};
+    use std::collections::HashMap;
+    use std::fmt;
+    use std::future::Future;
+    use std::pin::Pin;
+    use std::sync::{Arc, Mutex};
+    use std::task::{Context, Poll};
+
+    use tonic::{Code, Status};
+    use tower_layer::Layer;
+    use tower_service::Service;
+
+    use super::retry_delay;
+    use crate::pacing::Pacer;
+    use crate::runtime::Timer;
+    use crate::RateLimiter;
+
+    type MakeLimiter<T> = dyn Fn(&str) -> RateLimiter<T> + Send + Sync;
+
+    /// Applies [`MethodPacing`] to gRPC clients.
+    ///
+    /// Each method is paced by its own [`Pacer`], created on its first call with the rate
+    /// limiter returned for the method's full name, e.g. `/nenya.Orders/Checkout`. Clones share
+    /// the pacers, so every channel wrapped by the same layer stays within the same rates.
+    ///
+    /// ```rust
+    /// use std::sync::Arc;
+    ///
+    /// use nenya::runtime::TokioTimer;
+    /// use nenya::tonic::MethodPacingLayer;
+    /// use nenya::RateLimiterBuilder;
+    ///
+    /// let layer = MethodPacingLayer::new(
+    ///     |_method: &str| RateLimiterBuilder::new(20.0).min_rate(1.0).build(),
+    ///     Arc::new(TokioTimer),
+    /// );
+    /// // let channel = ServiceBuilder::new().layer(layer).service(channel);
+    /// # let _: MethodPacingLayer<f64> = layer;
+    /// ```
+    pub struct MethodPacingLayer<T> {
+        pacers: Arc<MethodPacers<T>>,
+    }
+
+    struct MethodPacers<T> {
+        make_limiter: Box<MakeLimiter<T>>,
+        timer: Arc<dyn Timer>,
+        pacers: Mutex<HashMap<String, Arc<Pacer<T>>>>,
+    }
+
+    impl<T: Float + Signed + FromPrimitive + Copy> MethodPacingLayer<T> {
+        /// Creates a layer pacing each method with the rate limiter `make_limiter` returns for
+        /// it, waiting with `timer`.
+        pub fn new(
+            make_limiter: impl Fn(&str) -> RateLimiter<T> + Send + Sync + 'static,
+            timer: Arc<dyn Timer>,
+        ) -> Self {
+            MethodPacingLayer {
+                pacers: Arc::new(MethodPacers {
+                    make_limiter: Box::new(make_limiter),
+                    timer,
+                    pacers: Mutex::new(HashMap::new()),
+                }),
+            }
+        }
+
+        /// Returns the pacer of `method`, creating it if the method was not called yet.
+        pub fn pacer(&self, method: &str) -> Arc<Pacer<T>> {
+            self.pacers.pacer(method)
+        }
+    }
+
+    impl<T: Float + Signed + FromPrimitive + Copy> MethodPacers<T> {
+        fn pacer(&self, method: &str) -> Arc<Pacer<T>> {
+            let mut pacers = self.pacers.lock().unwrap();
+            if let Some(pacer) = pacers.get(method) {
+                return pacer.clone();
+            }
+            let pacer = Arc::new(Pacer::with_timer(
+                (self.make_limiter)(method),
+                self.timer.clone(),
+            ));
+            pacers.insert(method.to_string(), pacer.clone());
+            pacer
+        }
+    }
+
+    impl<T> Clone for MethodPacingLayer<T> {
+        fn clone(&self) -> Self {
+            MethodPacingLayer {
+                pacers: self.pacers.clone(),
+            }
+        }
+    }
+
+    impl<T> fmt::Debug for MethodPacingLayer<T> {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.debug_struct("MethodPacingLayer").finish_non_exhaustive()
+        }
+    }
+
+    impl<S, T> Layer<S> for MethodPacingLayer<T> {
+        type Service = MethodPacing<S, T>;
+
+        fn layer(&self, inner: S) -> Self::Service {
+            MethodPacing {
+                inner,
+                pacers: self.pacers.clone(),
+            }
+        }
+    }
+
+    /// Client service pacing each call by its method and feeding the server's answers back
+    /// into the method's pacer.
+    ///
+    /// `RESOURCE_EXHAUSTED` and `UNAVAILABLE` lower the method's rate and pause it for the
+    /// status' retry delay, any other answer raises the rate again. Only statuses sent in the
+    /// response headers are seen, which is where servers put them when they fail a call without
+    /// sending a message, as they do when throttling.
+    pub struct MethodPacing<S, T> {
+        inner: S,
+        pacers: Arc<MethodPacers<T>>,
+    }
+
+    impl<S: Clone, T> Clone for MethodPacing<S, T> {
+        fn clone(&self) -> Self {
+            MethodPacing {
+                inner: self.inner.clone(),
+                pacers: self.pacers.clone(),
+            }
+        }
+    }
+
+    impl<S: fmt::Debug, T> fmt::Debug for MethodPacing<S, T> {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.debug_struct("MethodPacing")
+                .field("inner", &self.inner)
+                .finish_non_exhaustive()
+        }
+    }
+
+    impl<S, B, RB, T> Service<http::Request<B>> for MethodPacing<S, T>
+    where
+        S: Service<http::Request<B>, Response = http::Response<RB>> + Clone + Send + 'static,
+        S::Future: Send + 'static,
+        B: Send + 'static,
+        T: Float + Signed + FromPrimitive + Copy + Send + 'static,
+    {
+        type Response = http::Response<RB>;
+        type Error = S::Error;
+        type Future = Pin<Box<dyn Future<Output = Result<Self::Response, S::Error>> + Send>>;
+
+        fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+            self.inner.poll_ready(cx)
+        }
+
+        fn call(&mut self, request: http::Request<B>) -> Self::Future {
+            let pacer = self.pacers.pacer(request.uri().path());
+            // Call the service that was polled ready, leaving a fresh clone for the next call
+            let clone = self.inner.clone();
+            let mut inner = std::mem::replace(&mut self.inner, clone);
+            Box::pin(async move {
+                pacer.acquire().await;
+                let response = inner.call(request).await?;
+                match Status::from_header_map(response.headers()) {
+                    Some(status)
+                        if matches!(status.code(), Code::ResourceExhausted | Code::Unavailable) =>
+                    {
+                        pacer.record_throttled(retry_delay(&status));
+                    }
+                    _ => pacer.record_success(),
+                }
+                Ok(response)
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::Infallible;
+    use std::task::Waker;
+
+    #[derive(Clone)]
+    struct Unimplemented;
+
+    impl Service<http::Request<()>> for Unimplemented {
+        type Response = http::Response<BoxBody>;
+        type Error = Infallible;
+        type Future = std::future::Ready<Result<Self::Response, Infallible>>;
+
+        fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Infallible>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, _request: http::Request<()>) -> Self::Future {
+            std::future::ready(Ok(Status::unimplemented("").to_http()))
+        }
+    }
+
+    fn call(service: &mut MethodRateLimit<Unimplemented, f64>, method: &str) -> Status {
+        let request = http::Request::builder().uri(method).body(()).unwrap();
+        let mut future = service.call(request);
+        match future
+            .as_mut()
+            .poll(&mut Context::from_waker(Waker::noop()))
+        {
+            Poll::Ready(Ok(response)) => Status::from_header_map(response.headers()).unwrap(),
+            Poll::Ready(Err(err)) => match err {},
+            Poll::Pending => panic!("responses are always ready"),
+        }
+    }
+
+    #[test]
+    fn test_throttles_each_method_with_retry_info() {
+        let limiter = Arc::new(Mutex::new(KeyedRateLimiter::new(5.0)));
+        let mut service = MethodRateLimitLayer::new(limiter).layer(Unimplemented);
+
+        assert_eq!(call(&mut service, "/a.Svc/Hot").code(), Code::Unimplemented);
+        let throttled = (0..20)
+            .map(|_| call(&mut service, "/a.Svc/Hot"))
+            .find(|status| status.code() == Code::ResourceExhausted)
+            .unwrap();
+        assert!(throttled.message().contains("/a.Svc/Hot"));
+        assert!(retry_delay(&throttled).is_some_and(|delay| delay > Duration::ZERO));
+
+        assert_eq!(
+            call(&mut service, "/a.Svc/Cold").code(),
+            Code::Unimplemented
+        );
+    }
+
+    #[test]
+    fn test_retry_delay_round_trips() {
+        let status = resource_exhausted("slow down", Duration::from_millis(1500));
+        assert_eq!(retry_delay(&status), Some(Duration::from_millis(1500)));
+        assert_eq!(retry_delay(&Status::unavailable("")), None);
+    }
+
+    #[test]
+    fn test_failed_calls_lower_method_rates() {
+        #[derive(Clone)]
+        struct Flaky;
+
+        impl Service<http::Request<()>> for Flaky {
+            type Response = http::Response<BoxBody>;
+            type Error = Infallible;
+            type Future = std::future::Ready<Result<Self::Response, Infallible>>;
+
+            fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Infallible>> {
+                Poll::Ready(Ok(()))
+            }
+
+            fn call(&mut self, request: http::Request<()>) -> Self::Future {
+                let status = match request.uri().path() {
+                    "/a.Svc/Flaky" => Status::unavailable("overloaded"),
+                    _ => Status::not_found("no such order"),
+                };
+                std::future::ready(Ok(status.to_http()))
+            }
+        }
+
+        let limiter = Arc::new(Mutex::new(KeyedRateLimiter::new(100.0)));
+        let layer = MethodRateLimitLayer::new(limiter.clone()).error_target(0.1);
+        let mut service = layer.layer(Flaky);
+        let mut cx = Context::from_waker(Waker::noop());
+        let mut call = |method: &str| {
+            let request = http::Request::builder().uri(method).body(()).unwrap();
+            assert!(service.call(request).as_mut().poll(&mut cx).is_ready());
+        };
+
+        for _ in 0..10 {
+            call("/a.Svc/Missing");
+        }
+        assert_eq!(limiter.lock().unwrap().key_rate(), 100.0);
+        for _ in 0..10 {
+            call("/a.Svc/Flaky");
+        }
+        let key_rate = limiter.lock().unwrap().key_rate();
+        assert!((10.0..100.0).contains(&key_rate), "{key_rate}");
+    }
+
+    #[cfg(feature = "pacing")]
+    #[test]
+    fn test_paces_calls_with_server_feedback() {
+        use crate::runtime::{Sleep, Timer};
+        use crate::RateLimiterBuilder;
+
+        #[derive(Debug)]
+        struct Immediate;
+
+        impl Timer for Immediate {
+            fn sleep(&self, _duration: Duration) -> Sleep {
+                Box::pin(std::future::ready(()))
+            }
+        }
+
+        #[derive(Clone)]
+        struct Throttling;
+
+        impl Service<http::Request<()>> for Throttling {
+            type Response = http::Response<BoxBody>;
+            type Error = Infallible;
+            type Future = std::future::Ready<Result<Self::Response, Infallible>>;
+
+            fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Infallible>> {
+                Poll::Ready(Ok(()))
+            }
+
+            fn call(&mut self, request: http::Request<()>) -> Self::Future {
+                let status = match request.uri().path() {
+                    "/a.Svc/Hot" => resource_exhausted("slow down", Duration::from_secs(5)),
+                    _ => Status::ok(""),
+                };
+                std::future::ready(Ok(status.to_http()))
+            }
+        }
+
+        let layer = MethodPacingLayer::new(
+            |_method: &str| {
+                RateLimiterBuilder::new(100.0)
+                    .min_rate(10.0)
+                    .max_rate(100.0)
+                    .build()
+            },
+            Arc::new(Immediate),
+        );
+        let mut service = layer.layer(Throttling);
+        let mut cx = Context::from_waker(Waker::noop());
+        for method in ["/a.Svc/Hot", "/a.Svc/Cold"] {
+            let request = http::Request::builder().uri(method).body(()).unwrap();
+            assert!(service.call(request).as_mut().poll(&mut cx).is_ready());
+        }
+
+        let hot = layer.pacer("/a.Svc/Hot");
+        assert_eq!(hot.setpoint(), 50.0);
+        let wait = hot.try_acquire().unwrap();
+        assert!(wait > Duration::from_secs(4) && wait <= Duration::from_secs(5));
+
+        let cold = layer.pacer("/a.Svc/Cold");
+        assert_eq!(cold.setpoint(), 100.0);
+        assert_eq!(cold.try_acquire(), None);
+    }
+}
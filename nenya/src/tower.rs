@@ -0,0 +1,435 @@
+//! [Tower](https://docs.rs/tower) middleware rate limiting the requests of any service.
+//!
+//! [`RateLimitLayer`] wraps a service so every request first asks a shared [`RateLimiter`]
+//! whether it should be throttled. Throttled requests never reach the inner service; they are
+//! answered by a rejection function given the [`Throttled`] decision, which turns it into
+//! whatever the service returns for overload, e.g. an HTTP 429 response with a `Retry-After`
+//! header or an error.
+//!
+//! With [`RateLimitLayer::latency_target`], the layer also measures how long the inner service
+//! takes to respond and lowers the PID controller's setpoint while responses are slower than the
+//! target, so the limiter backs off when the service is struggling before it starts failing.
+//! [`RateLimitLayer::error_target`] does the same while too many requests fail with an error.
+//!
+//! [`KeyedRateLimitLayer`] limits each key of a [`KeyedRateLimiter`] instead, picking the key of
+//! every request with a [`KeyExtractor`].
+//!
+//! # Example
+//!
+//! ```rust
+//! use std::sync::{Arc, Mutex};
+//! use std::time::Duration;
+//!
+//! use nenya::tower::{RateLimitLayer, Throttled};
+//! use nenya::RateLimiterBuilder;
+//! use tower_layer::Layer;
+//!
+//! # struct Echo;
+//! # impl tower_service::Service<String> for Echo {
+//! #     type Response = String;
+//! #     type Error = String;
+//! #     type Future = std::future::Ready<Result<String, String>>;
+//! #     fn poll_ready(
+//! #         &mut self,
+//! #         _: &mut std::task::Context<'_>,
+//! #     ) -> std::task::Poll<Result<(), String>> {
+//! #         std::task::Poll::Ready(Ok(()))
+//! #     }
+//! #     fn call(&mut self, request: String) -> Self::Future {
+//! #         std::future::ready(Ok(request))
+//! #     }
+//! # }
+//! let limiter = Arc::new(Mutex::new(RateLimiterBuilder::new(100.0).build()));
+//! let layer = RateLimitLayer::new(limiter, |throttled: Throttled| -> Result<String, _> {
+//!     Err(format!("retry in {:?}", throttled.retry_after))
+//! })
+//! .latency_target(Duration::from_millis(250));
+//! let service = layer.layer(Echo);
+//! ```
+
+use num_traits::{Float, FromPrimitive, Signed};
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use tower_layer::Layer;
+use tower_service::Service;
+
+use crate::feedback::ResponseFeedback;
+use crate::key::KeyExtractor;
+use crate::keyed::KeyedRateLimiter;
+use crate::RateLimiter;
+
+/// Decision passed to the rejection function of a throttled request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Throttled {
+    /// How long the caller should wait before retrying.
+    pub retry_after: Duration,
+}
+
+/// Applies [`RateLimit`] to services.
+pub struct RateLimitLayer<T, R> {
+    rate_limiter: Arc<Mutex<RateLimiter<T>>>,
+    reject: Arc<R>,
+    feedback: Option<Arc<Mutex<ResponseFeedback<T>>>>,
+}
+
+impl<T, R> RateLimitLayer<T, R>
+where
+    T: Float + Signed + FromPrimitive + Copy,
+{
+    /// Creates a layer throttling requests with `rate_limiter`, answering throttled requests
+    /// with `reject`.
+    ///
+    /// The rate limiter is shared by every service the layer wraps.
+    pub fn new(rate_limiter: Arc<Mutex<RateLimiter<T>>>, reject: R) -> Self {
+        RateLimitLayer {
+            rate_limiter,
+            reject: Arc::new(reject),
+            feedback: None,
+        }
+    }
+
+    /// Feeds response latency back into the PID controller, lowering its setpoint while the
+    /// smoothed latency exceeds `target_latency`.
+    pub fn latency_target(mut self, target_latency: Duration) -> Self {
+        ResponseFeedback::configure(&mut self.feedback).set_latency_target(target_latency);
+        self
+    }
+
+    /// Feeds the inner service's errors back into the PID controller, lowering its setpoint
+    /// while the smoothed fraction of requests failing with an error exceeds `error_target`.
+    pub fn error_target(mut self, error_target: f64) -> Self {
+        ResponseFeedback::configure(&mut self.feedback).set_error_target(error_target);
+        self
+    }
+}
+
+impl<T, R> Clone for RateLimitLayer<T, R> {
+    fn clone(&self) -> Self {
+        RateLimitLayer {
+            rate_limiter: self.rate_limiter.clone(),
+            reject: self.reject.clone(),
+            feedback: self.feedback.clone(),
+        }
+    }
+}
+
+impl<T: fmt::Debug, R> fmt::Debug for RateLimitLayer<T, R> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RateLimitLayer")
+            .field("rate_limiter", &self.rate_limiter)
+            .field("feedback", &self.feedback)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<S, T, R> Layer<S> for RateLimitLayer<T, R> {
+    type Service = RateLimit<S, T, R>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RateLimit {
+            inner,
+            rate_limiter: self.rate_limiter.clone(),
+            reject: self.reject.clone(),
+            feedback: self.feedback.clone(),
+        }
+    }
+}
+
+/// Service throttling requests to the inner service with a shared [`RateLimiter`].
+pub struct RateLimit<S, T, R> {
+    inner: S,
+    rate_limiter: Arc<Mutex<RateLimiter<T>>>,
+    reject: Arc<R>,
+    feedback: Option<Arc<Mutex<ResponseFeedback<T>>>>,
+}
+
+impl<S: Clone, T, R> Clone for RateLimit<S, T, R> {
+    fn clone(&self) -> Self {
+        RateLimit {
+            inner: self.inner.clone(),
+            rate_limiter: self.rate_limiter.clone(),
+            reject: self.reject.clone(),
+            feedback: self.feedback.clone(),
+        }
+    }
+}
+
+impl<S: fmt::Debug, T: fmt::Debug, R> fmt::Debug for RateLimit<S, T, R> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RateLimit")
+            .field("inner", &self.inner)
+            .field("rate_limiter", &self.rate_limiter)
+            .field("feedback", &self.feedback)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<S, Request, T, R> Service<Request> for RateLimit<S, T, R>
+where
+    S: Service<Request>,
+    S::Response: Send + 'static,
+    S::Error: Send + 'static,
+    S::Future: Send + 'static,
+    T: Float + Signed + FromPrimitive + Copy + Send + 'static,
+    R: Fn(Throttled) -> Result<S::Response, S::Error>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<S::Response, S::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request) -> Self::Future {
+        let retry_after = {
+            let mut rate_limiter = self.rate_limiter.lock().unwrap();
+            rate_limiter
+                .should_throttle()
+                .then(|| rate_limiter.retry_after())
+        };
+        if let Some(retry_after) = retry_after {
+            return Box::pin(std::future::ready((self.reject)(Throttled { retry_after })));
+        }
+
+        let response = self.inner.call(request);
+        let Some(feedback) = self.feedback.clone() else {
+            return Box::pin(response);
+        };
+        let rate_limiter = self.rate_limiter.clone();
+        let started = Instant::now();
+        Box::pin(async move {
+            let response = response.await;
+            let mut rate_limiter = rate_limiter.lock().unwrap();
+            feedback.lock().unwrap().observe_setpoint(
+                started.elapsed(),
+                response.is_err(),
+                &mut rate_limiter,
+            );
+            response
+        })
+    }
+}
+
+/// Applies [`KeyedRateLimit`] to services.
+pub struct KeyedRateLimitLayer<T, E, R> {
+    limiter: Arc<Mutex<KeyedRateLimiter<String, T>>>,
+    extractor: Arc<E>,
+    reject: Arc<R>,
+}
+
+impl<T, E, R> KeyedRateLimitLayer<T, E, R> {
+    /// Creates a layer limiting the requests of each key from `extractor` with `limiter`,
+    /// answering throttled requests with `reject`.
+    ///
+    /// Requests without a key share a single limit.
+    pub fn new(limiter: Arc<Mutex<KeyedRateLimiter<String, T>>>, extractor: E, reject: R) -> Self {
+        KeyedRateLimitLayer {
+            limiter,
+            extractor: Arc::new(extractor),
+            reject: Arc::new(reject),
+        }
+    }
+}
+
+impl<T, E, R> Clone for KeyedRateLimitLayer<T, E, R> {
+    fn clone(&self) -> Self {
+        KeyedRateLimitLayer {
+            limiter: self.limiter.clone(),
+            extractor: self.extractor.clone(),
+            reject: self.reject.clone(),
+        }
+    }
+}
+
+impl<T: fmt::Debug, E, R> fmt::Debug for KeyedRateLimitLayer<T, E, R> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("KeyedRateLimitLayer")
+            .field("limiter", &self.limiter)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<S, T, E, R> Layer<S> for KeyedRateLimitLayer<T, E, R> {
+    type Service = KeyedRateLimit<S, T, E, R>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        KeyedRateLimit {
+            inner,
+            limiter: self.limiter.clone(),
+            extractor: self.extractor.clone(),
+            reject: self.reject.clone(),
+        }
+    }
+}
+
+/// Service throttling the requests of each key to the inner service with a shared
+/// [`KeyedRateLimiter`].
+pub struct KeyedRateLimit<S, T, E, R> {
+    inner: S,
+    limiter: Arc<Mutex<KeyedRateLimiter<String, T>>>,
+    extractor: Arc<E>,
+    reject: Arc<R>,
+}
+
+impl<S: Clone, T, E, R> Clone for KeyedRateLimit<S, T, E, R> {
+    fn clone(&self) -> Self {
+        KeyedRateLimit {
+            inner: self.inner.clone(),
+            limiter: self.limiter.clone(),
+            extractor: self.extractor.clone(),
+            reject: self.reject.clone(),
+        }
+    }
+}
+
+impl<S: fmt::Debug, T: fmt::Debug, E, R> fmt::Debug for KeyedRateLimit<S, T, E, R> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("KeyedRateLimit")
+            .field("inner", &self.inner)
+            .field("limiter", &self.limiter)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<S, Request, T, E, R> Service<Request> for KeyedRateLimit<S, T, E, R>
+where
+    S: Service<Request>,
+    S::Response: Send + 'static,
+    S::Error: Send + 'static,
+    S::Future: Send + 'static,
+    T: Float + Signed + FromPrimitive + Copy,
+    E: KeyExtractor<Request>,
+    R: Fn(Throttled) -> Result<S::Response, S::Error>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<S::Response, S::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request) -> Self::Future {
+        let key = self.extractor.extract(&request).unwrap_or_default();
+        let retry_after = {
+            let mut limiter = self.limiter.lock().unwrap();
+            limiter
+                .should_throttle(&key)
+                .then(|| limiter.retry_after(&key))
+        };
+        if let Some(retry_after) = retry_after {
+            return Box::pin(std::future::ready((self.reject)(Throttled { retry_after })));
+        }
+        Box::pin(self.inner.call(request))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::RateLimiterBuilder;
+    use std::task::Waker;
+
+    #[derive(Clone)]
+    struct Echo;
+
+    impl Service<u32> for Echo {
+        type Response = u32;
+        type Error = Duration;
+        type Future = std::future::Ready<Result<u32, Duration>>;
+
+        fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Duration>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, request: u32) -> Self::Future {
+            std::future::ready(Ok(request))
+        }
+    }
+
+    fn ready<F: Future + Unpin + ?Sized>(future: &mut F) -> F::Output {
+        match Pin::new(future).poll(&mut Context::from_waker(Waker::noop())) {
+            Poll::Ready(output) => output,
+            Poll::Pending => panic!("echo futures are always ready"),
+        }
+    }
+
+    #[test]
+    fn test_rejects_throttled_requests() {
+        let rate_limiter = Arc::new(Mutex::new(RateLimiterBuilder::new(5.0).build()));
+        let layer = RateLimitLayer::new(rate_limiter, |throttled: Throttled| {
+            Err(throttled.retry_after)
+        });
+        let mut service = layer.layer(Echo);
+
+        let results: Vec<_> = (0..20).map(|i| ready(&mut service.call(i))).collect();
+        assert_eq!(results[0], Ok(0));
+        let rejection = results.iter().find_map(|result| result.err());
+        assert!(rejection.is_some_and(|retry_after| retry_after > Duration::ZERO));
+    }
+
+    #[test]
+    fn test_keyed_layer_limits_each_key() {
+        let limiter = Arc::new(Mutex::new(KeyedRateLimiter::new(5.0)));
+        let even_odd = |request: &u32| Some((request % 2).to_string());
+        let layer = KeyedRateLimitLayer::new(limiter.clone(), even_odd, |throttled: Throttled| {
+            Err(throttled.retry_after)
+        });
+        let mut service = layer.layer(Echo);
+
+        let results: Vec<_> = (0..40).map(|i| ready(&mut service.call(i * 2))).collect();
+        assert!(results.iter().any(Result::is_err));
+        assert_eq!(ready(&mut service.call(1)), Ok(1));
+        assert_eq!(limiter.lock().unwrap().len(), 2);
+    }
+
+    #[derive(Clone)]
+    struct FailOdd;
+
+    impl Service<u32> for FailOdd {
+        type Response = u32;
+        type Error = Duration;
+        type Future = std::future::Ready<Result<u32, Duration>>;
+
+        fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Duration>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, request: u32) -> Self::Future {
+            std::future::ready(if request % 2 == 1 {
+                Err(Duration::ZERO)
+            } else {
+                Ok(request)
+            })
+        }
+    }
+
+    #[test]
+    fn test_errors_lower_setpoint() {
+        let rate_limiter = Arc::new(Mutex::new(
+            RateLimiterBuilder::new(1000.0).min_rate(1.0).build(),
+        ));
+        let layer = RateLimitLayer::new(rate_limiter.clone(), |throttled: Throttled| {
+            Err(throttled.retry_after)
+        })
+        .error_target(0.1);
+        let mut service = layer.layer(FailOdd);
+
+        for i in 0..20 {
+            let _ = ready(&mut service.call(i * 2));
+        }
+        assert_eq!(rate_limiter.lock().unwrap().setpoint(), 1000.0);
+
+        for i in 0..40 {
+            let _ = ready(&mut service.call(i));
+        }
+        let setpoint = rate_limiter.lock().unwrap().setpoint();
+        assert!((100.0..500.0).contains(&setpoint), "{setpoint}");
+    }
+}
@@ -0,0 +1,350 @@
+//! Search for PID gains suited to a traffic trace.
+//!
+//! A [`GainTuner`] replays the same arrivals, such as a [`Recording`] of production traffic,
+//! in virtual time for every gain set of a `kp`/`ki`/`kd` grid, and scores each run with a cost
+//! adding up three penalties, each a fraction so their weights compare:
+//!
+//! - overshoot: how far the accepted rate exceeded the target rate, relative to it
+//! - settling time: the share of the run the accepted rate spent further than the tolerance from
+//!   the ideal, which is the request rate capped at the target rate
+//! - under-target throttling: the share of requests throttled while the request rate was at or
+//!   below the target rate
+//!
+//! # Example
+//!
+//! ```rust
+//! use std::time::Duration;
+//!
+//! use nenya::simulation::PoissonArrivals;
+//! use nenya::tuning::GainTunerBuilder;
+//!
+//! let arrivals: Vec<Duration> = PoissonArrivals::new(80.0, 7)
+//!     .take_while(|arrival| *arrival < Duration::from_secs(600))
+//!     .collect();
+//! let tuner = GainTunerBuilder::new(50.0, arrivals)
+//!     .min_rate(10.0)
+//!     .max_rate(100.0)
+//!     .build();
+//!
+//! let evaluations = tuner.search(&[0.1, 0.5, 1.0], &[0.0, 0.1], &[0.0, 0.05]);
+//! println!("best gains: {:?}", evaluations[0].gains);
+//! ```
+//!
+//! [`Recording`]: crate::recording::Recording
+
+use std::time::Duration;
+
+use crate::pid_controller::PIDControllerBuilder;
+use crate::recording::Recording;
+use crate::simulation::SimulationBuilder;
+use crate::RateLimiterBuilder;
+
+/// Gains of a PID controller.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Gains {
+    pub kp: f64,
+    pub ki: f64,
+    pub kd: f64,
+}
+
+/// Weights of the penalties adding up to the cost of a run.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CostWeights {
+    pub overshoot: f64,
+    pub settling_time: f64,
+    pub under_target_throttling: f64,
+}
+
+impl Default for CostWeights {
+    /// Weighs every penalty equally.
+    fn default() -> Self {
+        CostWeights {
+            overshoot: 1.0,
+            settling_time: 1.0,
+            under_target_throttling: 1.0,
+        }
+    }
+}
+
+/// Score of a gain set on the tuner's arrivals.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Evaluation {
+    pub gains: Gains,
+    /// Weighted sum of the penalties, lower is better.
+    pub cost: f64,
+    /// Largest excess of the accepted rate over the target rate, relative to the target rate.
+    pub overshoot: f64,
+    /// Time the accepted rate spent further than the tolerance from the ideal.
+    pub settling_time: Duration,
+    /// Fraction of the requests throttled while the request rate was at or below the target.
+    pub under_target_throttle_ratio: f64,
+}
+
+/// Scores PID gains by simulating a rate limiter against recorded arrivals.
+#[derive(Debug, Clone)]
+pub struct GainTuner {
+    arrivals: Vec<Duration>,
+    target_rate: f64,
+    min_rate: f64,
+    max_rate: f64,
+    update_interval: Duration,
+    tick: Duration,
+    duration: Duration,
+    tolerance: f64,
+    weights: CostWeights,
+}
+
+impl GainTuner {
+    /// Simulates the rate limiter with `gains` and returns the score of the run.
+    pub fn evaluate(&self, gains: Gains) -> Evaluation {
+        let rate_limiter = RateLimiterBuilder::new(self.target_rate)
+            .min_rate(self.min_rate)
+            .max_rate(self.max_rate)
+            .update_interval(self.update_interval)
+            .pid_controller(
+                PIDControllerBuilder::new(self.target_rate)
+                    .kp(gains.kp)
+                    .ki(gains.ki)
+                    .kd(gains.kd)
+                    .build(),
+            );
+        let report =
+            SimulationBuilder::from_arrivals(rate_limiter, self.arrivals.clone().into_iter())
+                .tick(self.tick)
+                .build()
+                .run(self.duration);
+
+        let mut overshoot: f64 = 0.0;
+        let mut unsettled_ticks = 0;
+        let mut under_target_offered = 0;
+        let mut under_target_throttled = 0;
+        for tick in &report.ticks {
+            overshoot = overshoot.max((tick.accepted_rate - self.target_rate) / self.target_rate);
+            let ideal = tick.request_rate.min(self.target_rate);
+            if (tick.accepted_rate - ideal).abs() > self.tolerance * self.target_rate {
+                unsettled_ticks += 1;
+            }
+            if tick.request_rate <= self.target_rate {
+                under_target_offered += tick.offered;
+                under_target_throttled += tick.throttled;
+            }
+        }
+
+        let unsettled_share = if report.ticks.is_empty() {
+            0.0
+        } else {
+            unsettled_ticks as f64 / report.ticks.len() as f64
+        };
+        let under_target_throttle_ratio = if under_target_offered == 0 {
+            0.0
+        } else {
+            under_target_throttled as f64 / under_target_offered as f64
+        };
+        Evaluation {
+            gains,
+            cost: self.weights.overshoot * overshoot
+                + self.weights.settling_time * unsettled_share
+                + self.weights.under_target_throttling * under_target_throttle_ratio,
+            overshoot,
+            settling_time: self.tick * unsettled_ticks,
+            under_target_throttle_ratio,
+        }
+    }
+
+    /// Evaluates every combination of the gains, returning the evaluations from the lowest cost
+    /// to the highest.
+    pub fn search(&self, kp: &[f64], ki: &[f64], kd: &[f64]) -> Vec<Evaluation> {
+        let mut evaluations = Vec::with_capacity(kp.len() * ki.len() * kd.len());
+        for &kp in kp {
+            for &ki in ki {
+                for &kd in kd {
+                    evaluations.push(self.evaluate(Gains { kp, ki, kd }));
+                }
+            }
+        }
+        evaluations.sort_by(|a, b| a.cost.total_cmp(&b.cost));
+        evaluations
+    }
+
+    /// Returns the virtual time every run lasts.
+    pub fn duration(&self) -> Duration {
+        self.duration
+    }
+}
+
+/// Builder for creating a `GainTuner` instance.
+pub struct GainTunerBuilder {
+    arrivals: Vec<Duration>,
+    target_rate: f64,
+    min_rate: Option<f64>,
+    max_rate: Option<f64>,
+    update_interval: Duration,
+    tick: Duration,
+    duration: Option<Duration>,
+    tolerance: f64,
+    weights: CostWeights,
+}
+
+impl GainTunerBuilder {
+    /// Creates a new `GainTunerBuilder` simulating a rate limiter with `target_rate` against
+    /// `arrivals`, the sorted times since the start of the trace.
+    ///
+    /// By default the rate limiter's minimum and maximum rates are the target rate, its update
+    /// interval is one second, the runs last until the last arrival in ticks of 100 ms, the
+    /// tolerance is 5% of the target rate, and the penalties weigh the same.
+    pub fn new(target_rate: f64, arrivals: Vec<Duration>) -> Self {
+        GainTunerBuilder {
+            arrivals,
+            target_rate,
+            min_rate: None,
+            max_rate: None,
+            update_interval: Duration::from_secs(1),
+            tick: Duration::from_millis(100),
+            duration: None,
+            tolerance: 0.05,
+            weights: CostWeights::default(),
+        }
+    }
+
+    /// Creates a new `GainTunerBuilder` simulating a rate limiter with `target_rate` against the
+    /// arrivals of `recording`, with the defaults of [`GainTunerBuilder::new`].
+    pub fn from_recording(target_rate: f64, recording: &Recording) -> Self {
+        Self::new(target_rate, recording.arrivals())
+    }
+
+    /// Sets the minimum allowable rate of requests.
+    pub fn min_rate(mut self, min_rate: f64) -> Self {
+        self.min_rate = Some(min_rate);
+        self
+    }
+
+    /// Sets the maximum allowable rate of requests.
+    pub fn max_rate(mut self, max_rate: f64) -> Self {
+        self.max_rate = Some(max_rate);
+        self
+    }
+
+    /// Sets the interval between updates of the target rate.
+    pub fn update_interval(mut self, update_interval: Duration) -> Self {
+        self.update_interval = update_interval;
+        self
+    }
+
+    /// Sets the virtual time between the metrics the runs are scored on.
+    pub fn tick(mut self, tick: Duration) -> Self {
+        self.tick = tick;
+        self
+    }
+
+    /// Sets the virtual time every run lasts.
+    pub fn duration(mut self, duration: Duration) -> Self {
+        self.duration = Some(duration);
+        self
+    }
+
+    /// Sets the distance from the ideal accepted rate, as a fraction of the target rate, within
+    /// which a run counts as settled.
+    pub fn tolerance(mut self, tolerance: f64) -> Self {
+        self.tolerance = tolerance;
+        self
+    }
+
+    /// Sets the weights of the penalties adding up to the cost.
+    pub fn weights(mut self, weights: CostWeights) -> Self {
+        self.weights = weights;
+        self
+    }
+
+    /// Builds and returns the `GainTuner` instance.
+    pub fn build(self) -> GainTuner {
+        let duration = self.duration.unwrap_or_else(|| {
+            let last = self.arrivals.last().copied().unwrap_or_default();
+            // Round up to whole ticks so the last arrival is sent
+            let ticks = (last.as_nanos() / self.tick.as_nanos().max(1) + 1) as u32;
+            self.tick * ticks
+        });
+        GainTuner {
+            arrivals: self.arrivals,
+            target_rate: self.target_rate,
+            min_rate: self.min_rate.unwrap_or(self.target_rate),
+            max_rate: self.max_rate.unwrap_or(self.target_rate),
+            update_interval: self.update_interval,
+            tick: self.tick,
+            duration,
+            tolerance: self.tolerance,
+            weights: self.weights,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulation::{OnOffGenerator, PoissonArrivals};
+
+    /// Arrivals alternating between 10 seconds at 150 and 20 seconds at 30 requests per second.
+    fn bursty_arrivals() -> Vec<Duration> {
+        let generator = OnOffGenerator::new(
+            150.0,
+            30.0,
+            Duration::from_secs(10),
+            Duration::from_secs(20),
+        );
+        PoissonArrivals::time_varying(generator, 150.0, 3)
+            .take_while(|arrival| *arrival < Duration::from_secs(300))
+            .collect()
+    }
+
+    fn tuner() -> GainTuner {
+        GainTunerBuilder::new(50.0, bursty_arrivals())
+            .min_rate(10.0)
+            .max_rate(100.0)
+            .build()
+    }
+
+    #[test]
+    fn test_scores_gains() {
+        let tuner = tuner();
+        assert_eq!(tuner.duration(), Duration::from_secs(300));
+
+        // Without corrections the target stays put, so the limiter only overshoots it at the
+        // start of the bursts, before the rate window fills, and throttles no traffic below it
+        let fixed = tuner.evaluate(Gains::default());
+        assert!(fixed.overshoot < 0.25, "{fixed:?}");
+        assert_eq!(fixed.under_target_throttle_ratio, 0.0);
+        assert!(fixed.settling_time > Duration::ZERO);
+
+        // Strong proportional gain raises the target to the maximum between bursts, letting
+        // the start of every burst through
+        let aggressive = tuner.evaluate(Gains {
+            kp: 2.0,
+            ki: 0.5,
+            kd: 0.0,
+        });
+        assert!(aggressive.overshoot > 1.0, "{aggressive:?}");
+        assert!(aggressive.cost > fixed.cost, "{aggressive:?} {fixed:?}");
+        assert_eq!(tuner.evaluate(aggressive.gains), aggressive);
+    }
+
+    #[test]
+    fn test_search_orders_by_cost() {
+        let evaluations = tuner().search(&[0.0, 0.5, 2.0], &[0.0, 0.5], &[0.0]);
+        assert_eq!(evaluations.len(), 6);
+        assert!(evaluations
+            .windows(2)
+            .all(|pair| pair[0].cost <= pair[1].cost));
+
+        let weights = CostWeights {
+            overshoot: 0.0,
+            settling_time: 0.0,
+            under_target_throttling: 0.0,
+        };
+        let tuner = GainTunerBuilder::new(50.0, bursty_arrivals())
+            .weights(weights)
+            .build();
+        assert!(tuner
+            .search(&[0.5], &[0.1], &[0.0, 0.1])
+            .iter()
+            .all(|evaluation| evaluation.cost == 0.0));
+    }
+}